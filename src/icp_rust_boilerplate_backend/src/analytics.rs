@@ -0,0 +1,499 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::require_admin;
+use crate::indexes::assignments_for_post;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    Assignment, AssignmentStatus, FoodShareError, Memory, SurplusRecord, MEMORY_MANAGER,
+    SURPLUS_POSTS_STORAGE, SURPLUS_RECORDS_STORAGE,
+};
+use candid::{Decode, Encode};
+use chrono::{DateTime, Datelike, Months, NaiveDate};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::collections::{BTreeMap, BTreeSet};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+// Donors and receivers count as active with a delivery in this many days
+const ACTIVE_WITHIN_DAYS: u64 = 30;
+// Weekly delivery rate is averaged over this many weeks
+const RATE_WEEKS: u64 = 4;
+const MAX_BUCKETS: usize = 400;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ImpactFactors {
+    // Meals one kilogram of rescued food provides
+    meals_per_kg: f64,
+    // Kilograms of CO2e avoided per kilogram of food kept from landfill
+    kg_co2e_per_kg: f64,
+}
+
+impl Default for ImpactFactors {
+    fn default() -> Self {
+        Self {
+            meals_per_kg: 2.4,
+            kg_co2e_per_kg: 2.5,
+        }
+    }
+}
+
+impl Storable for ImpactFactors {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Running totals of rescued food, updated as deliveries are recorded. Each
+// surplus post counts once, at its first delivery leg.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct ImpactCounters {
+    deliveries: u64,
+    delivered_kg: u64,
+    first_delivery_at: Option<u64>,
+    last_delivery_at: Option<u64>,
+}
+
+impl ImpactCounters {
+    fn count(&mut self, quantity_kg: u64, delivered_at: u64) {
+        self.deliveries += 1;
+        self.delivered_kg += quantity_kg;
+        self.first_delivery_at = Some(
+            self.first_delivery_at
+                .map_or(delivered_at, |first| first.min(delivered_at)),
+        );
+        self.last_delivery_at = Some(
+            self.last_delivery_at
+                .map_or(delivered_at, |last| last.max(delivered_at)),
+        );
+    }
+}
+
+impl Storable for ImpactCounters {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ImpactCounters {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// ImpactBucket is a custom enum type that is used to represent the length of the periods impact is grouped by
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ImpactBucket {
+    Day,
+    // Monday to Sunday, UTC
+    Week,
+    Month,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct GlobalImpact {
+    deliveries: u64,
+    kg_rescued: u64,
+    meals_equivalent: u64,
+    kg_co2e_avoided: f64,
+    // Average over the last four weeks
+    deliveries_per_week: f64,
+    // Donors and receivers with a delivery in the last 30 days
+    active_donors: u64,
+    active_receivers: u64,
+    first_delivery_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DonorImpact {
+    donor_id: u64,
+    deliveries: u64,
+    kg_rescued: u64,
+    meals_equivalent: u64,
+    kg_co2e_avoided: f64,
+    first_delivery_at: Option<u64>,
+    last_delivery_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PeriodImpact {
+    starts_at: u64,
+    ends_at: u64,
+    deliveries: u64,
+    kg_rescued: u64,
+    meals_equivalent: u64,
+    kg_co2e_avoided: f64,
+    active_donors: u64,
+    active_receivers: u64,
+}
+
+type ActivityIndex = RefCell<StableBTreeMap<(u64, u64), (), Memory>>;
+
+thread_local! {
+    static IMPACT_FACTORS: RefCell<Cell<ImpactFactors, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(72))),
+            ImpactFactors::default(),
+        )
+        .expect("Cannot create the impact factors")
+    );
+
+    static GLOBAL_IMPACT: RefCell<Cell<ImpactCounters, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(73))),
+            ImpactCounters::default(),
+        )
+        .expect("Cannot create the global impact counters")
+    );
+
+    static DONOR_IMPACT: RefCell<StableBTreeMap<u64, ImpactCounters, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(74)))
+    ));
+
+    // Keyed by days since the epoch, UTC
+    static DAILY_IMPACT: RefCell<StableBTreeMap<u64, ImpactCounters, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(75)))
+    ));
+
+    // (day, donor_id) for every day a donor had food delivered
+    static ACTIVE_DONORS: ActivityIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(76)))
+    ));
+
+    // (day, receiver_id) for every day a receiver got a delivery
+    static ACTIVE_RECEIVERS: ActivityIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(77)))
+    ));
+}
+
+fn impact_factors() -> ImpactFactors {
+    IMPACT_FACTORS.with(|cell| cell.borrow().get().clone())
+}
+
+fn meals(factors: &ImpactFactors, kg: u64) -> u64 {
+    (kg as f64 * factors.meals_per_kg).round() as u64
+}
+
+fn co2e(factors: &ImpactFactors, kg: u64) -> f64 {
+    kg as f64 * factors.kg_co2e_per_kg
+}
+
+// Function to count one delivery leg. Only the first leg of a post adds its
+// food; every leg marks its receiver active.
+fn count_delivery(record: &SurplusRecord, receiver_id: Option<u64>, first_leg: bool) {
+    let day = record.delivered_at / NANOS_PER_DAY;
+    if let Some(receiver_id) = receiver_id {
+        ACTIVE_RECEIVERS.with(|index| index.borrow_mut().insert((day, receiver_id), ()));
+    }
+    if !first_leg {
+        return;
+    }
+    let Some(post) =
+        SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&record.surplus_post_id))
+    else {
+        return;
+    };
+    let quantity_kg = post.quantity_kg as u64;
+
+    GLOBAL_IMPACT.with(|cell| {
+        let mut counters = cell.borrow().get().clone();
+        counters.count(quantity_kg, record.delivered_at);
+        cell.borrow_mut()
+            .set(counters)
+            .expect("Cannot update the global impact counters");
+    });
+    DAILY_IMPACT.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let mut counters = storage.get(&day).unwrap_or_default();
+        counters.count(quantity_kg, record.delivered_at);
+        storage.insert(day, counters);
+    });
+    if let Ok(donor_id) = post.donor_id.parse::<u64>() {
+        DONOR_IMPACT.with(|storage| {
+            let mut storage = storage.borrow_mut();
+            let mut counters = storage.get(&donor_id).unwrap_or_default();
+            counters.count(quantity_kg, record.delivered_at);
+            storage.insert(donor_id, counters);
+        });
+        ACTIVE_DONORS.with(|index| index.borrow_mut().insert((day, donor_id), ()));
+    }
+}
+
+// Function to count a new delivery towards the impact figures
+pub(crate) fn record_delivery_impact(record: &SurplusRecord, assignment: &Assignment) {
+    // Onward legs of a hub flow deliver food an earlier leg already counted
+    let first_leg = !assignments_for_post(assignment.surplus_post_id)
+        .iter()
+        .any(|other| other.id != assignment.id && other.status == AssignmentStatus::Delivered);
+    count_delivery(record, Some(assignment.receiver_id), first_leg);
+}
+
+fn distinct_active(
+    index: &'static std::thread::LocalKey<ActivityIndex>,
+    first_day: u64,
+    end_day: u64,
+) -> u64 {
+    index.with(|index| {
+        index
+            .borrow()
+            .range((first_day, 0)..(end_day, 0))
+            .map(|((_, id), _)| id)
+            .collect::<BTreeSet<u64>>()
+            .len() as u64
+    })
+}
+
+fn day_start(day: u64) -> u64 {
+    day * NANOS_PER_DAY
+}
+
+fn date_of(day: u64) -> NaiveDate {
+    DateTime::from_timestamp_nanos(day_start(day) as i64).date_naive()
+}
+
+fn day_of(date: NaiveDate) -> u64 {
+    date.signed_duration_since(NaiveDate::default()).num_days() as u64
+}
+
+// Function to get the first day of the bucket holding a day
+fn bucket_start(day: u64, bucket: ImpactBucket) -> u64 {
+    match bucket {
+        ImpactBucket::Day => day,
+        // The epoch fell on a Thursday, three days after a Monday
+        ImpactBucket::Week => day - (day + 3) % 7,
+        ImpactBucket::Month => date_of(day).with_day(1).map_or(day, day_of),
+    }
+}
+
+fn next_bucket(start: u64, bucket: ImpactBucket) -> u64 {
+    match bucket {
+        ImpactBucket::Day => start + 1,
+        ImpactBucket::Week => start + 7,
+        ImpactBucket::Month => date_of(start)
+            .checked_add_months(Months::new(1))
+            .map_or(start + 31, day_of),
+    }
+}
+
+// Function to get the impact figures of the whole platform
+#[ic_cdk::query]
+fn get_global_impact() -> GlobalImpact {
+    let factors = impact_factors();
+    let counters = GLOBAL_IMPACT.with(|cell| cell.borrow().get().clone());
+    let today = time() / NANOS_PER_DAY;
+    let rate_from = today.saturating_sub(RATE_WEEKS * 7 - 1);
+    let recent_deliveries: u64 = DAILY_IMPACT.with(|storage| {
+        storage
+            .borrow()
+            .range(rate_from..)
+            .map(|(_, counters)| counters.deliveries)
+            .sum()
+    });
+    let active_from = today.saturating_sub(ACTIVE_WITHIN_DAYS - 1);
+
+    GlobalImpact {
+        deliveries: counters.deliveries,
+        kg_rescued: counters.delivered_kg,
+        meals_equivalent: meals(&factors, counters.delivered_kg),
+        kg_co2e_avoided: co2e(&factors, counters.delivered_kg),
+        deliveries_per_week: recent_deliveries as f64 / RATE_WEEKS as f64,
+        active_donors: distinct_active(&ACTIVE_DONORS, active_from, today + 1),
+        active_receivers: distinct_active(&ACTIVE_RECEIVERS, active_from, today + 1),
+        first_delivery_at: counters.first_delivery_at,
+    }
+}
+
+// Function to get the impact figures of a donor
+#[ic_cdk::query]
+fn get_donor_impact(donor_id: u64) -> Result<DonorImpact, FoodShareError> {
+    let counters = DONOR_IMPACT
+        .with(|storage| storage.borrow().get(&donor_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Donor {} has no recorded deliveries", donor_id),
+        })?;
+    let factors = impact_factors();
+
+    Ok(DonorImpact {
+        donor_id,
+        deliveries: counters.deliveries,
+        kg_rescued: counters.delivered_kg,
+        meals_equivalent: meals(&factors, counters.delivered_kg),
+        kg_co2e_avoided: co2e(&factors, counters.delivered_kg),
+        first_delivery_at: counters.first_delivery_at,
+        last_delivery_at: counters.last_delivery_at,
+    })
+}
+
+// Function to get the impact figures of each day, week or month between two
+// timestamps. Figures are kept per UTC day, so the first and last buckets
+// cover the whole periods holding the start and the end.
+#[ic_cdk::query]
+fn get_impact_by_period(
+    start: u64,
+    end: u64,
+    bucket: ImpactBucket,
+) -> Result<Vec<PeriodImpact>, FoodShareError> {
+    if start >= end {
+        return Err(FoodShareError::Validation {
+            field: "end".to_string(),
+            reason: "End must be after start".to_string(),
+        });
+    }
+
+    let factors = impact_factors();
+    let end_day = (end - 1) / NANOS_PER_DAY + 1;
+    let mut periods = Vec::new();
+    let mut bucket_day = bucket_start(start / NANOS_PER_DAY, bucket);
+    while bucket_day < end_day {
+        if periods.len() >= MAX_BUCKETS {
+            return Err(FoodShareError::Validation {
+                field: "bucket".to_string(),
+                reason: format!(
+                    "Range covers more than {} buckets; use a longer bucket",
+                    MAX_BUCKETS
+                ),
+            });
+        }
+        let next_day = next_bucket(bucket_day, bucket);
+        let (deliveries, kg) = DAILY_IMPACT.with(|storage| {
+            storage.borrow().range(bucket_day..next_day).fold(
+                (0, 0),
+                |(deliveries, kg), (_, counters)| {
+                    (deliveries + counters.deliveries, kg + counters.delivered_kg)
+                },
+            )
+        });
+        periods.push(PeriodImpact {
+            starts_at: day_start(bucket_day),
+            ends_at: day_start(next_day),
+            deliveries,
+            kg_rescued: kg,
+            meals_equivalent: meals(&factors, kg),
+            kg_co2e_avoided: co2e(&factors, kg),
+            active_donors: distinct_active(&ACTIVE_DONORS, bucket_day, next_day),
+            active_receivers: distinct_active(&ACTIVE_RECEIVERS, bucket_day, next_day),
+        });
+        bucket_day = next_day;
+    }
+
+    Ok(periods)
+}
+
+#[ic_cdk::query]
+fn get_impact_factors() -> ImpactFactors {
+    impact_factors()
+}
+
+// Function for admins to set the meal and CO2e factors; figures already
+// counted are converted with the new factors
+#[ic_cdk::update]
+fn set_impact_factors(factors: ImpactFactors) -> Result<ImpactFactors, FoodShareError> {
+    start_trace("set_impact_factors");
+    require_admin("update the impact factors")?;
+
+    for (field, value) in [
+        ("meals_per_kg", factors.meals_per_kg),
+        ("kg_co2e_per_kg", factors.kg_co2e_per_kg),
+    ] {
+        if !value.is_finite() || value < 0.0 {
+            return Err(FoodShareError::Validation {
+                field: field.to_string(),
+                reason: "Factor must be a non-negative number".to_string(),
+            });
+        }
+    }
+
+    let before = IMPACT_FACTORS
+        .with(|cell| cell.borrow_mut().set(factors.clone()))
+        .expect("Cannot update the impact factors");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::ImpactFactors,
+        0,
+        Some(&before),
+        Some(&factors),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Impact factors set to {} meals and {} kg CO2e per kg",
+            factors.meals_per_kg, factors.kg_co2e_per_kg
+        ),
+    );
+
+    Ok(factors)
+}
+
+// Function for admins to recount the impact figures from the stored
+// deliveries, e.g. for deliveries recorded before the figures were kept
+#[ic_cdk::update]
+fn rebuild_analytics() -> Result<u64, FoodShareError> {
+    start_trace("rebuild_analytics");
+    require_admin("rebuild analytics")?;
+
+    GLOBAL_IMPACT.with(|cell| {
+        cell.borrow_mut()
+            .set(ImpactCounters::default())
+            .expect("Cannot reset the global impact counters")
+    });
+    for storage in [&DONOR_IMPACT, &DAILY_IMPACT] {
+        storage.with(|storage| {
+            let mut storage = storage.borrow_mut();
+            let ids: Vec<u64> = storage.iter().map(|(id, _)| id).collect();
+            for id in ids {
+                storage.remove(&id);
+            }
+        });
+    }
+    for index in [&ACTIVE_DONORS, &ACTIVE_RECEIVERS] {
+        index.with(|index| {
+            let mut index = index.borrow_mut();
+            let keys: Vec<(u64, u64)> = index.iter().map(|(key, _)| key).collect();
+            for key in keys {
+                index.remove(&key);
+            }
+        });
+    }
+
+    let mut records: Vec<SurplusRecord> = SURPLUS_RECORDS_STORAGE
+        .with(|storage| storage.borrow().iter().map(|(_, record)| record).collect());
+    records.sort_by_key(|record| (record.delivered_at, record.id));
+    let mut counted_posts = BTreeSet::new();
+    let mut post_assignments: BTreeMap<u64, Vec<Assignment>> = BTreeMap::new();
+    for record in &records {
+        let assignments = post_assignments
+            .entry(record.surplus_post_id)
+            .or_insert_with(|| assignments_for_post(record.surplus_post_id));
+        let receiver_id = assignments
+            .iter()
+            .find(|assignment| {
+                assignment.driver_id == record.driver_id
+                    && assignment.status == AssignmentStatus::Delivered
+            })
+            .map(|assignment| assignment.receiver_id);
+        count_delivery(
+            record,
+            receiver_id,
+            counted_posts.insert(record.surplus_post_id),
+        );
+    }
+
+    log(
+        LogLevel::Info,
+        format!("Rebuilt impact figures from {} deliveries", records.len()),
+    );
+
+    Ok(records.len() as u64)
+}
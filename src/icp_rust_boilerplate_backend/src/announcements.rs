@@ -0,0 +1,227 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::{is_admin, is_governance_or_admin};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    next_id, FoodShareError, Memory, DONORS_STORAGE, DRIVERS_STORAGE, MEMORY_MANAGER,
+    RECEIVERS_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_TITLE_LEN: usize = 120;
+const MAX_BODY_LEN: usize = 2_000;
+const MAX_ACTIVE_ANNOUNCEMENTS: usize = 50;
+const MAX_LIFETIME_NANOS: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+
+// AnnouncementRole is a custom enum type that is used to represent a group of users an announcement is for
+#[derive(
+    candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+pub(crate) enum AnnouncementRole {
+    Donor,
+    Receiver,
+    Driver,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Announcement {
+    id: u64,
+    target_roles: Vec<AnnouncementRole>,
+    title: String,
+    body: String,
+    created_by: Principal,
+    created_at: u64,
+    // Hidden from get_active_announcements from this time on
+    expires_at: u64,
+}
+
+impl Storable for Announcement {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Announcement {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static ANNOUNCEMENTS_STORAGE: RefCell<StableBTreeMap<u64, Announcement, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(113)))
+    ));
+}
+
+// Function to get the roles of the caller: one for every kind of active profile it owns
+fn caller_roles() -> Vec<AnnouncementRole> {
+    let owner = Some(ic_cdk::caller());
+    let mut roles = Vec::new();
+    if DONORS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .any(|(_, donor)| donor.active && donor.owner == owner)
+    }) {
+        roles.push(AnnouncementRole::Donor);
+    }
+    if RECEIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .any(|(_, receiver)| receiver.active && receiver.owner == owner)
+    }) {
+        roles.push(AnnouncementRole::Receiver);
+    }
+    if DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .any(|(_, driver)| driver.active && driver.owner == owner)
+    }) {
+        roles.push(AnnouncementRole::Driver);
+    }
+    roles
+}
+
+// Function to drop announcements that have expired, so the collection only
+// holds the ones that can still be shown
+fn remove_expired(now: u64) {
+    ANNOUNCEMENTS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let expired: Vec<u64> = storage
+            .iter()
+            .filter(|(_, announcement)| announcement.expires_at <= now)
+            .map(|(id, _)| id)
+            .collect();
+        for id in expired {
+            storage.remove(&id);
+        }
+    });
+}
+
+// Function for admins and the governance canister to broadcast an announcement,
+// e.g. "depot closed Friday", to every user with one of the target roles until
+// it expires
+#[ic_cdk::update]
+fn create_announcement(
+    target_roles: Vec<AnnouncementRole>,
+    title: String,
+    body: String,
+    expires_at: u64,
+) -> Result<Announcement, FoodShareError> {
+    start_trace("create_announcement");
+    if !is_governance_or_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only admins or governance can create announcements".to_string(),
+        });
+    }
+
+    let mut target_roles = target_roles;
+    target_roles.sort();
+    target_roles.dedup();
+    if target_roles.is_empty() {
+        return Err(FoodShareError::Validation {
+            field: "target_roles".to_string(),
+            reason: "Choose at least one role to announce to".to_string(),
+        });
+    }
+    let title = title.trim().to_string();
+    if title.is_empty() || title.len() > MAX_TITLE_LEN {
+        return Err(FoodShareError::Validation {
+            field: "title".to_string(),
+            reason: format!("Title must be between 1 and {} bytes", MAX_TITLE_LEN),
+        });
+    }
+    let body = body.trim().to_string();
+    if body.is_empty() || body.len() > MAX_BODY_LEN {
+        return Err(FoodShareError::Validation {
+            field: "body".to_string(),
+            reason: format!("Body must be between 1 and {} bytes", MAX_BODY_LEN),
+        });
+    }
+    let now = time();
+    if expires_at <= now || expires_at - now > MAX_LIFETIME_NANOS {
+        return Err(FoodShareError::Validation {
+            field: "expires_at".to_string(),
+            reason: "Announcements must expire in the future and within 90 days".to_string(),
+        });
+    }
+
+    remove_expired(now);
+    if ANNOUNCEMENTS_STORAGE.with(|storage| storage.borrow().len()) as usize
+        >= MAX_ACTIVE_ANNOUNCEMENTS
+    {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "At most {} announcements can be active at once",
+                MAX_ACTIVE_ANNOUNCEMENTS
+            ),
+        });
+    }
+
+    let announcement = Announcement {
+        id: next_id(),
+        target_roles,
+        title,
+        body,
+        created_by: ic_cdk::caller(),
+        created_at: now,
+        expires_at,
+    };
+    ANNOUNCEMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(announcement.id, announcement.clone())
+    });
+    audit(
+        AuditAction::Created,
+        AuditEntity::Announcement,
+        announcement.id,
+        None,
+        Some(&announcement),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Announcement {} created for {:?}",
+            announcement.id, announcement.target_roles
+        ),
+    );
+
+    Ok(announcement)
+}
+
+// Function to get the unexpired announcements for the caller's roles, newest
+// first. Admins see every unexpired announcement.
+#[ic_cdk::query]
+fn get_active_announcements() -> Vec<Announcement> {
+    let now = time();
+    let sees_all = is_admin(&ic_cdk::caller());
+    let roles = if sees_all { Vec::new() } else { caller_roles() };
+    let mut announcements: Vec<Announcement> = ANNOUNCEMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, announcement)| announcement)
+            .filter(|announcement| {
+                announcement.expires_at > now
+                    && (sees_all
+                        || announcement
+                            .target_roles
+                            .iter()
+                            .any(|role| roles.contains(role)))
+            })
+            .collect()
+    });
+    announcements.reverse();
+    announcements
+}
@@ -0,0 +1,353 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::require_admin;
+use crate::observability::{current_trace_id, log, resume_trace, start_trace, LogLevel};
+use crate::profile_completeness::check_posting_allowed;
+use crate::verification::check_post_quota;
+use crate::{
+    insert_surplus_post, is_active_donor, next_id, FoodShareError, FoodType, Memory, SurplusPost,
+    SurplusPostPayload, DONORS_STORAGE, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode};
+use chrono::NaiveDate;
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+const TOKEN_PREFIX: &str = "fsk";
+const MAX_QUANTITY_KG: u32 = 100_000;
+const MAX_HANDLING_INSTRUCTIONS_LEN: usize = 500;
+
+// TokenScope is a custom enum type that is used to represent what a machine token may do
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum TokenScope {
+    Posting,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ApiToken {
+    id: u64,
+    donor_id: u64,
+    label: String,
+    scope: TokenScope,
+    secret_hash: Vec<u8>,
+    created_at: u64,
+    last_used_at: Option<u64>,
+    revoked_at: Option<u64>,
+}
+
+impl Storable for ApiToken {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ApiToken {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// ApiTokenInfo is the public view of a token; the secret is never returned after issuance
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ApiTokenInfo {
+    id: u64,
+    donor_id: u64,
+    label: String,
+    scope: TokenScope,
+    created_at: u64,
+    last_used_at: Option<u64>,
+    revoked_at: Option<u64>,
+}
+
+impl From<&ApiToken> for ApiTokenInfo {
+    fn from(token: &ApiToken) -> Self {
+        Self {
+            id: token.id,
+            donor_id: token.donor_id,
+            label: token.label.clone(),
+            scope: token.scope,
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+            revoked_at: token.revoked_at,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct IssuedApiToken {
+    info: ApiTokenInfo,
+    // Only shown once; store it in the integrating system
+    token: String,
+}
+
+// Api Surplus Post Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct ApiSurplusPostPayload {
+    food_type: FoodType,
+    quantity_kg: u32,
+    best_before_date: String,
+    handling_instructions: String,
+}
+
+thread_local! {
+    static API_TOKENS_STORAGE: RefCell<StableBTreeMap<u64, ApiToken, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+    ));
+}
+
+fn hash_secret(secret: &str) -> Vec<u8> {
+    Sha256::digest(secret.as_bytes()).to_vec()
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Function to mint a new secret for a token id, returning the full token string and its hash
+async fn mint_secret(token_id: u64) -> Result<(String, Vec<u8>), FoodShareError> {
+    let trace_id = current_trace_id();
+    let result = raw_rand().await;
+    resume_trace(&trace_id);
+
+    let (random_bytes,) = result.map_err(|(code, msg)| FoodShareError::InternalError {
+        msg: format!("Cannot generate token secret ({:?}): {}", code, msg),
+    })?;
+    let secret = to_hex(&random_bytes);
+    let token = format!("{}_{}_{}", TOKEN_PREFIX, token_id, secret);
+
+    Ok((token, hash_secret(&secret)))
+}
+
+// Function to resolve a presented token into the active token record it belongs to
+fn verify_token(token: &str) -> Result<ApiToken, FoodShareError> {
+    let invalid = || FoodShareError::Unauthorized {
+        msg: "Invalid API token".to_string(),
+    };
+
+    let mut parts = token.splitn(3, '_');
+    if parts.next() != Some(TOKEN_PREFIX) {
+        return Err(invalid());
+    }
+    let token_id: u64 = parts
+        .next()
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(invalid)?;
+    let secret = parts.next().ok_or_else(invalid)?;
+
+    let record = API_TOKENS_STORAGE
+        .with(|storage| storage.borrow().get(&token_id))
+        .ok_or_else(invalid)?;
+    if record.revoked_at.is_some() || record.secret_hash != hash_secret(secret) {
+        return Err(invalid());
+    }
+
+    Ok(record)
+}
+
+// Function to issue a posting-only machine token for a donor
+#[ic_cdk::update]
+async fn issue_api_token(donor_id: u64, label: String) -> Result<IssuedApiToken, FoodShareError> {
+    start_trace("issue_api_token");
+    require_admin("issue API tokens")?;
+
+    let donor_exists = DONORS_STORAGE.with(|storage| storage.borrow().contains_key(&donor_id));
+    if !donor_exists {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Donor with id={} not found", donor_id),
+        });
+    }
+
+    let id = next_id();
+    let (token, secret_hash) = mint_secret(id).await?;
+
+    let record = ApiToken {
+        id,
+        donor_id,
+        label,
+        scope: TokenScope::Posting,
+        secret_hash,
+        created_at: time(),
+        last_used_at: None,
+        revoked_at: None,
+    };
+    API_TOKENS_STORAGE.with(|storage| storage.borrow_mut().insert(id, record.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::ApiToken,
+        id,
+        None,
+        Some(&record),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Issued API token {} for donor {}", id, donor_id),
+    );
+
+    Ok(IssuedApiToken {
+        info: ApiTokenInfo::from(&record),
+        token,
+    })
+}
+
+// Function to replace a token's secret; the previous secret stops working immediately
+#[ic_cdk::update]
+async fn rotate_api_token(token_id: u64) -> Result<IssuedApiToken, FoodShareError> {
+    start_trace("rotate_api_token");
+    require_admin("rotate API tokens")?;
+
+    let record = API_TOKENS_STORAGE
+        .with(|storage| storage.borrow().get(&token_id))
+        .filter(|record| record.revoked_at.is_none())
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Active API token with id={} not found", token_id),
+        })?;
+
+    let (token, secret_hash) = mint_secret(token_id).await?;
+
+    // Re-read after the await so a concurrent revocation is not undone
+    let mut record = API_TOKENS_STORAGE
+        .with(|storage| storage.borrow().get(&record.id))
+        .filter(|record| record.revoked_at.is_none())
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Active API token with id={} not found", token_id),
+        })?;
+    let before = record.clone();
+    record.secret_hash = secret_hash;
+    API_TOKENS_STORAGE.with(|storage| storage.borrow_mut().insert(token_id, record.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::ApiToken,
+        token_id,
+        Some(&before),
+        Some(&record),
+    );
+
+    log(LogLevel::Info, format!("Rotated API token {}", token_id));
+
+    Ok(IssuedApiToken {
+        info: ApiTokenInfo::from(&record),
+        token,
+    })
+}
+
+// Function to permanently revoke a token
+#[ic_cdk::update]
+fn revoke_api_token(token_id: u64) -> Result<ApiTokenInfo, FoodShareError> {
+    start_trace("revoke_api_token");
+    require_admin("revoke API tokens")?;
+
+    let mut record = API_TOKENS_STORAGE
+        .with(|storage| storage.borrow().get(&token_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("API token with id={} not found", token_id),
+        })?;
+    if record.revoked_at.is_none() {
+        let before = record.clone();
+        record.revoked_at = Some(time());
+        API_TOKENS_STORAGE.with(|storage| storage.borrow_mut().insert(token_id, record.clone()));
+        audit(
+            AuditAction::Revoked,
+            AuditEntity::ApiToken,
+            token_id,
+            Some(&before),
+            Some(&record),
+        );
+        log(LogLevel::Info, format!("Revoked API token {}", token_id));
+    }
+
+    Ok(ApiTokenInfo::from(&record))
+}
+
+// Function to list the tokens issued for a donor
+#[ic_cdk::query]
+fn list_api_tokens(donor_id: u64) -> Result<Vec<ApiTokenInfo>, FoodShareError> {
+    require_admin("list API tokens")?;
+
+    Ok(API_TOKENS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, record)| record.donor_id == donor_id)
+            .map(|(_, record)| ApiTokenInfo::from(&record))
+            .collect()
+    }))
+}
+
+// Function for POS/ERP integrations to create a surplus post with a machine token.
+// Machine callers skip the interactive governance check, so the payload
+// schema is checked strictly instead.
+#[ic_cdk::update]
+fn api_create_surplus_post(
+    token: String,
+    payload: ApiSurplusPostPayload,
+) -> Result<SurplusPost, FoodShareError> {
+    start_trace("api_create_surplus_post");
+
+    let mut record = verify_token(&token)?;
+    if record.scope != TokenScope::Posting {
+        return Err(FoodShareError::Unauthorized {
+            msg: "API token is not allowed to create posts".to_string(),
+        });
+    }
+
+    if payload.quantity_kg == 0 || payload.quantity_kg > MAX_QUANTITY_KG {
+        return Err(FoodShareError::Validation {
+            field: "quantity_kg".to_string(),
+            reason: format!("quantity_kg must be between 1 and {}", MAX_QUANTITY_KG),
+        });
+    }
+    if NaiveDate::parse_from_str(&payload.best_before_date, "%Y-%m-%d").is_err() {
+        return Err(FoodShareError::Validation {
+            field: "best_before_date".to_string(),
+            reason: "best_before_date must be formatted as YYYY-MM-DD".to_string(),
+        });
+    }
+    if payload.handling_instructions.len() > MAX_HANDLING_INSTRUCTIONS_LEN {
+        return Err(FoodShareError::Validation {
+            field: "handling_instructions".to_string(),
+            reason: format!(
+                "handling_instructions must be at most {} bytes",
+                MAX_HANDLING_INSTRUCTIONS_LEN
+            ),
+        });
+    }
+
+    if !is_active_donor(record.donor_id) {
+        return Err(FoodShareError::NotFound {
+            msg: "Donor ID does not exist or is deactivated".to_string(),
+        });
+    }
+    check_posting_allowed(record.donor_id, payload.quantity_kg)?;
+    check_post_quota(record.donor_id)?;
+
+    record.last_used_at = Some(time());
+    API_TOKENS_STORAGE.with(|storage| storage.borrow_mut().insert(record.id, record.clone()));
+
+    log(
+        LogLevel::Info,
+        format!("API token {} used to post surplus", record.id),
+    );
+
+    Ok(insert_surplus_post(SurplusPostPayload {
+        donor_id: record.donor_id.to_string(),
+        food_type: payload.food_type,
+        food_category_id: None,
+        organization_id: None,
+        quantity_kg: payload.quantity_kg,
+        best_before_date: payload.best_before_date,
+        handling_instructions: payload.handling_instructions,
+        latitude: None,
+        longitude: None,
+        photo_asset_ids: None,
+        allergens: None,
+        dietary_tags: None,
+    }))
+}
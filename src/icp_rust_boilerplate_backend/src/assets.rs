@@ -0,0 +1,356 @@
+use crate::api_tokens::to_hex;
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::{authorize, EndpointClass};
+use crate::observability::{log, start_timer_trace, start_trace, LogLevel};
+use crate::{next_id, FoodShareError, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_CHUNK_BYTES: u64 = 64 * 1024;
+const MAX_ASSET_BYTES: u64 = 2 * 1024 * 1024;
+// Uploads a principal may have open at once
+const MAX_PENDING_UPLOADS: usize = 5;
+const UPLOAD_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// Uploads not finished within a day are dropped with their chunks
+const UPLOAD_TIMEOUT_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+// Food photos and scanned documents such as hygiene certificates
+const ALLOWED_CONTENT_TYPES: &[&str] =
+    &["image/jpeg", "image/png", "image/webp", "application/pdf"];
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Asset {
+    id: u64,
+    owner: Principal,
+    content_type: String,
+    size: u64,
+    chunk_count: u64,
+    // Hex SHA-256 of the content, set when the upload is finished
+    sha256: Option<String>,
+    created_at: u64,
+    finished_at: Option<u64>,
+}
+
+impl Asset {
+    // Every chunk is full except possibly the last one
+    fn chunk_len(&self, index: u64) -> u64 {
+        if index + 1 == self.chunk_count {
+            self.size - MAX_CHUNK_BYTES * index
+        } else {
+            MAX_CHUNK_BYTES
+        }
+    }
+
+    pub(crate) fn is_image(&self) -> bool {
+        self.content_type.starts_with("image/")
+    }
+
+    pub(crate) fn sha256(&self) -> Option<String> {
+        self.sha256.clone()
+    }
+}
+
+impl Storable for Asset {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Asset {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Raw bytes of one chunk, stored as is
+struct AssetChunk(Vec<u8>);
+
+impl Storable for AssetChunk {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        AssetChunk(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for AssetChunk {
+    const MAX_SIZE: u32 = MAX_CHUNK_BYTES as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static ASSETS: RefCell<StableBTreeMap<u64, Asset, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(62)))
+    ));
+
+    // (asset_id, chunk index)
+    static ASSET_CHUNKS: RefCell<StableBTreeMap<(u64, u64), AssetChunk, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(63)))
+    ));
+}
+
+fn load_asset(asset_id: u64) -> Result<Asset, FoodShareError> {
+    ASSETS
+        .with(|storage| storage.borrow().get(&asset_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Asset with id={} not found", asset_id),
+        })
+}
+
+// Function to load an upload the caller started and has not finished yet
+fn pending_upload(asset_id: u64) -> Result<Asset, FoodShareError> {
+    let asset = load_asset(asset_id)?;
+    if asset.owner != ic_cdk::caller() {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the uploader can add to this asset".to_string(),
+        });
+    }
+    if asset.finished_at.is_some() {
+        return Err(FoodShareError::Conflict {
+            msg: "Asset upload is already finished".to_string(),
+        });
+    }
+    Ok(asset)
+}
+
+// Function to check that an asset was uploaded in full by the caller, so posts
+// and delivery proofs only reference content their author provided
+pub(crate) fn owned_asset(asset_id: u64) -> Result<Asset, FoodShareError> {
+    let asset = load_asset(asset_id)?;
+    if asset.owner != ic_cdk::caller() {
+        return Err(FoodShareError::Unauthorized {
+            msg: format!("Asset {} was uploaded by someone else", asset_id),
+        });
+    }
+    if asset.finished_at.is_none() {
+        return Err(FoodShareError::Validation {
+            field: "asset_id".to_string(),
+            reason: format!("Upload of asset {} is not finished", asset_id),
+        });
+    }
+    Ok(asset)
+}
+
+fn remove_chunks(asset_id: u64) {
+    ASSET_CHUNKS.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let keys: Vec<(u64, u64)> = storage
+            .range((asset_id, 0)..=(asset_id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            storage.remove(&key);
+        }
+    });
+}
+
+// Function to start uploading an asset of a known content type and size; the
+// content is then sent in chunks of at most 64 KiB
+#[ic_cdk::update]
+async fn begin_upload(content_type: String, size: u64) -> Result<Asset, FoodShareError> {
+    start_trace("begin_upload");
+    authorize(EndpointClass::Posting).await?;
+
+    if !ALLOWED_CONTENT_TYPES.contains(&content_type.as_str()) {
+        return Err(FoodShareError::Validation {
+            field: "content_type".to_string(),
+            reason: format!(
+                "Content type must be one of {}",
+                ALLOWED_CONTENT_TYPES.join(", ")
+            ),
+        });
+    }
+    if size == 0 || size > MAX_ASSET_BYTES {
+        return Err(FoodShareError::Validation {
+            field: "size".to_string(),
+            reason: format!("Asset size must be between 1 and {} bytes", MAX_ASSET_BYTES),
+        });
+    }
+
+    let caller = ic_cdk::caller();
+    let pending = ASSETS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, asset)| asset.owner == caller && asset.finished_at.is_none())
+            .count()
+    });
+    if pending >= MAX_PENDING_UPLOADS {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "At most {} uploads can be in progress at once",
+                MAX_PENDING_UPLOADS
+            ),
+        });
+    }
+
+    let asset = Asset {
+        id: next_id(),
+        owner: caller,
+        content_type,
+        size,
+        chunk_count: size.div_ceil(MAX_CHUNK_BYTES),
+        sha256: None,
+        created_at: time(),
+        finished_at: None,
+    };
+    ASSETS.with(|storage| storage.borrow_mut().insert(asset.id, asset.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::Asset,
+        asset.id,
+        None,
+        Some(&asset),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Started upload of asset {} ({} bytes)", asset.id, size),
+    );
+
+    Ok(asset)
+}
+
+// Function to store one chunk of an upload; sending a chunk again replaces it
+#[ic_cdk::update]
+async fn upload_chunk(asset_id: u64, index: u64, bytes: Vec<u8>) -> Result<(), FoodShareError> {
+    start_trace("upload_chunk");
+    authorize(EndpointClass::Posting).await?;
+
+    let asset = pending_upload(asset_id)?;
+    if index >= asset.chunk_count {
+        return Err(FoodShareError::Validation {
+            field: "index".to_string(),
+            reason: format!("Chunk index must be below {}", asset.chunk_count),
+        });
+    }
+    let expected = asset.chunk_len(index);
+    if bytes.len() as u64 != expected {
+        return Err(FoodShareError::Validation {
+            field: "bytes".to_string(),
+            reason: format!("Chunk {} must be {} bytes", index, expected),
+        });
+    }
+
+    ASSET_CHUNKS.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert((asset_id, index), AssetChunk(bytes))
+    });
+
+    Ok(())
+}
+
+// Function to finish an upload once every chunk is stored, fixing its content hash
+#[ic_cdk::update]
+async fn finish_upload(asset_id: u64) -> Result<Asset, FoodShareError> {
+    start_trace("finish_upload");
+    authorize(EndpointClass::Posting).await?;
+
+    let before = pending_upload(asset_id)?;
+    let mut hasher = Sha256::new();
+    ASSET_CHUNKS.with(|storage| {
+        let storage = storage.borrow();
+        for index in 0..before.chunk_count {
+            let chunk = storage
+                .get(&(asset_id, index))
+                .ok_or(FoodShareError::Validation {
+                    field: "asset_id".to_string(),
+                    reason: format!("Chunk {} has not been uploaded", index),
+                })?;
+            hasher.update(&chunk.0);
+        }
+        Ok(())
+    })?;
+
+    let mut asset = before.clone();
+    asset.sha256 = Some(to_hex(&hasher.finalize()));
+    asset.finished_at = Some(time());
+    ASSETS.with(|storage| storage.borrow_mut().insert(asset_id, asset.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::Asset,
+        asset_id,
+        Some(&before),
+        Some(&asset),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Finished upload of asset {}", asset_id),
+    );
+
+    Ok(asset)
+}
+
+// Function to get the details of a finished asset, or of the caller's own upload
+#[ic_cdk::query]
+fn get_asset(asset_id: u64) -> Result<Asset, FoodShareError> {
+    let asset = load_asset(asset_id)?;
+    if asset.finished_at.is_none() && asset.owner != ic_cdk::caller() {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Asset with id={} not found", asset_id),
+        });
+    }
+    Ok(asset)
+}
+
+// Function to read one chunk of a finished asset
+#[ic_cdk::query]
+fn get_asset_chunk(asset_id: u64, index: u64) -> Result<Vec<u8>, FoodShareError> {
+    let asset = load_asset(asset_id)?;
+    if asset.finished_at.is_none() {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Asset with id={} not found", asset_id),
+        });
+    }
+    ASSET_CHUNKS
+        .with(|storage| storage.borrow().get(&(asset_id, index)))
+        .map(|chunk| chunk.0)
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Asset {} has no chunk {}", asset_id, index),
+        })
+}
+
+// Function to register the periodic sweep that drops abandoned uploads
+pub(crate) fn start_upload_pruning() {
+    ic_cdk_timers::set_timer_interval(UPLOAD_PRUNE_INTERVAL, prune_abandoned_uploads);
+}
+
+// Function to delete uploads left unfinished past the timeout, with their chunks
+fn prune_abandoned_uploads() {
+    start_timer_trace("prune_abandoned_uploads");
+
+    let cutoff = time().saturating_sub(UPLOAD_TIMEOUT_NANOS);
+    let abandoned: Vec<u64> = ASSETS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, asset)| asset.finished_at.is_none() && asset.created_at < cutoff)
+            .map(|(id, _)| id)
+            .collect()
+    });
+
+    for asset_id in &abandoned {
+        remove_chunks(*asset_id);
+        ASSETS.with(|storage| storage.borrow_mut().remove(asset_id));
+    }
+
+    if !abandoned.is_empty() {
+        log(
+            LogLevel::Info,
+            format!("Pruned {} abandoned uploads", abandoned.len()),
+        );
+    }
+}
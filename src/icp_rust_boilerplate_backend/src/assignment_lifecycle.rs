@@ -0,0 +1,220 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::cancellation_policy::{
+    cancelling_party, record_cancellation as record_policy_cancellation,
+};
+use crate::delivery_proofs::{submit_delivery, DeliveryProofPayload};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, is_governance_or_admin, EndpointClass};
+use crate::indexes::store_assignment;
+use crate::notifications::{notify_assignment, NotificationKind};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::pickup_confirmations::record_pickup;
+use crate::reputation::record_cancellation;
+use crate::reservations::{record_reservation_outcome, release_post, ReservationOutcome};
+use crate::{
+    Assignment, AssignmentStatus, FoodShareError, ASSIGNMENTS_STORAGE, DRIVERS_STORAGE,
+    RECEIVERS_STORAGE,
+};
+
+const MAX_REASON_LEN: usize = 200;
+
+fn load_assignment(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })
+}
+
+pub(crate) fn is_driver_owner(assignment: &Assignment) -> bool {
+    let caller = ic_cdk::caller();
+    DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.driver_id))
+        .is_some_and(|driver| driver.owner == Some(caller))
+}
+
+pub(crate) fn is_receiver_owner(assignment: &Assignment) -> bool {
+    let caller = ic_cdk::caller();
+    RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.receiver_id))
+        .is_some_and(|receiver| receiver.owner == Some(caller))
+}
+
+// Function to load an assignment the caller may progress as its driver
+fn driver_assignment(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    let assignment = load_assignment(assignment_id)?;
+    if !is_driver_owner(&assignment) && !is_governance_or_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the assigned driver or governance can update this assignment".to_string(),
+        });
+    }
+    Ok(assignment)
+}
+
+fn validate_reason(reason: &str) -> Result<(), FoodShareError> {
+    if reason.trim().is_empty() || reason.len() > MAX_REASON_LEN {
+        return Err(FoodShareError::Validation {
+            field: "reason".to_string(),
+            reason: format!("Reason must be between 1 and {} bytes", MAX_REASON_LEN),
+        });
+    }
+    Ok(())
+}
+
+// Function to apply a transition, store the assignment and record it in the event log
+pub(crate) fn store_transition(
+    mut assignment: Assignment,
+    next: AssignmentStatus,
+    detail: String,
+) -> Result<Assignment, FoodShareError> {
+    let before = assignment.clone();
+    assignment.transition(next)?;
+    store_assignment(&assignment);
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::Assignment,
+        assignment.id,
+        Some(&before),
+        Some(&assignment),
+    );
+    if matches!(next, AssignmentStatus::Cancelled | AssignmentStatus::Failed) {
+        record_cancellation(&assignment);
+    }
+
+    record_event(
+        EntityType::Assignment,
+        assignment.id,
+        Some(assignment.id),
+        EventKind::Updated,
+        detail,
+    );
+    log(
+        LogLevel::Info,
+        format!("Assignment {} moved to {:?}", assignment.id, next),
+    );
+
+    Ok(assignment)
+}
+
+// Function for the driver to accept a pending assignment
+#[ic_cdk::update]
+async fn accept_assignment(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    start_trace("accept_assignment");
+    authorize(EndpointClass::Logistics).await?;
+
+    let assignment = driver_assignment(assignment_id)?;
+    let assignment = store_transition(
+        assignment,
+        AssignmentStatus::Accepted,
+        "Driver accepted the assignment".to_string(),
+    )?;
+    notify_assignment(
+        &assignment,
+        NotificationKind::PickupScheduled,
+        &format!(
+            "Driver {} accepted the assignment and will pick up surplus post {}",
+            assignment.driver_id, assignment.surplus_post_id
+        ),
+    );
+    Ok(assignment)
+}
+
+// Function for the driver to confirm they collected the food from the donor or hub
+#[ic_cdk::update]
+async fn mark_picked_up(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    start_trace("mark_picked_up");
+    authorize(EndpointClass::Logistics).await?;
+
+    let assignment = driver_assignment(assignment_id)?;
+    let assignment = store_transition(
+        assignment,
+        AssignmentStatus::PickedUp,
+        "Driver picked up the food".to_string(),
+    )?;
+    record_pickup(&assignment);
+    Ok(assignment)
+}
+
+// Function for the driver to report they are on the way to the receiver
+#[ic_cdk::update]
+async fn mark_in_transit(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    start_trace("mark_in_transit");
+    authorize(EndpointClass::Logistics).await?;
+
+    let assignment = driver_assignment(assignment_id)?;
+    store_transition(
+        assignment,
+        AssignmentStatus::InTransit,
+        "Driver is in transit to the receiver".to_string(),
+    )
+}
+
+// Function for the driver to complete an assignment, optionally with a photo of
+// the drop-off; the delivery is recorded once the receiver confirms it
+#[ic_cdk::update]
+async fn complete_assignment(
+    assignment_id: u64,
+    proof: Option<DeliveryProofPayload>,
+) -> Result<Assignment, FoodShareError> {
+    start_trace("complete_assignment");
+    authorize(EndpointClass::Logistics).await?;
+
+    let assignment = driver_assignment(assignment_id)?;
+    submit_delivery(assignment, proof.unwrap_or_default())
+}
+
+// Function for the driver, the receiver or governance to cancel an assignment before pickup.
+// A cancelled first leg returns its post to the open listing. Who cancelled and
+// when decides whether the cancellation policy penalizes it.
+#[ic_cdk::update]
+async fn cancel_assignment(
+    assignment_id: u64,
+    reason: String,
+) -> Result<Assignment, FoodShareError> {
+    start_trace("cancel_assignment");
+    authorize(EndpointClass::Logistics).await?;
+
+    validate_reason(&reason)?;
+
+    let assignment = load_assignment(assignment_id)?;
+    let cancelled_by =
+        cancelling_party(is_driver_owner(&assignment), is_receiver_owner(&assignment))?;
+
+    let assignment = store_transition(
+        assignment,
+        AssignmentStatus::Cancelled,
+        format!("Assignment cancelled by {:?}: {}", cancelled_by, reason),
+    )?;
+    record_policy_cancellation(&assignment, cancelled_by, &reason);
+
+    // Onward hub legs do not hold an open post; the food stays at the hub
+    if assignment.previous_leg_id.is_none() {
+        release_post(&assignment);
+        record_reservation_outcome(&assignment, ReservationOutcome::Cancelled);
+    }
+
+    Ok(assignment)
+}
+
+// Function for the driver or governance to report that a picked up delivery could not be completed
+#[ic_cdk::update]
+async fn fail_assignment(assignment_id: u64, reason: String) -> Result<Assignment, FoodShareError> {
+    start_trace("fail_assignment");
+    authorize(EndpointClass::Logistics).await?;
+
+    validate_reason(&reason)?;
+
+    let assignment = driver_assignment(assignment_id)?;
+    let assignment = store_transition(
+        assignment,
+        AssignmentStatus::Failed,
+        format!("Delivery failed: {}", reason),
+    )?;
+
+    // The food has left the donor, so the post is not offered again
+    if assignment.previous_leg_id.is_none() {
+        record_reservation_outcome(&assignment, ReservationOutcome::Cancelled);
+    }
+
+    Ok(assignment)
+}
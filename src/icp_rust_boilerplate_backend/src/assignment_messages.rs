@@ -0,0 +1,449 @@
+use crate::assignment_lifecycle::{is_driver_owner, is_receiver_owner};
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::{authorize, is_governance_or_admin, require_admin, EndpointClass};
+use crate::indexes::{
+    assignments_for_driver, assignments_for_post, assignments_for_receiver, messages_after,
+    posts_for_donor, store_message,
+};
+use crate::notifications::{notify_assignment, NotificationKind};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::pagination::max_page_size;
+use crate::{
+    is_post_donor, next_id, Assignment, AssignmentStatus, FoodShareError, Memory,
+    StorablePrincipal, ASSIGNMENTS_STORAGE, DONORS_STORAGE, DRIVERS_STORAGE, MEMORY_MANAGER,
+    RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_MESSAGE_LEN: usize = 1_000;
+// Largest number of assignment threads one bulk message may reach
+const MAX_BULK_ASSIGNMENTS: usize = 200;
+
+// SenderKind is a custom enum type that is used to represent who wrote a message in an assignment thread
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum SenderKind {
+    Participant,
+    // Sent by a coordinator to many threads at once
+    System,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AssignmentMessage {
+    pub(crate) id: u64,
+    pub(crate) assignment_id: u64,
+    sender: Principal,
+    sender_kind: SenderKind,
+    body: String,
+    pub(crate) created_at: u64,
+    // When a participant other than the sender first marked it read
+    read_at: Option<u64>,
+}
+
+impl Storable for AssignmentMessage {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for AssignmentMessage {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Selects the assignments a bulk message goes to; every criterion given must match
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct ParticipantFilter {
+    // Empty means every assignment that is still in progress
+    statuses: Vec<AssignmentStatus>,
+    // Matches the region of the driver or of the donor
+    region: Option<String>,
+    // Assignments created within [created_from, created_to)
+    created_from: Option<u64>,
+    created_to: Option<u64>,
+}
+
+// One page of an assignment's thread, oldest first. Threads are archived when
+// their assignment closes and take no further messages.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AssignmentThread {
+    assignment_id: u64,
+    archived: bool,
+    // When the assignment reached its closing status
+    archived_at: Option<u64>,
+    messages: Vec<AssignmentMessage>,
+    // Pass back as `cursor` for the next page; None once the thread is read to the end
+    next_cursor: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct UnreadCount {
+    assignment_id: u64,
+    // Messages from others after the last one the caller marked read
+    unread: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct BulkMessageResult {
+    assignment_ids: Vec<u64>,
+    messages_sent: u64,
+}
+
+thread_local! {
+    pub(crate) static ASSIGNMENT_MESSAGES_STORAGE: RefCell<StableBTreeMap<u64, AssignmentMessage, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(57)))
+    ));
+
+    // Id of the last message each reader marked read, per thread
+    static THREAD_READ_MARKERS: RefCell<StableBTreeMap<(StorablePrincipal, u64), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(112)))
+    ));
+}
+
+fn validate_body(body: &str) -> Result<(), FoodShareError> {
+    if body.trim().is_empty() || body.len() > MAX_MESSAGE_LEN {
+        return Err(FoodShareError::Validation {
+            field: "message".to_string(),
+            reason: format!("Message must be between 1 and {} bytes", MAX_MESSAGE_LEN),
+        });
+    }
+    Ok(())
+}
+
+// Function to check whether the caller is the donor, receiver or driver of an assignment
+pub(crate) fn is_participant(assignment: &Assignment) -> bool {
+    is_driver_owner(assignment)
+        || is_receiver_owner(assignment)
+        || SURPLUS_POSTS_STORAGE
+            .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+            .is_some_and(|post| is_post_donor(&post))
+}
+
+fn load_thread_assignment(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    let assignment = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })?;
+    if !is_participant(&assignment) && !is_governance_or_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the participants of this assignment or governance can use its thread"
+                .to_string(),
+        });
+    }
+    Ok(assignment)
+}
+
+// Function to get when the thread of an assignment was archived: the time its
+// assignment reached a closing status, or None while it is still open
+fn archived_at(assignment: &Assignment) -> Option<u64> {
+    if !assignment.status.is_closed() {
+        return None;
+    }
+    assignment
+        .status_history
+        .last()
+        .map(|change| change.changed_at)
+        .or(Some(assignment.created_at))
+}
+
+fn insert_message(assignment_id: u64, sender_kind: SenderKind, body: &str) -> AssignmentMessage {
+    let id = next_id();
+    let message = AssignmentMessage {
+        id,
+        assignment_id,
+        sender: ic_cdk::caller(),
+        sender_kind,
+        body: body.trim().to_string(),
+        created_at: time(),
+        read_at: None,
+    };
+    store_message(&message);
+    audit(
+        AuditAction::Created,
+        AuditEntity::AssignmentMessage,
+        id,
+        None,
+        Some(&message),
+    );
+    message
+}
+
+// Function to write a system message into an assignment's thread, e.g. to
+// explain why the assignment was cancelled
+pub(crate) fn post_system_message(assignment_id: u64, body: &str) -> AssignmentMessage {
+    insert_message(assignment_id, SenderKind::System, body)
+}
+
+fn last_read_id(reader: Principal, assignment_id: u64) -> Option<u64> {
+    THREAD_READ_MARKERS.with(|markers| {
+        markers
+            .borrow()
+            .get(&(StorablePrincipal(reader), assignment_id))
+    })
+}
+
+// Function to get the assignments a principal takes part in through any of
+// their donor, receiver or driver profiles, keyed by id
+fn reader_assignments(reader: Principal) -> BTreeMap<u64, Assignment> {
+    let owner = Some(reader);
+    let donor_ids: Vec<u64> = DONORS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, donor)| donor.owner == owner)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    let receiver_ids: Vec<u64> = RECEIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, receiver)| receiver.owner == owner)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    let driver_ids: Vec<u64> = DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, driver)| driver.owner == owner)
+            .map(|(id, _)| id)
+            .collect()
+    });
+
+    let donor_assignments = donor_ids
+        .into_iter()
+        .flat_map(posts_for_donor)
+        .flat_map(|post| assignments_for_post(post.id));
+    let receiver_assignments = receiver_ids.into_iter().flat_map(assignments_for_receiver);
+    let driver_assignments = driver_ids.into_iter().flat_map(assignments_for_driver);
+    donor_assignments
+        .chain(receiver_assignments)
+        .chain(driver_assignments)
+        .map(|assignment| (assignment.id, assignment))
+        .collect()
+}
+
+fn in_region(assignment: &Assignment, region: &str) -> bool {
+    let driver_region = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.driver_id))
+        .map(|driver| driver.region);
+    let donor_region = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+        .and_then(|post| post.donor_id.parse::<u64>().ok())
+        .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)))
+        .map(|donor| donor.region);
+    [driver_region, donor_region]
+        .into_iter()
+        .flatten()
+        .any(|assignment_region| assignment_region.eq_ignore_ascii_case(region.trim()))
+}
+
+fn matches_filter(assignment: &Assignment, filter: &ParticipantFilter) -> bool {
+    let status_matches = if filter.statuses.is_empty() {
+        assignment.status.is_active()
+    } else {
+        filter.statuses.contains(&assignment.status)
+    };
+    status_matches
+        && !assignment.status.is_closed()
+        && filter
+            .created_from
+            .is_none_or(|from| assignment.created_at >= from)
+        && filter
+            .created_to
+            .is_none_or(|to| assignment.created_at < to)
+        && filter
+            .region
+            .as_deref()
+            .is_none_or(|region| in_region(assignment, region))
+}
+
+// Function for the donor, receiver or driver of an assignment to write in its
+// thread while the assignment is open
+#[ic_cdk::update]
+async fn send_assignment_message(
+    assignment_id: u64,
+    text: String,
+) -> Result<AssignmentMessage, FoodShareError> {
+    start_trace("send_assignment_message");
+    authorize(EndpointClass::Logistics).await?;
+
+    validate_body(&text)?;
+    let assignment = load_thread_assignment(assignment_id)?;
+    if !is_participant(&assignment) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor, receiver and driver of this assignment can write in its thread"
+                .to_string(),
+        });
+    }
+    if assignment.status.is_closed() {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "The thread of assignment {} was archived when it closed",
+                assignment.id
+            ),
+        });
+    }
+    let message = insert_message(assignment.id, SenderKind::Participant, &text);
+
+    log(
+        LogLevel::Info,
+        format!("New message in the thread of assignment {}", assignment.id),
+    );
+
+    Ok(message)
+}
+
+// Function to get the page of the thread of an assignment after a cursor,
+// oldest first; from the start of the thread when there is no cursor
+#[ic_cdk::query]
+fn get_assignment_thread(
+    assignment_id: u64,
+    cursor: Option<u64>,
+    limit: u64,
+) -> Result<AssignmentThread, FoodShareError> {
+    let assignment = load_thread_assignment(assignment_id)?;
+    let archived_at = archived_at(&assignment);
+    let limit = limit.clamp(1, max_page_size()) as usize;
+    let mut messages = messages_after(assignment_id, cursor, limit + 1);
+    let next_cursor = if messages.len() > limit {
+        messages.truncate(limit);
+        messages.last().map(|message| message.id)
+    } else {
+        None
+    };
+    Ok(AssignmentThread {
+        assignment_id,
+        archived: archived_at.is_some(),
+        archived_at,
+        messages,
+        next_cursor,
+    })
+}
+
+// Function for a participant to mark every message in an assignment's thread
+// as read. Messages from others get their read receipt the first time; the
+// number of messages newly read by the caller is returned.
+#[ic_cdk::update]
+fn mark_messages_read(assignment_id: u64) -> Result<u64, FoodShareError> {
+    start_trace("mark_messages_read");
+    let assignment = load_thread_assignment(assignment_id)?;
+    let reader = ic_cdk::caller();
+    let last_read = last_read_id(reader, assignment.id);
+    let now = time();
+
+    let unread = messages_after(assignment.id, last_read, usize::MAX);
+    let latest = unread.last().map(|message| message.id);
+    let mut newly_read = 0;
+    for mut message in unread {
+        if message.sender == reader {
+            continue;
+        }
+        newly_read += 1;
+        if message.read_at.is_none() {
+            message.read_at = Some(now);
+            store_message(&message);
+        }
+    }
+    if let Some(latest) = latest {
+        THREAD_READ_MARKERS.with(|markers| {
+            markers
+                .borrow_mut()
+                .insert((StorablePrincipal(reader), assignment.id), latest)
+        });
+    }
+
+    Ok(newly_read)
+}
+
+// Function to count, per assignment thread the caller takes part in, the
+// messages from others they have not marked read. Threads without unread
+// messages are left out.
+#[ic_cdk::query]
+fn get_unread_counts() -> Vec<UnreadCount> {
+    let reader = ic_cdk::caller();
+    reader_assignments(reader)
+        .into_keys()
+        .filter_map(|assignment_id| {
+            let unread = messages_after(
+                assignment_id,
+                last_read_id(reader, assignment_id),
+                usize::MAX,
+            )
+            .iter()
+            .filter(|message| message.sender != reader)
+            .count() as u64;
+            (unread > 0).then_some(UnreadCount {
+                assignment_id,
+                unread,
+            })
+        })
+        .collect()
+}
+
+// Function for coordinators to send one system message into the thread of
+// every open assignment matching a filter, e.g. when weather cancels a day's
+// routes. Filters matching more than MAX_BULK_ASSIGNMENTS assignments are rejected.
+#[ic_cdk::update]
+fn message_assignment_participants(
+    filter: ParticipantFilter,
+    message: String,
+) -> Result<BulkMessageResult, FoodShareError> {
+    start_trace("message_assignment_participants");
+    require_admin("message assignment participants")?;
+
+    validate_body(&message)?;
+    let assignments: Vec<Assignment> = ASSIGNMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, assignment)| assignment)
+            .filter(|assignment| matches_filter(assignment, &filter))
+            .collect()
+    });
+    if assignments.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: "No assignments match the filter".to_string(),
+        });
+    }
+    if assignments.len() > MAX_BULK_ASSIGNMENTS {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "{} assignments match the filter; narrow it to at most {}",
+                assignments.len(),
+                MAX_BULK_ASSIGNMENTS
+            ),
+        });
+    }
+
+    for assignment in &assignments {
+        insert_message(assignment.id, SenderKind::System, &message);
+        notify_assignment(assignment, NotificationKind::CoordinatorMessage, &message);
+    }
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Coordinator {} messaged the participants of {} assignments",
+            ic_cdk::caller(),
+            assignments.len()
+        ),
+    );
+
+    Ok(BulkMessageResult {
+        messages_sent: assignments.len() as u64,
+        assignment_ids: assignments.iter().map(|assignment| assignment.id).collect(),
+    })
+}
@@ -0,0 +1,195 @@
+use crate::api_tokens::to_hex;
+use crate::governance::require_admin;
+use crate::observability::current_trace_id;
+use crate::pagination::{paginate, Page};
+use crate::{FoodShareError, Memory, MEMORY_MANAGER};
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+// AuditAction is a custom enum type that is used to represent what an update call did to an entity
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum AuditAction {
+    Created,
+    Updated,
+    StatusChanged,
+    Deactivated,
+    Revoked,
+    Configured,
+}
+
+// AuditEntity is a custom enum type that is used to represent the kind of entity an audit event is about.
+// Canister-wide settings have their own variant and entity id 0.
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum AuditEntity {
+    Donor,
+    Receiver,
+    Driver,
+    SurplusPost,
+    Assignment,
+    AssignmentMessage,
+    ContactKey,
+    SurplusRecord,
+    FoodRequest,
+    ApiToken,
+    Asset,
+    CategoryPause,
+    Commitment,
+    DeliveryProof,
+    Dispute,
+    DriverAvailability,
+    FrontendTarget,
+    GroupRequest,
+    SupportConsent,
+    ImpersonationSession,
+    MaintenanceJob,
+    Match,
+    Notification,
+    Payout,
+    PostQuestion,
+    RegionTransfer,
+    ReportAnchor,
+    ResearchConsent,
+    ResearchExtract,
+    Review,
+    StorageAlert,
+    Ticket,
+    GovernanceConfig,
+    EmissionsConfig,
+    RewardConfig,
+    HoldPolicy,
+    CompletenessConfig,
+    DeliveryConfirmationConfig,
+    StorageAlertConfig,
+    PageSize,
+    RadiusExpansionConfig,
+    RecurringTemplate,
+    StandingCommitment,
+    PostMortem,
+    PostMortemConfig,
+    Category,
+    Organization,
+    CategoryLabels,
+    NotificationTemplate,
+    ImpactFactors,
+    DropoffChecklist,
+    PickupConfirmation,
+    PickupConfirmationConfig,
+    Region,
+    Claim,
+    ClaimConfig,
+    DonorLead,
+    LeadIntakeConfig,
+    Route,
+    TemperatureLog,
+    ColdChainConfig,
+    VerificationRequest,
+    VerificationConfig,
+    RateLimitConfig,
+    Settings,
+    QuantityReservation,
+    GeocodingConfig,
+    Webhook,
+    Announcement,
+    CancellationPolicy,
+    Admin,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AuditEvent {
+    id: u64,
+    // Links the audit event to the log entries of the same call
+    trace_id: String,
+    actor: Principal,
+    action: AuditAction,
+    entity_type: AuditEntity,
+    entity_id: u64,
+    // sha256 of the Candid encoding of the entity, hex encoded; None before creation
+    before_hash: Option<String>,
+    after_hash: Option<String>,
+    timestamp: u64,
+}
+
+impl Storable for AuditEvent {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for AuditEvent {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static AUDIT_EVENTS: RefCell<StableBTreeMap<u64, AuditEvent, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(49)))
+    ));
+}
+
+fn value_hash<T: CandidType>(value: &T) -> String {
+    to_hex(&Sha256::digest(Encode!(value).unwrap()))
+}
+
+// Function to append an audit event for a change made by the current update
+// call. `before` and `after` are the entity as it was and as it is now.
+pub(crate) fn audit<T: CandidType>(
+    action: AuditAction,
+    entity_type: AuditEntity,
+    entity_id: u64,
+    before: Option<&T>,
+    after: Option<&T>,
+) {
+    AUDIT_EVENTS.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let id = storage.len();
+        storage.insert(
+            id,
+            AuditEvent {
+                id,
+                trace_id: current_trace_id(),
+                actor: ic_cdk::caller(),
+                action,
+                entity_type,
+                entity_id,
+                before_hash: before.map(value_hash),
+                after_hash: after.map(value_hash),
+                timestamp: time(),
+            },
+        );
+    });
+}
+
+// Function to get every recorded change to an entity, oldest first
+#[ic_cdk::query]
+fn get_audit_trail(entity_type: AuditEntity, entity_id: u64) -> Vec<AuditEvent> {
+    AUDIT_EVENTS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, event)| event)
+            .filter(|event| event.entity_type == entity_type && event.entity_id == entity_id)
+            .collect()
+    })
+}
+
+// Function for admins to page through audit events recorded at or after `timestamp`
+#[ic_cdk::query]
+fn get_audit_events_since(
+    timestamp: u64,
+    offset: u64,
+    limit: u64,
+) -> Result<Page<AuditEvent>, FoodShareError> {
+    require_admin("read the audit log")?;
+    Ok(paginate(&AUDIT_EVENTS, offset, limit, |event| {
+        event.timestamp >= timestamp
+    }))
+}
@@ -0,0 +1,267 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, is_admin, EndpointClass};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    add_driver_profile, add_receiver_profile, add_surplus_post, contact_vault, has_driver_profile,
+    has_receiver_profile, organizations, registering_principal, DriverPayload, DriverProfile,
+    FoodShareError, ReceiverPayload, ReceiverProfile, SurplusPost, SurplusPostPayload,
+    DRIVERS_STORAGE, RECEIVERS_STORAGE,
+};
+use candid::Principal;
+
+const MAX_BATCH_SIZE: usize = 200;
+
+// One receiver to onboard in bulk
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct ReceiverBatchItem {
+    // Principal the profile belongs to; None leaves it unowned until an owner is assigned
+    owner: Option<Principal>,
+    profile: ReceiverPayload,
+}
+
+// One driver to onboard in bulk
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct DriverBatchItem {
+    // Principal the profile belongs to; None leaves it unowned until an owner is assigned
+    owner: Option<Principal>,
+    profile: DriverPayload,
+}
+
+// Function to check that the caller may onboard in bulk: admins always may,
+// others must manage the organization they onboard for
+fn check_batch<T>(organization_id: Option<u64>, items: &[T]) -> Result<(), FoodShareError> {
+    if items.is_empty() || items.len() > MAX_BATCH_SIZE {
+        return Err(FoodShareError::Validation {
+            field: "payloads".to_string(),
+            reason: format!("A batch must have between 1 and {} items", MAX_BATCH_SIZE),
+        });
+    }
+    if is_admin(&ic_cdk::caller()) {
+        return Ok(());
+    }
+    match organization_id {
+        Some(organization_id) => organizations::check_manager(organization_id),
+        None => Err(FoodShareError::Unauthorized {
+            msg: "Only admins or organization managers can create in bulk".to_string(),
+        }),
+    }
+}
+
+fn log_batch<T>(kind: &str, results: &[Result<T, FoodShareError>]) {
+    let created = results.iter().filter(|result| result.is_ok()).count();
+    log(
+        LogLevel::Info,
+        format!("Batch created {} of {} {}", created, results.len(), kind),
+    );
+}
+
+// Function to check the owner given for a bulk profile: a signed-in principal
+// without an active profile of the same role
+fn check_owner(
+    owner: Principal,
+    already_registered: fn(Principal) -> bool,
+) -> Result<(), FoodShareError> {
+    if owner == Principal::anonymous() {
+        return Err(FoodShareError::Validation {
+            field: "owner".to_string(),
+            reason: "Anonymous principals cannot own profiles".to_string(),
+        });
+    }
+    if already_registered(owner) {
+        return Err(FoodShareError::Conflict {
+            msg: format!("{} already has a profile of this kind", owner),
+        });
+    }
+    Ok(())
+}
+
+// Function for admins or organization managers to register many receivers in
+// one call. Each item is validated on its own, so one bad row does not stop
+// the rest. Profiles belong to the owner given per item, or to nobody until
+// one is assigned, and record the organization they were onboarded for.
+#[ic_cdk::update]
+async fn batch_create_receivers(
+    organization_id: Option<u64>,
+    items: Vec<ReceiverBatchItem>,
+) -> Result<Vec<Result<ReceiverProfile, FoodShareError>>, FoodShareError> {
+    start_trace("batch_create_receivers");
+    authorize(EndpointClass::Registration).await?;
+    registering_principal()?;
+    check_batch(organization_id, &items)?;
+    contact_vault::ensure_key().await?;
+
+    let results: Vec<_> = items
+        .into_iter()
+        .map(|item| {
+            if let Some(owner) = item.owner {
+                check_owner(owner, has_receiver_profile)?;
+            }
+            add_receiver_profile(item.profile, item.owner, organization_id)
+        })
+        .collect();
+    log_batch("receivers", &results);
+
+    Ok(results)
+}
+
+// Function for admins or organization managers to register many drivers in
+// one call, with a result per item and the same ownership rules as receivers
+#[ic_cdk::update]
+async fn batch_create_drivers(
+    organization_id: Option<u64>,
+    items: Vec<DriverBatchItem>,
+) -> Result<Vec<Result<DriverProfile, FoodShareError>>, FoodShareError> {
+    start_trace("batch_create_drivers");
+    authorize(EndpointClass::Registration).await?;
+    registering_principal()?;
+    check_batch(organization_id, &items)?;
+    contact_vault::ensure_key().await?;
+
+    let results: Vec<_> = items
+        .into_iter()
+        .map(|item| {
+            if let Some(owner) = item.owner {
+                check_owner(owner, has_driver_profile)?;
+            }
+            add_driver_profile(item.profile, item.owner, organization_id)
+        })
+        .collect();
+    log_batch("drivers", &results);
+
+    Ok(results)
+}
+
+// Function to check that the caller may assign the owner of a profile
+// onboarded in bulk: admins, or managers of the organization it was onboarded for
+fn check_assigner(
+    owner: Option<Principal>,
+    organization_id: Option<u64>,
+) -> Result<(), FoodShareError> {
+    if owner.is_some() {
+        return Err(FoodShareError::Conflict {
+            msg: "The profile already has an owner".to_string(),
+        });
+    }
+    if is_admin(&ic_cdk::caller()) {
+        return Ok(());
+    }
+    match organization_id {
+        Some(organization_id) => organizations::check_manager(organization_id),
+        None => Err(FoodShareError::Unauthorized {
+            msg: "Only admins can assign an owner to this profile".to_string(),
+        }),
+    }
+}
+
+// Function for admins or managers of the onboarding organization to hand an
+// unowned receiver profile to the principal it belongs to
+#[ic_cdk::update]
+async fn assign_receiver_owner(
+    receiver_id: u64,
+    owner: Principal,
+) -> Result<ReceiverProfile, FoodShareError> {
+    start_trace("assign_receiver_owner");
+    authorize(EndpointClass::Registration).await?;
+
+    let before = RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&receiver_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Receiver ID does not exist".to_string(),
+        })?;
+    check_assigner(before.owner, before.organization_id)?;
+    check_owner(owner, has_receiver_profile)?;
+
+    let mut receiver = before.clone();
+    receiver.owner = Some(owner);
+    RECEIVERS_STORAGE.with(|storage| storage.borrow_mut().insert(receiver_id, receiver.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::Receiver,
+        receiver_id,
+        Some(&before),
+        Some(&receiver),
+    );
+    record_event(
+        EntityType::Receiver,
+        receiver_id,
+        None,
+        EventKind::Updated,
+        format!("Receiver {} was assigned an owner", receiver.name),
+    );
+    log(
+        LogLevel::Info,
+        format!("Assigned {} as owner of receiver {}", owner, receiver_id),
+    );
+
+    Ok(contact_vault::redacted(receiver))
+}
+
+// Function for admins or managers of the onboarding organization to hand an
+// unowned driver profile to the principal it belongs to
+#[ic_cdk::update]
+async fn assign_driver_owner(
+    driver_id: u64,
+    owner: Principal,
+) -> Result<DriverProfile, FoodShareError> {
+    start_trace("assign_driver_owner");
+    authorize(EndpointClass::Registration).await?;
+
+    let before = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&driver_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Driver ID does not exist".to_string(),
+        })?;
+    check_assigner(before.owner, before.organization_id)?;
+    check_owner(owner, has_driver_profile)?;
+
+    let mut driver = before.clone();
+    driver.owner = Some(owner);
+    DRIVERS_STORAGE.with(|storage| storage.borrow_mut().insert(driver_id, driver.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::Driver,
+        driver_id,
+        Some(&before),
+        Some(&driver),
+    );
+    record_event(
+        EntityType::Driver,
+        driver_id,
+        None,
+        EventKind::Updated,
+        format!("Driver {} was assigned an owner", driver.name),
+    );
+    log(
+        LogLevel::Info,
+        format!("Assigned {} as owner of driver {}", owner, driver_id),
+    );
+
+    Ok(contact_vault::redacted(driver))
+}
+
+// Function for admins or organization managers to create many surplus posts in
+// one call, with the same checks as single posts and a result per item
+#[ic_cdk::update]
+async fn batch_create_surplus_posts(
+    organization_id: Option<u64>,
+    payloads: Vec<SurplusPostPayload>,
+) -> Result<Vec<Result<SurplusPost, FoodShareError>>, FoodShareError> {
+    start_trace("batch_create_surplus_posts");
+    authorize(EndpointClass::Posting).await?;
+    check_batch(organization_id, &payloads)?;
+
+    let results: Vec<_> = payloads
+        .into_iter()
+        .map(|mut payload| {
+            // Posts made for an organization must be for donors it acts for
+            if organization_id.is_some() {
+                payload.organization_id = organization_id;
+            }
+            add_surplus_post(payload)
+        })
+        .collect();
+    log_batch("surplus posts", &results);
+
+    Ok(results)
+}
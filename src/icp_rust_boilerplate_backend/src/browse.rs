@@ -0,0 +1,208 @@
+use crate::category_pauses::is_food_type_paused;
+use crate::frontend_notify::{queue_post_change, PostChangeKind};
+use crate::maintenance::{scan_batch, ScanStep};
+use crate::{FoodType, Memory, SurplusPost, MEMORY_MANAGER, SURPLUS_POSTS_STORAGE};
+use candid::{Decode, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::ops::Bound;
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_PAGE_SIZE: u64 = 100;
+
+// PostSummary is the lightweight view of an open surplus post used for browsing
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PostSummary {
+    id: u64,
+    donor_id: String,
+    food_type: FoodType,
+    quantity_kg: u32,
+    best_before_date: String,
+}
+
+impl Storable for PostSummary {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for PostSummary {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PostSummaryPage {
+    items: Vec<PostSummary>,
+    next_cursor: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct OpenPostPage {
+    items: Vec<SurplusPost>,
+    next_cursor: Option<u64>,
+}
+
+thread_local! {
+    // Summaries of open posts only, kept in id order so keyset pages are a range scan
+    static OPEN_POST_SUMMARIES: RefCell<StableBTreeMap<u64, PostSummary, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))
+    ));
+}
+
+pub(crate) fn post_summary(post: &SurplusPost) -> PostSummary {
+    PostSummary {
+        id: post.id,
+        donor_id: post.donor_id.clone(),
+        food_type: post.food_type,
+        quantity_kg: post.quantity_kg,
+        best_before_date: post.best_before_date.clone(),
+    }
+}
+
+// Function to add or refresh the summary of an open post
+pub(crate) fn index_open_post(post: &SurplusPost) {
+    let summary = post_summary(post);
+    OPEN_POST_SUMMARIES.with(|storage| storage.borrow_mut().insert(post.id, summary));
+    queue_post_change(post.id, PostChangeKind::Published);
+}
+
+// Function to drop a post from the open listing once it is no longer available
+pub(crate) fn remove_open_post(post_id: u64) {
+    if OPEN_POST_SUMMARIES
+        .with(|storage| storage.borrow_mut().remove(&post_id))
+        .is_some()
+    {
+        queue_post_change(post_id, PostChangeKind::Closed);
+    }
+}
+
+// Function to check whether a post belongs in the open listing
+pub(crate) fn is_listable(post: &SurplusPost) -> bool {
+    !post.assigned && post.withdrawn_at.is_none() && post.expired_at.is_none()
+}
+
+// Function to get the ids of every open post, in id order
+pub(crate) fn open_post_ids() -> Vec<u64> {
+    OPEN_POST_SUMMARIES.with(|storage| storage.borrow().iter().map(|(id, _)| id).collect())
+}
+
+// Function to list open posts in one batch that are missing from the listing
+pub(crate) fn fill_open_posts(cursor: Option<&[u8]>) -> ScanStep {
+    let (posts, next_cursor) =
+        SURPLUS_POSTS_STORAGE.with(|storage| scan_batch(&storage.borrow(), cursor));
+    let mut step = ScanStep {
+        next_cursor,
+        scanned: posts.len() as u64,
+        ..Default::default()
+    };
+    for (_, post) in posts.iter().filter(|(_, post)| is_listable(post)) {
+        if !OPEN_POST_SUMMARIES.with(|storage| storage.borrow().contains_key(&post.id)) {
+            index_open_post(post);
+            step.inserted += 1;
+        }
+    }
+    step
+}
+
+// Function to drop summaries in one batch whose post is gone or no longer open
+pub(crate) fn prune_open_posts(cursor: Option<&[u8]>) -> ScanStep {
+    let (summaries, next_cursor) =
+        OPEN_POST_SUMMARIES.with(|storage| scan_batch(&storage.borrow(), cursor));
+    let mut step = ScanStep {
+        next_cursor,
+        scanned: summaries.len() as u64,
+        ..Default::default()
+    };
+    for (post_id, _) in summaries {
+        let listable = SURPLUS_POSTS_STORAGE
+            .with(|storage| storage.borrow().get(&post_id))
+            .is_some_and(|post| is_listable(&post));
+        if !listable {
+            remove_open_post(post_id);
+            step.removed += 1;
+        }
+    }
+    step
+}
+
+// Function to read one page of open post summaries strictly after the cursor,
+// keeping only the summaries accepted by `filter`
+fn summary_page(
+    after_post_id: Option<u64>,
+    limit: u64,
+    filter: impl Fn(&PostSummary) -> bool,
+) -> (Vec<PostSummary>, Option<u64>) {
+    let limit = limit.clamp(1, MAX_PAGE_SIZE) as usize;
+    let start = match after_post_id {
+        Some(after) => Bound::Excluded(after),
+        None => Bound::Unbounded,
+    };
+
+    OPEN_POST_SUMMARIES.with(|storage| {
+        let storage = storage.borrow();
+        let mut items: Vec<PostSummary> = storage
+            .range((start, Bound::Unbounded))
+            .map(|(_, summary)| summary)
+            .filter(|summary| filter(summary))
+            .take(limit + 1)
+            .collect();
+
+        // Fetching one extra row tells us whether another page exists
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|summary| summary.id)
+        } else {
+            None
+        };
+
+        (items, next_cursor)
+    })
+}
+
+// Function to get a page of open post summaries for infinite scrolling
+#[ic_cdk::query]
+fn get_post_summaries(after_post_id: Option<u64>, limit: u64) -> PostSummaryPage {
+    let (items, next_cursor) = summary_page(after_post_id, limit, |_| true);
+
+    PostSummaryPage { items, next_cursor }
+}
+
+// Function to get a page of full open surplus posts using the same cursor as the summaries
+#[ic_cdk::query]
+fn get_open_surplus_posts(after_post_id: Option<u64>, limit: u64) -> OpenPostPage {
+    let (summaries, next_cursor) = summary_page(after_post_id, limit, |_| true);
+
+    open_post_page(summaries, next_cursor)
+}
+
+// Function to get a page of open surplus posts without the food types a receiver has paused
+#[ic_cdk::query]
+fn get_open_surplus_posts_for_receiver(
+    receiver_id: u64,
+    after_post_id: Option<u64>,
+    limit: u64,
+) -> OpenPostPage {
+    let (summaries, next_cursor) = summary_page(after_post_id, limit, |summary| {
+        !is_food_type_paused(receiver_id, summary.food_type)
+    });
+
+    open_post_page(summaries, next_cursor)
+}
+
+fn open_post_page(summaries: Vec<PostSummary>, next_cursor: Option<u64>) -> OpenPostPage {
+    let items = SURPLUS_POSTS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        summaries
+            .iter()
+            .filter_map(|summary| storage.get(&summary.id))
+            .collect()
+    });
+
+    OpenPostPage { items, next_cursor }
+}
@@ -0,0 +1,385 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::{is_governance_or_admin, require_admin};
+use crate::notifications::{notify_driver, NotificationKind};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::reputation::record_late_cancellation;
+use crate::{
+    Assignment, AssignmentStatus, DriverProfile, FoodShareError, Memory, ASSIGNMENTS_STORAGE,
+    MEMORY_MANAGER,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+const NANOS_PER_DAY: u64 = 24 * 60 * NANOS_PER_MINUTE;
+const MAX_FREE_WINDOW_MINUTES: u64 = 24 * 60;
+const MAX_SUSPENSION_DAYS: u64 = 90;
+
+// CancellingParty is a custom enum type that is used to represent who cancelled an assignment
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum CancellingParty {
+    Driver,
+    Receiver,
+    // Admins or the governance canister; never penalized
+    Governance,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CancellationPolicy {
+    // Minutes after the driver accepts during which either side may still
+    // cancel without a penalty; cancelling before acceptance is always free
+    free_window_minutes: u64,
+    // Late driver cancellations after the pickup window opened that lead to a suspension
+    no_show_limit: u32,
+    suspension_days: u64,
+}
+
+impl Default for CancellationPolicy {
+    fn default() -> Self {
+        Self {
+            free_window_minutes: 15,
+            no_show_limit: 3,
+            suspension_days: 7,
+        }
+    }
+}
+
+impl Storable for CancellationPolicy {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CancellationRecord {
+    assignment_id: u64,
+    cancelled_by: CancellingParty,
+    reason: String,
+    cancelled_at: u64,
+    // Cancelled after the free window, which counts against the party
+    penalized: bool,
+    // The driver cancelled after the agreed pickup window had opened
+    no_show: bool,
+}
+
+impl Storable for CancellationRecord {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for CancellationRecord {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct DriverStanding {
+    driver_id: u64,
+    // No-shows since the last suspension
+    no_shows: u32,
+    suspensions: u32,
+    suspended_until: Option<u64>,
+}
+
+impl Storable for DriverStanding {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DriverStanding {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static CANCELLATION_POLICY: RefCell<Cell<CancellationPolicy, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(115))),
+            CancellationPolicy::default(),
+        )
+        .expect("Cannot create the cancellation policy")
+    );
+
+    // Keyed by assignment
+    static CANCELLATION_RECORDS: RefCell<StableBTreeMap<u64, CancellationRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(116)))
+    ));
+
+    // Keyed by driver
+    static DRIVER_STANDINGS: RefCell<StableBTreeMap<u64, DriverStanding, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(117)))
+    ));
+}
+
+fn policy() -> CancellationPolicy {
+    CANCELLATION_POLICY.with(|cell| cell.borrow().get().clone())
+}
+
+fn standing(driver_id: u64) -> DriverStanding {
+    DRIVER_STANDINGS
+        .with(|storage| storage.borrow().get(&driver_id))
+        .unwrap_or(DriverStanding {
+            driver_id,
+            ..Default::default()
+        })
+}
+
+// Function to work out which side the caller cancels an assignment as
+pub(crate) fn cancelling_party(
+    is_driver: bool,
+    is_receiver: bool,
+) -> Result<CancellingParty, FoodShareError> {
+    if is_driver {
+        Ok(CancellingParty::Driver)
+    } else if is_receiver {
+        Ok(CancellingParty::Receiver)
+    } else if is_governance_or_admin(&ic_cdk::caller()) {
+        Ok(CancellingParty::Governance)
+    } else {
+        Err(FoodShareError::Unauthorized {
+            msg: "Only the driver, the receiver or governance can cancel this assignment"
+                .to_string(),
+        })
+    }
+}
+
+// Function to reject assigning a driver who is suspended for no-shows
+pub(crate) fn check_not_suspended(driver: &DriverProfile) -> Result<(), FoodShareError> {
+    match standing(driver.id).suspended_until {
+        Some(until) if until > time() => Err(FoodShareError::Conflict {
+            msg: format!(
+                "Driver {} is suspended for repeated no-shows until {}",
+                driver.id, until
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Function to get when the driver accepted the assignment, if they did
+fn accepted_at(assignment: &Assignment) -> Option<u64> {
+    assignment
+        .status_history
+        .iter()
+        .find(|change| change.status == AssignmentStatus::Accepted)
+        .map(|change| change.changed_at)
+}
+
+// Function to apply the cancellation policy to an assignment that was just
+// cancelled: late cancellations count against the driver's reputation, and a
+// driver reaching the no-show limit is suspended
+pub(crate) fn record_cancellation(
+    assignment: &Assignment,
+    cancelled_by: CancellingParty,
+    reason: &str,
+) -> CancellationRecord {
+    let policy = policy();
+    let now = time();
+    let penalized = cancelled_by != CancellingParty::Governance
+        && accepted_at(assignment).is_some_and(|accepted_at| {
+            now > accepted_at + policy.free_window_minutes * NANOS_PER_MINUTE
+        });
+    let no_show = penalized
+        && cancelled_by == CancellingParty::Driver
+        && assignment
+            .pickup_window_start
+            .is_some_and(|window_start| now >= window_start);
+    let record = CancellationRecord {
+        assignment_id: assignment.id,
+        cancelled_by,
+        reason: reason.to_string(),
+        cancelled_at: now,
+        penalized,
+        no_show,
+    };
+    CANCELLATION_RECORDS.with(|storage| storage.borrow_mut().insert(assignment.id, record.clone()));
+
+    if penalized && cancelled_by == CancellingParty::Driver {
+        record_late_cancellation(assignment.driver_id, no_show);
+    }
+    if no_show {
+        let mut standing = standing(assignment.driver_id);
+        standing.no_shows += 1;
+        if policy.no_show_limit > 0 && standing.no_shows >= policy.no_show_limit {
+            let until = now + policy.suspension_days * NANOS_PER_DAY;
+            standing.no_shows = 0;
+            standing.suspensions += 1;
+            standing.suspended_until = Some(until);
+            notify_driver(
+                assignment,
+                NotificationKind::DriverSuspended,
+                &format!(
+                    "You are suspended from new assignments for {} days after {} no-shows",
+                    policy.suspension_days, policy.no_show_limit
+                ),
+            );
+            log(
+                LogLevel::Warn,
+                format!(
+                    "Driver {} suspended until {} after repeated no-shows",
+                    assignment.driver_id, until
+                ),
+            );
+        }
+        DRIVER_STANDINGS
+            .with(|storage| storage.borrow_mut().insert(assignment.driver_id, standing));
+    }
+
+    record
+}
+
+// Function to list (driver_id, no_show) for every penalized driver
+// cancellation, for rebuilding reputation
+pub(crate) fn penalized_driver_cancellations() -> Vec<(u64, bool)> {
+    let records: Vec<CancellationRecord> = CANCELLATION_RECORDS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, record)| record)
+            .filter(|record| record.penalized && record.cancelled_by == CancellingParty::Driver)
+            .collect()
+    });
+    records
+        .into_iter()
+        .filter_map(|record| {
+            ASSIGNMENTS_STORAGE
+                .with(|storage| storage.borrow().get(&record.assignment_id))
+                .map(|assignment| (assignment.driver_id, record.no_show))
+        })
+        .collect()
+}
+
+// Function to get the cancellation policy
+#[ic_cdk::query]
+fn get_cancellation_policy() -> CancellationPolicy {
+    policy()
+}
+
+// Function for admins to set the free cancellation window and the no-show suspension rule.
+// A no-show limit of 0 turns suspensions off.
+#[ic_cdk::update]
+fn set_cancellation_policy(
+    policy: CancellationPolicy,
+) -> Result<CancellationPolicy, FoodShareError> {
+    start_trace("set_cancellation_policy");
+    require_admin("update the cancellation policy")?;
+
+    if policy.free_window_minutes > MAX_FREE_WINDOW_MINUTES {
+        return Err(FoodShareError::Validation {
+            field: "free_window_minutes".to_string(),
+            reason: format!(
+                "Free cancellation window can be at most {} minutes",
+                MAX_FREE_WINDOW_MINUTES
+            ),
+        });
+    }
+    if !(1..=MAX_SUSPENSION_DAYS).contains(&policy.suspension_days) {
+        return Err(FoodShareError::Validation {
+            field: "suspension_days".to_string(),
+            reason: format!(
+                "Suspensions must last between 1 and {} days",
+                MAX_SUSPENSION_DAYS
+            ),
+        });
+    }
+
+    let before = CANCELLATION_POLICY
+        .with(|cell| cell.borrow_mut().set(policy.clone()))
+        .expect("Cannot update the cancellation policy");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::CancellationPolicy,
+        0,
+        Some(&before),
+        Some(&policy),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Cancellations are free for {} minutes after acceptance; {} no-shows suspend a driver for {} days",
+            policy.free_window_minutes, policy.no_show_limit, policy.suspension_days
+        ),
+    );
+
+    Ok(policy)
+}
+
+// Function to get who cancelled an assignment and whether it was penalized
+#[ic_cdk::query]
+fn get_cancellation_record(assignment_id: u64) -> Result<CancellationRecord, FoodShareError> {
+    if ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .is_none()
+    {
+        return Err(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        });
+    }
+    CANCELLATION_RECORDS
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!(
+                "Assignment {} was not cancelled through cancel_assignment",
+                assignment_id
+            ),
+        })
+}
+
+// Function to get a driver's no-show count and suspension
+#[ic_cdk::query]
+fn get_driver_standing(driver_id: u64) -> DriverStanding {
+    standing(driver_id)
+}
+
+// Function for admins to lift a driver's suspension early
+#[ic_cdk::update]
+fn lift_driver_suspension(driver_id: u64) -> Result<DriverStanding, FoodShareError> {
+    start_trace("lift_driver_suspension");
+    require_admin("lift driver suspensions")?;
+
+    let before = standing(driver_id);
+    if before.suspended_until.is_none_or(|until| until <= time()) {
+        return Err(FoodShareError::Conflict {
+            msg: format!("Driver {} is not suspended", driver_id),
+        });
+    }
+    let after = DriverStanding {
+        suspended_until: None,
+        ..before.clone()
+    };
+    DRIVER_STANDINGS.with(|storage| storage.borrow_mut().insert(driver_id, after.clone()));
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::Driver,
+        driver_id,
+        Some(&before),
+        Some(&after),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Suspension of driver {} lifted", driver_id),
+    );
+
+    Ok(after)
+}
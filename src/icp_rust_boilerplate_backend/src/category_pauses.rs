@@ -0,0 +1,191 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::{authorize, EndpointClass};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{FoodShareError, FoodType, Memory, MEMORY_MANAGER, RECEIVERS_STORAGE};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const MAX_PAUSE_SECS: u64 = 90 * 24 * 60 * 60;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CategoryPause {
+    food_type: FoodType,
+    paused_at: u64,
+    // None pauses the food type until the receiver resumes it
+    paused_until: Option<u64>,
+}
+
+impl CategoryPause {
+    fn is_active(&self, now: u64) -> bool {
+        self.paused_until.is_none_or(|until| until > now)
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct ReceiverCategoryPauses {
+    pauses: Vec<CategoryPause>,
+}
+
+impl Storable for ReceiverCategoryPauses {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ReceiverCategoryPauses {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static CATEGORY_PAUSES_STORAGE: RefCell<StableBTreeMap<u64, ReceiverCategoryPauses, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+    ));
+}
+
+// Function to check whether a receiver currently declines a food type
+pub(crate) fn is_food_type_paused(receiver_id: u64, food_type: FoodType) -> bool {
+    let now = time();
+    CATEGORY_PAUSES_STORAGE
+        .with(|storage| storage.borrow().get(&receiver_id))
+        .is_some_and(|entry| {
+            entry
+                .pauses
+                .iter()
+                .any(|pause| pause.food_type == food_type && pause.is_active(now))
+        })
+}
+
+fn active_pauses(receiver_id: u64) -> Vec<CategoryPause> {
+    let now = time();
+    CATEGORY_PAUSES_STORAGE
+        .with(|storage| storage.borrow().get(&receiver_id))
+        .map(|entry| {
+            entry
+                .pauses
+                .into_iter()
+                .filter(|pause| pause.is_active(now))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn store_pauses(receiver_id: u64, pauses: Vec<CategoryPause>) {
+    audit(
+        AuditAction::Updated,
+        AuditEntity::CategoryPause,
+        receiver_id,
+        Some(&active_pauses(receiver_id)),
+        Some(&pauses),
+    );
+    CATEGORY_PAUSES_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if pauses.is_empty() {
+            storage.remove(&receiver_id);
+        } else {
+            storage.insert(receiver_id, ReceiverCategoryPauses { pauses });
+        }
+    });
+}
+
+// Function for a receiver to stop accepting a food type, optionally for a limited time
+#[ic_cdk::update]
+async fn pause_food_type(
+    receiver_id: u64,
+    food_type: FoodType,
+    duration_secs: Option<u64>,
+) -> Result<Vec<CategoryPause>, FoodShareError> {
+    start_trace("pause_food_type");
+    authorize(EndpointClass::Posting).await?;
+
+    let receiver_exists =
+        RECEIVERS_STORAGE.with(|storage| storage.borrow().contains_key(&receiver_id));
+    if !receiver_exists {
+        return Err(FoodShareError::NotFound {
+            msg: "Receiver ID does not exist".to_string(),
+        });
+    }
+    if duration_secs.is_some_and(|secs| secs == 0 || secs > MAX_PAUSE_SECS) {
+        return Err(FoodShareError::Validation {
+            field: "duration_secs".to_string(),
+            reason: format!(
+                "Pause duration must be between 1 and {} seconds",
+                MAX_PAUSE_SECS
+            ),
+        });
+    }
+
+    let now = time();
+    // Expired pauses are dropped whenever the receiver changes their toggles
+    let mut pauses: Vec<CategoryPause> = active_pauses(receiver_id)
+        .into_iter()
+        .filter(|pause| pause.food_type != food_type)
+        .collect();
+    pauses.push(CategoryPause {
+        food_type,
+        paused_at: now,
+        paused_until: duration_secs.map(|secs| now + secs * NANOS_PER_SECOND),
+    });
+    store_pauses(receiver_id, pauses.clone());
+
+    log(
+        LogLevel::Info,
+        format!("Receiver {} paused {:?}", receiver_id, food_type),
+    );
+
+    Ok(pauses)
+}
+
+// Function for a receiver to accept a paused food type again
+#[ic_cdk::update]
+async fn resume_food_type(
+    receiver_id: u64,
+    food_type: FoodType,
+) -> Result<Vec<CategoryPause>, FoodShareError> {
+    start_trace("resume_food_type");
+    authorize(EndpointClass::Posting).await?;
+
+    let receiver_exists =
+        RECEIVERS_STORAGE.with(|storage| storage.borrow().contains_key(&receiver_id));
+    if !receiver_exists {
+        return Err(FoodShareError::NotFound {
+            msg: "Receiver ID does not exist".to_string(),
+        });
+    }
+
+    let pauses: Vec<CategoryPause> = active_pauses(receiver_id)
+        .into_iter()
+        .filter(|pause| pause.food_type != food_type)
+        .collect();
+    store_pauses(receiver_id, pauses.clone());
+
+    log(
+        LogLevel::Info,
+        format!("Receiver {} resumed {:?}", receiver_id, food_type),
+    );
+
+    Ok(pauses)
+}
+
+// Function to get the food types a receiver is currently not accepting
+#[ic_cdk::query]
+fn get_category_pauses(receiver_id: u64) -> Result<Vec<CategoryPause>, FoodShareError> {
+    let receiver_exists =
+        RECEIVERS_STORAGE.with(|storage| storage.borrow().contains_key(&receiver_id));
+    if !receiver_exists {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Receiver with id={} not found", receiver_id),
+        });
+    }
+
+    Ok(active_pauses(receiver_id))
+}
@@ -0,0 +1,19 @@
+use crate::{report_anchors, transparency};
+use sha2::{Digest, Sha256};
+
+// Function to fold every certified value into the canister's single certified
+// data slot: sha256(report chain head || transparency root)
+pub(crate) fn certified_root(chain_head: &[u8], transparency_root: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(chain_head);
+    hasher.update(transparency_root);
+    hasher.finalize().to_vec()
+}
+
+// Function to publish the current root as certified data. Certified data is
+// cleared on upgrade, so this also runs from post_upgrade.
+pub(crate) fn certify() {
+    let (chain_head, _) = report_anchors::chain_head();
+    let root = certified_root(&chain_head, &transparency::transparency_root());
+    ic_cdk::api::set_certified_data(&root);
+}
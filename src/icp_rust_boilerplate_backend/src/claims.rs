@@ -0,0 +1,491 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::category_pauses::is_food_type_paused;
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, is_admin, require_admin, EndpointClass};
+use crate::matching::matched_receiver;
+use crate::observability::{log, start_timer_trace, start_trace, LogLevel};
+use crate::{
+    next_id, FoodShareError, Memory, SurplusPost, DONORS_STORAGE, MEMORY_MANAGER,
+    RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+const CLAIM_SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// The sweep runs every 5 minutes, so shorter windows would not be honoured
+const MIN_RESPONSE_MINUTES: u64 = 15;
+const MAX_RESPONSE_MINUTES: u64 = 24 * 60;
+const MAX_QUEUE_LEN: usize = 20;
+
+// ClaimStatus is a custom enum type that is used to represent where a receiver's claim on a post stands
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ClaimStatus {
+    // Waiting behind earlier claims
+    Queued,
+    // First in the queue, waiting for the donor to decide
+    Pending,
+    // The post is held for the receiver until a driver is assigned
+    Approved,
+    Declined,
+    // The donor did not decide in time, or the post closed first
+    Expired,
+}
+
+impl ClaimStatus {
+    fn is_open(self) -> bool {
+        matches!(self, ClaimStatus::Queued | ClaimStatus::Pending)
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Claim {
+    pub(crate) id: u64,
+    pub(crate) post_id: u64,
+    pub(crate) receiver_id: u64,
+    pub(crate) status: ClaimStatus,
+    claimed_at: u64,
+    // When the claim reached the front of the queue
+    pending_since: Option<u64>,
+    decided_at: Option<u64>,
+}
+
+impl Storable for Claim {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Claim {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ClaimConfig {
+    // Minutes the donor has to decide on the claim at the front of the queue
+    response_minutes: u64,
+}
+
+impl Default for ClaimConfig {
+    fn default() -> Self {
+        Self {
+            response_minutes: 60,
+        }
+    }
+}
+
+impl Storable for ClaimConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static CLAIM_CONFIG: RefCell<Cell<ClaimConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(84))),
+            ClaimConfig::default(),
+        )
+        .expect("Cannot create the claim config")
+    );
+
+    static CLAIMS: RefCell<StableBTreeMap<u64, Claim, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(85)))
+    ));
+
+    // (post_id, claim_id); claim ids grow, so a post's claims come out in queue order
+    static CLAIMS_BY_POST: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(86)))
+    ));
+}
+
+fn claims_for_post(post_id: u64) -> Vec<Claim> {
+    let ids: Vec<u64> = CLAIMS_BY_POST.with(|index| {
+        index
+            .borrow()
+            .range((post_id, 0)..=(post_id, u64::MAX))
+            .map(|((_, claim_id), _)| claim_id)
+            .collect()
+    });
+    CLAIMS.with(|storage| {
+        let storage = storage.borrow();
+        ids.iter().filter_map(|id| storage.get(id)).collect()
+    })
+}
+
+// Function to get the receiver a post is held for by an approved claim
+pub(crate) fn approved_receiver(post_id: u64) -> Option<u64> {
+    claims_for_post(post_id)
+        .into_iter()
+        .find(|claim| claim.status == ClaimStatus::Approved)
+        .map(|claim| claim.receiver_id)
+}
+
+// Function to get every approved claim, oldest first
+pub(crate) fn approved_claims() -> Vec<Claim> {
+    CLAIMS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, claim)| claim)
+            .filter(|claim| claim.status == ClaimStatus::Approved)
+            .collect()
+    })
+}
+
+pub(crate) fn is_open_post(post: &SurplusPost) -> bool {
+    !post.assigned && post.withdrawn_at.is_none() && post.expired_at.is_none()
+}
+
+fn store_claim(before: Option<&Claim>, claim: &Claim) {
+    CLAIMS.with(|storage| storage.borrow_mut().insert(claim.id, claim.clone()));
+    audit(
+        match before {
+            None => AuditAction::Created,
+            Some(_) => AuditAction::StatusChanged,
+        },
+        AuditEntity::Claim,
+        claim.id,
+        before,
+        Some(claim),
+    );
+}
+
+// Function to close a claim with a final status
+fn close_claim(claim: &Claim, status: ClaimStatus) {
+    let mut closed = claim.clone();
+    closed.status = status;
+    closed.decided_at = Some(time());
+    store_claim(Some(claim), &closed);
+}
+
+// Function to move the next queued claim on a post to the front, if nothing
+// is pending or approved ahead of it
+fn advance_queue(post_id: u64) {
+    let claims = claims_for_post(post_id);
+    if claims
+        .iter()
+        .any(|claim| matches!(claim.status, ClaimStatus::Pending | ClaimStatus::Approved))
+    {
+        return;
+    }
+    let Some(next) = claims
+        .iter()
+        .find(|claim| claim.status == ClaimStatus::Queued)
+    else {
+        return;
+    };
+
+    let mut pending = next.clone();
+    pending.status = ClaimStatus::Pending;
+    pending.pending_since = Some(time());
+    store_claim(Some(next), &pending);
+    record_event(
+        EntityType::SurplusPost,
+        post_id,
+        None,
+        EventKind::Updated,
+        format!(
+            "Claim of receiver {} is waiting for the donor",
+            pending.receiver_id
+        ),
+    );
+}
+
+// Function for a receiver to join the queue for an open post; the first claim
+// waits for the donor straight away, later ones once those ahead are settled
+#[ic_cdk::update]
+async fn claim_surplus_post(post_id: u64) -> Result<Claim, FoodShareError> {
+    start_trace("claim_surplus_post");
+    authorize(EndpointClass::Posting).await?;
+
+    let post = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&post_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Surplus post ID does not exist".to_string(),
+        })?;
+    if !is_open_post(&post) {
+        return Err(FoodShareError::Conflict {
+            msg: "Only open posts can be claimed".to_string(),
+        });
+    }
+
+    let caller = ic_cdk::caller();
+    let receiver = RECEIVERS_STORAGE
+        .with(|storage| {
+            storage
+                .borrow()
+                .iter()
+                .map(|(_, receiver)| receiver)
+                .find(|receiver| receiver.active && receiver.owner == Some(caller))
+        })
+        .ok_or(FoodShareError::Unauthorized {
+            msg: "Only receivers can claim posts".to_string(),
+        })?;
+    if is_food_type_paused(receiver.id, post.food_type) {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Receiver is not accepting {:?} at the moment",
+                post.food_type
+            ),
+        });
+    }
+    if matched_receiver(post_id).is_some_and(|receiver_id| receiver_id != receiver.id) {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus post is matched to another receiver".to_string(),
+        });
+    }
+
+    let claims = claims_for_post(post_id);
+    if claims
+        .iter()
+        .any(|claim| claim.receiver_id == receiver.id && claim.status != ClaimStatus::Expired)
+    {
+        return Err(FoodShareError::Conflict {
+            msg: "Receiver has already claimed this post".to_string(),
+        });
+    }
+    if claims.iter().filter(|claim| claim.status.is_open()).count() >= MAX_QUEUE_LEN {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!("At most {} receivers can queue for a post", MAX_QUEUE_LEN),
+        });
+    }
+
+    let claim = Claim {
+        id: next_id(),
+        post_id,
+        receiver_id: receiver.id,
+        status: ClaimStatus::Queued,
+        claimed_at: time(),
+        pending_since: None,
+        decided_at: None,
+    };
+    store_claim(None, &claim);
+    CLAIMS_BY_POST.with(|index| index.borrow_mut().insert((post_id, claim.id), ()));
+    advance_queue(post_id);
+
+    log(
+        LogLevel::Info,
+        format!("Receiver {} claimed post {}", receiver.id, post_id),
+    );
+
+    load_claim(claim.id)
+}
+
+pub(crate) fn load_claim(claim_id: u64) -> Result<Claim, FoodShareError> {
+    CLAIMS
+        .with(|storage| storage.borrow().get(&claim_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Claim ID does not exist".to_string(),
+        })
+}
+
+// Function to check the caller owns the post's donor profile and get the
+// claim waiting for the donor from a receiver
+fn pending_claim(post_id: u64, receiver_id: u64) -> Result<Claim, FoodShareError> {
+    let post = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&post_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Surplus post ID does not exist".to_string(),
+        })?;
+    let caller = ic_cdk::caller();
+    let is_donor = post
+        .donor_id
+        .parse::<u64>()
+        .ok()
+        .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)))
+        .is_some_and(|donor| donor.owner == Some(caller));
+    if !is_donor && !is_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor can decide on claims for this post".to_string(),
+        });
+    }
+    if !is_open_post(&post) {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus post is no longer open".to_string(),
+        });
+    }
+
+    let claim = claims_for_post(post_id)
+        .into_iter()
+        .rev()
+        .find(|claim| claim.receiver_id == receiver_id)
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Receiver {} has not claimed this post", receiver_id),
+        })?;
+    if claim.status != ClaimStatus::Pending {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Only the claim at the front of the queue can be decided; this one is {:?}",
+                claim.status
+            ),
+        });
+    }
+    Ok(claim)
+}
+
+// Function for the donor to approve the claim at the front of the queue; the
+// post is then held for that receiver until a driver is assigned
+#[ic_cdk::update]
+async fn approve_claim(post_id: u64, receiver_id: u64) -> Result<Claim, FoodShareError> {
+    start_trace("approve_claim");
+    authorize(EndpointClass::Posting).await?;
+
+    let claim = pending_claim(post_id, receiver_id)?;
+    close_claim(&claim, ClaimStatus::Approved);
+    record_event(
+        EntityType::SurplusPost,
+        post_id,
+        None,
+        EventKind::Updated,
+        format!("Donor approved the claim of receiver {}", receiver_id),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Approved claim {} of receiver {} on post {}",
+            claim.id, receiver_id, post_id
+        ),
+    );
+
+    load_claim(claim.id)
+}
+
+// Function for the donor to decline the claim at the front of the queue and
+// offer the post to the next receiver
+#[ic_cdk::update]
+async fn decline_claim(post_id: u64, receiver_id: u64) -> Result<Claim, FoodShareError> {
+    start_trace("decline_claim");
+    authorize(EndpointClass::Posting).await?;
+
+    let claim = pending_claim(post_id, receiver_id)?;
+    close_claim(&claim, ClaimStatus::Declined);
+    advance_queue(post_id);
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Declined claim {} of receiver {} on post {}",
+            claim.id, receiver_id, post_id
+        ),
+    );
+
+    load_claim(claim.id)
+}
+
+// Function to get the claims on a post in queue order
+#[ic_cdk::query]
+fn get_claim_queue(post_id: u64) -> Result<Vec<Claim>, FoodShareError> {
+    if SURPLUS_POSTS_STORAGE.with(|storage| !storage.borrow().contains_key(&post_id)) {
+        return Err(FoodShareError::NotFound {
+            msg: "Surplus post ID does not exist".to_string(),
+        });
+    }
+    Ok(claims_for_post(post_id))
+}
+
+// Function to register the periodic sweep that passes lapsed claims to the next receiver
+pub(crate) fn start_claim_sweep() {
+    ic_cdk_timers::set_timer_interval(CLAIM_SWEEP_INTERVAL, expire_claims);
+}
+
+// Function to expire claims the donor left undecided past the response window,
+// and the open claims of posts that closed, then move each queue along
+fn expire_claims() {
+    start_timer_trace("expire_claims");
+
+    let response_minutes = CLAIM_CONFIG.with(|cell| cell.borrow().get().response_minutes);
+    let cutoff = time().saturating_sub(response_minutes * NANOS_PER_MINUTE);
+    let open: Vec<Claim> = CLAIMS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, claim)| claim)
+            .filter(|claim| claim.status.is_open())
+            .collect()
+    });
+
+    let mut expired = 0;
+    let mut advanced = Vec::new();
+    for claim in open {
+        let post_open = SURPLUS_POSTS_STORAGE
+            .with(|storage| storage.borrow().get(&claim.post_id))
+            .is_some_and(|post| is_open_post(&post));
+        let lapsed = claim.status == ClaimStatus::Pending
+            && claim.pending_since.is_some_and(|since| since <= cutoff);
+        if !post_open || lapsed {
+            close_claim(&claim, ClaimStatus::Expired);
+            expired += 1;
+            if post_open && !advanced.contains(&claim.post_id) {
+                advanced.push(claim.post_id);
+            }
+        }
+    }
+    for post_id in advanced {
+        advance_queue(post_id);
+    }
+
+    if expired > 0 {
+        log(LogLevel::Info, format!("Expired {} claims", expired));
+    }
+}
+
+#[ic_cdk::query]
+fn get_claim_config() -> ClaimConfig {
+    CLAIM_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Function for admins to set how long donors have to decide on a claim before
+// it passes to the next receiver
+#[ic_cdk::update]
+fn set_claim_config(config: ClaimConfig) -> Result<ClaimConfig, FoodShareError> {
+    start_trace("set_claim_config");
+    require_admin("update the claim config")?;
+
+    if !(MIN_RESPONSE_MINUTES..=MAX_RESPONSE_MINUTES).contains(&config.response_minutes) {
+        return Err(FoodShareError::Validation {
+            field: "response_minutes".to_string(),
+            reason: format!(
+                "Response window must be between {} and {} minutes",
+                MIN_RESPONSE_MINUTES, MAX_RESPONSE_MINUTES
+            ),
+        });
+    }
+
+    let before = CLAIM_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the claim config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::ClaimConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Donors now have {} minutes to decide on a claim",
+            config.response_minutes
+        ),
+    );
+
+    Ok(config)
+}
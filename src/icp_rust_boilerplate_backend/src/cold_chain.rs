@@ -0,0 +1,360 @@
+use crate::assignment_lifecycle::{is_driver_owner, is_receiver_owner};
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, is_governance_or_admin, require_admin, EndpointClass};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    AssignmentStatus, FoodShareError, FoodType, Memory, ASSIGNMENTS_STORAGE, MEMORY_MANAGER,
+    SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_READINGS: usize = 200;
+// Device clocks drift, so readings may be stamped slightly ahead of the canister
+const MAX_CLOCK_SKEW_NANOS: u64 = 5 * 60 * 1_000_000_000;
+const MIN_CELSIUS: f64 = -60.0;
+const MAX_CELSIUS: f64 = 80.0;
+
+// ColdChainStatus is a custom enum type that is used to represent whether a delivery stayed cold enough
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ColdChainStatus {
+    // No reading has gone above the food type's threshold, or it has none
+    WithinLimits,
+    ColdChainViolated,
+}
+
+// Temperature Reading Payload
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TemperatureReading {
+    assignment_id: u64,
+    celsius: f64,
+    // When the driver's thermometer took the reading
+    recorded_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct LoggedReading {
+    celsius: f64,
+    recorded_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TemperatureLog {
+    assignment_id: u64,
+    food_type: FoodType,
+    // Threshold in force when the first reading was logged
+    max_celsius: Option<f64>,
+    // Oldest first
+    readings: Vec<LoggedReading>,
+    status: ColdChainStatus,
+    // Reading time of the first reading above the threshold
+    violated_at: Option<u64>,
+}
+
+impl Storable for TemperatureLog {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for TemperatureLog {
+    const MAX_SIZE: u32 = 8192;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ColdChainThreshold {
+    food_type: FoodType,
+    max_celsius: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ColdChainConfig {
+    // Food types without a threshold are logged but never flagged
+    thresholds: Vec<ColdChainThreshold>,
+}
+
+impl Default for ColdChainConfig {
+    fn default() -> Self {
+        Self {
+            thresholds: vec![
+                ColdChainThreshold {
+                    food_type: FoodType::Dairy,
+                    max_celsius: 5.0,
+                },
+                ColdChainThreshold {
+                    food_type: FoodType::Meat,
+                    max_celsius: 4.0,
+                },
+            ],
+        }
+    }
+}
+
+impl Storable for ColdChainConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static COLD_CHAIN_CONFIG: RefCell<Cell<ColdChainConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(92))),
+            ColdChainConfig::default(),
+        )
+        .expect("Cannot create the cold chain config")
+    );
+
+    // Keyed by assignment
+    static TEMPERATURE_LOGS: RefCell<StableBTreeMap<u64, TemperatureLog, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(93)))
+    ));
+}
+
+fn max_celsius_for(food_type: FoodType) -> Option<f64> {
+    COLD_CHAIN_CONFIG.with(|cell| {
+        cell.borrow()
+            .get()
+            .thresholds
+            .iter()
+            .find(|threshold| threshold.food_type == food_type)
+            .map(|threshold| threshold.max_celsius)
+    })
+}
+
+// Function to check whether a food type has to travel in a refrigerated vehicle
+pub(crate) fn requires_refrigeration(food_type: FoodType) -> bool {
+    max_celsius_for(food_type).is_some()
+}
+
+// Function for the driver to log a temperature reading while carrying the
+// food; the delivery is flagged once a reading goes above its food type's
+// threshold, and stays flagged
+#[ic_cdk::update]
+async fn record_temperature(reading: TemperatureReading) -> Result<TemperatureLog, FoodShareError> {
+    start_trace("record_temperature");
+    authorize(EndpointClass::Logistics).await?;
+
+    if !(MIN_CELSIUS..=MAX_CELSIUS).contains(&reading.celsius) {
+        return Err(FoodShareError::Validation {
+            field: "celsius".to_string(),
+            reason: format!(
+                "Temperature must be between {} and {} degrees",
+                MIN_CELSIUS, MAX_CELSIUS
+            ),
+        });
+    }
+    if reading.recorded_at > time() + MAX_CLOCK_SKEW_NANOS {
+        return Err(FoodShareError::Validation {
+            field: "recorded_at".to_string(),
+            reason: "Reading time is in the future".to_string(),
+        });
+    }
+
+    let assignment = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&reading.assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })?;
+    if !is_driver_owner(&assignment) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the assigned driver can log temperatures".to_string(),
+        });
+    }
+    if !matches!(
+        assignment.status,
+        AssignmentStatus::PickedUp | AssignmentStatus::InTransit
+    ) {
+        return Err(FoodShareError::Conflict {
+            msg: "Temperatures can only be logged while the food is in transit".to_string(),
+        });
+    }
+    let picked_up_at = assignment
+        .status_history
+        .iter()
+        .find(|change| change.status == AssignmentStatus::PickedUp)
+        .map_or(0, |change| change.changed_at);
+    if reading.recorded_at < picked_up_at {
+        return Err(FoodShareError::Validation {
+            field: "recorded_at".to_string(),
+            reason: "Reading was taken before the food was picked up".to_string(),
+        });
+    }
+
+    let mut log_entry = match TEMPERATURE_LOGS.with(|storage| storage.borrow().get(&assignment.id))
+    {
+        Some(log_entry) => log_entry,
+        None => {
+            let food_type = SURPLUS_POSTS_STORAGE
+                .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+                .map(|post| post.food_type)
+                .unwrap_or_default();
+            TemperatureLog {
+                assignment_id: assignment.id,
+                food_type,
+                max_celsius: max_celsius_for(food_type),
+                readings: Vec::new(),
+                status: ColdChainStatus::WithinLimits,
+                violated_at: None,
+            }
+        }
+    };
+    if log_entry.readings.len() >= MAX_READINGS {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "At most {} readings can be logged per delivery",
+                MAX_READINGS
+            ),
+        });
+    }
+
+    let position = log_entry
+        .readings
+        .partition_point(|logged| logged.recorded_at <= reading.recorded_at);
+    log_entry.readings.insert(
+        position,
+        LoggedReading {
+            celsius: reading.celsius,
+            recorded_at: reading.recorded_at,
+        },
+    );
+
+    let violated = log_entry
+        .max_celsius
+        .is_some_and(|max_celsius| reading.celsius > max_celsius);
+    if violated {
+        let before = log_entry.clone();
+        let first_violation = log_entry.status == ColdChainStatus::WithinLimits;
+        log_entry.status = ColdChainStatus::ColdChainViolated;
+        log_entry.violated_at = Some(
+            log_entry
+                .violated_at
+                .map_or(reading.recorded_at, |at| at.min(reading.recorded_at)),
+        );
+        if first_violation {
+            audit(
+                AuditAction::StatusChanged,
+                AuditEntity::TemperatureLog,
+                assignment.id,
+                Some(&before),
+                Some(&log_entry),
+            );
+            record_event(
+                EntityType::Assignment,
+                assignment.id,
+                Some(assignment.id),
+                EventKind::Updated,
+                format!(
+                    "Cold chain violated: {:.1} C is above the {:?} limit of {:.1} C",
+                    reading.celsius,
+                    log_entry.food_type,
+                    log_entry.max_celsius.unwrap_or_default()
+                ),
+            );
+            log(
+                LogLevel::Warn,
+                format!("Cold chain violated on assignment {}", assignment.id),
+            );
+        }
+    }
+    TEMPERATURE_LOGS.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(assignment.id, log_entry.clone())
+    });
+
+    Ok(log_entry)
+}
+
+// Function for the driver, the receiver or governance to get the temperature
+// readings of a delivery and whether it stayed cold enough
+#[ic_cdk::query]
+fn get_temperature_log(assignment_id: u64) -> Result<TemperatureLog, FoodShareError> {
+    let assignment = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })?;
+    if !is_driver_owner(&assignment)
+        && !is_receiver_owner(&assignment)
+        && !is_governance_or_admin(&ic_cdk::caller())
+    {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the driver, the receiver or governance can view this log".to_string(),
+        });
+    }
+    TEMPERATURE_LOGS
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "No temperatures have been logged for this assignment".to_string(),
+        })
+}
+
+#[ic_cdk::query]
+fn get_cold_chain_config() -> ColdChainConfig {
+    COLD_CHAIN_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Function for admins to set the highest temperature each food type may reach
+// in transit; deliveries already logging keep the threshold they started with
+#[ic_cdk::update]
+fn set_cold_chain_config(config: ColdChainConfig) -> Result<ColdChainConfig, FoodShareError> {
+    start_trace("set_cold_chain_config");
+    require_admin("update the cold chain config")?;
+
+    for (index, threshold) in config.thresholds.iter().enumerate() {
+        if !(MIN_CELSIUS..=MAX_CELSIUS).contains(&threshold.max_celsius) {
+            return Err(FoodShareError::Validation {
+                field: "thresholds".to_string(),
+                reason: format!(
+                    "Thresholds must be between {} and {} degrees",
+                    MIN_CELSIUS, MAX_CELSIUS
+                ),
+            });
+        }
+        if config.thresholds[..index]
+            .iter()
+            .any(|other| other.food_type == threshold.food_type)
+        {
+            return Err(FoodShareError::Validation {
+                field: "thresholds".to_string(),
+                reason: format!("{:?} has more than one threshold", threshold.food_type),
+            });
+        }
+    }
+
+    let before = COLD_CHAIN_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the cold chain config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::ColdChainConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Cold chain thresholds set for {} food types",
+            config.thresholds.len()
+        ),
+    );
+
+    Ok(config)
+}
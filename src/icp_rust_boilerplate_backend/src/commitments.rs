@@ -0,0 +1,325 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::{authorize, is_governance_or_admin, require_admin, EndpointClass};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    next_id, FoodShareError, Memory, DONORS_STORAGE, MEMORY_MANAGER, SURPLUS_POSTS_STORAGE,
+    SURPLUS_RECORDS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// CommitmentPeriod is a custom enum type that is used to represent how often a commitment target resets
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum CommitmentPeriod {
+    Weekly,
+    Monthly,
+}
+
+impl CommitmentPeriod {
+    // Periods have a fixed length counted from the commitment start, so a
+    // "monthly" period is 30 days rather than a calendar month
+    fn length_nanos(&self) -> u64 {
+        match self {
+            CommitmentPeriod::Weekly => 7 * NANOS_PER_DAY,
+            CommitmentPeriod::Monthly => 30 * NANOS_PER_DAY,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Commitment {
+    id: u64,
+    donor_id: u64,
+    target_kg: u64,
+    period: CommitmentPeriod,
+    starts_at: u64,
+    ended_at: Option<u64>,
+    created_at: u64,
+}
+
+impl Storable for Commitment {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Commitment {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Commitment Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct CommitmentPayload {
+    donor_id: u64,
+    target_kg: u64,
+    period: CommitmentPeriod,
+    // Defaults to now
+    starts_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CommitmentProgress {
+    commitment: Commitment,
+    period_number: u64,
+    period_start: u64,
+    period_end: u64,
+    delivered_kg: u64,
+    target_kg: u64,
+    fulfillment_percent: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CommitmentVariance {
+    commitment_id: u64,
+    donor_id: u64,
+    period_start: u64,
+    period_end: u64,
+    target_kg: u64,
+    delivered_kg: u64,
+    // Positive when the donor delivered more than committed
+    variance_kg: i64,
+}
+
+thread_local! {
+    static COMMITMENTS_STORAGE: RefCell<StableBTreeMap<u64, Commitment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)))
+    ));
+}
+
+// Function to sum the kg of a donor's posts delivered within [start, end).
+// A post is counted once even when a hub flow delivers it over several legs.
+fn delivered_kg(donor_id: u64, start: u64, end: u64) -> u64 {
+    let mut post_ids: Vec<u64> = SURPLUS_RECORDS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, record)| (start..end).contains(&record.delivered_at))
+            .map(|(_, record)| record.surplus_post_id)
+            .collect()
+    });
+    post_ids.sort_unstable();
+    post_ids.dedup();
+
+    SURPLUS_POSTS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        post_ids
+            .iter()
+            .filter_map(|id| storage.get(id))
+            .filter(|post| post.donor_id == donor_id.to_string())
+            .map(|post| post.quantity_kg as u64)
+            .sum()
+    })
+}
+
+// Function to find the bounds of the period containing `at`
+fn period_bounds(commitment: &Commitment, at: u64) -> (u64, u64, u64) {
+    let length = commitment.period.length_nanos();
+    let number = at.saturating_sub(commitment.starts_at) / length;
+    let start = commitment.starts_at + number * length;
+    (number, start, start + length)
+}
+
+fn progress(commitment: Commitment) -> CommitmentProgress {
+    let now = commitment.ended_at.unwrap_or_else(time);
+    let (period_number, period_start, period_end) = period_bounds(&commitment, now);
+    let delivered_kg = delivered_kg(commitment.donor_id, period_start, period_end);
+    let fulfillment_percent = if commitment.target_kg == 0 {
+        0.0
+    } else {
+        delivered_kg as f64 * 100.0 / commitment.target_kg as f64
+    };
+
+    CommitmentProgress {
+        target_kg: commitment.target_kg,
+        commitment,
+        period_number,
+        period_start,
+        period_end,
+        delivered_kg,
+        fulfillment_percent,
+    }
+}
+
+// Function to record a donor's recurring supply commitment
+#[ic_cdk::update]
+async fn create_commitment(payload: CommitmentPayload) -> Result<Commitment, FoodShareError> {
+    start_trace("create_commitment");
+    authorize(EndpointClass::Posting).await?;
+
+    let donor = DONORS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.donor_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Donor ID does not exist".to_string(),
+        })?;
+
+    let caller = ic_cdk::caller();
+    if donor.owner != Some(caller) && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor or governance can sign a commitment".to_string(),
+        });
+    }
+    if payload.target_kg == 0 {
+        return Err(FoodShareError::Validation {
+            field: "target_kg".to_string(),
+            reason: "Commitment target must be greater than zero".to_string(),
+        });
+    }
+
+    let id = next_id();
+    let now = time();
+    let commitment = Commitment {
+        id,
+        donor_id: payload.donor_id,
+        target_kg: payload.target_kg,
+        period: payload.period,
+        starts_at: payload.starts_at.unwrap_or(now),
+        ended_at: None,
+        created_at: now,
+    };
+
+    COMMITMENTS_STORAGE.with(|storage| storage.borrow_mut().insert(id, commitment.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::Commitment,
+        id,
+        None,
+        Some(&commitment),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Donor {} committed {} kg {:?}",
+            commitment.donor_id, commitment.target_kg, commitment.period
+        ),
+    );
+
+    Ok(commitment)
+}
+
+// Function to end a commitment; its last period remains in reports
+#[ic_cdk::update]
+fn end_commitment(commitment_id: u64) -> Result<Commitment, FoodShareError> {
+    start_trace("end_commitment");
+
+    let mut commitment = COMMITMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&commitment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Commitment with id={} not found", commitment_id),
+        })?;
+
+    let caller = ic_cdk::caller();
+    let is_owner = DONORS_STORAGE
+        .with(|storage| storage.borrow().get(&commitment.donor_id))
+        .is_some_and(|donor| donor.owner == Some(caller));
+    if !is_owner && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor or governance can end a commitment".to_string(),
+        });
+    }
+
+    if commitment.ended_at.is_none() {
+        let before = commitment.clone();
+        commitment.ended_at = Some(time());
+        COMMITMENTS_STORAGE.with(|storage| {
+            storage
+                .borrow_mut()
+                .insert(commitment_id, commitment.clone())
+        });
+        audit(
+            AuditAction::StatusChanged,
+            AuditEntity::Commitment,
+            commitment_id,
+            Some(&before),
+            Some(&commitment),
+        );
+        log(
+            LogLevel::Info,
+            format!("Ended commitment {}", commitment_id),
+        );
+    }
+
+    Ok(commitment)
+}
+
+// Function to get the current-period progress of a commitment
+#[ic_cdk::query]
+fn get_commitment_progress(commitment_id: u64) -> Result<CommitmentProgress, FoodShareError> {
+    COMMITMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&commitment_id))
+        .map(progress)
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Commitment with id={} not found", commitment_id),
+        })
+}
+
+// Function to get the progress of every commitment a donor has signed
+#[ic_cdk::query]
+fn get_donor_commitments(donor_id: u64) -> Result<Vec<CommitmentProgress>, FoodShareError> {
+    let commitments: Vec<Commitment> = COMMITMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, commitment)| commitment.donor_id == donor_id)
+            .map(|(_, commitment)| commitment)
+            .collect()
+    });
+
+    if commitments.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: "No commitments found.".to_string(),
+        });
+    }
+
+    Ok(commitments.into_iter().map(progress).collect())
+}
+
+// Function for coordinators to get the variance of the last completed period of every commitment
+#[ic_cdk::query]
+fn get_commitment_variance_report() -> Result<Vec<CommitmentVariance>, FoodShareError> {
+    require_admin("view commitment reports")?;
+
+    let now = time();
+    let commitments: Vec<Commitment> = COMMITMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, commitment)| commitment)
+            .collect()
+    });
+
+    Ok(commitments
+        .into_iter()
+        .filter_map(|commitment| {
+            let at = commitment.ended_at.unwrap_or(now);
+            let (number, current_start, _) = period_bounds(&commitment, at);
+            // Only periods that have fully elapsed have a final variance
+            if number == 0 {
+                return None;
+            }
+            let length = commitment.period.length_nanos();
+            let period_start = current_start - length;
+            let delivered_kg = delivered_kg(commitment.donor_id, period_start, current_start);
+            Some(CommitmentVariance {
+                commitment_id: commitment.id,
+                donor_id: commitment.donor_id,
+                period_start,
+                period_end: current_start,
+                target_kg: commitment.target_kg,
+                delivered_kg,
+                variance_kg: delivered_kg as i64 - commitment.target_kg as i64,
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,980 @@
+use crate::assignment_lifecycle::{is_driver_owner, is_receiver_owner};
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::EntityType;
+use crate::governance::{authorize, is_governance_or_admin, require_admin, EndpointClass};
+use crate::maintenance::{check_idle, scan_batch, start_reencryption, MaintenanceJob, ScanStep};
+use crate::observability::{current_trace_id, log, resume_trace, start_trace, LogLevel};
+use crate::{
+    is_post_donor, Assignment, DonorProfile, DriverProfile, FoodShareError, Memory,
+    ReceiverProfile, ASSIGNMENTS_STORAGE, DONORS_STORAGE, DRIVERS_STORAGE, MEMORY_MANAGER,
+    RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::thread::LocalKey;
+use std::{borrow::Cow, cell::RefCell};
+
+// Sealed values are "enc<key id>.<base64 payload>"; values sealed before the
+// compact encoding use ":" and a hex payload. Stored values without the
+// prefix are plaintext from before encryption.
+const SEALED_PREFIX: &str = "enc";
+const COMPACT_SEPARATOR: char = '.';
+const HEX_SEPARATOR: char = ':';
+// URL-safe base64 alphabet; payloads are written without padding
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+// Values sealed before keys were rotated use the first key
+const FIRST_KEY_ID: u32 = 1;
+const NONCE_LEN: usize = 12;
+// Sealing adds 28 bytes and a third on top, so long emails would crowd out the
+// rest of a stored profile
+pub(crate) const MAX_EMAIL_LEN: usize = 100;
+// Contact reads kept in the access log; the oldest are dropped beyond this
+const MAX_CONTACT_ACCESSES: u64 = 50_000;
+// Several per read, so a log that grew before the cap shrinks back under it
+const EVICTIONS_PER_ACCESS: usize = 4;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct VaultState {
+    // 32 byte AES-256-GCM key from raw_rand; empty until the first contact is sealed
+    key: Vec<u8>,
+    // Nonces come from a counter so one is never used twice under any key
+    next_nonce: u64,
+    // Id of `key`; None until the first rotation, meaning FIRST_KEY_ID
+    key_id: Option<u32>,
+    // Keys replaced by a rotation, kept so values not yet re-encrypted still open
+    previous_keys: Option<Vec<RetiredKey>>,
+    // Key for the email index, derived from the first contact key and kept
+    // across rotations; None until the index is built
+    email_key: Option<Vec<u8>>,
+}
+
+impl VaultState {
+    fn key_id(&self) -> u32 {
+        self.key_id.unwrap_or(FIRST_KEY_ID)
+    }
+
+    fn key_for(&self, key_id: u32) -> Option<&[u8]> {
+        if key_id == self.key_id() {
+            return Some(&self.key);
+        }
+        self.previous_keys
+            .iter()
+            .flatten()
+            .find(|retired| retired.key_id == key_id)
+            .map(|retired| retired.key.as_slice())
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct RetiredKey {
+    key_id: u32,
+    key: Vec<u8>,
+    retired_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct KeyUsage {
+    key_id: u32,
+    fields: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ContactKeyStatus {
+    current_key_id: u32,
+    previous_key_ids: Vec<u32>,
+    // Stored contact fields per key they are sealed under
+    fields_by_key: Vec<KeyUsage>,
+    plaintext_fields: u64,
+}
+
+// Recorded in the audit trail instead of the vault state, which holds the keys
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct KeyRotation {
+    from_key_id: u32,
+    to_key_id: u32,
+}
+
+impl Storable for VaultState {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ContactDetails {
+    phone_number: String,
+    email: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ContactAccess {
+    id: u64,
+    trace_id: String,
+    reader: Principal,
+    entity_type: EntityType,
+    entity_id: u64,
+    accessed_at: u64,
+}
+
+impl Storable for ContactAccess {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ContactAccess {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// ((entity type, entity id), access id)
+type AccessIndex = RefCell<StableBTreeMap<((u64, u64), u64), (), Memory>>;
+
+thread_local! {
+    static VAULT_STATE: RefCell<Cell<VaultState, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(58))),
+            VaultState::default(),
+        )
+        .expect("Cannot create the contact vault")
+    );
+
+    static CONTACT_ACCESS_LOG: RefCell<StableBTreeMap<u64, ContactAccess, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(59)))
+    ));
+
+    // Reads of one profile's contact details, in the order they happened
+    static CONTACT_ACCESS_BY_PROFILE: AccessIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(133)))
+    ));
+
+    // (keyed hash of role and normalized email, profile id), so emails can be
+    // checked for uniqueness without opening every stored profile
+    static PROFILE_EMAILS: RefCell<StableBTreeMap<([u8; 32], u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(132)))
+    ));
+}
+
+// Profiles whose phone number and email are stored sealed
+pub(crate) trait ContactFields {
+    fn contact_fields(&mut self) -> [&mut String; 2];
+}
+
+impl ContactFields for DonorProfile {
+    fn contact_fields(&mut self) -> [&mut String; 2] {
+        [&mut self.phone_number, &mut self.email]
+    }
+}
+
+impl ContactFields for ReceiverProfile {
+    fn contact_fields(&mut self) -> [&mut String; 2] {
+        [&mut self.phone_number, &mut self.email]
+    }
+}
+
+impl ContactFields for DriverProfile {
+    fn contact_fields(&mut self) -> [&mut String; 2] {
+        [&mut self.phone_number, &mut self.email]
+    }
+}
+
+// Function to create the vault key on first use, and the email index along
+// with it. Update calls that seal contact details call this before sealing.
+pub(crate) async fn ensure_key() -> Result<(), FoodShareError> {
+    if VAULT_STATE.with(|cell| !cell.borrow().get().key.is_empty()) {
+        ensure_email_index();
+        return Ok(());
+    }
+
+    let random_bytes = random_key().await?;
+    VAULT_STATE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        // Another call may have created the key while this one waited
+        if cell.get().key.is_empty() {
+            let mut state = cell.get().clone();
+            state.key = random_bytes[..32].to_vec();
+            cell.set(state).expect("Cannot store the contact key");
+        }
+    });
+    ensure_email_index();
+    Ok(())
+}
+
+// Function to derive the email index key and index the emails of every stored
+// profile, once; profiles stored before the index existed are opened here
+fn ensure_email_index() {
+    let built = VAULT_STATE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut state = cell.get().clone();
+        if state.email_key.is_some() {
+            return true;
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(b"profile email index");
+        hasher.update(&state.key);
+        state.email_key = Some(hasher.finalize().to_vec());
+        cell.set(state).expect("Cannot store the email index key");
+        false
+    });
+    if built {
+        return;
+    }
+
+    let mut emails: Vec<(EntityType, u64, String)> = Vec::new();
+    DONORS_STORAGE.with(|storage| {
+        for (id, donor) in storage.borrow().iter() {
+            emails.push((EntityType::Donor, id, open(&donor.email)));
+        }
+    });
+    RECEIVERS_STORAGE.with(|storage| {
+        for (id, receiver) in storage.borrow().iter() {
+            emails.push((EntityType::Receiver, id, open(&receiver.email)));
+        }
+    });
+    DRIVERS_STORAGE.with(|storage| {
+        for (id, driver) in storage.borrow().iter() {
+            emails.push((EntityType::Driver, id, open(&driver.email)));
+        }
+    });
+    for (entity_type, id, email) in emails {
+        index_email(entity_type, id, None, &email);
+    }
+}
+
+// Function to hash an email for the index under the email key. Emails are
+// compared lowercased, and each role has its own digests.
+fn email_digest(entity_type: EntityType, email: &str) -> [u8; 32] {
+    let email_key = VAULT_STATE.with(|cell| cell.borrow().get().email_key.clone());
+    let mut hasher = Sha256::new();
+    hasher.update(email_key.expect("The email index is built with the contact key"));
+    hasher.update(format!("{:?}", entity_type).as_bytes());
+    hasher.update([0]);
+    hasher.update(email.trim().to_ascii_lowercase().as_bytes());
+    hasher.finalize().into()
+}
+
+// Function to move a profile's email index entry from its previous plaintext
+// email to its current one
+pub(crate) fn index_email(entity_type: EntityType, id: u64, previous: Option<&str>, current: &str) {
+    PROFILE_EMAILS.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous.filter(|previous| !previous.is_empty()) {
+            index.remove(&(email_digest(entity_type, previous), id));
+        }
+        if !current.is_empty() {
+            index.insert((email_digest(entity_type, current), id), ());
+        }
+    });
+}
+
+// Function to check whether a profile of the role other than `except` already
+// uses an email
+pub(crate) fn email_taken(entity_type: EntityType, email: &str, except: Option<u64>) -> bool {
+    let digest = email_digest(entity_type, email);
+    PROFILE_EMAILS.with(|index| {
+        index
+            .borrow()
+            .range((digest, 0)..=(digest, u64::MAX))
+            .any(|((_, id), _)| Some(id) != except)
+    })
+}
+
+async fn random_key() -> Result<Vec<u8>, FoodShareError> {
+    let trace_id = current_trace_id();
+    let result = raw_rand().await;
+    resume_trace(&trace_id);
+
+    let (random_bytes,) = result.map_err(|(code, msg)| FoodShareError::InternalError {
+        msg: format!("Cannot generate the contact key ({:?}): {}", code, msg),
+    })?;
+    Ok(random_bytes)
+}
+
+fn cipher(key: &[u8]) -> Aes256Gcm {
+    Aes256Gcm::new_from_slice(key).expect("Contact key must be 32 bytes")
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Function to encode bytes as unpadded base64, which takes a third less room than hex
+fn to_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..=chunk.len() {
+            encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * i)) as usize & 63] as char);
+        }
+    }
+    encoded
+}
+
+fn from_base64(encoded: &str) -> Option<Vec<u8>> {
+    if encoded.len() % 4 == 1 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    for chunk in encoded.as_bytes().chunks(4) {
+        let mut group = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|a| a == c)? as u32;
+            group |= value << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            bytes.push((group >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+// Function to encrypt a contact value for storage; empty values stay empty
+pub(crate) fn seal(plaintext: &str) -> String {
+    if plaintext.is_empty() {
+        return String::new();
+    }
+    VAULT_STATE.with(|cell| {
+        let mut state = cell.borrow().get().clone();
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..8].copy_from_slice(&state.next_nonce.to_be_bytes());
+        state.next_nonce += 1;
+
+        let sealed = cipher(&state.key)
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+            .expect("Cannot encrypt contact details");
+        let key_id = state.key_id();
+        cell.borrow_mut()
+            .set(state)
+            .expect("Cannot update the contact vault");
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&sealed);
+        format!(
+            "{}{}{}{}",
+            SEALED_PREFIX,
+            key_id,
+            COMPACT_SEPARATOR,
+            to_base64(&payload)
+        )
+    })
+}
+
+// Function to split a sealed value into the id of its key, its separator and
+// its encoded payload
+fn sealed_parts(stored: &str) -> Option<(u32, char, &str)> {
+    let rest = stored.strip_prefix(SEALED_PREFIX)?;
+    let (key_id, payload) = rest.split_at(rest.find([COMPACT_SEPARATOR, HEX_SEPARATOR])?);
+    let mut payload = payload.chars();
+    let separator = payload.next()?;
+    Some((key_id.parse().ok()?, separator, payload.as_str()))
+}
+
+fn is_sealed(stored: &str) -> bool {
+    sealed_parts(stored).is_some()
+}
+
+// Function to decrypt a stored contact value under the current or a previous
+// key; values stored before encryption are returned as they are
+pub(crate) fn open(stored: &str) -> String {
+    let Some((key_id, separator, payload)) = sealed_parts(stored) else {
+        return stored.to_string();
+    };
+    let bytes = match separator {
+        HEX_SEPARATOR => from_hex(payload),
+        _ => from_base64(payload),
+    };
+    let Some(bytes) = bytes.filter(|bytes| bytes.len() > NONCE_LEN) else {
+        return String::new();
+    };
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    VAULT_STATE
+        .with(|cell| {
+            let cell = cell.borrow();
+            let key = cell.get().key_for(key_id)?;
+            cipher(key)
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .ok()
+        })
+        .and_then(|plaintext| String::from_utf8(plaintext).ok())
+        .unwrap_or_default()
+}
+
+// Function to seal the contact fields of a profile before it is stored. Every
+// field is taken as plaintext, even one that looks sealed, so callers open
+// stored fields before changing a profile.
+pub(crate) fn sealed<T: ContactFields>(mut profile: T) -> T {
+    for field in profile.contact_fields() {
+        *field = seal(field);
+    }
+    profile
+}
+
+// Function to seal only the contact fields stored before encryption, for
+// profiles read back from storage
+fn sealed_where_plain<T: ContactFields>(mut profile: T) -> T {
+    for field in profile.contact_fields() {
+        if !is_sealed(field) {
+            *field = seal(field);
+        }
+    }
+    profile
+}
+
+// Function to seal again, under the current key, the contact fields of a
+// profile that are plaintext, sealed under a previous key or still hex
+// encoded. Returns None when the profile is already up to date.
+fn resealed<T: ContactFields>(mut profile: T) -> Option<T> {
+    let current_key_id = VAULT_STATE.with(|cell| cell.borrow().get().key_id());
+    let mut changed = false;
+    for field in profile.contact_fields() {
+        let up_to_date = field.is_empty()
+            || sealed_parts(field).is_some_and(|(key_id, separator, _)| {
+                key_id == current_key_id && separator == COMPACT_SEPARATOR
+            });
+        if !up_to_date {
+            *field = seal(&open(field));
+            changed = true;
+        }
+    }
+    changed.then_some(profile)
+}
+
+// Function to decrypt the contact fields of a profile for its owner
+pub(crate) fn revealed<T: ContactFields>(mut profile: T) -> T {
+    for field in profile.contact_fields() {
+        *field = open(field);
+    }
+    profile
+}
+
+// Function to blank the contact fields of a profile returned to anyone who
+// has not asked for them through a logged contact read
+pub(crate) fn redacted<T: ContactFields>(mut profile: T) -> T {
+    for field in profile.contact_fields() {
+        field.clear();
+    }
+    profile
+}
+
+fn active_assignments(matches: impl Fn(&Assignment) -> bool) -> Vec<Assignment> {
+    ASSIGNMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, assignment)| assignment)
+            .filter(|assignment| assignment.status.is_active() && matches(assignment))
+            .collect()
+    })
+}
+
+fn is_assignment_donor(assignment: &Assignment) -> bool {
+    SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+        .is_some_and(|post| is_post_donor(&post))
+}
+
+fn posted_by(assignment: &Assignment, donor_id: u64) -> bool {
+    SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+        .is_some_and(|post| post.donor_id == donor_id.to_string())
+}
+
+// Function to check whether the caller may read a profile's contact details:
+// its owner, governance, or the other side of an assignment still in progress
+fn may_read_contact(entity_type: EntityType, entity_id: u64, owner: Option<Principal>) -> bool {
+    let caller = ic_cdk::caller();
+    if owner == Some(caller) || is_governance_or_admin(&caller) {
+        return true;
+    }
+    match entity_type {
+        EntityType::Donor => active_assignments(|assignment| posted_by(assignment, entity_id))
+            .iter()
+            .any(|assignment| is_driver_owner(assignment) || is_receiver_owner(assignment)),
+        EntityType::Receiver => {
+            active_assignments(|assignment| assignment.receiver_id == entity_id)
+                .iter()
+                .any(|assignment| is_driver_owner(assignment) || is_assignment_donor(assignment))
+        }
+        EntityType::Driver => active_assignments(|assignment| assignment.driver_id == entity_id)
+            .iter()
+            .any(|assignment| is_receiver_owner(assignment) || is_assignment_donor(assignment)),
+        _ => false,
+    }
+}
+
+fn profile_key(entity_type: EntityType, entity_id: u64) -> (u64, u64) {
+    (entity_type as u64, entity_id)
+}
+
+// Function to log a contact read and drop the oldest reads once the log is
+// over MAX_CONTACT_ACCESSES. Ids keep increasing as old reads are dropped.
+fn record_access(entity_type: EntityType, entity_id: u64) {
+    let evicted = CONTACT_ACCESS_LOG.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let first_id = storage.iter().next().map_or(0, |(id, _)| id);
+        let id = first_id + storage.len();
+        storage.insert(
+            id,
+            ContactAccess {
+                id,
+                trace_id: current_trace_id(),
+                reader: ic_cdk::caller(),
+                entity_type,
+                entity_id,
+                accessed_at: time(),
+            },
+        );
+        CONTACT_ACCESS_BY_PROFILE.with(|index| {
+            index
+                .borrow_mut()
+                .insert((profile_key(entity_type, entity_id), id), ())
+        });
+
+        let excess = storage.len().saturating_sub(MAX_CONTACT_ACCESSES) as usize;
+        let evicted: Vec<ContactAccess> = storage
+            .iter()
+            .take(excess.min(EVICTIONS_PER_ACCESS))
+            .map(|(_, access)| access)
+            .collect();
+        for access in &evicted {
+            storage.remove(&access.id);
+        }
+        evicted
+    });
+    CONTACT_ACCESS_BY_PROFILE.with(|index| {
+        let mut index = index.borrow_mut();
+        for access in evicted {
+            index.remove(&(profile_key(access.entity_type, access.entity_id), access.id));
+        }
+    });
+}
+
+// Function to index the contact reads logged before the index existed. Runs
+// on every upgrade but only does work while the index is empty.
+pub(crate) fn migrate_access_index() {
+    if !CONTACT_ACCESS_BY_PROFILE.with(|index| index.borrow().is_empty()) {
+        return;
+    }
+    let entries: Vec<((u64, u64), u64)> = CONTACT_ACCESS_LOG.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(id, access)| (profile_key(access.entity_type, access.entity_id), id))
+            .collect()
+    });
+    CONTACT_ACCESS_BY_PROFILE.with(|index| {
+        let mut index = index.borrow_mut();
+        for entry in entries {
+            index.insert(entry, ());
+        }
+    });
+}
+
+// Function to decrypt a profile's contact details for an authorized caller and log the read
+fn read_contact<T: ContactFields>(
+    entity_type: EntityType,
+    entity_id: u64,
+    profile: Option<(T, Option<Principal>)>,
+) -> Result<ContactDetails, FoodShareError> {
+    let (mut profile, owner) = profile.ok_or(FoodShareError::NotFound {
+        msg: format!("{:?} with id={} not found", entity_type, entity_id),
+    })?;
+    if !may_read_contact(entity_type, entity_id, owner) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the owner, governance or a party to an active assignment can read these contact details".to_string(),
+        });
+    }
+
+    record_access(entity_type, entity_id);
+    log(
+        LogLevel::Info,
+        format!(
+            "{} read the contact details of {:?} {}",
+            ic_cdk::caller(),
+            entity_type,
+            entity_id
+        ),
+    );
+
+    let [phone_number, email] = profile.contact_fields();
+    Ok(ContactDetails {
+        phone_number: open(phone_number),
+        email: open(email),
+    })
+}
+
+// Function to read a donor's phone number and email; every read is logged
+#[ic_cdk::update]
+async fn read_donor_contact(donor_id: u64) -> Result<ContactDetails, FoodShareError> {
+    start_trace("read_donor_contact");
+    authorize(EndpointClass::Logistics).await?;
+
+    let donor = DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id));
+    read_contact(
+        EntityType::Donor,
+        donor_id,
+        donor.map(|donor| {
+            let owner = donor.owner;
+            (donor, owner)
+        }),
+    )
+}
+
+// Function to read a receiver's phone number and email; every read is logged
+#[ic_cdk::update]
+async fn read_receiver_contact(receiver_id: u64) -> Result<ContactDetails, FoodShareError> {
+    start_trace("read_receiver_contact");
+    authorize(EndpointClass::Logistics).await?;
+
+    let receiver = RECEIVERS_STORAGE.with(|storage| storage.borrow().get(&receiver_id));
+    read_contact(
+        EntityType::Receiver,
+        receiver_id,
+        receiver.map(|receiver| {
+            let owner = receiver.owner;
+            (receiver, owner)
+        }),
+    )
+}
+
+// Function to read a driver's phone number and email; every read is logged
+#[ic_cdk::update]
+async fn read_driver_contact(driver_id: u64) -> Result<ContactDetails, FoodShareError> {
+    start_trace("read_driver_contact");
+    authorize(EndpointClass::Logistics).await?;
+
+    let driver = DRIVERS_STORAGE.with(|storage| storage.borrow().get(&driver_id));
+    read_contact(
+        EntityType::Driver,
+        driver_id,
+        driver.map(|driver| {
+            let owner = driver.owner;
+            (driver, owner)
+        }),
+    )
+}
+
+// Function to get who read a profile's contact details and when, for its owner or admins
+#[ic_cdk::query]
+fn get_contact_access_log(
+    entity_type: EntityType,
+    entity_id: u64,
+) -> Result<Vec<ContactAccess>, FoodShareError> {
+    let owner = match entity_type {
+        EntityType::Donor => DONORS_STORAGE
+            .with(|storage| storage.borrow().get(&entity_id))
+            .and_then(|donor| donor.owner),
+        EntityType::Receiver => RECEIVERS_STORAGE
+            .with(|storage| storage.borrow().get(&entity_id))
+            .and_then(|receiver| receiver.owner),
+        EntityType::Driver => DRIVERS_STORAGE
+            .with(|storage| storage.borrow().get(&entity_id))
+            .and_then(|driver| driver.owner),
+        _ => None,
+    };
+    let caller = ic_cdk::caller();
+    if owner != Some(caller) && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the owner or governance can see who read these contact details".to_string(),
+        });
+    }
+
+    let key = profile_key(entity_type, entity_id);
+    let ids: Vec<u64> = CONTACT_ACCESS_BY_PROFILE.with(|index| {
+        index
+            .borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    Ok(CONTACT_ACCESS_LOG.with(|storage| {
+        let storage = storage.borrow();
+        ids.iter().filter_map(|id| storage.get(id)).collect()
+    }))
+}
+
+// Function for admins to seal the contact details stored before encryption
+#[ic_cdk::update]
+async fn seal_existing_contacts() -> Result<u64, FoodShareError> {
+    start_trace("seal_existing_contacts");
+    require_admin("seal existing contact details")?;
+    ensure_key().await?;
+
+    let is_plain = |fields: [&mut String; 2]| {
+        fields
+            .iter()
+            .any(|field| !field.is_empty() && !is_sealed(field))
+    };
+    let mut count = 0;
+    DONORS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let plain: Vec<DonorProfile> = storage
+            .iter()
+            .map(|(_, donor)| donor)
+            .filter(|donor| is_plain(donor.clone().contact_fields()))
+            .collect();
+        for donor in plain {
+            storage.insert(donor.id, sealed_where_plain(donor));
+            count += 1;
+        }
+    });
+    RECEIVERS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let plain: Vec<ReceiverProfile> = storage
+            .iter()
+            .map(|(_, receiver)| receiver)
+            .filter(|receiver| is_plain(receiver.clone().contact_fields()))
+            .collect();
+        for receiver in plain {
+            storage.insert(receiver.id, sealed_where_plain(receiver));
+            count += 1;
+        }
+    });
+    DRIVERS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let plain: Vec<DriverProfile> = storage
+            .iter()
+            .map(|(_, driver)| driver)
+            .filter(|driver| is_plain(driver.clone().contact_fields()))
+            .collect();
+        for driver in plain {
+            storage.insert(driver.id, sealed_where_plain(driver));
+            count += 1;
+        }
+    });
+
+    log(
+        LogLevel::Info,
+        format!("Sealed the contact details of {} profiles", count),
+    );
+
+    Ok(count)
+}
+
+fn reseal_batch<T: ContactFields + BoundedStorable + Clone>(
+    storage: &'static LocalKey<RefCell<StableBTreeMap<u64, T, Memory>>>,
+    cursor: Option<&[u8]>,
+) -> ScanStep {
+    storage.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let (entries, next_cursor) = scan_batch(&storage, cursor);
+        let scanned = entries.len() as u64;
+        let mut inserted = 0;
+        for (id, profile) in entries {
+            if let Some(profile) = resealed(profile) {
+                storage.insert(id, profile);
+                inserted += 1;
+            }
+        }
+        ScanStep {
+            next_cursor,
+            scanned,
+            inserted,
+            ..Default::default()
+        }
+    })
+}
+
+// Function to re-encrypt one batch of profiles under the current key. The
+// first cursor byte picks the collection: donors, then receivers, then drivers.
+pub(crate) fn reseal_contacts(cursor: Option<&[u8]>) -> ScanStep {
+    let (collection, key_cursor) = match cursor {
+        Some([collection, rest @ ..]) => (*collection, (!rest.is_empty()).then_some(rest)),
+        _ => (0, None),
+    };
+    let mut step = match collection {
+        0 => reseal_batch(&DONORS_STORAGE, key_cursor),
+        1 => reseal_batch(&RECEIVERS_STORAGE, key_cursor),
+        _ => reseal_batch(&DRIVERS_STORAGE, key_cursor),
+    };
+    step.next_cursor = match step.next_cursor.take() {
+        Some(key) => Some([vec![collection], key].concat()),
+        None if collection < 2 => Some(vec![collection + 1]),
+        None => None,
+    };
+    step
+}
+
+// Function for admins to replace the contact key. Values sealed under earlier
+// keys keep opening while a maintenance job re-encrypts them in batches.
+#[ic_cdk::update]
+async fn rotate_contact_key() -> Result<MaintenanceJob, FoodShareError> {
+    start_trace("rotate_contact_key");
+    require_admin("rotate the contact key")?;
+
+    if VAULT_STATE.with(|cell| cell.borrow().get().key.is_empty()) {
+        return Err(FoodShareError::Conflict {
+            msg: "There is no contact key to rotate yet".to_string(),
+        });
+    }
+    // Rotating while another job runs would leave nothing re-encrypting the old values
+    check_idle()?;
+
+    let random_bytes = random_key().await?;
+    let rotation = VAULT_STATE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut state = cell.get().clone();
+        let from_key_id = state.key_id();
+        let mut previous_keys = state.previous_keys.take().unwrap_or_default();
+        previous_keys.push(RetiredKey {
+            key_id: from_key_id,
+            key: std::mem::take(&mut state.key),
+            retired_at: time(),
+        });
+        state.previous_keys = Some(previous_keys);
+        state.key = random_bytes[..32].to_vec();
+        state.key_id = Some(from_key_id + 1);
+        cell.set(state).expect("Cannot store the contact key");
+        KeyRotation {
+            from_key_id,
+            to_key_id: from_key_id + 1,
+        }
+    });
+    audit(
+        AuditAction::Configured,
+        AuditEntity::ContactKey,
+        0,
+        None,
+        Some(&rotation),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Rotated the contact key from {} to {}",
+            rotation.from_key_id, rotation.to_key_id
+        ),
+    );
+
+    start_reencryption()
+}
+
+// Function for admins to re-encrypt every contact field not yet under the
+// current key, e.g. when a rotation could not start its job
+#[ic_cdk::update]
+fn reencrypt_contacts() -> Result<MaintenanceJob, FoodShareError> {
+    start_trace("reencrypt_contacts");
+    require_admin("re-encrypt contact details")?;
+    start_reencryption()
+}
+
+// Function for admins to see which keys the stored contact fields are sealed under
+#[ic_cdk::query]
+fn get_contact_key_status() -> Result<ContactKeyStatus, FoodShareError> {
+    require_admin("view the contact key status")?;
+
+    let mut fields: Vec<String> = Vec::new();
+    DONORS_STORAGE.with(|storage| {
+        for (_, mut donor) in storage.borrow().iter() {
+            fields.extend(donor.contact_fields().map(|field| field.clone()));
+        }
+    });
+    RECEIVERS_STORAGE.with(|storage| {
+        for (_, mut receiver) in storage.borrow().iter() {
+            fields.extend(receiver.contact_fields().map(|field| field.clone()));
+        }
+    });
+    DRIVERS_STORAGE.with(|storage| {
+        for (_, mut driver) in storage.borrow().iter() {
+            fields.extend(driver.contact_fields().map(|field| field.clone()));
+        }
+    });
+
+    let mut fields_by_key: Vec<KeyUsage> = Vec::new();
+    let mut plaintext_fields = 0;
+    for field in fields.iter().filter(|field| !field.is_empty()) {
+        match sealed_parts(field) {
+            Some((key_id, _, _)) => match fields_by_key
+                .iter_mut()
+                .find(|usage| usage.key_id == key_id)
+            {
+                Some(usage) => usage.fields += 1,
+                None => fields_by_key.push(KeyUsage { key_id, fields: 1 }),
+            },
+            None => plaintext_fields += 1,
+        }
+    }
+    fields_by_key.sort_by_key(|usage| usage.key_id);
+
+    Ok(VAULT_STATE.with(|cell| {
+        let cell = cell.borrow();
+        let state = cell.get();
+        ContactKeyStatus {
+            current_key_id: state.key_id(),
+            previous_key_ids: state
+                .previous_keys
+                .iter()
+                .flatten()
+                .map(|retired| retired.key_id)
+                .collect(),
+            fields_by_key,
+            plaintext_fields,
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Contact {
+        phone_number: String,
+        email: String,
+    }
+
+    impl ContactFields for Contact {
+        fn contact_fields(&mut self) -> [&mut String; 2] {
+            [&mut self.phone_number, &mut self.email]
+        }
+    }
+
+    fn with_test_key() {
+        VAULT_STATE.with(|cell| {
+            let mut state = cell.borrow().get().clone();
+            state.key = vec![7; 32];
+            cell.borrow_mut().set(state).unwrap();
+        });
+    }
+
+    #[test]
+    fn email_that_looks_sealed_is_still_sealed() {
+        with_test_key();
+        let email = "enc1.x@example.com";
+        let mut contact = sealed(Contact {
+            phone_number: "+254700000000".to_string(),
+            email: email.to_string(),
+        });
+        assert_ne!(contact.email, email);
+        assert_eq!(open(&contact.email), email);
+
+        // Profiles read back from storage are not sealed twice
+        let stored = contact.email.clone();
+        contact = sealed_where_plain(contact);
+        assert_eq!(contact.email, stored);
+    }
+}
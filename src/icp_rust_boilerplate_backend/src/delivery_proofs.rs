@@ -0,0 +1,446 @@
+use crate::assets::owned_asset;
+use crate::assignment_lifecycle::{is_driver_owner, is_receiver_owner, store_transition};
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::dropoff_checklists::{check_dropoff, store_completed_checklist, DropoffAnswer};
+use crate::governance::{authorize, is_governance_or_admin, require_admin, EndpointClass};
+use crate::notifications::{notify_assignment, NotificationKind};
+use crate::observability::{log, start_timer_trace, start_trace, LogLevel};
+use crate::reservations::{record_reservation_outcome, ReservationOutcome};
+use crate::{
+    deliver_assignment, Assignment, AssignmentStatus, FoodShareError, Memory, SurplusRecord,
+    ASSIGNMENTS_STORAGE, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const CONFIRMATION_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// The sweep runs hourly, so shorter windows would not be honoured
+const MIN_WINDOW_HOURS: u64 = 1;
+const MAX_WINDOW_HOURS: u64 = 14 * 24;
+const MAX_REASON_LEN: usize = 200;
+
+// Proof the driver submits with a delivery
+#[derive(candid::CandidType, Deserialize, Serialize, Default)]
+pub(crate) struct DeliveryProofPayload {
+    // Photo of the drop-off, uploaded by the driver through the assets module
+    photo_asset_id: Option<u64>,
+    // Answers to the receiver's drop-off checklist, in its order
+    checklist: Option<Vec<DropoffAnswer>>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DeliveryProof {
+    assignment_id: u64,
+    photo_asset_id: Option<u64>,
+    // Hex SHA-256 of the photo when it was submitted
+    photo_sha256: Option<String>,
+    submitted_at: u64,
+    // The receiver confirms by this time or the delivery is disputed
+    confirm_by: u64,
+    confirmed_at: Option<u64>,
+    dispute_reason: Option<String>,
+    disputed_at: Option<u64>,
+    resolved_by: Option<Principal>,
+    resolved_at: Option<u64>,
+    // None when the receiver had no drop-off checklist
+    checklist_passed: Option<bool>,
+}
+
+impl Storable for DeliveryProof {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DeliveryProof {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DeliveryConfirmationConfig {
+    // Hours the receiver has to confirm a submitted delivery
+    window_hours: u64,
+}
+
+impl Default for DeliveryConfirmationConfig {
+    fn default() -> Self {
+        Self { window_hours: 48 }
+    }
+}
+
+impl Storable for DeliveryConfirmationConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static DELIVERY_PROOFS: RefCell<StableBTreeMap<u64, DeliveryProof, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(60)))
+    ));
+
+    static CONFIRMATION_CONFIG: RefCell<Cell<DeliveryConfirmationConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(61))),
+            DeliveryConfirmationConfig::default(),
+        )
+        .expect("Cannot create the delivery confirmation config")
+    );
+}
+
+fn load_assignment(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })
+}
+
+fn store_proof(before: Option<&DeliveryProof>, proof: &DeliveryProof) {
+    DELIVERY_PROOFS.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(proof.assignment_id, proof.clone())
+    });
+    audit(
+        match before {
+            Some(_) => AuditAction::Updated,
+            None => AuditAction::Created,
+        },
+        AuditEntity::DeliveryProof,
+        proof.assignment_id,
+        before,
+        Some(proof),
+    );
+}
+
+fn validate_reason(reason: &str) -> Result<(), FoodShareError> {
+    if reason.trim().is_empty() || reason.len() > MAX_REASON_LEN {
+        return Err(FoodShareError::Validation {
+            field: "reason".to_string(),
+            reason: format!("Reason must be between 1 and {} bytes", MAX_REASON_LEN),
+        });
+    }
+    Ok(())
+}
+
+// Function for the driver to hand over the food: the assignment waits for the
+// receiver to confirm it, and the surplus record is only created once they do.
+// A failed drop-off check disputes the delivery straight away, so governance
+// decides whether the receiver has to take it.
+pub(crate) fn submit_delivery(
+    assignment: Assignment,
+    payload: DeliveryProofPayload,
+) -> Result<Assignment, FoodShareError> {
+    let photo = payload.photo_asset_id.map(owned_asset).transpose()?;
+    let checklist = check_dropoff(&assignment, payload.checklist)?;
+
+    let assignment = store_transition(
+        assignment,
+        AssignmentStatus::AwaitingConfirmation,
+        "Driver delivered the food; waiting for the receiver to confirm".to_string(),
+    )?;
+
+    let now = time();
+    let window_hours = CONFIRMATION_CONFIG.with(|cell| cell.borrow().get().window_hours);
+    let proof = DeliveryProof {
+        assignment_id: assignment.id,
+        photo_asset_id: payload.photo_asset_id,
+        photo_sha256: photo.and_then(|photo| photo.sha256()),
+        submitted_at: now,
+        confirm_by: now + window_hours * NANOS_PER_HOUR,
+        confirmed_at: None,
+        dispute_reason: None,
+        disputed_at: None,
+        resolved_by: None,
+        resolved_at: None,
+        checklist_passed: checklist.as_ref().map(|checklist| checklist.passed()),
+    };
+    store_proof(None, &proof);
+
+    if let Some(checklist) = checklist {
+        store_completed_checklist(&checklist);
+        if !checklist.passed() {
+            let reason = format!(
+                "Drop-off checks failed: {}",
+                checklist.failures().join(", ")
+            );
+            return open_dispute(assignment, reason.chars().take(MAX_REASON_LEN).collect());
+        }
+    }
+
+    notify_assignment(
+        &assignment,
+        NotificationKind::DeliverySubmitted,
+        &format!(
+            "Surplus post {} was dropped off; the receiver has {} hours to confirm it",
+            assignment.surplus_post_id, window_hours
+        ),
+    );
+
+    Ok(assignment)
+}
+
+// Function to finalize a delivery the receiver confirmed within the window
+pub(crate) fn confirm_submitted_delivery(
+    assignment: Assignment,
+) -> Result<SurplusRecord, FoodShareError> {
+    let before = DELIVERY_PROOFS.with(|storage| storage.borrow().get(&assignment.id));
+    if let Some(before) = &before {
+        if time() > before.confirm_by {
+            return Err(FoodShareError::Expired {
+                msg: "The confirmation window has closed; the delivery is under dispute"
+                    .to_string(),
+            });
+        }
+    }
+
+    let record = deliver_assignment(assignment)?;
+    if let Some(before) = before {
+        let mut proof = before.clone();
+        proof.confirmed_at = Some(time());
+        store_proof(Some(&before), &proof);
+    }
+    Ok(record)
+}
+
+// Function to move a submitted delivery to Disputed and let governance know
+fn open_dispute(assignment: Assignment, reason: String) -> Result<Assignment, FoodShareError> {
+    let assignment = store_transition(
+        assignment,
+        AssignmentStatus::Disputed,
+        format!("Delivery disputed: {}", reason),
+    )?;
+
+    if let Some(before) = DELIVERY_PROOFS.with(|storage| storage.borrow().get(&assignment.id)) {
+        let mut proof = before.clone();
+        proof.dispute_reason = Some(reason.clone());
+        proof.disputed_at = Some(time());
+        store_proof(Some(&before), &proof);
+    }
+
+    notify_assignment(
+        &assignment,
+        NotificationKind::DeliveryDisputed,
+        &format!(
+            "Delivery of surplus post {} is disputed: {}",
+            assignment.surplus_post_id, reason
+        ),
+    );
+
+    Ok(assignment)
+}
+
+// Function for the receiver to dispute a submitted delivery, e.g. when the
+// food never arrived or was not what was posted
+#[ic_cdk::update]
+async fn dispute_delivery(
+    assignment_id: u64,
+    reason: String,
+) -> Result<Assignment, FoodShareError> {
+    start_trace("dispute_delivery");
+    authorize(EndpointClass::Logistics).await?;
+
+    validate_reason(&reason)?;
+
+    let assignment = load_assignment(assignment_id)?;
+    if !is_receiver_owner(&assignment) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the receiver can dispute this delivery".to_string(),
+        });
+    }
+
+    open_dispute(assignment, reason)
+}
+
+// Function for governance to settle a disputed delivery, either recording it as
+// delivered or as failed
+#[ic_cdk::update]
+async fn resolve_delivery_dispute(
+    assignment_id: u64,
+    delivered: bool,
+) -> Result<Assignment, FoodShareError> {
+    start_trace("resolve_delivery_dispute");
+    authorize(EndpointClass::Logistics).await?;
+
+    let caller = ic_cdk::caller();
+    if !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only governance can resolve a disputed delivery".to_string(),
+        });
+    }
+
+    let assignment = load_assignment(assignment_id)?;
+    if assignment.status != AssignmentStatus::Disputed {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Only disputed deliveries can be resolved, this one is {:?}",
+                assignment.status
+            ),
+        });
+    }
+
+    let assignment = if delivered {
+        deliver_assignment(assignment)?;
+        load_assignment(assignment_id)?
+    } else {
+        let assignment = store_transition(
+            assignment,
+            AssignmentStatus::Failed,
+            "Delivery failed: governance upheld the dispute".to_string(),
+        )?;
+        if assignment.previous_leg_id.is_none() {
+            record_reservation_outcome(&assignment, ReservationOutcome::Cancelled);
+        }
+        assignment
+    };
+
+    if let Some(before) = DELIVERY_PROOFS.with(|storage| storage.borrow().get(&assignment_id)) {
+        let mut proof = before.clone();
+        proof.resolved_by = Some(caller);
+        proof.resolved_at = Some(time());
+        store_proof(Some(&before), &proof);
+    }
+    log(
+        LogLevel::Info,
+        format!(
+            "Resolved the disputed delivery of assignment {} as {:?}",
+            assignment_id, assignment.status
+        ),
+    );
+
+    Ok(assignment)
+}
+
+// Function for the parties of an assignment, or governance, to get the proof
+// submitted with its delivery
+#[ic_cdk::query]
+fn get_delivery_proof(assignment_id: u64) -> Result<DeliveryProof, FoodShareError> {
+    let assignment = load_assignment(assignment_id)?;
+    if !is_driver_owner(&assignment)
+        && !is_receiver_owner(&assignment)
+        && !is_governance_or_admin(&ic_cdk::caller())
+    {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the driver, the receiver or governance can view this delivery proof"
+                .to_string(),
+        });
+    }
+
+    DELIVERY_PROOFS
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "No delivery has been submitted for this assignment".to_string(),
+        })
+}
+
+#[ic_cdk::query]
+fn get_delivery_confirmation_config() -> DeliveryConfirmationConfig {
+    CONFIRMATION_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Function for admins to set how long receivers have to confirm a delivery;
+// deliveries already submitted keep their deadline
+#[ic_cdk::update]
+fn set_delivery_confirmation_config(
+    config: DeliveryConfirmationConfig,
+) -> Result<DeliveryConfirmationConfig, FoodShareError> {
+    start_trace("set_delivery_confirmation_config");
+    require_admin("update the delivery confirmation config")?;
+
+    if !(MIN_WINDOW_HOURS..=MAX_WINDOW_HOURS).contains(&config.window_hours) {
+        return Err(FoodShareError::Validation {
+            field: "window_hours".to_string(),
+            reason: format!(
+                "Confirmation window must be between {} and {} hours",
+                MIN_WINDOW_HOURS, MAX_WINDOW_HOURS
+            ),
+        });
+    }
+
+    let before = CONFIRMATION_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the delivery confirmation config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::DeliveryConfirmationConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Delivery confirmation window set to {} hours",
+            config.window_hours
+        ),
+    );
+
+    Ok(config)
+}
+
+// Function to register the periodic sweep that disputes deliveries left unconfirmed
+pub(crate) fn start_confirmation_sweep() {
+    ic_cdk_timers::set_timer_interval(CONFIRMATION_SWEEP_INTERVAL, dispute_unconfirmed_deliveries);
+}
+
+// Function to dispute every submitted delivery whose confirmation window has passed
+fn dispute_unconfirmed_deliveries() {
+    start_timer_trace("dispute_unconfirmed_deliveries");
+
+    let now = time();
+    let lapsed: Vec<Assignment> = DELIVERY_PROOFS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, proof)| proof.confirmed_at.is_none() && proof.disputed_at.is_none())
+            .filter(|(_, proof)| proof.confirm_by <= now)
+            .filter_map(|(assignment_id, _)| {
+                ASSIGNMENTS_STORAGE.with(|assignments| assignments.borrow().get(&assignment_id))
+            })
+            .filter(|assignment| assignment.status == AssignmentStatus::AwaitingConfirmation)
+            .collect()
+    });
+
+    let count = lapsed.len();
+    for assignment in lapsed {
+        let assignment_id = assignment.id;
+        if open_dispute(
+            assignment,
+            "The receiver did not confirm the delivery in time".to_string(),
+        )
+        .is_err()
+        {
+            log(
+                LogLevel::Warn,
+                format!(
+                    "Could not dispute the delivery of assignment {}",
+                    assignment_id
+                ),
+            );
+        }
+    }
+
+    if count > 0 {
+        log(
+            LogLevel::Info,
+            format!("Disputed {} unconfirmed deliveries", count),
+        );
+    }
+}
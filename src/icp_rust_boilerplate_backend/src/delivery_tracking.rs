@@ -0,0 +1,200 @@
+use crate::assignment_lifecycle::{is_driver_owner, is_receiver_owner};
+use crate::geo::{haversine_km, validate_point};
+use crate::governance::{authorize, EndpointClass};
+use crate::observability::start_trace;
+use crate::{
+    is_post_donor, Assignment, AssignmentStatus, FoodShareError, Memory, VehicleType,
+    ASSIGNMENTS_STORAGE, DRIVERS_STORAGE, MEMORY_MANAGER, RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+// Oldest pings are dropped once a trail holds this many
+const MAX_TRAIL_PINGS: usize = 50;
+// Pings closer together than this are rejected to keep the trail meaningful
+const MIN_PING_INTERVAL_NANOS: u64 = 15 * NANOS_PER_SECOND;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct LocationPing {
+    latitude: f64,
+    longitude: f64,
+    recorded_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct DeliveryTrail {
+    // Oldest first
+    pings: Vec<LocationPing>,
+}
+
+impl Storable for DeliveryTrail {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DeliveryTrail {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DeliveryTracking {
+    assignment_id: u64,
+    status: AssignmentStatus,
+    // Pings the driver sent while in transit, oldest first
+    trail: Vec<LocationPing>,
+    // Straight-line distance from the last ping to the receiver
+    remaining_km: Option<f64>,
+    // Only while the assignment is in transit and both ends have coordinates
+    eta_minutes: Option<u32>,
+    estimated_arrival_at: Option<u64>,
+}
+
+thread_local! {
+    static DELIVERY_TRAILS: RefCell<StableBTreeMap<u64, DeliveryTrail, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(114)))
+    ));
+}
+
+// Function to get the average urban speed assumed for a vehicle type
+fn average_speed_kmh(vehicle_type: VehicleType) -> f64 {
+    match vehicle_type {
+        VehicleType::Bicycle => 15.0,
+        VehicleType::Truck => 25.0,
+        VehicleType::Motorbike | VehicleType::Car | VehicleType::ElectricCar | VehicleType::Van => {
+            30.0
+        }
+    }
+}
+
+fn load_assignment(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })
+}
+
+// Function for the assigned driver to report where they are while the
+// assignment is in transit. The trail keeps the latest MAX_TRAIL_PINGS pings.
+#[ic_cdk::update]
+async fn update_location_ping(
+    assignment_id: u64,
+    lat: f64,
+    lng: f64,
+) -> Result<LocationPing, FoodShareError> {
+    start_trace("update_location_ping");
+    authorize(EndpointClass::Logistics).await?;
+
+    validate_point(lat, lng)?;
+    let assignment = load_assignment(assignment_id)?;
+    if !is_driver_owner(&assignment) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the assigned driver can report its location".to_string(),
+        });
+    }
+    if assignment.status != AssignmentStatus::InTransit {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Locations can only be reported in transit; the assignment is {:?}",
+                assignment.status
+            ),
+        });
+    }
+
+    let now = time();
+    let mut trail = DELIVERY_TRAILS
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .unwrap_or_default();
+    if trail
+        .pings
+        .last()
+        .is_some_and(|last| now < last.recorded_at + MIN_PING_INTERVAL_NANOS)
+    {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "Send at most one location every {} seconds",
+                MIN_PING_INTERVAL_NANOS / NANOS_PER_SECOND
+            ),
+        });
+    }
+    let ping = LocationPing {
+        latitude: lat,
+        longitude: lng,
+        recorded_at: now,
+    };
+    trail.pings.push(ping);
+    if trail.pings.len() > MAX_TRAIL_PINGS {
+        let excess = trail.pings.len() - MAX_TRAIL_PINGS;
+        trail.pings.drain(..excess);
+    }
+    DELIVERY_TRAILS.with(|storage| storage.borrow_mut().insert(assignment_id, trail));
+
+    Ok(ping)
+}
+
+// Function for the donor and receiver of an assignment to follow its delivery.
+// The ETA assumes a straight line from the driver's last ping to the receiver
+// at the average speed of the driver's vehicle type.
+#[ic_cdk::query]
+fn get_delivery_tracking(assignment_id: u64) -> Result<DeliveryTracking, FoodShareError> {
+    let assignment = load_assignment(assignment_id)?;
+    let is_donor = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+        .is_some_and(|post| is_post_donor(&post));
+    if !is_donor && !is_receiver_owner(&assignment) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor and receiver of this assignment can track its delivery"
+                .to_string(),
+        });
+    }
+
+    let trail = DELIVERY_TRAILS
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .unwrap_or_default();
+    let destination = RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.receiver_id))
+        .and_then(|receiver| Some((receiver.latitude?, receiver.longitude?)));
+    let remaining_km = match (trail.pings.last(), destination) {
+        (Some(last), Some((latitude, longitude))) => Some(haversine_km(
+            last.latitude,
+            last.longitude,
+            latitude,
+            longitude,
+        )),
+        _ => None,
+    };
+
+    let eta_minutes = remaining_km
+        .filter(|_| assignment.status == AssignmentStatus::InTransit)
+        .map(|remaining_km| {
+            let speed_kmh = DRIVERS_STORAGE
+                .with(|storage| storage.borrow().get(&assignment.driver_id))
+                .map_or(average_speed_kmh(VehicleType::default()), |driver| {
+                    average_speed_kmh(driver.vehicle_type)
+                });
+            (remaining_km / speed_kmh * 60.0).ceil() as u32
+        });
+    let estimated_arrival_at = eta_minutes
+        .zip(trail.pings.last())
+        .map(|(minutes, last)| last.recorded_at + minutes as u64 * 60 * NANOS_PER_SECOND);
+
+    Ok(DeliveryTracking {
+        assignment_id,
+        status: assignment.status,
+        trail: trail.pings,
+        remaining_km,
+        eta_minutes,
+        estimated_arrival_at,
+    })
+}
@@ -0,0 +1,198 @@
+use crate::browse::open_post_ids;
+use crate::category_pauses::is_food_type_paused;
+use crate::driver_availability::is_available_now;
+use crate::driver_load::check_weekly_cap;
+use crate::geo::haversine_km;
+use crate::matching::matched_receiver;
+use crate::post_expiry::expires_at;
+use crate::radius_expansion::within_reach;
+use crate::regions::require_region_admin;
+use crate::{
+    DriverProfile, FoodShareError, FoodType, ReceiverProfile, SurplusPost, DONORS_STORAGE,
+    DRIVERS_STORAGE, RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use ic_cdk::api::time;
+use std::collections::BTreeSet;
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+// Open posts expiring within this many hours are urgent
+const URGENT_WITHIN_HOURS: u64 = 24;
+const MAX_QUEUE_LEN: usize = 50;
+const MAX_CANDIDATES: usize = 5;
+// Reach assumed for drivers around posts without a tracked reach
+const DEFAULT_DRIVER_RADIUS_KM: f64 = 10.0;
+// Posts closer to expiry than this all count as this close
+const MIN_HOURS_LEFT: f64 = 0.5;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DispatchCandidate {
+    id: u64,
+    name: String,
+    // None when either side has no coordinates
+    distance_km: Option<f64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DispatchItem {
+    post_id: u64,
+    donor_id: String,
+    food_type: FoodType,
+    quantity_kg: u32,
+    expires_at: u64,
+    hours_left: f64,
+    // Kilograms at risk per hour left; higher is more urgent
+    urgency_score: f64,
+    // Drivers who could take the post now, nearest first
+    drivers: Vec<DispatchCandidate>,
+    // Receivers the post can go to, nearest first
+    receivers: Vec<DispatchCandidate>,
+}
+
+fn distance_between(
+    post: &SurplusPost,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+) -> Option<f64> {
+    Some(haversine_km(
+        post.latitude?,
+        post.longitude?,
+        latitude?,
+        longitude?,
+    ))
+}
+
+fn nearest(mut candidates: Vec<DispatchCandidate>) -> Vec<DispatchCandidate> {
+    let key = |candidate: &DispatchCandidate| candidate.distance_km.unwrap_or(f64::MAX);
+    candidates.sort_by(|a, b| key(a).total_cmp(&key(b)).then(a.id.cmp(&b.id)));
+    candidates.truncate(MAX_CANDIDATES);
+    candidates
+}
+
+// Function to get the drivers near a post: within its reach when both sides
+// have coordinates, otherwise in the region
+fn nearby_drivers(
+    post: &SurplusPost,
+    region: &str,
+    drivers: &[DriverProfile],
+) -> Vec<DispatchCandidate> {
+    let radius_km = post.reach_radius_km.unwrap_or(DEFAULT_DRIVER_RADIUS_KM);
+    nearest(
+        drivers
+            .iter()
+            .filter_map(|driver| {
+                let distance_km = distance_between(post, driver.latitude, driver.longitude);
+                let nearby = match distance_km {
+                    Some(distance_km) => distance_km <= radius_km,
+                    None => driver.region.trim().eq_ignore_ascii_case(region),
+                };
+                nearby.then(|| DispatchCandidate {
+                    id: driver.id,
+                    name: driver.name.clone(),
+                    distance_km,
+                })
+            })
+            .collect(),
+    )
+}
+
+// Function to get the receivers a post could be assigned to: within its reach,
+// taking its food type, and the matched receiver only if a match holds it
+fn eligible_receivers(post: &SurplusPost, receivers: &[ReceiverProfile]) -> Vec<DispatchCandidate> {
+    let matched = matched_receiver(post.id);
+    nearest(
+        receivers
+            .iter()
+            .filter(|receiver| matched.is_none_or(|receiver_id| receiver_id == receiver.id))
+            .filter(|receiver| within_reach(post, receiver.latitude, receiver.longitude))
+            .filter(|receiver| !is_food_type_paused(receiver.id, post.food_type))
+            .map(|receiver| DispatchCandidate {
+                id: receiver.id,
+                name: receiver.name.clone(),
+                distance_km: distance_between(post, receiver.latitude, receiver.longitude),
+            })
+            .collect(),
+    )
+}
+
+// Function for dispatchers to get the region's open posts expiring within a
+// day, most urgent first, with the drivers and receivers who could take each.
+// Posts come from the open post listing rather than a scan of every post, so
+// the queue stays cheap to poll.
+#[ic_cdk::query]
+fn get_dispatch_queue(region: String) -> Result<Vec<DispatchItem>, FoodShareError> {
+    require_region_admin(&region, "view the dispatch queue")?;
+
+    let region = region.trim().to_string();
+    if region.is_empty() {
+        return Err(FoodShareError::Validation {
+            field: "region".to_string(),
+            reason: "Region is required".to_string(),
+        });
+    }
+
+    let donors: BTreeSet<String> = DONORS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, donor)| donor.active && donor.region.trim().eq_ignore_ascii_case(&region))
+            .map(|(id, _)| id.to_string())
+            .collect()
+    });
+    let now = time();
+    let cutoff = now + URGENT_WITHIN_HOURS * NANOS_PER_HOUR;
+    let mut urgent: Vec<(SurplusPost, u64, f64)> = SURPLUS_POSTS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        open_post_ids()
+            .into_iter()
+            .filter_map(|post_id| storage.get(&post_id))
+            .filter(|post| donors.contains(&post.donor_id))
+            .filter_map(|post| {
+                let expires_at = expires_at(&post).filter(|at| *at > now && *at <= cutoff)?;
+                let hours_left = (expires_at - now) as f64 / NANOS_PER_HOUR as f64;
+                Some((post, expires_at, hours_left))
+            })
+            .collect()
+    });
+    let score = |post: &SurplusPost, hours_left: f64| {
+        post.quantity_kg as f64 / hours_left.max(MIN_HOURS_LEFT)
+    };
+    urgent.sort_by(|(a, _, a_hours), (b, _, b_hours)| {
+        score(b, *b_hours)
+            .total_cmp(&score(a, *a_hours))
+            .then(a.id.cmp(&b.id))
+    });
+    urgent.truncate(MAX_QUEUE_LEN);
+
+    let drivers: Vec<DriverProfile> = DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, driver)| driver)
+            .filter(|driver| driver.active)
+            .filter(|driver| check_weekly_cap(driver).is_ok() && is_available_now(driver))
+            .collect()
+    });
+    let receivers: Vec<ReceiverProfile> = RECEIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, receiver)| receiver)
+            .filter(|receiver| receiver.active)
+            .collect()
+    });
+
+    Ok(urgent
+        .into_iter()
+        .map(|(post, expires_at, hours_left)| DispatchItem {
+            post_id: post.id,
+            donor_id: post.donor_id.clone(),
+            food_type: post.food_type,
+            quantity_kg: post.quantity_kg,
+            expires_at,
+            hours_left,
+            urgency_score: score(&post, hours_left),
+            drivers: nearby_drivers(&post, &region, &drivers),
+            receivers: eligible_receivers(&post, &receivers),
+        })
+        .collect())
+}
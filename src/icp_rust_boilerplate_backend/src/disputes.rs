@@ -0,0 +1,512 @@
+use crate::assets::owned_asset;
+use crate::assignment_lifecycle::{is_driver_owner, is_receiver_owner};
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{
+    authorize, is_admin, is_governance_or_admin, require_admin, EndpointClass,
+};
+use crate::indexes::{record_for_assignment, store_record};
+use crate::notifications::{notify_assignment, NotificationKind};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::reputation::{record_lost_dispute, void_rating};
+use crate::{
+    is_post_donor, next_id, Assignment, AssignmentStatus, FoodShareError, Memory,
+    ASSIGNMENTS_STORAGE, DONORS_STORAGE, DRIVERS_STORAGE, MEMORY_MANAGER, RECEIVERS_STORAGE,
+    SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_TEXT_LEN: usize = 500;
+const MAX_EVIDENCE: usize = 10;
+
+// DisputeStatus is a custom enum type that is used to represent where a dispute is in moderation
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum DisputeStatus {
+    Open,
+    // A moderator has been assigned
+    UnderReview,
+    Resolved,
+    Dismissed,
+}
+
+impl DisputeStatus {
+    fn can_move_to(&self, next: DisputeStatus) -> bool {
+        use DisputeStatus::*;
+        matches!(
+            (self, next),
+            (Open, UnderReview)
+                | (Open, Dismissed)
+                | (UnderReview, UnderReview)
+                | (UnderReview, Resolved)
+                | (UnderReview, Dismissed)
+        )
+    }
+
+    fn is_closed(&self) -> bool {
+        matches!(self, DisputeStatus::Resolved | DisputeStatus::Dismissed)
+    }
+}
+
+// Outcome a moderator settles a dispute with
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DisputeResolution {
+    // Party found at fault; a driver or donor at fault has it counted in their reputation
+    at_fault: Option<EntityType>,
+    // Removes the receiver's rating of the delivery from the record and the reputations
+    void_rating: bool,
+    note: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Dispute {
+    id: u64,
+    assignment_id: u64,
+    // Donor, Receiver or Driver
+    opened_by_type: EntityType,
+    opened_by: Principal,
+    reason: String,
+    // Finished uploads, see the assets module
+    evidence_asset_ids: Vec<u64>,
+    moderator: Option<Principal>,
+    status: DisputeStatus,
+    resolution: Option<DisputeResolution>,
+    opened_at: u64,
+    updated_at: u64,
+    closed_at: Option<u64>,
+}
+
+impl Storable for Dispute {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Dispute {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Dispute Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct DisputePayload {
+    assignment_id: u64,
+    reason: String,
+    evidence_asset_ids: Vec<u64>,
+}
+
+thread_local! {
+    static DISPUTES_STORAGE: RefCell<StableBTreeMap<u64, Dispute, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(64)))
+    ));
+}
+
+fn validate_text(field: &str, text: &str) -> Result<(), FoodShareError> {
+    if text.trim().is_empty() || text.len() > MAX_TEXT_LEN {
+        return Err(FoodShareError::Validation {
+            field: field.to_string(),
+            reason: format!("Text must be between 1 and {} bytes", MAX_TEXT_LEN),
+        });
+    }
+    Ok(())
+}
+
+fn validate_evidence(asset_ids: &[u64]) -> Result<(), FoodShareError> {
+    if asset_ids.len() > MAX_EVIDENCE {
+        return Err(FoodShareError::Validation {
+            field: "evidence_asset_ids".to_string(),
+            reason: format!("A dispute can have at most {} attachments", MAX_EVIDENCE),
+        });
+    }
+    for asset_id in asset_ids {
+        owned_asset(*asset_id)?;
+    }
+    Ok(())
+}
+
+fn load_assignment(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })
+}
+
+fn load_dispute(dispute_id: u64) -> Result<Dispute, FoodShareError> {
+    DISPUTES_STORAGE
+        .with(|storage| storage.borrow().get(&dispute_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Dispute with id={} not found", dispute_id),
+        })
+}
+
+// Function to get the side the caller is on in an assignment, if any
+fn caller_party(assignment: &Assignment) -> Option<EntityType> {
+    let is_donor = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+        .is_some_and(|post| is_post_donor(&post));
+    if is_donor {
+        Some(EntityType::Donor)
+    } else if is_receiver_owner(assignment) {
+        Some(EntityType::Receiver)
+    } else if is_driver_owner(assignment) {
+        Some(EntityType::Driver)
+    } else {
+        None
+    }
+}
+
+// Function to get the principals behind the donor, receiver and driver of an assignment
+fn party_principals(assignment: &Assignment) -> Vec<Principal> {
+    let donor = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+        .and_then(|post| post.donor_id.parse::<u64>().ok())
+        .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)))
+        .and_then(|donor| donor.owner);
+    let receiver = RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.receiver_id))
+        .and_then(|receiver| receiver.owner);
+    let driver = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.driver_id))
+        .and_then(|driver| driver.owner);
+    [donor, receiver, driver].into_iter().flatten().collect()
+}
+
+// Function to load a dispute the caller moderates; admins may act on any dispute
+fn moderated_dispute(dispute_id: u64) -> Result<Dispute, FoodShareError> {
+    let dispute = load_dispute(dispute_id)?;
+    let caller = ic_cdk::caller();
+    if dispute.moderator != Some(caller) && !is_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the assigned moderator or an admin can act on this dispute".to_string(),
+        });
+    }
+    Ok(dispute)
+}
+
+// Function to apply a status change, store the dispute and tell the parties
+fn store_dispute(
+    before: &Dispute,
+    mut dispute: Dispute,
+    next: DisputeStatus,
+) -> Result<Dispute, FoodShareError> {
+    if !before.status.can_move_to(next) {
+        return Err(FoodShareError::Conflict {
+            msg: format!("Dispute cannot move from {:?} to {:?}", before.status, next),
+        });
+    }
+    let now = time();
+    dispute.status = next;
+    dispute.updated_at = now;
+    if next.is_closed() {
+        dispute.closed_at = Some(now);
+    }
+
+    DISPUTES_STORAGE.with(|storage| storage.borrow_mut().insert(dispute.id, dispute.clone()));
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::Dispute,
+        dispute.id,
+        Some(before),
+        Some(&dispute),
+    );
+    record_event(
+        EntityType::Assignment,
+        dispute.assignment_id,
+        Some(dispute.assignment_id),
+        EventKind::Updated,
+        format!("Dispute {} is {:?}", dispute.id, next),
+    );
+    if let Ok(assignment) = load_assignment(dispute.assignment_id) {
+        notify_assignment(
+            &assignment,
+            NotificationKind::DisputeUpdated,
+            &format!(
+                "Dispute {} on surplus post {} is {:?}",
+                dispute.id, assignment.surplus_post_id, next
+            ),
+        );
+    }
+    log(
+        LogLevel::Info,
+        format!("Dispute {} moved to {:?}", dispute.id, next),
+    );
+
+    Ok(dispute)
+}
+
+// Function to get every dispute a moderator found against a driver or donor,
+// for recounting reputations
+pub(crate) fn upheld_disputes() -> Vec<(Assignment, EntityType)> {
+    DISPUTES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter_map(|(_, dispute)| {
+                let at_fault = dispute.resolution?.at_fault?;
+                Some((load_assignment(dispute.assignment_id).ok()?, at_fault))
+            })
+            .collect()
+    })
+}
+
+// Function for the donor, the receiver or the driver of an assignment to open
+// a dispute about it; each party has at most one dispute open per assignment
+#[ic_cdk::update]
+async fn open_dispute(payload: DisputePayload) -> Result<Dispute, FoodShareError> {
+    start_trace("open_dispute");
+    authorize(EndpointClass::Logistics).await?;
+
+    validate_text("reason", &payload.reason)?;
+    validate_evidence(&payload.evidence_asset_ids)?;
+
+    let assignment = load_assignment(payload.assignment_id)?;
+    let opened_by_type = caller_party(&assignment).ok_or(FoodShareError::Unauthorized {
+        msg: "Only the donor, the receiver or the driver of this assignment can dispute it"
+            .to_string(),
+    })?;
+    let caller = ic_cdk::caller();
+    let already_open = DISPUTES_STORAGE.with(|storage| {
+        storage.borrow().iter().any(|(_, dispute)| {
+            dispute.assignment_id == assignment.id
+                && dispute.opened_by == caller
+                && !dispute.status.is_closed()
+        })
+    });
+    if already_open {
+        return Err(FoodShareError::Conflict {
+            msg: "You already have an open dispute on this assignment".to_string(),
+        });
+    }
+
+    let now = time();
+    let dispute = Dispute {
+        id: next_id(),
+        assignment_id: assignment.id,
+        opened_by_type,
+        opened_by: caller,
+        reason: payload.reason,
+        evidence_asset_ids: payload.evidence_asset_ids,
+        moderator: None,
+        status: DisputeStatus::Open,
+        resolution: None,
+        opened_at: now,
+        updated_at: now,
+        closed_at: None,
+    };
+    DISPUTES_STORAGE.with(|storage| storage.borrow_mut().insert(dispute.id, dispute.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::Dispute,
+        dispute.id,
+        None,
+        Some(&dispute),
+    );
+
+    record_event(
+        EntityType::Assignment,
+        assignment.id,
+        Some(assignment.id),
+        EventKind::Updated,
+        format!("{:?} opened dispute {}", opened_by_type, dispute.id),
+    );
+    notify_assignment(
+        &assignment,
+        NotificationKind::DisputeUpdated,
+        &format!(
+            "The {:?} opened a dispute on surplus post {}",
+            opened_by_type, assignment.surplus_post_id
+        ),
+    );
+    log(LogLevel::Info, format!("Opened dispute {}", dispute.id));
+
+    Ok(dispute)
+}
+
+// Function for the party that opened a dispute to attach more evidence while
+// it is still being handled
+#[ic_cdk::update]
+async fn add_dispute_evidence(dispute_id: u64, asset_id: u64) -> Result<Dispute, FoodShareError> {
+    start_trace("add_dispute_evidence");
+    authorize(EndpointClass::Logistics).await?;
+
+    let before = load_dispute(dispute_id)?;
+    if before.opened_by != ic_cdk::caller() {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the party that opened this dispute can add evidence".to_string(),
+        });
+    }
+    if before.status.is_closed() {
+        return Err(FoodShareError::Conflict {
+            msg: format!("Dispute is already {:?}", before.status),
+        });
+    }
+    if before.evidence_asset_ids.contains(&asset_id) {
+        return Err(FoodShareError::Conflict {
+            msg: "Asset is already attached to this dispute".to_string(),
+        });
+    }
+
+    let mut dispute = before.clone();
+    dispute.evidence_asset_ids.push(asset_id);
+    validate_evidence(&dispute.evidence_asset_ids)?;
+    dispute.updated_at = time();
+    DISPUTES_STORAGE.with(|storage| storage.borrow_mut().insert(dispute_id, dispute.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::Dispute,
+        dispute_id,
+        Some(&before),
+        Some(&dispute),
+    );
+
+    Ok(dispute)
+}
+
+// Function for admins to hand a dispute to a moderator, or to reassign it,
+// which puts it under review. Parties of the assignment cannot moderate it.
+#[ic_cdk::update]
+fn assign_dispute_moderator(
+    dispute_id: u64,
+    moderator: Principal,
+) -> Result<Dispute, FoodShareError> {
+    start_trace("assign_dispute_moderator");
+    require_admin("assign dispute moderators")?;
+
+    let before = load_dispute(dispute_id)?;
+    let assignment = load_assignment(before.assignment_id)?;
+    if moderator == Principal::anonymous() || party_principals(&assignment).contains(&moderator) {
+        return Err(FoodShareError::Validation {
+            field: "moderator".to_string(),
+            reason: "Moderator cannot be anonymous or a party of the assignment".to_string(),
+        });
+    }
+    let mut dispute = before.clone();
+    dispute.moderator = Some(moderator);
+    store_dispute(&before, dispute, DisputeStatus::UnderReview)
+}
+
+// Function for the moderator to settle a dispute under review, optionally
+// counting it against the party at fault and voiding the delivery's rating
+#[ic_cdk::update]
+fn resolve_dispute(
+    dispute_id: u64,
+    resolution: DisputeResolution,
+) -> Result<Dispute, FoodShareError> {
+    start_trace("resolve_dispute");
+    let before = moderated_dispute(dispute_id)?;
+
+    validate_text("note", &resolution.note)?;
+    if resolution.at_fault.is_some_and(|party| {
+        !matches!(
+            party,
+            EntityType::Donor | EntityType::Receiver | EntityType::Driver
+        )
+    }) {
+        return Err(FoodShareError::Validation {
+            field: "at_fault".to_string(),
+            reason: "Party at fault must be the Donor, the Receiver or the Driver".to_string(),
+        });
+    }
+    let assignment = load_assignment(before.assignment_id)?;
+    let rated_record = if resolution.void_rating {
+        let record = record_for_assignment(assignment.id)
+            .filter(|record| record.rating.is_some())
+            .filter(|_| assignment.status == AssignmentStatus::Delivered)
+            .ok_or(FoodShareError::Validation {
+                field: "void_rating".to_string(),
+                reason: "The delivery has no rating to void".to_string(),
+            })?;
+        Some(record)
+    } else {
+        None
+    };
+
+    let mut dispute = before.clone();
+    dispute.resolution = Some(resolution.clone());
+    let dispute = store_dispute(&before, dispute, DisputeStatus::Resolved)?;
+
+    if let Some(party) = resolution.at_fault {
+        record_lost_dispute(&assignment, party);
+    }
+    if let Some(record_before) = rated_record {
+        let mut record = record_before.clone();
+        record.rating = None;
+        store_record(&record);
+        audit(
+            AuditAction::Updated,
+            AuditEntity::SurplusRecord,
+            record.id,
+            Some(&record_before),
+            Some(&record),
+        );
+        void_rating(&record_before);
+    }
+
+    Ok(dispute)
+}
+
+// Function for the moderator, or an admin before review starts, to close a
+// dispute without any finding
+#[ic_cdk::update]
+fn dismiss_dispute(dispute_id: u64, note: String) -> Result<Dispute, FoodShareError> {
+    start_trace("dismiss_dispute");
+    let before = moderated_dispute(dispute_id)?;
+
+    validate_text("note", &note)?;
+    let mut dispute = before.clone();
+    dispute.resolution = Some(DisputeResolution {
+        at_fault: None,
+        void_rating: false,
+        note,
+    });
+    store_dispute(&before, dispute, DisputeStatus::Dismissed)
+}
+
+// Function for the parties of an assignment, or governance, to get its disputes
+#[ic_cdk::query]
+fn get_assignment_disputes(assignment_id: u64) -> Result<Vec<Dispute>, FoodShareError> {
+    let assignment = load_assignment(assignment_id)?;
+    if caller_party(&assignment).is_none() && !is_governance_or_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the parties of this assignment or governance can view its disputes"
+                .to_string(),
+        });
+    }
+
+    Ok(DISPUTES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, dispute)| dispute)
+            .filter(|dispute| dispute.assignment_id == assignment_id)
+            .collect()
+    }))
+}
+
+// Function for governance to list the disputes in a status, oldest first
+#[ic_cdk::query]
+fn get_disputes_by_status(status: DisputeStatus) -> Result<Vec<Dispute>, FoodShareError> {
+    if !is_governance_or_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only governance can list disputes".to_string(),
+        });
+    }
+
+    Ok(DISPUTES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, dispute)| dispute)
+            .filter(|dispute| dispute.status == status)
+            .collect()
+    }))
+}
@@ -0,0 +1,623 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::contact_vault::{self, ContactFields};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::require_admin;
+use crate::id_sequences::{allocate_id, IdEntity};
+use crate::observability::{current_trace_id, log, resume_trace, start_trace, LogLevel};
+use crate::validation::{normalize_email, normalize_phone_number};
+use crate::{
+    next_id, taxonomy, BusinessType, DonorProfile, FoodShareError, Memory, DONORS_STORAGE,
+    MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+    HttpResponse as OutcallResponse, TransformArgs, TransformContext,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const MAX_NAME_LEN: usize = 100;
+const MAX_ADDRESS_LEN: usize = 200;
+const MAX_REGION_LEN: usize = 64;
+const MAX_MESSAGE_LEN: usize = 1000;
+const MAX_NOTES_LEN: usize = 500;
+const MAX_CAPTCHA_TOKEN_LEN: usize = 4096;
+const MAX_URL_LEN: usize = 256;
+const MAX_SECRET_LEN: usize = 256;
+const MAX_LEADS_PER_HOUR: u64 = 1000;
+// Enough for a small request and response on a 13-node subnet
+const CAPTCHA_OUTCALL_CYCLES: u128 = 1_000_000_000;
+const CAPTCHA_MAX_RESPONSE_BYTES: u64 = 2048;
+
+// LeadStatus is a custom enum type that is used to represent where a donor lead is in triage
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum LeadStatus {
+    New,
+    Contacted,
+    Rejected,
+    // A donor profile was created from the lead
+    Converted,
+}
+
+impl LeadStatus {
+    fn is_open(self) -> bool {
+        matches!(self, LeadStatus::New | LeadStatus::Contacted)
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DonorLead {
+    id: u64,
+    business_name: String,
+    contact_name: String,
+    phone_number: String,
+    email: String,
+    address: String,
+    business_type: BusinessType,
+    region: String,
+    message: String,
+    status: LeadStatus,
+    // Anonymous when the form was sent without signing in
+    submitted_by: Principal,
+    submitted_at: u64,
+    triaged_by: Option<Principal>,
+    triaged_at: Option<u64>,
+    notes: Option<String>,
+    // Set once the lead is converted
+    donor_id: Option<u64>,
+}
+
+impl ContactFields for DonorLead {
+    fn contact_fields(&mut self) -> [&mut String; 2] {
+        [&mut self.phone_number, &mut self.email]
+    }
+}
+
+impl Storable for DonorLead {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DonorLead {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct LeadIntakeConfig {
+    // Verification endpoint the captcha token is posted to, e.g. a siteverify
+    // URL. Every replica makes the call, so it should be a proxy that answers
+    // repeated checks of the same token alike.
+    captcha_verify_url: Option<String>,
+    captcha_secret: Option<String>,
+    // Interest forms accepted per hour across all callers
+    max_leads_per_hour: u64,
+}
+
+impl Default for LeadIntakeConfig {
+    fn default() -> Self {
+        Self {
+            captcha_verify_url: None,
+            captcha_secret: None,
+            max_leads_per_hour: 30,
+        }
+    }
+}
+
+impl Storable for LeadIntakeConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Forms submitted in the current hour
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct LeadIntakeWindow {
+    window_start: u64,
+    count: u64,
+}
+
+impl Storable for LeadIntakeWindow {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static DONOR_LEADS: RefCell<StableBTreeMap<u64, DonorLead, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(88)))
+    ));
+
+    static LEAD_INTAKE_CONFIG: RefCell<Cell<LeadIntakeConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(89))),
+            LeadIntakeConfig::default(),
+        )
+        .expect("Cannot create the lead intake config")
+    );
+
+    static LEAD_INTAKE_WINDOW: RefCell<Cell<LeadIntakeWindow, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(90))),
+            LeadIntakeWindow::default(),
+        )
+        .expect("Cannot create the lead intake window")
+    );
+}
+
+// Donor Interest Form
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct DonorInterestForm {
+    business_name: String,
+    contact_name: String,
+    email: String,
+    phone_number: String,
+    address: String,
+    business_type: BusinessType,
+    region: String,
+    message: String,
+    captcha_token: String,
+}
+
+// The only part of a captcha verification response that is kept
+#[derive(Serialize, Deserialize)]
+struct CaptchaVerdict {
+    success: bool,
+}
+
+fn lead_intake_config() -> LeadIntakeConfig {
+    LEAD_INTAKE_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+fn check_len(field: &str, value: &str, min: usize, max: usize) -> Result<(), FoodShareError> {
+    let len = value.trim().chars().count();
+    if len < min || len > max {
+        return Err(FoodShareError::Validation {
+            field: field.to_string(),
+            reason: format!("Must be between {} and {} characters", min, max),
+        });
+    }
+    Ok(())
+}
+
+// Function to check a form and normalize its email and phone number in place
+fn validate_form(form: &mut DonorInterestForm) -> Result<(), FoodShareError> {
+    check_len("business_name", &form.business_name, 1, MAX_NAME_LEN)?;
+    check_len("contact_name", &form.contact_name, 1, MAX_NAME_LEN)?;
+    check_len("address", &form.address, 1, MAX_ADDRESS_LEN)?;
+    check_len("region", &form.region, 1, MAX_REGION_LEN)?;
+    check_len("message", &form.message, 0, MAX_MESSAGE_LEN)?;
+    form.email = normalize_email(&form.email)?;
+    form.phone_number = normalize_phone_number(&form.phone_number)?;
+    if form.captcha_token.is_empty() || form.captcha_token.len() > MAX_CAPTCHA_TOKEN_LEN {
+        return Err(FoodShareError::Validation {
+            field: "captcha_token".to_string(),
+            reason: "Captcha token is missing or too long".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Function to count a submission against the hourly limit, rejecting it once
+// the limit is reached; attempts count before the captcha is checked, since
+// each check costs an outcall
+fn take_intake_slot(max_per_hour: u64) -> Result<(), FoodShareError> {
+    LEAD_INTAKE_WINDOW.with(|cell| {
+        let mut window = cell.borrow().get().clone();
+        let now = time();
+        if now.saturating_sub(window.window_start) >= NANOS_PER_HOUR {
+            window = LeadIntakeWindow {
+                window_start: now,
+                count: 0,
+            };
+        }
+        if window.count >= max_per_hour {
+            return Err(FoodShareError::QuotaExceeded {
+                msg: "Too many interest forms right now, please try again later".to_string(),
+            });
+        }
+        window.count += 1;
+        cell.borrow_mut()
+            .set(window)
+            .expect("Cannot update the lead intake window");
+        Ok(())
+    })
+}
+
+// Function to percent-encode a value for a form-encoded request body or a URL
+pub(crate) fn form_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// Function to check a captcha token with the configured verification endpoint
+async fn verify_captcha(config: &LeadIntakeConfig, token: &str) -> Result<(), FoodShareError> {
+    let (Some(url), Some(secret)) = (&config.captcha_verify_url, &config.captcha_secret) else {
+        return Err(FoodShareError::InternalError {
+            msg: "Captcha verification is not configured".to_string(),
+        });
+    };
+
+    let request = CanisterHttpRequestArgument {
+        url: url.clone(),
+        max_response_bytes: Some(CAPTCHA_MAX_RESPONSE_BYTES),
+        method: HttpMethod::POST,
+        headers: vec![HttpHeader {
+            name: "Content-Type".to_string(),
+            value: "application/x-www-form-urlencoded".to_string(),
+        }],
+        body: Some(
+            format!(
+                "secret={}&response={}",
+                form_encode(secret),
+                form_encode(token)
+            )
+            .into_bytes(),
+        ),
+        transform: Some(TransformContext::from_name(
+            "transform_captcha_response".to_string(),
+            Vec::new(),
+        )),
+    };
+
+    let trace_id = current_trace_id();
+    let result = http_request(request, CAPTCHA_OUTCALL_CYCLES).await;
+    resume_trace(&trace_id);
+
+    let (response,) = result.map_err(|(code, msg)| FoodShareError::InternalError {
+        msg: format!("Cannot verify the captcha ({:?}): {}", code, msg),
+    })?;
+    let verified = serde_json::from_slice::<CaptchaVerdict>(&response.body)
+        .is_ok_and(|verdict| verdict.success);
+    if !verified {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Captcha verification failed".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Function to reduce a captcha verification response to its verdict, so every
+// replica sees the same response
+#[ic_cdk::query]
+fn transform_captcha_response(args: TransformArgs) -> OutcallResponse {
+    let success = serde_json::from_slice::<CaptchaVerdict>(&args.response.body)
+        .is_ok_and(|verdict| verdict.success);
+    OutcallResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: serde_json::to_vec(&CaptchaVerdict { success })
+            .expect("Cannot encode captcha verdict"),
+    }
+}
+
+// Function for a business to register interest in donating before any
+// coordinator is involved. Callers need not sign in; forms are rate limited
+// and must carry a captcha token the verification endpoint accepts.
+#[ic_cdk::update]
+async fn submit_donor_interest(mut form: DonorInterestForm) -> Result<u64, FoodShareError> {
+    start_trace("submit_donor_interest");
+
+    validate_form(&mut form)?;
+    let email = form.email.clone();
+    let already_open = DONOR_LEADS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .any(|(_, lead)| lead.status.is_open() && contact_vault::open(&lead.email) == email)
+    });
+    if already_open {
+        return Err(FoodShareError::Conflict {
+            msg: "We already have your interest and will be in touch".to_string(),
+        });
+    }
+
+    let config = lead_intake_config();
+    take_intake_slot(config.max_leads_per_hour)?;
+    verify_captcha(&config, &form.captcha_token).await?;
+    contact_vault::ensure_key().await?;
+
+    let lead = contact_vault::sealed(DonorLead {
+        id: next_id(),
+        business_name: form.business_name.trim().to_string(),
+        contact_name: form.contact_name.trim().to_string(),
+        phone_number: form.phone_number,
+        email,
+        address: form.address.trim().to_string(),
+        business_type: form.business_type,
+        region: form.region.trim().to_string(),
+        message: form.message.trim().to_string(),
+        status: LeadStatus::New,
+        submitted_by: ic_cdk::caller(),
+        submitted_at: time(),
+        triaged_by: None,
+        triaged_at: None,
+        notes: None,
+        donor_id: None,
+    });
+    DONOR_LEADS.with(|storage| storage.borrow_mut().insert(lead.id, lead.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::DonorLead,
+        lead.id,
+        None,
+        Some(&lead),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Received donor interest lead {}", lead.id),
+    );
+
+    Ok(lead.id)
+}
+
+fn load_lead(lead_id: u64) -> Result<DonorLead, FoodShareError> {
+    DONOR_LEADS
+        .with(|storage| storage.borrow().get(&lead_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Lead ID does not exist".to_string(),
+        })
+}
+
+fn store_lead(before: &DonorLead, lead: &DonorLead) {
+    DONOR_LEADS.with(|storage| storage.borrow_mut().insert(lead.id, lead.clone()));
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::DonorLead,
+        lead.id,
+        Some(before),
+        Some(lead),
+    );
+}
+
+// Function for coordinators to get the triage queue, oldest first; without a
+// status it holds the leads still open
+#[ic_cdk::query]
+fn get_lead_queue(status: Option<LeadStatus>) -> Result<Vec<DonorLead>, FoodShareError> {
+    require_admin("view donor leads")?;
+
+    Ok(DONOR_LEADS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, lead)| lead)
+            .filter(|lead| match status {
+                Some(status) => lead.status == status,
+                None => lead.status.is_open(),
+            })
+            .map(contact_vault::revealed)
+            .collect()
+    }))
+}
+
+// Function for coordinators to record that a lead was contacted or rejected
+#[ic_cdk::update]
+fn triage_lead(
+    lead_id: u64,
+    status: LeadStatus,
+    notes: Option<String>,
+) -> Result<DonorLead, FoodShareError> {
+    start_trace("triage_lead");
+    require_admin("triage donor leads")?;
+
+    if !matches!(status, LeadStatus::Contacted | LeadStatus::Rejected) {
+        return Err(FoodShareError::Validation {
+            field: "status".to_string(),
+            reason: "Leads can only be marked contacted or rejected; convert them instead"
+                .to_string(),
+        });
+    }
+    if let Some(notes) = &notes {
+        check_len("notes", notes, 0, MAX_NOTES_LEN)?;
+    }
+
+    let before = load_lead(lead_id)?;
+    if !before.status.is_open() {
+        return Err(FoodShareError::Conflict {
+            msg: format!("Lead has already been {:?}", before.status),
+        });
+    }
+    let mut lead = before.clone();
+    lead.status = status;
+    lead.triaged_by = Some(ic_cdk::caller());
+    lead.triaged_at = Some(time());
+    if notes.is_some() {
+        lead.notes = notes;
+    }
+    store_lead(&before, &lead);
+
+    log(
+        LogLevel::Info,
+        format!("Marked donor lead {} {:?}", lead_id, status),
+    );
+
+    Ok(contact_vault::revealed(lead))
+}
+
+// Function for coordinators to turn an open lead into a donor profile. The
+// profile belongs to the owner given, or to whoever sent the form if they
+// were signed in.
+#[ic_cdk::update]
+async fn convert_lead(
+    lead_id: u64,
+    owner: Option<Principal>,
+) -> Result<DonorProfile, FoodShareError> {
+    start_trace("convert_lead");
+    require_admin("convert donor leads")?;
+    contact_vault::ensure_key().await?;
+
+    let before = load_lead(lead_id)?;
+    if !before.status.is_open() {
+        return Err(FoodShareError::Conflict {
+            msg: format!("Lead has already been {:?}", before.status),
+        });
+    }
+    let owner = owner
+        .or_else(|| (before.submitted_by != Principal::anonymous()).then_some(before.submitted_by))
+        .filter(|owner| *owner != Principal::anonymous())
+        .ok_or(FoodShareError::Validation {
+            field: "owner".to_string(),
+            reason: "An owner is required for leads sent without signing in".to_string(),
+        })?;
+
+    let contact = contact_vault::revealed(before.clone());
+    let already_registered = contact_vault::email_taken(EntityType::Donor, &contact.email, None)
+        || DONORS_STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .iter()
+                .any(|(_, donor)| donor.active && donor.owner == Some(owner))
+        });
+    if already_registered {
+        return Err(FoodShareError::Conflict {
+            msg: "The owner or email already has a donor profile".to_string(),
+        });
+    }
+
+    let id = allocate_id(IdEntity::Profile);
+    let donor_profile = contact_vault::sealed(DonorProfile {
+        id,
+        name: contact.business_name,
+        phone_number: contact.phone_number,
+        email: contact.email.clone(),
+        address: contact.address,
+        business_type: contact.business_type,
+        business_type_id: taxonomy::builtin_business_type(contact.business_type),
+        region: contact.region,
+        latitude: None,
+        longitude: None,
+        owner: Some(owner),
+        active: true,
+        deactivated_at: None,
+        created_at: time(),
+        verified: None,
+    });
+    DONORS_STORAGE.with(|storage| storage.borrow_mut().insert(id, donor_profile.clone()));
+    contact_vault::index_email(EntityType::Donor, id, None, &contact.email);
+    audit(
+        AuditAction::Created,
+        AuditEntity::Donor,
+        id,
+        None,
+        Some(&donor_profile),
+    );
+
+    let mut lead = before.clone();
+    lead.status = LeadStatus::Converted;
+    lead.triaged_by = Some(ic_cdk::caller());
+    lead.triaged_at = Some(time());
+    lead.donor_id = Some(id);
+    store_lead(&before, &lead);
+
+    record_event(
+        EntityType::Donor,
+        id,
+        None,
+        EventKind::Created,
+        format!(
+            "Donor {} pre-registered from lead {}",
+            donor_profile.name, lead_id
+        ),
+    );
+    log(
+        LogLevel::Info,
+        format!("Converted donor lead {} into donor {}", lead_id, id),
+    );
+
+    Ok(contact_vault::revealed(donor_profile))
+}
+
+// Function for admins to get the captcha and rate limit settings of the interest form
+#[ic_cdk::query]
+fn get_lead_intake_config() -> Result<LeadIntakeConfig, FoodShareError> {
+    require_admin("view the lead intake config")?;
+    Ok(lead_intake_config())
+}
+
+// Function for admins to set the captcha endpoint and secret and the hourly
+// limit of the interest form
+#[ic_cdk::update]
+fn set_lead_intake_config(config: LeadIntakeConfig) -> Result<LeadIntakeConfig, FoodShareError> {
+    start_trace("set_lead_intake_config");
+    require_admin("update the lead intake config")?;
+
+    if let Some(url) = &config.captcha_verify_url {
+        if !url.starts_with("https://") || url.len() > MAX_URL_LEN {
+            return Err(FoodShareError::Validation {
+                field: "captcha_verify_url".to_string(),
+                reason: format!("URL must use https and be at most {} bytes", MAX_URL_LEN),
+            });
+        }
+    }
+    if config
+        .captcha_secret
+        .as_ref()
+        .is_some_and(|secret| secret.is_empty() || secret.len() > MAX_SECRET_LEN)
+    {
+        return Err(FoodShareError::Validation {
+            field: "captcha_secret".to_string(),
+            reason: format!("Secret must be between 1 and {} bytes", MAX_SECRET_LEN),
+        });
+    }
+    if !(1..=MAX_LEADS_PER_HOUR).contains(&config.max_leads_per_hour) {
+        return Err(FoodShareError::Validation {
+            field: "max_leads_per_hour".to_string(),
+            reason: format!("Hourly limit must be between 1 and {}", MAX_LEADS_PER_HOUR),
+        });
+    }
+
+    let before = LEAD_INTAKE_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the lead intake config");
+    // Keep the captcha secret out of the audit log
+    let redact = |config: &LeadIntakeConfig| LeadIntakeConfig {
+        captcha_secret: config
+            .captcha_secret
+            .as_ref()
+            .map(|_| "<redacted>".to_string()),
+        ..config.clone()
+    };
+    audit(
+        AuditAction::Configured,
+        AuditEntity::LeadIntakeConfig,
+        0,
+        Some(&redact(&before)),
+        Some(&redact(&config)),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Interest forms now limited to {} per hour",
+            config.max_leads_per_hour
+        ),
+    );
+
+    Ok(config)
+}
@@ -0,0 +1,325 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::{authorize, is_governance_or_admin, EndpointClass};
+use crate::indexes::assignments_for_driver;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::vehicles::load_limit_kg;
+use crate::{DriverProfile, FoodShareError, Memory, VehicleType, DRIVERS_STORAGE, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use chrono::{DateTime, Datelike, Timelike};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+const MAX_SHIFTS: usize = 28;
+const MAX_CONCURRENT_ASSIGNMENTS: u32 = 20;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct ShiftWindow {
+    // 0 is Monday, 6 is Sunday
+    day: u8,
+    // Minutes after midnight UTC; the window ends before end_minute
+    start_minute: u16,
+    end_minute: u16,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DriverAvailability {
+    driver_id: u64,
+    online: bool,
+    // Empty means the driver takes assignments whenever they are online
+    weekly_schedule: Vec<ShiftWindow>,
+    max_concurrent_assignments: u32,
+    vehicle_capacity_kg: u32,
+    updated_at: u64,
+}
+
+impl Storable for DriverAvailability {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DriverAvailability {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Availability Payload; a capacity left as None uses the vehicle type's typical load
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct AvailabilityPayload {
+    weekly_schedule: Vec<ShiftWindow>,
+    max_concurrent_assignments: u32,
+    vehicle_capacity_kg: Option<u32>,
+}
+
+thread_local! {
+    static AVAILABILITY_STORAGE: RefCell<StableBTreeMap<u64, DriverAvailability, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(45)))
+    ));
+}
+
+// Function to get the typical load of a vehicle type in kilograms
+pub(crate) fn vehicle_capacity_kg(vehicle_type: VehicleType) -> u32 {
+    match vehicle_type {
+        VehicleType::Bicycle => 20,
+        VehicleType::Motorbike => 40,
+        VehicleType::Car | VehicleType::ElectricCar => 200,
+        VehicleType::Van => 1_000,
+        VehicleType::Truck => 10_000,
+    }
+}
+
+// Function to check whether a driver has set their availability
+pub(crate) fn has_availability(driver_id: u64) -> bool {
+    AVAILABILITY_STORAGE.with(|storage| storage.borrow().contains_key(&driver_id))
+}
+
+// Function to get a driver's availability. Drivers who never set one keep the
+// old behaviour: always online, one assignment at a time.
+pub(crate) fn availability_of(driver: &DriverProfile) -> DriverAvailability {
+    AVAILABILITY_STORAGE
+        .with(|storage| storage.borrow().get(&driver.id))
+        .unwrap_or_else(|| DriverAvailability {
+            driver_id: driver.id,
+            online: true,
+            weekly_schedule: Vec::new(),
+            max_concurrent_assignments: 1,
+            vehicle_capacity_kg: vehicle_capacity_kg(driver.vehicle_type),
+            updated_at: 0,
+        })
+}
+
+impl DriverAvailability {
+    pub(crate) fn capacity_kg(&self) -> u32 {
+        self.vehicle_capacity_kg
+    }
+
+    fn on_shift(&self, now: u64) -> bool {
+        if self.weekly_schedule.is_empty() {
+            return true;
+        }
+        let now = DateTime::from_timestamp_nanos(now as i64);
+        let day = now.weekday().num_days_from_monday() as u8;
+        let minute = (now.hour() * 60 + now.minute()) as u16;
+        self.weekly_schedule.iter().any(|shift| {
+            shift.day == day && (shift.start_minute..shift.end_minute).contains(&minute)
+        })
+    }
+}
+
+// Function to get the assignment count and kilograms a driver is carrying right now
+fn current_load(driver_id: u64) -> (u32, u64) {
+    assignments_for_driver(driver_id)
+        .iter()
+        .filter(|assignment| assignment.status.is_active())
+        .fold((0, 0), |(count, kg), assignment| {
+            (count + 1, kg + assignment.carried_kg() as u64)
+        })
+}
+
+// Function to reject an assignment of `quantity_kg` to a driver who is offline,
+// off shift, at their concurrent limit or out of vehicle capacity
+pub(crate) fn check_can_take(
+    driver: &DriverProfile,
+    quantity_kg: u32,
+) -> Result<(), FoodShareError> {
+    let availability = availability_of(driver);
+    if !availability.online {
+        return Err(FoodShareError::Conflict {
+            msg: "Driver is offline".to_string(),
+        });
+    }
+    if !availability.on_shift(time()) {
+        return Err(FoodShareError::Conflict {
+            msg: "Driver is outside their scheduled shifts".to_string(),
+        });
+    }
+
+    let (active, carried_kg) = current_load(driver.id);
+    if active >= availability.max_concurrent_assignments {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "Driver already has {} of {} concurrent assignments",
+                active, availability.max_concurrent_assignments
+            ),
+        });
+    }
+    let capacity_kg = load_limit_kg(driver, availability.vehicle_capacity_kg);
+    if carried_kg + quantity_kg as u64 > capacity_kg as u64 {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "{} kg would exceed the driver's vehicle capacity of {} kg ({} kg already on board)",
+                quantity_kg, capacity_kg, carried_kg
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+// Function to check whether a driver can take another assignment now, whatever its size
+pub(crate) fn is_available_now(driver: &DriverProfile) -> bool {
+    let availability = availability_of(driver);
+    availability.online
+        && availability.on_shift(time())
+        && current_load(driver.id).0 < availability.max_concurrent_assignments
+}
+
+fn validate_schedule(weekly_schedule: &[ShiftWindow]) -> Result<(), FoodShareError> {
+    if weekly_schedule.len() > MAX_SHIFTS {
+        return Err(FoodShareError::Validation {
+            field: "weekly_schedule".to_string(),
+            reason: format!("A schedule can have at most {} shifts", MAX_SHIFTS),
+        });
+    }
+    let invalid = weekly_schedule.iter().any(|shift| {
+        shift.day > 6
+            || shift.start_minute >= shift.end_minute
+            || shift.end_minute > MINUTES_PER_DAY
+    });
+    if invalid {
+        return Err(FoodShareError::Validation {
+            field: "weekly_schedule".to_string(),
+            reason: "Shifts need a day from 0 to 6 and start before they end, within the day"
+                .to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Function to load a driver the caller may manage: the driver themselves or governance
+fn owned_driver(driver_id: u64) -> Result<DriverProfile, FoodShareError> {
+    let driver = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&driver_id))
+        .filter(|driver| driver.active)
+        .ok_or(FoodShareError::NotFound {
+            msg: "Driver ID does not exist or is deactivated".to_string(),
+        })?;
+
+    let caller = ic_cdk::caller();
+    if driver.owner != Some(caller) && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the driver or governance can change their availability".to_string(),
+        });
+    }
+    Ok(driver)
+}
+
+fn store_availability(availability: DriverAvailability) -> DriverAvailability {
+    let before = AVAILABILITY_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(availability.driver_id, availability.clone())
+    });
+    audit(
+        if before.is_some() {
+            AuditAction::Updated
+        } else {
+            AuditAction::Created
+        },
+        AuditEntity::DriverAvailability,
+        availability.driver_id,
+        before.as_ref(),
+        Some(&availability),
+    );
+    availability
+}
+
+// Function for a driver to set their shifts, concurrent assignment limit and vehicle capacity
+#[ic_cdk::update]
+async fn set_availability(
+    driver_id: u64,
+    payload: AvailabilityPayload,
+) -> Result<DriverAvailability, FoodShareError> {
+    start_trace("set_availability");
+    authorize(EndpointClass::Registration).await?;
+
+    let driver = owned_driver(driver_id)?;
+    validate_schedule(&payload.weekly_schedule)?;
+    if !(1..=MAX_CONCURRENT_ASSIGNMENTS).contains(&payload.max_concurrent_assignments) {
+        return Err(FoodShareError::Validation {
+            field: "max_concurrent_assignments".to_string(),
+            reason: format!(
+                "Concurrent assignments must be between 1 and {}",
+                MAX_CONCURRENT_ASSIGNMENTS
+            ),
+        });
+    }
+    if payload.vehicle_capacity_kg == Some(0) {
+        return Err(FoodShareError::Validation {
+            field: "vehicle_capacity_kg".to_string(),
+            reason: "Vehicle capacity must be greater than zero".to_string(),
+        });
+    }
+
+    let availability = store_availability(DriverAvailability {
+        weekly_schedule: payload.weekly_schedule,
+        max_concurrent_assignments: payload.max_concurrent_assignments,
+        vehicle_capacity_kg: payload
+            .vehicle_capacity_kg
+            .unwrap_or_else(|| vehicle_capacity_kg(driver.vehicle_type)),
+        updated_at: time(),
+        ..availability_of(&driver)
+    });
+
+    log(
+        LogLevel::Info,
+        format!("Updated availability of driver {}", driver_id),
+    );
+
+    Ok(availability)
+}
+
+async fn set_online(driver_id: u64, online: bool) -> Result<DriverAvailability, FoodShareError> {
+    authorize(EndpointClass::Registration).await?;
+
+    let driver = owned_driver(driver_id)?;
+    let availability = store_availability(DriverAvailability {
+        online,
+        updated_at: time(),
+        ..availability_of(&driver)
+    });
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Driver {} went {}",
+            driver_id,
+            if online { "online" } else { "offline" }
+        ),
+    );
+
+    Ok(availability)
+}
+
+// Function for a driver to start taking assignments
+#[ic_cdk::update]
+async fn go_online(driver_id: u64) -> Result<DriverAvailability, FoodShareError> {
+    start_trace("go_online");
+    set_online(driver_id, true).await
+}
+
+// Function for a driver to stop taking new assignments; current ones carry on
+#[ic_cdk::update]
+async fn go_offline(driver_id: u64) -> Result<DriverAvailability, FoodShareError> {
+    start_trace("go_offline");
+    set_online(driver_id, false).await
+}
+
+// Function to get a driver's availability
+#[ic_cdk::query]
+fn get_availability(driver_id: u64) -> Result<DriverAvailability, FoodShareError> {
+    DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&driver_id))
+        .map(|driver| availability_of(&driver))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Driver with id={} not found", driver_id),
+        })
+}
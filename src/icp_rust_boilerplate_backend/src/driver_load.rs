@@ -0,0 +1,186 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::contact_vault::redacted;
+use crate::governance::{authorize, is_governance_or_admin, EndpointClass};
+use crate::indexes::assignments_for_driver;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{DriverProfile, FoodShareError, DRIVERS_STORAGE};
+use ic_cdk::api::time;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const WEEK_NANOS: u64 = 7 * NANOS_PER_DAY;
+const MONTH_NANOS: u64 = 30 * NANOS_PER_DAY;
+// An assignment counts half as much towards a driver's load after this long
+const LOAD_HALF_LIFE_NANOS: u64 = 3 * NANOS_PER_DAY;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DriverLoad {
+    driver_id: u64,
+    driver_name: String,
+    assignments_last_7_days: u64,
+    assignments_last_30_days: u64,
+    decayed_load: f64,
+    weekly_assignment_cap: Option<u32>,
+    // Share of all assignments in the last 30 days
+    share_percent: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct FairnessReport {
+    drivers: Vec<DriverLoad>,
+    total_assignments_last_30_days: u64,
+    // 0 when assignments are spread evenly, approaching 1 when a few drivers get them all
+    gini_coefficient: f64,
+}
+
+// Function to get the creation times of every assignment given to a driver
+fn assignment_times(driver_id: u64) -> Vec<u64> {
+    assignments_for_driver(driver_id)
+        .iter()
+        .map(|assignment| assignment.created_at)
+        .collect()
+}
+
+fn count_since(times: &[u64], window_nanos: u64, now: u64) -> u64 {
+    times
+        .iter()
+        .filter(|created_at| **created_at + window_nanos > now)
+        .count() as u64
+}
+
+// Function to sum a driver's assignments, each weighted down by its age so
+// that a busy week long ago does not count against them today
+pub(crate) fn decayed_load(driver_id: u64) -> f64 {
+    let now = time();
+    assignment_times(driver_id)
+        .iter()
+        .map(|created_at| {
+            let age = now.saturating_sub(*created_at) as f64;
+            0.5f64.powf(age / LOAD_HALF_LIFE_NANOS as f64)
+        })
+        .sum()
+}
+
+// Function to get the number of assignments a driver received in the last 7 days
+pub(crate) fn assignments_this_week(driver_id: u64) -> u64 {
+    count_since(&assignment_times(driver_id), WEEK_NANOS, time())
+}
+
+// Function to reject an assignment that would take a driver over their own weekly cap
+pub(crate) fn check_weekly_cap(driver: &DriverProfile) -> Result<(), FoodShareError> {
+    match driver.weekly_assignment_cap {
+        Some(cap) if assignments_this_week(driver.id) >= cap as u64 => {
+            Err(FoodShareError::QuotaExceeded {
+                msg: format!("Driver has reached their weekly cap of {} assignments", cap),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+// Function for a driver to limit how many assignments they take per 7 days
+#[ic_cdk::update]
+async fn set_weekly_assignment_cap(
+    driver_id: u64,
+    cap: Option<u32>,
+) -> Result<DriverProfile, FoodShareError> {
+    start_trace("set_weekly_assignment_cap");
+    authorize(EndpointClass::Registration).await?;
+
+    let mut driver = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&driver_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Driver ID does not exist".to_string(),
+        })?;
+
+    let caller = ic_cdk::caller();
+    if driver.owner != Some(caller) && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the driver or governance can change the weekly cap".to_string(),
+        });
+    }
+    if cap == Some(0) {
+        return Err(FoodShareError::Validation {
+            field: "weekly_assignment_cap".to_string(),
+            reason: "Weekly cap must be at least 1; deactivate the profile to stop".to_string(),
+        });
+    }
+
+    let before = driver.clone();
+    driver.weekly_assignment_cap = cap;
+    DRIVERS_STORAGE.with(|storage| storage.borrow_mut().insert(driver_id, driver.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::Driver,
+        driver_id,
+        Some(&before),
+        Some(&driver),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Driver {} set weekly cap to {:?}", driver_id, cap),
+    );
+
+    Ok(redacted(driver))
+}
+
+// Function to get how evenly assignments are spread across active drivers
+#[ic_cdk::query]
+fn get_driver_fairness_report() -> Result<FairnessReport, FoodShareError> {
+    let now = time();
+    let mut drivers: Vec<DriverLoad> = DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, driver)| driver.active)
+            .map(|(_, driver)| {
+                let times = assignment_times(driver.id);
+                DriverLoad {
+                    driver_id: driver.id,
+                    driver_name: driver.name,
+                    assignments_last_7_days: count_since(&times, WEEK_NANOS, now),
+                    assignments_last_30_days: count_since(&times, MONTH_NANOS, now),
+                    decayed_load: decayed_load(driver.id),
+                    weekly_assignment_cap: driver.weekly_assignment_cap,
+                    share_percent: 0.0,
+                }
+            })
+            .collect()
+    });
+
+    if drivers.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: "No drivers found.".to_string(),
+        });
+    }
+
+    let total: u64 = drivers.iter().map(|d| d.assignments_last_30_days).sum();
+    if total > 0 {
+        for driver in drivers.iter_mut() {
+            driver.share_percent = driver.assignments_last_30_days as f64 * 100.0 / total as f64;
+        }
+    }
+
+    // Gini coefficient over the 30-day counts, computed from the sorted values
+    let mut counts: Vec<u64> = drivers.iter().map(|d| d.assignments_last_30_days).collect();
+    counts.sort_unstable();
+    let n = counts.len() as f64;
+    let gini_coefficient = if total == 0 {
+        0.0
+    } else {
+        let weighted: f64 = counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| (i as f64 + 1.0) * *count as f64)
+            .sum();
+        (2.0 * weighted) / (n * total as f64) - (n + 1.0) / n
+    };
+
+    drivers.sort_by(|a, b| b.decayed_load.total_cmp(&a.decayed_load));
+
+    Ok(FairnessReport {
+        drivers,
+        total_assignments_last_30_days: total,
+        gini_coefficient,
+    })
+}
@@ -0,0 +1,237 @@
+use crate::cancellation_policy::check_not_suspended;
+use crate::driver_availability::{availability_of, is_available_now};
+use crate::driver_load::{assignments_this_week, decayed_load};
+use crate::geo::haversine_km;
+use crate::reputation::driver_reputation;
+use crate::vehicles::{check_refrigeration, load_limit_kg};
+use crate::{
+    DriverProfile, FoodShareError, SurplusPost, DONORS_STORAGE, DRIVERS_STORAGE,
+    SURPLUS_POSTS_STORAGE,
+};
+
+// Drivers within NEAR_KM of the pickup get full distance points, dropping to none at FAR_KM
+const NEAR_KM: f64 = 2.0;
+const FAR_KM: f64 = 50.0;
+
+// A driver whose decayed assignment load reaches this gets no recent-load points
+const RECENT_LOAD_CEILING: f64 = 5.0;
+
+// Weights of each factor in the total score; they add up to 100
+const DISTANCE_WEIGHT: u32 = 30;
+const AVAILABILITY_WEIGHT: u32 = 25;
+const CAPACITY_WEIGHT: u32 = 20;
+const REPUTATION_WEIGHT: u32 = 15;
+const RECENT_LOAD_WEIGHT: u32 = 10;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ScoreFactor {
+    name: String,
+    // 0-100 before weighting
+    score: u32,
+    weight: u32,
+    explanation: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DriverSuggestion {
+    driver_id: u64,
+    driver_name: String,
+    // Weighted sum of the factor scores, 0-100
+    total_score: u32,
+    factors: Vec<ScoreFactor>,
+}
+
+fn factor(name: &str, score: u32, weight: u32, explanation: String) -> ScoreFactor {
+    ScoreFactor {
+        name: name.to_string(),
+        score,
+        weight,
+        explanation,
+    }
+}
+
+// Function to score one driver for a post; every factor carries its own explanation
+fn score_driver(
+    driver: &DriverProfile,
+    post: &SurplusPost,
+    donor_region: &str,
+) -> DriverSuggestion {
+    let mut factors = Vec::with_capacity(5);
+    let quantity_kg = post.quantity_kg;
+
+    // Distance is approximated by region unless both the pickup and the driver have coordinates
+    let coordinates = post
+        .latitude
+        .zip(post.longitude)
+        .zip(driver.latitude.zip(driver.longitude));
+    factors.push(
+        if let Some(((post_lat, post_lng), (driver_lat, driver_lng))) = coordinates {
+            let distance_km = haversine_km(post_lat, post_lng, driver_lat, driver_lng);
+            let score = ((FAR_KM - distance_km) / (FAR_KM - NEAR_KM) * 100.0).clamp(0.0, 100.0);
+            factor(
+                "distance",
+                score as u32,
+                DISTANCE_WEIGHT,
+                format!("Driver is {:.1} km from the pickup", distance_km),
+            )
+        } else if donor_region.is_empty() || driver.region.is_empty() {
+            factor(
+                "distance",
+                50,
+                DISTANCE_WEIGHT,
+                "Region unknown for donor or driver".to_string(),
+            )
+        } else if driver.region.eq_ignore_ascii_case(donor_region) {
+            factor(
+                "distance",
+                100,
+                DISTANCE_WEIGHT,
+                format!("Driver is in the donor's region ({})", donor_region),
+            )
+        } else {
+            factor(
+                "distance",
+                0,
+                DISTANCE_WEIGHT,
+                format!(
+                    "Driver is in {} while the donor is in {}",
+                    driver.region, donor_region
+                ),
+            )
+        },
+    );
+
+    let available = is_available_now(driver);
+    let week_count = assignments_this_week(driver.id);
+    let at_cap = driver
+        .weekly_assignment_cap
+        .is_some_and(|cap| week_count >= cap as u64);
+    factors.push(if !available {
+        factor(
+            "availability",
+            0,
+            AVAILABILITY_WEIGHT,
+            "Driver is offline, off shift or at their concurrent assignment limit".to_string(),
+        )
+    } else if at_cap {
+        factor(
+            "availability",
+            0,
+            AVAILABILITY_WEIGHT,
+            format!(
+                "Driver reached their weekly cap ({} assignments this week)",
+                week_count
+            ),
+        )
+    } else {
+        factor(
+            "availability",
+            100,
+            AVAILABILITY_WEIGHT,
+            "Driver is online and can take another assignment".to_string(),
+        )
+    });
+
+    let capacity_kg = load_limit_kg(driver, availability_of(driver).capacity_kg());
+    let capacity_score = if capacity_kg >= quantity_kg {
+        100
+    } else {
+        (capacity_kg as u64 * 100 / quantity_kg.max(1) as u64) as u32
+    };
+    factors.push(factor(
+        "capacity",
+        capacity_score,
+        CAPACITY_WEIGHT,
+        format!(
+            "{:?} carries {} kg for a {} kg post",
+            driver.vehicle_type, capacity_kg, quantity_kg
+        ),
+    ));
+
+    let reputation = driver_reputation(driver.id);
+    // Ratings are on a 1-5 scale
+    factors.push(match reputation.average_rating {
+        None => factor(
+            "reputation",
+            50,
+            REPUTATION_WEIGHT,
+            "No ratings yet, scored as neutral".to_string(),
+        ),
+        Some(average_rating) => factor(
+            "reputation",
+            (average_rating * 20.0).round() as u32,
+            REPUTATION_WEIGHT,
+            format!(
+                "Average rating {:.2} from {} deliveries",
+                average_rating, reputation.rating_count
+            ),
+        ),
+    });
+
+    // Recent assignments decay over time, so drivers who have been left out
+    // recently rank above those who keep getting work
+    let load = decayed_load(driver.id);
+    factors.push(factor(
+        "recent_load",
+        ((1.0 - (load / RECENT_LOAD_CEILING).min(1.0)) * 100.0).round() as u32,
+        RECENT_LOAD_WEIGHT,
+        format!(
+            "Decayed load {:.2} from recent assignments ({} this week)",
+            load, week_count
+        ),
+    ));
+
+    let total_score = factors.iter().map(|f| f.score * f.weight).sum::<u32>() / 100;
+
+    DriverSuggestion {
+        driver_id: driver.id,
+        driver_name: driver.name.clone(),
+        total_score,
+        factors,
+    }
+}
+
+// Function to get active drivers ranked for a surplus post, with the score breakdown behind each rank
+#[ic_cdk::query]
+fn suggest_drivers(post_id: u64) -> Result<Vec<DriverSuggestion>, FoodShareError> {
+    let post = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&post_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Surplus post with id={} not found", post_id),
+        })?;
+    let donor_region = post
+        .donor_id
+        .parse::<u64>()
+        .ok()
+        .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)))
+        .map(|donor| donor.region)
+        .unwrap_or_default();
+
+    let mut suggestions: Vec<DriverSuggestion> = DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            // Drivers whose vehicle cannot keep the food cold could not be assigned it
+            .filter(|(_, driver)| {
+                driver.active
+                    && check_refrigeration(driver, post.food_type).is_ok()
+                    && check_not_suspended(driver).is_ok()
+            })
+            .map(|(_, driver)| score_driver(&driver, &post, &donor_region))
+            .collect()
+    });
+
+    if suggestions.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: "No drivers found.".to_string(),
+        });
+    }
+
+    suggestions.sort_by(|a, b| {
+        b.total_score
+            .cmp(&a.total_score)
+            .then(a.driver_id.cmp(&b.driver_id))
+    });
+
+    Ok(suggestions)
+}
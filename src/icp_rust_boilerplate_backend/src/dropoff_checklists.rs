@@ -0,0 +1,354 @@
+use crate::assignment_lifecycle::{is_driver_owner, is_receiver_owner};
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::{authorize, is_governance_or_admin, EndpointClass};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    is_active_receiver, Assignment, FoodShareError, Memory, ASSIGNMENTS_STORAGE, MEMORY_MANAGER,
+    RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use chrono::{DateTime, NaiveDate};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_CHECKS: usize = 10;
+const MAX_LABEL_LEN: usize = 80;
+// Readings outside this range are treated as typos rather than food temperatures
+const MIN_READING_CELSIUS: f64 = -60.0;
+const MAX_READING_CELSIUS: f64 = 100.0;
+
+// DropoffCheck is a custom enum type that is used to represent one item a receiver requires at drop-off
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) enum DropoffCheck {
+    // The driver reads the food's temperature; it passes at or below the limit
+    MaxTemperature { celsius: f64 },
+    // The driver confirms the packaging is intact
+    PackagingIntact,
+    // Checked against the post: its best before date must be at least this many days away
+    MinDaysToBestBefore { days: u32 },
+    // Anything else the driver confirms, e.g. "Allergens labelled"
+    Confirm { label: String },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DropoffChecklist {
+    receiver_id: u64,
+    checks: Vec<DropoffCheck>,
+    updated_at: u64,
+}
+
+impl Storable for DropoffChecklist {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DropoffChecklist {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Driver's answer to one check, in the order of the receiver's checklist
+#[derive(candid::CandidType, Deserialize, Serialize, Clone)]
+pub(crate) struct DropoffAnswer {
+    // Required for MaxTemperature checks
+    temperature_celsius: Option<f64>,
+    // Whether the driver confirms the check; unused by MinDaysToBestBefore
+    confirmed: bool,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CheckedItem {
+    check: DropoffCheck,
+    temperature_celsius: Option<f64>,
+    confirmed: bool,
+    passed: bool,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CompletedChecklist {
+    assignment_id: u64,
+    receiver_id: u64,
+    items: Vec<CheckedItem>,
+    passed: bool,
+    completed_at: u64,
+}
+
+impl Storable for CompletedChecklist {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for CompletedChecklist {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Keyed by receiver
+    static DROPOFF_CHECKLISTS: RefCell<StableBTreeMap<u64, DropoffChecklist, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(78)))
+    ));
+
+    // Keyed by assignment
+    static COMPLETED_CHECKLISTS: RefCell<StableBTreeMap<u64, CompletedChecklist, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(79)))
+    ));
+}
+
+fn validate_checks(checks: &[DropoffCheck]) -> Result<(), FoodShareError> {
+    if checks.len() > MAX_CHECKS {
+        return Err(FoodShareError::Validation {
+            field: "checks".to_string(),
+            reason: format!("A checklist can have at most {} checks", MAX_CHECKS),
+        });
+    }
+    for check in checks {
+        match check {
+            DropoffCheck::MaxTemperature { celsius }
+                if !(MIN_READING_CELSIUS..=MAX_READING_CELSIUS).contains(celsius) =>
+            {
+                return Err(FoodShareError::Validation {
+                    field: "checks".to_string(),
+                    reason: format!(
+                        "Temperature limit must be between {} and {} °C",
+                        MIN_READING_CELSIUS, MAX_READING_CELSIUS
+                    ),
+                });
+            }
+            DropoffCheck::Confirm { label }
+                if label.trim().is_empty() || label.chars().count() > MAX_LABEL_LEN =>
+            {
+                return Err(FoodShareError::Validation {
+                    field: "checks".to_string(),
+                    reason: format!("Labels must be between 1 and {} characters", MAX_LABEL_LEN),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Function to get the whole days left until a post's best before date; None
+// when the post or its date cannot be read
+fn days_to_best_before(surplus_post_id: u64) -> Option<i64> {
+    let post = SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&surplus_post_id))?;
+    let best_before = NaiveDate::parse_from_str(&post.best_before_date, "%Y-%m-%d").ok()?;
+    let today = DateTime::from_timestamp_nanos(time() as i64).date_naive();
+    Some(best_before.signed_duration_since(today).num_days())
+}
+
+// Function to check the driver's answers against the receiver's drop-off
+// checklist; None when the receiver has no checklist
+pub(crate) fn check_dropoff(
+    assignment: &Assignment,
+    answers: Option<Vec<DropoffAnswer>>,
+) -> Result<Option<CompletedChecklist>, FoodShareError> {
+    let Some(checklist) =
+        DROPOFF_CHECKLISTS.with(|storage| storage.borrow().get(&assignment.receiver_id))
+    else {
+        return Ok(None);
+    };
+    if checklist.checks.is_empty() {
+        return Ok(None);
+    }
+    let answers = answers.unwrap_or_default();
+    if answers.len() != checklist.checks.len() {
+        return Err(FoodShareError::Validation {
+            field: "checklist".to_string(),
+            reason: format!(
+                "Receiver {} requires answers to {} drop-off checks",
+                assignment.receiver_id,
+                checklist.checks.len()
+            ),
+        });
+    }
+
+    let mut items = Vec::new();
+    for (check, answer) in checklist.checks.into_iter().zip(answers) {
+        let passed = match &check {
+            DropoffCheck::MaxTemperature { celsius } => {
+                let reading = answer
+                    .temperature_celsius
+                    .filter(|reading| (MIN_READING_CELSIUS..=MAX_READING_CELSIUS).contains(reading))
+                    .ok_or(FoodShareError::Validation {
+                        field: "temperature_celsius".to_string(),
+                        reason: "Temperature checks need a reading in °C".to_string(),
+                    })?;
+                reading <= *celsius
+            }
+            DropoffCheck::MinDaysToBestBefore { days } => {
+                days_to_best_before(assignment.surplus_post_id)
+                    .is_some_and(|left| left >= *days as i64)
+            }
+            DropoffCheck::PackagingIntact | DropoffCheck::Confirm { .. } => answer.confirmed,
+        };
+        items.push(CheckedItem {
+            check,
+            temperature_celsius: answer.temperature_celsius,
+            confirmed: answer.confirmed,
+            passed,
+        });
+    }
+
+    Ok(Some(CompletedChecklist {
+        assignment_id: assignment.id,
+        receiver_id: assignment.receiver_id,
+        passed: items.iter().all(|item| item.passed),
+        items,
+        completed_at: time(),
+    }))
+}
+
+// Function to keep a completed checklist with the delivery it was done for
+pub(crate) fn store_completed_checklist(completed: &CompletedChecklist) {
+    COMPLETED_CHECKLISTS.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(completed.assignment_id, completed.clone())
+    });
+    audit(
+        AuditAction::Created,
+        AuditEntity::DropoffChecklist,
+        completed.assignment_id,
+        None,
+        Some(completed),
+    );
+}
+
+impl CompletedChecklist {
+    pub(crate) fn passed(&self) -> bool {
+        self.passed
+    }
+
+    // Function to describe the checks that failed, e.g. for a dispute reason
+    pub(crate) fn failures(&self) -> Vec<String> {
+        self.items
+            .iter()
+            .filter(|item| !item.passed)
+            .map(|item| match &item.check {
+                DropoffCheck::MaxTemperature { celsius } => format!(
+                    "temperature {} °C above {} °C",
+                    item.temperature_celsius.unwrap_or_default(),
+                    celsius
+                ),
+                DropoffCheck::PackagingIntact => "packaging not intact".to_string(),
+                DropoffCheck::MinDaysToBestBefore { days } => {
+                    format!("best before less than {} days away", days)
+                }
+                DropoffCheck::Confirm { label } => format!("not confirmed: {}", label),
+            })
+            .collect()
+    }
+}
+
+// Function for a receiver to set the checks drivers complete when dropping food
+// off with them; an empty list removes the checklist
+#[ic_cdk::update]
+async fn set_dropoff_checklist(
+    receiver_id: u64,
+    checks: Vec<DropoffCheck>,
+) -> Result<DropoffChecklist, FoodShareError> {
+    start_trace("set_dropoff_checklist");
+    authorize(EndpointClass::Logistics).await?;
+
+    if !is_active_receiver(receiver_id) {
+        return Err(FoodShareError::NotFound {
+            msg: "Receiver ID does not exist or is deactivated".to_string(),
+        });
+    }
+    let caller = ic_cdk::caller();
+    let is_receiver = RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&receiver_id))
+        .is_some_and(|receiver| receiver.owner == Some(caller));
+    if !is_receiver && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the receiver or governance can set its drop-off checklist".to_string(),
+        });
+    }
+    validate_checks(&checks)?;
+
+    let before = DROPOFF_CHECKLISTS.with(|storage| storage.borrow().get(&receiver_id));
+    let checklist = DropoffChecklist {
+        receiver_id,
+        checks,
+        updated_at: time(),
+    };
+    DROPOFF_CHECKLISTS.with(|storage| storage.borrow_mut().insert(receiver_id, checklist.clone()));
+    audit(
+        if before.is_some() {
+            AuditAction::Updated
+        } else {
+            AuditAction::Created
+        },
+        AuditEntity::DropoffChecklist,
+        receiver_id,
+        before.as_ref(),
+        Some(&checklist),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Receiver {} now requires {} drop-off checks",
+            receiver_id,
+            checklist.checks.len()
+        ),
+    );
+
+    Ok(checklist)
+}
+
+// Function to get the checks a receiver requires at drop-off, so drivers can
+// prepare before they arrive
+#[ic_cdk::query]
+fn get_dropoff_checklist(receiver_id: u64) -> Result<DropoffChecklist, FoodShareError> {
+    DROPOFF_CHECKLISTS
+        .with(|storage| storage.borrow().get(&receiver_id))
+        .filter(|checklist| !checklist.checks.is_empty())
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Receiver {} has no drop-off checklist", receiver_id),
+        })
+}
+
+// Function for the parties of an assignment, or governance, to get the
+// checklist the driver completed at drop-off
+#[ic_cdk::query]
+fn get_completed_checklist(assignment_id: u64) -> Result<CompletedChecklist, FoodShareError> {
+    let assignment = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })?;
+    if !is_driver_owner(&assignment)
+        && !is_receiver_owner(&assignment)
+        && !is_governance_or_admin(&ic_cdk::caller())
+    {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the driver, the receiver or governance can view this checklist".to_string(),
+        });
+    }
+
+    COMPLETED_CHECKLISTS
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!(
+                "No drop-off checklist was completed for assignment {}",
+                assignment_id
+            ),
+        })
+}
@@ -0,0 +1,275 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::{authorize, require_admin, EndpointClass};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{FoodShareError, Memory, VehicleType, DRIVERS_STORAGE, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_CANDIDATES: usize = 50;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct EmissionsFactor {
+    vehicle_type: VehicleType,
+    grams_co2_per_km: u32,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct EmissionsConfig {
+    factors: Vec<EmissionsFactor>,
+    // When set, a lower-emission driver wins over the fastest one if its ETA
+    // is within `comparable_eta_percent` of the fastest ETA
+    prefer_low_emission: bool,
+    comparable_eta_percent: u32,
+}
+
+impl Default for EmissionsConfig {
+    fn default() -> Self {
+        let factor = |vehicle_type, grams_co2_per_km| EmissionsFactor {
+            vehicle_type,
+            grams_co2_per_km,
+        };
+        Self {
+            factors: vec![
+                factor(VehicleType::Bicycle, 0),
+                factor(VehicleType::Motorbike, 100),
+                factor(VehicleType::Car, 170),
+                factor(VehicleType::ElectricCar, 50),
+                factor(VehicleType::Van, 250),
+                factor(VehicleType::Truck, 800),
+            ],
+            prefer_low_emission: false,
+            comparable_eta_percent: 15,
+        }
+    }
+}
+
+impl EmissionsConfig {
+    fn grams_per_km(&self, vehicle_type: VehicleType) -> u32 {
+        self.factors
+            .iter()
+            .find(|factor| factor.vehicle_type == vehicle_type)
+            .map(|factor| factor.grams_co2_per_km)
+            .unwrap_or_default()
+    }
+}
+
+impl Storable for EmissionsConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct EmissionsStats {
+    selections: u64,
+    chosen_grams_co2: u64,
+    lowest_possible_grams_co2: u64,
+    low_emission_overrides: u64,
+}
+
+impl Storable for EmissionsStats {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Driver Candidate Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct DriverCandidate {
+    driver_id: u64,
+    eta_minutes: u32,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DriverSelection {
+    chosen_driver_id: u64,
+    chosen_eta_minutes: u32,
+    chosen_grams_co2: u64,
+    fastest_driver_id: u64,
+    lowest_emission_driver_id: u64,
+    lowest_emission_grams_co2: u64,
+    // Extra emissions of the chosen driver over the cheapest-emission alternative
+    emissions_delta_grams: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct EmissionsReport {
+    selections: u64,
+    chosen_grams_co2: u64,
+    lowest_possible_grams_co2: u64,
+    emissions_delta_grams: u64,
+    low_emission_overrides: u64,
+}
+
+thread_local! {
+    static EMISSIONS_CONFIG: RefCell<Cell<EmissionsConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21))),
+            EmissionsConfig::default(),
+        )
+        .expect("Cannot create the emissions config")
+    );
+
+    static EMISSIONS_STATS: RefCell<Cell<EmissionsStats, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22))),
+            EmissionsStats::default(),
+        )
+        .expect("Cannot create the emissions stats")
+    );
+}
+
+fn emissions_config() -> EmissionsConfig {
+    EMISSIONS_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Function to choose a driver for a trip of `distance_km` among candidates
+// with known ETAs, recording the emissions outcome for analytics
+#[ic_cdk::update]
+async fn select_driver(
+    distance_km: u32,
+    candidates: Vec<DriverCandidate>,
+) -> Result<DriverSelection, FoodShareError> {
+    start_trace("select_driver");
+    authorize(EndpointClass::Logistics).await?;
+
+    if candidates.is_empty() || candidates.len() > MAX_CANDIDATES {
+        return Err(FoodShareError::Validation {
+            field: "candidates".to_string(),
+            reason: format!("Provide between 1 and {} driver candidates", MAX_CANDIDATES),
+        });
+    }
+
+    let config = emissions_config();
+
+    // (driver_id, eta_minutes, grams_co2) for every candidate
+    let mut options: Vec<(u64, u32, u64)> = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        let driver = DRIVERS_STORAGE
+            .with(|storage| storage.borrow().get(&candidate.driver_id))
+            .ok_or(FoodShareError::NotFound {
+                msg: format!("Driver ID {} does not exist", candidate.driver_id),
+            })?;
+        let grams_co2 = distance_km as u64 * config.grams_per_km(driver.vehicle_type) as u64;
+        options.push((driver.id, candidate.eta_minutes, grams_co2));
+    }
+
+    let fastest = *options
+        .iter()
+        .min_by_key(|(id, eta, grams)| (*eta, *grams, *id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "No driver candidates".to_string(),
+        })?;
+    let lowest_emission = *options
+        .iter()
+        .min_by_key(|(id, eta, grams)| (*grams, *eta, *id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "No driver candidates".to_string(),
+        })?;
+
+    let chosen = if config.prefer_low_emission {
+        let eta_limit =
+            fastest.1 as u64 + fastest.1 as u64 * config.comparable_eta_percent as u64 / 100;
+        *options
+            .iter()
+            .filter(|(_, eta, _)| *eta as u64 <= eta_limit)
+            .min_by_key(|(id, eta, grams)| (*grams, *eta, *id))
+            .unwrap_or(&fastest)
+    } else {
+        fastest
+    };
+
+    let selection = DriverSelection {
+        chosen_driver_id: chosen.0,
+        chosen_eta_minutes: chosen.1,
+        chosen_grams_co2: chosen.2,
+        fastest_driver_id: fastest.0,
+        lowest_emission_driver_id: lowest_emission.0,
+        lowest_emission_grams_co2: lowest_emission.2,
+        emissions_delta_grams: chosen.2 - lowest_emission.2,
+    };
+
+    EMISSIONS_STATS.with(|cell| {
+        let mut stats = cell.borrow().get().clone();
+        stats.selections += 1;
+        stats.chosen_grams_co2 += selection.chosen_grams_co2;
+        stats.lowest_possible_grams_co2 += selection.lowest_emission_grams_co2;
+        if chosen.0 != fastest.0 {
+            stats.low_emission_overrides += 1;
+        }
+        cell.borrow_mut()
+            .set(stats)
+            .expect("Cannot update the emissions stats");
+    });
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Selected driver {} ({} g CO2, {} g above lowest)",
+            selection.chosen_driver_id, selection.chosen_grams_co2, selection.emissions_delta_grams
+        ),
+    );
+
+    Ok(selection)
+}
+
+// Function to get the estimated emissions of chosen drivers against the cheapest-emission alternatives
+#[ic_cdk::query]
+fn get_emissions_report() -> EmissionsReport {
+    EMISSIONS_STATS.with(|cell| {
+        let stats = cell.borrow();
+        let stats = stats.get();
+        EmissionsReport {
+            selections: stats.selections,
+            chosen_grams_co2: stats.chosen_grams_co2,
+            lowest_possible_grams_co2: stats.lowest_possible_grams_co2,
+            emissions_delta_grams: stats.chosen_grams_co2 - stats.lowest_possible_grams_co2,
+            low_emission_overrides: stats.low_emission_overrides,
+        }
+    })
+}
+
+// Function to get the emissions configuration
+#[ic_cdk::query]
+fn get_emissions_config() -> EmissionsConfig {
+    emissions_config()
+}
+
+// Function to update the per-vehicle emissions factors and selection weighting
+#[ic_cdk::update]
+fn set_emissions_config(config: EmissionsConfig) -> Result<EmissionsConfig, FoodShareError> {
+    start_trace("set_emissions_config");
+    require_admin("update the emissions config")?;
+
+    if config.comparable_eta_percent > 100 {
+        return Err(FoodShareError::Validation {
+            field: "comparable_eta_percent".to_string(),
+            reason: "comparable_eta_percent must be between 0 and 100".to_string(),
+        });
+    }
+
+    let before = EMISSIONS_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the emissions config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::EmissionsConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(LogLevel::Info, "Updated emissions config".to_string());
+
+    Ok(config)
+}
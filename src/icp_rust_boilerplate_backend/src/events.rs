@@ -0,0 +1,176 @@
+use crate::assignment_messages::{is_participant, AssignmentMessage};
+use crate::governance::is_governance_or_admin;
+use crate::indexes::{
+    events_for_assignment, events_for_post, messages_for_assignment, reviews_for_assignment,
+    store_event,
+};
+use crate::observability::current_trace_id;
+use crate::reviews::Review;
+use crate::webhooks::queue_event;
+use crate::{FoodShareError, Memory, StatusChange, ASSIGNMENTS_STORAGE, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// EntityType is a custom enum type that is used to represent the kind of entity an event is about
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum EntityType {
+    Donor,
+    Receiver,
+    Driver,
+    SurplusPost,
+    Assignment,
+    SurplusRecord,
+    FoodRequest,
+}
+
+// EventKind is a custom enum type that is used to represent what happened to an entity
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum EventKind {
+    Created,
+    Updated,
+    Assigned,
+    Delivered,
+    Expired,
+    Transferred,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DomainEvent {
+    pub(crate) id: u64,
+    pub(crate) trace_id: String,
+    pub(crate) actor: Principal,
+    pub(crate) entity_type: EntityType,
+    pub(crate) entity_id: u64,
+    pub(crate) assignment_id: Option<u64>,
+    pub(crate) kind: EventKind,
+    pub(crate) detail: String,
+    pub(crate) timestamp: u64,
+}
+
+// TimelineEntry is a custom enum type that is used to represent one item on the timeline of an assignment
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum TimelineEntry {
+    Event(DomainEvent),
+    StatusChange(StatusChange),
+    Message(AssignmentMessage),
+    Review(Review),
+}
+
+impl TimelineEntry {
+    fn timestamp(&self) -> u64 {
+        match self {
+            TimelineEntry::Event(event) => event.timestamp,
+            TimelineEntry::StatusChange(change) => change.changed_at,
+            TimelineEntry::Message(message) => message.created_at,
+            TimelineEntry::Review(review) => review.created_at,
+        }
+    }
+}
+
+impl Storable for DomainEvent {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DomainEvent {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    pub(crate) static EVENTS_STORAGE: RefCell<StableBTreeMap<u64, DomainEvent, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+    ));
+}
+
+// Function to append an event to the event log. `assignment_id` links the
+// event to the assignment whose timeline it belongs to, if any.
+pub(crate) fn record_event(
+    entity_type: EntityType,
+    entity_id: u64,
+    assignment_id: Option<u64>,
+    kind: EventKind,
+    detail: String,
+) {
+    let event = DomainEvent {
+        id: EVENTS_STORAGE.with(|storage| storage.borrow().len()),
+        trace_id: current_trace_id(),
+        actor: ic_cdk::caller(),
+        entity_type,
+        entity_id,
+        assignment_id,
+        kind,
+        detail,
+        timestamp: time(),
+    };
+    store_event(&event);
+    queue_event(&event);
+}
+
+// Function to read up to `limit` events with ids strictly after `after_id`
+pub(crate) fn events_after(after_id: Option<u64>, limit: usize) -> Vec<DomainEvent> {
+    let start = after_id.map_or(0, |id| id + 1);
+    EVENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .range(start..)
+            .take(limit)
+            .map(|(_, event)| event)
+            .collect()
+    })
+}
+
+// Function to get the events recorded against a post itself, oldest first
+pub(crate) fn post_events(post_id: u64) -> Vec<DomainEvent> {
+    events_for_post(post_id)
+}
+
+// Function to get the chronological timeline of an assignment: its events and
+// those of the surplus post it covers, its status changes, its reviews and,
+// for participants and governance, the messages in its thread
+#[ic_cdk::query]
+fn get_assignment_timeline(id: u64) -> Result<Vec<TimelineEntry>, FoodShareError> {
+    let assignment = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Assignment with id={} not found", id),
+        })?;
+
+    let mut events = events_for_assignment(id);
+    events.extend(events_for_post(assignment.surplus_post_id));
+    events.sort_by_key(|event| event.id);
+    let mut timeline: Vec<TimelineEntry> = events.into_iter().map(TimelineEntry::Event).collect();
+    timeline.extend(
+        assignment
+            .status_history
+            .iter()
+            .cloned()
+            .map(TimelineEntry::StatusChange),
+    );
+    if is_participant(&assignment) || is_governance_or_admin(&ic_cdk::caller()) {
+        timeline.extend(
+            messages_for_assignment(id)
+                .into_iter()
+                .map(TimelineEntry::Message),
+        );
+    }
+    timeline.extend(
+        reviews_for_assignment(id)
+            .into_iter()
+            .map(TimelineEntry::Review),
+    );
+
+    // Stable, so entries with the same timestamp keep the order above
+    timeline.sort_by_key(TimelineEntry::timestamp);
+
+    Ok(timeline)
+}
@@ -0,0 +1,417 @@
+use crate::events::{events_after, DomainEvent, EntityType, EventKind};
+use crate::governance::require_admin;
+use crate::{
+    Assignment, AssignmentStatus, FoodShareError, FoodType, SurplusRecord, ASSIGNMENTS_STORAGE,
+    SURPLUS_POSTS_STORAGE, SURPLUS_RECORDS_STORAGE,
+};
+
+// Bump only when a field is removed or changes meaning; adding event types
+// or optional fields keeps the same version
+const EXPORT_SCHEMA_VERSION: u16 = 1;
+const MAX_EXPORT_PAGE_SIZE: u64 = 500;
+// Report chunks stay well below the 2 MB reply limit, leaving room for the Candid envelope
+const MAX_CHUNK_BYTES: usize = 1_500_000;
+
+// ExportEvent is the flattened, stable shape of a domain event for external
+// data warehouses. Enum values are exported as fixed strings so internal
+// renames never reach downstream ETL.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ExportEvent {
+    // Version of this record layout
+    schema_version: u16,
+    // Monotonic position in the feed, usable as the next cursor
+    sequence: u64,
+    // "<entity>.<action>", e.g. "surplus_post.created"
+    event_type: String,
+    // One of donor, receiver, driver, surplus_post, assignment, surplus_record, food_request
+    entity_type: String,
+    entity_id: u64,
+    // Assignment whose timeline the event belongs to, if any
+    assignment_id: Option<u64>,
+    // Textual principal of the caller that caused the event
+    actor: String,
+    // Nanoseconds since the Unix epoch
+    occurred_at: u64,
+    trace_id: String,
+    // Human readable description; not meant for parsing
+    detail: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ExportPage {
+    schema_version: u16,
+    events: Vec<ExportEvent>,
+    // Pass back as `cursor` to continue; unchanged when the feed is drained
+    next_cursor: Option<u64>,
+}
+
+fn entity_type_name(entity_type: EntityType) -> &'static str {
+    match entity_type {
+        EntityType::Donor => "donor",
+        EntityType::Receiver => "receiver",
+        EntityType::Driver => "driver",
+        EntityType::SurplusPost => "surplus_post",
+        EntityType::Assignment => "assignment",
+        EntityType::SurplusRecord => "surplus_record",
+        EntityType::FoodRequest => "food_request",
+    }
+}
+
+fn event_kind_name(kind: EventKind) -> &'static str {
+    match kind {
+        EventKind::Created => "created",
+        EventKind::Updated => "updated",
+        EventKind::Assigned => "assigned",
+        EventKind::Delivered => "delivered",
+        EventKind::Expired => "expired",
+        EventKind::Transferred => "transferred",
+    }
+}
+
+impl From<DomainEvent> for ExportEvent {
+    fn from(event: DomainEvent) -> Self {
+        let entity_type = entity_type_name(event.entity_type);
+        Self {
+            schema_version: EXPORT_SCHEMA_VERSION,
+            sequence: event.id,
+            event_type: format!("{}.{}", entity_type, event_kind_name(event.kind)),
+            entity_type: entity_type.to_string(),
+            entity_id: event.entity_id,
+            assignment_id: event.assignment_id,
+            actor: event.actor.to_text(),
+            occurred_at: event.timestamp,
+            trace_id: event.trace_id,
+            detail: event.detail,
+        }
+    }
+}
+
+// Function to read the export feed after a cursor
+#[ic_cdk::query]
+fn get_export_events(cursor: Option<u64>, limit: u64) -> Result<ExportPage, FoodShareError> {
+    require_admin("read the export feed")?;
+
+    let limit = limit.clamp(1, MAX_EXPORT_PAGE_SIZE) as usize;
+    let events: Vec<ExportEvent> = events_after(cursor, limit)
+        .into_iter()
+        .map(ExportEvent::from)
+        .collect();
+    let next_cursor = events.last().map(|event| event.sequence).or(cursor);
+
+    Ok(ExportPage {
+        schema_version: EXPORT_SCHEMA_VERSION,
+        events,
+        next_cursor,
+    })
+}
+
+// ExportFormat is a custom enum type that is used to represent the text format of a report export
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ExportFormat {
+    // With a header row in the first chunk only
+    Csv,
+    // Each chunk is a JSON array of row objects
+    Json,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ExportChunk {
+    format: ExportFormat,
+    data: String,
+    rows: u64,
+    // Pass back as `cursor` for the next chunk; None once the export is complete
+    next_cursor: Option<u64>,
+}
+
+// One flattened row of a report export
+trait ExportRow: serde::Serialize {
+    const HEADER: &'static [&'static str];
+
+    fn csv_fields(&self) -> Vec<String>;
+}
+
+#[derive(Serialize)]
+struct SurplusRecordRow {
+    id: u64,
+    surplus_post_id: u64,
+    donor_id: String,
+    driver_id: u64,
+    food_type: String,
+    quantity_kg: Option<u32>,
+    delivered_at: u64,
+    confirmed_at: Option<u64>,
+    beneficiaries: Option<u32>,
+    meal_program: Option<String>,
+    rating: Option<u8>,
+}
+
+impl ExportRow for SurplusRecordRow {
+    const HEADER: &'static [&'static str] = &[
+        "id",
+        "surplus_post_id",
+        "donor_id",
+        "driver_id",
+        "food_type",
+        "quantity_kg",
+        "delivered_at",
+        "confirmed_at",
+        "beneficiaries",
+        "meal_program",
+        "rating",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.surplus_post_id.to_string(),
+            self.donor_id.clone(),
+            self.driver_id.to_string(),
+            self.food_type.clone(),
+            optional(self.quantity_kg),
+            self.delivered_at.to_string(),
+            optional(self.confirmed_at),
+            optional(self.beneficiaries),
+            self.meal_program.clone().unwrap_or_default(),
+            optional(self.rating),
+        ]
+    }
+}
+
+impl From<SurplusRecord> for SurplusRecordRow {
+    fn from(record: SurplusRecord) -> Self {
+        let post =
+            SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&record.surplus_post_id));
+        Self {
+            id: record.id,
+            surplus_post_id: record.surplus_post_id,
+            donor_id: post
+                .as_ref()
+                .map(|post| post.donor_id.clone())
+                .unwrap_or_default(),
+            driver_id: record.driver_id,
+            food_type: post
+                .as_ref()
+                .map(|post| food_type_name(post.food_type).to_string())
+                .unwrap_or_default(),
+            quantity_kg: post.map(|post| post.quantity_kg),
+            delivered_at: record.delivered_at,
+            confirmed_at: record.confirmed_at,
+            beneficiaries: record.beneficiaries,
+            meal_program: record.meal_program,
+            rating: record.rating,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AssignmentRow {
+    id: u64,
+    surplus_post_id: u64,
+    receiver_id: u64,
+    driver_id: u64,
+    status: String,
+    previous_leg_id: Option<u64>,
+    created_at: u64,
+    // Time of the latest status change
+    updated_at: u64,
+}
+
+impl ExportRow for AssignmentRow {
+    const HEADER: &'static [&'static str] = &[
+        "id",
+        "surplus_post_id",
+        "receiver_id",
+        "driver_id",
+        "status",
+        "previous_leg_id",
+        "created_at",
+        "updated_at",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.surplus_post_id.to_string(),
+            self.receiver_id.to_string(),
+            self.driver_id.to_string(),
+            self.status.clone(),
+            optional(self.previous_leg_id),
+            self.created_at.to_string(),
+            self.updated_at.to_string(),
+        ]
+    }
+}
+
+impl From<Assignment> for AssignmentRow {
+    fn from(assignment: Assignment) -> Self {
+        Self {
+            id: assignment.id,
+            surplus_post_id: assignment.surplus_post_id,
+            receiver_id: assignment.receiver_id,
+            driver_id: assignment.driver_id,
+            status: assignment_status_name(assignment.status).to_string(),
+            previous_leg_id: assignment.previous_leg_id,
+            created_at: assignment.created_at,
+            updated_at: assignment
+                .status_history
+                .last()
+                .map_or(assignment.created_at, |change| change.changed_at),
+        }
+    }
+}
+
+fn food_type_name(food_type: FoodType) -> &'static str {
+    match food_type {
+        FoodType::Vegetables => "vegetables",
+        FoodType::Fruits => "fruits",
+        FoodType::Dairy => "dairy",
+        FoodType::Meat => "meat",
+        FoodType::Grains => "grains",
+        FoodType::Bakery => "bakery",
+        FoodType::Beverages => "beverages",
+        FoodType::Other => "other",
+    }
+}
+
+fn assignment_status_name(status: AssignmentStatus) -> &'static str {
+    match status {
+        AssignmentStatus::Pending => "pending",
+        AssignmentStatus::Accepted => "accepted",
+        AssignmentStatus::PickedUp => "picked_up",
+        AssignmentStatus::InTransit => "in_transit",
+        AssignmentStatus::AwaitingConfirmation => "awaiting_confirmation",
+        AssignmentStatus::Disputed => "disputed",
+        AssignmentStatus::Delivered => "delivered",
+        AssignmentStatus::Cancelled => "cancelled",
+        AssignmentStatus::Failed => "failed",
+        AssignmentStatus::Expired => "expired",
+        AssignmentStatus::TimedOut => "timed_out",
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+// Function to quote a CSV field when it holds a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_line(fields: &[String]) -> String {
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+fn validate_range(start_ts: u64, end_ts: u64) -> Result<(), FoodShareError> {
+    if start_ts >= end_ts {
+        return Err(FoodShareError::Validation {
+            field: "end_ts".to_string(),
+            reason: "End must be after start".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Function to write rows, in id order, into a chunk that fits the reply limit.
+// The chunk ends before the first row that would not fit and points past the
+// last row it holds.
+fn export_chunk<R: ExportRow>(
+    format: ExportFormat,
+    cursor: Option<u64>,
+    rows: impl Iterator<Item = (u64, R)>,
+) -> ExportChunk {
+    let mut data = match (format, cursor) {
+        (ExportFormat::Csv, None) => csv_line(
+            &R::HEADER
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>(),
+        ),
+        (ExportFormat::Csv, Some(_)) => String::new(),
+        (ExportFormat::Json, _) => "[".to_string(),
+    };
+    let mut count = 0;
+    let mut last_id = None;
+    let mut truncated = false;
+    for (id, row) in rows {
+        let text = match format {
+            ExportFormat::Csv => csv_line(&row.csv_fields()),
+            ExportFormat::Json => {
+                let object = serde_json::to_string(&row).expect("Cannot encode export row");
+                if count == 0 {
+                    object
+                } else {
+                    format!(",{}", object)
+                }
+            }
+        };
+        if data.len() + text.len() + 1 > MAX_CHUNK_BYTES {
+            truncated = true;
+            break;
+        }
+        data.push_str(&text);
+        count += 1;
+        last_id = Some(id);
+    }
+    if format == ExportFormat::Json {
+        data.push(']');
+    }
+
+    ExportChunk {
+        format,
+        data,
+        rows: count,
+        next_cursor: if truncated { last_id.or(cursor) } else { None },
+    }
+}
+
+// Function for admins to export the deliveries made between two timestamps as
+// CSV or JSON text, one chunk at a time
+#[ic_cdk::query]
+fn export_surplus_records(
+    format: ExportFormat,
+    start_ts: u64,
+    end_ts: u64,
+    cursor: Option<u64>,
+) -> Result<ExportChunk, FoodShareError> {
+    require_admin("export surplus records")?;
+    validate_range(start_ts, end_ts)?;
+
+    let from = cursor.map_or(0, |cursor| cursor.saturating_add(1));
+    Ok(SURPLUS_RECORDS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        let rows = storage
+            .range(from..)
+            .filter(|(_, record)| (start_ts..end_ts).contains(&record.delivered_at))
+            .map(|(id, record)| (id, SurplusRecordRow::from(record)));
+        export_chunk(format, cursor, rows)
+    }))
+}
+
+// Function for admins to export the assignments created between two timestamps
+// as CSV or JSON text, one chunk at a time
+#[ic_cdk::query]
+fn export_assignments(
+    format: ExportFormat,
+    start_ts: u64,
+    end_ts: u64,
+    cursor: Option<u64>,
+) -> Result<ExportChunk, FoodShareError> {
+    require_admin("export assignments")?;
+    validate_range(start_ts, end_ts)?;
+
+    let from = cursor.map_or(0, |cursor| cursor.saturating_add(1));
+    Ok(ASSIGNMENTS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        let rows = storage
+            .range(from..)
+            .filter(|(_, assignment)| (start_ts..end_ts).contains(&assignment.created_at))
+            .map(|(id, assignment)| (id, AssignmentRow::from(assignment)));
+        export_chunk(format, cursor, rows)
+    }))
+}
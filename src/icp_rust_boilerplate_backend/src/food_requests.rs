@@ -0,0 +1,291 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, is_governance_or_admin, EndpointClass};
+use crate::matching::release_matches;
+use crate::observability::{log, start_timer_trace, start_trace, LogLevel};
+use crate::{
+    FoodRequest, FoodRequestStatus, FoodShareError, FoodType, Memory, FOOD_REQUESTS_STORAGE,
+    MEMORY_MANAGER, RECEIVERS_STORAGE,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::time::Duration;
+
+const REQUEST_EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+thread_local! {
+    // (food_request_id, post_id) for every surplus post counted towards a
+    // request. Kept apart from the request so it never outgrows its stored size.
+    static FULFILLING_POSTS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(130)))
+    ));
+}
+
+// Function to record that a post counts towards a request. Returns false when
+// it was already counted, including by requests from before the index.
+fn mark_counted(food_request: &FoodRequest, post_id: u64) -> bool {
+    if food_request
+        .fulfilled_post_ids
+        .as_ref()
+        .is_some_and(|post_ids| post_ids.contains(&post_id))
+    {
+        return false;
+    }
+    FULFILLING_POSTS.with(|index| {
+        index
+            .borrow_mut()
+            .insert((food_request.id, post_id), ())
+            .is_none()
+    })
+}
+
+// Function to get every surplus post counted towards a request, in id order
+pub(crate) fn fulfilling_post_ids(food_request: &FoodRequest) -> Vec<u64> {
+    let mut post_ids = food_request.fulfilled_post_ids.clone().unwrap_or_default();
+    FULFILLING_POSTS.with(|index| {
+        post_ids.extend(
+            index
+                .borrow()
+                .range((food_request.id, 0)..=(food_request.id, u64::MAX))
+                .map(|((_, post_id), _)| post_id),
+        )
+    });
+    post_ids.sort_unstable();
+    post_ids
+}
+
+// Function to count a surplus post towards a food request. Each post counts
+// once; closed requests and posts already counted are ignored.
+pub(crate) fn record_fulfillment(food_request_id: u64, post_id: u64, quantity_kg: u32) {
+    let Some(before) = FOOD_REQUESTS_STORAGE.with(|storage| storage.borrow().get(&food_request_id))
+    else {
+        return;
+    };
+    if !before.is_open() || !mark_counted(&before, post_id) {
+        return;
+    }
+
+    let mut food_request = before.clone();
+    let fulfilled_kg = before
+        .quantity_fulfilled_kg
+        .unwrap_or(0)
+        .saturating_add(quantity_kg);
+    let status = if fulfilled_kg >= before.quantity_kg {
+        FoodRequestStatus::Fulfilled
+    } else {
+        FoodRequestStatus::PartiallyFulfilled
+    };
+    food_request.quantity_fulfilled_kg = Some(fulfilled_kg);
+    food_request.status = Some(status);
+    food_request.assigned = status == FoodRequestStatus::Fulfilled;
+
+    FOOD_REQUESTS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(food_request_id, food_request.clone())
+    });
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::FoodRequest,
+        food_request_id,
+        Some(&before),
+        Some(&food_request),
+    );
+    record_event(
+        EntityType::FoodRequest,
+        food_request_id,
+        None,
+        EventKind::Updated,
+        format!(
+            "Surplus post {} brought the request to {} of {} kg",
+            post_id, fulfilled_kg, food_request.quantity_kg
+        ),
+    );
+}
+
+// Function to move an open food request to Cancelled or Expired and release
+// the posts still held for it by matches
+fn close_request(before: &FoodRequest, status: FoodRequestStatus) -> FoodRequest {
+    let mut food_request = before.clone();
+    food_request.status = Some(status);
+    FOOD_REQUESTS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(food_request.id, food_request.clone())
+    });
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::FoodRequest,
+        food_request.id,
+        Some(before),
+        Some(&food_request),
+    );
+    release_matches(food_request.id);
+    food_request
+}
+
+// Function for the receiver who made a food request, or governance, to cancel
+// it while it is still open or partially fulfilled
+#[ic_cdk::update]
+async fn cancel_food_request(food_request_id: u64) -> Result<FoodRequest, FoodShareError> {
+    start_trace("cancel_food_request");
+    authorize(EndpointClass::Posting).await?;
+
+    let before = FOOD_REQUESTS_STORAGE
+        .with(|storage| storage.borrow().get(&food_request_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Food request ID does not exist".to_string(),
+        })?;
+
+    let caller = ic_cdk::caller();
+    let is_receiver = RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&before.receiver_id))
+        .is_some_and(|receiver| receiver.owner == Some(caller));
+    if !is_receiver && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the requesting receiver or governance can cancel this request".to_string(),
+        });
+    }
+    if !before.is_open() {
+        return Err(FoodShareError::Conflict {
+            msg: format!("Food request is already {:?}", before.status()),
+        });
+    }
+
+    let food_request = close_request(&before, FoodRequestStatus::Cancelled);
+    record_event(
+        EntityType::FoodRequest,
+        food_request_id,
+        None,
+        EventKind::Updated,
+        format!(
+            "Request cancelled with {} of {} kg fulfilled",
+            food_request.quantity_fulfilled_kg.unwrap_or(0),
+            food_request.quantity_kg
+        ),
+    );
+    log(
+        LogLevel::Info,
+        format!("Cancelled food request {}", food_request_id),
+    );
+
+    Ok(food_request)
+}
+
+// Function to get the surplus posts counted towards a food request
+#[ic_cdk::query]
+fn get_food_request_posts(food_request_id: u64) -> Result<Vec<u64>, FoodShareError> {
+    let food_request = FOOD_REQUESTS_STORAGE
+        .with(|storage| storage.borrow().get(&food_request_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Food request ID does not exist".to_string(),
+        })?;
+    Ok(fulfilling_post_ids(&food_request))
+}
+
+// Function to get the food requests that still take surplus posts, oldest
+// first, optionally for one food type
+#[ic_cdk::query]
+fn get_open_food_requests(food_type: Option<FoodType>) -> Vec<FoodRequest> {
+    FOOD_REQUESTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, request)| request)
+            .filter(|request| request.is_open())
+            .filter(|request| food_type.is_none_or(|food_type| request.food_type == food_type))
+            .collect()
+    })
+}
+
+// Function to register the periodic sweep that expires food requests past their needed-by time
+pub(crate) fn start_request_expiry() {
+    ic_cdk_timers::set_timer_interval(REQUEST_EXPIRY_INTERVAL, expire_food_requests);
+}
+
+// Function to expire open food requests whose needed-by time has passed;
+// whatever was fulfilled before then stays recorded
+fn expire_food_requests() {
+    start_timer_trace("expire_food_requests");
+
+    let now = time();
+    let expired: Vec<FoodRequest> = FOOD_REQUESTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, request)| request)
+            .filter(|request| request.is_open())
+            .filter(|request| request.needed_by.is_some_and(|needed_by| needed_by <= now))
+            .collect()
+    });
+
+    for before in &expired {
+        close_request(before, FoodRequestStatus::Expired);
+        record_event(
+            EntityType::FoodRequest,
+            before.id,
+            None,
+            EventKind::Expired,
+            format!(
+                "Request expired with {} of {} kg fulfilled",
+                before.quantity_fulfilled_kg.unwrap_or(0),
+                before.quantity_kg
+            ),
+        );
+    }
+
+    if !expired.is_empty() {
+        log(
+            LogLevel::Info,
+            format!("Expired {} food requests", expired.len()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::MAX_REQUEST_NOTES_LEN;
+    use ic_stable_structures::{BoundedStorable, Storable};
+
+    fn largest_request() -> FoodRequest {
+        FoodRequest {
+            id: u64::MAX,
+            receiver_id: u64::MAX,
+            food_type: FoodType::default(),
+            quantity_kg: u32::MAX,
+            notes: "n".repeat(MAX_REQUEST_NOTES_LEN),
+            assigned: false,
+            created_at: u64::MAX,
+            status: Some(FoodRequestStatus::PartiallyFulfilled),
+            quantity_fulfilled_kg: Some(u32::MAX),
+            fulfilled_post_ids: None,
+            needed_by: Some(u64::MAX),
+            organization_id: Some(u64::MAX),
+        }
+    }
+
+    #[test]
+    fn request_filled_with_posts_still_fits() {
+        let food_request = largest_request();
+        for post_id in 0..1_000 {
+            assert!(mark_counted(&food_request, post_id));
+        }
+        assert!(!mark_counted(&food_request, 7));
+        assert_eq!(fulfilling_post_ids(&food_request).len(), 1_000);
+        assert!(food_request.to_bytes().len() <= FoodRequest::MAX_SIZE as usize);
+    }
+
+    #[test]
+    fn posts_counted_before_the_index_are_not_counted_again() {
+        let mut food_request = largest_request();
+        food_request.id = 1;
+        food_request.fulfilled_post_ids = Some(vec![3, 5]);
+        assert!(!mark_counted(&food_request, 5));
+        assert!(mark_counted(&food_request, 4));
+        assert_eq!(fulfilling_post_ids(&food_request), vec![3, 4, 5]);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::require_admin;
+use crate::observability::{
+    current_trace_id, log, resume_trace, start_timer_trace, start_trace, LogLevel,
+};
+use crate::reference_codes::{reference_code, ReferenceKind};
+use crate::{FoodShareError, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_METHOD: &str = "on_post_changes";
+const MAX_METHOD_LEN: usize = 64;
+// Oldest changes are dropped past this so an unreachable frontend cannot grow the queue forever
+const MAX_PENDING_CHANGES: usize = 1_000;
+
+// PostChangeKind is a custom enum type that is used to represent what happened to a post in the open listing
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum PostChangeKind {
+    Published,
+    Closed,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PostChange {
+    post_id: u64,
+    // Code the frontend shows next to the post; None for changes queued before codes existed
+    reference_code: Option<String>,
+    kind: PostChangeKind,
+    changed_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct FrontendTarget {
+    canister: Option<Principal>,
+    method: String,
+}
+
+impl Default for FrontendTarget {
+    fn default() -> Self {
+        Self {
+            canister: None,
+            method: DEFAULT_METHOD.to_string(),
+        }
+    }
+}
+
+impl Storable for FrontendTarget {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static FRONTEND_TARGET: RefCell<Cell<FrontendTarget, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29))),
+            FrontendTarget::default(),
+        )
+        .expect("Cannot create the frontend target")
+    );
+
+    // Pending changes live on the heap and are carried across upgrades by
+    // the state snapshot
+    static PENDING_CHANGES: RefCell<Vec<PostChange>> = const { RefCell::new(Vec::new()) };
+}
+
+fn frontend_target() -> FrontendTarget {
+    FRONTEND_TARGET.with(|cell| cell.borrow().get().clone())
+}
+
+// Function to queue a post change for the next batch sent to the frontend canister
+pub(crate) fn queue_post_change(post_id: u64, kind: PostChangeKind) {
+    if frontend_target().canister.is_none() {
+        return;
+    }
+    PENDING_CHANGES.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        // Only the latest change of a post matters to the frontend
+        pending.retain(|change| change.post_id != post_id);
+        pending.push(PostChange {
+            post_id,
+            reference_code: Some(reference_code(ReferenceKind::Post, post_id)),
+            kind,
+            changed_at: time(),
+        });
+        if pending.len() > MAX_PENDING_CHANGES {
+            let excess = pending.len() - MAX_PENDING_CHANGES;
+            pending.drain(..excess);
+        }
+    });
+}
+
+// Function to get the queued post changes for the upgrade snapshot
+pub(crate) fn pending_post_changes() -> Vec<PostChange> {
+    PENDING_CHANGES.with(|pending| pending.borrow().clone())
+}
+
+// Function to put back the post changes queued before an upgrade
+pub(crate) fn restore_pending_post_changes(changes: Vec<PostChange>) {
+    PENDING_CHANGES.with(|pending| *pending.borrow_mut() = changes);
+}
+
+// Function to register the periodic flush of queued post changes
+pub(crate) fn start_notification_flush() {
+    ic_cdk_timers::set_timer_interval(FLUSH_INTERVAL, || ic_cdk::spawn(flush_post_changes()));
+}
+
+// Function to send the queued post changes to the frontend canister in one call.
+// A failed batch is put back in front of newer changes and retried next minute.
+async fn flush_post_changes() {
+    start_timer_trace("flush_post_changes");
+    let target = frontend_target();
+    let Some(canister) = target.canister else {
+        return;
+    };
+    let batch: Vec<PostChange> = PENDING_CHANGES.with(|pending| pending.take());
+    if batch.is_empty() {
+        return;
+    }
+
+    let trace_id = current_trace_id();
+    let result: Result<(), _> = ic_cdk::call(canister, &target.method, (batch.clone(),)).await;
+    resume_trace(&trace_id);
+
+    match result {
+        Ok(()) => log(
+            LogLevel::Info,
+            format!("Sent {} post changes to {}", batch.len(), canister),
+        ),
+        Err((code, msg)) => {
+            log(
+                LogLevel::Warn,
+                format!(
+                    "Frontend notification failed ({:?}): {}, requeued {} changes",
+                    code,
+                    msg,
+                    batch.len()
+                ),
+            );
+            PENDING_CHANGES.with(|pending| {
+                let mut pending = pending.borrow_mut();
+                let newer = std::mem::replace(&mut *pending, batch);
+                pending.retain(|change| newer.iter().all(|n| n.post_id != change.post_id));
+                pending.extend(newer);
+                if pending.len() > MAX_PENDING_CHANGES {
+                    let excess = pending.len() - MAX_PENDING_CHANGES;
+                    pending.drain(..excess);
+                }
+            });
+        }
+    }
+}
+
+// Function to register the frontend canister notified of post changes
+#[ic_cdk::update]
+fn register_frontend_target(
+    canister: Principal,
+    method: Option<String>,
+) -> Result<FrontendTarget, FoodShareError> {
+    start_trace("register_frontend_target");
+    require_admin("register the frontend target")?;
+
+    let method = method.unwrap_or_else(|| DEFAULT_METHOD.to_string());
+    if method.is_empty() || method.len() > MAX_METHOD_LEN {
+        return Err(FoodShareError::Validation {
+            field: "method".to_string(),
+            reason: format!("Method must be between 1 and {} bytes", MAX_METHOD_LEN),
+        });
+    }
+
+    let target = FrontendTarget {
+        canister: Some(canister),
+        method,
+    };
+    let before = FRONTEND_TARGET
+        .with(|cell| cell.borrow_mut().set(target.clone()))
+        .expect("Cannot update the frontend target");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::FrontendTarget,
+        0,
+        Some(&before),
+        Some(&target),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Registered frontend target {}", canister),
+    );
+
+    Ok(target)
+}
+
+// Function to stop notifying the frontend canister; queued changes are dropped
+#[ic_cdk::update]
+fn unregister_frontend_target() -> Result<(), FoodShareError> {
+    start_trace("unregister_frontend_target");
+    require_admin("unregister the frontend target")?;
+
+    let before = FRONTEND_TARGET
+        .with(|cell| cell.borrow_mut().set(FrontendTarget::default()))
+        .expect("Cannot update the frontend target");
+    audit(
+        AuditAction::Revoked,
+        AuditEntity::FrontendTarget,
+        0,
+        Some(&before),
+        Some(&FrontendTarget::default()),
+    );
+    PENDING_CHANGES.with(|pending| pending.borrow_mut().clear());
+
+    log(LogLevel::Info, "Unregistered frontend target".to_string());
+
+    Ok(())
+}
+
+// Function to get the frontend canister notified of post changes
+#[ic_cdk::query]
+fn get_frontend_target() -> FrontendTarget {
+    frontend_target()
+}
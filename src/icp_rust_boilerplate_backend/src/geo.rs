@@ -0,0 +1,151 @@
+use crate::browse::is_listable;
+use crate::contact_vault::redacted;
+use crate::driver_availability::is_available_now;
+use crate::driver_load::check_weekly_cap;
+use crate::radius_expansion::within_reach;
+use crate::{DriverProfile, FoodShareError, SurplusPost, DRIVERS_STORAGE, SURPLUS_POSTS_STORAGE};
+
+// Mean radius of the Earth used by the haversine formula
+const EARTH_RADIUS_KM: f64 = 6371.0;
+pub(crate) const MAX_RADIUS_KM: f64 = 500.0;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct NearbyPost {
+    post: SurplusPost,
+    distance_km: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct NearbyDriver {
+    driver: DriverProfile,
+    distance_km: f64,
+}
+
+// Function to get the great-circle distance between two points in kilometres
+pub(crate) fn haversine_km(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lng = (lng2 - lng1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lng / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+pub(crate) fn validate_point(latitude: f64, longitude: f64) -> Result<(), FoodShareError> {
+    if !(-90.0..=90.0).contains(&latitude) {
+        return Err(FoodShareError::Validation {
+            field: "latitude".to_string(),
+            reason: "Latitude must be between -90 and 90".to_string(),
+        });
+    }
+    if !(-180.0..=180.0).contains(&longitude) {
+        return Err(FoodShareError::Validation {
+            field: "longitude".to_string(),
+            reason: "Longitude must be between -180 and 180".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Function to validate optional coordinates from a payload; they are given together or not at all
+pub(crate) fn validate_coordinates(
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+) -> Result<(), FoodShareError> {
+    match (latitude, longitude) {
+        (Some(latitude), Some(longitude)) => validate_point(latitude, longitude),
+        (None, None) => Ok(()),
+        _ => Err(FoodShareError::Validation {
+            field: "latitude".to_string(),
+            reason: "Latitude and longitude must be given together".to_string(),
+        }),
+    }
+}
+
+pub(crate) fn validate_search(lat: f64, lng: f64, radius_km: f64) -> Result<(), FoodShareError> {
+    validate_point(lat, lng)?;
+    if !(radius_km > 0.0 && radius_km <= MAX_RADIUS_KM) {
+        return Err(FoodShareError::Validation {
+            field: "radius_km".to_string(),
+            reason: format!(
+                "Radius must be greater than 0 and at most {} km",
+                MAX_RADIUS_KM
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Function to get the distance to a stored location, if it has one
+fn distance_to(lat: f64, lng: f64, latitude: Option<f64>, longitude: Option<f64>) -> Option<f64> {
+    Some(haversine_km(lat, lng, latitude?, longitude?))
+}
+
+// Function to get open surplus posts within a radius, nearest first; posts
+// without coordinates, and posts whose reach does not yet extend to the
+// searcher, are left out
+#[ic_cdk::query]
+fn get_posts_near(lat: f64, lng: f64, radius_km: f64) -> Result<Vec<NearbyPost>, FoodShareError> {
+    validate_search(lat, lng, radius_km)?;
+
+    let mut posts: Vec<NearbyPost> = SURPLUS_POSTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, post)| post)
+            .filter(is_listable)
+            .filter(|post| within_reach(post, Some(lat), Some(lng)))
+            .filter_map(|post| {
+                let distance_km = distance_to(lat, lng, post.latitude, post.longitude)?;
+                (distance_km <= radius_km).then_some(NearbyPost { post, distance_km })
+            })
+            .collect()
+    });
+
+    if posts.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: "No surplus posts found nearby.".to_string(),
+        });
+    }
+
+    posts.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+    Ok(posts)
+}
+
+// Function to get active drivers within a radius who could take an assignment
+// now: online, on shift, below their concurrent limit and weekly cap, nearest first
+#[ic_cdk::query]
+fn get_available_drivers_near(
+    lat: f64,
+    lng: f64,
+    radius_km: f64,
+) -> Result<Vec<NearbyDriver>, FoodShareError> {
+    validate_search(lat, lng, radius_km)?;
+
+    let mut drivers: Vec<NearbyDriver> = DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, driver)| driver)
+            .filter(|driver| driver.active)
+            .filter_map(|driver| {
+                let distance_km = distance_to(lat, lng, driver.latitude, driver.longitude)?;
+                (distance_km <= radius_km).then_some(NearbyDriver {
+                    driver: redacted(driver),
+                    distance_km,
+                })
+            })
+            .collect()
+    });
+    drivers.retain(|nearby| {
+        check_weekly_cap(&nearby.driver).is_ok() && is_available_now(&nearby.driver)
+    });
+
+    if drivers.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: "No available drivers found nearby.".to_string(),
+        });
+    }
+
+    drivers.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+    Ok(drivers)
+}
@@ -0,0 +1,510 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::browse::{index_open_post, is_listable};
+use crate::donor_leads::form_encode;
+use crate::geo::validate_point;
+use crate::governance::require_admin;
+use crate::indexes::store_post;
+use crate::observability::{current_trace_id, log, resume_trace, start_trace, LogLevel};
+use crate::radius_expansion::{initial_reach, track_post};
+use crate::{
+    FoodShareError, Memory, DONORS_STORAGE, DRIVERS_STORAGE, MEMORY_MANAGER, RECEIVERS_STORAGE,
+    SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+    HttpResponse as OutcallResponse, TransformArgs, TransformContext,
+};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_URL_LEN: usize = 256;
+const MAX_API_KEY_LEN: usize = 256;
+const MAX_ADDRESS_LEN: usize = 200;
+// Enough for a small request and response on a 13-node subnet
+const GEOCODE_OUTCALL_CYCLES: u128 = 1_000_000_000;
+const GEOCODE_MAX_RESPONSE_BYTES: u64 = 16 * 1024;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct GeocodingConfig {
+    // Lookup URL with an {address} placeholder and an optional {key} one, e.g.
+    // "https://nominatim.example.org/search?format=json&limit=1&q={address}".
+    // Every replica makes the call, so the service should answer repeated
+    // lookups of the same address alike. None turns geocoding off.
+    api_url: Option<String>,
+    api_key: Option<String>,
+    // Lookups are skipped while the canister holds fewer cycles than this
+    min_cycles_balance: u64,
+}
+
+impl Default for GeocodingConfig {
+    fn default() -> Self {
+        Self {
+            api_url: None,
+            api_key: None,
+            min_cycles_balance: 1_000_000_000_000,
+        }
+    }
+}
+
+impl Storable for GeocodingConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CachedLocation {
+    // Normalized address the coordinates belong to
+    address: String,
+    latitude: f64,
+    longitude: f64,
+    resolved_at: u64,
+}
+
+impl Storable for CachedLocation {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for CachedLocation {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct GeocodingStats {
+    cache_hits: u64,
+    lookups: u64,
+    resolved: u64,
+    // Outcalls that failed or returned no usable coordinates
+    failed: u64,
+    skipped_low_cycles: u64,
+    last_error: Option<String>,
+}
+
+impl Storable for GeocodingStats {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// The record a finished lookup writes its coordinates to
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum GeocodeTarget {
+    Donor(u64),
+    Receiver(u64),
+    Driver(u64),
+    // Posts are picked up at their donor's address
+    SurplusPost(u64),
+}
+
+// Coordinates reduced from a geocoding response by the transform
+#[derive(Serialize, Deserialize)]
+struct Coordinates {
+    latitude: f64,
+    longitude: f64,
+}
+
+thread_local! {
+    static GEOCODING_CONFIG: RefCell<Cell<GeocodingConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(107))),
+            GeocodingConfig::default(),
+        )
+        .expect("Cannot create the geocoding config")
+    );
+
+    // Keyed by the sha256 of the normalized address
+    static GEOCODE_CACHE: RefCell<StableBTreeMap<[u8; 32], CachedLocation, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(108)))
+    ));
+
+    static GEOCODING_STATS: RefCell<Cell<GeocodingStats, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(109))),
+            GeocodingStats::default(),
+        )
+        .expect("Cannot create the geocoding stats")
+    );
+}
+
+fn geocoding_config() -> GeocodingConfig {
+    GEOCODING_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+fn update_stats(change: impl FnOnce(&mut GeocodingStats)) {
+    GEOCODING_STATS
+        .with(|cell| {
+            let mut stats = cell.borrow().get().clone();
+            change(&mut stats);
+            cell.borrow_mut().set(stats)
+        })
+        .expect("Cannot update the geocoding stats");
+}
+
+// Function to reduce an address to the form it is cached under, so spacing,
+// case and stray commas do not cause a second lookup
+fn normalize_address(address: &str) -> String {
+    address
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn cache_key(normalized: &str) -> [u8; 32] {
+    Sha256::digest(normalized.as_bytes()).into()
+}
+
+// Function to get the cached coordinates of an address, if it was looked up before
+pub(crate) fn cached_coordinates(address: &str) -> Option<(f64, f64)> {
+    let normalized = normalize_address(address);
+    if normalized.is_empty() {
+        return None;
+    }
+    let location = GEOCODE_CACHE
+        .with(|cache| cache.borrow().get(&cache_key(&normalized)))
+        .filter(|location| location.address == normalized)?;
+    update_stats(|stats| stats.cache_hits += 1);
+    Some((location.latitude, location.longitude))
+}
+
+// Function to fill in coordinates a payload left out from the cache
+pub(crate) fn known_coordinates(
+    address: &str,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+) -> (Option<f64>, Option<f64>) {
+    match (latitude, longitude) {
+        (None, None) => cached_coordinates(address)
+            .map(|(latitude, longitude)| (Some(latitude), Some(longitude)))
+            .unwrap_or_default(),
+        coordinates => coordinates,
+    }
+}
+
+// Function to look an address up in the background and write its coordinates
+// to the target once they arrive. Does nothing when geocoding is off; the
+// record simply keeps no coordinates, as before.
+pub(crate) fn geocode_later(target: GeocodeTarget, address: &str) {
+    if geocoding_config().api_url.is_none() || normalize_address(address).is_empty() {
+        return;
+    }
+    ic_cdk::spawn(geocode_and_apply(target, address.to_string()));
+}
+
+async fn geocode_and_apply(target: GeocodeTarget, address: String) {
+    match geocode(&address).await {
+        Ok((latitude, longitude)) => apply(target, &address, latitude, longitude),
+        Err(reason) => {
+            update_stats(|stats| stats.last_error = Some(reason.clone()));
+            log(
+                LogLevel::Warn,
+                format!("Cannot geocode the address of {:?}: {}", target, reason),
+            );
+        }
+    }
+}
+
+// Function to resolve an address through the cache or the configured service
+async fn geocode(address: &str) -> Result<(f64, f64), String> {
+    if let Some(coordinates) = cached_coordinates(address) {
+        return Ok(coordinates);
+    }
+    let config = geocoding_config();
+    let Some(api_url) = &config.api_url else {
+        return Err("Geocoding is not configured".to_string());
+    };
+    if ic_cdk::api::canister_balance() < config.min_cycles_balance {
+        update_stats(|stats| stats.skipped_low_cycles += 1);
+        return Err("Cycles balance is below the geocoding minimum".to_string());
+    }
+
+    let normalized = normalize_address(address);
+    let url = api_url
+        .replace("{address}", &form_encode(&normalized))
+        .replace(
+            "{key}",
+            &form_encode(config.api_key.as_deref().unwrap_or_default()),
+        );
+    let request = CanisterHttpRequestArgument {
+        url,
+        max_response_bytes: Some(GEOCODE_MAX_RESPONSE_BYTES),
+        method: HttpMethod::GET,
+        headers: vec![HttpHeader {
+            name: "User-Agent".to_string(),
+            value: "food-share-canister".to_string(),
+        }],
+        body: None,
+        transform: Some(TransformContext::from_name(
+            "transform_geocode_response".to_string(),
+            Vec::new(),
+        )),
+    };
+
+    update_stats(|stats| stats.lookups += 1);
+    let trace_id = current_trace_id();
+    let result = http_request(request, GEOCODE_OUTCALL_CYCLES).await;
+    resume_trace(&trace_id);
+
+    let coordinates = result
+        .map_err(|(code, msg)| format!("Outcall failed ({:?}): {}", code, msg))
+        .and_then(|(response,)| {
+            serde_json::from_slice::<Option<Coordinates>>(&response.body)
+                .ok()
+                .flatten()
+                .filter(|found| validate_point(found.latitude, found.longitude).is_ok())
+                .ok_or_else(|| "The service found no location for the address".to_string())
+        });
+    let coordinates = match coordinates {
+        Ok(coordinates) => coordinates,
+        Err(reason) => {
+            update_stats(|stats| stats.failed += 1);
+            return Err(reason);
+        }
+    };
+
+    GEOCODE_CACHE.with(|cache| {
+        cache.borrow_mut().insert(
+            cache_key(&normalized),
+            CachedLocation {
+                address: normalized,
+                latitude: coordinates.latitude,
+                longitude: coordinates.longitude,
+                resolved_at: time(),
+            },
+        )
+    });
+    update_stats(|stats| stats.resolved += 1);
+    Ok((coordinates.latitude, coordinates.longitude))
+}
+
+// Function to write looked up coordinates to a record, unless its address
+// changed or it was given coordinates while the lookup was in flight
+fn apply(target: GeocodeTarget, address: &str, latitude: f64, longitude: f64) {
+    let normalized = normalize_address(address);
+    let same = |current: &str| normalize_address(current) == normalized;
+    match target {
+        GeocodeTarget::Donor(id) => {
+            let Some(before) = DONORS_STORAGE.with(|storage| storage.borrow().get(&id)) else {
+                return;
+            };
+            // Donors are looked up again when their address changes, so the
+            // result replaces the coordinates of the old address
+            if !same(&before.address) {
+                return;
+            }
+            let mut donor = before.clone();
+            donor.latitude = Some(latitude);
+            donor.longitude = Some(longitude);
+            DONORS_STORAGE.with(|storage| storage.borrow_mut().insert(id, donor.clone()));
+            audit(
+                AuditAction::Updated,
+                AuditEntity::Donor,
+                id,
+                Some(&before),
+                Some(&donor),
+            );
+        }
+        GeocodeTarget::Receiver(id) => {
+            let Some(before) = RECEIVERS_STORAGE.with(|storage| storage.borrow().get(&id)) else {
+                return;
+            };
+            if !same(&before.address) || before.latitude.is_some() {
+                return;
+            }
+            let mut receiver = before.clone();
+            receiver.latitude = Some(latitude);
+            receiver.longitude = Some(longitude);
+            RECEIVERS_STORAGE.with(|storage| storage.borrow_mut().insert(id, receiver.clone()));
+            audit(
+                AuditAction::Updated,
+                AuditEntity::Receiver,
+                id,
+                Some(&before),
+                Some(&receiver),
+            );
+        }
+        GeocodeTarget::Driver(id) => {
+            let Some(before) = DRIVERS_STORAGE.with(|storage| storage.borrow().get(&id)) else {
+                return;
+            };
+            if !same(&before.address) || before.latitude.is_some() {
+                return;
+            }
+            let mut driver = before.clone();
+            driver.latitude = Some(latitude);
+            driver.longitude = Some(longitude);
+            DRIVERS_STORAGE.with(|storage| storage.borrow_mut().insert(id, driver.clone()));
+            audit(
+                AuditAction::Updated,
+                AuditEntity::Driver,
+                id,
+                Some(&before),
+                Some(&driver),
+            );
+        }
+        GeocodeTarget::SurplusPost(id) => {
+            let Some(before) = SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&id))
+            else {
+                return;
+            };
+            if before.latitude.is_some() {
+                return;
+            }
+            let mut post = before.clone();
+            post.latitude = Some(latitude);
+            post.longitude = Some(longitude);
+            post.reach_radius_km = initial_reach(post.latitude);
+            store_post(&post);
+            track_post(&post);
+            if is_listable(&post) {
+                index_open_post(&post);
+            }
+            audit(
+                AuditAction::Updated,
+                AuditEntity::SurplusPost,
+                id,
+                Some(&before),
+                Some(&post),
+            );
+        }
+    }
+    log(
+        LogLevel::Info,
+        format!("Geocoded the address of {:?}", target),
+    );
+}
+
+// Function to reduce a geocoding response to the coordinates of its first
+// match, so every replica sees the same response. Accepts Nominatim-style
+// arrays of { lat, lon } with string or number values, and single objects
+// with latitude/longitude fields.
+#[ic_cdk::query]
+fn transform_geocode_response(args: TransformArgs) -> OutcallResponse {
+    let parsed = serde_json::from_slice::<serde_json::Value>(&args.response.body).ok();
+    let first = match parsed {
+        Some(serde_json::Value::Array(matches)) => matches.into_iter().next(),
+        other => other,
+    };
+    let number = |value: Option<&serde_json::Value>| match value? {
+        serde_json::Value::Number(number) => number.as_f64(),
+        serde_json::Value::String(text) => text.parse().ok(),
+        _ => None,
+    };
+    let coordinates = first.and_then(|found| {
+        Some(Coordinates {
+            latitude: number(found.get("lat").or_else(|| found.get("latitude")))?,
+            longitude: number(found.get("lon").or_else(|| found.get("longitude")))?,
+        })
+    });
+    OutcallResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: serde_json::to_vec(&coordinates).expect("Cannot encode coordinates"),
+    }
+}
+
+// Function for admins to see the geocoding config, including the API key
+#[ic_cdk::query]
+fn get_geocoding_config() -> Result<GeocodingConfig, FoodShareError> {
+    require_admin("view the geocoding config")?;
+    Ok(geocoding_config())
+}
+
+// Function for admins to set the geocoding service; an api_url of None turns
+// geocoding off
+#[ic_cdk::update]
+fn set_geocoding_config(config: GeocodingConfig) -> Result<GeocodingConfig, FoodShareError> {
+    start_trace("set_geocoding_config");
+    require_admin("update the geocoding config")?;
+
+    if let Some(url) = &config.api_url {
+        if !url.starts_with("https://") || url.len() > MAX_URL_LEN {
+            return Err(FoodShareError::Validation {
+                field: "api_url".to_string(),
+                reason: format!("URL must use https and be at most {} bytes", MAX_URL_LEN),
+            });
+        }
+        if !url.contains("{address}") {
+            return Err(FoodShareError::Validation {
+                field: "api_url".to_string(),
+                reason: "URL must contain an {address} placeholder".to_string(),
+            });
+        }
+    }
+    if config
+        .api_key
+        .as_ref()
+        .is_some_and(|key| key.is_empty() || key.len() > MAX_API_KEY_LEN)
+    {
+        return Err(FoodShareError::Validation {
+            field: "api_key".to_string(),
+            reason: format!("API key must be between 1 and {} bytes", MAX_API_KEY_LEN),
+        });
+    }
+
+    let before = GEOCODING_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the geocoding config");
+    // Keep the API key out of the audit log
+    let redact = |config: &GeocodingConfig| GeocodingConfig {
+        api_key: config.api_key.as_ref().map(|_| "<redacted>".to_string()),
+        ..config.clone()
+    };
+    audit(
+        AuditAction::Configured,
+        AuditEntity::GeocodingConfig,
+        0,
+        Some(&redact(&before)),
+        Some(&redact(&config)),
+    );
+
+    log(LogLevel::Info, "Updated the geocoding config".to_string());
+
+    Ok(config)
+}
+
+// Function for admins to see how geocoding lookups have gone
+#[ic_cdk::query]
+fn get_geocoding_stats() -> Result<GeocodingStats, FoodShareError> {
+    require_admin("view geocoding stats")?;
+    Ok(GEOCODING_STATS.with(|cell| cell.borrow().get().clone()))
+}
+
+// Function for admins to look an address up straight away, e.g. to check the
+// service works or to warm the cache
+#[ic_cdk::update]
+async fn geocode_address(address: String) -> Result<(f64, f64), FoodShareError> {
+    start_trace("geocode_address");
+    require_admin("geocode addresses")?;
+
+    if normalize_address(&address).is_empty() || address.len() > MAX_ADDRESS_LEN {
+        return Err(FoodShareError::Validation {
+            field: "address".to_string(),
+            reason: format!("Address must be between 1 and {} bytes", MAX_ADDRESS_LEN),
+        });
+    }
+    geocode(&address)
+        .await
+        .map_err(|msg| FoodShareError::InternalError { msg })
+}
@@ -0,0 +1,568 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::maintenance::{scan_batch, ScanStep};
+use crate::observability::{current_trace_id, log, resume_trace, start_trace, LogLevel};
+use crate::rate_limits::check_rate_limit;
+use crate::sessions::record_activity;
+use crate::{FoodShareError, Memory, StorablePrincipal, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+// Governance decisions are cached between one second and one day
+const MIN_DECISION_CACHE_TTL_SECS: u64 = 1;
+const MAX_DECISION_CACHE_TTL_SECS: u64 = 24 * 60 * 60;
+
+// AuthDecision is a custom enum type that is used to represent the outcome of a governance check
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum AuthDecision {
+    Accepted,
+    Denied,
+    Unavailable,
+}
+
+// EndpointClass is a custom enum type that is used to group update calls by the authorization policy they share
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum EndpointClass {
+    Registration,
+    Posting,
+    Logistics,
+}
+
+// FailurePolicy is a custom enum type that is used to decide what happens when governance is unavailable
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum FailurePolicy {
+    FailOpen,
+    FailClosed,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct GovernanceConfig {
+    governance_canister: Option<Principal>,
+    registration_policy: FailurePolicy,
+    posting_policy: FailurePolicy,
+    logistics_policy: FailurePolicy,
+    decision_cache_ttl_secs: u64,
+}
+
+impl Default for GovernanceConfig {
+    fn default() -> Self {
+        // Registrations and new posts wait for governance to come back, while
+        // deliveries already in flight keep moving during an outage
+        Self {
+            governance_canister: None,
+            registration_policy: FailurePolicy::FailClosed,
+            posting_policy: FailurePolicy::FailClosed,
+            logistics_policy: FailurePolicy::FailOpen,
+            decision_cache_ttl_secs: 300,
+        }
+    }
+}
+
+impl GovernanceConfig {
+    fn policy_for(&self, class: EndpointClass) -> FailurePolicy {
+        match class {
+            EndpointClass::Registration => self.registration_policy,
+            EndpointClass::Posting => self.posting_policy,
+            EndpointClass::Logistics => self.logistics_policy,
+        }
+    }
+}
+
+impl Storable for GovernanceConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CachedDecision {
+    decision: AuthDecision,
+    expires_at: u64,
+}
+
+impl Storable for CachedDecision {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for CachedDecision {
+    const MAX_SIZE: u32 = 64;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A principal granted admin rights in addition to the canister controllers
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AdminGrant {
+    principal: Principal,
+    // None for the admin named when the canister was installed
+    added_by: Option<Principal>,
+    added_at: u64,
+}
+
+impl Storable for AdminGrant {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for AdminGrant {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CacheCounters {
+    hits: u64,
+    misses: u64,
+    invalidations: u64,
+}
+
+impl Storable for CacheCounters {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AuthorizationCacheMetrics {
+    hits: u64,
+    misses: u64,
+    invalidations: u64,
+    cached_entries: u64,
+    hit_rate_percent: f64,
+}
+
+thread_local! {
+    static GOVERNANCE_CONFIG: RefCell<Cell<GovernanceConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))),
+            GovernanceConfig::default(),
+        )
+        .expect("Cannot create the governance config")
+    );
+
+    static DECISION_CACHE: RefCell<StableBTreeMap<StorablePrincipal, CachedDecision, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))
+    ));
+
+    static CACHE_COUNTERS: RefCell<Cell<CacheCounters, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))),
+            CacheCounters::default(),
+        )
+        .expect("Cannot create the authorization cache counters")
+    );
+
+    static ADMINS: RefCell<StableBTreeMap<StorablePrincipal, AdminGrant, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(118)))
+    ));
+}
+
+// Governance Config Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct GovernanceConfigPayload {
+    governance_canister: Option<Principal>,
+    registration_policy: FailurePolicy,
+    posting_policy: FailurePolicy,
+    logistics_policy: FailurePolicy,
+    decision_cache_ttl_secs: u64,
+}
+
+// Function to check whether a principal may administer the canister: the
+// controllers and every principal in the admin set
+pub(crate) fn is_admin(principal: &Principal) -> bool {
+    ic_cdk::api::is_controller(principal)
+        || ADMINS.with(|admins| admins.borrow().contains_key(&StorablePrincipal(*principal)))
+}
+
+// Function to store the first admin named in the install argument
+pub(crate) fn bootstrap_admin(admin: Principal) {
+    if admin == Principal::anonymous() {
+        ic_cdk::trap("The anonymous principal cannot be made an admin");
+    }
+    let grant = AdminGrant {
+        principal: admin,
+        added_by: None,
+        added_at: time(),
+    };
+    ADMINS.with(|admins| admins.borrow_mut().insert(StorablePrincipal(admin), grant));
+}
+
+// Function to reject callers that are not admins
+pub(crate) fn require_admin(action: &str) -> Result<(), FoodShareError> {
+    if is_admin(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(FoodShareError::Unauthorized {
+            msg: format!("Only admins can {}", action),
+        })
+    }
+}
+
+// Function to check whether a principal is an admin or the configured governance canister
+pub(crate) fn is_governance_or_admin(principal: &Principal) -> bool {
+    is_admin(principal) || governance_config().governance_canister == Some(*principal)
+}
+
+fn governance_config() -> GovernanceConfig {
+    GOVERNANCE_CONFIG.with(|config| config.borrow().get().clone())
+}
+
+fn update_counters(update: impl FnOnce(&mut CacheCounters)) {
+    CACHE_COUNTERS.with(|cell| {
+        let mut counters = cell.borrow().get().clone();
+        update(&mut counters);
+        cell.borrow_mut()
+            .set(counters)
+            .expect("Cannot update the authorization cache counters");
+    });
+}
+
+// Function to evict the expired decisions in one batch of the cache
+pub(crate) fn prune_decision_cache(cursor: Option<&[u8]>) -> ScanStep {
+    let (entries, next_cursor) = DECISION_CACHE.with(|cache| scan_batch(&cache.borrow(), cursor));
+    let now = time();
+    let expired: Vec<StorablePrincipal> = entries
+        .iter()
+        .filter(|(_, cached)| cached.expires_at <= now)
+        .map(|(key, _)| *key)
+        .collect();
+    DECISION_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        for key in &expired {
+            cache.remove(key);
+        }
+    });
+    ScanStep {
+        next_cursor,
+        scanned: entries.len() as u64,
+        removed: expired.len() as u64,
+        ..Default::default()
+    }
+}
+
+// Function to look up a cached decision, evicting it once its TTL has passed
+fn cached_decision(principal: Principal) -> Option<AuthDecision> {
+    let key = StorablePrincipal(principal);
+    let cached = DECISION_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        match cache.get(&key) {
+            Some(cached) if cached.expires_at > time() => Some(cached.decision),
+            Some(_) => {
+                cache.remove(&key);
+                None
+            }
+            None => None,
+        }
+    });
+
+    match cached {
+        Some(_) => update_counters(|counters| counters.hits += 1),
+        None => update_counters(|counters| counters.misses += 1),
+    }
+
+    cached
+}
+
+// Function to ask the governance canister whether a principal is accepted.
+// Call failures are reported as `Unavailable` rather than as a denial.
+pub(crate) async fn is_governance_accepted(principal: Principal) -> AuthDecision {
+    let config = governance_config();
+
+    // Without a governance canister every caller is accepted
+    let Some(governance_canister) = config.governance_canister else {
+        return AuthDecision::Accepted;
+    };
+
+    if let Some(decision) = cached_decision(principal) {
+        return decision;
+    }
+
+    let result: Result<(bool,), _> =
+        ic_cdk::call(governance_canister, "is_accepted", (principal,)).await;
+
+    let decision = match result {
+        Ok((true,)) => AuthDecision::Accepted,
+        Ok((false,)) => AuthDecision::Denied,
+        Err((code, msg)) => {
+            log(
+                LogLevel::Warn,
+                format!("Governance call failed ({:?}): {}", code, msg),
+            );
+            return AuthDecision::Unavailable;
+        }
+    };
+
+    DECISION_CACHE.with(|cache| {
+        cache.borrow_mut().insert(
+            StorablePrincipal(principal),
+            CachedDecision {
+                decision,
+                expires_at: time().saturating_add(
+                    config
+                        .decision_cache_ttl_secs
+                        .saturating_mul(NANOS_PER_SECOND),
+                ),
+            },
+        )
+    });
+
+    decision
+}
+
+// Function to authorize the caller for an endpoint class, applying the
+// configured fail-open/fail-closed policy when governance is unavailable.
+// The caller's rate limit is spent first so floods never reach governance,
+// and callers let through are recorded as active.
+pub(crate) async fn authorize(class: EndpointClass) -> Result<(), FoodShareError> {
+    check_rate_limit(class)?;
+    let trace_id = current_trace_id();
+    let decision = is_governance_accepted(ic_cdk::caller()).await;
+    resume_trace(&trace_id);
+
+    let result = match decision {
+        AuthDecision::Accepted => Ok(()),
+        AuthDecision::Denied => {
+            log(
+                LogLevel::Warn,
+                format!("Governance denied {:?} call", class),
+            );
+            Err(FoodShareError::Unauthorized {
+                msg: "Caller is not accepted by governance".to_string(),
+            })
+        }
+        AuthDecision::Unavailable => match governance_config().policy_for(class) {
+            FailurePolicy::FailOpen => {
+                log(
+                    LogLevel::Warn,
+                    format!("Governance unavailable, allowing {:?} call", class),
+                );
+                Ok(())
+            }
+            FailurePolicy::FailClosed => {
+                log(
+                    LogLevel::Error,
+                    format!("Governance unavailable, rejecting {:?} call", class),
+                );
+                Err(FoodShareError::InternalError {
+                    msg: "Governance is unavailable, please try again later".to_string(),
+                })
+            }
+        },
+    };
+    if result.is_ok() {
+        record_activity(ic_cdk::caller(), Some(class));
+    }
+    result
+}
+
+// Function to get the governance configuration
+#[ic_cdk::query]
+fn get_governance_config() -> GovernanceConfig {
+    governance_config()
+}
+
+// Function to update the governance configuration
+#[ic_cdk::update]
+fn set_governance_config(
+    payload: GovernanceConfigPayload,
+) -> Result<GovernanceConfig, FoodShareError> {
+    start_trace("set_governance_config");
+
+    if !is_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only admins can update the governance config".to_string(),
+        });
+    }
+    if !(MIN_DECISION_CACHE_TTL_SECS..=MAX_DECISION_CACHE_TTL_SECS)
+        .contains(&payload.decision_cache_ttl_secs)
+    {
+        return Err(FoodShareError::Validation {
+            field: "decision_cache_ttl_secs".to_string(),
+            reason: format!(
+                "Must be between {} and {} seconds",
+                MIN_DECISION_CACHE_TTL_SECS, MAX_DECISION_CACHE_TTL_SECS
+            ),
+        });
+    }
+
+    let config = GovernanceConfig {
+        governance_canister: payload.governance_canister,
+        registration_policy: payload.registration_policy,
+        posting_policy: payload.posting_policy,
+        logistics_policy: payload.logistics_policy,
+        decision_cache_ttl_secs: payload.decision_cache_ttl_secs,
+    };
+
+    let before = GOVERNANCE_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the governance config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::GovernanceConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    // Decisions made against the previous governance canister no longer apply
+    DECISION_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let keys: Vec<StorablePrincipal> = cache.iter().map(|(key, _)| key).collect();
+        for key in keys {
+            cache.remove(&key);
+        }
+    });
+
+    log(LogLevel::Info, "Updated governance config".to_string());
+
+    Ok(config)
+}
+
+// Function for admins and controllers to grant admin rights to a principal
+#[ic_cdk::update]
+fn add_admin(principal: Principal) -> Result<AdminGrant, FoodShareError> {
+    start_trace("add_admin");
+    require_admin("add admins")?;
+
+    if principal == Principal::anonymous() {
+        return Err(FoodShareError::Validation {
+            field: "principal".to_string(),
+            reason: "The anonymous principal cannot be made an admin".to_string(),
+        });
+    }
+    if ADMINS.with(|admins| admins.borrow().contains_key(&StorablePrincipal(principal))) {
+        return Err(FoodShareError::Conflict {
+            msg: format!("{} is already an admin", principal),
+        });
+    }
+
+    let grant = AdminGrant {
+        principal,
+        added_by: Some(ic_cdk::caller()),
+        added_at: time(),
+    };
+    ADMINS.with(|admins| {
+        admins
+            .borrow_mut()
+            .insert(StorablePrincipal(principal), grant.clone())
+    });
+    audit(
+        AuditAction::Created,
+        AuditEntity::Admin,
+        0,
+        None,
+        Some(&grant),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("{} granted admin rights to {}", ic_cdk::caller(), principal),
+    );
+
+    Ok(grant)
+}
+
+// Function for admins and controllers to take admin rights away from a
+// principal in the admin set. Controllers stay admins regardless.
+#[ic_cdk::update]
+fn remove_admin(principal: Principal) -> Result<(), FoodShareError> {
+    start_trace("remove_admin");
+    require_admin("remove admins")?;
+
+    let grant = ADMINS
+        .with(|admins| admins.borrow_mut().remove(&StorablePrincipal(principal)))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("{} is not in the admin set", principal),
+        })?;
+    audit(
+        AuditAction::Revoked,
+        AuditEntity::Admin,
+        0,
+        Some(&grant),
+        None::<&AdminGrant>,
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "{} removed admin rights from {}",
+            ic_cdk::caller(),
+            principal
+        ),
+    );
+
+    Ok(())
+}
+
+// Function for admins to list the admin set; controllers are not included
+#[ic_cdk::query]
+fn list_admins() -> Result<Vec<AdminGrant>, FoodShareError> {
+    require_admin("list admins")?;
+    Ok(ADMINS.with(|admins| admins.borrow().iter().map(|(_, grant)| grant).collect()))
+}
+
+// Function to drop the cached authorization decision for a principal
+#[ic_cdk::update]
+fn invalidate_authorization(principal: Principal) -> Result<(), FoodShareError> {
+    start_trace("invalidate_authorization");
+
+    if !is_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only admins can invalidate authorization decisions".to_string(),
+        });
+    }
+
+    DECISION_CACHE.with(|cache| cache.borrow_mut().remove(&StorablePrincipal(principal)));
+    update_counters(|counters| counters.invalidations += 1);
+
+    log(
+        LogLevel::Info,
+        format!("Invalidated authorization decision for {}", principal),
+    );
+
+    Ok(())
+}
+
+// Function to get the authorization cache metrics
+#[ic_cdk::query]
+fn get_authorization_cache_metrics() -> AuthorizationCacheMetrics {
+    let counters = CACHE_COUNTERS.with(|cell| cell.borrow().get().clone());
+    let cached_entries = DECISION_CACHE.with(|cache| cache.borrow().len());
+    let lookups = counters.hits + counters.misses;
+    let hit_rate_percent = if lookups == 0 {
+        0.0
+    } else {
+        counters.hits as f64 * 100.0 / lookups as f64
+    };
+
+    AuthorizationCacheMetrics {
+        hits: counters.hits,
+        misses: counters.misses,
+        invalidations: counters.invalidations,
+        cached_entries,
+        hit_rate_percent,
+    }
+}
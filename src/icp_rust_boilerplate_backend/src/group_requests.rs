@@ -0,0 +1,431 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::food_requests::record_fulfillment;
+use crate::governance::{authorize, EndpointClass};
+use crate::id_sequences::{allocate_id, IdEntity};
+use crate::indexes::assignments_for_post;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::validation;
+use crate::{
+    next_id, FoodRequest, FoodRequestStatus, FoodShareError, FoodType, Memory, ReceiverType,
+    FOOD_REQUESTS_STORAGE, MEMORY_MANAGER, RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+    SURPLUS_RECORDS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_GROUP_MEMBERS: usize = 20;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct GroupMember {
+    receiver_id: u64,
+    share_kg: u32,
+    signed: bool,
+    received_kg: u32,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct GroupRequest {
+    id: u64,
+    food_request_id: u64,
+    hub_receiver_id: u64,
+    members: Vec<GroupMember>,
+    attributed_record_ids: Vec<u64>,
+    created_at: u64,
+}
+
+impl GroupRequest {
+    fn fully_signed(&self) -> bool {
+        self.members.iter().all(|member| member.signed)
+    }
+}
+
+impl Storable for GroupRequest {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for GroupRequest {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static GROUP_REQUESTS_STORAGE: RefCell<StableBTreeMap<u64, GroupRequest, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+    ));
+}
+
+// Group Member Share Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct GroupMemberShare {
+    receiver_id: u64,
+    share_kg: u32,
+}
+
+// Group Request Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct GroupRequestPayload {
+    hub_receiver_id: u64,
+    food_type: FoodType,
+    notes: String,
+    members: Vec<GroupMemberShare>,
+}
+
+// Function to split a delivered quantity across members in proportion to
+// their shares, handing out the rounding remainder by largest fraction
+fn split_kg(total_kg: u32, shares: &[u32]) -> Vec<u32> {
+    let total_shares: u64 = shares.iter().map(|share| *share as u64).sum();
+    if total_shares == 0 {
+        return vec![0; shares.len()];
+    }
+
+    let mut allocated: Vec<u32> = Vec::with_capacity(shares.len());
+    let mut remainders: Vec<(u64, usize)> = Vec::with_capacity(shares.len());
+    for (index, share) in shares.iter().enumerate() {
+        let exact = total_kg as u64 * *share as u64;
+        allocated.push((exact / total_shares) as u32);
+        remainders.push((exact % total_shares, index));
+    }
+
+    let leftover = total_kg - allocated.iter().sum::<u32>();
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, index) in remainders.into_iter().take(leftover as usize) {
+        allocated[index] += 1;
+    }
+
+    allocated
+}
+
+// Function to create a group request pooling several receivers behind one hub
+#[ic_cdk::update]
+async fn create_group_request(
+    payload: GroupRequestPayload,
+) -> Result<GroupRequest, FoodShareError> {
+    start_trace("create_group_request");
+    authorize(EndpointClass::Posting).await?;
+
+    // Validate the payload to ensure that the group has members with shares
+    if payload.members.is_empty() || payload.members.len() > MAX_GROUP_MEMBERS {
+        return Err(FoodShareError::Validation {
+            field: "members".to_string(),
+            reason: format!(
+                "A group request needs between 1 and {} members",
+                MAX_GROUP_MEMBERS
+            ),
+        });
+    }
+    validation::check_max_len("notes", &payload.notes, validation::MAX_REQUEST_NOTES_LEN)?;
+    if payload.members.iter().any(|member| member.share_kg == 0) {
+        return Err(FoodShareError::Validation {
+            field: "shares".to_string(),
+            reason: "Every member share must be greater than zero".to_string(),
+        });
+    }
+
+    // Validate the payload to ensure that the hub and every member exist exactly once
+    let all_exist = RECEIVERS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        storage.contains_key(&payload.hub_receiver_id)
+            && payload
+                .members
+                .iter()
+                .all(|member| storage.contains_key(&member.receiver_id))
+    });
+    if !all_exist {
+        return Err(FoodShareError::NotFound {
+            msg: "Receiver ID does not exist".to_string(),
+        });
+    }
+    let hub_is_hub = RECEIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .get(&payload.hub_receiver_id)
+            .is_some_and(|hub| hub.receiver_type == ReceiverType::Hub)
+    });
+    if !hub_is_hub {
+        return Err(FoodShareError::Validation {
+            field: "hub_receiver_id".to_string(),
+            reason: "Group requests must be delivered to a hub receiver".to_string(),
+        });
+    }
+    let mut member_ids: Vec<u64> = payload.members.iter().map(|m| m.receiver_id).collect();
+    member_ids.sort_unstable();
+    member_ids.dedup();
+    if member_ids.len() != payload.members.len() {
+        return Err(FoodShareError::Conflict {
+            msg: "A receiver can only join a group request once".to_string(),
+        });
+    }
+
+    let quantity_kg = payload
+        .members
+        .iter()
+        .try_fold(0u32, |total, member| total.checked_add(member.share_kg))
+        .ok_or(FoodShareError::Validation {
+            field: "quantity_kg".to_string(),
+            reason: "Total group quantity is too large".to_string(),
+        })?;
+
+    // The pooled demand is a single food request owned by the hub receiver
+    let food_request_id = allocate_id(IdEntity::FoodRequest);
+    let food_request = FoodRequest {
+        id: food_request_id,
+        receiver_id: payload.hub_receiver_id,
+        food_type: payload.food_type,
+        quantity_kg,
+        notes: payload.notes,
+        assigned: false,
+        created_at: time(),
+        status: Some(FoodRequestStatus::Open),
+        quantity_fulfilled_kg: Some(0),
+        fulfilled_post_ids: None,
+        needed_by: None,
+        organization_id: None,
+    };
+    FOOD_REQUESTS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(food_request_id, food_request.clone())
+    });
+    audit(
+        AuditAction::Created,
+        AuditEntity::FoodRequest,
+        food_request_id,
+        None,
+        Some(&food_request),
+    );
+
+    let id = next_id();
+    let group_request = GroupRequest {
+        id,
+        food_request_id,
+        hub_receiver_id: payload.hub_receiver_id,
+        members: payload
+            .members
+            .into_iter()
+            .map(|member| GroupMember {
+                receiver_id: member.receiver_id,
+                share_kg: member.share_kg,
+                // The hub signs implicitly when it is also a member
+                signed: member.receiver_id == payload.hub_receiver_id,
+                received_kg: 0,
+            })
+            .collect(),
+        attributed_record_ids: Vec::new(),
+        created_at: time(),
+    };
+
+    GROUP_REQUESTS_STORAGE.with(|storage| storage.borrow_mut().insert(id, group_request.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::GroupRequest,
+        id,
+        None,
+        Some(&group_request),
+    );
+
+    record_event(
+        EntityType::FoodRequest,
+        food_request_id,
+        None,
+        EventKind::Created,
+        format!("Group request {} pooled {} kg", id, quantity_kg),
+    );
+    log(LogLevel::Info, format!("Created group request {}", id));
+
+    Ok(group_request)
+}
+
+// Function for a member receiver to co-sign a group request
+#[ic_cdk::update]
+async fn co_sign_group_request(
+    group_request_id: u64,
+    receiver_id: u64,
+) -> Result<GroupRequest, FoodShareError> {
+    start_trace("co_sign_group_request");
+    authorize(EndpointClass::Posting).await?;
+
+    let mut group_request = GROUP_REQUESTS_STORAGE
+        .with(|storage| storage.borrow().get(&group_request_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Group request ID does not exist".to_string(),
+        })?;
+    let before = group_request.clone();
+
+    let member = group_request
+        .members
+        .iter_mut()
+        .find(|member| member.receiver_id == receiver_id)
+        .ok_or(FoodShareError::Conflict {
+            msg: "Receiver is not a member of this group request".to_string(),
+        })?;
+    if member.signed {
+        return Err(FoodShareError::Conflict {
+            msg: "Receiver has already co-signed this group request".to_string(),
+        });
+    }
+    member.signed = true;
+
+    GROUP_REQUESTS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(group_request_id, group_request.clone())
+    });
+    audit(
+        AuditAction::Updated,
+        AuditEntity::GroupRequest,
+        group_request_id,
+        Some(&before),
+        Some(&group_request),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Receiver {} co-signed group request {}",
+            receiver_id, group_request_id
+        ),
+    );
+
+    Ok(group_request)
+}
+
+// Function to attribute a combined delivery to the hub across the group members
+#[ic_cdk::update]
+async fn attribute_group_delivery(
+    group_request_id: u64,
+    surplus_record_id: u64,
+) -> Result<GroupRequest, FoodShareError> {
+    start_trace("attribute_group_delivery");
+    authorize(EndpointClass::Logistics).await?;
+
+    let mut group_request = GROUP_REQUESTS_STORAGE
+        .with(|storage| storage.borrow().get(&group_request_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Group request ID does not exist".to_string(),
+        })?;
+    let before = group_request.clone();
+
+    if !group_request.fully_signed() {
+        return Err(FoodShareError::Conflict {
+            msg: "All members must co-sign before deliveries are attributed".to_string(),
+        });
+    }
+    if group_request
+        .attributed_record_ids
+        .contains(&surplus_record_id)
+    {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus record is already attributed to this group".to_string(),
+        });
+    }
+
+    let surplus_record = SURPLUS_RECORDS_STORAGE
+        .with(|storage| storage.borrow().get(&surplus_record_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Surplus record ID does not exist".to_string(),
+        })?;
+
+    // Validate that the record is a delivery to the group's hub
+    let delivered_to_hub = assignments_for_post(surplus_record.surplus_post_id)
+        .iter()
+        .any(|assignment| {
+            assignment.driver_id == surplus_record.driver_id
+                && assignment.receiver_id == group_request.hub_receiver_id
+        });
+    if !delivered_to_hub {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus record was not delivered to the group's hub".to_string(),
+        });
+    }
+
+    let delivered_kg = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&surplus_record.surplus_post_id))
+        .map(|post| post.quantity_kg)
+        .ok_or(FoodShareError::NotFound {
+            msg: "Surplus post ID does not exist".to_string(),
+        })?;
+
+    let shares: Vec<u32> = group_request.members.iter().map(|m| m.share_kg).collect();
+    for (member, kg) in group_request
+        .members
+        .iter_mut()
+        .zip(split_kg(delivered_kg, &shares))
+    {
+        member.received_kg += kg;
+    }
+    group_request.attributed_record_ids.push(surplus_record_id);
+
+    GROUP_REQUESTS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(group_request_id, group_request.clone())
+    });
+    audit(
+        AuditAction::Updated,
+        AuditEntity::GroupRequest,
+        group_request_id,
+        Some(&before),
+        Some(&group_request),
+    );
+
+    record_fulfillment(
+        group_request.food_request_id,
+        surplus_record.surplus_post_id,
+        delivered_kg,
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Attributed {} kg from record {} to group request {}",
+            delivered_kg, surplus_record_id, group_request_id
+        ),
+    );
+
+    Ok(group_request)
+}
+
+// Function to get a group request by id
+#[ic_cdk::query]
+fn get_group_request(id: u64) -> Result<GroupRequest, FoodShareError> {
+    GROUP_REQUESTS_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Group request with id={} not found", id),
+        })
+}
+
+// Function to get the group requests a receiver belongs to
+#[ic_cdk::query]
+fn get_group_requests_for_receiver(receiver_id: u64) -> Result<Vec<GroupRequest>, FoodShareError> {
+    GROUP_REQUESTS_STORAGE.with(|storage| {
+        let records: Vec<GroupRequest> = storage
+            .borrow()
+            .iter()
+            .filter(|(_, group)| {
+                group.hub_receiver_id == receiver_id
+                    || group.members.iter().any(|m| m.receiver_id == receiver_id)
+            })
+            .map(|(_, group)| group)
+            .collect();
+
+        if records.is_empty() {
+            Err(FoodShareError::NotFound {
+                msg: "No group requests found.".to_string(),
+            })
+        } else {
+            Ok(records)
+        }
+    })
+}
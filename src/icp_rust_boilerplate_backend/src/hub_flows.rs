@@ -0,0 +1,291 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, EndpointClass};
+use crate::id_sequences::{allocate_id, IdEntity};
+use crate::indexes::{assignments_for_post, record_for_assignment, store_assignment};
+use crate::notifications::{notify_assignment, NotificationKind};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::status_codes::issue_reference;
+use crate::{
+    is_active_receiver, Assignment, AssignmentStatus, FoodShareError, ReceiverType, StatusChange,
+    ASSIGNMENTS_STORAGE, DRIVERS_STORAGE, RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use ic_cdk::api::time;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct FlowLeg {
+    assignment_id: u64,
+    receiver_id: u64,
+    driver_id: u64,
+    created_at: u64,
+    delivered_at: Option<u64>,
+    duration_secs: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct HubFlow {
+    surplus_post_id: u64,
+    legs: Vec<FlowLeg>,
+    final_receiver_id: u64,
+    completed: bool,
+    // Delivered food is only counted once the flow reaches its final receiver
+    impact_kg: u32,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct HubMetrics {
+    hub_receiver_id: u64,
+    inbound_legs: u64,
+    inbound_delivered: u64,
+    outbound_legs: u64,
+    outbound_delivered: u64,
+    kg_received: u64,
+    kg_redistributed: u64,
+}
+
+// Onward Assignment Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct OnwardAssignmentPayload {
+    previous_assignment_id: u64,
+    receiver_id: u64,
+    driver_id: u64,
+}
+
+fn delivered_at(assignment: &Assignment) -> Option<u64> {
+    record_for_assignment(assignment.id).map(|record| record.delivered_at)
+}
+
+// Onward legs carry the same surplus post, so only that post's assignments are checked
+fn onward_leg(previous: &Assignment) -> Option<Assignment> {
+    assignments_for_post(previous.surplus_post_id)
+        .into_iter()
+        .find(|assignment| {
+            assignment.previous_leg_id == Some(previous.id) && !assignment.status.releases_post()
+        })
+}
+
+fn is_hub(receiver_id: u64) -> bool {
+    RECEIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .get(&receiver_id)
+            .is_some_and(|receiver| receiver.receiver_type == ReceiverType::Hub)
+    })
+}
+
+// Function to create the hub→receiver leg of a two-leg delivery flow
+#[ic_cdk::update]
+async fn create_onward_assignment(
+    payload: OnwardAssignmentPayload,
+) -> Result<Assignment, FoodShareError> {
+    start_trace("create_onward_assignment");
+    authorize(EndpointClass::Logistics).await?;
+
+    let previous = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.previous_assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Previous assignment ID does not exist".to_string(),
+        })?;
+
+    // Validate the flow to ensure the first leg ended at a hub that has received the food
+    if !is_hub(previous.receiver_id) {
+        return Err(FoodShareError::Conflict {
+            msg: "Previous assignment was not delivered to a hub".to_string(),
+        });
+    }
+    if delivered_at(&previous).is_none() {
+        return Err(FoodShareError::Conflict {
+            msg: "Previous assignment has not been delivered to the hub yet".to_string(),
+        });
+    }
+    if onward_leg(&previous).is_some() {
+        return Err(FoodShareError::Conflict {
+            msg: "Previous assignment already has an onward leg".to_string(),
+        });
+    }
+
+    // Validate the payload to ensure that the receiver_id exists
+    if !is_active_receiver(payload.receiver_id) {
+        return Err(FoodShareError::NotFound {
+            msg: "Receiver ID does not exist or is deactivated".to_string(),
+        });
+    }
+    if payload.receiver_id == previous.receiver_id {
+        return Err(FoodShareError::Validation {
+            field: "receiver_id".to_string(),
+            reason: "Onward leg must deliver to a different receiver".to_string(),
+        });
+    }
+
+    // Validate the payload to ensure that the driver_id exists
+    let driver = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.driver_id))
+        .filter(|driver| driver.active)
+        .ok_or(FoodShareError::NotFound {
+            msg: "Driver ID does not exist or is deactivated".to_string(),
+        })?;
+    crate::driver_load::check_weekly_cap(&driver)?;
+
+    // Validate that the driver is on shift with room for the load
+    crate::driver_availability::check_can_take(&driver, previous.carried_kg())?;
+    if let Some(post) =
+        SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&previous.surplus_post_id))
+    {
+        crate::vehicles::check_refrigeration(&driver, post.food_type)?;
+    }
+
+    let id = allocate_id(IdEntity::Assignment);
+
+    let now = time();
+    let assignment = Assignment {
+        id,
+        surplus_post_id: previous.surplus_post_id,
+        receiver_id: payload.receiver_id,
+        driver_id: payload.driver_id,
+        status: AssignmentStatus::Pending,
+        status_history: vec![StatusChange {
+            status: AssignmentStatus::Pending,
+            changed_at: now,
+        }],
+        previous_leg_id: Some(previous.id),
+        created_at: now,
+        pickup_window_start: None,
+        pickup_window_end: None,
+        pickup_proposal: None,
+        // The onward leg carries the same share of the post as the leg into the hub
+        quantity_kg: previous.quantity_kg,
+    };
+
+    store_assignment(&assignment);
+    audit(
+        AuditAction::Created,
+        AuditEntity::Assignment,
+        id,
+        None,
+        Some(&assignment),
+    );
+    issue_reference(id);
+
+    record_event(
+        EntityType::Assignment,
+        id,
+        Some(id),
+        EventKind::Created,
+        format!(
+            "Onward leg from hub {} to receiver {} following assignment {}",
+            previous.receiver_id, assignment.receiver_id, previous.id
+        ),
+    );
+    notify_assignment(
+        &assignment,
+        NotificationKind::PostAssigned,
+        &format!(
+            "Onward leg from hub {} to receiver {} was assigned to driver {}",
+            previous.receiver_id, assignment.receiver_id, assignment.driver_id
+        ),
+    );
+    log(LogLevel::Info, format!("Created onward assignment {}", id));
+
+    Ok(assignment)
+}
+
+// Function to get the end-to-end flow an assignment belongs to
+#[ic_cdk::query]
+fn get_hub_flow(assignment_id: u64) -> Result<HubFlow, FoodShareError> {
+    let not_found = || FoodShareError::NotFound {
+        msg: format!("Assignment with id={} not found", assignment_id),
+    };
+
+    // Walk back to the first leg, then forward through every onward leg
+    let mut first = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or_else(not_found)?;
+    while let Some(previous_id) = first.previous_leg_id {
+        first = ASSIGNMENTS_STORAGE
+            .with(|storage| storage.borrow().get(&previous_id))
+            .ok_or_else(not_found)?;
+    }
+
+    let surplus_post_id = first.surplus_post_id;
+    let mut legs = Vec::new();
+    let mut current = Some(first);
+    while let Some(assignment) = current {
+        let delivered_at = delivered_at(&assignment);
+        legs.push(FlowLeg {
+            assignment_id: assignment.id,
+            receiver_id: assignment.receiver_id,
+            driver_id: assignment.driver_id,
+            created_at: assignment.created_at,
+            delivered_at,
+            duration_secs: delivered_at
+                .map(|delivered| delivered.saturating_sub(assignment.created_at) / 1_000_000_000),
+        });
+        current = onward_leg(&assignment);
+    }
+
+    let last = legs.last().ok_or_else(not_found)?;
+    let final_receiver_id = last.receiver_id;
+    let completed = last.delivered_at.is_some() && !is_hub(final_receiver_id);
+    let impact_kg = if completed {
+        SURPLUS_POSTS_STORAGE
+            .with(|storage| storage.borrow().get(&surplus_post_id))
+            .map(|post| post.quantity_kg)
+            .unwrap_or_default()
+    } else {
+        0
+    };
+
+    Ok(HubFlow {
+        surplus_post_id,
+        legs,
+        final_receiver_id,
+        completed,
+        impact_kg,
+    })
+}
+
+// Function to get inbound and outbound logistics metrics for a hub
+#[ic_cdk::query]
+fn get_hub_metrics(hub_receiver_id: u64) -> Result<HubMetrics, FoodShareError> {
+    if !is_hub(hub_receiver_id) {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Hub receiver with id={} not found", hub_receiver_id),
+        });
+    }
+
+    let mut metrics = HubMetrics {
+        hub_receiver_id,
+        ..Default::default()
+    };
+
+    ASSIGNMENTS_STORAGE.with(|storage| {
+        for (_, assignment) in storage.borrow().iter() {
+            let quantity_kg = assignment.carried_kg() as u64;
+            let delivered = delivered_at(&assignment).is_some();
+
+            if assignment.receiver_id == hub_receiver_id {
+                metrics.inbound_legs += 1;
+                if delivered {
+                    metrics.inbound_delivered += 1;
+                    metrics.kg_received += quantity_kg;
+                }
+            }
+
+            let from_hub = assignment.previous_leg_id.is_some_and(|previous_id| {
+                storage
+                    .borrow()
+                    .get(&previous_id)
+                    .is_some_and(|previous| previous.receiver_id == hub_receiver_id)
+            });
+            if from_hub {
+                metrics.outbound_legs += 1;
+                if delivered {
+                    metrics.outbound_delivered += 1;
+                    metrics.kg_redistributed += quantity_kg;
+                }
+            }
+        }
+    });
+
+    Ok(metrics)
+}
@@ -0,0 +1,93 @@
+use crate::governance::require_admin;
+use crate::{FoodShareError, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// IdEntity is a custom enum type that is used to represent which id sequence an entity draws from
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum IdEntity {
+    // Donors, receivers and drivers share one sequence, since profiles are
+    // looked up by id across all three collections
+    Profile,
+    SurplusPost,
+    Assignment,
+    SurplusRecord,
+    FoodRequest,
+    // Every other collection
+    Shared,
+}
+
+impl IdEntity {
+    // Stable map key; new variants must only ever be appended
+    fn key(self) -> u8 {
+        self as u8
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct IdSequence {
+    // The id the next allocation returns
+    next_id: u64,
+}
+
+impl Storable for IdSequence {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for IdSequence {
+    const MAX_SIZE: u32 = 64;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // The counter every entity shared before sequences existed; only read to
+    // seed new sequences past every id it handed out
+    static LEGACY_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
+            .expect("Cannot create a counter")
+    );
+
+    static ID_SEQUENCES: RefCell<StableBTreeMap<u8, IdSequence, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(103)))
+    ));
+}
+
+// Function to read a sequence, starting a missing one where the legacy counter stopped
+fn sequence(entity: IdEntity) -> IdSequence {
+    ID_SEQUENCES
+        .with(|storage| storage.borrow().get(&entity.key()))
+        .unwrap_or_else(|| IdSequence {
+            next_id: LEGACY_ID_COUNTER.with(|counter| *counter.borrow().get()),
+        })
+}
+
+// Function to allocate the next id of an entity type. Running out of ids traps,
+// so the call that needed one is rolled back instead of reusing an id.
+pub(crate) fn allocate_id(entity: IdEntity) -> u64 {
+    let current = sequence(entity);
+    let Some(next_id) = current.next_id.checked_add(1) else {
+        ic_cdk::trap(&format!("The {:?} id sequence is exhausted", entity));
+    };
+    ID_SEQUENCES.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(entity.key(), IdSequence { next_id })
+    });
+    current.next_id
+}
+
+// Function for admins to see the id the next allocation of an entity type returns
+#[ic_cdk::query]
+fn peek_next_id(entity: IdEntity) -> Result<u64, FoodShareError> {
+    require_admin("peek at id sequences")?;
+    Ok(sequence(entity).next_id)
+}
@@ -0,0 +1,296 @@
+use crate::assignment_lifecycle::is_receiver_owner;
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::delivery_proofs::confirm_submitted_delivery;
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, is_governance_or_admin, EndpointClass};
+use crate::indexes::{assignments_for_post, record_for_assignment, store_record};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::reputation::record_rating;
+use crate::settings::validate_rating;
+use crate::{
+    Assignment, AssignmentStatus, FoodShareError, SurplusRecord, ASSIGNMENTS_STORAGE,
+    SURPLUS_POSTS_STORAGE, SURPLUS_RECORDS_STORAGE,
+};
+use chrono::{Days, Months, NaiveDate, Weekday};
+use ic_cdk::api::time;
+use std::collections::BTreeMap;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const MAX_BENEFICIARIES: u32 = 100_000;
+const MAX_MEAL_PROGRAM_LEN: usize = 32;
+
+// Delivery Confirmation Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct DeliveryConfirmationPayload {
+    // People the delivery fed
+    beneficiaries: Option<u32>,
+    // Meal program the food went to, e.g. "school-lunch"
+    meal_program: Option<String>,
+    // Receiver's rating of the delivery, on the rating scale in the settings
+    rating: Option<u8>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct ProgramImpact {
+    meal_program: String,
+    deliveries: u64,
+    delivered_kg: u64,
+    beneficiaries: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct ImpactSummary {
+    // Each surplus post counts once, at its final delivery leg
+    deliveries: u64,
+    delivered_kg: u64,
+    // Receivers that got at least one delivery
+    receivers_served: u64,
+    // Deliveries the receiver confirmed with a beneficiary count
+    reported_deliveries: u64,
+    beneficiaries_served: u64,
+    // Kilograms per person across the deliveries that reported a count
+    kg_per_beneficiary: Option<f64>,
+    programs: Vec<ProgramImpact>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct GrantReport {
+    // Same period labels as report anchoring: "YYYY-Www" or "YYYY-MM"
+    period: String,
+    starts_at: u64,
+    ends_at: u64,
+    impact: ImpactSummary,
+}
+
+// Function to find the assignment that delivered a surplus record
+fn delivering_assignment(record: &SurplusRecord) -> Option<Assignment> {
+    if let Some(assignment_id) = record.assignment_id {
+        return ASSIGNMENTS_STORAGE.with(|storage| storage.borrow().get(&assignment_id));
+    }
+    assignments_for_post(record.surplus_post_id)
+        .into_iter()
+        .find(|assignment| {
+            assignment.driver_id == record.driver_id
+                && assignment.status == AssignmentStatus::Delivered
+        })
+}
+
+fn normalize_meal_program(meal_program: &str) -> Result<String, FoodShareError> {
+    let meal_program = meal_program.trim().to_lowercase();
+    let valid_chars = meal_program
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ' ');
+    if meal_program.is_empty() || meal_program.len() > MAX_MEAL_PROGRAM_LEN || !valid_chars {
+        return Err(FoodShareError::Validation {
+            field: "meal_program".to_string(),
+            reason: format!(
+                "Meal program must be 1 to {} letters, digits, spaces, dashes or underscores",
+                MAX_MEAL_PROGRAM_LEN
+            ),
+        });
+    }
+    Ok(meal_program)
+}
+
+// Function to get the start and end of a "YYYY-Www" or "YYYY-MM" period, in nanoseconds
+fn period_bounds(period: &str) -> Option<(u64, u64)> {
+    let (year, rest) = period.split_once('-')?;
+    if year.len() != 4 {
+        return None;
+    }
+    let year: i32 = year.parse().ok()?;
+    let (start, end) = match rest.strip_prefix('W') {
+        Some(week) if week.len() == 2 => {
+            let start = NaiveDate::from_isoywd_opt(year, week.parse().ok()?, Weekday::Mon)?;
+            (start, start.checked_add_days(Days::new(7))?)
+        }
+        None if rest.len() == 2 => {
+            let start = NaiveDate::from_ymd_opt(year, rest.parse().ok()?, 1)?;
+            (start, start.checked_add_months(Months::new(1))?)
+        }
+        _ => return None,
+    };
+    let to_nanos = |date: NaiveDate| {
+        u64::try_from(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp())
+            .ok()
+            .map(|secs| secs * NANOS_PER_SECOND)
+    };
+    Some((to_nanos(start)?, to_nanos(end)?))
+}
+
+// Function to aggregate the deliveries made in [starts_at, ends_at)
+fn summarize(starts_at: u64, ends_at: u64) -> ImpactSummary {
+    // Hub flows record one delivery per leg; keep the last leg of each post
+    let mut final_legs: BTreeMap<u64, SurplusRecord> = BTreeMap::new();
+    SURPLUS_RECORDS_STORAGE.with(|storage| {
+        for (_, record) in storage.borrow().iter() {
+            if (starts_at..ends_at).contains(&record.delivered_at) {
+                final_legs.insert(record.surplus_post_id, record);
+            }
+        }
+    });
+
+    let mut summary = ImpactSummary::default();
+    let mut receivers = Vec::new();
+    let mut reported_kg = 0;
+    let mut programs: BTreeMap<String, ProgramImpact> = BTreeMap::new();
+    for record in final_legs.into_values() {
+        let quantity_kg = SURPLUS_POSTS_STORAGE
+            .with(|storage| storage.borrow().get(&record.surplus_post_id))
+            .map(|post| post.quantity_kg as u64)
+            .unwrap_or_default();
+        summary.deliveries += 1;
+        summary.delivered_kg += quantity_kg;
+
+        if let Some(assignment) = delivering_assignment(&record) {
+            if !receivers.contains(&assignment.receiver_id) {
+                receivers.push(assignment.receiver_id);
+            }
+        }
+        if let Some(beneficiaries) = record.beneficiaries {
+            summary.reported_deliveries += 1;
+            summary.beneficiaries_served += beneficiaries as u64;
+            reported_kg += quantity_kg;
+        }
+        if let Some(meal_program) = record.meal_program {
+            let program = programs
+                .entry(meal_program.clone())
+                .or_insert_with(|| ProgramImpact {
+                    meal_program,
+                    ..Default::default()
+                });
+            program.deliveries += 1;
+            program.delivered_kg += quantity_kg;
+            program.beneficiaries += record.beneficiaries.unwrap_or_default() as u64;
+        }
+    }
+
+    summary.receivers_served = receivers.len() as u64;
+    summary.kg_per_beneficiary = (summary.beneficiaries_served > 0)
+        .then(|| reported_kg as f64 / summary.beneficiaries_served as f64);
+    summary.programs = programs.into_values().collect();
+    summary
+}
+
+// Function to find the unconfirmed record of a delivery finalized before
+// receivers had to confirm it
+fn unconfirmed_record(assignment: &Assignment) -> Option<SurplusRecord> {
+    record_for_assignment(assignment.id).filter(|record| record.confirmed_at.is_none())
+}
+
+// Function for the receiver to confirm a delivery the driver submitted, within
+// the confirmation window, which records it as a surplus record. The receiver
+// can report how many people it fed, which meal program it went to and how it
+// rates the delivery. A delivery is confirmed once.
+#[ic_cdk::update]
+async fn confirm_delivery(
+    assignment_id: u64,
+    payload: DeliveryConfirmationPayload,
+) -> Result<SurplusRecord, FoodShareError> {
+    start_trace("confirm_delivery");
+    authorize(EndpointClass::Logistics).await?;
+
+    let assignment = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })?;
+    if !is_receiver_owner(&assignment) && !is_governance_or_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the receiver or governance can confirm this delivery".to_string(),
+        });
+    }
+
+    if payload
+        .beneficiaries
+        .is_some_and(|count| count == 0 || count > MAX_BENEFICIARIES)
+    {
+        return Err(FoodShareError::Validation {
+            field: "beneficiaries".to_string(),
+            reason: format!("Beneficiaries must be between 1 and {}", MAX_BENEFICIARIES),
+        });
+    }
+    if let Some(rating) = payload.rating {
+        validate_rating(rating)?;
+    }
+    let meal_program = payload
+        .meal_program
+        .as_deref()
+        .map(normalize_meal_program)
+        .transpose()?;
+
+    let mut record = match assignment.status {
+        AssignmentStatus::AwaitingConfirmation => confirm_submitted_delivery(assignment)?,
+        AssignmentStatus::Delivered => {
+            unconfirmed_record(&assignment).ok_or(FoodShareError::Conflict {
+                msg: "Delivery has already been confirmed".to_string(),
+            })?
+        }
+        status => {
+            return Err(FoodShareError::Conflict {
+                msg: format!("A {:?} assignment has no delivery to confirm", status),
+            })
+        }
+    };
+    let record_id = record.id;
+
+    let before = record.clone();
+    record.confirmed_at = Some(time());
+    record.beneficiaries = payload.beneficiaries;
+    record.meal_program = meal_program;
+    record.rating = payload.rating;
+    store_record(&record);
+    audit(
+        AuditAction::Updated,
+        AuditEntity::SurplusRecord,
+        record_id,
+        Some(&before),
+        Some(&record),
+    );
+    record_rating(&record);
+
+    record_event(
+        EntityType::SurplusRecord,
+        record_id,
+        Some(assignment_id),
+        EventKind::Updated,
+        match record.beneficiaries {
+            Some(count) => format!("Receiver confirmed the delivery fed {} people", count),
+            None => "Receiver confirmed the delivery".to_string(),
+        },
+    );
+    log(
+        LogLevel::Info,
+        format!("Confirmed delivery of surplus record {}", record_id),
+    );
+
+    Ok(record)
+}
+
+// Function to get the deliveries, kilograms and people served between two timestamps
+#[ic_cdk::query]
+fn get_impact_summary(starts_at: u64, ends_at: u64) -> Result<ImpactSummary, FoodShareError> {
+    if starts_at >= ends_at {
+        return Err(FoodShareError::Validation {
+            field: "ends_at".to_string(),
+            reason: "End must be after start".to_string(),
+        });
+    }
+    Ok(summarize(starts_at, ends_at))
+}
+
+// Function to get the impact figures for a grant report covering a week or month
+#[ic_cdk::query]
+fn get_grant_report(period: String) -> Result<GrantReport, FoodShareError> {
+    let (starts_at, ends_at) = period_bounds(&period).ok_or(FoodShareError::Validation {
+        field: "period".to_string(),
+        reason: "Period must be formatted as YYYY-Www or YYYY-MM".to_string(),
+    })?;
+
+    Ok(GrantReport {
+        impact: summarize(starts_at, ends_at),
+        period,
+        starts_at,
+        ends_at,
+    })
+}
@@ -0,0 +1,409 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::contact_vault::redacted;
+use crate::governance::require_admin;
+use crate::indexes::posts_for_donor;
+use crate::maintenance::{scan_batch, ScanStep};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    Assignment, DonorProfile, DriverProfile, FoodRequest, FoodShareError, Memory, ReceiverProfile,
+    StorablePrincipal, SurplusPost, ASSIGNMENTS_STORAGE, DONORS_STORAGE, DRIVERS_STORAGE,
+    FOOD_REQUESTS_STORAGE, MEMORY_MANAGER, RECEIVERS_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const SESSION_TTL_SECS: u64 = 30 * 60;
+const MAX_CONSENT_SECS: u64 = 7 * 24 * 60 * 60;
+const MAX_TICKET_REFERENCE_LEN: usize = 64;
+
+// ImpersonationBasis is a custom enum type that is used to record why support may view a profile
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum ImpersonationBasis {
+    UserConsent,
+    SupportTicket(String),
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ImpersonationSession {
+    admin: Principal,
+    profile_id: u64,
+    basis: ImpersonationBasis,
+    started_at: u64,
+    expires_at: u64,
+    calls: u64,
+}
+
+impl Storable for ImpersonationSession {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ImpersonationSession {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// ImpersonatedProfile is the profile being viewed, whichever role it belongs to
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum ImpersonatedProfile {
+    Donor(DonorProfile),
+    Receiver(ReceiverProfile),
+    Driver(DriverProfile),
+}
+
+// ImpersonatedView is the read-only picture of what the profile's user sees
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ImpersonatedView {
+    // Always true; clients must show an impersonation banner when it is set
+    impersonating: bool,
+    session: ImpersonationSession,
+    profile: ImpersonatedProfile,
+    surplus_posts: Vec<SurplusPost>,
+    assignments: Vec<Assignment>,
+    food_requests: Vec<FoodRequest>,
+}
+
+thread_local! {
+    // Profile id -> time until which the user allows support to view their profile
+    static SUPPORT_CONSENTS: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+    ));
+
+    // At most one open impersonation session per admin
+    static IMPERSONATION_SESSIONS: RefCell<StableBTreeMap<StorablePrincipal, ImpersonationSession, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+    ));
+}
+
+// Function to drop the expired sessions in one batch of the session table
+pub(crate) fn prune_expired_sessions(cursor: Option<&[u8]>) -> ScanStep {
+    let (sessions, next_cursor) =
+        IMPERSONATION_SESSIONS.with(|storage| scan_batch(&storage.borrow(), cursor));
+    let now = time();
+    let expired: Vec<StorablePrincipal> = sessions
+        .iter()
+        .filter(|(_, session)| session.expires_at <= now)
+        .map(|(admin, _)| *admin)
+        .collect();
+    IMPERSONATION_SESSIONS.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for admin in &expired {
+            storage.remove(admin);
+        }
+    });
+    ScanStep {
+        next_cursor,
+        scanned: sessions.len() as u64,
+        removed: expired.len() as u64,
+        ..Default::default()
+    }
+}
+
+// Contact details stay sealed; an impersonating admin reads them through the logged contact reads
+pub(crate) fn find_profile(profile_id: u64) -> Option<ImpersonatedProfile> {
+    DONORS_STORAGE
+        .with(|storage| storage.borrow().get(&profile_id))
+        .map(|donor| ImpersonatedProfile::Donor(redacted(donor)))
+        .or_else(|| {
+            RECEIVERS_STORAGE
+                .with(|storage| storage.borrow().get(&profile_id))
+                .map(|receiver| ImpersonatedProfile::Receiver(redacted(receiver)))
+        })
+        .or_else(|| {
+            DRIVERS_STORAGE
+                .with(|storage| storage.borrow().get(&profile_id))
+                .map(|driver| ImpersonatedProfile::Driver(redacted(driver)))
+        })
+}
+
+impl ImpersonatedProfile {
+    pub(crate) fn owner(&self) -> Option<Principal> {
+        match self {
+            ImpersonatedProfile::Donor(donor) => donor.owner,
+            ImpersonatedProfile::Receiver(receiver) => receiver.owner,
+            ImpersonatedProfile::Driver(driver) => driver.owner,
+        }
+    }
+}
+
+// Function to check that the caller owns the profile they grant or revoke consent for
+fn require_profile_owner(profile_id: u64) -> Result<(), FoodShareError> {
+    let profile = find_profile(profile_id).ok_or(FoodShareError::NotFound {
+        msg: format!("Profile with id={} not found", profile_id),
+    })?;
+    if profile.owner() != Some(ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the profile owner can manage support consent".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn has_consent(profile_id: u64) -> bool {
+    SUPPORT_CONSENTS
+        .with(|storage| storage.borrow().get(&profile_id))
+        .is_some_and(|expires_at| expires_at > time())
+}
+
+// Function for a user to let support view their profile for a limited time
+#[ic_cdk::update]
+fn grant_support_consent(profile_id: u64, duration_secs: u64) -> Result<u64, FoodShareError> {
+    start_trace("grant_support_consent");
+    require_profile_owner(profile_id)?;
+    if duration_secs == 0 || duration_secs > MAX_CONSENT_SECS {
+        return Err(FoodShareError::Validation {
+            field: "duration_secs".to_string(),
+            reason: format!(
+                "Consent duration must be between 1 and {} seconds",
+                MAX_CONSENT_SECS
+            ),
+        });
+    }
+
+    let expires_at = time() + duration_secs * NANOS_PER_SECOND;
+    let before =
+        SUPPORT_CONSENTS.with(|storage| storage.borrow_mut().insert(profile_id, expires_at));
+    audit(
+        AuditAction::Created,
+        AuditEntity::SupportConsent,
+        profile_id,
+        before.as_ref(),
+        Some(&expires_at),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Profile {} granted support consent", profile_id),
+    );
+
+    Ok(expires_at)
+}
+
+// Function for a user to withdraw support consent before it expires
+#[ic_cdk::update]
+fn revoke_support_consent(profile_id: u64) -> Result<(), FoodShareError> {
+    start_trace("revoke_support_consent");
+    require_profile_owner(profile_id)?;
+
+    let before = SUPPORT_CONSENTS
+        .with(|storage| storage.borrow_mut().remove(&profile_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("No support consent for profile with id={}", profile_id),
+        })?;
+    audit(
+        AuditAction::Revoked,
+        AuditEntity::SupportConsent,
+        profile_id,
+        Some(&before),
+        None,
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Profile {} revoked support consent", profile_id),
+    );
+
+    Ok(())
+}
+
+// Function for support staff to open a read-only impersonation session.
+// Requires either the user's consent or a support ticket reference.
+#[ic_cdk::update]
+fn impersonate(
+    profile_id: u64,
+    ticket_reference: Option<String>,
+) -> Result<ImpersonationSession, FoodShareError> {
+    start_trace("impersonate");
+    require_admin("impersonate profiles")?;
+
+    if find_profile(profile_id).is_none() {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Profile with id={} not found", profile_id),
+        });
+    }
+
+    let basis = match ticket_reference.map(|ticket| ticket.trim().to_string()) {
+        Some(ticket) if ticket.is_empty() || ticket.len() > MAX_TICKET_REFERENCE_LEN => {
+            return Err(FoodShareError::Validation {
+                field: "ticket_reference".to_string(),
+                reason: format!(
+                    "Ticket reference must be between 1 and {} bytes",
+                    MAX_TICKET_REFERENCE_LEN
+                ),
+            });
+        }
+        Some(ticket) => ImpersonationBasis::SupportTicket(ticket),
+        None if has_consent(profile_id) => ImpersonationBasis::UserConsent,
+        None => {
+            return Err(FoodShareError::Unauthorized {
+                msg: "Impersonation requires user consent or a support ticket reference"
+                    .to_string(),
+            });
+        }
+    };
+
+    let admin = ic_cdk::caller();
+    let now = time();
+    let session = ImpersonationSession {
+        admin,
+        profile_id,
+        basis,
+        started_at: now,
+        expires_at: now + SESSION_TTL_SECS * NANOS_PER_SECOND,
+        calls: 0,
+    };
+    IMPERSONATION_SESSIONS.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(StorablePrincipal(admin), session.clone())
+    });
+    audit(
+        AuditAction::Created,
+        AuditEntity::ImpersonationSession,
+        profile_id,
+        None,
+        Some(&session),
+    );
+
+    log(
+        LogLevel::Warn,
+        format!(
+            "Admin {} started impersonating profile {} ({:?})",
+            admin.to_text(),
+            profile_id,
+            session.basis
+        ),
+    );
+
+    Ok(session)
+}
+
+// Function for support staff to close their impersonation session
+#[ic_cdk::update]
+fn end_impersonation() -> Result<(), FoodShareError> {
+    start_trace("end_impersonation");
+
+    let admin = ic_cdk::caller();
+    let session = IMPERSONATION_SESSIONS
+        .with(|storage| storage.borrow_mut().remove(&StorablePrincipal(admin)))
+        .ok_or(FoodShareError::NotFound {
+            msg: "No impersonation session is open".to_string(),
+        })?;
+    audit(
+        AuditAction::Revoked,
+        AuditEntity::ImpersonationSession,
+        session.profile_id,
+        Some(&session),
+        None,
+    );
+
+    log(
+        LogLevel::Warn,
+        format!(
+            "Admin {} stopped impersonating profile {} after {} calls",
+            admin.to_text(),
+            session.profile_id,
+            session.calls
+        ),
+    );
+
+    Ok(())
+}
+
+// Function to get what the impersonated user sees. This is an update call
+// so that every impersonated read is written to the audit log.
+#[ic_cdk::update]
+fn get_impersonated_view() -> Result<ImpersonatedView, FoodShareError> {
+    start_trace("get_impersonated_view");
+    require_admin("impersonate profiles")?;
+
+    let admin = ic_cdk::caller();
+    let key = StorablePrincipal(admin);
+    let mut session = IMPERSONATION_SESSIONS
+        .with(|storage| storage.borrow().get(&key))
+        .filter(|session| session.expires_at > time())
+        .ok_or(FoodShareError::Unauthorized {
+            msg: "No active impersonation session".to_string(),
+        })?;
+
+    // Consent can be withdrawn while a session is open
+    if session.basis == ImpersonationBasis::UserConsent && !has_consent(session.profile_id) {
+        IMPERSONATION_SESSIONS.with(|storage| storage.borrow_mut().remove(&key));
+        return Err(FoodShareError::Expired {
+            msg: "User consent for this impersonation has ended".to_string(),
+        });
+    }
+
+    let profile = find_profile(session.profile_id).ok_or(FoodShareError::NotFound {
+        msg: format!("Profile with id={} not found", session.profile_id),
+    })?;
+
+    let profile_id = session.profile_id;
+    let surplus_posts = match profile {
+        ImpersonatedProfile::Donor(_) => posts_for_donor(profile_id),
+        _ => Vec::new(),
+    };
+    let assignments = ASSIGNMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, assignment)| match profile {
+                ImpersonatedProfile::Donor(_) => surplus_posts
+                    .iter()
+                    .any(|post: &SurplusPost| post.id == assignment.surplus_post_id),
+                ImpersonatedProfile::Receiver(_) => assignment.receiver_id == profile_id,
+                ImpersonatedProfile::Driver(_) => assignment.driver_id == profile_id,
+            })
+            .map(|(_, assignment)| assignment)
+            .collect()
+    });
+    let food_requests = FOOD_REQUESTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, request)| request.receiver_id == profile_id)
+            .map(|(_, request)| request)
+            .collect()
+    });
+
+    let before = session.clone();
+    session.calls += 1;
+    IMPERSONATION_SESSIONS.with(|storage| storage.borrow_mut().insert(key, session.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::ImpersonationSession,
+        profile_id,
+        Some(&before),
+        Some(&session),
+    );
+
+    log(
+        LogLevel::Warn,
+        format!(
+            "Admin {} viewed profile {} while impersonating (call {})",
+            admin.to_text(),
+            profile_id,
+            session.calls
+        ),
+    );
+
+    Ok(ImpersonatedView {
+        impersonating: true,
+        session,
+        profile,
+        surplus_posts,
+        assignments,
+        food_requests,
+    })
+}
@@ -0,0 +1,694 @@
+use crate::assignment_messages::{AssignmentMessage, ASSIGNMENT_MESSAGES_STORAGE};
+use crate::browse::is_listable;
+use crate::events::{DomainEvent, EntityType, EventKind, EVENTS_STORAGE};
+use crate::maintenance::{scan_batch, ScanStep};
+use crate::reviews::{Review, REVIEWS_STORAGE};
+use crate::{
+    Assignment, AssignmentStatus, FoodType, Memory, SurplusPost, SurplusRecord,
+    ASSIGNMENTS_STORAGE, MEMORY_MANAGER, SURPLUS_POSTS_STORAGE, SURPLUS_RECORDS_STORAGE,
+};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::thread::LocalKey;
+
+// (key, entity id); entities map to at most one key per index
+type IdIndex = RefCell<StableBTreeMap<(u64, u64), (), Memory>>;
+type Primary<V> = RefCell<StableBTreeMap<u64, V, Memory>>;
+// None when the entity does not belong in the index
+type KeyFn<V> = fn(&V) -> Option<u64>;
+
+thread_local! {
+    // (surplus_post_id, assignment_id)
+    static ASSIGNMENTS_BY_POST: IdIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)))
+    ));
+
+    // (driver_id, assignment_id)
+    static ASSIGNMENTS_BY_DRIVER: IdIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)))
+    ));
+
+    // (receiver_id, assignment_id)
+    static ASSIGNMENTS_BY_RECEIVER: IdIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(131)))
+    ));
+
+    // (status discriminant, assignment_id)
+    static ASSIGNMENTS_BY_STATUS: IdIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(100)))
+    ));
+
+    // (donor_id, post_id)
+    static POSTS_BY_DONOR: IdIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(101)))
+    ));
+
+    // (food type discriminant, post_id), for open posts only
+    static OPEN_POSTS_BY_FOOD_TYPE: IdIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(102)))
+    ));
+
+    // (assignment_id, surplus_record_id)
+    static RECORDS_BY_ASSIGNMENT: IdIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(134)))
+    ));
+
+    // (assignment_id, event_id)
+    static EVENTS_BY_ASSIGNMENT: IdIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(120)))
+    ));
+
+    // (surplus_post_id, event_id), for events recorded against a post itself
+    static POST_EVENTS: IdIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(121)))
+    ));
+
+    // (assignment_id, message_id)
+    static MESSAGES_BY_ASSIGNMENT: IdIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(122)))
+    ));
+
+    // (assignment_id, review_id)
+    static REVIEWS_BY_ASSIGNMENT: IdIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(123)))
+    ));
+}
+
+// Discriminants are stable as long as new variants are only ever appended
+fn status_key(status: AssignmentStatus) -> u64 {
+    status as u64
+}
+
+fn food_type_key(food_type: FoodType) -> u64 {
+    food_type as u64
+}
+
+fn post_key(assignment: &Assignment) -> Option<u64> {
+    Some(assignment.surplus_post_id)
+}
+
+fn driver_key(assignment: &Assignment) -> Option<u64> {
+    Some(assignment.driver_id)
+}
+
+fn receiver_key(assignment: &Assignment) -> Option<u64> {
+    Some(assignment.receiver_id)
+}
+
+fn assignment_status_key(assignment: &Assignment) -> Option<u64> {
+    Some(status_key(assignment.status))
+}
+
+fn donor_key(post: &SurplusPost) -> Option<u64> {
+    post.donor_id.parse().ok()
+}
+
+fn open_food_type_key(post: &SurplusPost) -> Option<u64> {
+    is_listable(post).then(|| food_type_key(post.food_type))
+}
+
+fn record_assignment_key(record: &SurplusRecord) -> Option<u64> {
+    record.assignment_id
+}
+
+fn event_assignment_key(event: &DomainEvent) -> Option<u64> {
+    event.assignment_id
+}
+
+fn post_event_key(event: &DomainEvent) -> Option<u64> {
+    (event.entity_type == EntityType::SurplusPost && event.assignment_id.is_none())
+        .then_some(event.entity_id)
+}
+
+fn message_assignment_key(message: &AssignmentMessage) -> Option<u64> {
+    Some(message.assignment_id)
+}
+
+fn review_assignment_key(review: &Review) -> Option<u64> {
+    Some(review.assignment_id)
+}
+
+fn assignment_indexes() -> [(&'static LocalKey<IdIndex>, KeyFn<Assignment>); 4] {
+    [
+        (&ASSIGNMENTS_BY_POST, post_key),
+        (&ASSIGNMENTS_BY_DRIVER, driver_key),
+        (&ASSIGNMENTS_BY_RECEIVER, receiver_key),
+        (&ASSIGNMENTS_BY_STATUS, assignment_status_key),
+    ]
+}
+
+fn post_indexes() -> [(&'static LocalKey<IdIndex>, KeyFn<SurplusPost>); 2] {
+    [
+        (&POSTS_BY_DONOR, donor_key),
+        (&OPEN_POSTS_BY_FOOD_TYPE, open_food_type_key),
+    ]
+}
+
+fn event_indexes() -> [(&'static LocalKey<IdIndex>, KeyFn<DomainEvent>); 2] {
+    [
+        (&EVENTS_BY_ASSIGNMENT, event_assignment_key),
+        (&POST_EVENTS, post_event_key),
+    ]
+}
+
+// Function to move an entity's entry in one index from its previous key to its current one
+fn reindex<V>(
+    index: &'static LocalKey<IdIndex>,
+    key_of: KeyFn<V>,
+    id: u64,
+    previous: Option<&V>,
+    current: &V,
+) {
+    let old_key = previous.and_then(key_of);
+    let new_key = key_of(current);
+    if old_key == new_key {
+        return;
+    }
+    index.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(key) = old_key {
+            index.remove(&(key, id));
+        }
+        if let Some(key) = new_key {
+            index.insert((key, id), ());
+        }
+    });
+}
+
+// Function to store an assignment and update its index entries in the same call,
+// so the indexes never disagree with the stored assignment
+pub(crate) fn store_assignment(assignment: &Assignment) {
+    let previous = ASSIGNMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(assignment.id, assignment.clone())
+    });
+    for (index, key_of) in assignment_indexes() {
+        reindex(index, key_of, assignment.id, previous.as_ref(), assignment);
+    }
+}
+
+// Function to store a surplus post and update its index entries in the same call
+pub(crate) fn store_post(post: &SurplusPost) {
+    let previous =
+        SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow_mut().insert(post.id, post.clone()));
+    for (index, key_of) in post_indexes() {
+        reindex(index, key_of, post.id, previous.as_ref(), post);
+    }
+}
+
+// Function to store a surplus record and keep it indexed by the assignment that delivered it
+pub(crate) fn store_record(record: &SurplusRecord) {
+    let previous = SURPLUS_RECORDS_STORAGE
+        .with(|storage| storage.borrow_mut().insert(record.id, record.clone()));
+    reindex(
+        &RECORDS_BY_ASSIGNMENT,
+        record_assignment_key,
+        record.id,
+        previous.as_ref(),
+        record,
+    );
+}
+
+// Function to store an event and add its index entries. Events never change
+// once recorded, so they are only ever indexed once.
+pub(crate) fn store_event(event: &DomainEvent) {
+    EVENTS_STORAGE.with(|storage| storage.borrow_mut().insert(event.id, event.clone()));
+    for (index, key_of) in event_indexes() {
+        reindex(index, key_of, event.id, None, event);
+    }
+}
+
+// Function to store a thread message and keep it indexed by its assignment
+pub(crate) fn store_message(message: &AssignmentMessage) {
+    let previous = ASSIGNMENT_MESSAGES_STORAGE
+        .with(|storage| storage.borrow_mut().insert(message.id, message.clone()));
+    reindex(
+        &MESSAGES_BY_ASSIGNMENT,
+        message_assignment_key,
+        message.id,
+        previous.as_ref(),
+        message,
+    );
+}
+
+// Function to store a review and keep it indexed by its assignment
+pub(crate) fn store_review(review: &Review) {
+    let previous =
+        REVIEWS_STORAGE.with(|storage| storage.borrow_mut().insert(review.id, review.clone()));
+    reindex(
+        &REVIEWS_BY_ASSIGNMENT,
+        review_assignment_key,
+        review.id,
+        previous.as_ref(),
+        review,
+    );
+}
+
+fn index_ids(index: &'static LocalKey<IdIndex>, key: u64) -> Vec<u64> {
+    index.with(|index| {
+        index
+            .borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    })
+}
+
+fn load<V: BoundedStorable>(primary: &'static LocalKey<Primary<V>>, ids: &[u64]) -> Vec<V> {
+    primary.with(|storage| {
+        let storage = storage.borrow();
+        ids.iter().filter_map(|id| storage.get(id)).collect()
+    })
+}
+
+// Function to get every assignment, including onward legs, covering a surplus post
+pub(crate) fn assignments_for_post(post_id: u64) -> Vec<Assignment> {
+    load(
+        &ASSIGNMENTS_STORAGE,
+        &index_ids(&ASSIGNMENTS_BY_POST, post_id),
+    )
+}
+
+// Function to get every assignment given to a driver
+pub(crate) fn assignments_for_driver(driver_id: u64) -> Vec<Assignment> {
+    load(
+        &ASSIGNMENTS_STORAGE,
+        &index_ids(&ASSIGNMENTS_BY_DRIVER, driver_id),
+    )
+}
+
+// Function to get every assignment delivering to a receiver
+pub(crate) fn assignments_for_receiver(receiver_id: u64) -> Vec<Assignment> {
+    load(
+        &ASSIGNMENTS_STORAGE,
+        &index_ids(&ASSIGNMENTS_BY_RECEIVER, receiver_id),
+    )
+}
+
+// Function to get the surplus record of a delivered assignment
+pub(crate) fn record_for_assignment(assignment_id: u64) -> Option<SurplusRecord> {
+    load(
+        &SURPLUS_RECORDS_STORAGE,
+        &index_ids(&RECORDS_BY_ASSIGNMENT, assignment_id),
+    )
+    .into_iter()
+    .next()
+}
+
+// Function to get every assignment currently in the given status, in id order
+pub(crate) fn assignments_with_status(status: AssignmentStatus) -> Vec<Assignment> {
+    load(
+        &ASSIGNMENTS_STORAGE,
+        &index_ids(&ASSIGNMENTS_BY_STATUS, status_key(status)),
+    )
+}
+
+// Function to get every post a donor has made, in id order
+pub(crate) fn posts_for_donor(donor_id: u64) -> Vec<SurplusPost> {
+    load(
+        &SURPLUS_POSTS_STORAGE,
+        &index_ids(&POSTS_BY_DONOR, donor_id),
+    )
+}
+
+// Function to get the ids of the open posts of one food type, in id order
+pub(crate) fn open_post_ids_for_food_type(food_type: FoodType) -> Vec<u64> {
+    index_ids(&OPEN_POSTS_BY_FOOD_TYPE, food_type_key(food_type))
+}
+
+// Function to get the events linked to an assignment, in id order
+pub(crate) fn events_for_assignment(assignment_id: u64) -> Vec<DomainEvent> {
+    load(
+        &EVENTS_STORAGE,
+        &index_ids(&EVENTS_BY_ASSIGNMENT, assignment_id),
+    )
+}
+
+// Function to get the events recorded against a post itself, in id order
+pub(crate) fn events_for_post(post_id: u64) -> Vec<DomainEvent> {
+    load(&EVENTS_STORAGE, &index_ids(&POST_EVENTS, post_id))
+}
+
+// Function to get the messages in the thread of an assignment, oldest first
+pub(crate) fn messages_for_assignment(assignment_id: u64) -> Vec<AssignmentMessage> {
+    load(
+        &ASSIGNMENT_MESSAGES_STORAGE,
+        &index_ids(&MESSAGES_BY_ASSIGNMENT, assignment_id),
+    )
+}
+
+// Function to get up to `limit` messages in the thread of an assignment that
+// came after the given message, oldest first; from the start when there is none
+pub(crate) fn messages_after(
+    assignment_id: u64,
+    after: Option<u64>,
+    limit: usize,
+) -> Vec<AssignmentMessage> {
+    let Some(first) = after.map_or(Some(0), |after| after.checked_add(1)) else {
+        return Vec::new();
+    };
+    let ids: Vec<u64> = MESSAGES_BY_ASSIGNMENT.with(|index| {
+        index
+            .borrow()
+            .range((assignment_id, first)..=(assignment_id, u64::MAX))
+            .take(limit)
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    load(&ASSIGNMENT_MESSAGES_STORAGE, &ids)
+}
+
+// Function to get the reviews left for an assignment, oldest first
+pub(crate) fn reviews_for_assignment(assignment_id: u64) -> Vec<Review> {
+    load(
+        &REVIEWS_STORAGE,
+        &index_ids(&REVIEWS_BY_ASSIGNMENT, assignment_id),
+    )
+}
+
+// Function to add index entries missing for one batch of the primary collection
+fn fill_index<V: BoundedStorable>(
+    primary: &'static LocalKey<Primary<V>>,
+    index: &'static LocalKey<IdIndex>,
+    key_of: KeyFn<V>,
+    cursor: Option<&[u8]>,
+) -> ScanStep {
+    let (entities, next_cursor) = primary.with(|storage| scan_batch(&storage.borrow(), cursor));
+    let mut step = ScanStep {
+        next_cursor,
+        scanned: entities.len() as u64,
+        ..Default::default()
+    };
+    index.with(|index| {
+        let mut index = index.borrow_mut();
+        for (id, entity) in &entities {
+            let Some(key) = key_of(entity) else {
+                continue;
+            };
+            if index.insert((key, *id), ()).is_none() {
+                step.inserted += 1;
+            }
+        }
+    });
+    step
+}
+
+// Function to drop index entries in one batch that no longer match their entity
+fn prune_index<V: BoundedStorable>(
+    primary: &'static LocalKey<Primary<V>>,
+    index: &'static LocalKey<IdIndex>,
+    key_of: KeyFn<V>,
+    cursor: Option<&[u8]>,
+) -> ScanStep {
+    let (entries, next_cursor) = index.with(|index| scan_batch(&index.borrow(), cursor));
+    let scanned = entries.len() as u64;
+    let dangling: Vec<(u64, u64)> = primary.with(|storage| {
+        let storage = storage.borrow();
+        entries
+            .into_iter()
+            .map(|(entry, _)| entry)
+            .filter(|(key, id)| {
+                storage
+                    .get(id)
+                    .is_none_or(|entity| key_of(&entity) != Some(*key))
+            })
+            .collect()
+    });
+    index.with(|index| {
+        let mut index = index.borrow_mut();
+        for entry in &dangling {
+            index.remove(entry);
+        }
+    });
+    ScanStep {
+        next_cursor,
+        scanned,
+        removed: dangling.len() as u64,
+        ..Default::default()
+    }
+}
+
+pub(crate) fn fill_event_assignment_index(cursor: Option<&[u8]>) -> ScanStep {
+    fill_index(
+        &EVENTS_STORAGE,
+        &EVENTS_BY_ASSIGNMENT,
+        event_assignment_key,
+        cursor,
+    )
+}
+
+pub(crate) fn prune_event_assignment_index(cursor: Option<&[u8]>) -> ScanStep {
+    prune_index(
+        &EVENTS_STORAGE,
+        &EVENTS_BY_ASSIGNMENT,
+        event_assignment_key,
+        cursor,
+    )
+}
+
+pub(crate) fn fill_post_event_index(cursor: Option<&[u8]>) -> ScanStep {
+    fill_index(&EVENTS_STORAGE, &POST_EVENTS, post_event_key, cursor)
+}
+
+pub(crate) fn prune_post_event_index(cursor: Option<&[u8]>) -> ScanStep {
+    prune_index(&EVENTS_STORAGE, &POST_EVENTS, post_event_key, cursor)
+}
+
+pub(crate) fn fill_message_index(cursor: Option<&[u8]>) -> ScanStep {
+    fill_index(
+        &ASSIGNMENT_MESSAGES_STORAGE,
+        &MESSAGES_BY_ASSIGNMENT,
+        message_assignment_key,
+        cursor,
+    )
+}
+
+pub(crate) fn prune_message_index(cursor: Option<&[u8]>) -> ScanStep {
+    prune_index(
+        &ASSIGNMENT_MESSAGES_STORAGE,
+        &MESSAGES_BY_ASSIGNMENT,
+        message_assignment_key,
+        cursor,
+    )
+}
+
+pub(crate) fn fill_review_index(cursor: Option<&[u8]>) -> ScanStep {
+    fill_index(
+        &REVIEWS_STORAGE,
+        &REVIEWS_BY_ASSIGNMENT,
+        review_assignment_key,
+        cursor,
+    )
+}
+
+pub(crate) fn prune_review_index(cursor: Option<&[u8]>) -> ScanStep {
+    prune_index(
+        &REVIEWS_STORAGE,
+        &REVIEWS_BY_ASSIGNMENT,
+        review_assignment_key,
+        cursor,
+    )
+}
+
+// Function to build an index from scratch while it is still empty
+fn build_if_empty<V: BoundedStorable>(
+    primary: &'static LocalKey<Primary<V>>,
+    index: &'static LocalKey<IdIndex>,
+    key_of: KeyFn<V>,
+) {
+    if !index.with(|index| index.borrow().is_empty()) {
+        return;
+    }
+    let mut cursor = None;
+    loop {
+        let step = fill_index(primary, index, key_of, cursor.as_deref());
+        match step.next_cursor {
+            Some(next_cursor) => cursor = Some(next_cursor),
+            None => break,
+        }
+    }
+}
+
+// Function to build the indexes for entities created before they existed.
+// Runs on every upgrade but only does work for indexes that are empty.
+pub(crate) fn migrate_indexes() {
+    for (index, key_of) in assignment_indexes() {
+        build_if_empty(&ASSIGNMENTS_STORAGE, index, key_of);
+    }
+    for (index, key_of) in post_indexes() {
+        build_if_empty(&SURPLUS_POSTS_STORAGE, index, key_of);
+    }
+    for (index, key_of) in event_indexes() {
+        build_if_empty(&EVENTS_STORAGE, index, key_of);
+    }
+    build_if_empty(
+        &ASSIGNMENT_MESSAGES_STORAGE,
+        &MESSAGES_BY_ASSIGNMENT,
+        message_assignment_key,
+    );
+    build_if_empty(
+        &REVIEWS_STORAGE,
+        &REVIEWS_BY_ASSIGNMENT,
+        review_assignment_key,
+    );
+    if RECORDS_BY_ASSIGNMENT.with(|index| index.borrow().is_empty()) {
+        link_records_to_assignments();
+        build_if_empty(
+            &SURPLUS_RECORDS_STORAGE,
+            &RECORDS_BY_ASSIGNMENT,
+            record_assignment_key,
+        );
+    }
+}
+
+// Function to set the assignment of surplus records stored before records
+// kept it, from the delivery event recorded with each of them
+fn link_records_to_assignments() {
+    let delivered_by: BTreeMap<u64, u64> = EVENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, event)| {
+                event.entity_type == EntityType::SurplusRecord && event.kind == EventKind::Delivered
+            })
+            .filter_map(|(_, event)| Some((event.entity_id, event.assignment_id?)))
+            .collect()
+    });
+    let unlinked: Vec<SurplusRecord> = SURPLUS_RECORDS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, record)| record)
+            .filter(|record| record.assignment_id.is_none())
+            .collect()
+    });
+    SURPLUS_RECORDS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for mut record in unlinked {
+            if let Some(assignment_id) = delivered_by.get(&record.id) {
+                record.assignment_id = Some(*assignment_id);
+                storage.insert(record.id, record);
+            }
+        }
+    });
+}
+
+pub(crate) fn fill_post_index(cursor: Option<&[u8]>) -> ScanStep {
+    fill_index(&ASSIGNMENTS_STORAGE, &ASSIGNMENTS_BY_POST, post_key, cursor)
+}
+
+pub(crate) fn prune_post_index(cursor: Option<&[u8]>) -> ScanStep {
+    prune_index(&ASSIGNMENTS_STORAGE, &ASSIGNMENTS_BY_POST, post_key, cursor)
+}
+
+pub(crate) fn fill_driver_index(cursor: Option<&[u8]>) -> ScanStep {
+    fill_index(
+        &ASSIGNMENTS_STORAGE,
+        &ASSIGNMENTS_BY_DRIVER,
+        driver_key,
+        cursor,
+    )
+}
+
+pub(crate) fn prune_driver_index(cursor: Option<&[u8]>) -> ScanStep {
+    prune_index(
+        &ASSIGNMENTS_STORAGE,
+        &ASSIGNMENTS_BY_DRIVER,
+        driver_key,
+        cursor,
+    )
+}
+
+pub(crate) fn fill_receiver_index(cursor: Option<&[u8]>) -> ScanStep {
+    fill_index(
+        &ASSIGNMENTS_STORAGE,
+        &ASSIGNMENTS_BY_RECEIVER,
+        receiver_key,
+        cursor,
+    )
+}
+
+pub(crate) fn prune_receiver_index(cursor: Option<&[u8]>) -> ScanStep {
+    prune_index(
+        &ASSIGNMENTS_STORAGE,
+        &ASSIGNMENTS_BY_RECEIVER,
+        receiver_key,
+        cursor,
+    )
+}
+
+pub(crate) fn fill_record_index(cursor: Option<&[u8]>) -> ScanStep {
+    fill_index(
+        &SURPLUS_RECORDS_STORAGE,
+        &RECORDS_BY_ASSIGNMENT,
+        record_assignment_key,
+        cursor,
+    )
+}
+
+pub(crate) fn prune_record_index(cursor: Option<&[u8]>) -> ScanStep {
+    prune_index(
+        &SURPLUS_RECORDS_STORAGE,
+        &RECORDS_BY_ASSIGNMENT,
+        record_assignment_key,
+        cursor,
+    )
+}
+
+pub(crate) fn fill_status_index(cursor: Option<&[u8]>) -> ScanStep {
+    fill_index(
+        &ASSIGNMENTS_STORAGE,
+        &ASSIGNMENTS_BY_STATUS,
+        assignment_status_key,
+        cursor,
+    )
+}
+
+pub(crate) fn prune_status_index(cursor: Option<&[u8]>) -> ScanStep {
+    prune_index(
+        &ASSIGNMENTS_STORAGE,
+        &ASSIGNMENTS_BY_STATUS,
+        assignment_status_key,
+        cursor,
+    )
+}
+
+pub(crate) fn fill_donor_index(cursor: Option<&[u8]>) -> ScanStep {
+    fill_index(&SURPLUS_POSTS_STORAGE, &POSTS_BY_DONOR, donor_key, cursor)
+}
+
+pub(crate) fn prune_donor_index(cursor: Option<&[u8]>) -> ScanStep {
+    prune_index(&SURPLUS_POSTS_STORAGE, &POSTS_BY_DONOR, donor_key, cursor)
+}
+
+pub(crate) fn fill_food_type_index(cursor: Option<&[u8]>) -> ScanStep {
+    fill_index(
+        &SURPLUS_POSTS_STORAGE,
+        &OPEN_POSTS_BY_FOOD_TYPE,
+        open_food_type_key,
+        cursor,
+    )
+}
+
+pub(crate) fn prune_food_type_index(cursor: Option<&[u8]>) -> ScanStep {
+    prune_index(
+        &SURPLUS_POSTS_STORAGE,
+        &OPEN_POSTS_BY_FOOD_TYPE,
+        open_food_type_key,
+        cursor,
+    )
+}
@@ -0,0 +1,183 @@
+use crate::assignment_lifecycle::{is_driver_owner, store_transition};
+use crate::claims::{approved_claims, approved_receiver, is_open_post, load_claim, ClaimStatus};
+use crate::geo::{haversine_km, validate_search};
+use crate::governance::{authorize, EndpointClass};
+use crate::indexes::assignments_for_post;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::regions::GeoPoint;
+use crate::reputation::{job_cooldown_ends_at, record_job_release};
+use crate::reservations::{record_reservation_outcome, release_post, ReservationOutcome};
+use crate::{
+    assign_post, Assignment, AssignmentPayload, AssignmentStatus, FoodShareError, FoodType,
+    ASSIGNMENTS_STORAGE, DRIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use ic_cdk::api::time;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Job {
+    // The approved claim the job delivers
+    job_id: u64,
+    surplus_post_id: u64,
+    receiver_id: u64,
+    food_type: FoodType,
+    quantity_kg: u32,
+    best_before_date: String,
+    distance_km: f64,
+    // Pass back to accept_job; it changes whenever the job is taken or released
+    version: u64,
+}
+
+// Function to get the version of a job, which moves on every time a driver
+// takes the post, so a stale listing cannot be accepted
+fn job_version(post_id: u64) -> u64 {
+    assignments_for_post(post_id).len() as u64
+}
+
+// Function for drivers to list the approved claims still waiting for a driver
+// within a radius and weight limit, nearest first; posts without coordinates
+// are left out
+#[ic_cdk::query]
+fn get_available_jobs(
+    near: GeoPoint,
+    radius_km: f64,
+    max_weight_kg: u32,
+) -> Result<Vec<Job>, FoodShareError> {
+    validate_search(near.latitude, near.longitude, radius_km)?;
+
+    let mut jobs: Vec<Job> = approved_claims()
+        .into_iter()
+        .filter_map(|claim| {
+            let post =
+                SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&claim.post_id))?;
+            if !is_open_post(&post) || post.quantity_kg > max_weight_kg {
+                return None;
+            }
+            let distance_km = haversine_km(
+                near.latitude,
+                near.longitude,
+                post.latitude?,
+                post.longitude?,
+            );
+            (distance_km <= radius_km).then(|| Job {
+                job_id: claim.id,
+                surplus_post_id: post.id,
+                receiver_id: claim.receiver_id,
+                food_type: post.food_type,
+                quantity_kg: post.quantity_kg,
+                best_before_date: post.best_before_date,
+                distance_km,
+                version: job_version(post.id),
+            })
+        })
+        .collect();
+    jobs.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+
+    Ok(jobs)
+}
+
+// Function for a driver to take a job from the marketplace. The version must
+// match the one listed, so when two drivers go for the same job only the first
+// gets it and the second is told to refresh.
+#[ic_cdk::update]
+async fn accept_job(job_id: u64, version: u64) -> Result<Assignment, FoodShareError> {
+    start_trace("accept_job");
+    authorize(EndpointClass::Logistics).await?;
+
+    let claim = load_claim(job_id)
+        .ok()
+        .filter(|claim| claim.status == ClaimStatus::Approved)
+        .ok_or(FoodShareError::NotFound {
+            msg: "Job does not exist".to_string(),
+        })?;
+
+    let caller = ic_cdk::caller();
+    let driver = DRIVERS_STORAGE
+        .with(|storage| {
+            storage
+                .borrow()
+                .iter()
+                .map(|(_, driver)| driver)
+                .find(|driver| driver.active && driver.owner == Some(caller))
+        })
+        .ok_or(FoodShareError::Unauthorized {
+            msg: "Only drivers can take jobs".to_string(),
+        })?;
+    if let Some(ends_at) = job_cooldown_ends_at(driver.id).filter(|ends_at| *ends_at > time()) {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Driver released a job recently and can take another after {}",
+                ends_at
+            ),
+        });
+    }
+    if job_version(claim.post_id) != version {
+        return Err(FoodShareError::Conflict {
+            msg: "Job changed since it was listed; refresh the available jobs".to_string(),
+        });
+    }
+
+    let assignment = assign_post(AssignmentPayload {
+        receiver_id: claim.receiver_id,
+        surplus_post_id: claim.post_id,
+        driver_id: driver.id,
+        reservation_id: None,
+    })?;
+    let assignment = store_transition(
+        assignment,
+        AssignmentStatus::Accepted,
+        "Driver took the job from the marketplace".to_string(),
+    )?;
+
+    log(
+        LogLevel::Info,
+        format!("Driver {} took job {}", driver.id, job_id),
+    );
+
+    Ok(assignment)
+}
+
+// Function for a driver to hand a marketplace job back before pickup; the post
+// returns to the marketplace and the driver waits out a cooldown before taking
+// another
+#[ic_cdk::update]
+async fn release_job(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    start_trace("release_job");
+    authorize(EndpointClass::Logistics).await?;
+
+    let assignment = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })?;
+    if !is_driver_owner(&assignment) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the assigned driver can release this job".to_string(),
+        });
+    }
+    let is_job = assignment.previous_leg_id.is_none()
+        && approved_receiver(assignment.surplus_post_id) == Some(assignment.receiver_id);
+    if !is_job {
+        return Err(FoodShareError::Conflict {
+            msg: "Assignment was not taken from the marketplace".to_string(),
+        });
+    }
+
+    let assignment = store_transition(
+        assignment,
+        AssignmentStatus::Cancelled,
+        "Driver released the job back to the marketplace".to_string(),
+    )?;
+    release_post(&assignment);
+    record_reservation_outcome(&assignment, ReservationOutcome::Cancelled);
+    record_job_release(assignment.driver_id, time());
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Driver {} released assignment {}",
+            assignment.driver_id, assignment.id
+        ),
+    );
+
+    Ok(assignment)
+}
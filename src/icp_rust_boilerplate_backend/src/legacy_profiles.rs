@@ -0,0 +1,73 @@
+use crate::{
+    DonorProfile, DriverProfile, Memory, ReceiverProfile, DONORS_STORAGE, DRIVERS_STORAGE,
+    MEMORY_MANAGER, RECEIVERS_STORAGE,
+};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::thread::LocalKey;
+
+// A profile in the collections created when profiles were capped at 512
+// bytes. A stable map cannot raise the size of its values in place, so the
+// profiles are moved to new collections instead.
+struct LegacyProfile<T>(T);
+
+impl<T: Storable> Storable for LegacyProfile<T> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        self.0.to_bytes()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        LegacyProfile(T::from_bytes(bytes))
+    }
+}
+
+impl<T: Storable> BoundedStorable for LegacyProfile<T> {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+type Legacy<T> = RefCell<StableBTreeMap<u64, LegacyProfile<T>, Memory>>;
+type Current<T> = RefCell<StableBTreeMap<u64, T, Memory>>;
+
+thread_local! {
+    static LEGACY_DONORS: Legacy<DonorProfile> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
+    ));
+
+    static LEGACY_RECEIVERS: Legacy<ReceiverProfile> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
+    ));
+
+    static LEGACY_DRIVERS: Legacy<DriverProfile> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+    ));
+}
+
+// Function to move every profile left in a legacy collection to its current one
+fn move_profiles<T: BoundedStorable>(
+    legacy: &'static LocalKey<Legacy<T>>,
+    current: &'static LocalKey<Current<T>>,
+) {
+    let ids: Vec<u64> = legacy.with(|storage| storage.borrow().iter().map(|(id, _)| id).collect());
+    for id in ids {
+        let Some(LegacyProfile(profile)) = legacy.with(|storage| storage.borrow_mut().remove(&id))
+        else {
+            continue;
+        };
+        current.with(|storage| storage.borrow_mut().insert(id, profile));
+    }
+}
+
+// Function to move profiles stored before the larger profile collections
+// existed. Runs on every upgrade but only does work while the legacy
+// collections still hold profiles.
+pub(crate) fn migrate_profiles() {
+    move_profiles(&LEGACY_DONORS, &DONORS_STORAGE);
+    move_profiles(&LEGACY_RECEIVERS, &RECEIVERS_STORAGE);
+    move_profiles(&LEGACY_DRIVERS, &DRIVERS_STORAGE);
+}
@@ -1,13 +1,208 @@
 #[macro_use]
 extern crate serde;
-use candid::{Decode, Encode};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::management_canister::http_request::{
+    HttpResponse as OutcallResponse, TransformArgs,
+};
 use ic_cdk::api::time;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
+use ic_stable_structures::{BoundedStorable, DefaultMemoryImpl, StableBTreeMap, Storable};
 use std::{borrow::Cow, cell::RefCell};
 
+mod analytics;
+mod announcements;
+mod api_tokens;
+mod assets;
+mod assignment_lifecycle;
+mod assignment_messages;
+mod audit;
+mod batch_onboarding;
+mod browse;
+mod cancellation_policy;
+mod category_pauses;
+mod certification;
+mod claims;
+mod cold_chain;
+mod commitments;
+mod contact_vault;
+mod delivery_proofs;
+mod delivery_tracking;
+mod dispatch_queue;
+mod disputes;
+mod donor_leads;
+mod driver_availability;
+mod driver_load;
+mod driver_suggestions;
+mod dropoff_checklists;
+mod emissions;
+mod events;
+mod export_feed;
+mod food_requests;
+mod frontend_notify;
+mod geo;
+mod geocoding;
+mod governance;
+mod group_requests;
+mod hub_flows;
+mod id_sequences;
+mod impact;
+mod impersonation;
+mod indexes;
+mod job_marketplace;
+mod legacy_profiles;
+mod localization;
+mod maintenance;
+mod matching;
+mod notifications;
+mod observability;
+mod organizations;
+mod pagination;
+mod payouts;
+mod pickup_confirmations;
+mod pickup_windows;
+mod post_expiry;
+mod post_mortems;
+mod post_questions;
+mod post_search;
+mod profile_completeness;
+mod radius_expansion;
+mod rate_limits;
+mod recurring_posts;
+mod reference_codes;
+mod region_transfer;
+mod regions;
+mod relations;
+mod report_anchors;
+mod reputation;
+mod research_sharing;
+mod reservations;
+mod reviews;
+mod routes;
+mod sessions;
+mod settings;
+#[cfg(feature = "simulation")]
+mod simulation;
+mod split_donations;
+mod staffing_forecast;
+mod status_codes;
+mod storage_stats;
+mod taxonomy;
+mod tickets;
+mod transparency;
+mod upgrade_state;
+mod validation;
+mod vehicles;
+mod verification;
+mod webhooks;
+
+use analytics::{DonorImpact, GlobalImpact, ImpactBucket, ImpactFactors, PeriodImpact};
+use announcements::{Announcement, AnnouncementRole};
+use api_tokens::{ApiSurplusPostPayload, ApiTokenInfo, IssuedApiToken};
+use assets::Asset;
+use assignment_messages::{
+    AssignmentMessage, AssignmentThread, BulkMessageResult, ParticipantFilter, UnreadCount,
+};
+use audit::{audit, AuditAction, AuditEntity, AuditEvent};
+use batch_onboarding::{DriverBatchItem, ReceiverBatchItem};
+use browse::{OpenPostPage, PostSummaryPage};
+use cancellation_policy::{CancellationPolicy, CancellationRecord, DriverStanding};
+use category_pauses::CategoryPause;
+use claims::{Claim, ClaimConfig};
+use cold_chain::{ColdChainConfig, TemperatureLog, TemperatureReading};
+use commitments::{Commitment, CommitmentPayload, CommitmentProgress, CommitmentVariance};
+use contact_vault::{ContactAccess, ContactDetails, ContactKeyStatus};
+use delivery_proofs::{DeliveryConfirmationConfig, DeliveryProof, DeliveryProofPayload};
+use delivery_tracking::{DeliveryTracking, LocationPing};
+use dispatch_queue::DispatchItem;
+use disputes::{Dispute, DisputePayload, DisputeResolution, DisputeStatus};
+use donor_leads::{DonorInterestForm, DonorLead, LeadIntakeConfig, LeadStatus};
+use driver_availability::{AvailabilityPayload, DriverAvailability};
+use driver_load::FairnessReport;
+use driver_suggestions::DriverSuggestion;
+use dropoff_checklists::{CompletedChecklist, DropoffCheck, DropoffChecklist};
+use emissions::{DriverCandidate, DriverSelection, EmissionsConfig, EmissionsReport};
+use events::{record_event, EntityType, EventKind, TimelineEntry};
+use export_feed::{ExportChunk, ExportFormat, ExportPage};
+use frontend_notify::FrontendTarget;
+use geo::{NearbyDriver, NearbyPost};
+use geocoding::{GeocodeTarget, GeocodingConfig, GeocodingStats};
+use governance::{
+    authorize, AdminGrant, AuthorizationCacheMetrics, EndpointClass, GovernanceConfig,
+    GovernanceConfigPayload,
+};
+use group_requests::{GroupRequest, GroupRequestPayload};
+use hub_flows::{HubFlow, HubMetrics, OnwardAssignmentPayload};
+use id_sequences::{allocate_id, IdEntity};
+use impact::{DeliveryConfirmationPayload, GrantReport, ImpactSummary};
+use impersonation::{ImpersonatedView, ImpersonationSession};
+use job_marketplace::Job;
+use localization::{CategoryLabels, NotificationTemplate};
+use maintenance::MaintenanceJob;
+use matching::{PostMatch, ProposedMatch};
+use notifications::{Notification, NotificationKind};
+use observability::{log, start_trace, LogEntry, LogLevel};
+use organizations::{OrgRole, Organization, OrganizationPayload};
+use pagination::Page;
+use payouts::{Payout, PayoutStatus, RewardConfig, RewardSchedule};
+use pickup_confirmations::{PickupConfirmation, PickupConfirmationConfig};
+use pickup_windows::PickupProposal;
+use post_expiry::WasteStats;
+use post_mortems::{PostMortem, PostMortemConfig};
+use post_questions::PostQuestion;
+use post_search::{SurplusFilter, SurplusSort};
+use profile_completeness::{CompletenessConfig, ProfileCompleteness};
+use radius_expansion::{PostReach, RadiusExpansionConfig};
+use rate_limits::{RateLimitConfig, RateLimitStatus};
+use recurring_posts::{
+    RecurringTemplate, RecurringTemplatePayload, StandingCommitment, StandingCommitmentPayload,
+};
+use reference_codes::{ReferenceKind, ReferencedEntity};
+use region_transfer::RegionTransfer;
+use regions::{GeoPoint, Region, RegionBootstrapPayload, RegionBootstrapReport};
+use relations::AssignmentDetails;
+use report_anchors::{CertifiedChainHead, ReportAnchor, ReportVerification};
+use reputation::Reputation;
+use research_sharing::{ExtractLogEntry, ResearchConsent, ResearchExtract, ResearchScope};
+use reservations::{HoldPolicy, HoldSweepMetrics, RegionFunnelReport, ReservationOutcome};
+use reviews::{Review, ReviewPayload};
+use routes::RouteView;
+use sessions::{UserActivity, WhoAmI};
+use settings::{Settings, SettingsPatch};
+#[cfg(feature = "simulation")]
+use simulation::{SimulationConfig, SimulationReport};
+use split_donations::QuantityReservation;
+use staffing_forecast::DriverNeedForecast;
+use status_codes::{AssignmentStatusCode, StatusCodeEntry};
+use storage_stats::{StorageAlert, StorageAlertConfig, StorageStats};
+use taxonomy::{Category, CategoryPayload, TaxonomyKind, UpdateCategoryPayload};
+use tickets::{Ticket, TicketCategory, TicketEntityRef, TicketStatus};
+use transparency::{CertifiedRegionTransparency, HttpRequest, HttpResponse};
+use vehicles::Vehicle;
+use verification::{VerificationConfig, VerificationDocument, VerificationRequest};
+use webhooks::{WebhookDelivery, WebhookEvent, WebhookRegistration, WebhookView};
+
 type Memory = VirtualMemory<DefaultMemoryImpl>;
-type IdCell = Cell<u64, Memory>;
+
+const MAX_POST_PHOTOS: usize = 4;
+
+// StorablePrincipal wraps a Principal so it can be used as a stable map key
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct StorablePrincipal(Principal);
+
+impl Storable for StorablePrincipal {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(self.0.as_slice())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        StorablePrincipal(Principal::from_slice(bytes.as_ref()))
+    }
+}
+
+impl BoundedStorable for StorablePrincipal {
+    const MAX_SIZE: u32 = 29;
+    const IS_FIXED_SIZE: bool = false;
+}
 
 // BusinessType is a custom enum type that is used to represent the type of business
 #[derive(
@@ -22,6 +217,127 @@ enum BusinessType {
     Other,
 }
 
+// ReceiverType is a custom enum type that is used to represent the role a receiver plays in distribution
+#[derive(
+    candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug,
+)]
+
+enum ReceiverType {
+    #[default]
+    Standard,
+    Hub,
+}
+
+// VehicleType is a custom enum type that is used to represent what a driver delivers with
+#[derive(
+    candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug,
+)]
+
+enum VehicleType {
+    Bicycle,
+    Motorbike,
+    #[default]
+    Car,
+    ElectricCar,
+    Van,
+    Truck,
+}
+
+// AssignmentStatus is a custom enum type that is used to represent how far an assignment has progressed
+#[derive(
+    candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug,
+)]
+
+enum AssignmentStatus {
+    #[default]
+    Pending,
+    Accepted,
+    PickedUp,
+    InTransit,
+    // The driver dropped the food off and the receiver has yet to confirm it
+    AwaitingConfirmation,
+    // The receiver disputed the delivery, or let the confirmation window lapse
+    Disputed,
+    Delivered,
+    Cancelled,
+    Failed,
+    // The hold on the post lapsed before the driver accepted it; holds that
+    // lapse now end in TimedOut, so only older assignments carry this status
+    Expired,
+    // The driver did not accept within the hold timeout
+    TimedOut,
+}
+
+impl AssignmentStatus {
+    // Active assignments keep their driver busy and their post held
+    fn is_active(&self) -> bool {
+        use AssignmentStatus::*;
+        matches!(self, Pending | Accepted | PickedUp | InTransit)
+    }
+
+    // Closed assignments cannot change status any more
+    fn is_closed(&self) -> bool {
+        use AssignmentStatus::*;
+        matches!(self, Delivered | Cancelled | Failed | Expired | TimedOut)
+    }
+
+    // Cancelled, expired and timed out assignments return their post to the open listing
+    fn releases_post(&self) -> bool {
+        matches!(
+            self,
+            AssignmentStatus::Cancelled | AssignmentStatus::Expired | AssignmentStatus::TimedOut
+        )
+    }
+
+    fn can_move_to(&self, next: AssignmentStatus) -> bool {
+        use AssignmentStatus::*;
+        matches!(
+            (self, next),
+            (Pending, Accepted)
+                | (Pending, TimedOut)
+                | (Pending, Cancelled)
+                | (Accepted, PickedUp)
+                | (Accepted, Cancelled)
+                | (PickedUp, InTransit)
+                | (PickedUp, AwaitingConfirmation)
+                | (PickedUp, Failed)
+                | (InTransit, AwaitingConfirmation)
+                | (InTransit, Failed)
+                | (AwaitingConfirmation, Delivered)
+                | (AwaitingConfirmation, Disputed)
+                | (Disputed, Delivered)
+                | (Disputed, Failed)
+        )
+    }
+}
+
+// Allergen is a custom enum type that is used to represent a major allergen a post contains
+#[derive(
+    candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+enum Allergen {
+    Milk,
+    Eggs,
+    Fish,
+    Shellfish,
+    TreeNuts,
+    Peanuts,
+    Wheat,
+    Soy,
+    Sesame,
+}
+
+// DietaryTag is a custom enum type that is used to represent a diet a post is suitable for
+#[derive(
+    candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+enum DietaryTag {
+    Halal,
+    Kosher,
+    Vegan,
+    GlutenFree,
+}
+
 // FoodType is a custom enum type that is used to represent the type of food
 #[derive(
     candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug,
@@ -48,6 +364,29 @@ struct SurplusPost {
     best_before_date: String,
     handling_instructions: String,
     assigned: bool,
+    // Set when the donor pulls the post; withdrawn posts are never offered again
+    withdrawn_at: Option<u64>,
+    // Set by the expiry sweep once the best before date has passed unclaimed
+    expired_at: Option<u64>,
+    // Pickup location; defaults to the donor's location when not given
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    // Distance from the pickup location the post is offered within; widened
+    // over time while the post stays unclaimed. None for posts without coordinates
+    reach_radius_km: Option<f64>,
+    // None for posts created before creation times were recorded
+    created_at: Option<u64>,
+    // Uploaded food photos, see the assets module
+    photo_asset_ids: Option<Vec<u64>>,
+    // Category from the food taxonomy; food_type holds its nearest built-in match
+    food_category_id: Option<u64>,
+    // Organization a member posted on behalf of
+    organization_id: Option<u64>,
+    // Allergens the food contains; None when the donor did not declare them
+    allergens: Option<Vec<Allergen>>,
+    dietary_tags: Option<Vec<DietaryTag>>,
+    // Kilograms held by receivers' quantity reservations, see the split_donations module
+    reserved_kg: Option<u32>,
 }
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
@@ -58,7 +397,18 @@ struct DonorProfile {
     email: String,
     address: String,
     business_type: BusinessType,
+    // Category from the business type taxonomy; business_type holds its nearest built-in match
+    business_type_id: Option<u64>,
+    region: String,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    // Principal that registered the profile; None for profiles created before owners were tracked
+    owner: Option<Principal>,
+    active: bool,
+    deactivated_at: Option<u64>,
     created_at: u64,
+    // Badge set once a moderator approves the donor's documents, see the verification module
+    verified: Option<bool>,
 }
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
@@ -68,7 +418,17 @@ struct ReceiverProfile {
     phone_number: String,
     email: String,
     address: String,
+    receiver_type: ReceiverType,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    // Principal that registered the profile; None for profiles created before owners
+    // were tracked, or onboarded in bulk without one
+    owner: Option<Principal>,
+    active: bool,
+    deactivated_at: Option<u64>,
     created_at: u64,
+    // Organization the profile was onboarded in bulk for
+    organization_id: Option<u64>,
 }
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
@@ -78,7 +438,28 @@ struct DriverProfile {
     phone_number: String,
     email: String,
     address: String,
+    vehicle_type: VehicleType,
+    // Load and refrigeration details; None until the driver registers a vehicle
+    vehicle: Option<Vehicle>,
+    region: String,
+    // Most assignments the driver wants per 7 days; None for no limit
+    weekly_assignment_cap: Option<u32>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    // Principal that registered the profile; None for profiles created before owners
+    // were tracked, or onboarded in bulk without one
+    owner: Option<Principal>,
+    active: bool,
+    deactivated_at: Option<u64>,
     created_at: u64,
+    // Organization the profile was onboarded in bulk for
+    organization_id: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct StatusChange {
+    status: AssignmentStatus,
+    changed_at: u64,
 }
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
@@ -87,8 +468,48 @@ struct Assignment {
     receiver_id: u64,
     surplus_post_id: u64,
     driver_id: u64,
-    status: String,
+    status: AssignmentStatus,
+    // Every status the assignment has been in, oldest first
+    status_history: Vec<StatusChange>,
+    previous_leg_id: Option<u64>,
     created_at: u64,
+    // Pickup window agreed by the donor and the driver
+    pickup_window_start: Option<u64>,
+    pickup_window_end: Option<u64>,
+    // Window one of them proposed that the other has not confirmed yet
+    pickup_proposal: Option<PickupProposal>,
+    // Share of the post carried when delivering a quantity reservation; None for the whole post
+    quantity_kg: Option<u32>,
+}
+
+impl Assignment {
+    // Function to move the assignment to a new status, rejecting illegal transitions
+    fn transition(&mut self, next: AssignmentStatus) -> Result<(), FoodShareError> {
+        if !self.status.can_move_to(next) {
+            return Err(FoodShareError::Conflict {
+                msg: format!(
+                    "Assignment cannot move from {:?} to {:?}",
+                    self.status, next
+                ),
+            });
+        }
+        self.status = next;
+        self.status_history.push(StatusChange {
+            status: next,
+            changed_at: time(),
+        });
+        Ok(())
+    }
+
+    // Function to get the kilograms the assignment moves: its reserved share, or the whole post
+    fn carried_kg(&self) -> u32 {
+        self.quantity_kg.unwrap_or_else(|| {
+            SURPLUS_POSTS_STORAGE
+                .with(|storage| storage.borrow().get(&self.surplus_post_id))
+                .map(|post| post.quantity_kg)
+                .unwrap_or_default()
+        })
+    }
 }
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
@@ -98,6 +519,70 @@ struct SurplusRecord {
     driver_id: u64,
     delivered_at: u64,
     rating: Option<u8>,
+    // Reported by the receiver when they confirm the delivery
+    confirmed_at: Option<u64>,
+    beneficiaries: Option<u32>,
+    meal_program: Option<String>,
+    // Assignment that delivered it
+    assignment_id: Option<u64>,
+}
+
+// FoodRequestStatus is a custom enum type that is used to represent how far a food request has been met
+#[derive(
+    candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug,
+)]
+enum FoodRequestStatus {
+    #[default]
+    Open,
+    PartiallyFulfilled,
+    Fulfilled,
+    Cancelled,
+    // Its needed-by time passed before it was fulfilled
+    Expired,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct FoodRequest {
+    id: u64,
+    receiver_id: u64,
+    food_type: FoodType,
+    quantity_kg: u32,
+    notes: String,
+    // Kept in step with the status: true once the request is fulfilled
+    assigned: bool,
+    created_at: u64,
+    // None for requests created before statuses were tracked; see `status()`
+    status: Option<FoodRequestStatus>,
+    quantity_fulfilled_kg: Option<u32>,
+    // Surplus posts counted before they were kept in the food_requests index;
+    // see `food_requests::fulfilling_post_ids`
+    fulfilled_post_ids: Option<Vec<u64>>,
+    needed_by: Option<u64>,
+    // Organization a member requested on behalf of
+    organization_id: Option<u64>,
+}
+
+impl FoodRequest {
+    fn status(&self) -> FoodRequestStatus {
+        match self.status {
+            Some(status) => status,
+            None if self.assigned => FoodRequestStatus::Fulfilled,
+            None => FoodRequestStatus::Open,
+        }
+    }
+
+    // Open and partially fulfilled requests still take surplus posts
+    fn is_open(&self) -> bool {
+        matches!(
+            self.status(),
+            FoodRequestStatus::Open | FoodRequestStatus::PartiallyFulfilled
+        )
+    }
+
+    fn remaining_kg(&self) -> u32 {
+        self.quantity_kg
+            .saturating_sub(self.quantity_fulfilled_kg.unwrap_or(0))
+    }
 }
 
 impl Storable for SurplusPost {
@@ -126,7 +611,8 @@ impl Storable for DonorProfile {
 }
 
 impl BoundedStorable for DonorProfile {
-    const MAX_SIZE: u32 = 512;
+    // Room for sealed contact details next to the longest name, address and region
+    const MAX_SIZE: u32 = 2048;
     const IS_FIXED_SIZE: bool = false;
 }
 
@@ -141,7 +627,7 @@ impl Storable for ReceiverProfile {
 }
 
 impl BoundedStorable for ReceiverProfile {
-    const MAX_SIZE: u32 = 512;
+    const MAX_SIZE: u32 = 2048;
     const IS_FIXED_SIZE: bool = false;
 }
 
@@ -156,7 +642,7 @@ impl Storable for DriverProfile {
 }
 
 impl BoundedStorable for DriverProfile {
-    const MAX_SIZE: u32 = 512;
+    const MAX_SIZE: u32 = 2048;
     const IS_FIXED_SIZE: bool = false;
 }
 
@@ -190,29 +676,39 @@ impl BoundedStorable for SurplusRecord {
     const IS_FIXED_SIZE: bool = false;
 }
 
+impl Storable for FoodRequest {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for FoodRequest {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
         MemoryManager::init(DefaultMemoryImpl::default())
     );
 
-    static ID_COUNTER: RefCell<IdCell> = RefCell::new(
-        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
-            .expect("Cannot create a counter")
-    );
-
     static DONORS_STORAGE: RefCell<StableBTreeMap<u64, DonorProfile, Memory>> =
         RefCell::new(StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(124)))
     ));
 
     static RECEIVERS_STORAGE: RefCell<StableBTreeMap<u64, ReceiverProfile, Memory>> =
         RefCell::new(StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(125)))
     ));
 
     static DRIVERS_STORAGE: RefCell<StableBTreeMap<u64, DriverProfile, Memory>> =
         RefCell::new(StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(126)))
     ));
 
     static SURPLUS_POSTS_STORAGE: RefCell<StableBTreeMap<u64, SurplusPost, Memory>> =
@@ -229,6 +725,22 @@ thread_local! {
         RefCell::new(StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6)))
     ));
+
+    static FOOD_REQUESTS_STORAGE: RefCell<StableBTreeMap<u64, FoodRequest, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+    ));
+}
+
+// MyProfiles groups the profiles a caller owns, one per role
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct MyProfiles {
+    donor: Option<DonorProfile>,
+    receiver: Option<ReceiverProfile>,
+    driver: Option<DriverProfile>,
+    donor_completeness: Option<ProfileCompleteness>,
+    receiver_completeness: Option<ProfileCompleteness>,
+    driver_completeness: Option<ProfileCompleteness>,
 }
 
 // Donor Payload
@@ -239,6 +751,27 @@ struct DonorPayload {
     email: String,
     address: String,
     business_type: BusinessType,
+    // Overrides business_type when given
+    business_type_id: Option<u64>,
+    region: String,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+// Update Donor Payload; fields left as None keep their current value
+#[derive(candid::CandidType, Deserialize, Serialize)]
+struct UpdateDonorPayload {
+    name: Option<String>,
+    phone_number: Option<String>,
+    email: Option<String>,
+    address: Option<String>,
+    business_type: Option<BusinessType>,
+    // Overrides business_type when given
+    business_type_id: Option<u64>,
+    region: Option<String>,
+    // Coordinates are replaced together
+    latitude: Option<f64>,
+    longitude: Option<f64>,
 }
 
 // Receiver Payload
@@ -248,6 +781,9 @@ struct ReceiverPayload {
     phone_number: String,
     email: String,
     address: String,
+    receiver_type: ReceiverType,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
 }
 
 // Driver Payload
@@ -257,6 +793,10 @@ struct DriverPayload {
     phone_number: String,
     email: String,
     address: String,
+    vehicle_type: VehicleType,
+    region: String,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
 }
 
 // Surplus Post Payload
@@ -264,9 +804,36 @@ struct DriverPayload {
 struct SurplusPostPayload {
     donor_id: String,
     food_type: FoodType,
+    // Overrides food_type when given
+    food_category_id: Option<u64>,
     quantity_kg: u32,
     best_before_date: String,
     handling_instructions: String,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    // Finished uploads of the caller
+    photo_asset_ids: Option<Vec<u64>>,
+    // Posts on behalf of an organization the caller is a member of
+    organization_id: Option<u64>,
+    // An empty list declares the food free of every listed allergen
+    allergens: Option<Vec<Allergen>>,
+    dietary_tags: Option<Vec<DietaryTag>>,
+}
+
+// Update Surplus Post Payload; fields left as None keep their current value
+#[derive(candid::CandidType, Deserialize, Serialize)]
+struct UpdateSurplusPostPayload {
+    food_type: Option<FoodType>,
+    // Overrides food_type when given
+    food_category_id: Option<u64>,
+    quantity_kg: Option<u32>,
+    best_before_date: Option<String>,
+    handling_instructions: Option<String>,
+    // Coordinates are replaced together
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    // Replaces the post's photos; an empty list removes them
+    photo_asset_ids: Option<Vec<u64>>,
 }
 
 // Assignment Payload
@@ -275,6 +842,8 @@ struct AssignmentPayload {
     receiver_id: u64,
     surplus_post_id: u64,
     driver_id: u64,
+    // Quantity reservation the assignment delivers, for posts split between receivers
+    reservation_id: Option<u64>,
 }
 
 // Surplus Record Payload
@@ -284,180 +853,540 @@ struct SurplusRecordPayload {
     driver_id: u64,
 }
 
+// Food Request Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+struct FoodRequestPayload {
+    receiver_id: u64,
+    food_type: FoodType,
+    quantity_kg: u32,
+    notes: String,
+    // Time after which the request expires if it is still not fulfilled
+    needed_by: Option<u64>,
+    // Requests on behalf of an organization the caller is a member of
+    organization_id: Option<u64>,
+}
+
 // Function to create a new donor profile
 #[ic_cdk::update]
-fn create_donor_profile(payload: DonorPayload) -> Result<DonorProfile, String> {
+async fn create_donor_profile(payload: DonorPayload) -> Result<DonorProfile, FoodShareError> {
+    start_trace("create_donor_profile");
+    authorize(EndpointClass::Registration).await?;
+    contact_vault::ensure_key().await?;
+
     // Validate the payload to ensure that the required fields are present
     if payload.name.is_empty()
         && payload.phone_number.is_empty()
         && payload.email.is_empty()
         && payload.address.is_empty()
     {
-        return Err("All fields are required".to_string());
+        return Err(FoodShareError::Validation {
+            field: "payload".to_string(),
+            reason: "All fields are required".to_string(),
+        });
     }
+    validation::check_profile_text(&payload.name, &payload.address, &payload.region)?;
 
     // Validate the payload to ensure that the email format is correct
-    if !payload.email.contains('@') {
-        return Err("Invalid email format".to_string());
-    }
+    let email = validation::normalize_email(&payload.email)?;
 
-    // Ensure email address uniqueness
-    let email_exists = DONORS_STORAGE.with(|storage| {
+    // Ensure the caller registers at most one active donor profile
+    let caller = registering_principal()?;
+    let already_registered = DONORS_STORAGE.with(|storage| {
         storage
             .borrow()
             .iter()
-            .any(|(_, donor)| donor.email == payload.email)
+            .any(|(_, donor)| donor.active && donor.owner == Some(caller))
     });
-    if email_exists {
-        return Err("Email already exists".to_string());
+    if already_registered {
+        return Err(FoodShareError::Conflict {
+            msg: "Caller already has a donor profile".to_string(),
+        });
     }
 
-    // Validate the payload to ensure that the phone number format is correct
-    if payload.phone_number.len() != 10 {
-        return Err("Invalid phone number format".to_string());
+    // Ensure email address uniqueness
+    if contact_vault::email_taken(EntityType::Donor, &email, None) {
+        return Err(FoodShareError::Conflict {
+            msg: "Email already exists".to_string(),
+        });
     }
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("Cannot increment ID counter");
 
-    let donor_profile = DonorProfile {
+    // Validate the payload to ensure that the phone number format is correct
+    let phone_number = validation::normalize_phone_number(&payload.phone_number)?;
+    geo::validate_coordinates(payload.latitude, payload.longitude)?;
+    let (latitude, longitude) =
+        geocoding::known_coordinates(&payload.address, payload.latitude, payload.longitude);
+    let business_type_id = payload.business_type_id;
+    let business_type = match business_type_id {
+        Some(business_type_id) => taxonomy::business_type_for(business_type_id)?,
+        None => payload.business_type,
+    };
+
+    let id = allocate_id(IdEntity::Profile);
+
+    let donor_profile = contact_vault::sealed(DonorProfile {
         id,
         name: payload.name,
-        phone_number: payload.phone_number,
-        email: payload.email,
+        phone_number,
+        email: email.clone(),
         address: payload.address,
-        business_type: payload.business_type,
+        business_type,
+        business_type_id: business_type_id
+            .or_else(|| taxonomy::builtin_business_type(business_type)),
+        region: payload.region,
+        latitude,
+        longitude,
+        owner: Some(caller),
+        active: true,
+        deactivated_at: None,
         created_at: time(),
-    };
+        verified: None,
+    });
 
     DONORS_STORAGE.with(|storage| storage.borrow_mut().insert(id, donor_profile.clone()));
+    contact_vault::index_email(EntityType::Donor, id, None, &email);
+    if donor_profile.latitude.is_none() {
+        geocoding::geocode_later(GeocodeTarget::Donor(id), &donor_profile.address);
+    }
+    audit(
+        AuditAction::Created,
+        AuditEntity::Donor,
+        id,
+        None,
+        Some(&donor_profile),
+    );
+
+    record_event(
+        EntityType::Donor,
+        id,
+        None,
+        EventKind::Created,
+        format!("Donor {} registered", donor_profile.name),
+    );
+    log(LogLevel::Info, format!("Created donor profile {}", id));
+
+    Ok(contact_vault::revealed(donor_profile))
+}
+
+// Function to update selected fields of a donor profile
+#[ic_cdk::update]
+async fn update_donor_profile(
+    donor_id: u64,
+    payload: UpdateDonorPayload,
+) -> Result<DonorProfile, FoodShareError> {
+    start_trace("update_donor_profile");
+    authorize(EndpointClass::Registration).await?;
+    contact_vault::ensure_key().await?;
+
+    let donor_profile = DONORS_STORAGE
+        .with(|storage| storage.borrow().get(&donor_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Donor ID does not exist".to_string(),
+        })?;
+
+    // Only the profile owner or governance may change a profile
+    let caller = ic_cdk::caller();
+    if donor_profile.owner != Some(caller) && !governance::is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the profile owner or governance can update this profile".to_string(),
+        });
+    }
+    let before = donor_profile.clone();
+    // Sealed again as a whole before it is stored
+    let mut donor_profile = contact_vault::revealed(donor_profile);
+
+    if let Some(name) = payload.name {
+        // Validate the payload to ensure that the name is present
+        if name.is_empty() {
+            return Err(FoodShareError::Validation {
+                field: "name".to_string(),
+                reason: "Name cannot be empty".to_string(),
+            });
+        }
+        validation::check_max_len("name", &name, validation::MAX_NAME_LEN)?;
+        donor_profile.name = name;
+    }
+
+    if let Some(email) = payload.email {
+        // Validate the payload to ensure that the email format is correct
+        let email = validation::normalize_email(&email)?;
+
+        // Ensure email address uniqueness
+        if contact_vault::email_taken(EntityType::Donor, &email, Some(donor_id)) {
+            return Err(FoodShareError::Conflict {
+                msg: "Email already exists".to_string(),
+            });
+        }
+        donor_profile.email = email;
+    }
+
+    if let Some(phone_number) = payload.phone_number {
+        // Validate the payload to ensure that the phone number format is correct
+        donor_profile.phone_number = validation::normalize_phone_number(&phone_number)?;
+    }
+
+    if let Some(address) = payload.address {
+        if address.is_empty() {
+            return Err(FoodShareError::Validation {
+                field: "address".to_string(),
+                reason: "Address cannot be empty".to_string(),
+            });
+        }
+        validation::check_max_len("address", &address, validation::MAX_ADDRESS_LEN)?;
+        donor_profile.address = address;
+    }
+
+    if let Some(business_type_id) = payload.business_type_id {
+        donor_profile.business_type = taxonomy::business_type_for(business_type_id)?;
+        donor_profile.business_type_id = Some(business_type_id);
+    } else if let Some(business_type) = payload.business_type {
+        donor_profile.business_type = business_type;
+        donor_profile.business_type_id = taxonomy::builtin_business_type(business_type);
+    }
+
+    if let Some(region) = payload.region {
+        validation::check_max_len("region", &region, validation::MAX_REGION_LEN)?;
+        donor_profile.region = region;
+    }
+
+    // A new address given without coordinates is located again; until the
+    // lookup finishes the donor keeps the coordinates of the old address
+    let mut relocate = false;
+    if payload.latitude.is_some() || payload.longitude.is_some() {
+        geo::validate_coordinates(payload.latitude, payload.longitude)?;
+        donor_profile.latitude = payload.latitude;
+        donor_profile.longitude = payload.longitude;
+    } else if donor_profile.address != before.address {
+        match geocoding::cached_coordinates(&donor_profile.address) {
+            Some((latitude, longitude)) => {
+                donor_profile.latitude = Some(latitude);
+                donor_profile.longitude = Some(longitude);
+            }
+            None => relocate = true,
+        }
+    }
+
+    let previous_email = contact_vault::open(&before.email);
+    contact_vault::index_email(
+        EntityType::Donor,
+        donor_id,
+        Some(&previous_email),
+        &donor_profile.email,
+    );
+    let donor_profile = contact_vault::sealed(donor_profile);
+    DONORS_STORAGE.with(|storage| storage.borrow_mut().insert(donor_id, donor_profile.clone()));
+    if relocate {
+        geocoding::geocode_later(GeocodeTarget::Donor(donor_id), &donor_profile.address);
+    }
+    audit(
+        AuditAction::Updated,
+        AuditEntity::Donor,
+        donor_id,
+        Some(&before),
+        Some(&donor_profile),
+    );
+
+    record_event(
+        EntityType::Donor,
+        donor_id,
+        None,
+        EventKind::Updated,
+        format!("Donor {} updated their profile", donor_profile.name),
+    );
+    log(
+        LogLevel::Info,
+        format!("Updated donor profile {}", donor_id),
+    );
 
-    Ok(donor_profile)
+    Ok(contact_vault::revealed(donor_profile))
 }
 
 // Function to create a new receiver profile
 #[ic_cdk::update]
-fn create_receiver_profile(payload: ReceiverPayload) -> Result<ReceiverProfile, String> {
+async fn create_receiver_profile(
+    payload: ReceiverPayload,
+) -> Result<ReceiverProfile, FoodShareError> {
+    start_trace("create_receiver_profile");
+    authorize(EndpointClass::Registration).await?;
+    contact_vault::ensure_key().await?;
+
+    // Ensure the caller registers at most one active receiver profile
+    let caller = registering_principal()?;
+    if has_receiver_profile(caller) {
+        return Err(FoodShareError::Conflict {
+            msg: "Caller already has a receiver profile".to_string(),
+        });
+    }
+
+    add_receiver_profile(payload, Some(caller), None)
+}
+
+// Function to check whether a principal already owns an active receiver profile
+fn has_receiver_profile(owner: Principal) -> bool {
+    RECEIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .any(|(_, receiver)| receiver.active && receiver.owner == Some(owner))
+    })
+}
+
+// Function to validate a receiver payload and store the profile for an owner,
+// or unowned when onboarded in bulk without one; the contact key must already exist
+fn add_receiver_profile(
+    payload: ReceiverPayload,
+    owner: Option<Principal>,
+    organization_id: Option<u64>,
+) -> Result<ReceiverProfile, FoodShareError> {
     // Validate the payload to ensure that the required fields are present
     if payload.name.is_empty()
         && payload.phone_number.is_empty()
         && payload.email.is_empty()
         && payload.address.is_empty()
     {
-        return Err("All fields are required".to_string());
+        return Err(FoodShareError::Validation {
+            field: "payload".to_string(),
+            reason: "All fields are required".to_string(),
+        });
     }
+    validation::check_profile_text(&payload.name, &payload.address, "")?;
 
     // Validate the payload to ensure that the email format is correct
-    if !payload.email.contains('@') {
-        return Err("Invalid email format".to_string());
-    }
+    let email = validation::normalize_email(&payload.email)?;
 
     // Ensure email address uniqueness
-    let email_exists = RECEIVERS_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .any(|(_, receiver)| receiver.email == payload.email)
-    });
-    if email_exists {
-        return Err("Email already exists".to_string());
+    if contact_vault::email_taken(EntityType::Receiver, &email, None) {
+        return Err(FoodShareError::Conflict {
+            msg: "Email already exists".to_string(),
+        });
     }
 
     // Validate the payload to ensure that the phone number format is correct
-    if payload.phone_number.len() != 10 {
-        return Err("Invalid phone number format".to_string());
-    }
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("Cannot increment ID counter");
+    let phone_number = validation::normalize_phone_number(&payload.phone_number)?;
+    geo::validate_coordinates(payload.latitude, payload.longitude)?;
+    let (latitude, longitude) =
+        geocoding::known_coordinates(&payload.address, payload.latitude, payload.longitude);
 
-    let receiver_profile = ReceiverProfile {
+    let id = allocate_id(IdEntity::Profile);
+
+    let receiver_profile = contact_vault::sealed(ReceiverProfile {
         id,
         name: payload.name,
-        phone_number: payload.phone_number,
-        email: payload.email,
+        phone_number,
+        email: email.clone(),
         address: payload.address,
+        receiver_type: payload.receiver_type,
+        latitude,
+        longitude,
+        owner,
+        active: true,
+        deactivated_at: None,
         created_at: time(),
-    };
+        organization_id,
+    });
 
     RECEIVERS_STORAGE.with(|storage| storage.borrow_mut().insert(id, receiver_profile.clone()));
+    contact_vault::index_email(EntityType::Receiver, id, None, &email);
+    if receiver_profile.latitude.is_none() {
+        geocoding::geocode_later(GeocodeTarget::Receiver(id), &receiver_profile.address);
+    }
+    audit(
+        AuditAction::Created,
+        AuditEntity::Receiver,
+        id,
+        None,
+        Some(&receiver_profile),
+    );
 
-    Ok(receiver_profile)
+    record_event(
+        EntityType::Receiver,
+        id,
+        None,
+        EventKind::Created,
+        format!("Receiver {} registered", receiver_profile.name),
+    );
+    log(LogLevel::Info, format!("Created receiver profile {}", id));
+
+    Ok(contact_vault::revealed(receiver_profile))
 }
 
 // Function to create a new driver profile
 #[ic_cdk::update]
-fn create_driver_profile(payload: DriverPayload) -> Result<DriverProfile, String> {
+async fn create_driver_profile(payload: DriverPayload) -> Result<DriverProfile, FoodShareError> {
+    start_trace("create_driver_profile");
+    authorize(EndpointClass::Registration).await?;
+    contact_vault::ensure_key().await?;
+
+    // Ensure the caller registers at most one active driver profile
+    let caller = registering_principal()?;
+    if has_driver_profile(caller) {
+        return Err(FoodShareError::Conflict {
+            msg: "Caller already has a driver profile".to_string(),
+        });
+    }
+
+    add_driver_profile(payload, Some(caller), None)
+}
+
+// Function to check whether a principal already owns an active driver profile
+fn has_driver_profile(owner: Principal) -> bool {
+    DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .any(|(_, driver)| driver.active && driver.owner == Some(owner))
+    })
+}
+
+// Function to validate a driver payload and store the profile for an owner,
+// or unowned when onboarded in bulk without one; the contact key must already exist
+fn add_driver_profile(
+    payload: DriverPayload,
+    owner: Option<Principal>,
+    organization_id: Option<u64>,
+) -> Result<DriverProfile, FoodShareError> {
     // Validate the payload to ensure that the required fields are present
     if payload.name.is_empty()
         && payload.phone_number.is_empty()
         && payload.email.is_empty()
         && payload.address.is_empty()
     {
-        return Err("All fields are required".to_string());
+        return Err(FoodShareError::Validation {
+            field: "payload".to_string(),
+            reason: "All fields are required".to_string(),
+        });
     }
+    validation::check_profile_text(&payload.name, &payload.address, &payload.region)?;
 
     // Validate the payload to ensure that the email format is correct
-    if !payload.email.contains('@') {
-        return Err("Invalid email format".to_string());
-    }
+    let email = validation::normalize_email(&payload.email)?;
 
     // Ensure email address uniqueness
-    let email_exists = DRIVERS_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .any(|(_, driver)| driver.email == payload.email)
-    });
-    if email_exists {
-        return Err("Email already exists".to_string());
+    if contact_vault::email_taken(EntityType::Driver, &email, None) {
+        return Err(FoodShareError::Conflict {
+            msg: "Email already exists".to_string(),
+        });
     }
 
     // Validate the payload to ensure that the phone number format is correct
-    if payload.phone_number.len() != 10 {
-        return Err("Invalid phone number format".to_string());
-    }
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("Cannot increment ID counter");
+    let phone_number = validation::normalize_phone_number(&payload.phone_number)?;
+    geo::validate_coordinates(payload.latitude, payload.longitude)?;
+    let (latitude, longitude) =
+        geocoding::known_coordinates(&payload.address, payload.latitude, payload.longitude);
+
+    let id = allocate_id(IdEntity::Profile);
 
-    let driver_profile = DriverProfile {
+    let driver_profile = contact_vault::sealed(DriverProfile {
         id,
         name: payload.name,
-        phone_number: payload.phone_number,
-        email: payload.email,
+        phone_number,
+        email: email.clone(),
         address: payload.address,
+        vehicle_type: payload.vehicle_type,
+        vehicle: None,
+        region: payload.region,
+        weekly_assignment_cap: None,
+        latitude,
+        longitude,
+        owner,
+        active: true,
+        deactivated_at: None,
         created_at: time(),
-    };
+        organization_id,
+    });
 
     DRIVERS_STORAGE.with(|storage| storage.borrow_mut().insert(id, driver_profile.clone()));
+    contact_vault::index_email(EntityType::Driver, id, None, &email);
+    if driver_profile.latitude.is_none() {
+        geocoding::geocode_later(GeocodeTarget::Driver(id), &driver_profile.address);
+    }
+    audit(
+        AuditAction::Created,
+        AuditEntity::Driver,
+        id,
+        None,
+        Some(&driver_profile),
+    );
+
+    record_event(
+        EntityType::Driver,
+        id,
+        None,
+        EventKind::Created,
+        format!("Driver {} registered", driver_profile.name),
+    );
+    log(LogLevel::Info, format!("Created driver profile {}", id));
 
-    Ok(driver_profile)
+    Ok(contact_vault::revealed(driver_profile))
 }
 
-// Function to get all drivers
+// Function to get the caller's active profiles across all roles
 #[ic_cdk::query]
-fn get_all_drivers() -> Result<Vec<DriverProfile>, Error> {
-    DRIVERS_STORAGE.with(|storage| {
+fn get_my_profile() -> Result<MyProfiles, FoodShareError> {
+    let caller = Some(ic_cdk::caller());
+
+    let donor = DONORS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, donor)| donor)
+            .find(|donor| donor.active && donor.owner == caller)
+            .map(contact_vault::revealed)
+    });
+    let receiver = RECEIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, receiver)| receiver)
+            .find(|receiver| receiver.active && receiver.owner == caller)
+            .map(contact_vault::revealed)
+    });
+    let driver = DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, driver)| driver)
+            .find(|driver| driver.active && driver.owner == caller)
+            .map(contact_vault::revealed)
+    });
+
+    let profiles = MyProfiles {
+        donor_completeness: donor.as_ref().map(profile_completeness::donor_completeness),
+        receiver_completeness: receiver
+            .as_ref()
+            .map(profile_completeness::receiver_completeness),
+        driver_completeness: driver
+            .as_ref()
+            .map(profile_completeness::driver_completeness),
+        donor,
+        receiver,
+        driver,
+    };
+
+    if profiles.donor.is_none() && profiles.receiver.is_none() && profiles.driver.is_none() {
+        return Err(FoodShareError::NotFound {
+            msg: "No profiles found for the caller.".to_string(),
+        });
+    }
+
+    Ok(profiles)
+}
+
+// Function to get all donors
+#[ic_cdk::query]
+fn get_all_donors(include_inactive: Option<bool>) -> Result<Vec<DonorProfile>, FoodShareError> {
+    let include_inactive = include_inactive.unwrap_or(false);
+    DONORS_STORAGE.with(|storage| {
         let stable_btree_map = &*storage.borrow();
 
-        let records: Vec<DriverProfile> = stable_btree_map
+        let records: Vec<DonorProfile> = stable_btree_map
             .iter()
-            .map(|(_, record)| record.clone())
+            .filter(|(_, record)| include_inactive || record.active)
+            .map(|(_, record)| contact_vault::redacted(record))
             .collect();
 
         if records.is_empty() {
-            Err(Error::NotFound {
-                msg: "No drivers found.".to_string(),
+            Err(FoodShareError::NotFound {
+                msg: "No donors found.".to_string(),
             })
         } else {
             Ok(records)
@@ -465,40 +1394,357 @@ fn get_all_drivers() -> Result<Vec<DriverProfile>, Error> {
     })
 }
 
-// Function to create a new surplus post
-#[ic_cdk::update]
-fn create_surplus_post(payload: SurplusPostPayload) -> Result<SurplusPost, String> {
-    // Validate the payload to ensure that the required fields are present
-    if payload.donor_id.is_empty()
-        && payload.quantity_kg == 0
-        && payload.best_before_date.is_empty()
-        && payload.handling_instructions.is_empty()
-    {
-        return Err("All fields are required".to_string());
-    }
-    
-    // Validate the payload to ensure that the donor_id exists
-    let donor_id: u64 = payload
-        .donor_id
-        .parse()
-        .map_err(|_| "Invalid donor ID format".to_string())?;
-    let donor_exists = DONORS_STORAGE.with(|storage| storage.borrow().contains_key(&donor_id));
+// Function to get all receivers
+#[ic_cdk::query]
+fn get_all_receivers(
+    include_inactive: Option<bool>,
+) -> Result<Vec<ReceiverProfile>, FoodShareError> {
+    let include_inactive = include_inactive.unwrap_or(false);
+    RECEIVERS_STORAGE.with(|storage| {
+        let stable_btree_map = &*storage.borrow();
+
+        let records: Vec<ReceiverProfile> = stable_btree_map
+            .iter()
+            .filter(|(_, record)| include_inactive || record.active)
+            .map(|(_, record)| contact_vault::redacted(record))
+            .collect();
+
+        if records.is_empty() {
+            Err(FoodShareError::NotFound {
+                msg: "No receivers found.".to_string(),
+            })
+        } else {
+            Ok(records)
+        }
+    })
+}
+
+// Function to get all drivers
+#[ic_cdk::query]
+fn get_all_drivers(include_inactive: Option<bool>) -> Result<Vec<DriverProfile>, FoodShareError> {
+    let include_inactive = include_inactive.unwrap_or(false);
+    DRIVERS_STORAGE.with(|storage| {
+        let stable_btree_map = &*storage.borrow();
+
+        let records: Vec<DriverProfile> = stable_btree_map
+            .iter()
+            .filter(|(_, record)| include_inactive || record.active)
+            .map(|(_, record)| contact_vault::redacted(record))
+            .collect();
+
+        if records.is_empty() {
+            Err(FoodShareError::NotFound {
+                msg: "No drivers found.".to_string(),
+            })
+        } else {
+            Ok(records)
+        }
+    })
+}
+
+// Function to get a donor by id; the owner sees their contact details, others get them blank
+#[ic_cdk::query]
+fn get_donor(id: u64) -> Result<DonorProfile, FoodShareError> {
+    let profile = DONORS_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Donor with id={} not found", id),
+        })?;
+    if profile.owner == Some(ic_cdk::caller()) {
+        Ok(contact_vault::revealed(profile))
+    } else {
+        Ok(contact_vault::redacted(profile))
+    }
+}
+
+// Function to get a receiver by id; the owner sees their contact details, others get them blank
+#[ic_cdk::query]
+fn get_receiver(id: u64) -> Result<ReceiverProfile, FoodShareError> {
+    let profile = RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Receiver with id={} not found", id),
+        })?;
+    if profile.owner == Some(ic_cdk::caller()) {
+        Ok(contact_vault::revealed(profile))
+    } else {
+        Ok(contact_vault::redacted(profile))
+    }
+}
+
+// Function to get a driver by id; the owner sees their contact details, others get them blank
+#[ic_cdk::query]
+fn get_driver(id: u64) -> Result<DriverProfile, FoodShareError> {
+    let profile = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Driver with id={} not found", id),
+        })?;
+    if profile.owner == Some(ic_cdk::caller()) {
+        Ok(contact_vault::revealed(profile))
+    } else {
+        Ok(contact_vault::redacted(profile))
+    }
+}
+
+// Function to soft-delete a donor profile
+#[ic_cdk::update]
+async fn deactivate_donor(donor_id: u64) -> Result<DonorProfile, FoodShareError> {
+    start_trace("deactivate_donor");
+    authorize(EndpointClass::Registration).await?;
+
+    let mut donor_profile = DONORS_STORAGE
+        .with(|storage| storage.borrow().get(&donor_id))
+        .filter(|donor| donor.active)
+        .ok_or(FoodShareError::NotFound {
+            msg: "Donor ID does not exist or is already deactivated".to_string(),
+        })?;
+
+    let caller = ic_cdk::caller();
+    if donor_profile.owner != Some(caller) && !governance::is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the profile owner or governance can deactivate this profile".to_string(),
+        });
+    }
+    let before = donor_profile.clone();
+
+    // Validate that none of the donor's posts are still on their way
+    let donor_key = donor_id.to_string();
+    let has_open_assignments = has_open_assignments(|assignment| {
+        SURPLUS_POSTS_STORAGE
+            .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+            .is_some_and(|post| post.donor_id == donor_key)
+    });
+    if has_open_assignments {
+        return Err(FoodShareError::Conflict {
+            msg: "Donor still has open assignments".to_string(),
+        });
+    }
 
-    if !donor_exists {
-        return Err("Donor ID does not exist".to_string());
+    donor_profile.active = false;
+    donor_profile.deactivated_at = Some(time());
+    DONORS_STORAGE.with(|storage| storage.borrow_mut().insert(donor_id, donor_profile.clone()));
+
+    audit(
+        AuditAction::Deactivated,
+        AuditEntity::Donor,
+        donor_id,
+        Some(&before),
+        Some(&donor_profile),
+    );
+    log(
+        LogLevel::Info,
+        format!("Deactivated donor profile {}", donor_id),
+    );
+
+    Ok(contact_vault::redacted(donor_profile))
+}
+
+// Function to soft-delete a receiver profile
+#[ic_cdk::update]
+async fn deactivate_receiver(receiver_id: u64) -> Result<ReceiverProfile, FoodShareError> {
+    start_trace("deactivate_receiver");
+    authorize(EndpointClass::Registration).await?;
+
+    let mut receiver_profile = RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&receiver_id))
+        .filter(|receiver| receiver.active)
+        .ok_or(FoodShareError::NotFound {
+            msg: "Receiver ID does not exist or is already deactivated".to_string(),
+        })?;
+
+    let caller = ic_cdk::caller();
+    if receiver_profile.owner != Some(caller) && !governance::is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the profile owner or governance can deactivate this profile".to_string(),
+        });
     }
+    let before = receiver_profile.clone();
+
+    if has_open_assignments(|assignment| assignment.receiver_id == receiver_id) {
+        return Err(FoodShareError::Conflict {
+            msg: "Receiver still has open assignments".to_string(),
+        });
+    }
+
+    receiver_profile.active = false;
+    receiver_profile.deactivated_at = Some(time());
+    RECEIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(receiver_id, receiver_profile.clone())
+    });
+
+    audit(
+        AuditAction::Deactivated,
+        AuditEntity::Receiver,
+        receiver_id,
+        Some(&before),
+        Some(&receiver_profile),
+    );
+    log(
+        LogLevel::Info,
+        format!("Deactivated receiver profile {}", receiver_id),
+    );
+
+    Ok(contact_vault::redacted(receiver_profile))
+}
+
+// Function to soft-delete a driver profile
+#[ic_cdk::update]
+async fn deactivate_driver(driver_id: u64) -> Result<DriverProfile, FoodShareError> {
+    start_trace("deactivate_driver");
+    authorize(EndpointClass::Registration).await?;
+
+    let mut driver_profile = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&driver_id))
+        .filter(|driver| driver.active)
+        .ok_or(FoodShareError::NotFound {
+            msg: "Driver ID does not exist or is already deactivated".to_string(),
+        })?;
+
+    let caller = ic_cdk::caller();
+    if driver_profile.owner != Some(caller) && !governance::is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the profile owner or governance can deactivate this profile".to_string(),
+        });
+    }
+    let before = driver_profile.clone();
+
+    if has_open_assignments(|assignment| assignment.driver_id == driver_id) {
+        return Err(FoodShareError::Conflict {
+            msg: "Driver still has open assignments".to_string(),
+        });
+    }
+
+    driver_profile.active = false;
+    driver_profile.deactivated_at = Some(time());
+    DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(driver_id, driver_profile.clone())
+    });
+
+    audit(
+        AuditAction::Deactivated,
+        AuditEntity::Driver,
+        driver_id,
+        Some(&before),
+        Some(&driver_profile),
+    );
+    log(
+        LogLevel::Info,
+        format!("Deactivated driver profile {}", driver_id),
+    );
+
+    Ok(contact_vault::redacted(driver_profile))
+}
+
+// Function to check whether any active assignment matches the predicate
+fn has_open_assignments(matches: impl Fn(&Assignment) -> bool) -> bool {
+    use AssignmentStatus::*;
+    [Pending, Accepted, PickedUp, InTransit]
+        .into_iter()
+        .flat_map(indexes::assignments_with_status)
+        .any(|assignment| matches(&assignment))
+}
+
+// Function to create a new surplus post
+#[ic_cdk::update]
+async fn create_surplus_post(payload: SurplusPostPayload) -> Result<SurplusPost, FoodShareError> {
+    start_trace("create_surplus_post");
+    authorize(EndpointClass::Posting).await?;
+
+    add_surplus_post(payload)
+}
+
+// Function to validate a surplus post payload and store the post
+fn add_surplus_post(mut payload: SurplusPostPayload) -> Result<SurplusPost, FoodShareError> {
+    // Validate the payload to ensure that the required fields are present
+    if payload.donor_id.is_empty()
+        && payload.quantity_kg == 0
+        && payload.best_before_date.is_empty()
+        && payload.handling_instructions.is_empty()
+    {
+        return Err(FoodShareError::Validation {
+            field: "payload".to_string(),
+            reason: "All fields are required".to_string(),
+        });
+    }
+
+    // Validate the payload to ensure that the donor_id exists
+    let donor_id: u64 = payload
+        .donor_id
+        .parse()
+        .map_err(|_| FoodShareError::Validation {
+            field: "donor_id".to_string(),
+            reason: "Invalid donor ID format".to_string(),
+        })?;
+    if !is_active_donor(donor_id) {
+        return Err(FoodShareError::NotFound {
+            msg: "Donor ID does not exist or is deactivated".to_string(),
+        });
+    }
+    geo::validate_coordinates(payload.latitude, payload.longitude)?;
+    profile_completeness::check_posting_allowed(donor_id, payload.quantity_kg)?;
+    verification::check_post_quota(donor_id)?;
+    if let Some(photo_asset_ids) = &payload.photo_asset_ids {
+        validate_post_photos(photo_asset_ids)?;
+    }
+    if let Some(food_category_id) = payload.food_category_id {
+        payload.food_type = taxonomy::food_type_for(food_category_id)?;
+    }
+    if let Some(organization_id) = payload.organization_id {
+        organizations::check_acting_for(organization_id, EntityType::Donor, donor_id)?;
+    }
+    if let Some(allergens) = &mut payload.allergens {
+        allergens.sort();
+        allergens.dedup();
+    }
+    if let Some(dietary_tags) = &mut payload.dietary_tags {
+        dietary_tags.sort();
+        dietary_tags.dedup();
+    }
+    validate_food_tags(
+        payload.allergens.as_deref(),
+        payload.dietary_tags.as_deref(),
+    )?;
 
     // // Validate the payload to ensure that the best_before_date is in the future
     // if payload.best_before_date <= time() {
-    //     return Err("The best before date must be in the future".to_string());
+    //     return Err(FoodShareError::Validation { field: "best_before_date".to_string(), reason: "The best before date must be in the future".to_string() });
     // }
 
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("Cannot increment ID counter");
+    Ok(insert_surplus_post(payload))
+}
+
+// Function to look up the donor's address for a post that has no location yet
+fn locate_post_later(post: &SurplusPost) {
+    let donor = post
+        .donor_id
+        .parse::<u64>()
+        .ok()
+        .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)));
+    if let Some(donor) = donor {
+        geocoding::geocode_later(GeocodeTarget::SurplusPost(post.id), &donor.address);
+    }
+}
+
+// Function to store a validated surplus post and publish it to the open listing
+fn insert_surplus_post(payload: SurplusPostPayload) -> SurplusPost {
+    let id = allocate_id(IdEntity::SurplusPost);
+
+    // Posts without their own coordinates are picked up at the donor's location
+    let (latitude, longitude) = match (payload.latitude, payload.longitude) {
+        (None, None) => payload
+            .donor_id
+            .parse::<u64>()
+            .ok()
+            .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)))
+            .map(|donor| (donor.latitude, donor.longitude))
+            .unwrap_or_default(),
+        coordinates => coordinates,
+    };
 
     let surplus_post = SurplusPost {
         id,
@@ -508,16 +1754,315 @@ fn create_surplus_post(payload: SurplusPostPayload) -> Result<SurplusPost, Strin
         best_before_date: payload.best_before_date,
         handling_instructions: payload.handling_instructions,
         assigned: false,
+        withdrawn_at: None,
+        expired_at: None,
+        reach_radius_km: radius_expansion::initial_reach(latitude),
+        created_at: Some(time()),
+        photo_asset_ids: payload.photo_asset_ids,
+        food_category_id: payload
+            .food_category_id
+            .or_else(|| taxonomy::builtin_food_category(payload.food_type)),
+        organization_id: payload.organization_id,
+        latitude,
+        longitude,
+        allergens: payload.allergens,
+        dietary_tags: payload.dietary_tags,
+        reserved_kg: None,
     };
 
-    SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow_mut().insert(id, surplus_post.clone()));
+    indexes::store_post(&surplus_post);
+    browse::index_open_post(&surplus_post);
+    radius_expansion::track_post(&surplus_post);
+    if surplus_post.latitude.is_none() {
+        locate_post_later(&surplus_post);
+    }
+    audit(
+        AuditAction::Created,
+        AuditEntity::SurplusPost,
+        id,
+        None,
+        Some(&surplus_post),
+    );
+
+    record_event(
+        EntityType::SurplusPost,
+        id,
+        None,
+        EventKind::Created,
+        format!(
+            "{} kg of {:?} posted by donor {}",
+            surplus_post.quantity_kg, surplus_post.food_type, surplus_post.donor_id
+        ),
+    );
+    log(LogLevel::Info, format!("Created surplus post {}", id));
+
+    surplus_post
+}
+
+// Function to check that a post's dietary tags do not contradict its allergens
+fn validate_food_tags(
+    allergens: Option<&[Allergen]>,
+    dietary_tags: Option<&[DietaryTag]>,
+) -> Result<(), FoodShareError> {
+    let allergens = allergens.unwrap_or_default();
+    for tag in dietary_tags.unwrap_or_default() {
+        let excluded: &[Allergen] = match tag {
+            DietaryTag::Vegan => &[
+                Allergen::Milk,
+                Allergen::Eggs,
+                Allergen::Fish,
+                Allergen::Shellfish,
+            ],
+            DietaryTag::GlutenFree => &[Allergen::Wheat],
+            DietaryTag::Halal | DietaryTag::Kosher => &[],
+        };
+        if let Some(allergen) = allergens
+            .iter()
+            .find(|allergen| excluded.contains(allergen))
+        {
+            return Err(FoodShareError::Validation {
+                field: "dietary_tags".to_string(),
+                reason: format!(
+                    "A post containing {:?} cannot be tagged {:?}",
+                    allergen, tag
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Function to check that a post's photos are few enough and are images the caller uploaded
+fn validate_post_photos(photo_asset_ids: &[u64]) -> Result<(), FoodShareError> {
+    if photo_asset_ids.len() > MAX_POST_PHOTOS {
+        return Err(FoodShareError::Validation {
+            field: "photo_asset_ids".to_string(),
+            reason: format!("A post can have at most {} photos", MAX_POST_PHOTOS),
+        });
+    }
+    for asset_id in photo_asset_ids {
+        if !assets::owned_asset(*asset_id)?.is_image() {
+            return Err(FoodShareError::Validation {
+                field: "photo_asset_ids".to_string(),
+                reason: format!("Asset {} is not an image", asset_id),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Function to check that the caller registered the donor profile behind a post
+fn is_post_donor(post: &SurplusPost) -> bool {
+    let caller = ic_cdk::caller();
+    post.donor_id
+        .parse::<u64>()
+        .ok()
+        .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)))
+        .is_some_and(|donor| donor.owner == Some(caller))
+}
+
+// Function for the posting donor to correct a surplus post before it is assigned
+#[ic_cdk::update]
+async fn update_surplus_post(
+    post_id: u64,
+    payload: UpdateSurplusPostPayload,
+) -> Result<SurplusPost, FoodShareError> {
+    start_trace("update_surplus_post");
+    authorize(EndpointClass::Posting).await?;
+
+    let mut surplus_post = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&post_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Surplus post ID does not exist".to_string(),
+        })?;
+
+    if !is_post_donor(&surplus_post) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the posting donor can update this post".to_string(),
+        });
+    }
+    if surplus_post.withdrawn_at.is_some() {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus post has been withdrawn".to_string(),
+        });
+    }
+    if surplus_post.expired_at.is_some() {
+        return Err(FoodShareError::Expired {
+            msg: "Surplus post has expired".to_string(),
+        });
+    }
+    if surplus_post.assigned {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus post can no longer be changed once assigned".to_string(),
+        });
+    }
+    let before = surplus_post.clone();
+
+    if let Some(food_category_id) = payload.food_category_id {
+        surplus_post.food_type = taxonomy::food_type_for(food_category_id)?;
+        surplus_post.food_category_id = Some(food_category_id);
+    } else if let Some(food_type) = payload.food_type {
+        surplus_post.food_type = food_type;
+        surplus_post.food_category_id = taxonomy::builtin_food_category(food_type);
+    }
+
+    if let Some(quantity_kg) = payload.quantity_kg {
+        // Validate the payload to ensure that the quantity is present
+        if quantity_kg == 0 {
+            return Err(FoodShareError::Validation {
+                field: "quantity_kg".to_string(),
+                reason: "Quantity must be greater than zero".to_string(),
+            });
+        }
+        let reserved_kg = surplus_post.reserved_kg.unwrap_or_default();
+        if quantity_kg < reserved_kg {
+            return Err(FoodShareError::Validation {
+                field: "quantity_kg".to_string(),
+                reason: format!("Receivers have already reserved {} kg", reserved_kg),
+            });
+        }
+        if let Ok(donor_id) = surplus_post.donor_id.parse() {
+            profile_completeness::check_posting_allowed(donor_id, quantity_kg)?;
+        }
+        surplus_post.quantity_kg = quantity_kg;
+    }
+
+    if let Some(best_before_date) = payload.best_before_date {
+        if best_before_date.is_empty() {
+            return Err(FoodShareError::Validation {
+                field: "best_before_date".to_string(),
+                reason: "Best before date cannot be empty".to_string(),
+            });
+        }
+        surplus_post.best_before_date = best_before_date;
+    }
+
+    if let Some(handling_instructions) = payload.handling_instructions {
+        surplus_post.handling_instructions = handling_instructions;
+    }
+
+    if payload.latitude.is_some() || payload.longitude.is_some() {
+        geo::validate_coordinates(payload.latitude, payload.longitude)?;
+        surplus_post.latitude = payload.latitude;
+        surplus_post.longitude = payload.longitude;
+    }
+
+    if let Some(photo_asset_ids) = payload.photo_asset_ids {
+        validate_post_photos(&photo_asset_ids)?;
+        surplus_post.photo_asset_ids = Some(photo_asset_ids);
+    }
+
+    indexes::store_post(&surplus_post);
+    browse::index_open_post(&surplus_post);
+    audit(
+        AuditAction::Updated,
+        AuditEntity::SurplusPost,
+        post_id,
+        Some(&before),
+        Some(&surplus_post),
+    );
+
+    record_event(
+        EntityType::SurplusPost,
+        post_id,
+        None,
+        EventKind::Updated,
+        format!(
+            "Post updated to {} kg of {:?}",
+            surplus_post.quantity_kg, surplus_post.food_type
+        ),
+    );
+    log(LogLevel::Info, format!("Updated surplus post {}", post_id));
+
+    Ok(surplus_post)
+}
+
+// Function for the posting donor to pull a post before pickup; a pending or
+// accepted assignment on it is cancelled
+#[ic_cdk::update]
+async fn withdraw_surplus_post(post_id: u64) -> Result<SurplusPost, FoodShareError> {
+    start_trace("withdraw_surplus_post");
+    authorize(EndpointClass::Posting).await?;
+
+    let mut surplus_post = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&post_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Surplus post ID does not exist".to_string(),
+        })?;
+
+    if !is_post_donor(&surplus_post) && !governance::is_governance_or_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the posting donor can withdraw this post".to_string(),
+        });
+    }
+    if surplus_post.withdrawn_at.is_some() {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus post has already been withdrawn".to_string(),
+        });
+    }
+    if surplus_post.expired_at.is_some() {
+        return Err(FoodShareError::Expired {
+            msg: "Surplus post has expired".to_string(),
+        });
+    }
+
+    let assignments = indexes::assignments_for_post(post_id);
+    let picked_up = assignments.iter().any(|assignment| {
+        !assignment.status.releases_post()
+            && !assignment.status.can_move_to(AssignmentStatus::Cancelled)
+    });
+    if picked_up {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus post has already been picked up".to_string(),
+        });
+    }
+
+    for assignment in assignments
+        .into_iter()
+        .filter(|assignment| assignment.status.is_active())
+    {
+        let assignment = assignment_lifecycle::store_transition(
+            assignment,
+            AssignmentStatus::Cancelled,
+            "Assignment cancelled: the donor withdrew the surplus post".to_string(),
+        )?;
+        reservations::record_reservation_outcome(&assignment, ReservationOutcome::Cancelled);
+        let message = format!(
+            "Assignment {} was cancelled because the donor withdrew surplus post {}",
+            assignment.id, post_id
+        );
+        notifications::notify_assignment(&assignment, NotificationKind::PostWithdrawn, &message);
+        assignment_messages::post_system_message(assignment.id, &message);
+    }
+    split_donations::close_post_reservations(post_id);
+
+    let before = surplus_post.clone();
+    surplus_post.withdrawn_at = Some(time());
+    indexes::store_post(&surplus_post);
+    browse::remove_open_post(post_id);
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::SurplusPost,
+        post_id,
+        Some(&before),
+        Some(&surplus_post),
+    );
+
+    record_event(
+        EntityType::SurplusPost,
+        post_id,
+        None,
+        EventKind::Updated,
+        "Post withdrawn by the donor".to_string(),
+    );
+    log(LogLevel::Info, format!("Withdrew surplus post {}", post_id));
 
     Ok(surplus_post)
 }
 
 // Function to get all surplus posts
 #[ic_cdk::query]
-fn get_all_surplus_posts() -> Result<Vec<SurplusPost>, Error> {
+fn get_all_surplus_posts() -> Result<Vec<SurplusPost>, FoodShareError> {
     SURPLUS_POSTS_STORAGE.with(|storage| {
         let stable_btree_map = &*storage.borrow();
 
@@ -527,7 +2072,7 @@ fn get_all_surplus_posts() -> Result<Vec<SurplusPost>, Error> {
             .collect();
 
         if records.is_empty() {
-            Err(Error::NotFound {
+            Err(FoodShareError::NotFound {
                 msg: "No surplus posts found.".to_string(),
             })
         } else {
@@ -536,9 +2081,19 @@ fn get_all_surplus_posts() -> Result<Vec<SurplusPost>, Error> {
     })
 }
 
+// Function to get a surplus post by id
+#[ic_cdk::query]
+fn get_surplus_post(id: u64) -> Result<SurplusPost, FoodShareError> {
+    SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Surplus post with id={} not found", id),
+        })
+}
+
 // Function to get surplus post by food type
 #[ic_cdk::query]
-fn get_surplus_post_by_food_type(food_type: FoodType) -> Result<Vec<SurplusPost>, Error> {
+fn get_surplus_post_by_food_type(food_type: FoodType) -> Result<Vec<SurplusPost>, FoodShareError> {
     SURPLUS_POSTS_STORAGE.with(|storage| {
         let stable_btree_map = &*storage.borrow();
 
@@ -549,7 +2104,7 @@ fn get_surplus_post_by_food_type(food_type: FoodType) -> Result<Vec<SurplusPost>
             .collect();
 
         if records.is_empty() {
-            Err(Error::NotFound {
+            Err(FoodShareError::NotFound {
                 msg: "No surplus posts found.".to_string(),
             })
         } else {
@@ -559,135 +2114,531 @@ fn get_surplus_post_by_food_type(food_type: FoodType) -> Result<Vec<SurplusPost>
 }
 
 #[ic_cdk::update]
-fn create_assignment(payload: AssignmentPayload) -> Result<Assignment, String> {
+async fn create_assignment(payload: AssignmentPayload) -> Result<Assignment, FoodShareError> {
+    start_trace("create_assignment");
+    authorize(EndpointClass::Logistics).await?;
+
+    assign_post(payload)
+}
+
+// Function to validate and create an assignment once the caller has been authorized
+fn assign_post(payload: AssignmentPayload) -> Result<Assignment, FoodShareError> {
     // Validate the payload to ensure that the required fields are present
     if payload.surplus_post_id == 0 || payload.driver_id == 0 || payload.receiver_id == 0 {
-        return Err("All fields are required".to_string());
+        return Err(FoodShareError::Validation {
+            field: "payload".to_string(),
+            reason: "All fields are required".to_string(),
+        });
     }
 
     // Validate the payload to ensure that the receiver_id exists
-    let receiver_exists =
-        RECEIVERS_STORAGE.with(|storage| storage.borrow().contains_key(&payload.receiver_id));
-    if !receiver_exists {
-        return Err("Receiver ID does not exist".to_string());
+    if !is_active_receiver(payload.receiver_id) {
+        return Err(FoodShareError::NotFound {
+            msg: "Receiver ID does not exist or is deactivated".to_string(),
+        });
     }
 
     // Validate the payload to ensure that the surplus_post_id exists
-    let surplus_post_exists = SURPLUS_POSTS_STORAGE
-        .with(|storage| storage.borrow().contains_key(&payload.surplus_post_id));
-    if !surplus_post_exists {
-        return Err("Surplus post ID does not exist".to_string());
+    let surplus_post = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.surplus_post_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Surplus post ID does not exist".to_string(),
+        })?;
+
+    if surplus_post.withdrawn_at.is_some() {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus post has been withdrawn".to_string(),
+        });
+    }
+    if surplus_post.expired_at.is_some() {
+        return Err(FoodShareError::Expired {
+            msg: "Surplus post has expired".to_string(),
+        });
     }
 
-    // Validate the payload to ensure that the driver_id exists
-    let driver_exists =
-        DRIVERS_STORAGE.with(|storage| storage.borrow().contains_key(&payload.driver_id));
-    if !driver_exists {
-        return Err("Driver ID does not exist".to_string());
+    // Validate that the post is not held for another receiver by a match
+    if matching::matched_receiver(payload.surplus_post_id)
+        .is_some_and(|receiver_id| receiver_id != payload.receiver_id)
+    {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus post is matched to another receiver".to_string(),
+        });
     }
 
-    // Validate the payload to ensure that the surplus_post_id is not already assigned
-    let already_assigned = ASSIGNMENTS_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .any(|(_, assignment)| assignment.surplus_post_id == payload.surplus_post_id)
-    });
-    if already_assigned {
-        return Err("Surplus post ID is already assigned".to_string());
+    // Validate that the donor has not approved another receiver's claim
+    if claims::approved_receiver(payload.surplus_post_id)
+        .is_some_and(|receiver_id| receiver_id != payload.receiver_id)
+    {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus post is claimed by another receiver".to_string(),
+        });
     }
 
-    // Validate the payload to ensure that the driver_id is not already assigned to another post
-    let driver_assigned = ASSIGNMENTS_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .any(|(_, assignment)| assignment.driver_id == payload.driver_id)
-    });
-    if driver_assigned {
-        return Err("Driver ID is already assigned to another post".to_string());
+    // Validate that the receiver currently accepts this food type
+    if category_pauses::is_food_type_paused(payload.receiver_id, surplus_post.food_type) {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Receiver is not accepting {:?} at the moment",
+                surplus_post.food_type
+            ),
+        });
     }
 
+    // Validate the payload to ensure that the driver_id exists
+    let driver = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.driver_id))
+        .filter(|driver| driver.active)
+        .ok_or(FoodShareError::NotFound {
+            msg: "Driver ID does not exist or is deactivated".to_string(),
+        })?;
+    driver_load::check_weekly_cap(&driver)?;
+    cancellation_policy::check_not_suspended(&driver)?;
+
+    // A split post is delivered one reservation at a time; any other post by a single assignment
+    let reservation = match payload.reservation_id {
+        Some(reservation_id) => Some(split_donations::assignable_reservation(
+            reservation_id,
+            &payload,
+        )?),
+        None => {
+            if split_donations::has_live_reservations(payload.surplus_post_id) {
+                return Err(FoodShareError::Conflict {
+                    msg: "Surplus post is split between receivers; assign a reservation instead"
+                        .to_string(),
+                });
+            }
+            // Validate the payload to ensure that the surplus_post_id is not already assigned
+            let already_assigned = indexes::assignments_for_post(payload.surplus_post_id)
+                .iter()
+                .any(|assignment| !assignment.status.releases_post());
+            if already_assigned {
+                return Err(FoodShareError::Conflict {
+                    msg: "Surplus post ID is already assigned".to_string(),
+                });
+            }
+            None
+        }
+    };
+    let quantity_kg = reservation
+        .as_ref()
+        .map(|reservation| reservation.quantity_kg);
+
+    // Validate that the driver is on shift with room for the load
+    driver_availability::check_can_take(&driver, quantity_kg.unwrap_or(surplus_post.quantity_kg))?;
+    vehicles::check_refrigeration(&driver, surplus_post.food_type)?;
+
     // Increment the ID counter and create the assignment
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("Cannot increment ID counter");
+    let id = allocate_id(IdEntity::Assignment);
 
+    let now = time();
     let assignment = Assignment {
         id,
         surplus_post_id: payload.surplus_post_id,
         receiver_id: payload.receiver_id,
         driver_id: payload.driver_id,
-        status: "Pending".to_string(),
-        created_at: time(),
+        status: AssignmentStatus::Pending,
+        status_history: vec![StatusChange {
+            status: AssignmentStatus::Pending,
+            changed_at: now,
+        }],
+        previous_leg_id: None,
+        created_at: now,
+        pickup_window_start: None,
+        pickup_window_end: None,
+        pickup_proposal: None,
+        quantity_kg,
     };
 
-    ASSIGNMENTS_STORAGE.with(|storage| storage.borrow_mut().insert(id, assignment.clone()));
+    indexes::store_assignment(&assignment);
+    status_codes::issue_reference(id);
+    audit(
+        AuditAction::Created,
+        AuditEntity::Assignment,
+        id,
+        None,
+        Some(&assignment),
+    );
+
+    match &reservation {
+        // The reserved share already left the open quantity when it was reserved
+        Some(reservation) => split_donations::mark_assigned(reservation, id),
+        None => {
+            // Mark the surplus post as assigned so it leaves the open listing
+            let mut assigned_post = surplus_post.clone();
+            assigned_post.assigned = true;
+            indexes::store_post(&assigned_post);
+            audit(
+                AuditAction::StatusChanged,
+                AuditEntity::SurplusPost,
+                assigned_post.id,
+                Some(&surplus_post),
+                Some(&assigned_post),
+            );
+            browse::remove_open_post(payload.surplus_post_id);
+        }
+    }
+    reservations::record_reservation(&assignment);
+
+    record_event(
+        EntityType::Assignment,
+        id,
+        Some(id),
+        EventKind::Created,
+        format!(
+            "Driver {} assigned to deliver to receiver {}",
+            assignment.driver_id, assignment.receiver_id
+        ),
+    );
+    record_event(
+        EntityType::SurplusPost,
+        assignment.surplus_post_id,
+        Some(id),
+        EventKind::Assigned,
+        format!("Surplus post assigned under assignment {}", id),
+    );
+    notifications::notify_assignment(
+        &assignment,
+        NotificationKind::PostAssigned,
+        &format!(
+            "Surplus post {} was assigned to driver {} for receiver {}",
+            assignment.surplus_post_id, assignment.driver_id, assignment.receiver_id
+        ),
+    );
+    log(LogLevel::Info, format!("Created assignment {}", id));
 
     Ok(assignment)
 }
 
-// Function to create a new surplus record
+// Function to get an assignment by id
+#[ic_cdk::query]
+fn get_assignment(id: u64) -> Result<Assignment, FoodShareError> {
+    ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Assignment with id={} not found", id),
+        })
+}
+
+// Function for a driver to submit the delivery of a surplus post; the surplus
+// record is created once the receiver confirms it
 #[ic_cdk::update]
-fn create_surplus_record(payload: SurplusRecordPayload) -> Result<SurplusRecord, String> {
+async fn create_surplus_record(
+    payload: SurplusRecordPayload,
+) -> Result<Assignment, FoodShareError> {
+    start_trace("create_surplus_record");
+    authorize(EndpointClass::Logistics).await?;
+
     // Validate the payload to ensure that the required fields are present
     if payload.surplus_post_id == 0 && payload.driver_id == 0 {
-        return Err("All fields are required".to_string());
+        return Err(FoodShareError::Validation {
+            field: "payload".to_string(),
+            reason: "All fields are required".to_string(),
+        });
     }
 
     // Validate the payload to ensure that the surplus_post_id exists
     let surplus_post_exists = SURPLUS_POSTS_STORAGE
         .with(|storage| storage.borrow().contains_key(&payload.surplus_post_id));
     if !surplus_post_exists {
-        return Err("Surplus post ID does not exist".to_string());
+        return Err(FoodShareError::NotFound {
+            msg: "Surplus post ID does not exist".to_string(),
+        });
     }
-    
+
     // Validate the payload to ensure that the driver_id exists
     let driver_exists =
         DRIVERS_STORAGE.with(|storage| storage.borrow().contains_key(&payload.driver_id));
     if !driver_exists {
-        return Err("Driver ID does not exist".to_string());
+        return Err(FoodShareError::NotFound {
+            msg: "Driver ID does not exist".to_string(),
+        });
     }
 
     // Validate the payload to ensure that the surplus_post_id is already assigned to the driver id
-    let already_assigned = ASSIGNMENTS_STORAGE.with(|storage| {
-        storage
-            .borrow()
-            .iter()
-            .any(|(_, assignment)| assignment.surplus_post_id == payload.surplus_post_id && assignment.driver_id == payload.driver_id)
-    });
-    if !already_assigned {
-        return Err("Surplus post ID is not assigned to the driver ID".to_string());
-    }
-    
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("Cannot increment ID counter");
+    let assignment = indexes::assignments_for_post(payload.surplus_post_id)
+        .into_iter()
+        .find(|assignment| {
+            assignment.driver_id == payload.driver_id && assignment.status.is_active()
+        });
+    match assignment {
+        Some(assignment) => {
+            delivery_proofs::submit_delivery(assignment, DeliveryProofPayload::default())
+        }
+        None => Err(FoodShareError::Conflict {
+            msg: "Surplus post ID is not assigned to the driver ID".to_string(),
+        }),
+    }
+}
+
+// Function to mark an assignment delivered and record the delivery
+fn deliver_assignment(mut assignment: Assignment) -> Result<SurplusRecord, FoodShareError> {
+    let before = assignment.clone();
+    assignment.transition(AssignmentStatus::Delivered)?;
+    let assignment_id = assignment.id;
+
+    let id = allocate_id(IdEntity::SurplusRecord);
 
     let surplus_record = SurplusRecord {
         id,
-        surplus_post_id: payload.surplus_post_id,
-        driver_id: payload.driver_id,
+        surplus_post_id: assignment.surplus_post_id,
+        driver_id: assignment.driver_id,
         delivered_at: time(),
         rating: None,
+        confirmed_at: None,
+        beneficiaries: None,
+        meal_program: None,
+        assignment_id: Some(assignment_id),
     };
 
-    SURPLUS_RECORDS_STORAGE.with(|storage| storage.borrow_mut().insert(id, surplus_record.clone()));
+    indexes::store_record(&surplus_record);
+    audit(
+        AuditAction::Created,
+        AuditEntity::SurplusRecord,
+        id,
+        None,
+        Some(&surplus_record),
+    );
+
+    // Close the hold on the surplus post now that it has been delivered
+    indexes::store_assignment(&assignment);
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::Assignment,
+        assignment_id,
+        Some(&before),
+        Some(&assignment),
+    );
+    reservations::record_reservation_outcome(&assignment, ReservationOutcome::Converted);
+    payouts::queue_delivery_reward(&surplus_record, &assignment);
+    reputation::record_delivery(&surplus_record);
+    analytics::record_delivery_impact(&surplus_record, &assignment);
+
+    record_event(
+        EntityType::SurplusRecord,
+        id,
+        Some(assignment_id),
+        EventKind::Delivered,
+        format!(
+            "Driver {} delivered the surplus post",
+            surplus_record.driver_id
+        ),
+    );
+    notifications::notify_assignment(
+        &assignment,
+        NotificationKind::DeliveryCompleted,
+        &format!(
+            "Surplus post {} was delivered to receiver {}",
+            assignment.surplus_post_id, assignment.receiver_id
+        ),
+    );
+    log(LogLevel::Info, format!("Created surplus record {}", id));
 
     Ok(surplus_record)
 }
 
-// Error types
+// Function to get a surplus record by id
+#[ic_cdk::query]
+fn get_surplus_record(id: u64) -> Result<SurplusRecord, FoodShareError> {
+    SURPLUS_RECORDS_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Surplus record with id={} not found", id),
+        })
+}
+
+// Function to create a new food request
+#[ic_cdk::update]
+async fn create_food_request(payload: FoodRequestPayload) -> Result<FoodRequest, FoodShareError> {
+    start_trace("create_food_request");
+    authorize(EndpointClass::Posting).await?;
+
+    // Validate the payload to ensure that the requested quantity is present
+    if payload.quantity_kg == 0 {
+        return Err(FoodShareError::Validation {
+            field: "quantity_kg".to_string(),
+            reason: "Quantity must be greater than zero".to_string(),
+        });
+    }
+
+    validation::check_max_len("notes", &payload.notes, validation::MAX_REQUEST_NOTES_LEN)?;
+
+    // Validate the payload to ensure that the receiver_id exists
+    if !is_active_receiver(payload.receiver_id) {
+        return Err(FoodShareError::NotFound {
+            msg: "Receiver ID does not exist or is deactivated".to_string(),
+        });
+    }
+
+    if payload
+        .needed_by
+        .is_some_and(|needed_by| needed_by <= time())
+    {
+        return Err(FoodShareError::Validation {
+            field: "needed_by".to_string(),
+            reason: "Needed-by time must be in the future".to_string(),
+        });
+    }
+    if let Some(organization_id) = payload.organization_id {
+        organizations::check_acting_for(
+            organization_id,
+            EntityType::Receiver,
+            payload.receiver_id,
+        )?;
+    }
+
+    let id = allocate_id(IdEntity::FoodRequest);
+
+    let food_request = FoodRequest {
+        id,
+        receiver_id: payload.receiver_id,
+        food_type: payload.food_type,
+        quantity_kg: payload.quantity_kg,
+        notes: payload.notes,
+        assigned: false,
+        created_at: time(),
+        status: Some(FoodRequestStatus::Open),
+        quantity_fulfilled_kg: Some(0),
+        fulfilled_post_ids: None,
+        needed_by: payload.needed_by,
+        organization_id: payload.organization_id,
+    };
+
+    FOOD_REQUESTS_STORAGE.with(|storage| storage.borrow_mut().insert(id, food_request.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::FoodRequest,
+        id,
+        None,
+        Some(&food_request),
+    );
+
+    record_event(
+        EntityType::FoodRequest,
+        id,
+        None,
+        EventKind::Created,
+        format!(
+            "Receiver {} requested {} kg of {:?}",
+            food_request.receiver_id, food_request.quantity_kg, food_request.food_type
+        ),
+    );
+    log(LogLevel::Info, format!("Created food request {}", id));
+
+    Ok(food_request)
+}
+
+// Function to get all food requests
+#[ic_cdk::query]
+fn get_all_food_requests() -> Result<Vec<FoodRequest>, FoodShareError> {
+    FOOD_REQUESTS_STORAGE.with(|storage| {
+        let stable_btree_map = &*storage.borrow();
+
+        let records: Vec<FoodRequest> = stable_btree_map
+            .iter()
+            .map(|(_, record)| record.clone())
+            .collect();
+
+        if records.is_empty() {
+            Err(FoodShareError::NotFound {
+                msg: "No food requests found.".to_string(),
+            })
+        } else {
+            Ok(records)
+        }
+    })
+}
+
+// Function to get the principal registering a profile; anonymous callers cannot own one
+fn registering_principal() -> Result<Principal, FoodShareError> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Anonymous callers cannot register profiles".to_string(),
+        });
+    }
+    Ok(caller)
+}
+
+// Functions to check that a profile exists and has not been deactivated
+fn is_active_donor(donor_id: u64) -> bool {
+    DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id).is_some_and(|d| d.active))
+}
+
+fn is_active_receiver(receiver_id: u64) -> bool {
+    RECEIVERS_STORAGE.with(|storage| storage.borrow().get(&receiver_id).is_some_and(|r| r.active))
+}
+
+// Function to allocate the next id from the sequence shared by collections
+// without their own
+fn next_id() -> u64 {
+    allocate_id(IdEntity::Shared)
+}
+
+// The install argument names the first admin; later admins are added with add_admin
+#[ic_cdk::init]
+fn init(admin: Principal) {
+    governance::bootstrap_admin(admin);
+    taxonomy::migrate_taxonomy();
+    start_timers();
+}
+
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    upgrade_state::save_state();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    upgrade_state::restore_state();
+    legacy_profiles::migrate_profiles();
+    indexes::migrate_indexes();
+    webhooks::migrate_delivery_indexes();
+    contact_vault::migrate_access_index();
+    taxonomy::migrate_taxonomy();
+    start_timers();
+    certification::certify();
+}
+
+// Function to register the periodic jobs, which do not survive upgrades
+fn start_timers() {
+    reservations::start_hold_sweep();
+    tickets::start_sla_sweep();
+    frontend_notify::start_notification_flush();
+    storage_stats::start_usage_check();
+    post_expiry::start_expiry_sweep();
+    maintenance::resume_maintenance();
+    transparency::start_transparency_refresh();
+    notifications::start_notification_pruning();
+    radius_expansion::start_radius_expansion();
+    recurring_posts::start_template_sweep();
+    food_requests::start_request_expiry();
+    delivery_proofs::start_confirmation_sweep();
+    pickup_confirmations::start_pickup_confirmation_sweep();
+    assets::start_upload_pruning();
+    claims::start_claim_sweep();
+    webhooks::start_webhook_delivery();
+}
+
+// Error types, shared by every endpoint so the Candid interface documents how calls can fail
 #[derive(candid::CandidType, Deserialize, Serialize)]
-enum Error {
+enum FoodShareError {
     NotFound { msg: String },
-    UnAuthorized { msg: String },
+    Unauthorized { msg: String },
+    // Names the offending payload field
+    Validation { field: String, reason: String },
+    // The request clashes with the current state, e.g. it was already done
+    Conflict { msg: String },
+    QuotaExceeded { msg: String },
+    Expired { msg: String },
+    // A call to another canister or the system API failed
+    InternalError { msg: String },
+}
+
+// Function to get the Candid interface of the canister, under the name dfx and
+// agent-js look for, so clients can be generated from a running canister
+#[ic_cdk::query(name = "__get_candid_interface_tmp_hack")]
+fn get_candid_interface() -> String {
+    __export_service()
 }
 
 // need this to generate candid
@@ -0,0 +1,503 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::require_admin;
+use crate::notifications::{NotificationKind, MAX_PAYLOAD_LEN};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::taxonomy::load_category;
+use crate::{next_id, FoodShareError, Memory, StorablePrincipal, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_LOCALE_LEN: usize = 10;
+const MAX_LABEL_LEN: usize = 64;
+const MAX_LOCALES_PER_CATEGORY: usize = 20;
+
+// Values every notification about an assignment can fill in
+const ASSIGNMENT_PLACEHOLDERS: &[&str] = &[
+    "message",
+    "post_id",
+    "assignment_id",
+    "driver_id",
+    "receiver_id",
+];
+// Values the expiry warning to a donor can fill in
+const POST_PLACEHOLDERS: &[&str] = &[
+    "message",
+    "post_id",
+    "quantity_kg",
+    "food_type",
+    "best_before_date",
+];
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct LocalizedLabel {
+    locale: String,
+    label: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CategoryLabels {
+    category_id: u64,
+    labels: Vec<LocalizedLabel>,
+}
+
+impl Storable for CategoryLabels {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for CategoryLabels {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct NotificationTemplate {
+    id: u64,
+    kind: NotificationKind,
+    locale: String,
+    // Text with {placeholder} names filled in when the notification is sent
+    body: String,
+    updated_by: Principal,
+    updated_at: u64,
+}
+
+impl Storable for NotificationTemplate {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for NotificationTemplate {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A principal's preferred locale
+struct StoredLocale(String);
+
+impl Storable for StoredLocale {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(self.0.as_bytes())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        StoredLocale(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+impl BoundedStorable for StoredLocale {
+    const MAX_SIZE: u32 = MAX_LOCALE_LEN as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Keyed by category
+    static CATEGORY_LABELS: RefCell<StableBTreeMap<u64, CategoryLabels, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(69)))
+    ));
+
+    static NOTIFICATION_TEMPLATES: RefCell<StableBTreeMap<u64, NotificationTemplate, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(70)))
+    ));
+
+    static PREFERRED_LOCALES: RefCell<StableBTreeMap<StorablePrincipal, StoredLocale, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(71)))
+    ));
+}
+
+// Function to check a locale code such as "sw", "fr" or "pt-BR" and normalise its case
+pub(crate) fn normalize_locale(locale: &str) -> Result<String, FoodShareError> {
+    let invalid = || FoodShareError::Validation {
+        field: "locale".to_string(),
+        reason: "Locale must be a language code such as sw, fr or pt-BR".to_string(),
+    };
+    let (language, region) = match locale.trim().split_once('-') {
+        Some((language, region)) => (language, Some(region)),
+        None => (locale.trim(), None),
+    };
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(invalid());
+    }
+    let mut normalized = language.to_ascii_lowercase();
+    if let Some(region) = region {
+        if !(2..=3).contains(&region.len()) || !region.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(invalid());
+        }
+        normalized.push('-');
+        normalized.push_str(&region.to_ascii_uppercase());
+    }
+    Ok(normalized)
+}
+
+fn placeholders(kind: NotificationKind) -> &'static [&'static str] {
+    match kind {
+        NotificationKind::PostExpiring => POST_PLACEHOLDERS,
+        _ => ASSIGNMENT_PLACEHOLDERS,
+    }
+}
+
+// Function to check that a template fits a notification and only uses the
+// placeholders its kind fills in
+fn validate_template(kind: NotificationKind, body: &str) -> Result<(), FoodShareError> {
+    if body.trim().is_empty() || body.chars().count() > MAX_PAYLOAD_LEN {
+        return Err(FoodShareError::Validation {
+            field: "body".to_string(),
+            reason: format!(
+                "Template must be between 1 and {} characters",
+                MAX_PAYLOAD_LEN
+            ),
+        });
+    }
+    let mut rest = body;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            return Err(FoodShareError::Validation {
+                field: "body".to_string(),
+                reason: "Template has an unclosed placeholder".to_string(),
+            });
+        };
+        let name = &rest[start + 1..start + end];
+        if !placeholders(kind).contains(&name) {
+            return Err(FoodShareError::Validation {
+                field: "body".to_string(),
+                reason: format!(
+                    "{:?} notifications can use {{{}}}, not {{{}}}",
+                    kind,
+                    placeholders(kind).join("}, {"),
+                    name
+                ),
+            });
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+fn render(body: &str, values: &[(&str, String)]) -> String {
+    values.iter().fold(body.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+fn find_template(kind: NotificationKind, locale: &str) -> Option<NotificationTemplate> {
+    NOTIFICATION_TEMPLATES.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, template)| template)
+            .find(|template| template.kind == kind && template.locale == locale)
+    })
+}
+
+// Function to get the preferred locale of a principal, if they set one
+pub(crate) fn preferred_locale(principal: Principal) -> Option<String> {
+    PREFERRED_LOCALES
+        .with(|storage| storage.borrow().get(&StorablePrincipal(principal)))
+        .map(|locale| locale.0)
+}
+
+// Function to render a notification in the recipient's locale. Falls back from
+// a regional locale to its language; None when no template applies, so the
+// caller sends its own text.
+pub(crate) fn localized_notification(
+    recipient: Principal,
+    kind: NotificationKind,
+    values: &[(&str, String)],
+) -> Option<String> {
+    let locale = preferred_locale(recipient)?;
+    let language = locale.split('-').next().unwrap_or(&locale);
+    let template = find_template(kind, &locale).or_else(|| find_template(kind, language))?;
+    Some(render(&template.body, values))
+}
+
+// Function to get the label of a category in a locale, falling back from a
+// regional locale to its language
+pub(crate) fn category_label(category_id: u64, locale: &str) -> Option<String> {
+    let labels = CATEGORY_LABELS.with(|storage| storage.borrow().get(&category_id))?;
+    let language = locale.split('-').next().unwrap_or(locale);
+    [locale, language].into_iter().find_map(|wanted| {
+        labels
+            .labels
+            .iter()
+            .find(|label| label.locale == wanted)
+            .map(|label| label.label.clone())
+    })
+}
+
+// Function for admins to add or replace the label of a category in a locale
+#[ic_cdk::update]
+fn set_category_label(
+    category_id: u64,
+    locale: String,
+    label: String,
+) -> Result<CategoryLabels, FoodShareError> {
+    start_trace("set_category_label");
+    require_admin("translate category labels")?;
+
+    load_category(category_id)?;
+    let locale = normalize_locale(&locale)?;
+    let label = label.trim().to_string();
+    if label.is_empty() || label.chars().count() > MAX_LABEL_LEN {
+        return Err(FoodShareError::Validation {
+            field: "label".to_string(),
+            reason: format!("Label must be between 1 and {} characters", MAX_LABEL_LEN),
+        });
+    }
+
+    let before = CATEGORY_LABELS.with(|storage| storage.borrow().get(&category_id));
+    let mut labels = before.clone().unwrap_or(CategoryLabels {
+        category_id,
+        labels: Vec::new(),
+    });
+    let count = labels.labels.len();
+    match labels
+        .labels
+        .iter_mut()
+        .find(|entry| entry.locale == locale)
+    {
+        Some(entry) => entry.label = label,
+        None if count >= MAX_LOCALES_PER_CATEGORY => {
+            return Err(FoodShareError::QuotaExceeded {
+                msg: format!(
+                    "A category can have labels in at most {} locales",
+                    MAX_LOCALES_PER_CATEGORY
+                ),
+            });
+        }
+        None => labels.labels.push(LocalizedLabel {
+            locale: locale.clone(),
+            label,
+        }),
+    }
+    CATEGORY_LABELS.with(|storage| storage.borrow_mut().insert(category_id, labels.clone()));
+    audit(
+        if before.is_some() {
+            AuditAction::Updated
+        } else {
+            AuditAction::Created
+        },
+        AuditEntity::CategoryLabels,
+        category_id,
+        before.as_ref(),
+        Some(&labels),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Set {} label of category {}", locale, category_id),
+    );
+
+    Ok(labels)
+}
+
+// Function for admins to remove the label of a category in a locale
+#[ic_cdk::update]
+fn remove_category_label(
+    category_id: u64,
+    locale: String,
+) -> Result<CategoryLabels, FoodShareError> {
+    start_trace("remove_category_label");
+    require_admin("translate category labels")?;
+
+    let locale = normalize_locale(&locale)?;
+    let before = CATEGORY_LABELS
+        .with(|storage| storage.borrow().get(&category_id))
+        .filter(|labels| labels.labels.iter().any(|entry| entry.locale == locale))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Category {} has no {} label", category_id, locale),
+        })?;
+
+    let mut labels = before.clone();
+    labels.labels.retain(|entry| entry.locale != locale);
+    CATEGORY_LABELS.with(|storage| storage.borrow_mut().insert(category_id, labels.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::CategoryLabels,
+        category_id,
+        Some(&before),
+        Some(&labels),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Removed {} label of category {}", locale, category_id),
+    );
+
+    Ok(labels)
+}
+
+// Function to get the translated labels of a category
+#[ic_cdk::query]
+fn get_category_labels(category_id: u64) -> Result<CategoryLabels, FoodShareError> {
+    CATEGORY_LABELS
+        .with(|storage| storage.borrow().get(&category_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Category {} has no translated labels", category_id),
+        })
+}
+
+// Function for admins to add or replace the template of a notification kind in
+// a locale; recipients who prefer the locale get it instead of the default text
+#[ic_cdk::update]
+fn set_notification_template(
+    kind: NotificationKind,
+    locale: String,
+    body: String,
+) -> Result<NotificationTemplate, FoodShareError> {
+    start_trace("set_notification_template");
+    require_admin("change notification templates")?;
+
+    let locale = normalize_locale(&locale)?;
+    validate_template(kind, &body)?;
+
+    let before = find_template(kind, &locale);
+    let template = NotificationTemplate {
+        id: before.as_ref().map_or_else(next_id, |before| before.id),
+        kind,
+        locale,
+        body,
+        updated_by: ic_cdk::caller(),
+        updated_at: time(),
+    };
+    NOTIFICATION_TEMPLATES
+        .with(|storage| storage.borrow_mut().insert(template.id, template.clone()));
+    audit(
+        if before.is_some() {
+            AuditAction::Updated
+        } else {
+            AuditAction::Created
+        },
+        AuditEntity::NotificationTemplate,
+        template.id,
+        before.as_ref(),
+        Some(&template),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Set {} template for {:?} notifications",
+            template.locale, kind
+        ),
+    );
+
+    Ok(template)
+}
+
+// Function for admins to remove a template; the locale falls back to its
+// language's template or the default text
+#[ic_cdk::update]
+fn remove_notification_template(
+    kind: NotificationKind,
+    locale: String,
+) -> Result<NotificationTemplate, FoodShareError> {
+    start_trace("remove_notification_template");
+    require_admin("change notification templates")?;
+
+    let locale = normalize_locale(&locale)?;
+    let template = find_template(kind, &locale).ok_or(FoodShareError::NotFound {
+        msg: format!("No {} template for {:?} notifications", locale, kind),
+    })?;
+    NOTIFICATION_TEMPLATES.with(|storage| storage.borrow_mut().remove(&template.id));
+    audit(
+        AuditAction::Revoked,
+        AuditEntity::NotificationTemplate,
+        template.id,
+        Some(&template),
+        None,
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Removed {} template for {:?} notifications", locale, kind),
+    );
+
+    Ok(template)
+}
+
+// Function to get the notification templates, optionally of one locale
+#[ic_cdk::query]
+fn get_notification_templates(
+    locale: Option<String>,
+) -> Result<Vec<NotificationTemplate>, FoodShareError> {
+    let locale = locale.as_deref().map(normalize_locale).transpose()?;
+    Ok(NOTIFICATION_TEMPLATES.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, template)| template)
+            .filter(|template| {
+                locale
+                    .as_ref()
+                    .is_none_or(|locale| template.locale == *locale)
+            })
+            .collect()
+    }))
+}
+
+// Function to check a template and show it filled in with sample values, before saving it
+#[ic_cdk::query]
+fn preview_notification_template(
+    kind: NotificationKind,
+    body: String,
+) -> Result<String, FoodShareError> {
+    validate_template(kind, &body)?;
+    let samples = [
+        (
+            "message",
+            "Surplus post 42 was assigned to driver 3 for receiver 5".to_string(),
+        ),
+        ("post_id", "42".to_string()),
+        ("assignment_id", "7".to_string()),
+        ("driver_id", "3".to_string()),
+        ("receiver_id", "5".to_string()),
+        ("quantity_kg", "200".to_string()),
+        ("food_type", "Vegetables".to_string()),
+        ("best_before_date", "2026-01-31".to_string()),
+    ];
+    Ok(render(&body, &samples))
+}
+
+// Function for a user to choose the locale of their notifications and labels;
+// None goes back to the default text
+#[ic_cdk::update]
+fn set_my_locale(locale: Option<String>) -> Result<Option<String>, FoodShareError> {
+    start_trace("set_my_locale");
+
+    let caller = StorablePrincipal(ic_cdk::caller());
+    match locale {
+        Some(locale) => {
+            let locale = normalize_locale(&locale)?;
+            PREFERRED_LOCALES.with(|storage| {
+                storage
+                    .borrow_mut()
+                    .insert(caller, StoredLocale(locale.clone()))
+            });
+            Ok(Some(locale))
+        }
+        None => {
+            PREFERRED_LOCALES.with(|storage| storage.borrow_mut().remove(&caller));
+            Ok(None)
+        }
+    }
+}
+
+#[ic_cdk::query]
+fn get_my_locale() -> Option<String> {
+    preferred_locale(ic_cdk::caller())
+}
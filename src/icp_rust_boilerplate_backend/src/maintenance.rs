@@ -0,0 +1,468 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::require_admin;
+use crate::observability::{current_trace_id, log, resume_trace, start_trace, LogLevel};
+use crate::{
+    browse, contact_vault, governance, impersonation, indexes, notifications, observability,
+    rate_limits,
+};
+use crate::{next_id, FoodShareError, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::ops::Bound;
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+// Entries handled per timer tick, small enough to stay well inside the instruction limit
+const SCAN_BATCH_SIZE: usize = 500;
+
+// MaintenanceKind is a custom enum type that is used to represent what a maintenance job does
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum MaintenanceKind {
+    RebuildIndex,
+    CompactCollection,
+    // Sealing contact details again under the current key after a rotation
+    Reencrypt,
+}
+
+// MaintenancePhase is a custom enum type that is used to represent which pass a maintenance job is in
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum MaintenancePhase {
+    // Adding entries missing from an index, scanning its primary collection
+    Fill,
+    // Removing dangling or expired entries, scanning the collection itself
+    Prune,
+    Completed,
+    // Stopped before completing; the job says why
+    Failed,
+}
+
+impl MaintenancePhase {
+    fn is_finished(self) -> bool {
+        matches!(self, MaintenancePhase::Completed | MaintenancePhase::Failed)
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MaintenanceJob {
+    id: u64,
+    kind: MaintenanceKind,
+    name: String,
+    phase: MaintenancePhase,
+    // Encoded key of the last entry handled in the current phase
+    cursor: Option<Vec<u8>>,
+    scanned: u64,
+    inserted: u64,
+    removed: u64,
+    trace_id: String,
+    started_by: Principal,
+    started_at: u64,
+    finished_at: Option<u64>,
+    // Why the job failed
+    failure: Option<String>,
+}
+
+impl Storable for MaintenanceJob {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for MaintenanceJob {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Result of handling one batch of a resumable scan
+#[derive(Default)]
+pub(crate) struct ScanStep {
+    // None once the scan has reached the end of the collection
+    pub(crate) next_cursor: Option<Vec<u8>>,
+    pub(crate) scanned: u64,
+    pub(crate) inserted: u64,
+    pub(crate) removed: u64,
+}
+
+type StepFn = fn(Option<&[u8]>) -> ScanStep;
+
+struct MaintenanceTarget {
+    name: &'static str,
+    // Only indexes can be rebuilt from a primary collection
+    fill: Option<StepFn>,
+    prune: StepFn,
+}
+
+const TARGETS: &[MaintenanceTarget] = &[
+    MaintenanceTarget {
+        name: "assignments_by_post",
+        fill: Some(indexes::fill_post_index),
+        prune: indexes::prune_post_index,
+    },
+    MaintenanceTarget {
+        name: "assignments_by_driver",
+        fill: Some(indexes::fill_driver_index),
+        prune: indexes::prune_driver_index,
+    },
+    MaintenanceTarget {
+        name: "assignments_by_receiver",
+        fill: Some(indexes::fill_receiver_index),
+        prune: indexes::prune_receiver_index,
+    },
+    MaintenanceTarget {
+        name: "records_by_assignment",
+        fill: Some(indexes::fill_record_index),
+        prune: indexes::prune_record_index,
+    },
+    MaintenanceTarget {
+        name: "assignments_by_status",
+        fill: Some(indexes::fill_status_index),
+        prune: indexes::prune_status_index,
+    },
+    MaintenanceTarget {
+        name: "posts_by_donor",
+        fill: Some(indexes::fill_donor_index),
+        prune: indexes::prune_donor_index,
+    },
+    MaintenanceTarget {
+        name: "open_posts_by_food_type",
+        fill: Some(indexes::fill_food_type_index),
+        prune: indexes::prune_food_type_index,
+    },
+    MaintenanceTarget {
+        name: "events_by_assignment",
+        fill: Some(indexes::fill_event_assignment_index),
+        prune: indexes::prune_event_assignment_index,
+    },
+    MaintenanceTarget {
+        name: "post_events",
+        fill: Some(indexes::fill_post_event_index),
+        prune: indexes::prune_post_event_index,
+    },
+    MaintenanceTarget {
+        name: "messages_by_assignment",
+        fill: Some(indexes::fill_message_index),
+        prune: indexes::prune_message_index,
+    },
+    MaintenanceTarget {
+        name: "reviews_by_assignment",
+        fill: Some(indexes::fill_review_index),
+        prune: indexes::prune_review_index,
+    },
+    MaintenanceTarget {
+        name: "open_post_summaries",
+        fill: Some(browse::fill_open_posts),
+        prune: browse::prune_open_posts,
+    },
+    MaintenanceTarget {
+        name: "decision_cache",
+        fill: None,
+        prune: governance::prune_decision_cache,
+    },
+    MaintenanceTarget {
+        name: "impersonation_sessions",
+        fill: None,
+        prune: impersonation::prune_expired_sessions,
+    },
+    MaintenanceTarget {
+        name: "notifications",
+        fill: None,
+        prune: notifications::prune_notifications,
+    },
+    MaintenanceTarget {
+        name: "call_buckets",
+        fill: None,
+        prune: rate_limits::prune_drained_buckets,
+    },
+    MaintenanceTarget {
+        name: "logs_by_trace",
+        fill: Some(observability::fill_trace_index),
+        prune: observability::prune_trace_index,
+    },
+];
+
+// Re-encryption runs as a single Prune pass and is only started by key rotation
+const REENCRYPT_TARGETS: &[MaintenanceTarget] = &[MaintenanceTarget {
+    name: "contact_fields",
+    fill: None,
+    prune: contact_vault::reseal_contacts,
+}];
+
+thread_local! {
+    static MAINTENANCE_JOBS: RefCell<StableBTreeMap<u64, MaintenanceJob, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(41)))
+    ));
+}
+
+// Function to read the next batch of entries after a cursor. Together with the
+// returned cursor this lets long scans run across many messages and upgrades.
+pub(crate) fn scan_batch<K, V>(
+    map: &StableBTreeMap<K, V, Memory>,
+    cursor: Option<&[u8]>,
+) -> (Vec<(K, V)>, Option<Vec<u8>>)
+where
+    K: BoundedStorable + Ord + Clone,
+    V: BoundedStorable,
+{
+    let entries: Vec<(K, V)> = match cursor {
+        Some(cursor) => map
+            .range((
+                Bound::Excluded(K::from_bytes(Cow::Borrowed(cursor))),
+                Bound::Unbounded,
+            ))
+            .take(SCAN_BATCH_SIZE)
+            .collect(),
+        None => map.iter().take(SCAN_BATCH_SIZE).collect(),
+    };
+    let next_cursor = if entries.len() == SCAN_BATCH_SIZE {
+        entries.last().map(|(key, _)| key.to_bytes().into_owned())
+    } else {
+        None
+    };
+    (entries, next_cursor)
+}
+
+fn find_target(kind: MaintenanceKind, name: &str) -> Option<&'static MaintenanceTarget> {
+    let targets = match kind {
+        MaintenanceKind::Reencrypt => REENCRYPT_TARGETS,
+        _ => TARGETS,
+    };
+    targets.iter().find(|target| target.name == name)
+}
+
+fn running_job() -> Option<MaintenanceJob> {
+    MAINTENANCE_JOBS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, job)| job)
+            .find(|job| !job.phase.is_finished())
+    })
+}
+
+fn schedule_step() {
+    ic_cdk_timers::set_timer(Duration::ZERO, run_maintenance_step);
+}
+
+// Function to pick up a job interrupted by an upgrade; timers do not survive upgrades
+pub(crate) fn resume_maintenance() {
+    if running_job().is_some() {
+        schedule_step();
+    }
+}
+
+// Function to find the target of a job, failing the job when the target no
+// longer exists, e.g. after an upgrade renamed or removed it
+fn target_or_fail(job: &mut MaintenanceJob, now: u64) -> Option<&'static MaintenanceTarget> {
+    let target = find_target(job.kind, &job.name);
+    if target.is_none() {
+        job.phase = MaintenancePhase::Failed;
+        job.cursor = None;
+        job.finished_at = Some(now);
+        job.failure = Some(format!("Unknown {:?} target {}", job.kind, job.name));
+    }
+    target
+}
+
+// Function to handle one batch of the running job and schedule the next one
+fn run_maintenance_step() {
+    let Some(mut job) = running_job() else {
+        return;
+    };
+    resume_trace(&job.trace_id);
+    let Some(target) = target_or_fail(&mut job, time()) else {
+        log(
+            LogLevel::Error,
+            format!(
+                "Maintenance job {} failed: {}",
+                job.id,
+                job.failure.as_deref().unwrap_or_default()
+            ),
+        );
+        MAINTENANCE_JOBS.with(|storage| storage.borrow_mut().insert(job.id, job));
+        return;
+    };
+
+    let step_fn = match (job.phase, target.fill) {
+        (MaintenancePhase::Fill, Some(fill)) => fill,
+        _ => target.prune,
+    };
+    let step = step_fn(job.cursor.as_deref());
+    job.scanned += step.scanned;
+    job.inserted += step.inserted;
+    job.removed += step.removed;
+    job.cursor = step.next_cursor;
+
+    if job.cursor.is_none() {
+        job.phase = match job.phase {
+            MaintenancePhase::Fill => MaintenancePhase::Prune,
+            _ => MaintenancePhase::Completed,
+        };
+    }
+    if job.phase == MaintenancePhase::Completed {
+        job.finished_at = Some(time());
+        log(
+            LogLevel::Info,
+            format!(
+                "{:?} of {} finished: {} scanned, {} inserted, {} removed",
+                job.kind, job.name, job.scanned, job.inserted, job.removed
+            ),
+        );
+    }
+
+    MAINTENANCE_JOBS.with(|storage| storage.borrow_mut().insert(job.id, job.clone()));
+    if job.phase != MaintenancePhase::Completed {
+        schedule_step();
+    }
+}
+
+// Function to reject starting a job while another one is still running
+pub(crate) fn check_idle() -> Result<(), FoodShareError> {
+    match running_job() {
+        Some(running) => Err(FoodShareError::Conflict {
+            msg: format!(
+                "Maintenance job {} on {} is still running",
+                running.id, running.name
+            ),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn start_job(kind: MaintenanceKind, name: String) -> Result<MaintenanceJob, FoodShareError> {
+    let target = find_target(kind, &name)
+        .filter(|target| kind != MaintenanceKind::RebuildIndex || target.fill.is_some())
+        .ok_or(FoodShareError::Validation {
+            field: "name".to_string(),
+            reason: format!("Unknown {:?} target {}", kind, name),
+        })?;
+    check_idle()?;
+
+    let id = next_id();
+    let job = MaintenanceJob {
+        id,
+        kind,
+        name: target.name.to_string(),
+        phase: match kind {
+            MaintenanceKind::RebuildIndex => MaintenancePhase::Fill,
+            MaintenanceKind::CompactCollection | MaintenanceKind::Reencrypt => {
+                MaintenancePhase::Prune
+            }
+        },
+        cursor: None,
+        scanned: 0,
+        inserted: 0,
+        removed: 0,
+        trace_id: current_trace_id(),
+        started_by: ic_cdk::caller(),
+        started_at: time(),
+        finished_at: None,
+        failure: None,
+    };
+    MAINTENANCE_JOBS.with(|storage| storage.borrow_mut().insert(id, job.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::MaintenanceJob,
+        id,
+        None,
+        Some(&job),
+    );
+    schedule_step();
+
+    log(
+        LogLevel::Info,
+        format!("Started {:?} of {} as job {}", kind, job.name, id),
+    );
+
+    Ok(job)
+}
+
+// Function for admins to rebuild a secondary index from its primary collection
+#[ic_cdk::update]
+fn rebuild_index(name: String) -> Result<MaintenanceJob, FoodShareError> {
+    start_trace("rebuild_index");
+    require_admin("rebuild indexes")?;
+    start_job(MaintenanceKind::RebuildIndex, name)
+}
+
+// Function for admins to drop dangling and expired entries from a collection
+#[ic_cdk::update]
+fn compact_collection(name: String) -> Result<MaintenanceJob, FoodShareError> {
+    start_trace("compact_collection");
+    require_admin("compact collections")?;
+    start_job(MaintenanceKind::CompactCollection, name)
+}
+
+// Function to start re-encrypting the contact details not yet under the current key
+pub(crate) fn start_reencryption() -> Result<MaintenanceJob, FoodShareError> {
+    start_job(MaintenanceKind::Reencrypt, "contact_fields".to_string())
+}
+
+// Function for admins to follow the progress of a maintenance job
+#[ic_cdk::query]
+fn get_maintenance_job(job_id: u64) -> Result<MaintenanceJob, FoodShareError> {
+    require_admin("view maintenance jobs")?;
+
+    MAINTENANCE_JOBS
+        .with(|storage| storage.borrow().get(&job_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Maintenance job with id={} not found", job_id),
+        })
+}
+
+// Function for admins to list every maintenance job, oldest first
+#[ic_cdk::query]
+fn get_maintenance_jobs() -> Result<Vec<MaintenanceJob>, FoodShareError> {
+    require_admin("view maintenance jobs")?;
+
+    Ok(MAINTENANCE_JOBS.with(|storage| storage.borrow().iter().map(|(_, job)| job).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(name: &str) -> MaintenanceJob {
+        MaintenanceJob {
+            id: 1,
+            kind: MaintenanceKind::RebuildIndex,
+            name: name.to_string(),
+            phase: MaintenancePhase::Fill,
+            cursor: Some(vec![1]),
+            scanned: 0,
+            inserted: 0,
+            removed: 0,
+            trace_id: String::new(),
+            started_by: Principal::anonymous(),
+            started_at: 0,
+            finished_at: None,
+            failure: None,
+        }
+    }
+
+    #[test]
+    fn job_with_unknown_target_fails() {
+        let mut job = job("dropped_index");
+        assert!(target_or_fail(&mut job, 42).is_none());
+        assert_eq!(job.phase, MaintenancePhase::Failed);
+        assert!(job.phase.is_finished());
+        assert_eq!(job.finished_at, Some(42));
+        assert!(job.cursor.is_none());
+        assert!(job
+            .failure
+            .is_some_and(|failure| failure.contains("dropped_index")));
+    }
+
+    #[test]
+    fn job_with_known_target_keeps_running() {
+        let mut job = job("assignments_by_post");
+        assert!(target_or_fail(&mut job, 42).is_some());
+        assert_eq!(job.phase, MaintenancePhase::Fill);
+        assert!(job.finished_at.is_none());
+    }
+}
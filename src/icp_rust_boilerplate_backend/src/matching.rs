@@ -0,0 +1,436 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::category_pauses::is_food_type_paused;
+use crate::food_requests::record_fulfillment;
+use crate::governance::{authorize, is_governance_or_admin, EndpointClass};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::radius_expansion::within_reach;
+use crate::{
+    next_id, FoodRequest, FoodShareError, Memory, SurplusPost, DONORS_STORAGE,
+    FOOD_REQUESTS_STORAGE, MEMORY_MANAGER, RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use chrono::{DateTime, NaiveDate};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+// Weights of each factor in the match score; they add up to 100
+const FOOD_TYPE_WEIGHT: u32 = 40;
+const QUANTITY_WEIGHT: u32 = 25;
+const DISTANCE_WEIGHT: u32 = 20;
+const EXPIRY_WEIGHT: u32 = 15;
+
+// Posts expiring within a day score full expiry points, posts this far out score none
+const EXPIRY_HORIZON_DAYS: i64 = 14;
+// auto_match_all only proposes posts of the requested food type scoring at least this
+const MIN_PROPOSAL_SCORE: u32 = 60;
+
+// MatchStatus is a custom enum type that is used to represent whether a receiver has acted on a proposed match
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum MatchStatus {
+    Proposed,
+    Confirmed,
+    Declined,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PostMatch {
+    post_id: u64,
+    // Weighted sum of the factor scores, 0-100
+    score: u32,
+    food_type_score: u32,
+    quantity_score: u32,
+    distance_score: u32,
+    expiry_score: u32,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ProposedMatch {
+    id: u64,
+    food_request_id: u64,
+    post_id: u64,
+    pub(crate) receiver_id: u64,
+    score: u32,
+    pub(crate) status: MatchStatus,
+    pub(crate) created_at: u64,
+    pub(crate) decided_at: Option<u64>,
+}
+
+impl Storable for ProposedMatch {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ProposedMatch {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static MATCHES_STORAGE: RefCell<StableBTreeMap<u64, ProposedMatch, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28)))
+    ));
+}
+
+fn today() -> Option<NaiveDate> {
+    DateTime::from_timestamp((time() / NANOS_PER_SECOND) as i64, 0).map(|now| now.date_naive())
+}
+
+// Function to get the receiver a post is held for by a confirmed or proposed match
+pub(crate) fn matched_receiver(post_id: u64) -> Option<u64> {
+    MATCHES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .find(|(_, proposed)| {
+                proposed.post_id == post_id && proposed.status != MatchStatus::Declined
+            })
+            .map(|(_, proposed)| proposed.receiver_id)
+    })
+}
+
+// Function to get every match proposed for a post, whatever became of it
+pub(crate) fn matches_for_post(post_id: u64) -> Vec<ProposedMatch> {
+    MATCHES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, proposed)| proposed)
+            .filter(|proposed| proposed.post_id == post_id)
+            .collect()
+    })
+}
+
+// Function to decline the matches of a closed food request so their posts are
+// no longer held; matches whose post is already assigned are left alone
+pub(crate) fn release_matches(food_request_id: u64) {
+    let released: Vec<ProposedMatch> = MATCHES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, proposed)| proposed)
+            .filter(|proposed| {
+                proposed.food_request_id == food_request_id
+                    && proposed.status != MatchStatus::Declined
+            })
+            .filter(|proposed| {
+                SURPLUS_POSTS_STORAGE
+                    .with(|storage| storage.borrow().get(&proposed.post_id))
+                    .is_some_and(|post| !post.assigned)
+            })
+            .collect()
+    });
+    for before in released {
+        let mut proposed = before.clone();
+        proposed.status = MatchStatus::Declined;
+        proposed.decided_at = Some(time());
+        MATCHES_STORAGE.with(|storage| storage.borrow_mut().insert(proposed.id, proposed.clone()));
+        audit(
+            AuditAction::StatusChanged,
+            AuditEntity::Match,
+            proposed.id,
+            Some(&before),
+            Some(&proposed),
+        );
+    }
+}
+
+// Function to score one open post against a food request; returns None for
+// posts that are already past their best before date
+fn score_post(
+    request: &FoodRequest,
+    receiver_address: &str,
+    post: &SurplusPost,
+    today: Option<NaiveDate>,
+) -> Option<PostMatch> {
+    let food_type_score = if post.food_type == request.food_type {
+        100
+    } else {
+        0
+    };
+
+    let (smaller, larger) = if post.quantity_kg < request.quantity_kg {
+        (post.quantity_kg, request.quantity_kg)
+    } else {
+        (request.quantity_kg, post.quantity_kg)
+    };
+    let quantity_score = (smaller as u64 * 100 / larger.max(1) as u64) as u32;
+
+    // Receivers have no region, so a donor region named in the receiver's
+    // address counts as nearby; without a donor region the factor is neutral
+    let donor_region = post
+        .donor_id
+        .parse::<u64>()
+        .ok()
+        .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)))
+        .map(|donor| donor.region)
+        .unwrap_or_default();
+    let distance_score = if donor_region.is_empty() {
+        50
+    } else if receiver_address
+        .to_lowercase()
+        .contains(&donor_region.to_lowercase())
+    {
+        100
+    } else {
+        0
+    };
+
+    // Food close to its best before date is matched first so it is not wasted
+    let expiry_score = match (
+        NaiveDate::parse_from_str(&post.best_before_date, "%Y-%m-%d").ok(),
+        today,
+    ) {
+        (Some(best_before), Some(today)) => {
+            let days_left = (best_before - today).num_days();
+            if days_left < 0 {
+                return None;
+            }
+            let days_left = days_left.clamp(1, EXPIRY_HORIZON_DAYS);
+            ((EXPIRY_HORIZON_DAYS - days_left) * 100 / (EXPIRY_HORIZON_DAYS - 1)) as u32
+        }
+        _ => 50,
+    };
+
+    let score = (food_type_score * FOOD_TYPE_WEIGHT
+        + quantity_score * QUANTITY_WEIGHT
+        + distance_score * DISTANCE_WEIGHT
+        + expiry_score * EXPIRY_WEIGHT)
+        / 100;
+
+    Some(PostMatch {
+        post_id: post.id,
+        score,
+        food_type_score,
+        quantity_score,
+        distance_score,
+        expiry_score,
+    })
+}
+
+// Function to rank the open posts a receiver could take for a food request
+fn rank_posts(request: &FoodRequest) -> Vec<PostMatch> {
+    let receiver = RECEIVERS_STORAGE.with(|storage| storage.borrow().get(&request.receiver_id));
+    let (latitude, longitude) = receiver
+        .as_ref()
+        .map(|receiver| (receiver.latitude, receiver.longitude))
+        .unwrap_or_default();
+    let receiver_address = receiver
+        .map(|receiver| receiver.address)
+        .unwrap_or_default();
+    let today = today();
+
+    let mut matches: Vec<PostMatch> = SURPLUS_POSTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, post)| post)
+            .filter(|post| {
+                !post.assigned && post.withdrawn_at.is_none() && post.expired_at.is_none()
+            })
+            .filter(|post| !is_food_type_paused(request.receiver_id, post.food_type))
+            .filter(|post| within_reach(post, latitude, longitude))
+            .filter(|post| {
+                matched_receiver(post.id)
+                    .is_none_or(|receiver_id| receiver_id == request.receiver_id)
+            })
+            .filter_map(|post| score_post(request, &receiver_address, &post, today))
+            .collect()
+    });
+
+    matches.sort_by(|a, b| b.score.cmp(&a.score).then(a.post_id.cmp(&b.post_id)));
+    matches
+}
+
+// Function to get open surplus posts ranked for a food request
+#[ic_cdk::query]
+fn match_request_to_posts(request_id: u64) -> Result<Vec<PostMatch>, FoodShareError> {
+    let request = FOOD_REQUESTS_STORAGE
+        .with(|storage| storage.borrow().get(&request_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Food request with id={} not found", request_id),
+        })?;
+
+    let matches = rank_posts(&request);
+    if matches.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: "No open surplus posts found.".to_string(),
+        });
+    }
+
+    Ok(matches)
+}
+
+// Function for governance to propose the best open post for every unfulfilled
+// food request; receivers confirm or decline each proposal
+#[ic_cdk::update]
+async fn auto_match_all() -> Result<Vec<ProposedMatch>, FoodShareError> {
+    start_trace("auto_match_all");
+    authorize(EndpointClass::Logistics).await?;
+
+    if !is_governance_or_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only governance can run automatic matching".to_string(),
+        });
+    }
+
+    let requests: Vec<FoodRequest> = FOOD_REQUESTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, request)| request)
+            .filter(|request| request.is_open())
+            .collect()
+    });
+
+    let mut proposals = Vec::new();
+    for mut request in requests {
+        // A partially fulfilled request gets one more post at a time, sized to what is still missing
+        let has_open_match = MATCHES_STORAGE.with(|storage| {
+            storage.borrow().iter().any(|(_, proposed)| {
+                proposed.food_request_id == request.id && proposed.status == MatchStatus::Proposed
+            })
+        });
+        if has_open_match {
+            continue;
+        }
+        request.quantity_kg = request.remaining_kg();
+
+        // Posts proposed earlier in this run are held for their receiver, so
+        // each post is proposed to at most one request
+        let best = rank_posts(&request).into_iter().find(|candidate| {
+            candidate.food_type_score == 100
+                && candidate.score >= MIN_PROPOSAL_SCORE
+                && matched_receiver(candidate.post_id).is_none()
+        });
+        let Some(best) = best else {
+            continue;
+        };
+
+        let id = next_id();
+        let proposed = ProposedMatch {
+            id,
+            food_request_id: request.id,
+            post_id: best.post_id,
+            receiver_id: request.receiver_id,
+            score: best.score,
+            status: MatchStatus::Proposed,
+            created_at: time(),
+            decided_at: None,
+        };
+        MATCHES_STORAGE.with(|storage| storage.borrow_mut().insert(id, proposed.clone()));
+        audit(
+            AuditAction::Created,
+            AuditEntity::Match,
+            id,
+            None,
+            Some(&proposed),
+        );
+        proposals.push(proposed);
+    }
+
+    log(
+        LogLevel::Info,
+        format!("Proposed {} matches", proposals.len()),
+    );
+
+    Ok(proposals)
+}
+
+// Function for the receiver to accept or turn down a proposed match
+async fn decide_match(match_id: u64, status: MatchStatus) -> Result<ProposedMatch, FoodShareError> {
+    authorize(EndpointClass::Posting).await?;
+
+    let mut proposed = MATCHES_STORAGE
+        .with(|storage| storage.borrow().get(&match_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Match ID does not exist".to_string(),
+        })?;
+
+    let is_receiver = RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&proposed.receiver_id))
+        .is_some_and(|receiver| receiver.owner == Some(ic_cdk::caller()));
+    if !is_receiver {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the receiver can decide on this match".to_string(),
+        });
+    }
+    if proposed.status != MatchStatus::Proposed {
+        return Err(FoodShareError::Conflict {
+            msg: format!("Match has already been {:?}", proposed.status),
+        });
+    }
+
+    let before = proposed.clone();
+    proposed.status = status;
+    proposed.decided_at = Some(time());
+    MATCHES_STORAGE.with(|storage| storage.borrow_mut().insert(match_id, proposed.clone()));
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::Match,
+        match_id,
+        Some(&before),
+        Some(&proposed),
+    );
+
+    if status == MatchStatus::Confirmed {
+        let quantity_kg = SURPLUS_POSTS_STORAGE
+            .with(|storage| storage.borrow().get(&proposed.post_id))
+            .map_or(0, |post| post.quantity_kg);
+        record_fulfillment(proposed.food_request_id, proposed.post_id, quantity_kg);
+    }
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Receiver {} {:?} match {} for post {}",
+            proposed.receiver_id, status, match_id, proposed.post_id
+        ),
+    );
+
+    Ok(proposed)
+}
+
+// Function for the receiver to confirm a proposed match; the post is then held for them
+#[ic_cdk::update]
+async fn confirm_match(match_id: u64) -> Result<ProposedMatch, FoodShareError> {
+    start_trace("confirm_match");
+    decide_match(match_id, MatchStatus::Confirmed).await
+}
+
+// Function for the receiver to decline a proposed match so the post can be proposed elsewhere
+#[ic_cdk::update]
+async fn decline_match(match_id: u64) -> Result<ProposedMatch, FoodShareError> {
+    start_trace("decline_match");
+    decide_match(match_id, MatchStatus::Declined).await
+}
+
+// Function to get the proposed matches of a food request
+#[ic_cdk::query]
+fn get_matches_for_request(request_id: u64) -> Result<Vec<ProposedMatch>, FoodShareError> {
+    let matches: Vec<ProposedMatch> = MATCHES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, proposed)| proposed)
+            .filter(|proposed| proposed.food_request_id == request_id)
+            .collect()
+    });
+
+    if matches.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: "No matches found.".to_string(),
+        });
+    }
+
+    Ok(matches)
+}
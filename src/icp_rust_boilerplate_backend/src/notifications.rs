@@ -0,0 +1,290 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::localization::localized_notification;
+use crate::maintenance::{scan_batch, ScanStep};
+use crate::observability::{log, start_timer_trace, start_trace, LogLevel};
+use crate::{
+    next_id, Assignment, FoodShareError, Memory, SurplusPost, DONORS_STORAGE, DRIVERS_STORAGE,
+    MEMORY_MANAGER, RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const READ_RETENTION_NANOS: u64 = 30 * NANOS_PER_DAY;
+const UNREAD_RETENTION_NANOS: u64 = 90 * NANOS_PER_DAY;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+pub(crate) const MAX_PAYLOAD_LEN: usize = 256;
+
+// NotificationKind is a custom enum type that is used to represent which event a notification is about
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum NotificationKind {
+    PostAssigned,
+    PickupScheduled,
+    // The donor or the driver proposed a pickup window for the other to confirm
+    PickupProposed,
+    // The driver dropped the food off and the receiver is asked to confirm it
+    DeliverySubmitted,
+    DeliveryCompleted,
+    DeliveryDisputed,
+    // A dispute on the assignment was opened or changed status
+    DisputeUpdated,
+    PostExpiring,
+    // A coordinator wrote into the assignment thread
+    CoordinatorMessage,
+    // The driver did not accept in time and the post is open again
+    AssignmentTimedOut,
+    // The driver reached the no-show limit and cannot be assigned for a while
+    DriverSuspended,
+    // The donor withdrew the post and its assignment was cancelled
+    PostWithdrawn,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Notification {
+    id: u64,
+    recipient: Principal,
+    pub(crate) kind: NotificationKind,
+    // Surplus post the notification is about
+    post_id: u64,
+    payload: String,
+    pub(crate) created_at: u64,
+    read: bool,
+}
+
+impl Storable for Notification {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Notification {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static NOTIFICATIONS_STORAGE: RefCell<StableBTreeMap<u64, Notification, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(48)))
+    ));
+}
+
+// Sends the recipient's translated template when their locale has one, and
+// the default payload otherwise
+fn notify(
+    recipient: Option<Principal>,
+    kind: NotificationKind,
+    post_id: u64,
+    payload: &str,
+    values: &[(&str, String)],
+) {
+    let Some(recipient) = recipient else {
+        return;
+    };
+    let payload = localized_notification(recipient, kind, values).unwrap_or(payload.to_string());
+    let id = next_id();
+    let notification = Notification {
+        id,
+        recipient,
+        kind,
+        post_id,
+        payload: payload.chars().take(MAX_PAYLOAD_LEN).collect(),
+        created_at: time(),
+        read: false,
+    };
+    NOTIFICATIONS_STORAGE.with(|storage| storage.borrow_mut().insert(id, notification));
+}
+
+fn donor_owner(post: &SurplusPost) -> Option<Principal> {
+    let donor_id: u64 = post.donor_id.parse().ok()?;
+    DONORS_STORAGE
+        .with(|storage| storage.borrow().get(&donor_id))?
+        .owner
+}
+
+// Function to notify the donor, the receiver and the driver of an assignment
+pub(crate) fn notify_assignment(assignment: &Assignment, kind: NotificationKind, payload: &str) {
+    let donor = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+        .and_then(|post| donor_owner(&post));
+    let receiver = RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.receiver_id))
+        .and_then(|receiver| receiver.owner);
+    let driver = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.driver_id))
+        .and_then(|driver| driver.owner);
+
+    let mut recipients = vec![donor, receiver, driver];
+    // One principal can hold several roles; it only needs one notification
+    recipients.sort();
+    recipients.dedup();
+    let values = assignment_values(assignment, payload);
+    for recipient in recipients {
+        notify(
+            recipient,
+            kind,
+            assignment.surplus_post_id,
+            payload,
+            &values,
+        );
+    }
+}
+
+fn assignment_values(assignment: &Assignment, payload: &str) -> [(&'static str, String); 5] {
+    [
+        ("message", payload.to_string()),
+        ("post_id", assignment.surplus_post_id.to_string()),
+        ("assignment_id", assignment.id.to_string()),
+        ("driver_id", assignment.driver_id.to_string()),
+        ("receiver_id", assignment.receiver_id.to_string()),
+    ]
+}
+
+// Function to notify only the driver of an assignment
+pub(crate) fn notify_driver(assignment: &Assignment, kind: NotificationKind, payload: &str) {
+    let driver = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.driver_id))
+        .and_then(|driver| driver.owner);
+    notify(
+        driver,
+        kind,
+        assignment.surplus_post_id,
+        payload,
+        &assignment_values(assignment, payload),
+    );
+}
+
+// Function to warn a donor that their post is about to expire unclaimed
+pub(crate) fn notify_post_expiring(post: &SurplusPost) {
+    let message = format!(
+        "Your post of {} kg of {:?} expires after {} and has not been claimed",
+        post.quantity_kg, post.food_type, post.best_before_date
+    );
+    notify(
+        donor_owner(post),
+        NotificationKind::PostExpiring,
+        post.id,
+        &message,
+        &[
+            ("message", message.clone()),
+            ("post_id", post.id.to_string()),
+            ("quantity_kg", post.quantity_kg.to_string()),
+            ("food_type", format!("{:?}", post.food_type)),
+            ("best_before_date", post.best_before_date.clone()),
+        ],
+    );
+}
+
+// Function to get the notifications about a post that are still retained
+pub(crate) fn post_notifications(post_id: u64) -> Vec<Notification> {
+    NOTIFICATIONS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, notification)| notification)
+            .filter(|notification| notification.post_id == post_id)
+            .collect()
+    })
+}
+
+// Function to drop notifications past their retention: 30 days once read, 90 days otherwise
+pub(crate) fn prune_notifications(cursor: Option<&[u8]>) -> ScanStep {
+    let now = time();
+    NOTIFICATIONS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let (batch, next_cursor) = scan_batch(&storage, cursor);
+        let mut step = ScanStep {
+            next_cursor,
+            scanned: batch.len() as u64,
+            ..Default::default()
+        };
+        for (id, notification) in batch {
+            let retention = if notification.read {
+                READ_RETENTION_NANOS
+            } else {
+                UNREAD_RETENTION_NANOS
+            };
+            if now.saturating_sub(notification.created_at) > retention {
+                storage.remove(&id);
+                step.removed += 1;
+            }
+        }
+        step
+    })
+}
+
+// Function to register the daily retention pruning
+pub(crate) fn start_notification_pruning() {
+    ic_cdk_timers::set_timer_interval(PRUNE_INTERVAL, || run_pruning(None));
+}
+
+// Function to prune one batch and schedule the next, so pruning stays within the instruction limit
+fn run_pruning(cursor: Option<Vec<u8>>) {
+    start_timer_trace("prune_notifications");
+
+    let step = prune_notifications(cursor.as_deref());
+    if step.removed > 0 {
+        log(
+            LogLevel::Info,
+            format!("Pruned {} expired notifications", step.removed),
+        );
+    }
+    if let Some(next_cursor) = step.next_cursor {
+        ic_cdk_timers::set_timer(Duration::ZERO, move || run_pruning(Some(next_cursor)));
+    }
+}
+
+// Function to get the caller's notifications, newest first
+#[ic_cdk::query]
+fn get_my_notifications(unread_only: bool) -> Vec<Notification> {
+    let caller = ic_cdk::caller();
+    let mut notifications: Vec<Notification> = NOTIFICATIONS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, notification)| notification)
+            .filter(|notification| notification.recipient == caller)
+            .filter(|notification| !unread_only || !notification.read)
+            .collect()
+    });
+    notifications.reverse();
+    notifications
+}
+
+// Function for the recipient to mark a notification as read
+#[ic_cdk::update]
+fn mark_notification_read(notification_id: u64) -> Result<Notification, FoodShareError> {
+    start_trace("mark_notification_read");
+
+    let mut notification = NOTIFICATIONS_STORAGE
+        .with(|storage| storage.borrow().get(&notification_id))
+        .filter(|notification| notification.recipient == ic_cdk::caller())
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Notification with id={} not found", notification_id),
+        })?;
+
+    let before = notification.clone();
+    notification.read = true;
+    NOTIFICATIONS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(notification_id, notification.clone())
+    });
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::Notification,
+        notification_id,
+        Some(&before),
+        Some(&notification),
+    );
+
+    Ok(notification)
+}
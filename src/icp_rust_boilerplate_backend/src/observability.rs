@@ -0,0 +1,276 @@
+use crate::governance::require_admin;
+use crate::maintenance::{scan_batch, ScanStep};
+use crate::{FoodShareError, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Oldest entries are evicted once the log holds this many
+const MAX_LOG_ENTRIES: u64 = 100_000;
+// Evicting more than one entry per write lets a log that grew past the cap
+// before retention existed shrink back under it
+const EVICTIONS_PER_LOG: usize = 4;
+// Longest message kept in bytes; with the trace id and the rest of the entry it
+// stays under LogEntry::MAX_SIZE, whatever text a caller passes in
+const MAX_LOG_MESSAGE_LEN: usize = 768;
+const TRUNCATION_MARK: char = '…';
+
+// LogLevel is a custom enum type that is used to represent the severity of a log entry
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum LogLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct LogEntry {
+    id: u64,
+    trace_id: String,
+    level: LogLevel,
+    message: String,
+    timestamp: u64,
+}
+
+impl Storable for LogEntry {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for LogEntry {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    // Log ids are contiguous: entries are only appended and evicted oldest first
+    static LOGS_STORAGE: RefCell<StableBTreeMap<u64, LogEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7)))
+    ));
+
+    // (trace sequence, log id), for traced entries only
+    static LOGS_BY_TRACE: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(119)))
+    ));
+
+    // The trace id of the update call currently being executed. It is only valid
+    // until the next await point, so async code must capture it with
+    // `current_trace_id` before awaiting and restore it with `resume_trace`.
+    static CURRENT_TRACE: RefCell<Option<String>> = const { RefCell::new(None) };
+
+    // Operation of a timer run that has not logged anything yet. Its trace is
+    // only started once the run has work to record.
+    static PENDING_TRACE: RefCell<Option<&'static str>> = const { RefCell::new(None) };
+
+    static TRACE_SEQUENCE: RefCell<u64> = const { RefCell::new(0) };
+}
+
+// Function to start a new trace for an originating update call
+pub(crate) fn start_trace(operation: &str) -> String {
+    let sequence = TRACE_SEQUENCE.with(|seq| {
+        let mut seq = seq.borrow_mut();
+        *seq += 1;
+        *seq
+    });
+    let trace_id = format!("{:016x}-{:06x}", time(), sequence);
+
+    PENDING_TRACE.with(|pending| *pending.borrow_mut() = None);
+    CURRENT_TRACE.with(|current| *current.borrow_mut() = Some(trace_id.clone()));
+    log(LogLevel::Info, format!("{} started", operation));
+
+    trace_id
+}
+
+// Function to begin a timer run. Unlike start_trace it leaves no log entry
+// behind: the trace is only started once the run logs or records something,
+// so idle ticks cost nothing and never inherit the trace of an earlier message.
+pub(crate) fn start_timer_trace(operation: &'static str) {
+    CURRENT_TRACE.with(|current| *current.borrow_mut() = None);
+    PENDING_TRACE.with(|pending| *pending.borrow_mut() = Some(operation));
+}
+
+// Function to get the last trace sequence number for the upgrade snapshot
+pub(crate) fn trace_sequence() -> u64 {
+    TRACE_SEQUENCE.with(|seq| *seq.borrow())
+}
+
+// Function to continue the trace sequence from where it was before an upgrade
+pub(crate) fn restore_trace_sequence(sequence: u64) {
+    TRACE_SEQUENCE.with(|seq| *seq.borrow_mut() = sequence);
+}
+
+// Function to restore a trace captured before an await point or stored on a queued job
+pub(crate) fn resume_trace(trace_id: &str) {
+    PENDING_TRACE.with(|pending| *pending.borrow_mut() = None);
+    CURRENT_TRACE.with(|current| *current.borrow_mut() = Some(trace_id.to_string()));
+}
+
+// Function to get the trace id of the call currently being executed
+pub(crate) fn current_trace_id() -> String {
+    if let Some(operation) = PENDING_TRACE.with(|pending| pending.borrow_mut().take()) {
+        return start_trace(operation);
+    }
+    CURRENT_TRACE.with(|current| {
+        current
+            .borrow()
+            .clone()
+            .unwrap_or_else(|| "untraced".to_string())
+    })
+}
+
+// Function to get the sequence number a trace id was started with, which keys
+// the trace index
+fn trace_key(trace_id: &str) -> Option<u64> {
+    let (_, sequence) = trace_id.rsplit_once('-')?;
+    u64::from_str_radix(sequence, 16).ok()
+}
+
+// Function to cut a message down to MAX_LOG_MESSAGE_LEN bytes on a character
+// boundary, marking where it was cut
+fn truncated(mut message: String) -> String {
+    if message.len() <= MAX_LOG_MESSAGE_LEN {
+        return message;
+    }
+    let mut end = MAX_LOG_MESSAGE_LEN - TRUNCATION_MARK.len_utf8();
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    message.truncate(end);
+    message.push(TRUNCATION_MARK);
+    message
+}
+
+// Function to append a log entry tagged with the current trace id and evict
+// the oldest entries once the log is over MAX_LOG_ENTRIES. Long messages, such
+// as outcall responses, are truncated.
+pub(crate) fn log(level: LogLevel, message: String) {
+    let trace_id = current_trace_id();
+    let message = truncated(message);
+
+    let evicted = LOGS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let first_id = storage.iter().next().map_or(0, |(id, _)| id);
+        let id = first_id + storage.len();
+        if let Some(key) = trace_key(&trace_id) {
+            LOGS_BY_TRACE.with(|index| index.borrow_mut().insert((key, id), ()));
+        }
+        storage.insert(
+            id,
+            LogEntry {
+                id,
+                trace_id,
+                level,
+                message,
+                timestamp: time(),
+            },
+        );
+
+        let excess = storage.len().saturating_sub(MAX_LOG_ENTRIES) as usize;
+        let evicted: Vec<LogEntry> = storage
+            .iter()
+            .take(excess.min(EVICTIONS_PER_LOG))
+            .map(|(_, entry)| entry)
+            .collect();
+        for entry in &evicted {
+            storage.remove(&entry.id);
+        }
+        evicted
+    });
+    LOGS_BY_TRACE.with(|index| {
+        let mut index = index.borrow_mut();
+        for entry in evicted {
+            if let Some(key) = trace_key(&entry.trace_id) {
+                index.remove(&(key, entry.id));
+            }
+        }
+    });
+}
+
+// Function to add trace index entries missing for one batch of the log, e.g.
+// for entries written before the index existed
+pub(crate) fn fill_trace_index(cursor: Option<&[u8]>) -> ScanStep {
+    let (entries, next_cursor) = LOGS_STORAGE.with(|storage| scan_batch(&storage.borrow(), cursor));
+    let mut step = ScanStep {
+        next_cursor,
+        scanned: entries.len() as u64,
+        ..Default::default()
+    };
+    LOGS_BY_TRACE.with(|index| {
+        let mut index = index.borrow_mut();
+        for (id, entry) in &entries {
+            let Some(key) = trace_key(&entry.trace_id) else {
+                continue;
+            };
+            if index.insert((key, *id), ()).is_none() {
+                step.inserted += 1;
+            }
+        }
+    });
+    step
+}
+
+// Function to drop trace index entries in one batch whose log entry was evicted
+pub(crate) fn prune_trace_index(cursor: Option<&[u8]>) -> ScanStep {
+    let (entries, next_cursor) = LOGS_BY_TRACE.with(|index| scan_batch(&index.borrow(), cursor));
+    let scanned = entries.len() as u64;
+    let dangling: Vec<(u64, u64)> = LOGS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        entries
+            .into_iter()
+            .map(|(entry, _)| entry)
+            .filter(|(_, id)| !storage.contains_key(id))
+            .collect()
+    });
+    LOGS_BY_TRACE.with(|index| {
+        let mut index = index.borrow_mut();
+        for entry in &dangling {
+            index.remove(entry);
+        }
+    });
+    ScanStep {
+        next_cursor,
+        scanned,
+        removed: dangling.len() as u64,
+        ..Default::default()
+    }
+}
+
+// Function for admins to get all log entries still retained under a trace id
+#[ic_cdk::query]
+fn get_logs_by_trace(trace_id: String) -> Result<Vec<LogEntry>, FoodShareError> {
+    require_admin("read logs")?;
+
+    let ids: Vec<u64> = trace_key(&trace_id).map_or_else(Vec::new, |key| {
+        LOGS_BY_TRACE.with(|index| {
+            index
+                .borrow()
+                .range((key, 0)..=(key, u64::MAX))
+                .map(|((_, id), _)| id)
+                .collect()
+        })
+    });
+    let records: Vec<LogEntry> = LOGS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.iter()
+            .filter_map(|id| storage.get(id))
+            .filter(|entry| entry.trace_id == trace_id)
+            .collect()
+    });
+
+    if records.is_empty() {
+        Err(FoodShareError::NotFound {
+            msg: format!("No logs found for trace {}.", trace_id),
+        })
+    } else {
+        Ok(records)
+    }
+}
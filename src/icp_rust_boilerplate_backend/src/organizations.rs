@@ -0,0 +1,494 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::EntityType;
+use crate::governance::{authorize, is_admin, EndpointClass};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    next_id, registering_principal, FoodShareError, Memory, DONORS_STORAGE, MEMORY_MANAGER,
+    RECEIVERS_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_MEMBERS: usize = 50;
+const MAX_PENDING_INVITES: usize = 20;
+// Donor and receiver profiles an organization can act for, together
+const MAX_LINKED_PROFILES: usize = 20;
+
+// OrgRole is a custom enum type that is used to represent what a member may do for an organization
+#[derive(
+    candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+pub(crate) enum OrgRole {
+    // Posts surplus and requests food on behalf of the organization
+    Staff,
+    // Also invites and removes staff
+    Manager,
+    // Also manages managers and owners and links profiles
+    Owner,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct OrgMember {
+    principal: Principal,
+    role: OrgRole,
+    joined_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct OrgInvite {
+    principal: Principal,
+    role: OrgRole,
+    invited_by: Principal,
+    invited_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Organization {
+    id: u64,
+    name: String,
+    // Profiles members act for; each must have been registered by an owner
+    donor_ids: Vec<u64>,
+    receiver_ids: Vec<u64>,
+    members: Vec<OrgMember>,
+    // Invites waiting for the invited principal to accept
+    invites: Vec<OrgInvite>,
+    created_at: u64,
+    updated_at: u64,
+}
+
+impl Organization {
+    fn role_of(&self, principal: &Principal) -> Option<OrgRole> {
+        self.members
+            .iter()
+            .find(|member| member.principal == *principal)
+            .map(|member| member.role)
+    }
+
+    fn owner_count(&self) -> usize {
+        self.members
+            .iter()
+            .filter(|member| member.role == OrgRole::Owner)
+            .count()
+    }
+}
+
+impl Storable for Organization {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Organization {
+    const MAX_SIZE: u32 = 8 * 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Organization Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct OrganizationPayload {
+    name: String,
+    // Profiles registered by the caller that the organization acts for
+    donor_ids: Vec<u64>,
+    receiver_ids: Vec<u64>,
+}
+
+thread_local! {
+    static ORGANIZATIONS: RefCell<StableBTreeMap<u64, Organization, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(68)))
+    ));
+}
+
+fn load_organization(organization_id: u64) -> Result<Organization, FoodShareError> {
+    ORGANIZATIONS
+        .with(|storage| storage.borrow().get(&organization_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Organization with id={} not found", organization_id),
+        })
+}
+
+fn store_organization(before: Option<&Organization>, organization: &mut Organization) {
+    organization.updated_at = time();
+    ORGANIZATIONS.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(organization.id, organization.clone())
+    });
+    audit(
+        match before {
+            Some(_) => AuditAction::Updated,
+            None => AuditAction::Created,
+        },
+        AuditEntity::Organization,
+        organization.id,
+        before,
+        Some(&*organization),
+    );
+}
+
+// Function to check that the caller holds at least a role in an organization
+fn require_role(organization: &Organization, role: OrgRole) -> Result<OrgRole, FoodShareError> {
+    match organization.role_of(&ic_cdk::caller()) {
+        Some(held) if held >= role => Ok(held),
+        _ => Err(FoodShareError::Unauthorized {
+            msg: format!(
+                "Only a {:?} of organization {} can do this",
+                role, organization.id
+            ),
+        }),
+    }
+}
+
+// Function to check that the caller is at least a manager of an organization
+pub(crate) fn check_manager(organization_id: u64) -> Result<(), FoodShareError> {
+    require_role(&load_organization(organization_id)?, OrgRole::Manager).map(|_| ())
+}
+
+// Function to check that the caller registered every profile being linked
+fn check_linkable(donor_ids: &[u64], receiver_ids: &[u64]) -> Result<(), FoodShareError> {
+    if donor_ids.len() + receiver_ids.len() > MAX_LINKED_PROFILES {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "An organization can act for at most {} profiles",
+                MAX_LINKED_PROFILES
+            ),
+        });
+    }
+    let caller = Some(ic_cdk::caller());
+    for donor_id in donor_ids {
+        let owned = DONORS_STORAGE
+            .with(|storage| storage.borrow().get(donor_id))
+            .is_some_and(|donor| donor.active && donor.owner == caller);
+        if !owned {
+            return Err(FoodShareError::Unauthorized {
+                msg: format!("Donor {} is not an active profile of yours", donor_id),
+            });
+        }
+    }
+    for receiver_id in receiver_ids {
+        let owned = RECEIVERS_STORAGE
+            .with(|storage| storage.borrow().get(receiver_id))
+            .is_some_and(|receiver| receiver.active && receiver.owner == caller);
+        if !owned {
+            return Err(FoodShareError::Unauthorized {
+                msg: format!("Receiver {} is not an active profile of yours", receiver_id),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Function to check that the caller may create a post or food request for an
+// organization: they are a member, and the profile is one the organization acts for
+pub(crate) fn check_acting_for(
+    organization_id: u64,
+    entity_type: EntityType,
+    profile_id: u64,
+) -> Result<(), FoodShareError> {
+    let organization = load_organization(organization_id)?;
+    require_role(&organization, OrgRole::Staff)?;
+    let linked = match entity_type {
+        EntityType::Donor => organization.donor_ids.contains(&profile_id),
+        EntityType::Receiver => organization.receiver_ids.contains(&profile_id),
+        _ => false,
+    };
+    if !linked {
+        return Err(FoodShareError::Validation {
+            field: "organization_id".to_string(),
+            reason: format!(
+                "Organization {} does not act for {:?} {}",
+                organization_id, entity_type, profile_id
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Function to create an organization with the caller as its first owner
+#[ic_cdk::update]
+async fn create_organization(payload: OrganizationPayload) -> Result<Organization, FoodShareError> {
+    start_trace("create_organization");
+    authorize(EndpointClass::Registration).await?;
+    let caller = registering_principal()?;
+
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(FoodShareError::Validation {
+            field: "name".to_string(),
+            reason: format!("Name must be between 1 and {} characters", MAX_NAME_LEN),
+        });
+    }
+    check_linkable(&payload.donor_ids, &payload.receiver_ids)?;
+
+    let now = time();
+    let mut organization = Organization {
+        id: next_id(),
+        name,
+        donor_ids: payload.donor_ids,
+        receiver_ids: payload.receiver_ids,
+        members: vec![OrgMember {
+            principal: caller,
+            role: OrgRole::Owner,
+            joined_at: now,
+        }],
+        invites: Vec::new(),
+        created_at: now,
+        updated_at: now,
+    };
+    organization.donor_ids.sort();
+    organization.donor_ids.dedup();
+    organization.receiver_ids.sort();
+    organization.receiver_ids.dedup();
+    store_organization(None, &mut organization);
+
+    log(
+        LogLevel::Info,
+        format!("Created organization {}", organization.id),
+    );
+
+    Ok(organization)
+}
+
+// Function for an owner to link more of their profiles to an organization
+#[ic_cdk::update]
+async fn link_organization_profiles(
+    organization_id: u64,
+    donor_ids: Vec<u64>,
+    receiver_ids: Vec<u64>,
+) -> Result<Organization, FoodShareError> {
+    start_trace("link_organization_profiles");
+    authorize(EndpointClass::Registration).await?;
+
+    let before = load_organization(organization_id)?;
+    require_role(&before, OrgRole::Owner)?;
+    check_linkable(&donor_ids, &receiver_ids)?;
+
+    let mut organization = before.clone();
+    organization.donor_ids.extend(donor_ids);
+    organization.donor_ids.sort();
+    organization.donor_ids.dedup();
+    organization.receiver_ids.extend(receiver_ids);
+    organization.receiver_ids.sort();
+    organization.receiver_ids.dedup();
+    if organization.donor_ids.len() + organization.receiver_ids.len() > MAX_LINKED_PROFILES {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "An organization can act for at most {} profiles",
+                MAX_LINKED_PROFILES
+            ),
+        });
+    }
+    store_organization(Some(&before), &mut organization);
+
+    log(
+        LogLevel::Info,
+        format!("Linked profiles to organization {}", organization_id),
+    );
+
+    Ok(organization)
+}
+
+// Function to invite a principal into an organization. Managers invite staff;
+// owners invite any role. The invite takes effect once the principal accepts it.
+#[ic_cdk::update]
+async fn invite_member(
+    organization_id: u64,
+    principal: Principal,
+    role: OrgRole,
+) -> Result<Organization, FoodShareError> {
+    start_trace("invite_member");
+    authorize(EndpointClass::Registration).await?;
+
+    let before = load_organization(organization_id)?;
+    let inviter_role = require_role(&before, OrgRole::Manager)?;
+    if inviter_role == OrgRole::Manager && role != OrgRole::Staff {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Managers can only invite staff".to_string(),
+        });
+    }
+    if principal == Principal::anonymous() {
+        return Err(FoodShareError::Validation {
+            field: "principal".to_string(),
+            reason: "Anonymous principals cannot be members".to_string(),
+        });
+    }
+    if before.role_of(&principal).is_some() {
+        return Err(FoodShareError::Conflict {
+            msg: format!("{} is already a member", principal),
+        });
+    }
+    if before.members.len() >= MAX_MEMBERS {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!("An organization can have at most {} members", MAX_MEMBERS),
+        });
+    }
+
+    let mut organization = before.clone();
+    organization
+        .invites
+        .retain(|invite| invite.principal != principal);
+    if organization.invites.len() >= MAX_PENDING_INVITES {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!("At most {} invites can be pending", MAX_PENDING_INVITES),
+        });
+    }
+    organization.invites.push(OrgInvite {
+        principal,
+        role,
+        invited_by: ic_cdk::caller(),
+        invited_at: time(),
+    });
+    store_organization(Some(&before), &mut organization);
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Invited {} to organization {} as {:?}",
+            principal, organization_id, role
+        ),
+    );
+
+    Ok(organization)
+}
+
+// Function for an invited principal to join the organization in the role they were invited to
+#[ic_cdk::update]
+async fn accept_organization_invite(organization_id: u64) -> Result<Organization, FoodShareError> {
+    start_trace("accept_organization_invite");
+    authorize(EndpointClass::Registration).await?;
+
+    let caller = ic_cdk::caller();
+    let before = load_organization(organization_id)?;
+    let invite = before
+        .invites
+        .iter()
+        .find(|invite| invite.principal == caller)
+        .cloned()
+        .ok_or(FoodShareError::NotFound {
+            msg: format!(
+                "No invite to organization {} for the caller",
+                organization_id
+            ),
+        })?;
+    if before.members.len() >= MAX_MEMBERS {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!("An organization can have at most {} members", MAX_MEMBERS),
+        });
+    }
+
+    let mut organization = before.clone();
+    organization
+        .invites
+        .retain(|invite| invite.principal != caller);
+    organization.members.push(OrgMember {
+        principal: caller,
+        role: invite.role,
+        joined_at: time(),
+    });
+    store_organization(Some(&before), &mut organization);
+
+    log(
+        LogLevel::Info,
+        format!(
+            "{} joined organization {} as {:?}",
+            caller, organization_id, invite.role
+        ),
+    );
+
+    Ok(organization)
+}
+
+// Function to remove a member or withdraw a pending invite. Members can always
+// leave; managers remove staff and owners remove anyone, but never the last owner.
+#[ic_cdk::update]
+async fn remove_member(
+    organization_id: u64,
+    principal: Principal,
+) -> Result<Organization, FoodShareError> {
+    start_trace("remove_member");
+    authorize(EndpointClass::Registration).await?;
+
+    let before = load_organization(organization_id)?;
+    let role = before.role_of(&principal);
+    let invite_role = before
+        .invites
+        .iter()
+        .find(|invite| invite.principal == principal)
+        .map(|invite| invite.role);
+    let Some(target_role) = role.or(invite_role) else {
+        return Err(FoodShareError::NotFound {
+            msg: format!("{} is not a member or invitee", principal),
+        });
+    };
+
+    if principal != ic_cdk::caller() {
+        let remover_role = require_role(&before, OrgRole::Manager)?;
+        if remover_role == OrgRole::Manager && target_role != OrgRole::Staff {
+            return Err(FoodShareError::Unauthorized {
+                msg: "Managers can only remove staff".to_string(),
+            });
+        }
+    }
+    if role == Some(OrgRole::Owner) && before.owner_count() == 1 {
+        return Err(FoodShareError::Conflict {
+            msg: "The last owner cannot be removed".to_string(),
+        });
+    }
+
+    let mut organization = before.clone();
+    organization
+        .members
+        .retain(|member| member.principal != principal);
+    organization
+        .invites
+        .retain(|invite| invite.principal != principal);
+    store_organization(Some(&before), &mut organization);
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Removed {} from organization {}",
+            principal, organization_id
+        ),
+    );
+
+    Ok(organization)
+}
+
+// Function for members and admins to get an organization
+#[ic_cdk::query]
+fn get_organization(organization_id: u64) -> Result<Organization, FoodShareError> {
+    let organization = load_organization(organization_id)?;
+    if !is_admin(&ic_cdk::caller()) {
+        require_role(&organization, OrgRole::Staff)?;
+    }
+    Ok(organization)
+}
+
+// Function to get the organizations the caller is a member of or invited to
+#[ic_cdk::query]
+fn get_my_organizations() -> Vec<Organization> {
+    let caller = ic_cdk::caller();
+    ORGANIZATIONS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, organization)| organization)
+            .filter(|organization| {
+                organization.role_of(&caller).is_some()
+                    || organization
+                        .invites
+                        .iter()
+                        .any(|invite| invite.principal == caller)
+            })
+            .collect()
+    })
+}
@@ -0,0 +1,165 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::contact_vault::redacted;
+use crate::governance::require_admin;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    Assignment, DonorProfile, DriverProfile, FoodShareError, Memory, ReceiverProfile, SurplusPost,
+    SurplusRecord, ASSIGNMENTS_STORAGE, DONORS_STORAGE, DRIVERS_STORAGE, MEMORY_MANAGER,
+    RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE, SURPLUS_RECORDS_STORAGE,
+};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap};
+use std::cell::RefCell;
+
+const DEFAULT_MAX_PAGE_SIZE: u64 = 100;
+// Keeps a full page of the largest records well under the message size limit
+const MAX_PAGE_SIZE_CEILING: u64 = 1_000;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Page<T> {
+    items: Vec<T>,
+    // Number of items in the whole collection, not just this page
+    total: u64,
+    offset: u64,
+}
+
+impl<T> Page<T> {
+    // Function to wrap a page that was cut from a collection outside `paginate`
+    pub(crate) fn new(items: Vec<T>, total: u64, offset: u64) -> Self {
+        Page {
+            items,
+            total,
+            offset,
+        }
+    }
+
+    fn map_items(self, f: impl Fn(T) -> T) -> Self {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            ..self
+        }
+    }
+}
+
+thread_local! {
+    static MAX_PAGE_SIZE: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30))),
+            DEFAULT_MAX_PAGE_SIZE,
+        )
+        .expect("Cannot create the max page size")
+    );
+}
+
+pub(crate) fn max_page_size() -> u64 {
+    MAX_PAGE_SIZE.with(|cell| *cell.borrow().get())
+}
+
+// Function to read `limit` items starting at `offset` from a collection, counting only items accepted by `filter`
+pub(crate) fn paginate<T: BoundedStorable + Clone>(
+    storage: &'static std::thread::LocalKey<RefCell<StableBTreeMap<u64, T, Memory>>>,
+    offset: u64,
+    limit: u64,
+    filter: impl Fn(&T) -> bool,
+) -> Page<T> {
+    let limit = limit.clamp(1, max_page_size()) as usize;
+    storage.with(|storage| {
+        let storage = storage.borrow();
+        let mut total = 0;
+        let mut items = Vec::new();
+        for (_, item) in storage.iter() {
+            if !filter(&item) {
+                continue;
+            }
+            if total >= offset && items.len() < limit {
+                items.push(item);
+            }
+            total += 1;
+        }
+        Page {
+            items,
+            total,
+            offset,
+        }
+    })
+}
+
+// Function to get a page of active donor profiles
+#[ic_cdk::query]
+fn get_donors_paginated(offset: u64, limit: u64) -> Page<DonorProfile> {
+    paginate(&DONORS_STORAGE, offset, limit, |donor| donor.active).map_items(redacted)
+}
+
+// Function to get a page of active receiver profiles
+#[ic_cdk::query]
+fn get_receivers_paginated(offset: u64, limit: u64) -> Page<ReceiverProfile> {
+    paginate(&RECEIVERS_STORAGE, offset, limit, |receiver| {
+        receiver.active
+    })
+    .map_items(redacted)
+}
+
+// Function to get a page of active driver profiles
+#[ic_cdk::query]
+fn get_drivers_paginated(offset: u64, limit: u64) -> Page<DriverProfile> {
+    paginate(&DRIVERS_STORAGE, offset, limit, |driver| driver.active).map_items(redacted)
+}
+
+// Function to get a page of surplus posts
+#[ic_cdk::query]
+fn get_surplus_posts_paginated(offset: u64, limit: u64) -> Page<SurplusPost> {
+    paginate(&SURPLUS_POSTS_STORAGE, offset, limit, |_| true)
+}
+
+// Function to get a page of assignments
+#[ic_cdk::query]
+fn get_assignments_paginated(offset: u64, limit: u64) -> Page<Assignment> {
+    paginate(&ASSIGNMENTS_STORAGE, offset, limit, |_| true)
+}
+
+// Function to get a page of surplus records
+#[ic_cdk::query]
+fn get_surplus_records_paginated(offset: u64, limit: u64) -> Page<SurplusRecord> {
+    paginate(&SURPLUS_RECORDS_STORAGE, offset, limit, |_| true)
+}
+
+// Function to get the largest page the paginated queries return
+#[ic_cdk::query]
+fn get_max_page_size() -> u64 {
+    max_page_size()
+}
+
+// Function to change the largest page the paginated queries return
+#[ic_cdk::update]
+fn set_max_page_size(max_page_size: u64) -> Result<u64, FoodShareError> {
+    start_trace("set_max_page_size");
+    require_admin("change the page size")?;
+
+    if max_page_size == 0 || max_page_size > MAX_PAGE_SIZE_CEILING {
+        return Err(FoodShareError::Validation {
+            field: "max_page_size".to_string(),
+            reason: format!(
+                "Max page size must be between 1 and {}",
+                MAX_PAGE_SIZE_CEILING
+            ),
+        });
+    }
+
+    let before = MAX_PAGE_SIZE
+        .with(|cell| cell.borrow_mut().set(max_page_size))
+        .expect("Cannot update the max page size");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::PageSize,
+        0,
+        Some(&before),
+        Some(&max_page_size),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Set max page size to {}", max_page_size),
+    );
+
+    Ok(max_page_size)
+}
@@ -0,0 +1,677 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::geo::haversine_km;
+use crate::governance::{authorize, is_governance_or_admin, require_admin, EndpointClass};
+use crate::observability::{current_trace_id, log, resume_trace, start_trace, LogLevel};
+use crate::{
+    Assignment, FoodShareError, Memory, SurplusRecord, ASSIGNMENTS_STORAGE, DRIVERS_STORAGE,
+    MEMORY_MANAGER, RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode, Nat, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::collections::BTreeSet;
+use std::{borrow::Cow, cell::RefCell};
+
+// ICRC-1 ledgers deduplicate transfers for 24 hours; resubmissions stay well inside that
+const DEDUP_WINDOW_NANOS: u64 = 20 * 60 * 60 * 1_000_000_000;
+// Ledgers return at most this many transactions per query
+const RECONCILE_PAGE_SIZE: u64 = 2_000;
+// Reconciliation gives up past this many blocks and leaves the payout for an admin
+const MAX_RECONCILE_BLOCKS: u64 = 20_000;
+const MAX_ERROR_LEN: usize = 200;
+
+// PayoutStatus is a custom enum type that is used to represent where a delivery reward is in its transfer
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum PayoutStatus {
+    // Queued and never sent to the ledger
+    Pending,
+    // Sent to the ledger without a known outcome
+    Submitted,
+    Confirmed,
+    // Rejected by the ledger
+    Failed,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Payout {
+    record_id: u64,
+    // At most one payout is queued per assignment; None for payouts queued before this was tracked
+    assignment_id: Option<u64>,
+    driver_id: u64,
+    to: Principal,
+    amount: u64,
+    // What the amount was scaled by, when the schedule pays per kilogram or kilometre
+    quantity_kg: Option<u32>,
+    distance_km: Option<f64>,
+    status: PayoutStatus,
+    // Sent as the transfer's created_at_time so the ledger deduplicates resubmissions
+    created_at_time: u64,
+    // Ledger length right before the first submission; reconciliation scans from here
+    ledger_start_index: Option<u64>,
+    block_index: Option<u64>,
+    attempts: u32,
+    last_error: Option<String>,
+    queued_at: u64,
+    updated_at: u64,
+}
+
+impl Storable for Payout {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Payout {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct RewardConfig {
+    // ICRC-1 ledger the rewards are paid from; no payouts are queued while unset
+    ledger: Option<Principal>,
+    // Reward per delivery in the ledger's base units
+    reward_per_delivery: u64,
+    // Added per kilogram delivered and per kilometre from pickup to drop-off
+    reward_per_kg: Option<u64>,
+    reward_per_km: Option<u64>,
+    // Cap on the reward for one delivery
+    max_reward: Option<u64>,
+}
+
+impl RewardConfig {
+    // Function to work out the reward for a delivery of a quantity over a distance
+    fn reward_for(&self, quantity_kg: u32, distance_km: Option<f64>) -> u64 {
+        let per_kg = self
+            .reward_per_kg
+            .unwrap_or_default()
+            .saturating_mul(quantity_kg as u64);
+        let per_km = distance_km.map_or(0, |km| {
+            self.reward_per_km
+                .unwrap_or_default()
+                .saturating_mul(km.round() as u64)
+        });
+        let reward = self
+            .reward_per_delivery
+            .saturating_add(per_kg)
+            .saturating_add(per_km);
+        self.max_reward.map_or(reward, |max| reward.min(max))
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RewardSchedule {
+    reward_per_delivery: u64,
+    reward_per_kg: u64,
+    reward_per_km: u64,
+    max_reward: Option<u64>,
+}
+
+impl Storable for RewardConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// ICRC-1 ledger interface, limited to the fields used here
+#[derive(candid::CandidType, Deserialize, Clone, PartialEq, Eq)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    fee: Option<Nat>,
+    created_at_time: Option<u64>,
+    memo: Option<Vec<u8>>,
+    amount: Nat,
+}
+
+#[derive(candid::CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct GetTransactionsRequest {
+    start: Nat,
+    length: Nat,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct LedgerTransfer {
+    from: Account,
+    to: Account,
+    memo: Option<Vec<u8>>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct LedgerTransaction {
+    transfer: Option<LedgerTransfer>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct GetTransactionsResponse {
+    log_length: Nat,
+    // Transactions before this index have been moved to archive canisters
+    first_index: Nat,
+    transactions: Vec<LedgerTransaction>,
+}
+
+thread_local! {
+    static PAYOUTS_STORAGE: RefCell<StableBTreeMap<u64, Payout, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34)))
+    ));
+
+    static REWARD_CONFIG: RefCell<Cell<RewardConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35))),
+            RewardConfig::default(),
+        )
+        .expect("Cannot create the reward config")
+    );
+
+    // Records with a ledger call in progress; a second call for the same record is refused
+    static PAYOUTS_IN_FLIGHT: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+// Held for the duration of a payout's ledger calls. Dropping it, including when
+// a callback traps, lets the record be processed again.
+struct InFlightGuard(u64);
+
+impl InFlightGuard {
+    fn acquire(record_id: u64) -> Option<Self> {
+        PAYOUTS_IN_FLIGHT
+            .with(|in_flight| in_flight.borrow_mut().insert(record_id))
+            .then_some(Self(record_id))
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        PAYOUTS_IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().remove(&self.0));
+    }
+}
+
+fn reward_config() -> RewardConfig {
+    REWARD_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+fn store_payout(payout: &mut Payout) {
+    payout.updated_at = time();
+    let before = PAYOUTS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(payout.record_id, payout.clone())
+    });
+    audit(
+        if before.is_some() {
+            AuditAction::StatusChanged
+        } else {
+            AuditAction::Created
+        },
+        AuditEntity::Payout,
+        payout.record_id,
+        before.as_ref(),
+        Some(&*payout),
+    );
+}
+
+fn nat_to_u64(value: &Nat) -> Result<u64, String> {
+    u64::try_from(&value.0).map_err(|_| format!("Ledger value {} does not fit in u64", value))
+}
+
+// Ledger helpers report plain strings; endpoints surface them as internal errors
+fn ledger_error(msg: String) -> FoodShareError {
+    FoodShareError::InternalError { msg }
+}
+
+fn truncate_error(error: String) -> String {
+    error.chars().take(MAX_ERROR_LEN).collect()
+}
+
+// Function to get the distance a delivery covered: from the post, or from the
+// hub that handed it on, to the receiver. None when either end has no coordinates.
+fn delivery_distance_km(assignment: &Assignment, post_location: Option<(f64, f64)>) -> Option<f64> {
+    let location_of = |receiver_id: u64| {
+        RECEIVERS_STORAGE
+            .with(|storage| storage.borrow().get(&receiver_id))
+            .and_then(|receiver| Some((receiver.latitude?, receiver.longitude?)))
+    };
+    let (from_lat, from_lng) = match assignment.previous_leg_id {
+        Some(previous_id) => ASSIGNMENTS_STORAGE
+            .with(|storage| storage.borrow().get(&previous_id))
+            .and_then(|previous| location_of(previous.receiver_id))?,
+        None => post_location?,
+    };
+    let (to_lat, to_lng) = location_of(assignment.receiver_id)?;
+    Some(haversine_km(from_lat, from_lng, to_lat, to_lng))
+}
+
+// Function to queue the reward for a confirmed delivery and send it to the
+// ledger. Payouts are keyed by record and checked per assignment, so
+// delivering the same assignment again never creates a second payout.
+pub(crate) fn queue_delivery_reward(record: &SurplusRecord, assignment: &Assignment) {
+    let config = reward_config();
+    if config.ledger.is_none() {
+        return;
+    }
+    let already_queued = PAYOUTS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        storage.contains_key(&record.id)
+            || storage
+                .iter()
+                .any(|(_, payout)| payout.assignment_id == Some(assignment.id))
+    });
+    if already_queued {
+        return;
+    }
+    let Some(post) =
+        SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&record.surplus_post_id))
+    else {
+        return;
+    };
+    let distance_km = delivery_distance_km(assignment, post.latitude.zip(post.longitude));
+    let amount = config.reward_for(post.quantity_kg, distance_km);
+    if amount == 0 {
+        return;
+    }
+    let Some(to) = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&record.driver_id))
+        .and_then(|driver| driver.owner)
+    else {
+        log(
+            LogLevel::Warn,
+            format!(
+                "Driver {} has no owner to pay the reward for record {} to",
+                record.driver_id, record.id
+            ),
+        );
+        return;
+    };
+
+    let now = time();
+    let mut payout = Payout {
+        record_id: record.id,
+        assignment_id: Some(assignment.id),
+        driver_id: record.driver_id,
+        to,
+        amount,
+        quantity_kg: Some(post.quantity_kg),
+        distance_km,
+        status: PayoutStatus::Pending,
+        created_at_time: now,
+        ledger_start_index: None,
+        block_index: None,
+        attempts: 0,
+        last_error: None,
+        queued_at: now,
+        updated_at: now,
+    };
+    store_payout(&mut payout);
+    ic_cdk::spawn(send_delivery_reward(record.id));
+}
+
+// Function to send a newly queued reward. A failure leaves the payout for
+// governance to retry with submit_payout.
+async fn send_delivery_reward(record_id: u64) {
+    match pay_out(record_id).await {
+        Ok(payout) if payout.status == PayoutStatus::Confirmed => {}
+        Ok(_) | Err(_) => log(
+            LogLevel::Warn,
+            format!(
+                "Reward for record {} was not confirmed, it is left for submit_payout",
+                record_id
+            ),
+        ),
+    }
+}
+
+fn memo(record_id: u64) -> Vec<u8> {
+    record_id.to_be_bytes().to_vec()
+}
+
+async fn get_transactions(
+    ledger: Principal,
+    start: u64,
+    length: u64,
+) -> Result<GetTransactionsResponse, String> {
+    let request = GetTransactionsRequest {
+        start: Nat::from(start),
+        length: Nat::from(length),
+    };
+    let trace_id = current_trace_id();
+    let result: Result<(GetTransactionsResponse,), _> =
+        ic_cdk::call(ledger, "get_transactions", (request,)).await;
+    resume_trace(&trace_id);
+    result
+        .map(|(response,)| response)
+        .map_err(|(code, msg)| format!("Ledger query failed ({:?}): {}", code, msg))
+}
+
+// Function to look for an earlier submission of the payout on the ledger.
+// Returns the block it landed in, or None when the ledger has no such transfer.
+async fn reconcile(ledger: Principal, payout: &Payout) -> Result<Option<u64>, String> {
+    let Some(mut start) = payout.ledger_start_index else {
+        return Err("Payout was submitted without a ledger start index".to_string());
+    };
+    let from = Account {
+        owner: ic_cdk::id(),
+        subaccount: None,
+    };
+    let to = Account {
+        owner: payout.to,
+        subaccount: None,
+    };
+    let memo = memo(payout.record_id);
+    let end = start + MAX_RECONCILE_BLOCKS;
+
+    loop {
+        let response = get_transactions(ledger, start, RECONCILE_PAGE_SIZE).await?;
+        if nat_to_u64(&response.first_index)? > start {
+            return Err(format!(
+                "Blocks from {} are archived, reconcile the payout manually",
+                start
+            ));
+        }
+        let found = response.transactions.iter().position(|transaction| {
+            transaction.transfer.as_ref().is_some_and(|transfer| {
+                transfer.from == from && transfer.to == to && transfer.memo.as_ref() == Some(&memo)
+            })
+        });
+        if let Some(offset) = found {
+            return Ok(Some(start + offset as u64));
+        }
+
+        start += response.transactions.len() as u64;
+        if response.transactions.is_empty() || start >= nat_to_u64(&response.log_length)? {
+            return Ok(None);
+        }
+        if start >= end {
+            return Err(format!(
+                "No match in {} blocks, reconcile the payout manually",
+                MAX_RECONCILE_BLOCKS
+            ));
+        }
+    }
+}
+
+// Function for governance to pay out the reward queued for a delivery. Safe to
+// call again: confirmed payouts are returned as they are, and a payout that was
+// sent before is reconciled against the ledger before it is resent.
+#[ic_cdk::update]
+async fn submit_payout(record_id: u64) -> Result<Payout, FoodShareError> {
+    start_trace("submit_payout");
+    authorize(EndpointClass::Logistics).await?;
+
+    if !is_governance_or_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only governance can submit payouts".to_string(),
+        });
+    }
+    pay_out(record_id).await
+}
+
+// Function to send a queued payout to the ledger, reconciling earlier attempts first
+async fn pay_out(record_id: u64) -> Result<Payout, FoodShareError> {
+    let trace_id = current_trace_id();
+    let ledger = reward_config().ledger.ok_or(FoodShareError::Conflict {
+        msg: "No reward ledger is configured".to_string(),
+    })?;
+
+    let mut payout = PAYOUTS_STORAGE
+        .with(|storage| storage.borrow().get(&record_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "No payout is queued for this record".to_string(),
+        })?;
+    if payout.status == PayoutStatus::Confirmed {
+        return Ok(payout);
+    }
+    let _guard = InFlightGuard::acquire(record_id).ok_or(FoodShareError::Conflict {
+        msg: "A payout for this record is already in progress".to_string(),
+    })?;
+
+    if payout.attempts > 0 {
+        let reconciled = reconcile(ledger, &payout).await;
+        resume_trace(&trace_id);
+        match reconciled {
+            Ok(Some(block_index)) => {
+                payout.status = PayoutStatus::Confirmed;
+                payout.block_index = Some(block_index);
+                payout.last_error = None;
+                store_payout(&mut payout);
+                log(
+                    LogLevel::Info,
+                    format!(
+                        "Reconciled payout for record {} at block {}",
+                        record_id, block_index
+                    ),
+                );
+                return Ok(payout);
+            }
+            Ok(None) => {}
+            Err(error) => {
+                payout.last_error = Some(truncate_error(error.clone()));
+                store_payout(&mut payout);
+                return Err(ledger_error(error));
+            }
+        }
+    }
+
+    // The ledger holds no earlier transfer, so a fresh created_at_time is safe
+    // once the original has left the deduplication window
+    if payout.ledger_start_index.is_none()
+        || time().saturating_sub(payout.created_at_time) > DEDUP_WINDOW_NANOS
+    {
+        let response = get_transactions(ledger, 0, 0).await;
+        resume_trace(&trace_id);
+        let log_length = response.and_then(|response| nat_to_u64(&response.log_length));
+        payout.ledger_start_index = Some(log_length.map_err(ledger_error)?);
+        payout.created_at_time = time();
+    }
+
+    // Stored before the call so a lost reply leaves the payout Submitted, which
+    // forces reconciliation on the next attempt
+    payout.status = PayoutStatus::Submitted;
+    payout.attempts += 1;
+    store_payout(&mut payout);
+
+    let transfer = TransferArg {
+        from_subaccount: None,
+        to: Account {
+            owner: payout.to,
+            subaccount: None,
+        },
+        fee: None,
+        created_at_time: Some(payout.created_at_time),
+        memo: Some(memo(record_id)),
+        amount: Nat::from(payout.amount),
+    };
+    let result: Result<(Result<Nat, TransferError>,), _> =
+        ic_cdk::call(ledger, "icrc1_transfer", (transfer,)).await;
+    resume_trace(&trace_id);
+
+    match result {
+        Ok((Ok(block_index),))
+        | Ok((Err(TransferError::Duplicate {
+            duplicate_of: block_index,
+        }),)) => {
+            payout.status = PayoutStatus::Confirmed;
+            payout.block_index = Some(nat_to_u64(&block_index).map_err(ledger_error)?);
+            payout.last_error = None;
+        }
+        Ok((Err(error),)) => {
+            payout.status = PayoutStatus::Failed;
+            payout.last_error = Some(truncate_error(format!("{:?}", error)));
+        }
+        Err((code, msg)) => {
+            payout.last_error = Some(truncate_error(format!(
+                "Ledger call failed ({:?}): {}",
+                code, msg
+            )));
+        }
+    }
+    store_payout(&mut payout);
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Payout for record {} is {:?} after attempt {}",
+            record_id, payout.status, payout.attempts
+        ),
+    );
+
+    Ok(payout)
+}
+
+// Function to get the payout of a delivery
+#[ic_cdk::query]
+fn get_payout(record_id: u64) -> Result<Payout, FoodShareError> {
+    PAYOUTS_STORAGE
+        .with(|storage| storage.borrow().get(&record_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Payout for record with id={} not found", record_id),
+        })
+}
+
+// Function to get the payouts in a status, e.g. Submitted payouts awaiting reconciliation
+#[ic_cdk::query]
+fn get_payouts_by_status(status: PayoutStatus) -> Result<Vec<Payout>, FoodShareError> {
+    let payouts: Vec<Payout> = PAYOUTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, payout)| payout)
+            .filter(|payout| payout.status == status)
+            .collect()
+    });
+
+    if payouts.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: "No payouts found.".to_string(),
+        });
+    }
+
+    Ok(payouts)
+}
+
+// Function to set the ledger and amount used for delivery rewards
+#[ic_cdk::update]
+fn set_reward_config(
+    ledger: Principal,
+    reward_per_delivery: u64,
+) -> Result<RewardConfig, FoodShareError> {
+    start_trace("set_reward_config");
+    require_admin("change the reward config")?;
+
+    let config = RewardConfig {
+        ledger: Some(ledger),
+        reward_per_delivery,
+        ..reward_config()
+    };
+    let before = REWARD_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the reward config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::RewardConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Set delivery reward to {} on ledger {}",
+            reward_per_delivery, ledger
+        ),
+    );
+
+    Ok(config)
+}
+
+// Function to set how delivery rewards scale with the kilograms delivered and
+// the distance driven, on top of the base reward per delivery
+#[ic_cdk::update]
+fn set_reward_schedule(schedule: RewardSchedule) -> Result<RewardConfig, FoodShareError> {
+    start_trace("set_reward_schedule");
+    require_admin("change the reward schedule")?;
+
+    if schedule.max_reward == Some(0) {
+        return Err(FoodShareError::Validation {
+            field: "max_reward".to_string(),
+            reason: "Max reward must be above 0, or unset for no cap".to_string(),
+        });
+    }
+
+    let config = RewardConfig {
+        reward_per_delivery: schedule.reward_per_delivery,
+        reward_per_kg: Some(schedule.reward_per_kg),
+        reward_per_km: Some(schedule.reward_per_km),
+        max_reward: schedule.max_reward,
+        ..reward_config()
+    };
+    let before = REWARD_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the reward config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::RewardConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Set delivery reward schedule to {} per delivery, {} per kg, {} per km",
+            schedule.reward_per_delivery, schedule.reward_per_kg, schedule.reward_per_km
+        ),
+    );
+
+    Ok(config)
+}
+
+// Function to get the ledger and amount used for delivery rewards
+#[ic_cdk::query]
+fn get_reward_config() -> RewardConfig {
+    reward_config()
+}
+
+// Function for a driver to get the rewards paid or queued to them, newest first
+#[ic_cdk::query]
+fn get_my_reward_history() -> Vec<Payout> {
+    let caller = ic_cdk::caller();
+    let mut payouts: Vec<Payout> = PAYOUTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, payout)| payout)
+            .filter(|payout| payout.to == caller)
+            .collect()
+    });
+    payouts.sort_by_key(|payout| std::cmp::Reverse(payout.queued_at));
+    payouts
+}
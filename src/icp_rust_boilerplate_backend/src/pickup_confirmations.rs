@@ -0,0 +1,342 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, is_governance_or_admin, require_admin, EndpointClass};
+use crate::observability::{log, start_timer_trace, start_trace, LogLevel};
+use crate::{
+    Assignment, FoodShareError, Memory, ASSIGNMENTS_STORAGE, DONORS_STORAGE, MEMORY_MANAGER,
+    SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const AUTO_CONFIRM_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// The sweep runs hourly, so shorter delays would not be honoured
+const MIN_AUTO_CONFIRM_HOURS: u64 = 1;
+const MAX_AUTO_CONFIRM_HOURS: u64 = 7 * 24;
+
+// PickupConfirmer is a custom enum type that is used to represent how a pickup was confirmed
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum PickupConfirmer {
+    Donor,
+    // No one confirmed it within the configured delay
+    Auto,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PickupConfirmation {
+    assignment_id: u64,
+    surplus_post_id: u64,
+    donor_id: Option<u64>,
+    driver_id: u64,
+    // When the driver marked the food picked up
+    picked_up_at: u64,
+    confirmed_at: Option<u64>,
+    confirmed_by: Option<PickupConfirmer>,
+}
+
+impl Storable for PickupConfirmation {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for PickupConfirmation {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Running totals of how a donor's pickups were confirmed
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct PickupCounts {
+    by_donor: u64,
+    automatic: u64,
+}
+
+impl Storable for PickupCounts {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for PickupCounts {
+    const MAX_SIZE: u32 = 64;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PickupConfirmationConfig {
+    // Hours after pickup when an unconfirmed pickup is confirmed automatically
+    auto_confirm_hours: u64,
+}
+
+impl Default for PickupConfirmationConfig {
+    fn default() -> Self {
+        Self {
+            auto_confirm_hours: 24,
+        }
+    }
+}
+
+impl Storable for PickupConfirmationConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static PICKUP_CONFIRMATION_CONFIG: RefCell<Cell<PickupConfirmationConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(80))),
+            PickupConfirmationConfig::default(),
+        )
+        .expect("Cannot create the pickup confirmation config")
+    );
+
+    // Keyed by assignment
+    static PICKUP_CONFIRMATIONS: RefCell<StableBTreeMap<u64, PickupConfirmation, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(81)))
+    ));
+
+    // Keyed by donor
+    static DONOR_PICKUP_COUNTS: RefCell<StableBTreeMap<u64, PickupCounts, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(82)))
+    ));
+}
+
+fn post_donor(surplus_post_id: u64) -> Option<u64> {
+    SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&surplus_post_id))
+        .and_then(|post| post.donor_id.parse::<u64>().ok())
+}
+
+// Function to open the donor's confirmation of a pickup the driver just reported
+pub(crate) fn record_pickup(assignment: &Assignment) {
+    let pickup = PickupConfirmation {
+        assignment_id: assignment.id,
+        surplus_post_id: assignment.surplus_post_id,
+        donor_id: post_donor(assignment.surplus_post_id),
+        driver_id: assignment.driver_id,
+        picked_up_at: time(),
+        confirmed_at: None,
+        confirmed_by: None,
+    };
+    PICKUP_CONFIRMATIONS.with(|storage| storage.borrow_mut().insert(assignment.id, pickup));
+}
+
+// Function to get the share of a donor's confirmed pickups they confirmed
+// themselves rather than leaving to auto-confirmation
+pub(crate) fn pickup_confirmation_rate(donor_id: u64) -> Option<f64> {
+    let counts = DONOR_PICKUP_COUNTS.with(|storage| storage.borrow().get(&donor_id))?;
+    let total = counts.by_donor + counts.automatic;
+    (total > 0).then(|| counts.by_donor as f64 / total as f64)
+}
+
+fn confirm(mut pickup: PickupConfirmation, confirmer: PickupConfirmer) -> PickupConfirmation {
+    let before = pickup.clone();
+    pickup.confirmed_at = Some(time());
+    pickup.confirmed_by = Some(confirmer);
+    PICKUP_CONFIRMATIONS.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(pickup.assignment_id, pickup.clone())
+    });
+    audit(
+        AuditAction::Updated,
+        AuditEntity::PickupConfirmation,
+        pickup.assignment_id,
+        Some(&before),
+        Some(&pickup),
+    );
+    if let Some(donor_id) = pickup.donor_id {
+        DONOR_PICKUP_COUNTS.with(|storage| {
+            let mut storage = storage.borrow_mut();
+            let mut counts = storage.get(&donor_id).unwrap_or_default();
+            match confirmer {
+                PickupConfirmer::Donor => counts.by_donor += 1,
+                PickupConfirmer::Auto => counts.automatic += 1,
+            }
+            storage.insert(donor_id, counts);
+        });
+    }
+
+    record_event(
+        EntityType::Assignment,
+        pickup.assignment_id,
+        Some(pickup.assignment_id),
+        EventKind::Updated,
+        match confirmer {
+            PickupConfirmer::Donor => "Donor confirmed the food was picked up".to_string(),
+            PickupConfirmer::Auto => {
+                "Pickup confirmed automatically; the donor did not respond".to_string()
+            }
+        },
+    );
+    pickup
+}
+
+// Function for the donor to confirm the food actually left their premises
+#[ic_cdk::update]
+async fn confirm_pickup(assignment_id: u64) -> Result<PickupConfirmation, FoodShareError> {
+    start_trace("confirm_pickup");
+    authorize(EndpointClass::Logistics).await?;
+
+    let pickup = PICKUP_CONFIRMATIONS
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "The driver has not reported picking up this assignment".to_string(),
+        })?;
+    let caller = ic_cdk::caller();
+    let is_donor = pickup
+        .donor_id
+        .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)))
+        .is_some_and(|donor| donor.owner == Some(caller));
+    if !is_donor && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor or governance can confirm this pickup".to_string(),
+        });
+    }
+    if let Some(confirmer) = pickup.confirmed_by {
+        return Err(FoodShareError::Conflict {
+            msg: format!("Pickup was already confirmed ({:?})", confirmer),
+        });
+    }
+
+    let pickup = confirm(pickup, PickupConfirmer::Donor);
+    log(
+        LogLevel::Info,
+        format!("Donor confirmed the pickup of assignment {}", assignment_id),
+    );
+
+    Ok(pickup)
+}
+
+// Function for coordinators to get the pickups donors have not confirmed yet,
+// oldest first
+#[ic_cdk::query]
+fn get_unconfirmed_pickups() -> Result<Vec<PickupConfirmation>, FoodShareError> {
+    require_admin("view unconfirmed pickups")?;
+
+    let mut pickups: Vec<PickupConfirmation> = PICKUP_CONFIRMATIONS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, pickup)| pickup)
+            .filter(|pickup| pickup.confirmed_at.is_none())
+            .collect()
+    });
+    pickups.sort_by_key(|pickup| (pickup.picked_up_at, pickup.assignment_id));
+    Ok(pickups)
+}
+
+// Function to get the pickup confirmation of an assignment
+#[ic_cdk::query]
+fn get_pickup_confirmation(assignment_id: u64) -> Result<PickupConfirmation, FoodShareError> {
+    if !ASSIGNMENTS_STORAGE.with(|storage| storage.borrow().contains_key(&assignment_id)) {
+        return Err(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        });
+    }
+    PICKUP_CONFIRMATIONS
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "The driver has not reported picking up this assignment".to_string(),
+        })
+}
+
+#[ic_cdk::query]
+fn get_pickup_confirmation_config() -> PickupConfirmationConfig {
+    PICKUP_CONFIRMATION_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Function for admins to set how long donors have to confirm a pickup before
+// it is confirmed for them
+#[ic_cdk::update]
+fn set_pickup_confirmation_config(
+    config: PickupConfirmationConfig,
+) -> Result<PickupConfirmationConfig, FoodShareError> {
+    start_trace("set_pickup_confirmation_config");
+    require_admin("update the pickup confirmation config")?;
+
+    if !(MIN_AUTO_CONFIRM_HOURS..=MAX_AUTO_CONFIRM_HOURS).contains(&config.auto_confirm_hours) {
+        return Err(FoodShareError::Validation {
+            field: "auto_confirm_hours".to_string(),
+            reason: format!(
+                "Auto-confirmation delay must be between {} and {} hours",
+                MIN_AUTO_CONFIRM_HOURS, MAX_AUTO_CONFIRM_HOURS
+            ),
+        });
+    }
+
+    let before = PICKUP_CONFIRMATION_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the pickup confirmation config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::PickupConfirmationConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Pickups now confirmed automatically after {} hours",
+            config.auto_confirm_hours
+        ),
+    );
+
+    Ok(config)
+}
+
+// Function to register the periodic sweep that confirms pickups donors left unconfirmed
+pub(crate) fn start_pickup_confirmation_sweep() {
+    ic_cdk_timers::set_timer_interval(AUTO_CONFIRM_SWEEP_INTERVAL, auto_confirm_pickups);
+}
+
+// Function to confirm every pickup the donor has not confirmed within the delay
+fn auto_confirm_pickups() {
+    start_timer_trace("auto_confirm_pickups");
+
+    let delay = PICKUP_CONFIRMATION_CONFIG.with(|cell| cell.borrow().get().auto_confirm_hours);
+    let cutoff = time().saturating_sub(delay * NANOS_PER_HOUR);
+    let lapsed: Vec<PickupConfirmation> = PICKUP_CONFIRMATIONS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, pickup)| pickup)
+            .filter(|pickup| pickup.confirmed_at.is_none() && pickup.picked_up_at <= cutoff)
+            .collect()
+    });
+
+    let count = lapsed.len();
+    for pickup in lapsed {
+        confirm(pickup, PickupConfirmer::Auto);
+    }
+
+    if count > 0 {
+        log(
+            LogLevel::Info,
+            format!("Auto-confirmed {} pickups donors left unconfirmed", count),
+        );
+    }
+}
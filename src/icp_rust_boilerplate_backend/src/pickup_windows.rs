@@ -0,0 +1,220 @@
+use crate::assignment_lifecycle::is_driver_owner;
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, EndpointClass};
+use crate::indexes::store_assignment;
+use crate::notifications::{notify_assignment, NotificationKind};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::post_expiry::expires_at;
+use crate::{
+    is_post_donor, Assignment, AssignmentStatus, FoodShareError, ASSIGNMENTS_STORAGE,
+    RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use ic_cdk::api::time;
+
+// PickupParty is a custom enum type that is used to represent which side of a pickup proposed a window
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum PickupParty {
+    // The donor, or the hub an onward leg is collected from
+    Donor,
+    Driver,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PickupProposal {
+    window_start: u64,
+    window_end: u64,
+    proposed_by: PickupParty,
+    proposed_at: u64,
+}
+
+fn load_assignment(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    let assignment = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })?;
+    // Once the food is collected there is no pickup left to schedule
+    if !matches!(
+        assignment.status,
+        AssignmentStatus::Pending | AssignmentStatus::Accepted
+    ) {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Pickup cannot be scheduled for a {:?} assignment",
+                assignment.status
+            ),
+        });
+    }
+    Ok(assignment)
+}
+
+// Function to check whether the caller hands the food over: the donor of the
+// post, or for an onward leg the hub receiver of the previous leg
+fn is_pickup_owner(assignment: &Assignment) -> bool {
+    match assignment.previous_leg_id {
+        Some(previous_leg_id) => ASSIGNMENTS_STORAGE
+            .with(|storage| storage.borrow().get(&previous_leg_id))
+            .and_then(|previous| {
+                RECEIVERS_STORAGE.with(|storage| storage.borrow().get(&previous.receiver_id))
+            })
+            .is_some_and(|hub| hub.owner == Some(ic_cdk::caller())),
+        None => SURPLUS_POSTS_STORAGE
+            .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+            .is_some_and(|post| is_post_donor(&post)),
+    }
+}
+
+fn caller_party(assignment: &Assignment) -> Result<PickupParty, FoodShareError> {
+    if is_driver_owner(assignment) {
+        Ok(PickupParty::Driver)
+    } else if is_pickup_owner(assignment) {
+        Ok(PickupParty::Donor)
+    } else {
+        Err(FoodShareError::Unauthorized {
+            msg: "Only the donor and the driver of this assignment can schedule its pickup"
+                .to_string(),
+        })
+    }
+}
+
+// Function to reject windows that are empty, already over, or end after the
+// post's best before date
+fn validate_window(
+    assignment: &Assignment,
+    window_start: u64,
+    window_end: u64,
+) -> Result<(), FoodShareError> {
+    if window_start >= window_end {
+        return Err(FoodShareError::Validation {
+            field: "window_end".to_string(),
+            reason: "Pickup window must end after it starts".to_string(),
+        });
+    }
+    if window_end <= time() {
+        return Err(FoodShareError::Expired {
+            msg: "Pickup window has already ended".to_string(),
+        });
+    }
+    let post_expires_at = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+        .and_then(|post| expires_at(&post));
+    if post_expires_at.is_some_and(|expires_at| window_end > expires_at) {
+        return Err(FoodShareError::Validation {
+            field: "window_end".to_string(),
+            reason: "Pickup window must end by the post's best before date".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn store_pickup(before: &Assignment, assignment: &Assignment) {
+    store_assignment(assignment);
+    audit(
+        AuditAction::Updated,
+        AuditEntity::Assignment,
+        assignment.id,
+        Some(before),
+        Some(assignment),
+    );
+}
+
+// Function for the donor or the driver to propose a pickup window; a new
+// proposal replaces one the other side has not confirmed yet
+#[ic_cdk::update]
+async fn propose_pickup_time(
+    assignment_id: u64,
+    window_start: u64,
+    window_end: u64,
+) -> Result<Assignment, FoodShareError> {
+    start_trace("propose_pickup_time");
+    authorize(EndpointClass::Logistics).await?;
+
+    let mut assignment = load_assignment(assignment_id)?;
+    let proposed_by = caller_party(&assignment)?;
+    validate_window(&assignment, window_start, window_end)?;
+
+    let before = assignment.clone();
+    assignment.pickup_proposal = Some(PickupProposal {
+        window_start,
+        window_end,
+        proposed_by,
+        proposed_at: time(),
+    });
+    store_pickup(&before, &assignment);
+
+    notify_assignment(
+        &assignment,
+        NotificationKind::PickupProposed,
+        &format!(
+            "The {:?} proposed a pickup window for surplus post {}",
+            proposed_by, assignment.surplus_post_id
+        ),
+    );
+    log(
+        LogLevel::Info,
+        format!(
+            "{:?} proposed a pickup window for assignment {}",
+            proposed_by, assignment_id
+        ),
+    );
+
+    Ok(assignment)
+}
+
+// Function for the other side of a pickup proposal to accept it as the pickup window
+#[ic_cdk::update]
+async fn confirm_pickup_time(assignment_id: u64) -> Result<Assignment, FoodShareError> {
+    start_trace("confirm_pickup_time");
+    authorize(EndpointClass::Logistics).await?;
+
+    let mut assignment = load_assignment(assignment_id)?;
+    let party = caller_party(&assignment)?;
+    let proposal = assignment
+        .pickup_proposal
+        .clone()
+        .ok_or(FoodShareError::NotFound {
+            msg: "No pickup window has been proposed for this assignment".to_string(),
+        })?;
+    if proposal.proposed_by == party {
+        return Err(FoodShareError::Conflict {
+            msg: "A pickup window must be confirmed by the side that did not propose it"
+                .to_string(),
+        });
+    }
+    validate_window(&assignment, proposal.window_start, proposal.window_end)?;
+
+    let before = assignment.clone();
+    assignment.pickup_window_start = Some(proposal.window_start);
+    assignment.pickup_window_end = Some(proposal.window_end);
+    assignment.pickup_proposal = None;
+    store_pickup(&before, &assignment);
+
+    record_event(
+        EntityType::Assignment,
+        assignment_id,
+        Some(assignment_id),
+        EventKind::Updated,
+        format!(
+            "Pickup scheduled between {} and {}",
+            proposal.window_start, proposal.window_end
+        ),
+    );
+    notify_assignment(
+        &assignment,
+        NotificationKind::PickupScheduled,
+        &format!(
+            "Pickup of surplus post {} is scheduled for the agreed window",
+            assignment.surplus_post_id
+        ),
+    );
+    log(
+        LogLevel::Info,
+        format!(
+            "Confirmed the pickup window of assignment {}",
+            assignment_id
+        ),
+    );
+
+    Ok(assignment)
+}
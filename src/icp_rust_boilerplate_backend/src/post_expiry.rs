@@ -0,0 +1,213 @@
+use crate::assignment_lifecycle::store_transition;
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::browse::{is_listable, remove_open_post};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::indexes::{assignments_for_post, store_post};
+use crate::notifications::notify_post_expiring;
+use crate::observability::{log, start_timer_trace, LogLevel};
+use crate::post_mortems::record_post_mortem;
+use crate::reservations::{record_reservation_outcome, ReservationOutcome};
+use crate::settings::expiry_sweep_interval_secs;
+use crate::split_donations::close_post_reservations;
+use crate::{
+    AssignmentStatus, FoodShareError, Memory, SurplusPost, MEMORY_MANAGER, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use chrono::NaiveDate;
+use ic_cdk::api::time;
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const NANOS_PER_HOUR: u64 = 60 * 60 * NANOS_PER_SECOND;
+// Donors are warned once, in the sweep where their unclaimed post comes within this window
+const EXPIRY_WARNING_NANOS: u64 = 24 * NANOS_PER_HOUR;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct WasteStats {
+    expired_posts: u64,
+    expired_kg: u64,
+}
+
+impl Storable for WasteStats {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static WASTE_STATS: RefCell<Cell<WasteStats, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40))),
+            WasteStats::default(),
+        )
+        .expect("Cannot create the waste stats")
+    );
+
+    static EXPIRY_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+// Function to get the time a post expires: the end of its best before date, in UTC
+pub(crate) fn expires_at(post: &SurplusPost) -> Option<u64> {
+    let best_before = NaiveDate::parse_from_str(&post.best_before_date, "%Y-%m-%d").ok()?;
+    let end_of_day = best_before.succ_opt()?.and_hms_opt(0, 0, 0)?.and_utc();
+    u64::try_from(end_of_day.timestamp())
+        .ok()
+        .map(|secs| secs * NANOS_PER_SECOND)
+}
+
+// Function to register the periodic sweep that expires posts past their best
+// before date, replacing the running one when the interval setting changes
+pub(crate) fn start_expiry_sweep() {
+    let interval = Duration::from_secs(expiry_sweep_interval_secs());
+    let timer = ic_cdk_timers::set_timer_interval(interval, sweep_expired_posts);
+    if let Some(previous) = EXPIRY_TIMER.with(|cell| cell.borrow_mut().replace(timer)) {
+        ic_cdk_timers::clear_timer(previous);
+    }
+}
+
+// Function to flag posts past their best before date as expired and cancel the
+// assignments that have not picked them up yet. Posts already on their way to
+// a receiver are left to finish delivery.
+fn sweep_expired_posts() {
+    start_timer_trace("sweep_expired_posts");
+
+    let now = time();
+    warn_expiring_posts(now);
+    let expired: Vec<SurplusPost> = SURPLUS_POSTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, post)| post)
+            .filter(|post| post.withdrawn_at.is_none() && post.expired_at.is_none())
+            .filter(|post| expires_at(post).is_some_and(|expires_at| expires_at <= now))
+            .collect()
+    });
+
+    for mut post in expired {
+        let assignments = assignments_for_post(post.id);
+        let picked_up = assignments.iter().any(|assignment| {
+            !assignment.status.releases_post()
+                && !assignment.status.can_move_to(AssignmentStatus::Cancelled)
+        });
+        if picked_up {
+            continue;
+        }
+
+        for assignment in assignments
+            .into_iter()
+            .filter(|assignment| assignment.status.is_active())
+        {
+            if let Ok(assignment) = store_transition(
+                assignment,
+                AssignmentStatus::Cancelled,
+                "Assignment cancelled: the surplus post passed its best before date".to_string(),
+            ) {
+                record_reservation_outcome(&assignment, ReservationOutcome::Cancelled);
+            }
+        }
+        close_post_reservations(post.id);
+
+        let before = post.clone();
+        post.expired_at = Some(now);
+        store_post(&post);
+        audit(
+            AuditAction::StatusChanged,
+            AuditEntity::SurplusPost,
+            post.id,
+            Some(&before),
+            Some(&post),
+        );
+        remove_open_post(post.id);
+
+        WASTE_STATS
+            .with(|cell| {
+                let mut stats = cell.borrow().get().clone();
+                stats.expired_posts += 1;
+                stats.expired_kg += post.quantity_kg as u64;
+                cell.borrow_mut().set(stats)
+            })
+            .expect("Cannot update the waste stats");
+
+        record_event(
+            EntityType::SurplusPost,
+            post.id,
+            None,
+            EventKind::Expired,
+            format!(
+                "Post expired unclaimed after its best before date {}",
+                post.best_before_date
+            ),
+        );
+        log(
+            LogLevel::Warn,
+            format!(
+                "Surplus post {} expired with {} kg of food",
+                post.id, post.quantity_kg
+            ),
+        );
+        record_post_mortem(&post);
+    }
+}
+
+// Function to notify donors whose unclaimed posts entered the warning window
+// since the previous sweep
+fn warn_expiring_posts(now: u64) {
+    let window_end = now.saturating_add(EXPIRY_WARNING_NANOS);
+    let window_start = window_end.saturating_sub(expiry_sweep_interval_secs() * NANOS_PER_SECOND);
+    let expiring: Vec<SurplusPost> = SURPLUS_POSTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, post)| post)
+            .filter(is_listable)
+            .filter(|post| {
+                expires_at(post)
+                    .is_some_and(|expires_at| expires_at > window_start && expires_at <= window_end)
+            })
+            .collect()
+    });
+    for post in &expiring {
+        notify_post_expiring(post);
+    }
+}
+
+// Function to get open posts that expire within the given number of hours, soonest first
+#[ic_cdk::query]
+fn get_expiring_posts(within_hours: u64) -> Result<Vec<SurplusPost>, FoodShareError> {
+    let now = time();
+    let cutoff = now.saturating_add(within_hours.saturating_mul(NANOS_PER_HOUR));
+
+    let mut posts: Vec<(u64, SurplusPost)> = SURPLUS_POSTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, post)| post)
+            .filter(is_listable)
+            .filter_map(|post| expires_at(&post).map(|expires_at| (expires_at, post)))
+            .filter(|(expires_at, _)| *expires_at > now && *expires_at <= cutoff)
+            .collect()
+    });
+
+    if posts.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: "No expiring surplus posts found.".to_string(),
+        });
+    }
+
+    posts.sort_by_key(|(expires_at, post)| (*expires_at, post.id));
+    Ok(posts.into_iter().map(|(_, post)| post).collect())
+}
+
+// Function to get how many posts and kilograms of food expired unclaimed
+#[ic_cdk::query]
+fn get_waste_stats() -> WasteStats {
+    WASTE_STATS.with(|cell| cell.borrow().get().clone())
+}
@@ -0,0 +1,352 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{post_events, EventKind};
+use crate::governance::require_admin;
+use crate::indexes::assignments_for_post;
+use crate::matching::{matches_for_post, MatchStatus};
+use crate::notifications::{post_notifications, NotificationKind};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::radius_expansion::reach_expansions;
+use crate::{AssignmentStatus, FoodShareError, FoodType, Memory, SurplusPost, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// The oldest steps are dropped past this so a report always fits its bound
+const MAX_TIMELINE_STEPS: usize = 100;
+const MAX_DETAIL_LEN: usize = 160;
+
+// PostMortemStepKind is a custom enum type that is used to represent what happened at one step of a spoiled post's history
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum PostMortemStepKind {
+    Posted,
+    Updated,
+    // A receiver was matched to the post or a driver was assigned to it
+    Offered,
+    // A match was declined or an assignment ended without a delivery
+    Declined,
+    // One notification sent to everyone it concerned
+    Notified,
+    // The post's reach was widened or its donor warned it was about to expire
+    Escalated,
+    Expired,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PostMortemStep {
+    at: u64,
+    kind: PostMortemStepKind,
+    detail: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PostMortem {
+    post_id: u64,
+    donor_id: String,
+    food_type: FoodType,
+    quantity_kg: u32,
+    best_before_date: String,
+    created_at: Option<u64>,
+    expired_at: u64,
+    offers: u64,
+    declines: u64,
+    // Notifications about the post, one per recipient
+    notifications_sent: u64,
+    escalations: u64,
+    // Oldest first
+    timeline: Vec<PostMortemStep>,
+    generated_at: u64,
+}
+
+impl Storable for PostMortem {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for PostMortem {
+    const MAX_SIZE: u32 = 24 * 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PostMortemConfig {
+    // Posts spoiling with at least this much food get a report
+    min_quantity_kg: u32,
+}
+
+impl Default for PostMortemConfig {
+    fn default() -> Self {
+        Self {
+            min_quantity_kg: 100,
+        }
+    }
+}
+
+impl Storable for PostMortemConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static POST_MORTEM_CONFIG: RefCell<Cell<PostMortemConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(65))),
+            PostMortemConfig::default(),
+        )
+        .expect("Cannot create the post-mortem config")
+    );
+
+    // Keyed by the spoiled post
+    static POST_MORTEMS: RefCell<StableBTreeMap<u64, PostMortem, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(66)))
+    ));
+}
+
+fn step(at: u64, kind: PostMortemStepKind, detail: String) -> PostMortemStep {
+    PostMortemStep {
+        at,
+        kind,
+        detail: detail.chars().take(MAX_DETAIL_LEN).collect(),
+    }
+}
+
+// Function to put together the history of a post from its events, matches,
+// assignments, notifications and reach expansions
+fn build_timeline(post: &SurplusPost) -> Vec<PostMortemStep> {
+    let expansions = reach_expansions(post.id);
+    let mut timeline: Vec<PostMortemStep> = Vec::new();
+
+    for event in post_events(post.id) {
+        let kind = match event.kind {
+            EventKind::Created => PostMortemStepKind::Posted,
+            EventKind::Expired => PostMortemStepKind::Expired,
+            // Reach expansions are added from their own history below
+            EventKind::Updated
+                if expansions
+                    .iter()
+                    .any(|expansion| expansion.expanded_at == event.timestamp) =>
+            {
+                continue;
+            }
+            _ => PostMortemStepKind::Updated,
+        };
+        timeline.push(step(event.timestamp, kind, event.detail));
+    }
+
+    for expansion in expansions {
+        timeline.push(step(
+            expansion.expanded_at,
+            PostMortemStepKind::Escalated,
+            format!(
+                "Matching radius widened from {} km to {} km",
+                expansion.from_km, expansion.to_km
+            ),
+        ));
+    }
+
+    for proposed in matches_for_post(post.id) {
+        timeline.push(step(
+            proposed.created_at,
+            PostMortemStepKind::Offered,
+            format!("Matched to receiver {}", proposed.receiver_id),
+        ));
+        if let (MatchStatus::Declined, Some(decided_at)) = (proposed.status, proposed.decided_at) {
+            timeline.push(step(
+                decided_at,
+                PostMortemStepKind::Declined,
+                format!("Receiver {} declined the match", proposed.receiver_id),
+            ));
+        }
+    }
+
+    for assignment in assignments_for_post(post.id) {
+        timeline.push(step(
+            assignment.created_at,
+            PostMortemStepKind::Offered,
+            format!(
+                "Assignment {} offered to driver {} for receiver {}",
+                assignment.id, assignment.driver_id, assignment.receiver_id
+            ),
+        ));
+        for change in &assignment.status_history {
+            if matches!(
+                change.status,
+                AssignmentStatus::Cancelled
+                    | AssignmentStatus::Failed
+                    | AssignmentStatus::Expired
+                    | AssignmentStatus::TimedOut
+            ) {
+                timeline.push(step(
+                    change.changed_at,
+                    PostMortemStepKind::Declined,
+                    format!(
+                        "Assignment {} ended as {:?} with driver {}",
+                        assignment.id, change.status, assignment.driver_id
+                    ),
+                ));
+            }
+        }
+    }
+
+    // A notification sent to several parties at once counts as one fan-out
+    let mut fan_outs: Vec<(u64, NotificationKind, u64)> = Vec::new();
+    for notification in post_notifications(post.id) {
+        match fan_outs
+            .iter_mut()
+            .find(|(at, kind, _)| *at == notification.created_at && *kind == notification.kind)
+        {
+            Some((_, _, recipients)) => *recipients += 1,
+            None => fan_outs.push((notification.created_at, notification.kind, 1)),
+        }
+    }
+    for (at, kind, recipients) in fan_outs {
+        let step_kind = if kind == NotificationKind::PostExpiring {
+            PostMortemStepKind::Escalated
+        } else {
+            PostMortemStepKind::Notified
+        };
+        timeline.push(step(
+            at,
+            step_kind,
+            format!("{:?} notification sent to {} recipients", kind, recipients),
+        ));
+    }
+
+    timeline.sort_by_key(|step| step.at);
+    if timeline.len() > MAX_TIMELINE_STEPS {
+        let excess = timeline.len() - MAX_TIMELINE_STEPS;
+        timeline.drain(..excess);
+    }
+    timeline
+}
+
+fn count(timeline: &[PostMortemStep], kind: PostMortemStepKind) -> u64 {
+    timeline.iter().filter(|step| step.kind == kind).count() as u64
+}
+
+// Function to write the post-mortem of a post that just expired unclaimed, if
+// it held enough food to warrant one
+pub(crate) fn record_post_mortem(post: &SurplusPost) {
+    let config = POST_MORTEM_CONFIG.with(|cell| cell.borrow().get().clone());
+    if post.quantity_kg < config.min_quantity_kg {
+        return;
+    }
+    let Some(expired_at) = post.expired_at else {
+        return;
+    };
+
+    let timeline = build_timeline(post);
+    let report = PostMortem {
+        post_id: post.id,
+        donor_id: post.donor_id.clone(),
+        food_type: post.food_type,
+        quantity_kg: post.quantity_kg,
+        best_before_date: post.best_before_date.clone(),
+        created_at: post.created_at,
+        expired_at,
+        offers: count(&timeline, PostMortemStepKind::Offered),
+        declines: count(&timeline, PostMortemStepKind::Declined),
+        notifications_sent: post_notifications(post.id).len() as u64,
+        escalations: count(&timeline, PostMortemStepKind::Escalated),
+        timeline,
+        generated_at: time(),
+    };
+    POST_MORTEMS.with(|storage| storage.borrow_mut().insert(post.id, report.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::PostMortem,
+        post.id,
+        None,
+        Some(&report),
+    );
+
+    log(
+        LogLevel::Warn,
+        format!(
+            "Wrote post-mortem for surplus post {}: {} offers, {} declines, {} escalations",
+            post.id, report.offers, report.declines, report.escalations
+        ),
+    );
+}
+
+// Function to get the post-mortem of a spoiled post
+#[ic_cdk::query]
+fn get_post_mortem(post_id: u64) -> Result<PostMortem, FoodShareError> {
+    require_admin("view post-mortems")?;
+
+    POST_MORTEMS
+        .with(|storage| storage.borrow().get(&post_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("No post-mortem for surplus post {}", post_id),
+        })
+}
+
+// Function to get the post-mortems of posts that spoiled since a time, newest first
+#[ic_cdk::query]
+fn get_post_mortems(since: u64) -> Result<Vec<PostMortem>, FoodShareError> {
+    require_admin("view post-mortems")?;
+
+    let mut reports: Vec<PostMortem> = POST_MORTEMS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, report)| report)
+            .filter(|report| report.expired_at >= since)
+            .collect()
+    });
+    reports.sort_by_key(|report| std::cmp::Reverse((report.expired_at, report.post_id)));
+    Ok(reports)
+}
+
+#[ic_cdk::query]
+fn get_post_mortem_config() -> PostMortemConfig {
+    POST_MORTEM_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Function for admins to set how much food a spoiled post must hold to get a
+// post-mortem; reports already written are kept
+#[ic_cdk::update]
+fn set_post_mortem_config(config: PostMortemConfig) -> Result<PostMortemConfig, FoodShareError> {
+    start_trace("set_post_mortem_config");
+    require_admin("update the post-mortem config")?;
+
+    if config.min_quantity_kg == 0 {
+        return Err(FoodShareError::Validation {
+            field: "min_quantity_kg".to_string(),
+            reason: "Threshold must be at least 1 kg".to_string(),
+        });
+    }
+
+    let before = POST_MORTEM_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the post-mortem config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::PostMortemConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Post-mortems now written for spoiled posts of {} kg or more",
+            config.min_quantity_kg
+        ),
+    );
+
+    Ok(config)
+}
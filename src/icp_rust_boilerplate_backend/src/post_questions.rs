@@ -0,0 +1,313 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::category_pauses::is_food_type_paused;
+use crate::governance::{authorize, require_admin, EndpointClass};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    next_id, FoodShareError, Memory, DONORS_STORAGE, MEMORY_MANAGER, RECEIVERS_STORAGE,
+    SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_TEXT_LEN: usize = 500;
+// Questions with this many flags are hidden until a moderator reviews them
+const FLAGS_TO_HIDE: usize = 3;
+const MAX_FLAGS: usize = 10;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PostQuestion {
+    id: u64,
+    post_id: u64,
+    receiver_id: u64,
+    question: String,
+    asked_at: u64,
+    answer: Option<String>,
+    answered_at: Option<u64>,
+    flagged_by: Vec<Principal>,
+    hidden: bool,
+    // Set once a moderator has decided, so further flags no longer hide it
+    moderated: bool,
+}
+
+impl Storable for PostQuestion {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for PostQuestion {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static POST_QUESTIONS_STORAGE: RefCell<StableBTreeMap<u64, PostQuestion, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25)))
+    ));
+}
+
+fn validate_text(text: &str) -> Result<(), FoodShareError> {
+    if text.trim().is_empty() || text.len() > MAX_TEXT_LEN {
+        return Err(FoodShareError::Validation {
+            field: "text".to_string(),
+            reason: format!("Text must be between 1 and {} bytes", MAX_TEXT_LEN),
+        });
+    }
+    Ok(())
+}
+
+// Function for an eligible receiver to ask a public question about an open post
+#[ic_cdk::update]
+async fn ask_post_question(post_id: u64, question: String) -> Result<PostQuestion, FoodShareError> {
+    start_trace("ask_post_question");
+    authorize(EndpointClass::Posting).await?;
+
+    validate_text(&question)?;
+
+    let post = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&post_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Surplus post ID does not exist".to_string(),
+        })?;
+    if post.assigned || post.withdrawn_at.is_some() || post.expired_at.is_some() {
+        return Err(FoodShareError::Conflict {
+            msg: "Questions can only be asked while the post is open".to_string(),
+        });
+    }
+
+    // Only receivers who could claim the post may ask about it
+    let caller = ic_cdk::caller();
+    let receiver = RECEIVERS_STORAGE
+        .with(|storage| {
+            storage
+                .borrow()
+                .iter()
+                .map(|(_, receiver)| receiver)
+                .find(|receiver| receiver.active && receiver.owner == Some(caller))
+        })
+        .ok_or(FoodShareError::Unauthorized {
+            msg: "Only receivers can ask questions about posts".to_string(),
+        })?;
+    if is_food_type_paused(receiver.id, post.food_type) {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Receiver is not accepting {:?} at the moment",
+                post.food_type
+            ),
+        });
+    }
+
+    let id = next_id();
+    let post_question = PostQuestion {
+        id,
+        post_id,
+        receiver_id: receiver.id,
+        question,
+        asked_at: time(),
+        answer: None,
+        answered_at: None,
+        flagged_by: Vec::new(),
+        hidden: false,
+        moderated: false,
+    };
+
+    POST_QUESTIONS_STORAGE.with(|storage| storage.borrow_mut().insert(id, post_question.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::PostQuestion,
+        id,
+        None,
+        Some(&post_question),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Receiver {} asked question {} on post {}",
+            receiver.id, id, post_id
+        ),
+    );
+
+    Ok(post_question)
+}
+
+// Function for the donor of a post to answer a question about it
+#[ic_cdk::update]
+async fn answer_post_question(
+    question_id: u64,
+    answer: String,
+) -> Result<PostQuestion, FoodShareError> {
+    start_trace("answer_post_question");
+    authorize(EndpointClass::Posting).await?;
+
+    validate_text(&answer)?;
+
+    let mut post_question = POST_QUESTIONS_STORAGE
+        .with(|storage| storage.borrow().get(&question_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Question ID does not exist".to_string(),
+        })?;
+
+    let is_donor = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&post_question.post_id))
+        .and_then(|post| post.donor_id.parse::<u64>().ok())
+        .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)))
+        .is_some_and(|donor| donor.owner == Some(ic_cdk::caller()));
+    if !is_donor {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor of the post can answer its questions".to_string(),
+        });
+    }
+
+    let before = post_question.clone();
+    post_question.answer = Some(answer);
+    post_question.answered_at = Some(time());
+    POST_QUESTIONS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(question_id, post_question.clone())
+    });
+    audit(
+        AuditAction::Updated,
+        AuditEntity::PostQuestion,
+        question_id,
+        Some(&before),
+        Some(&post_question),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Answered question {} on post {}",
+            question_id, post_question.post_id
+        ),
+    );
+
+    Ok(post_question)
+}
+
+// Function to flag a question or its answer as inappropriate
+#[ic_cdk::update]
+fn flag_post_question(question_id: u64) -> Result<(), FoodShareError> {
+    start_trace("flag_post_question");
+
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Anonymous callers cannot flag questions".to_string(),
+        });
+    }
+
+    let mut post_question = POST_QUESTIONS_STORAGE
+        .with(|storage| storage.borrow().get(&question_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Question with id={} not found", question_id),
+        })?;
+
+    if post_question.flagged_by.contains(&caller) || post_question.flagged_by.len() >= MAX_FLAGS {
+        return Ok(());
+    }
+    let before = post_question.clone();
+    post_question.flagged_by.push(caller);
+    if !post_question.moderated && post_question.flagged_by.len() >= FLAGS_TO_HIDE {
+        post_question.hidden = true;
+        log(
+            LogLevel::Warn,
+            format!("Question {} hidden pending moderation", question_id),
+        );
+    }
+
+    POST_QUESTIONS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(question_id, post_question.clone())
+    });
+    audit(
+        AuditAction::Updated,
+        AuditEntity::PostQuestion,
+        question_id,
+        Some(&before),
+        Some(&post_question),
+    );
+
+    Ok(())
+}
+
+// Function for moderators to hide or restore a flagged question
+#[ic_cdk::update]
+fn moderate_post_question(question_id: u64, hidden: bool) -> Result<PostQuestion, FoodShareError> {
+    start_trace("moderate_post_question");
+    require_admin("moderate questions")?;
+
+    let mut post_question = POST_QUESTIONS_STORAGE
+        .with(|storage| storage.borrow().get(&question_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Question with id={} not found", question_id),
+        })?;
+
+    let before = post_question.clone();
+    post_question.hidden = hidden;
+    post_question.moderated = true;
+    POST_QUESTIONS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(question_id, post_question.clone())
+    });
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::PostQuestion,
+        question_id,
+        Some(&before),
+        Some(&post_question),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Moderated question {} (hidden: {})", question_id, hidden),
+    );
+
+    Ok(post_question)
+}
+
+// Function to get the visible questions and answers of a post
+#[ic_cdk::query]
+fn get_post_questions(post_id: u64) -> Result<Vec<PostQuestion>, FoodShareError> {
+    let post_exists = SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().contains_key(&post_id));
+    if !post_exists {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Surplus post with id={} not found", post_id),
+        });
+    }
+
+    Ok(POST_QUESTIONS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, question)| question.post_id == post_id && !question.hidden)
+            .map(|(_, question)| question)
+            .collect()
+    }))
+}
+
+// Function for moderators to get the questions hidden by flags
+#[ic_cdk::query]
+fn get_flagged_post_questions() -> Result<Vec<PostQuestion>, FoodShareError> {
+    require_admin("review flagged questions")?;
+
+    Ok(POST_QUESTIONS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, question)| !question.flagged_by.is_empty() && !question.moderated)
+            .map(|(_, question)| question)
+            .collect()
+    }))
+}
@@ -0,0 +1,190 @@
+use crate::browse::{is_listable, open_post_ids};
+use crate::indexes::open_post_ids_for_food_type;
+use crate::pagination::{max_page_size, Page};
+use crate::{
+    Allergen, BusinessType, DietaryTag, FoodShareError, FoodType, SurplusPost, DONORS_STORAGE,
+    SURPLUS_POSTS_STORAGE,
+};
+use chrono::NaiveDate;
+use std::cmp::Reverse;
+
+// SurplusSort is a custom enum type that is used to represent the order search results are returned in
+#[derive(
+    candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default,
+)]
+pub(crate) enum SurplusSort {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+    // Posts without a valid best before date come last
+    ExpiringSoonest,
+    LargestQuantity,
+}
+
+// Criteria for searching surplus posts; every criterion given must match
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct SurplusFilter {
+    // Empty means any food type
+    food_types: Vec<FoodType>,
+    min_quantity_kg: Option<u32>,
+    max_quantity_kg: Option<u32>,
+    // Only posts still open to claim: not assigned, withdrawn or expired
+    unassigned_only: bool,
+    donor_business_type: Option<BusinessType>,
+    // Best before date within [expires_from, expires_to], as "YYYY-MM-DD"
+    expires_from: Option<String>,
+    expires_to: Option<String>,
+    // Posts created before creation times were recorded never match
+    created_after: Option<u64>,
+    // Posts must carry every tag given
+    dietary_tags: Option<Vec<DietaryTag>>,
+    // Posts must declare none of these; posts without declared allergens never match
+    excluded_allergens: Option<Vec<Allergen>>,
+}
+
+fn parse_date(field: &str, date: Option<&str>) -> Result<Option<NaiveDate>, FoodShareError> {
+    date.map(|date| {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| FoodShareError::Validation {
+            field: field.to_string(),
+            reason: "Date must be formatted as YYYY-MM-DD".to_string(),
+        })
+    })
+    .transpose()
+}
+
+fn best_before(post: &SurplusPost) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(&post.best_before_date, "%Y-%m-%d").ok()
+}
+
+fn donor_business_type(post: &SurplusPost) -> Option<BusinessType> {
+    let donor_id: u64 = post.donor_id.parse().ok()?;
+    DONORS_STORAGE
+        .with(|storage| storage.borrow().get(&donor_id))
+        .map(|donor| donor.business_type)
+}
+
+// Function to get the ids of the open posts of the given food types, or of
+// every open post when none are given, so unassigned-only searches skip the rest
+fn open_candidate_ids(food_types: &[FoodType]) -> Vec<u64> {
+    if food_types.is_empty() {
+        return open_post_ids();
+    }
+    let mut ids: Vec<u64> = food_types
+        .iter()
+        .flat_map(|food_type| open_post_ids_for_food_type(*food_type))
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+// Function to search surplus posts by several criteria at once, one page at a time
+#[ic_cdk::query]
+fn search_surplus_posts(
+    filter: SurplusFilter,
+    sort: Option<SurplusSort>,
+    offset: u64,
+    limit: u64,
+) -> Result<Page<SurplusPost>, FoodShareError> {
+    if filter
+        .min_quantity_kg
+        .zip(filter.max_quantity_kg)
+        .is_some_and(|(min, max)| min > max)
+    {
+        return Err(FoodShareError::Validation {
+            field: "max_quantity_kg".to_string(),
+            reason: "Max quantity must not be below the min quantity".to_string(),
+        });
+    }
+    let expires_from = parse_date("expires_from", filter.expires_from.as_deref())?;
+    let expires_to = parse_date("expires_to", filter.expires_to.as_deref())?;
+    if expires_from
+        .zip(expires_to)
+        .is_some_and(|(from, to)| from > to)
+    {
+        return Err(FoodShareError::Validation {
+            field: "expires_to".to_string(),
+            reason: "Expiry window must not end before it starts".to_string(),
+        });
+    }
+
+    let candidate_ids = filter
+        .unassigned_only
+        .then(|| open_candidate_ids(&filter.food_types));
+    let mut posts: Vec<SurplusPost> = SURPLUS_POSTS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        let candidates: Box<dyn Iterator<Item = SurplusPost>> = match &candidate_ids {
+            Some(ids) => Box::new(ids.iter().filter_map(|id| storage.get(id))),
+            None => Box::new(storage.iter().map(|(_, post)| post)),
+        };
+        candidates
+            .filter(|post| {
+                filter.food_types.is_empty() || filter.food_types.contains(&post.food_type)
+            })
+            .filter(|post| {
+                filter
+                    .min_quantity_kg
+                    .is_none_or(|min| post.quantity_kg >= min)
+            })
+            .filter(|post| {
+                filter
+                    .max_quantity_kg
+                    .is_none_or(|max| post.quantity_kg <= max)
+            })
+            .filter(|post| !filter.unassigned_only || is_listable(post))
+            .filter(|post| {
+                filter
+                    .created_after
+                    .is_none_or(|after| post.created_at.is_some_and(|created| created > after))
+            })
+            .filter(|post| {
+                if expires_from.is_none() && expires_to.is_none() {
+                    return true;
+                }
+                best_before(post).is_some_and(|date| {
+                    expires_from.is_none_or(|from| date >= from)
+                        && expires_to.is_none_or(|to| date <= to)
+                })
+            })
+            .filter(|post| {
+                filter.dietary_tags.as_ref().is_none_or(|wanted| {
+                    let tags = post.dietary_tags.as_deref().unwrap_or_default();
+                    wanted.iter().all(|tag| tags.contains(tag))
+                })
+            })
+            .filter(|post| {
+                filter.excluded_allergens.as_ref().is_none_or(|excluded| {
+                    post.allergens.as_ref().is_some_and(|allergens| {
+                        !allergens.iter().any(|allergen| excluded.contains(allergen))
+                    })
+                })
+            })
+            .filter(|post| {
+                filter
+                    .donor_business_type
+                    .is_none_or(|business_type| donor_business_type(post) == Some(business_type))
+            })
+            .collect()
+    });
+
+    // Ids come from one increasing counter, so they follow creation order
+    match sort.unwrap_or_default() {
+        SurplusSort::NewestFirst => posts.sort_by_key(|post| Reverse(post.id)),
+        SurplusSort::OldestFirst => posts.sort_by_key(|post| post.id),
+        SurplusSort::ExpiringSoonest => {
+            posts.sort_by_key(|post| (best_before(post).is_none(), best_before(post), post.id))
+        }
+        SurplusSort::LargestQuantity => {
+            posts.sort_by_key(|post| (Reverse(post.quantity_kg), post.id))
+        }
+    }
+
+    let total = posts.len() as u64;
+    let limit = limit.clamp(1, max_page_size()) as usize;
+    let items = posts
+        .into_iter()
+        .skip(offset.min(total) as usize)
+        .take(limit)
+        .collect();
+    Ok(Page::new(items, total, offset))
+}
@@ -0,0 +1,226 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::contact_vault::open;
+use crate::driver_availability::has_availability;
+use crate::governance::require_admin;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    DonorProfile, DriverProfile, FoodShareError, Memory, ReceiverProfile, DONORS_STORAGE,
+    MEMORY_MANAGER,
+};
+use candid::{Decode, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::cmp::Reverse;
+use std::{borrow::Cow, cell::RefCell};
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct FieldWeight {
+    field: String,
+    weight: u32,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CompletenessConfig {
+    // Fields a role does not have are left out of its score
+    field_weights: Vec<FieldWeight>,
+    // Posts above this size need a donor profile at least `large_post_min_percent` complete
+    large_post_kg: u32,
+    large_post_min_percent: u32,
+}
+
+impl Default for CompletenessConfig {
+    fn default() -> Self {
+        let weight = |field: &str, weight| FieldWeight {
+            field: field.to_string(),
+            weight,
+        };
+        Self {
+            field_weights: vec![
+                weight("name", 20),
+                weight("phone_number", 15),
+                weight("email", 15),
+                weight("address", 15),
+                weight("region", 10),
+                weight("location", 15),
+                weight("availability", 10),
+            ],
+            large_post_kg: 100,
+            large_post_min_percent: 80,
+        }
+    }
+}
+
+impl Storable for CompletenessConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ProfileCompleteness {
+    score_percent: u32,
+    // Heaviest first, so the first one is worth the most to fill in
+    missing_fields: Vec<String>,
+    nudge: Option<String>,
+}
+
+thread_local! {
+    static COMPLETENESS_CONFIG: RefCell<Cell<CompletenessConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(46))),
+            CompletenessConfig::default(),
+        )
+        .expect("Cannot create the completeness config")
+    );
+}
+
+fn completeness_config() -> CompletenessConfig {
+    COMPLETENESS_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Function to score the fields a role has, given whether each one is filled in
+fn score(fields: &[(&str, bool)]) -> ProfileCompleteness {
+    let config = completeness_config();
+    let weighted: Vec<(&str, bool, u32)> = fields
+        .iter()
+        .filter_map(|(field, filled)| {
+            config
+                .field_weights
+                .iter()
+                .find(|weight| weight.field == *field)
+                .map(|weight| (*field, *filled, weight.weight))
+        })
+        .collect();
+
+    let total: u32 = weighted.iter().map(|(_, _, weight)| weight).sum();
+    let filled: u32 = weighted
+        .iter()
+        .filter(|(_, filled, _)| *filled)
+        .map(|(_, _, weight)| weight)
+        .sum();
+    let mut missing: Vec<(&str, u32)> = weighted
+        .iter()
+        .filter(|(_, filled, weight)| !filled && *weight > 0)
+        .map(|(field, _, weight)| (*field, *weight))
+        .collect();
+    missing.sort_by_key(|(_, weight)| Reverse(*weight));
+
+    let score_percent = (filled * 100).checked_div(total).unwrap_or(100);
+    ProfileCompleteness {
+        score_percent,
+        nudge: missing.first().map(|(field, weight)| {
+            format!(
+                "Add your {} to raise your profile to {}% complete",
+                field.replace('_', " "),
+                (filled + weight) * 100 / total
+            )
+        }),
+        missing_fields: missing
+            .into_iter()
+            .map(|(field, _)| field.to_string())
+            .collect(),
+    }
+}
+
+fn has_text(value: &str) -> bool {
+    !value.trim().is_empty()
+}
+
+pub(crate) fn donor_completeness(donor: &DonorProfile) -> ProfileCompleteness {
+    score(&[
+        ("name", has_text(&donor.name)),
+        ("phone_number", has_text(&donor.phone_number)),
+        ("email", open(&donor.email).contains('@')),
+        ("address", has_text(&donor.address)),
+        ("region", has_text(&donor.region)),
+        ("location", donor.latitude.is_some()),
+    ])
+}
+
+pub(crate) fn receiver_completeness(receiver: &ReceiverProfile) -> ProfileCompleteness {
+    score(&[
+        ("name", has_text(&receiver.name)),
+        ("phone_number", has_text(&receiver.phone_number)),
+        ("email", open(&receiver.email).contains('@')),
+        ("address", has_text(&receiver.address)),
+        ("location", receiver.latitude.is_some()),
+    ])
+}
+
+pub(crate) fn driver_completeness(driver: &DriverProfile) -> ProfileCompleteness {
+    score(&[
+        ("name", has_text(&driver.name)),
+        ("phone_number", has_text(&driver.phone_number)),
+        ("email", open(&driver.email).contains('@')),
+        ("address", has_text(&driver.address)),
+        ("region", has_text(&driver.region)),
+        ("location", driver.latitude.is_some()),
+        ("availability", has_availability(driver.id)),
+    ])
+}
+
+// Function to reject large posts from donors whose profile is not complete enough
+pub(crate) fn check_posting_allowed(donor_id: u64, quantity_kg: u32) -> Result<(), FoodShareError> {
+    let config = completeness_config();
+    if quantity_kg <= config.large_post_kg {
+        return Ok(());
+    }
+    let Some(donor) = DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)) else {
+        return Ok(());
+    };
+
+    let completeness = donor_completeness(&donor);
+    if completeness.score_percent < config.large_post_min_percent {
+        return Err(FoodShareError::Unauthorized {
+            msg: format!(
+                "Posts over {} kg need a donor profile at least {}% complete (currently {}%); missing: {}",
+                config.large_post_kg,
+                config.large_post_min_percent,
+                completeness.score_percent,
+                completeness.missing_fields.join(", ")
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Function to get the field weights and feature thresholds used for completeness
+#[ic_cdk::query]
+fn get_completeness_config() -> CompletenessConfig {
+    completeness_config()
+}
+
+// Function for admins to change the field weights and feature thresholds
+#[ic_cdk::update]
+fn set_completeness_config(
+    config: CompletenessConfig,
+) -> Result<CompletenessConfig, FoodShareError> {
+    start_trace("set_completeness_config");
+    require_admin("update the completeness config")?;
+
+    if config.large_post_min_percent > 100 {
+        return Err(FoodShareError::Validation {
+            field: "large_post_min_percent".to_string(),
+            reason: "Completeness threshold must be at most 100".to_string(),
+        });
+    }
+
+    let before = COMPLETENESS_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the completeness config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::CompletenessConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(LogLevel::Info, "Updated completeness config".to_string());
+
+    Ok(config)
+}
@@ -0,0 +1,317 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::browse::is_listable;
+use crate::events::{record_event, EntityType, EventKind};
+use crate::geo::{haversine_km, MAX_RADIUS_KM};
+use crate::governance::require_admin;
+use crate::indexes::store_post;
+use crate::observability::{log, start_timer_trace, start_trace, LogLevel};
+use crate::{FoodShareError, Memory, SurplusPost, MEMORY_MANAGER, SURPLUS_POSTS_STORAGE};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+const RADIUS_SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// Expansion intervals shorter than the sweep would never be honoured
+const MIN_INTERVAL_MINUTES: u64 = 5;
+// Keeps the expansion history of a post within its storage bound
+const MAX_EXPANSION_STEPS: f64 = 24.0;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RadiusExpansionConfig {
+    // Reach of a new post with coordinates
+    pub(crate) start_radius_km: f64,
+    // Added to the reach each time an interval passes with the post still unclaimed
+    pub(crate) step_km: f64,
+    pub(crate) interval_minutes: u64,
+    pub(crate) max_radius_km: f64,
+}
+
+impl Default for RadiusExpansionConfig {
+    fn default() -> Self {
+        Self {
+            start_radius_km: 2.0,
+            step_km: 3.0,
+            interval_minutes: 60,
+            max_radius_km: 20.0,
+        }
+    }
+}
+
+impl Storable for RadiusExpansionConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReachExpansion {
+    pub(crate) from_km: f64,
+    pub(crate) to_km: f64,
+    pub(crate) expanded_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PostReach {
+    post_id: u64,
+    radius_km: f64,
+    // Time the reach was last set, when the post was created or last widened
+    updated_at: u64,
+    expansions: Vec<ReachExpansion>,
+}
+
+impl Storable for PostReach {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for PostReach {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static RADIUS_EXPANSION_CONFIG: RefCell<Cell<RadiusExpansionConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(50))),
+            RadiusExpansionConfig::default(),
+        )
+        .expect("Cannot create the radius expansion config")
+    );
+
+    static POST_REACH: RefCell<StableBTreeMap<u64, PostReach, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(51)))
+    ));
+}
+
+pub(crate) fn radius_expansion_config() -> RadiusExpansionConfig {
+    RADIUS_EXPANSION_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+// Function to get the reach a new post starts with; posts without coordinates have none
+pub(crate) fn initial_reach(latitude: Option<f64>) -> Option<f64> {
+    latitude.map(|_| radius_expansion_config().start_radius_km)
+}
+
+// Function to start tracking the reach of a newly created post
+pub(crate) fn track_post(post: &SurplusPost) {
+    if let Some(radius_km) = post.reach_radius_km {
+        POST_REACH.with(|storage| {
+            storage.borrow_mut().insert(
+                post.id,
+                PostReach {
+                    post_id: post.id,
+                    radius_km,
+                    updated_at: time(),
+                    expansions: Vec::new(),
+                },
+            )
+        });
+    }
+}
+
+// Function to get each step a post's reach was widened by, oldest first
+pub(crate) fn reach_expansions(post_id: u64) -> Vec<ReachExpansion> {
+    POST_REACH
+        .with(|storage| storage.borrow().get(&post_id))
+        .map(|reach| reach.expansions)
+        .unwrap_or_default()
+}
+
+// Function to check whether a point is within a post's reach. Posts or points
+// without coordinates, and posts created before reach was tracked, reach everyone.
+pub(crate) fn within_reach(
+    post: &SurplusPost,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+) -> bool {
+    match (
+        post.reach_radius_km,
+        post.latitude,
+        post.longitude,
+        latitude,
+        longitude,
+    ) {
+        (Some(radius_km), Some(post_lat), Some(post_lng), Some(lat), Some(lng)) => {
+            haversine_km(post_lat, post_lng, lat, lng) <= radius_km
+        }
+        _ => true,
+    }
+}
+
+// Function to register the periodic sweep that widens the reach of unclaimed posts
+pub(crate) fn start_radius_expansion() {
+    ic_cdk_timers::set_timer_interval(RADIUS_SWEEP_INTERVAL, expand_unclaimed_posts);
+}
+
+// Function to widen the reach of every unclaimed post whose interval has passed
+// since its reach was last set, one step at a time up to the configured cap
+fn expand_unclaimed_posts() {
+    start_timer_trace("expand_unclaimed_posts");
+
+    let config = radius_expansion_config();
+    let now = time();
+    let interval = config.interval_minutes * NANOS_PER_MINUTE;
+    let posts: Vec<SurplusPost> = SURPLUS_POSTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, post)| post)
+            .filter(is_listable)
+            .filter(|post| post.latitude.is_some())
+            .collect()
+    });
+
+    for mut post in posts {
+        // Posts from before reach was tracked start at the configured radius now
+        let tracked = POST_REACH.with(|storage| storage.borrow().get(&post.id));
+        let untracked = tracked.is_none();
+        let mut reach = tracked.unwrap_or_else(|| PostReach {
+            post_id: post.id,
+            radius_km: post.reach_radius_km.unwrap_or(config.start_radius_km),
+            updated_at: now,
+            expansions: Vec::new(),
+        });
+        let due = now.saturating_sub(reach.updated_at) >= interval;
+        let to_km = (reach.radius_km + config.step_km).min(config.max_radius_km);
+        if due && to_km > reach.radius_km {
+            reach.expansions.push(ReachExpansion {
+                from_km: reach.radius_km,
+                to_km,
+                expanded_at: now,
+            });
+            reach.radius_km = to_km;
+            reach.updated_at = now;
+        } else if !untracked {
+            continue;
+        }
+        POST_REACH.with(|storage| storage.borrow_mut().insert(post.id, reach.clone()));
+
+        if post.reach_radius_km == Some(reach.radius_km) {
+            continue;
+        }
+        let before = post.clone();
+        post.reach_radius_km = Some(reach.radius_km);
+        store_post(&post);
+        audit(
+            AuditAction::Updated,
+            AuditEntity::SurplusPost,
+            post.id,
+            Some(&before),
+            Some(&post),
+        );
+
+        let detail = match before.reach_radius_km {
+            Some(from_km) => format!(
+                "Matching radius widened from {} km to {} km",
+                from_km, reach.radius_km
+            ),
+            None => format!("Matching radius set to {} km", reach.radius_km),
+        };
+        record_event(
+            EntityType::SurplusPost,
+            post.id,
+            None,
+            EventKind::Updated,
+            detail.clone(),
+        );
+        log(
+            LogLevel::Info,
+            format!("Surplus post {}: {}", post.id, detail),
+        );
+    }
+}
+
+// Function to get the current reach of a post and each step it was widened by
+#[ic_cdk::query]
+fn get_post_reach(post_id: u64) -> Result<PostReach, FoodShareError> {
+    POST_REACH
+        .with(|storage| storage.borrow().get(&post_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("No matching radius tracked for surplus post {}", post_id),
+        })
+}
+
+// Function to get the rules used to widen the reach of unclaimed posts
+#[ic_cdk::query]
+fn get_radius_expansion_config() -> RadiusExpansionConfig {
+    radius_expansion_config()
+}
+
+// Function to check that a radius expansion config reaches its cap in a
+// bounded number of steps, no faster than the sweep runs
+pub(crate) fn validate_config(config: &RadiusExpansionConfig) -> Result<(), FoodShareError> {
+    if !(config.start_radius_km > 0.0 && config.start_radius_km <= config.max_radius_km) {
+        return Err(FoodShareError::Validation {
+            field: "start_radius_km".to_string(),
+            reason: "Start radius must be greater than 0 and at most the max radius".to_string(),
+        });
+    }
+    if config.max_radius_km > MAX_RADIUS_KM {
+        return Err(FoodShareError::Validation {
+            field: "max_radius_km".to_string(),
+            reason: format!("Max radius must be at most {} km", MAX_RADIUS_KM),
+        });
+    }
+    if config.step_km.is_nan()
+        || config.step_km <= 0.0
+        || (config.max_radius_km - config.start_radius_km) / config.step_km > MAX_EXPANSION_STEPS
+    {
+        return Err(FoodShareError::Validation {
+            field: "step_km".to_string(),
+            reason: format!(
+                "Step must be greater than 0 and reach the max radius within {} steps",
+                MAX_EXPANSION_STEPS
+            ),
+        });
+    }
+    if config.interval_minutes < MIN_INTERVAL_MINUTES {
+        return Err(FoodShareError::Validation {
+            field: "interval_minutes".to_string(),
+            reason: format!("Interval must be at least {} minutes", MIN_INTERVAL_MINUTES),
+        });
+    }
+    Ok(())
+}
+
+// Function for admins to change the start radius, expansion step, interval and cap.
+// Posts keep the reach they already have and widen by the new rules from then on.
+#[ic_cdk::update]
+fn set_radius_expansion_config(
+    config: RadiusExpansionConfig,
+) -> Result<RadiusExpansionConfig, FoodShareError> {
+    start_trace("set_radius_expansion_config");
+    require_admin("update the radius expansion config")?;
+    validate_config(&config)?;
+
+    let before = RADIUS_EXPANSION_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the radius expansion config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::RadiusExpansionConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(
+        LogLevel::Info,
+        "Updated radius expansion config".to_string(),
+    );
+
+    Ok(config)
+}
@@ -0,0 +1,307 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::{is_admin, require_admin, EndpointClass};
+use crate::maintenance::{scan_batch, ScanStep};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{FoodShareError, Memory, StorablePrincipal, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const MAX_CALLS_PER_HOUR: u32 = 10_000;
+const MAX_EXEMPT_PRINCIPALS: usize = 50;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RateLimitConfig {
+    registration_per_hour: u32,
+    posting_per_hour: u32,
+    logistics_per_hour: u32,
+    // Principals such as partner integrations that are never limited; admins
+    // are always exempt
+    exempt_principals: Vec<Principal>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            registration_per_hour: 10,
+            posting_per_hour: 60,
+            logistics_per_hour: 300,
+            exempt_principals: Vec::new(),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn per_hour(&self, class: EndpointClass) -> u32 {
+        match class {
+            EndpointClass::Registration => self.registration_per_hour,
+            EndpointClass::Posting => self.posting_per_hour,
+            EndpointClass::Logistics => self.logistics_per_hour,
+        }
+    }
+}
+
+impl Storable for RateLimitConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Calls spent from a bucket; they trickle back at the class's hourly rate, so
+// an idle bucket drains to zero and is full again
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default)]
+struct Bucket {
+    spent: f64,
+    updated_at: u64,
+}
+
+impl Bucket {
+    fn spent_at(&self, per_hour: u32, now: u64) -> f64 {
+        let elapsed_hours = now.saturating_sub(self.updated_at) as f64 / NANOS_PER_HOUR as f64;
+        (self.spent - elapsed_hours * per_hour as f64).max(0.0)
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CallBuckets {
+    registration: Bucket,
+    posting: Bucket,
+    logistics: Bucket,
+}
+
+impl CallBuckets {
+    fn bucket_mut(&mut self, class: EndpointClass) -> &mut Bucket {
+        match class {
+            EndpointClass::Registration => &mut self.registration,
+            EndpointClass::Posting => &mut self.posting,
+            EndpointClass::Logistics => &mut self.logistics,
+        }
+    }
+
+    fn is_drained(&self, config: &RateLimitConfig, now: u64) -> bool {
+        [
+            (self.registration, EndpointClass::Registration),
+            (self.posting, EndpointClass::Posting),
+            (self.logistics, EndpointClass::Logistics),
+        ]
+        .iter()
+        .all(|(bucket, class)| bucket.spent_at(config.per_hour(*class), now) == 0.0)
+    }
+}
+
+impl Storable for CallBuckets {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for CallBuckets {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RateLimitStatus {
+    class: EndpointClass,
+    limit_per_hour: u32,
+    remaining: u32,
+    // When the next call becomes available; None while calls remain
+    next_call_at: Option<u64>,
+    exempt: bool,
+}
+
+thread_local! {
+    static RATE_LIMIT_CONFIG: RefCell<Cell<RateLimitConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(96))),
+            RateLimitConfig::default(),
+        )
+        .expect("Cannot create the rate limit config")
+    );
+
+    static CALL_BUCKETS: RefCell<StableBTreeMap<StorablePrincipal, CallBuckets, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(97)))
+    ));
+}
+
+fn rate_limit_config() -> RateLimitConfig {
+    RATE_LIMIT_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+fn is_exempt(config: &RateLimitConfig, principal: &Principal) -> bool {
+    is_admin(principal) || config.exempt_principals.contains(principal)
+}
+
+// Function to spend one call from the caller's bucket for an endpoint class,
+// rejecting the call once the bucket is empty
+pub(crate) fn check_rate_limit(class: EndpointClass) -> Result<(), FoodShareError> {
+    let caller = ic_cdk::caller();
+    let config = rate_limit_config();
+    if is_exempt(&config, &caller) {
+        return Ok(());
+    }
+
+    let per_hour = config.per_hour(class);
+    let now = time();
+    let key = StorablePrincipal(caller);
+    let mut buckets = CALL_BUCKETS
+        .with(|storage| storage.borrow().get(&key))
+        .unwrap_or_default();
+    let bucket = buckets.bucket_mut(class);
+    let spent = bucket.spent_at(per_hour, now);
+    if spent + 1.0 > per_hour as f64 {
+        log(
+            LogLevel::Warn,
+            format!("Rate limited {:?} call from {}", class, caller),
+        );
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "Too many {:?} calls; the limit is {} per hour",
+                class, per_hour
+            ),
+        });
+    }
+    *bucket = Bucket {
+        spent: spent + 1.0,
+        updated_at: now,
+    };
+    CALL_BUCKETS.with(|storage| storage.borrow_mut().insert(key, buckets));
+
+    Ok(())
+}
+
+// Function to drop the buckets that have fully drained in one batch, since
+// they hold nothing a fresh bucket would not
+pub(crate) fn prune_drained_buckets(cursor: Option<&[u8]>) -> ScanStep {
+    let (entries, next_cursor) = CALL_BUCKETS.with(|storage| scan_batch(&storage.borrow(), cursor));
+    let config = rate_limit_config();
+    let now = time();
+    let drained: Vec<StorablePrincipal> = entries
+        .iter()
+        .filter(|(_, buckets)| buckets.is_drained(&config, now))
+        .map(|(key, _)| *key)
+        .collect();
+    CALL_BUCKETS.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for key in &drained {
+            storage.remove(key);
+        }
+    });
+    ScanStep {
+        next_cursor,
+        scanned: entries.len() as u64,
+        removed: drained.len() as u64,
+        ..Default::default()
+    }
+}
+
+// Function for callers to see how many calls of each class they have left
+#[ic_cdk::query]
+fn get_my_rate_limit_status() -> Vec<RateLimitStatus> {
+    let caller = ic_cdk::caller();
+    let config = rate_limit_config();
+    let exempt = is_exempt(&config, &caller);
+    let now = time();
+    let mut buckets = CALL_BUCKETS
+        .with(|storage| storage.borrow().get(&StorablePrincipal(caller)))
+        .unwrap_or_default();
+
+    [
+        EndpointClass::Registration,
+        EndpointClass::Posting,
+        EndpointClass::Logistics,
+    ]
+    .into_iter()
+    .map(|class| {
+        let per_hour = config.per_hour(class);
+        let spent = buckets.bucket_mut(class).spent_at(per_hour, now);
+        let available = (per_hour as f64 - spent).max(0.0);
+        let next_call_at = (!exempt && available < 1.0).then(|| {
+            now + ((1.0 - available) / per_hour.max(1) as f64 * NANOS_PER_HOUR as f64).ceil() as u64
+        });
+        RateLimitStatus {
+            class,
+            limit_per_hour: per_hour,
+            remaining: if exempt {
+                per_hour
+            } else {
+                available.floor() as u32
+            },
+            next_call_at,
+            exempt,
+        }
+    })
+    .collect()
+}
+
+#[ic_cdk::query]
+fn get_rate_limit_config() -> RateLimitConfig {
+    rate_limit_config()
+}
+
+// Function for admins to set the hourly call limits of each endpoint class and
+// the principals exempt from them
+#[ic_cdk::update]
+fn set_rate_limit_config(mut config: RateLimitConfig) -> Result<RateLimitConfig, FoodShareError> {
+    start_trace("set_rate_limit_config");
+    require_admin("update the rate limit config")?;
+
+    let limits = [
+        config.registration_per_hour,
+        config.posting_per_hour,
+        config.logistics_per_hour,
+    ];
+    if limits
+        .iter()
+        .any(|limit| !(1..=MAX_CALLS_PER_HOUR).contains(limit))
+    {
+        return Err(FoodShareError::Validation {
+            field: "config".to_string(),
+            reason: format!("Hourly limits must be between 1 and {}", MAX_CALLS_PER_HOUR),
+        });
+    }
+    config.exempt_principals.sort();
+    config.exempt_principals.dedup();
+    if config.exempt_principals.len() > MAX_EXEMPT_PRINCIPALS {
+        return Err(FoodShareError::Validation {
+            field: "exempt_principals".to_string(),
+            reason: format!("At most {} principals can be exempt", MAX_EXEMPT_PRINCIPALS),
+        });
+    }
+
+    let before = RATE_LIMIT_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the rate limit config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::RateLimitConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Rate limits set to {}/{}/{} calls per hour with {} exempt principals",
+            config.registration_per_hour,
+            config.posting_per_hour,
+            config.logistics_per_hour,
+            config.exempt_principals.len()
+        ),
+    );
+
+    Ok(config)
+}
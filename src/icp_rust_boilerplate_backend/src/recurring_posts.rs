@@ -0,0 +1,820 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, is_governance_or_admin, EndpointClass};
+use crate::observability::{log, start_timer_trace, start_trace, LogLevel};
+use crate::profile_completeness::check_posting_allowed;
+use crate::verification::check_post_quota;
+use crate::{
+    assign_post, insert_surplus_post, is_active_donor, is_active_receiver, next_id,
+    AssignmentPayload, FoodShareError, FoodType, Memory, SurplusPostPayload, DONORS_STORAGE,
+    DRIVERS_STORAGE, MEMORY_MANAGER,
+};
+use candid::{Decode, Encode};
+use chrono::{DateTime, Datelike, Days, NaiveDate, Timelike};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const TEMPLATE_SWEEP_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const MINUTES_PER_DAY: u16 = 24 * 60;
+const MAX_SHELF_LIFE_DAYS: u32 = 30;
+const MAX_HANDLING_INSTRUCTIONS_LEN: usize = 500;
+// Upcoming skip dates kept per template or commitment; past dates are dropped
+const MAX_SKIP_DATES: usize = 52;
+
+// Recurrence is a custom enum type that is used to represent how often a recurring template posts
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Recurrence {
+    Daily,
+    Weekly,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RecurringTemplate {
+    pub(crate) id: u64,
+    donor_id: u64,
+    // 0 is Monday, 6 is Sunday; unused by daily templates
+    day: u8,
+    // Minutes after midnight UTC when the post goes up
+    post_minute: u16,
+    food_type: FoodType,
+    quantity_kg: u32,
+    handling_instructions: String,
+    // Best before date of each post, counted in days from the day it goes up
+    shelf_life_days: u32,
+    // "YYYY-MM-DD" dates the donor is closed, e.g. holidays
+    skip_dates: Vec<String>,
+    active: bool,
+    // "YYYY-MM-DD" date of the last post created from the template
+    last_posted_on: Option<String>,
+    created_at: u64,
+    // None for templates created before daily ones, which are weekly
+    recurrence: Option<Recurrence>,
+    // Set while the donor has paused the template
+    paused_at: Option<u64>,
+}
+
+impl RecurringTemplate {
+    // Function to check whether the template posts on a weekday (0 is Monday)
+    fn posts_on(&self, day: u8) -> bool {
+        match self.recurrence.unwrap_or(Recurrence::Weekly) {
+            Recurrence::Daily => true,
+            Recurrence::Weekly => self.day == day,
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.active && self.paused_at.is_none()
+    }
+}
+
+impl Storable for RecurringTemplate {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for RecurringTemplate {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A driver's standing claim on every post a template creates
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StandingCommitment {
+    id: u64,
+    template_id: u64,
+    driver_id: u64,
+    // Where the driver takes the food each time
+    receiver_id: u64,
+    // "YYYY-MM-DD" dates the driver cannot make; those posts go to the open pool
+    skip_dates: Vec<String>,
+    active: bool,
+    created_at: u64,
+    ended_at: Option<u64>,
+}
+
+impl Storable for StandingCommitment {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for StandingCommitment {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Recurring Template Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct RecurringTemplatePayload {
+    pub(crate) donor_id: u64,
+    // Weekly when not given
+    recurrence: Option<Recurrence>,
+    day: u8,
+    post_minute: u16,
+    food_type: FoodType,
+    quantity_kg: u32,
+    handling_instructions: String,
+    shelf_life_days: u32,
+}
+
+// Standing Commitment Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct StandingCommitmentPayload {
+    template_id: u64,
+    driver_id: u64,
+    receiver_id: u64,
+}
+
+thread_local! {
+    static TEMPLATES_STORAGE: RefCell<StableBTreeMap<u64, RecurringTemplate, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(54)))
+    ));
+
+    static STANDING_COMMITMENTS_STORAGE: RefCell<StableBTreeMap<u64, StandingCommitment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(55)))
+    ));
+}
+
+fn today() -> NaiveDate {
+    DateTime::from_timestamp_nanos(time() as i64).date_naive()
+}
+
+fn load_template(template_id: u64) -> Result<RecurringTemplate, FoodShareError> {
+    TEMPLATES_STORAGE
+        .with(|storage| storage.borrow().get(&template_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Recurring template with id={} not found", template_id),
+        })
+}
+
+fn load_standing_commitment(commitment_id: u64) -> Result<StandingCommitment, FoodShareError> {
+    STANDING_COMMITMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&commitment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Standing commitment with id={} not found", commitment_id),
+        })
+}
+
+fn is_donor_owner(donor_id: u64) -> bool {
+    let caller = ic_cdk::caller();
+    DONORS_STORAGE
+        .with(|storage| storage.borrow().get(&donor_id))
+        .is_some_and(|donor| donor.owner == Some(caller))
+        || is_governance_or_admin(&caller)
+}
+
+fn is_driver_owner(driver_id: u64) -> bool {
+    let caller = ic_cdk::caller();
+    DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&driver_id))
+        .is_some_and(|driver| driver.owner == Some(caller))
+        || is_governance_or_admin(&caller)
+}
+
+// Function to add a future date to a skip list, dropping dates that have passed
+fn add_skip_date(skip_dates: &mut Vec<String>, date: &str) -> Result<(), FoodShareError> {
+    let invalid = |reason: &str| FoodShareError::Validation {
+        field: "date".to_string(),
+        reason: reason.to_string(),
+    };
+    let skip_on = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| invalid("Date must be formatted as YYYY-MM-DD"))?;
+    let today = today();
+    if skip_on < today {
+        return Err(invalid("Date must not be in the past"));
+    }
+
+    skip_dates.retain(|skipped| {
+        NaiveDate::parse_from_str(skipped, "%Y-%m-%d").is_ok_and(|skipped| skipped >= today)
+    });
+    if skip_dates.iter().any(|skipped| skipped == date) {
+        return Ok(());
+    }
+    if skip_dates.len() >= MAX_SKIP_DATES {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!("At most {} upcoming dates can be skipped", MAX_SKIP_DATES),
+        });
+    }
+    skip_dates.push(date.to_string());
+    skip_dates.sort();
+    Ok(())
+}
+
+fn active_commitment_for(template_id: u64) -> Option<StandingCommitment> {
+    STANDING_COMMITMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, commitment)| commitment)
+            .find(|commitment| commitment.active && commitment.template_id == template_id)
+    })
+}
+
+// Function to register the sweep that posts recurring templates on their day
+pub(crate) fn start_template_sweep() {
+    ic_cdk_timers::set_timer_interval(TEMPLATE_SWEEP_INTERVAL, post_due_templates);
+}
+
+// Function to create today's post for every template whose day and time have
+// come, unless the donor skips today. A committed driver gets the post straight
+// away; if they skip today or cannot take it, the post stays in the open pool.
+fn post_due_templates() {
+    start_timer_trace("post_due_templates");
+
+    let now = DateTime::from_timestamp_nanos(time() as i64);
+    let today = now.date_naive();
+    let day = now.weekday().num_days_from_monday() as u8;
+    let minute = (now.hour() * 60 + now.minute()) as u16;
+    let date = today.format("%Y-%m-%d").to_string();
+
+    let due: Vec<RecurringTemplate> = TEMPLATES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, template)| template)
+            .filter(|template| template.is_running() && template.posts_on(day))
+            .filter(|template| template.post_minute <= minute)
+            .filter(|template| template.last_posted_on.as_deref() != Some(date.as_str()))
+            .collect()
+    });
+
+    for mut template in due {
+        let before = template.clone();
+        template.last_posted_on = Some(date.clone());
+        TEMPLATES_STORAGE
+            .with(|storage| storage.borrow_mut().insert(template.id, template.clone()));
+        audit(
+            AuditAction::Updated,
+            AuditEntity::RecurringTemplate,
+            template.id,
+            Some(&before),
+            Some(&template),
+        );
+
+        if template.skip_dates.contains(&date)
+            || !is_active_donor(template.donor_id)
+            || check_post_quota(template.donor_id).is_err()
+        {
+            log(
+                LogLevel::Info,
+                format!("Skipped recurring template {} on {}", template.id, date),
+            );
+            continue;
+        }
+
+        let Some(best_before) = today.checked_add_days(Days::new(template.shelf_life_days as u64))
+        else {
+            continue;
+        };
+        let post = insert_surplus_post(SurplusPostPayload {
+            donor_id: template.donor_id.to_string(),
+            food_type: template.food_type,
+            food_category_id: None,
+            organization_id: None,
+            quantity_kg: template.quantity_kg,
+            best_before_date: best_before.format("%Y-%m-%d").to_string(),
+            handling_instructions: template.handling_instructions.clone(),
+            latitude: None,
+            longitude: None,
+            photo_asset_ids: None,
+            allergens: None,
+            dietary_tags: None,
+        });
+        log(
+            LogLevel::Info,
+            format!(
+                "Posted surplus post {} from recurring template {}",
+                post.id, template.id
+            ),
+        );
+
+        let Some(commitment) = active_commitment_for(template.id) else {
+            continue;
+        };
+        if commitment.skip_dates.contains(&date) {
+            record_event(
+                EntityType::SurplusPost,
+                post.id,
+                None,
+                EventKind::Updated,
+                format!(
+                    "Committed driver {} skips {}; post left in the open pool",
+                    commitment.driver_id, date
+                ),
+            );
+            continue;
+        }
+        let assigned = assign_post(AssignmentPayload {
+            receiver_id: commitment.receiver_id,
+            surplus_post_id: post.id,
+            driver_id: commitment.driver_id,
+            reservation_id: None,
+        });
+        if assigned.is_err() {
+            record_event(
+                EntityType::SurplusPost,
+                post.id,
+                None,
+                EventKind::Updated,
+                format!(
+                    "Committed driver {} could not take the post; left in the open pool",
+                    commitment.driver_id
+                ),
+            );
+            log(
+                LogLevel::Warn,
+                format!(
+                    "Standing commitment {} could not assign post {}",
+                    commitment.id, post.id
+                ),
+            );
+        }
+    }
+}
+
+// Function to check the schedule, quantity and instructions of a recurring
+// template against the limits and the donor's posting allowance
+pub(crate) fn validate_template_payload(
+    payload: &RecurringTemplatePayload,
+) -> Result<(), FoodShareError> {
+    let recurrence = payload.recurrence.unwrap_or(Recurrence::Weekly);
+    if recurrence == Recurrence::Weekly && payload.day > 6 {
+        return Err(FoodShareError::Validation {
+            field: "day".to_string(),
+            reason: "Day must be between 0 (Monday) and 6 (Sunday)".to_string(),
+        });
+    }
+    if payload.post_minute >= MINUTES_PER_DAY {
+        return Err(FoodShareError::Validation {
+            field: "post_minute".to_string(),
+            reason: format!("Post minute must be below {}", MINUTES_PER_DAY),
+        });
+    }
+    if payload.quantity_kg == 0 {
+        return Err(FoodShareError::Validation {
+            field: "quantity_kg".to_string(),
+            reason: "Quantity must be greater than zero".to_string(),
+        });
+    }
+    if payload.shelf_life_days > MAX_SHELF_LIFE_DAYS {
+        return Err(FoodShareError::Validation {
+            field: "shelf_life_days".to_string(),
+            reason: format!("Shelf life must be at most {} days", MAX_SHELF_LIFE_DAYS),
+        });
+    }
+    if payload.handling_instructions.len() > MAX_HANDLING_INSTRUCTIONS_LEN {
+        return Err(FoodShareError::Validation {
+            field: "handling_instructions".to_string(),
+            reason: format!(
+                "Handling instructions must be at most {} bytes",
+                MAX_HANDLING_INSTRUCTIONS_LEN
+            ),
+        });
+    }
+    check_posting_allowed(payload.donor_id, payload.quantity_kg)
+}
+
+// Function to store a recurring template from a payload already validated
+pub(crate) fn insert_template(payload: RecurringTemplatePayload) -> RecurringTemplate {
+    let recurrence = payload.recurrence.unwrap_or(Recurrence::Weekly);
+    let id = next_id();
+    let template = RecurringTemplate {
+        id,
+        donor_id: payload.donor_id,
+        day: payload.day,
+        post_minute: payload.post_minute,
+        food_type: payload.food_type,
+        quantity_kg: payload.quantity_kg,
+        handling_instructions: payload.handling_instructions,
+        shelf_life_days: payload.shelf_life_days,
+        skip_dates: Vec::new(),
+        active: true,
+        last_posted_on: None,
+        created_at: time(),
+        recurrence: Some(recurrence),
+        paused_at: None,
+    };
+
+    TEMPLATES_STORAGE.with(|storage| storage.borrow_mut().insert(id, template.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::RecurringTemplate,
+        id,
+        None,
+        Some(&template),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Created {:?} recurring template {} for donor {}",
+            recurrence, id, template.donor_id
+        ),
+    );
+
+    template
+}
+
+// Function for a donor to set up a post that goes up every day, or every week
+// on the same day, at the same time
+#[ic_cdk::update]
+async fn create_recurring_template(
+    payload: RecurringTemplatePayload,
+) -> Result<RecurringTemplate, FoodShareError> {
+    start_trace("create_recurring_template");
+    authorize(EndpointClass::Posting).await?;
+
+    if !is_active_donor(payload.donor_id) {
+        return Err(FoodShareError::NotFound {
+            msg: "Donor ID does not exist or is deactivated".to_string(),
+        });
+    }
+    if !is_donor_owner(payload.donor_id) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor or governance can set up a recurring post".to_string(),
+        });
+    }
+    validate_template_payload(&payload)?;
+
+    Ok(insert_template(payload))
+}
+
+// Function for the donor to skip a recurring post on a date, e.g. a holiday
+#[ic_cdk::update]
+async fn skip_template_date(
+    template_id: u64,
+    date: String,
+) -> Result<RecurringTemplate, FoodShareError> {
+    start_trace("skip_template_date");
+    authorize(EndpointClass::Posting).await?;
+
+    let mut template = load_template(template_id)?;
+    if !is_donor_owner(template.donor_id) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor or governance can change this recurring post".to_string(),
+        });
+    }
+
+    let before = template.clone();
+    add_skip_date(&mut template.skip_dates, &date)?;
+    TEMPLATES_STORAGE.with(|storage| storage.borrow_mut().insert(template_id, template.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::RecurringTemplate,
+        template_id,
+        Some(&before),
+        Some(&template),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Recurring template {} skips {}", template_id, date),
+    );
+
+    Ok(template)
+}
+
+// Function for the donor to stop a recurring post; its standing commitment ends with it
+#[ic_cdk::update]
+async fn deactivate_recurring_template(
+    template_id: u64,
+) -> Result<RecurringTemplate, FoodShareError> {
+    start_trace("deactivate_recurring_template");
+    authorize(EndpointClass::Posting).await?;
+
+    let mut template = load_template(template_id)?;
+    if !is_donor_owner(template.donor_id) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor or governance can change this recurring post".to_string(),
+        });
+    }
+    if !template.active {
+        return Err(FoodShareError::Conflict {
+            msg: "Recurring template is already inactive".to_string(),
+        });
+    }
+
+    let before = template.clone();
+    template.active = false;
+    TEMPLATES_STORAGE.with(|storage| storage.borrow_mut().insert(template_id, template.clone()));
+    audit(
+        AuditAction::Deactivated,
+        AuditEntity::RecurringTemplate,
+        template_id,
+        Some(&before),
+        Some(&template),
+    );
+    if let Some(commitment) = active_commitment_for(template_id) {
+        end_commitment(commitment);
+    }
+
+    log(
+        LogLevel::Info,
+        format!("Deactivated recurring template {}", template_id),
+    );
+
+    Ok(template)
+}
+
+// Function for the donor to pause or resume a recurring post, e.g. while the
+// shop is closed for renovation. A paused template keeps its standing
+// commitment and picks up again on its next day after being resumed.
+#[ic_cdk::update]
+async fn pause_recurring_template(
+    template_id: u64,
+    paused: bool,
+) -> Result<RecurringTemplate, FoodShareError> {
+    start_trace("pause_recurring_template");
+    authorize(EndpointClass::Posting).await?;
+
+    let mut template = load_template(template_id)?;
+    if !is_donor_owner(template.donor_id) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor or governance can change this recurring post".to_string(),
+        });
+    }
+    if !template.active {
+        return Err(FoodShareError::Conflict {
+            msg: "Recurring template is inactive".to_string(),
+        });
+    }
+    if template.paused_at.is_some() == paused {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Recurring template is already {}",
+                if paused { "paused" } else { "running" }
+            ),
+        });
+    }
+
+    let before = template.clone();
+    template.paused_at = paused.then(time);
+    if !paused {
+        // Posting is due again from the next matching day, not a day missed while paused
+        template.last_posted_on = Some(today().format("%Y-%m-%d").to_string());
+    }
+    TEMPLATES_STORAGE.with(|storage| storage.borrow_mut().insert(template_id, template.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::RecurringTemplate,
+        template_id,
+        Some(&before),
+        Some(&template),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "{} recurring template {}",
+            if paused { "Paused" } else { "Resumed" },
+            template_id
+        ),
+    );
+
+    Ok(template)
+}
+
+// Function to get the recurring templates of the donors the caller owns
+#[ic_cdk::query]
+fn get_my_recurring_templates() -> Vec<RecurringTemplate> {
+    let caller = ic_cdk::caller();
+    let donor_ids: Vec<u64> = DONORS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, donor)| donor.owner == Some(caller))
+            .map(|(id, _)| id)
+            .collect()
+    });
+    TEMPLATES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, template)| template)
+            .filter(|template| donor_ids.contains(&template.donor_id))
+            .collect()
+    })
+}
+
+// Function to get a donor's recurring templates
+#[ic_cdk::query]
+fn get_recurring_templates(donor_id: u64) -> Vec<RecurringTemplate> {
+    TEMPLATES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, template)| template)
+            .filter(|template| template.donor_id == donor_id)
+            .collect()
+    })
+}
+
+// Function to get the donors of the active templates that post on a weekday
+// (0 is Monday) and already existed at a time, one entry per template. With a
+// date, templates the donor skips that day are left out.
+pub(crate) fn scheduled_template_donors(day: u8, date: Option<&str>, existed_at: u64) -> Vec<u64> {
+    TEMPLATES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, template)| template)
+            .filter(|template| template.is_running() && template.posts_on(day))
+            .filter(|template| template.created_at <= existed_at)
+            .filter(|template| {
+                date.is_none_or(|date| !template.skip_dates.iter().any(|skip| skip == date))
+            })
+            .map(|template| template.donor_id)
+            .collect()
+    })
+}
+
+// Function for a driver to take every post of a recurring template to one receiver.
+// A template has at most one committed driver at a time.
+#[ic_cdk::update]
+async fn create_standing_commitment(
+    payload: StandingCommitmentPayload,
+) -> Result<StandingCommitment, FoodShareError> {
+    start_trace("create_standing_commitment");
+    authorize(EndpointClass::Logistics).await?;
+
+    let template = load_template(payload.template_id)?;
+    if !template.active {
+        return Err(FoodShareError::Conflict {
+            msg: "Recurring template is inactive".to_string(),
+        });
+    }
+    if !DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.driver_id))
+        .is_some_and(|driver| driver.active)
+    {
+        return Err(FoodShareError::NotFound {
+            msg: "Driver ID does not exist or is deactivated".to_string(),
+        });
+    }
+    if !is_driver_owner(payload.driver_id) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the driver or governance can make a standing commitment".to_string(),
+        });
+    }
+    if !is_active_receiver(payload.receiver_id) {
+        return Err(FoodShareError::NotFound {
+            msg: "Receiver ID does not exist or is deactivated".to_string(),
+        });
+    }
+    if let Some(existing) = active_commitment_for(payload.template_id) {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Recurring template is already committed to driver {}",
+                existing.driver_id
+            ),
+        });
+    }
+
+    let id = next_id();
+    let commitment = StandingCommitment {
+        id,
+        template_id: payload.template_id,
+        driver_id: payload.driver_id,
+        receiver_id: payload.receiver_id,
+        skip_dates: Vec::new(),
+        active: true,
+        created_at: time(),
+        ended_at: None,
+    };
+
+    STANDING_COMMITMENTS_STORAGE
+        .with(|storage| storage.borrow_mut().insert(id, commitment.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::StandingCommitment,
+        id,
+        None,
+        Some(&commitment),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Driver {} committed to recurring template {}",
+            commitment.driver_id, commitment.template_id
+        ),
+    );
+
+    Ok(commitment)
+}
+
+// Function for the committed driver to skip a date; that day's post goes to the open pool
+#[ic_cdk::update]
+async fn skip_standing_commitment(
+    commitment_id: u64,
+    date: String,
+) -> Result<StandingCommitment, FoodShareError> {
+    start_trace("skip_standing_commitment");
+    authorize(EndpointClass::Logistics).await?;
+
+    let mut commitment = load_standing_commitment(commitment_id)?;
+    if !is_driver_owner(commitment.driver_id) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the driver or governance can change this commitment".to_string(),
+        });
+    }
+    if !commitment.active {
+        return Err(FoodShareError::Conflict {
+            msg: "Standing commitment has ended".to_string(),
+        });
+    }
+
+    let before = commitment.clone();
+    add_skip_date(&mut commitment.skip_dates, &date)?;
+    STANDING_COMMITMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(commitment_id, commitment.clone())
+    });
+    audit(
+        AuditAction::Updated,
+        AuditEntity::StandingCommitment,
+        commitment_id,
+        Some(&before),
+        Some(&commitment),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Standing commitment {} skips {}", commitment_id, date),
+    );
+
+    Ok(commitment)
+}
+
+fn end_commitment(mut commitment: StandingCommitment) -> StandingCommitment {
+    let before = commitment.clone();
+    commitment.active = false;
+    commitment.ended_at = Some(time());
+    STANDING_COMMITMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(commitment.id, commitment.clone())
+    });
+    audit(
+        AuditAction::Deactivated,
+        AuditEntity::StandingCommitment,
+        commitment.id,
+        Some(&before),
+        Some(&commitment),
+    );
+    log(
+        LogLevel::Info,
+        format!("Ended standing commitment {}", commitment.id),
+    );
+    commitment
+}
+
+// Function for the driver or the donor to end a standing commitment
+#[ic_cdk::update]
+async fn end_standing_commitment(commitment_id: u64) -> Result<StandingCommitment, FoodShareError> {
+    start_trace("end_standing_commitment");
+    authorize(EndpointClass::Logistics).await?;
+
+    let commitment = load_standing_commitment(commitment_id)?;
+    let donor_id = load_template(commitment.template_id)?.donor_id;
+    if !is_driver_owner(commitment.driver_id) && !is_donor_owner(donor_id) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the driver, the donor or governance can end this commitment".to_string(),
+        });
+    }
+    if !commitment.active {
+        return Err(FoodShareError::Conflict {
+            msg: "Standing commitment has already ended".to_string(),
+        });
+    }
+
+    Ok(end_commitment(commitment))
+}
+
+// Function to get the standing commitments made on a recurring template
+#[ic_cdk::query]
+fn get_standing_commitments(template_id: u64) -> Vec<StandingCommitment> {
+    STANDING_COMMITMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, commitment)| commitment)
+            .filter(|commitment| commitment.template_id == template_id)
+            .collect()
+    })
+}
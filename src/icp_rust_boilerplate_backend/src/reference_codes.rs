@@ -0,0 +1,125 @@
+use crate::{
+    Assignment, FoodShareError, SurplusPost, SurplusRecord, ASSIGNMENTS_STORAGE,
+    SURPLUS_POSTS_STORAGE, SURPLUS_RECORDS_STORAGE,
+};
+
+// Crockford's base32 alphabet leaves out I, L, O and U so codes survive being read aloud
+const CROCKFORD_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+// Codes are padded to at least this many symbols, e.g. P-00A7
+const MIN_CODE_LEN: usize = 4;
+// Enough symbols for any u64
+const MAX_CODE_LEN: usize = 13;
+
+// ReferenceKind is a custom enum type that is used to represent which collection a reference code points into
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ReferenceKind {
+    Post,
+    Assignment,
+    Record,
+}
+
+impl ReferenceKind {
+    fn prefix(self) -> char {
+        match self {
+            ReferenceKind::Post => 'P',
+            ReferenceKind::Assignment => 'A',
+            ReferenceKind::Record => 'R',
+        }
+    }
+
+    fn from_prefix(prefix: char) -> Option<Self> {
+        match prefix {
+            'P' => Some(ReferenceKind::Post),
+            'A' => Some(ReferenceKind::Assignment),
+            'R' => Some(ReferenceKind::Record),
+            _ => None,
+        }
+    }
+}
+
+// ReferencedEntity is a custom enum type that is used to represent what a reference code resolved to
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum ReferencedEntity {
+    Post(SurplusPost),
+    Assignment(Assignment),
+    Record(SurplusRecord),
+}
+
+fn encode(mut id: u64) -> String {
+    let mut symbols = Vec::with_capacity(MAX_CODE_LEN);
+    while id > 0 || symbols.len() < MIN_CODE_LEN {
+        symbols.push(CROCKFORD_ALPHABET[(id % 32) as usize]);
+        id /= 32;
+    }
+    symbols.reverse();
+    String::from_utf8(symbols).expect("Crockford symbols are ASCII")
+}
+
+// Function to decode one symbol, accepting lowercase and the letters commonly
+// misheard for digits (I and L for 1, O for 0)
+fn decode_symbol(symbol: char) -> Option<u64> {
+    let symbol = match symbol.to_ascii_uppercase() {
+        'I' | 'L' => '1',
+        'O' => '0',
+        other => other,
+    };
+    CROCKFORD_ALPHABET
+        .iter()
+        .position(|candidate| *candidate as char == symbol)
+        .map(|value| value as u64)
+}
+
+// Function to get the reference code of an entity, e.g. P-7F3K for a surplus post
+pub(crate) fn reference_code(kind: ReferenceKind, id: u64) -> String {
+    format!("{}-{}", kind.prefix(), encode(id))
+}
+
+// Function to parse a reference code, ignoring case, spaces and extra dashes
+fn parse_reference_code(code: &str) -> Option<(ReferenceKind, u64)> {
+    let mut symbols = code.chars().filter(|c| !c.is_whitespace() && *c != '-');
+    let kind = ReferenceKind::from_prefix(symbols.next()?.to_ascii_uppercase())?;
+    let symbols: Vec<char> = symbols.collect();
+    if symbols.is_empty() || symbols.len() > MAX_CODE_LEN {
+        return None;
+    }
+    symbols
+        .into_iter()
+        .try_fold(0u64, |id, symbol| {
+            id.checked_mul(32)?.checked_add(decode_symbol(symbol)?)
+        })
+        .map(|id| (kind, id))
+}
+
+// Function to look up a post, assignment or record by the reference code read out over the phone
+#[ic_cdk::query]
+fn find_by_reference_code(code: String) -> Result<ReferencedEntity, FoodShareError> {
+    let (kind, id) = parse_reference_code(&code).ok_or(FoodShareError::Validation {
+        field: "code".to_string(),
+        reason: "Reference code must look like P-7F3K, A-7F3K or R-7F3K".to_string(),
+    })?;
+
+    let entity = match kind {
+        ReferenceKind::Post => SURPLUS_POSTS_STORAGE
+            .with(|storage| storage.borrow().get(&id))
+            .map(ReferencedEntity::Post),
+        ReferenceKind::Assignment => ASSIGNMENTS_STORAGE
+            .with(|storage| storage.borrow().get(&id))
+            .map(ReferencedEntity::Assignment),
+        ReferenceKind::Record => SURPLUS_RECORDS_STORAGE
+            .with(|storage| storage.borrow().get(&id))
+            .map(ReferencedEntity::Record),
+    };
+    entity.ok_or(FoodShareError::NotFound {
+        msg: format!(
+            "No {:?} with reference code {}",
+            kind,
+            reference_code(kind, id)
+        ),
+    })
+}
+
+// Function to get the reference code of a post, assignment or record by its id
+#[ic_cdk::query]
+fn get_reference_code(kind: ReferenceKind, id: u64) -> String {
+    reference_code(kind, id)
+}
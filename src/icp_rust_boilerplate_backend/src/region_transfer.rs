@@ -0,0 +1,234 @@
+use crate::assignment_lifecycle::store_transition;
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, EndpointClass};
+use crate::indexes::assignments_for_driver;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::reservations::{record_reservation_outcome, release_post, ReservationOutcome};
+use crate::{
+    next_id, AssignmentStatus, FoodShareError, Memory, DONORS_STORAGE, DRIVERS_STORAGE,
+    MEMORY_MANAGER,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_REGION_LEN: usize = 64;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RegionTransfer {
+    id: u64,
+    owner: Principal,
+    entity_type: EntityType,
+    profile_id: u64,
+    from_region: String,
+    to_region: String,
+    // Assignments not yet picked up, cancelled so their posts can be reassigned locally
+    cancelled_assignment_ids: Vec<u64>,
+    transferred_at: u64,
+}
+
+impl Storable for RegionTransfer {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for RegionTransfer {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static REGION_TRANSFERS: RefCell<StableBTreeMap<u64, RegionTransfer, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(47)))
+    ));
+}
+
+fn store_transfer(transfer: &RegionTransfer) {
+    REGION_TRANSFERS.with(|storage| storage.borrow_mut().insert(transfer.id, transfer.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::RegionTransfer,
+        transfer.id,
+        None,
+        Some(transfer),
+    );
+    record_event(
+        transfer.entity_type,
+        transfer.profile_id,
+        None,
+        EventKind::Transferred,
+        format!(
+            "Moved from region {} to {}",
+            transfer.from_region, transfer.to_region
+        ),
+    );
+    log(
+        LogLevel::Info,
+        format!(
+            "Transferred {:?} {} from {} to {}",
+            transfer.entity_type, transfer.profile_id, transfer.from_region, transfer.to_region
+        ),
+    );
+}
+
+// Function to cancel a driver's assignments that have not been picked up yet.
+// Fails without changing anything while food is on board.
+fn hand_over_assignments(driver_id: u64) -> Result<Vec<u64>, FoodShareError> {
+    let active: Vec<_> = assignments_for_driver(driver_id)
+        .into_iter()
+        .filter(|assignment| assignment.status.is_active())
+        .collect();
+    if active
+        .iter()
+        .any(|assignment| !assignment.status.can_move_to(AssignmentStatus::Cancelled))
+    {
+        return Err(FoodShareError::Conflict {
+            msg: "Finish the deliveries already picked up before moving region".to_string(),
+        });
+    }
+
+    let mut cancelled = Vec::with_capacity(active.len());
+    for assignment in active {
+        let assignment = store_transition(
+            assignment,
+            AssignmentStatus::Cancelled,
+            "Assignment cancelled: the driver moved to another region".to_string(),
+        )?;
+        // Onward hub legs do not hold an open post; the food stays at the hub
+        if assignment.previous_leg_id.is_none() {
+            release_post(&assignment);
+            record_reservation_outcome(&assignment, ReservationOutcome::Cancelled);
+        }
+        cancelled.push(assignment.id);
+    }
+    Ok(cancelled)
+}
+
+// Function for a relocating user to move their donor and driver profiles to
+// another region. Profiles keep their ids, so ratings, delivery history and
+// other records tied to them carry over. Unstarted driver assignments are
+// cancelled so the posts can be picked up by someone local; donor posts stay
+// where the food is.
+#[ic_cdk::update]
+async fn request_region_transfer(
+    target_region: String,
+) -> Result<Vec<RegionTransfer>, FoodShareError> {
+    start_trace("request_region_transfer");
+    authorize(EndpointClass::Registration).await?;
+
+    let target_region = target_region.trim().to_string();
+    if target_region.is_empty() || target_region.len() > MAX_REGION_LEN {
+        return Err(FoodShareError::Validation {
+            field: "target_region".to_string(),
+            reason: format!("Region must be between 1 and {} bytes", MAX_REGION_LEN),
+        });
+    }
+
+    let caller = ic_cdk::caller();
+    let donor = DONORS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, donor)| donor)
+            .find(|donor| donor.active && donor.owner == Some(caller))
+    });
+    let driver = DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, driver)| driver)
+            .find(|driver| driver.active && driver.owner == Some(caller))
+    });
+    let donor = donor.filter(|donor| !donor.region.eq_ignore_ascii_case(&target_region));
+    let driver = driver.filter(|driver| !driver.region.eq_ignore_ascii_case(&target_region));
+    if donor.is_none() && driver.is_none() {
+        return Err(FoodShareError::NotFound {
+            msg: "No donor or driver profile outside the target region found for the caller"
+                .to_string(),
+        });
+    }
+
+    let now = time();
+    let mut transfers = Vec::new();
+
+    if let Some(mut driver) = driver {
+        let cancelled_assignment_ids = hand_over_assignments(driver.id)?;
+        let transfer = RegionTransfer {
+            id: next_id(),
+            owner: caller,
+            entity_type: EntityType::Driver,
+            profile_id: driver.id,
+            from_region: driver.region.clone(),
+            to_region: target_region.clone(),
+            cancelled_assignment_ids,
+            transferred_at: now,
+        };
+        let before = driver.clone();
+        driver.region = target_region.clone();
+        // Old coordinates point to the previous city; the user sets new ones
+        driver.latitude = None;
+        driver.longitude = None;
+        DRIVERS_STORAGE.with(|storage| storage.borrow_mut().insert(driver.id, driver.clone()));
+        audit(
+            AuditAction::Updated,
+            AuditEntity::Driver,
+            driver.id,
+            Some(&before),
+            Some(&driver),
+        );
+        store_transfer(&transfer);
+        transfers.push(transfer);
+    }
+
+    if let Some(mut donor) = donor {
+        let transfer = RegionTransfer {
+            id: next_id(),
+            owner: caller,
+            entity_type: EntityType::Donor,
+            profile_id: donor.id,
+            from_region: donor.region.clone(),
+            to_region: target_region.clone(),
+            cancelled_assignment_ids: Vec::new(),
+            transferred_at: now,
+        };
+        let before = donor.clone();
+        donor.region = target_region.clone();
+        donor.latitude = None;
+        donor.longitude = None;
+        DONORS_STORAGE.with(|storage| storage.borrow_mut().insert(donor.id, donor.clone()));
+        audit(
+            AuditAction::Updated,
+            AuditEntity::Donor,
+            donor.id,
+            Some(&before),
+            Some(&donor),
+        );
+        store_transfer(&transfer);
+        transfers.push(transfer);
+    }
+
+    Ok(transfers)
+}
+
+// Function to get the caller's past region transfers, oldest first
+#[ic_cdk::query]
+fn get_my_region_transfers() -> Vec<RegionTransfer> {
+    let caller = ic_cdk::caller();
+    REGION_TRANSFERS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, transfer)| transfer)
+            .filter(|transfer| transfer.owner == caller)
+            .collect()
+    })
+}
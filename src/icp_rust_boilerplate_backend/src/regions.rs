@@ -0,0 +1,316 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::geo::validate_point;
+use crate::governance::{is_admin, require_admin};
+use crate::localization::normalize_locale;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::recurring_posts::{
+    insert_template, validate_template_payload, RecurringTemplatePayload,
+};
+use crate::{next_id, FoodShareError, Memory, DONORS_STORAGE, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_REGION_NAME_LEN: usize = 64;
+const MIN_ZONE_POINTS: usize = 3;
+const MAX_ZONE_POINTS: usize = 100;
+const MAX_REGION_ADMINS: usize = 20;
+const MAX_SEED_TEMPLATES: usize = 50;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct GeoPoint {
+    pub(crate) latitude: f64,
+    pub(crate) longitude: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Region {
+    id: u64,
+    // Matches the region named on donor and driver profiles, ignoring case
+    name: String,
+    // Locale shown to users of the region who have not picked one
+    default_locale: Option<String>,
+    // Polygon the region serves, in order around its edge
+    service_zone: Vec<GeoPoint>,
+    // Principals who coordinate the region without being canister admins
+    admins: Vec<Principal>,
+    // Recurring templates created when the region was bootstrapped
+    seed_template_ids: Vec<u64>,
+    created_by: Principal,
+    created_at: u64,
+}
+
+impl Region {
+    // Function to check whether a point lies inside the service zone, by
+    // counting how many zone edges a ray east from the point crosses
+    fn serves(&self, latitude: f64, longitude: f64) -> bool {
+        let zone = &self.service_zone;
+        let mut inside = false;
+        let mut previous = zone[zone.len() - 1];
+        for point in zone {
+            if (point.latitude > latitude) != (previous.latitude > latitude) {
+                let crossing = point.longitude
+                    + (latitude - point.latitude) * (previous.longitude - point.longitude)
+                        / (previous.latitude - point.latitude);
+                if longitude < crossing {
+                    inside = !inside;
+                }
+            }
+            previous = *point;
+        }
+        inside
+    }
+}
+
+impl Storable for Region {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Region {
+    const MAX_SIZE: u32 = 8192;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static REGIONS: RefCell<StableBTreeMap<u64, Region, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(83)))
+    ));
+}
+
+// Region Bootstrap Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct RegionBootstrapPayload {
+    name: String,
+    default_locale: Option<String>,
+    service_zone: Vec<GeoPoint>,
+    admins: Vec<Principal>,
+    // Weekly or daily posts from donors already registered in the region
+    seed_templates: Vec<RecurringTemplatePayload>,
+}
+
+#[derive(candid::CandidType, Serialize)]
+pub(crate) struct RegionBootstrapReport {
+    region: Region,
+    // What was set up, in the order it was done
+    steps: Vec<String>,
+}
+
+fn find_region(name: &str) -> Option<Region> {
+    REGIONS.with(|regions| {
+        regions
+            .borrow()
+            .iter()
+            .map(|(_, region)| region)
+            .find(|region| region.name.eq_ignore_ascii_case(name))
+    })
+}
+
+// Function to check whether a principal coordinates a region, as a canister
+// admin or one of the region's own admins
+pub(crate) fn is_region_admin(region: &str, principal: &Principal) -> bool {
+    is_admin(principal)
+        || find_region(region.trim()).is_some_and(|region| region.admins.contains(principal))
+}
+
+// Function to reject callers that do not coordinate a region
+pub(crate) fn require_region_admin(region: &str, action: &str) -> Result<(), FoodShareError> {
+    if is_region_admin(region, &ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(FoodShareError::Unauthorized {
+            msg: format!("Only admins of region {} can {}", region.trim(), action),
+        })
+    }
+}
+
+fn validate_service_zone(zone: &[GeoPoint]) -> Result<(), FoodShareError> {
+    if !(MIN_ZONE_POINTS..=MAX_ZONE_POINTS).contains(&zone.len()) {
+        return Err(FoodShareError::Validation {
+            field: "service_zone".to_string(),
+            reason: format!(
+                "Service zone must have between {} and {} points",
+                MIN_ZONE_POINTS, MAX_ZONE_POINTS
+            ),
+        });
+    }
+    for point in zone {
+        validate_point(point.latitude, point.longitude)?;
+    }
+    Ok(())
+}
+
+fn validate_region_admins(admins: &[Principal]) -> Result<Vec<Principal>, FoodShareError> {
+    let mut admins = admins.to_vec();
+    admins.sort();
+    admins.dedup();
+    if admins.len() > MAX_REGION_ADMINS {
+        return Err(FoodShareError::Validation {
+            field: "admins".to_string(),
+            reason: format!("A region can have at most {} admins", MAX_REGION_ADMINS),
+        });
+    }
+    if admins.contains(&Principal::anonymous()) {
+        return Err(FoodShareError::Validation {
+            field: "admins".to_string(),
+            reason: "The anonymous principal cannot be a region admin".to_string(),
+        });
+    }
+    Ok(admins)
+}
+
+fn validate_seed_templates(
+    region: &str,
+    templates: &[RecurringTemplatePayload],
+) -> Result<(), FoodShareError> {
+    if templates.len() > MAX_SEED_TEMPLATES {
+        return Err(FoodShareError::Validation {
+            field: "seed_templates".to_string(),
+            reason: format!("At most {} templates can be seeded", MAX_SEED_TEMPLATES),
+        });
+    }
+    for template in templates {
+        let in_region = DONORS_STORAGE.with(|storage| {
+            storage
+                .borrow()
+                .get(&template.donor_id)
+                .is_some_and(|donor| {
+                    donor.active && donor.region.trim().eq_ignore_ascii_case(region)
+                })
+        });
+        if !in_region {
+            return Err(FoodShareError::Validation {
+                field: "seed_templates".to_string(),
+                reason: format!(
+                    "Donor {} is not an active donor in region {}",
+                    template.donor_id, region
+                ),
+            });
+        }
+        validate_template_payload(template)?;
+    }
+    Ok(())
+}
+
+// Function for admins to set up a new region in one call: the region and its
+// service zone, its admins and its seed recurring templates. Everything is
+// validated before anything is stored, so a rejected payload leaves no
+// partly set up region behind.
+#[ic_cdk::update]
+fn bootstrap_region(
+    payload: RegionBootstrapPayload,
+) -> Result<RegionBootstrapReport, FoodShareError> {
+    start_trace("bootstrap_region");
+    require_admin("bootstrap regions")?;
+
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_REGION_NAME_LEN {
+        return Err(FoodShareError::Validation {
+            field: "name".to_string(),
+            reason: format!(
+                "Name must be between 1 and {} characters",
+                MAX_REGION_NAME_LEN
+            ),
+        });
+    }
+    if find_region(&name).is_some() {
+        return Err(FoodShareError::Conflict {
+            msg: format!("Region {} already exists", name),
+        });
+    }
+    let default_locale = payload
+        .default_locale
+        .as_deref()
+        .map(normalize_locale)
+        .transpose()?;
+    validate_service_zone(&payload.service_zone)?;
+    let admins = validate_region_admins(&payload.admins)?;
+    validate_seed_templates(&name, &payload.seed_templates)?;
+
+    let mut steps = vec![
+        format!("Created region {}", name),
+        format!(
+            "Set the service zone to a {}-point polygon",
+            payload.service_zone.len()
+        ),
+    ];
+    if let Some(locale) = &default_locale {
+        steps.push(format!("Set the default locale to {}", locale));
+    }
+    steps.push(format!("Assigned {} region admins", admins.len()));
+
+    let seed_template_ids: Vec<u64> = payload
+        .seed_templates
+        .into_iter()
+        .map(|template| insert_template(template).id)
+        .collect();
+    steps.push(format!(
+        "Seeded {} recurring templates",
+        seed_template_ids.len()
+    ));
+
+    let region = Region {
+        id: next_id(),
+        name,
+        default_locale,
+        service_zone: payload.service_zone,
+        admins,
+        seed_template_ids,
+        created_by: ic_cdk::caller(),
+        created_at: time(),
+    };
+    REGIONS.with(|regions| regions.borrow_mut().insert(region.id, region.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::Region,
+        region.id,
+        None,
+        Some(&region),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Bootstrapped region {} ({})", region.id, region.name),
+    );
+
+    Ok(RegionBootstrapReport { region, steps })
+}
+
+// Function to get all regions that have been set up
+#[ic_cdk::query]
+fn get_regions() -> Vec<Region> {
+    REGIONS.with(|regions| regions.borrow().iter().map(|(_, region)| region).collect())
+}
+
+// Function to get a region by name
+#[ic_cdk::query]
+fn get_region(name: String) -> Result<Region, FoodShareError> {
+    find_region(name.trim()).ok_or(FoodShareError::NotFound {
+        msg: format!("Region {} not found", name.trim()),
+    })
+}
+
+// Function to find the region whose service zone covers a location
+#[ic_cdk::query]
+fn get_region_for_location(latitude: f64, longitude: f64) -> Result<Region, FoodShareError> {
+    validate_point(latitude, longitude)?;
+    REGIONS
+        .with(|regions| {
+            regions
+                .borrow()
+                .iter()
+                .map(|(_, region)| region)
+                .find(|region| region.serves(latitude, longitude))
+        })
+        .ok_or(FoodShareError::NotFound {
+            msg: "No region serves this location".to_string(),
+        })
+}
@@ -0,0 +1,136 @@
+use crate::browse::{post_summary, PostSummary};
+use crate::indexes::{
+    assignments_for_driver, assignments_for_receiver, posts_for_donor, record_for_assignment,
+};
+use crate::{
+    Assignment, AssignmentStatus, FoodShareError, SurplusPost, SurplusRecord, ASSIGNMENTS_STORAGE,
+    DONORS_STORAGE, DRIVERS_STORAGE, RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+
+// ProfileSummary is the contact-free view of a profile embedded in joined results
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ProfileSummary {
+    id: u64,
+    name: String,
+    active: bool,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AssignmentDetails {
+    assignment: Assignment,
+    // None when the related entity no longer exists
+    post: Option<PostSummary>,
+    donor: Option<ProfileSummary>,
+    receiver: Option<ProfileSummary>,
+    driver: Option<ProfileSummary>,
+    // Set once the receiver has confirmed the delivery
+    record: Option<SurplusRecord>,
+}
+
+// Function to embed the post, profiles and delivery record an assignment refers to
+fn assignment_details(assignment: Assignment) -> AssignmentDetails {
+    let post =
+        SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&assignment.surplus_post_id));
+    let donor = post
+        .as_ref()
+        .and_then(|post| post.donor_id.parse::<u64>().ok())
+        .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)))
+        .map(|donor| ProfileSummary {
+            id: donor.id,
+            name: donor.name,
+            active: donor.active,
+        });
+    let receiver = RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.receiver_id))
+        .map(|receiver| ProfileSummary {
+            id: receiver.id,
+            name: receiver.name,
+            active: receiver.active,
+        });
+    let driver = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.driver_id))
+        .map(|driver| ProfileSummary {
+            id: driver.id,
+            name: driver.name,
+            active: driver.active,
+        });
+    let record = (assignment.status == AssignmentStatus::Delivered)
+        .then(|| record_for_assignment(assignment.id))
+        .flatten();
+
+    AssignmentDetails {
+        post: post.as_ref().map(post_summary),
+        donor,
+        receiver,
+        driver,
+        record,
+        assignment,
+    }
+}
+
+// Function to get every post a donor has made, newest first
+#[ic_cdk::query]
+fn get_donor_posts(donor_id: u64) -> Result<Vec<SurplusPost>, FoodShareError> {
+    if DONORS_STORAGE.with(|storage| !storage.borrow().contains_key(&donor_id)) {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Donor with id={} not found", donor_id),
+        });
+    }
+    let mut posts = posts_for_donor(donor_id);
+    posts.reverse();
+
+    Ok(posts)
+}
+
+// Function to get a driver's assignments, newest first, optionally only those
+// in the given statuses
+#[ic_cdk::query]
+fn get_driver_assignments(
+    driver_id: u64,
+    status_filter: Option<Vec<AssignmentStatus>>,
+) -> Result<Vec<Assignment>, FoodShareError> {
+    if DRIVERS_STORAGE.with(|storage| !storage.borrow().contains_key(&driver_id)) {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Driver with id={} not found", driver_id),
+        });
+    }
+    let mut assignments: Vec<Assignment> = assignments_for_driver(driver_id)
+        .into_iter()
+        .filter(|assignment| {
+            status_filter
+                .as_ref()
+                .is_none_or(|statuses| statuses.contains(&assignment.status))
+        })
+        .collect();
+    assignments.reverse();
+
+    Ok(assignments)
+}
+
+// Function to get every assignment delivering to a receiver, newest first,
+// with the post, the profiles involved and the delivery record
+#[ic_cdk::query]
+fn get_receiver_deliveries(receiver_id: u64) -> Result<Vec<AssignmentDetails>, FoodShareError> {
+    if RECEIVERS_STORAGE.with(|storage| !storage.borrow().contains_key(&receiver_id)) {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Receiver with id={} not found", receiver_id),
+        });
+    }
+    Ok(assignments_for_receiver(receiver_id)
+        .into_iter()
+        .rev()
+        .map(assignment_details)
+        .collect())
+}
+
+// Function to get an assignment together with its post, donor, receiver and
+// driver, so frontends do not have to join them
+#[ic_cdk::query]
+fn get_assignment_details(id: u64) -> Result<AssignmentDetails, FoodShareError> {
+    ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&id))
+        .map(assignment_details)
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Assignment with id={} not found", id),
+        })
+}
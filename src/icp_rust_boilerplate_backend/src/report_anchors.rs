@@ -0,0 +1,212 @@
+use crate::api_tokens::to_hex;
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::certification;
+use crate::governance::require_admin;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{transparency, FoodShareError, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+// Head of an empty chain
+const GENESIS_HASH: [u8; 32] = [0; 32];
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReportAnchor {
+    sequence: u64,
+    // "YYYY-Www" for weekly reports, "YYYY-MM" for monthly reports
+    period: String,
+    report_hash: String,
+    // sha256(previous chain hash || period || report hash), hex encoded
+    chain_hash: String,
+    anchored_by: Principal,
+    anchored_at: u64,
+}
+
+impl Storable for ReportAnchor {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ReportAnchor {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReportVerification {
+    matches: bool,
+    // The anchor recorded for the period, if any
+    anchor: Option<ReportAnchor>,
+    chain_head: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CertifiedChainHead {
+    chain_head: String,
+    length: u64,
+    // Certified data is sha256(chain head || transparency root)
+    transparency_root: String,
+    // Certificate over the certified data; only available in query calls
+    certificate: Option<Vec<u8>>,
+}
+
+thread_local! {
+    static REPORT_ANCHORS: RefCell<StableBTreeMap<u64, ReportAnchor, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)))
+    ));
+}
+
+fn is_valid_period(period: &str) -> bool {
+    let bytes = period.as_bytes();
+    let year_ok = bytes.len() >= 7 && bytes[..4].iter().all(u8::is_ascii_digit) && bytes[4] == b'-';
+    if !year_ok {
+        return false;
+    }
+    match &period[5..] {
+        week if week.starts_with('W') => week[1..]
+            .parse::<u32>()
+            .is_ok_and(|n| week.len() == 3 && (1..=53).contains(&n)),
+        month => month
+            .parse::<u32>()
+            .is_ok_and(|n| month.len() == 2 && (1..=12).contains(&n)),
+    }
+}
+
+fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+pub(crate) fn chain_head() -> (Vec<u8>, u64) {
+    REPORT_ANCHORS.with(|storage| {
+        let storage = storage.borrow();
+        match storage.iter().last() {
+            Some((sequence, anchor)) => (decode_hex(&anchor.chain_hash), sequence + 1),
+            None => (GENESIS_HASH.to_vec(), 0),
+        }
+    })
+}
+
+fn decode_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .filter_map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+// Function for admins to anchor the SHA-256 hash of a generated weekly or monthly report
+#[ic_cdk::update]
+fn anchor_report(period: String, report_hash: String) -> Result<ReportAnchor, FoodShareError> {
+    start_trace("anchor_report");
+    require_admin("anchor reports")?;
+
+    if !is_valid_period(&period) {
+        return Err(FoodShareError::Validation {
+            field: "period".to_string(),
+            reason: "Period must be formatted as YYYY-Www or YYYY-MM".to_string(),
+        });
+    }
+    let report_hash = report_hash.to_lowercase();
+    if !is_valid_hash(&report_hash) {
+        return Err(FoodShareError::Validation {
+            field: "report_hash".to_string(),
+            reason: "Report hash must be a hex encoded SHA-256 digest".to_string(),
+        });
+    }
+    // Anchors are append-only; a corrected report needs a new period label
+    let already_anchored = REPORT_ANCHORS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .any(|(_, anchor)| anchor.period == period)
+    });
+    if already_anchored {
+        return Err(FoodShareError::Conflict {
+            msg: format!("A report is already anchored for {}", period),
+        });
+    }
+
+    let (previous_head, sequence) = chain_head();
+    let mut hasher = Sha256::new();
+    hasher.update(&previous_head);
+    hasher.update(period.as_bytes());
+    hasher.update(report_hash.as_bytes());
+    let chain_hash = to_hex(&hasher.finalize());
+
+    let anchor = ReportAnchor {
+        sequence,
+        period,
+        report_hash,
+        chain_hash,
+        anchored_by: ic_cdk::caller(),
+        anchored_at: time(),
+    };
+    REPORT_ANCHORS.with(|storage| storage.borrow_mut().insert(sequence, anchor.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::ReportAnchor,
+        sequence,
+        None,
+        Some(&anchor),
+    );
+    certification::certify();
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Anchored report for {} as chain entry {}",
+            anchor.period, sequence
+        ),
+    );
+
+    Ok(anchor)
+}
+
+// Function for anyone to check that a report's SHA-256 hash matches the one anchored for its period
+#[ic_cdk::query]
+fn verify_report(period: String, hash: String) -> ReportVerification {
+    let hash = hash.to_lowercase();
+    let anchor = REPORT_ANCHORS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .find(|(_, anchor)| anchor.period == period)
+            .map(|(_, anchor)| anchor)
+    });
+    let (head, _) = chain_head();
+
+    ReportVerification {
+        matches: anchor
+            .as_ref()
+            .is_some_and(|anchor| anchor.report_hash == hash),
+        anchor,
+        chain_head: to_hex(&head),
+    }
+}
+
+// Function to get every anchor in chain order so the chain can be recomputed independently
+#[ic_cdk::query]
+fn get_report_anchors() -> Vec<ReportAnchor> {
+    REPORT_ANCHORS.with(|storage| storage.borrow().iter().map(|(_, anchor)| anchor).collect())
+}
+
+// Function to get the chain head together with the certificate that covers it
+#[ic_cdk::query]
+fn get_certified_chain_head() -> CertifiedChainHead {
+    let (head, length) = chain_head();
+    CertifiedChainHead {
+        chain_head: to_hex(&head),
+        length,
+        transparency_root: to_hex(&transparency::transparency_root()),
+        certificate: ic_cdk::api::data_certificate(),
+    }
+}
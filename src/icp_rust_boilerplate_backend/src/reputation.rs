@@ -0,0 +1,390 @@
+use crate::cancellation_policy::penalized_driver_cancellations;
+use crate::disputes::upheld_disputes;
+use crate::events::EntityType;
+use crate::governance::require_admin;
+use crate::indexes::assignments_with_status;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::pickup_confirmations::pickup_confirmation_rate;
+use crate::post_expiry::expires_at;
+use crate::{
+    Assignment, AssignmentStatus, FoodShareError, Memory, SurplusRecord, DONORS_STORAGE,
+    DRIVERS_STORAGE, MEMORY_MANAGER, SURPLUS_POSTS_STORAGE, SURPLUS_RECORDS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_TOP_DRIVERS: u64 = 50;
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+// Each job a driver has released adds this much to their cooldown, up to the cap
+const JOB_RELEASE_COOLDOWN_HOURS: u64 = 2;
+const MAX_JOB_COOLDOWN_HOURS: u64 = 24;
+
+// Running totals behind a driver's or donor's reputation, updated as
+// deliveries are recorded, rated and cancelled
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct ReputationCounters {
+    deliveries: u64,
+    // Deliveries made before the post's best before date ran out
+    on_time_deliveries: u64,
+    // Assignments that ended cancelled or failed
+    cancellations: u64,
+    ratings: u64,
+    rating_sum: u64,
+    // Disputes a moderator found them at fault in
+    disputes_lost: Option<u64>,
+    // Drivers only: assignments they cancelled after the free cancellation window
+    late_cancellations: Option<u64>,
+    // Drivers only: late cancellations made after the pickup window opened
+    no_shows: Option<u64>,
+}
+
+impl Storable for ReputationCounters {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ReputationCounters {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Marketplace jobs a driver took and then released
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct JobReleases {
+    count: u64,
+    last_released_at: u64,
+}
+
+impl Storable for JobReleases {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for JobReleases {
+    const MAX_SIZE: u32 = 64;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Reputation {
+    id: u64,
+    // Ratings are on the scale in the settings; None until the first rating
+    pub(crate) average_rating: Option<f64>,
+    pub(crate) rating_count: u64,
+    delivery_count: u64,
+    // Share of deliveries made on time, 0-1
+    on_time_rate: Option<f64>,
+    // Share of finished assignments that were cancelled or failed, 0-1
+    cancellation_rate: Option<f64>,
+    disputes_lost: u64,
+    // Donors only: share of pickups the donor confirmed rather than leaving
+    // them to be confirmed automatically, 0-1
+    pickup_confirmation_rate: Option<f64>,
+    // Drivers only: marketplace jobs taken and then released
+    job_releases: Option<u64>,
+    // Drivers only: cancellations the cancellation policy penalized, and the no-shows among them
+    late_cancellations: Option<u64>,
+    no_shows: Option<u64>,
+}
+
+thread_local! {
+    static DRIVER_REPUTATION: RefCell<StableBTreeMap<u64, ReputationCounters, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(52)))
+    ));
+
+    static DONOR_REPUTATION: RefCell<StableBTreeMap<u64, ReputationCounters, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(53)))
+    ));
+
+    // Keyed by driver
+    static DRIVER_JOB_RELEASES: RefCell<StableBTreeMap<u64, JobReleases, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(87)))
+    ));
+}
+
+type ReputationStorage =
+    std::thread::LocalKey<RefCell<StableBTreeMap<u64, ReputationCounters, Memory>>>;
+
+fn update_counters(
+    storage: &'static ReputationStorage,
+    id: u64,
+    update: impl FnOnce(&mut ReputationCounters),
+) {
+    storage.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let mut counters = storage.get(&id).unwrap_or_default();
+        update(&mut counters);
+        storage.insert(id, counters);
+    });
+}
+
+fn post_donor(surplus_post_id: u64) -> Option<u64> {
+    SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&surplus_post_id))
+        .and_then(|post| post.donor_id.parse::<u64>().ok())
+}
+
+fn delivered_on_time(record: &SurplusRecord) -> bool {
+    SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&record.surplus_post_id))
+        .and_then(|post| expires_at(&post))
+        .is_none_or(|expires_at| record.delivered_at <= expires_at)
+}
+
+// Function to count a new delivery towards the driver's and donor's reputation
+pub(crate) fn record_delivery(record: &SurplusRecord) {
+    let on_time = delivered_on_time(record) as u64;
+    let count = |counters: &mut ReputationCounters| {
+        counters.deliveries += 1;
+        counters.on_time_deliveries += on_time;
+    };
+    update_counters(&DRIVER_REPUTATION, record.driver_id, count);
+    if let Some(donor_id) = post_donor(record.surplus_post_id) {
+        update_counters(&DONOR_REPUTATION, donor_id, count);
+    }
+}
+
+// Function to count the rating a receiver gave a delivery
+pub(crate) fn record_rating(record: &SurplusRecord) {
+    let Some(rating) = record.rating else {
+        return;
+    };
+    let count = |counters: &mut ReputationCounters| {
+        counters.ratings += 1;
+        counters.rating_sum += rating as u64;
+    };
+    update_counters(&DRIVER_REPUTATION, record.driver_id, count);
+    if let Some(donor_id) = post_donor(record.surplus_post_id) {
+        update_counters(&DONOR_REPUTATION, donor_id, count);
+    }
+}
+
+// Function to count an assignment that ended cancelled or failed
+pub(crate) fn record_cancellation(assignment: &Assignment) {
+    let count = |counters: &mut ReputationCounters| counters.cancellations += 1;
+    update_counters(&DRIVER_REPUTATION, assignment.driver_id, count);
+    if let Some(donor_id) = post_donor(assignment.surplus_post_id) {
+        update_counters(&DONOR_REPUTATION, donor_id, count);
+    }
+}
+
+// Function to count a cancellation by a driver that the cancellation policy penalized
+pub(crate) fn record_late_cancellation(driver_id: u64, no_show: bool) {
+    update_counters(&DRIVER_REPUTATION, driver_id, |counters| {
+        counters.late_cancellations = Some(counters.late_cancellations.unwrap_or_default() + 1);
+        if no_show {
+            counters.no_shows = Some(counters.no_shows.unwrap_or_default() + 1);
+        }
+    });
+}
+
+// Function to count a marketplace job the driver released, which starts or
+// lengthens their cooldown before they can take another
+pub(crate) fn record_job_release(driver_id: u64, released_at: u64) {
+    DRIVER_JOB_RELEASES.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let mut releases = storage.get(&driver_id).unwrap_or_default();
+        releases.count += 1;
+        releases.last_released_at = released_at;
+        storage.insert(driver_id, releases);
+    });
+}
+
+// Function to get when a driver's cooldown after releasing jobs ends; it
+// grows with every job they have released
+pub(crate) fn job_cooldown_ends_at(driver_id: u64) -> Option<u64> {
+    let releases = DRIVER_JOB_RELEASES.with(|storage| storage.borrow().get(&driver_id))?;
+    let hours = (releases.count * JOB_RELEASE_COOLDOWN_HOURS).min(MAX_JOB_COOLDOWN_HOURS);
+    Some(releases.last_released_at + hours * NANOS_PER_HOUR)
+}
+
+// Function to take back a rating voided by a dispute resolution
+pub(crate) fn void_rating(record: &SurplusRecord) {
+    let Some(rating) = record.rating else {
+        return;
+    };
+    let uncount = |counters: &mut ReputationCounters| {
+        counters.ratings = counters.ratings.saturating_sub(1);
+        counters.rating_sum = counters.rating_sum.saturating_sub(rating as u64);
+    };
+    update_counters(&DRIVER_REPUTATION, record.driver_id, uncount);
+    if let Some(donor_id) = post_donor(record.surplus_post_id) {
+        update_counters(&DONOR_REPUTATION, donor_id, uncount);
+    }
+}
+
+// Function to count a dispute the driver or the donor of an assignment was found at fault in
+pub(crate) fn record_lost_dispute(assignment: &Assignment, at_fault: EntityType) {
+    let count = |counters: &mut ReputationCounters| {
+        counters.disputes_lost = Some(counters.disputes_lost.unwrap_or_default() + 1);
+    };
+    match at_fault {
+        EntityType::Driver => update_counters(&DRIVER_REPUTATION, assignment.driver_id, count),
+        EntityType::Donor => {
+            if let Some(donor_id) = post_donor(assignment.surplus_post_id) {
+                update_counters(&DONOR_REPUTATION, donor_id, count);
+            }
+        }
+        // Receivers have no reputation
+        _ => {}
+    }
+}
+
+fn to_reputation(id: u64, counters: &ReputationCounters) -> Reputation {
+    let ratio = |part: u64, whole: u64| (whole > 0).then(|| part as f64 / whole as f64);
+    Reputation {
+        id,
+        average_rating: ratio(counters.rating_sum, counters.ratings),
+        rating_count: counters.ratings,
+        delivery_count: counters.deliveries,
+        on_time_rate: ratio(counters.on_time_deliveries, counters.deliveries),
+        cancellation_rate: ratio(
+            counters.cancellations,
+            counters.deliveries + counters.cancellations,
+        ),
+        disputes_lost: counters.disputes_lost.unwrap_or_default(),
+        pickup_confirmation_rate: None,
+        job_releases: None,
+        late_cancellations: None,
+        no_shows: None,
+    }
+}
+
+// Function to get a driver's reputation; drivers without history get empty figures
+pub(crate) fn driver_reputation(driver_id: u64) -> Reputation {
+    let counters = DRIVER_REPUTATION
+        .with(|storage| storage.borrow().get(&driver_id))
+        .unwrap_or_default();
+    let job_releases = DRIVER_JOB_RELEASES
+        .with(|storage| storage.borrow().get(&driver_id))
+        .map_or(0, |releases| releases.count);
+    Reputation {
+        job_releases: Some(job_releases),
+        late_cancellations: Some(counters.late_cancellations.unwrap_or_default()),
+        no_shows: Some(counters.no_shows.unwrap_or_default()),
+        ..to_reputation(driver_id, &counters)
+    }
+}
+
+// Function to get the aggregate rating, delivery count, on-time and cancellation rate of a driver
+#[ic_cdk::query]
+fn get_driver_reputation(id: u64) -> Result<Reputation, FoodShareError> {
+    if !DRIVERS_STORAGE.with(|storage| storage.borrow().contains_key(&id)) {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Driver with id={} not found", id),
+        });
+    }
+    Ok(driver_reputation(id))
+}
+
+// Function to get the aggregate figures across deliveries of a donor's posts
+#[ic_cdk::query]
+fn get_donor_reputation(id: u64) -> Result<Reputation, FoodShareError> {
+    if !DONORS_STORAGE.with(|storage| storage.borrow().contains_key(&id)) {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Donor with id={} not found", id),
+        });
+    }
+    let counters = DONOR_REPUTATION
+        .with(|storage| storage.borrow().get(&id))
+        .unwrap_or_default();
+    Ok(Reputation {
+        pickup_confirmation_rate: pickup_confirmation_rate(id),
+        ..to_reputation(id, &counters)
+    })
+}
+
+// Function to get the best rated active drivers with at least one delivery,
+// ties broken by on-time rate and then delivery count
+#[ic_cdk::query]
+fn get_top_drivers(limit: u64) -> Vec<Reputation> {
+    let limit = limit.clamp(1, MAX_TOP_DRIVERS) as usize;
+    let mut reputations: Vec<Reputation> = DRIVER_REPUTATION.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, counters)| counters.deliveries > 0)
+            .filter(|(id, _)| {
+                DRIVERS_STORAGE
+                    .with(|drivers| drivers.borrow().get(id))
+                    .is_some_and(|driver| driver.active)
+            })
+            .map(|(id, counters)| to_reputation(id, &counters))
+            .collect()
+    });
+
+    let key = |value: Option<f64>| value.unwrap_or(-1.0);
+    reputations.sort_by(|a, b| {
+        key(b.average_rating)
+            .total_cmp(&key(a.average_rating))
+            .then(key(b.on_time_rate).total_cmp(&key(a.on_time_rate)))
+            .then(b.delivery_count.cmp(&a.delivery_count))
+            .then(a.id.cmp(&b.id))
+    });
+    reputations.truncate(limit);
+    reputations
+}
+
+// Function for admins to recount every reputation from the stored deliveries
+// and assignments, e.g. for history recorded before reputation was tracked
+#[ic_cdk::update]
+fn rebuild_reputation() -> Result<u64, FoodShareError> {
+    start_trace("rebuild_reputation");
+    require_admin("rebuild reputation")?;
+
+    for storage in [&DRIVER_REPUTATION, &DONOR_REPUTATION] {
+        storage.with(|storage| {
+            let mut storage = storage.borrow_mut();
+            let ids: Vec<u64> = storage.iter().map(|(id, _)| id).collect();
+            for id in ids {
+                storage.remove(&id);
+            }
+        });
+    }
+
+    let records: Vec<SurplusRecord> = SURPLUS_RECORDS_STORAGE
+        .with(|storage| storage.borrow().iter().map(|(_, record)| record).collect());
+    for record in &records {
+        record_delivery(record);
+        record_rating(record);
+    }
+    let cancelled: Vec<Assignment> = [AssignmentStatus::Cancelled, AssignmentStatus::Failed]
+        .into_iter()
+        .flat_map(assignments_with_status)
+        .collect();
+    for assignment in &cancelled {
+        record_cancellation(assignment);
+    }
+    for (assignment, at_fault) in upheld_disputes() {
+        record_lost_dispute(&assignment, at_fault);
+    }
+    for (driver_id, no_show) in penalized_driver_cancellations() {
+        record_late_cancellation(driver_id, no_show);
+    }
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Rebuilt reputation from {} deliveries and {} cancellations",
+            records.len(),
+            cancelled.len()
+        ),
+    );
+
+    Ok(records.len() as u64)
+}
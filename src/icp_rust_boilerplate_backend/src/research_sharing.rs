@@ -0,0 +1,387 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::require_admin;
+use crate::impersonation::find_profile;
+use crate::indexes::assignments_for_post;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    next_id, AssignmentStatus, FoodShareError, FoodType, Memory, DONORS_STORAGE, MEMORY_MANAGER,
+    SURPLUS_POSTS_STORAGE, SURPLUS_RECORDS_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Bumped whenever the research sharing terms change; consents given under
+// older terms no longer count until the user accepts the new ones
+const RESEARCH_TERMS_VERSION: u32 = 1;
+const WEEK_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+const MIN_K_THRESHOLD: u32 = 5;
+const MAX_PARTNER_LEN: usize = 100;
+const UNSPECIFIED_REGION: &str = "unspecified";
+
+// ResearchScope is a custom enum type that is used to represent what a profile agrees to share with research partners
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ResearchScope {
+    // Quantities, food types, coarse regions and weeks of deliveries
+    DeliveryHistory,
+    // Ratings received as a driver
+    Ratings,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ResearchConsent {
+    profile_id: u64,
+    scopes: Vec<ResearchScope>,
+    terms_version: u32,
+    granted_at: u64,
+}
+
+impl ResearchConsent {
+    fn covers(&self, scope: ResearchScope) -> bool {
+        self.terms_version == RESEARCH_TERMS_VERSION && self.scopes.contains(&scope)
+    }
+}
+
+impl Storable for ResearchConsent {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ResearchConsent {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// One anonymized row: deliveries grouped by donor region, food type and week
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ExtractGroup {
+    region: String,
+    food_type: FoodType,
+    week_start: u64,
+    deliveries: u64,
+    total_kg: u64,
+    // Only from drivers who consented to share ratings; None when fewer than k ratings
+    average_rating: Option<f64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ResearchExtract {
+    extract_id: u64,
+    terms_version: u32,
+    k_threshold: u32,
+    groups: Vec<ExtractGroup>,
+    // Consented deliveries left out because their group had fewer than k deliveries
+    suppressed_deliveries: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ExtractLogEntry {
+    id: u64,
+    partner: String,
+    produced_by: Principal,
+    produced_at: u64,
+    terms_version: u32,
+    k_threshold: u32,
+    groups: u64,
+    included_deliveries: u64,
+    suppressed_deliveries: u64,
+}
+
+impl Storable for ExtractLogEntry {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ExtractLogEntry {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static RESEARCH_CONSENTS: RefCell<StableBTreeMap<u64, ResearchConsent, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26)))
+    ));
+
+    static EXTRACT_LOG: RefCell<StableBTreeMap<u64, ExtractLogEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27)))
+    ));
+}
+
+fn consents_to(profile_id: u64, scope: ResearchScope) -> bool {
+    RESEARCH_CONSENTS
+        .with(|storage| storage.borrow().get(&profile_id))
+        .is_some_and(|consent| consent.covers(scope))
+}
+
+// Function for a user to choose what their profile shares with research partners
+// under the current terms; an empty list withdraws consent
+#[ic_cdk::update]
+fn set_research_consent(
+    profile_id: u64,
+    scopes: Vec<ResearchScope>,
+) -> Result<Option<ResearchConsent>, FoodShareError> {
+    start_trace("set_research_consent");
+
+    let profile = find_profile(profile_id).ok_or(FoodShareError::NotFound {
+        msg: format!("Profile with id={} not found", profile_id),
+    })?;
+    if profile.owner() != Some(ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the profile owner can manage research consent".to_string(),
+        });
+    }
+
+    if scopes.is_empty() {
+        let before = RESEARCH_CONSENTS.with(|storage| storage.borrow_mut().remove(&profile_id));
+        audit(
+            AuditAction::Revoked,
+            AuditEntity::ResearchConsent,
+            profile_id,
+            before.as_ref(),
+            None,
+        );
+        log(
+            LogLevel::Info,
+            format!("Profile {} withdrew research consent", profile_id),
+        );
+        return Ok(None);
+    }
+
+    let mut unique_scopes = Vec::with_capacity(scopes.len());
+    for scope in scopes {
+        if !unique_scopes.contains(&scope) {
+            unique_scopes.push(scope);
+        }
+    }
+    let consent = ResearchConsent {
+        profile_id,
+        scopes: unique_scopes,
+        terms_version: RESEARCH_TERMS_VERSION,
+        granted_at: time(),
+    };
+    let before =
+        RESEARCH_CONSENTS.with(|storage| storage.borrow_mut().insert(profile_id, consent.clone()));
+    audit(
+        if before.is_some() {
+            AuditAction::Updated
+        } else {
+            AuditAction::Created
+        },
+        AuditEntity::ResearchConsent,
+        profile_id,
+        before.as_ref(),
+        Some(&consent),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Profile {} consented to research scopes {:?}",
+            profile_id, consent.scopes
+        ),
+    );
+
+    Ok(Some(consent))
+}
+
+// Function to get the research consent of a profile
+#[ic_cdk::query]
+fn get_research_consent(profile_id: u64) -> Result<ResearchConsent, FoodShareError> {
+    RESEARCH_CONSENTS
+        .with(|storage| storage.borrow().get(&profile_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("No research consent for profile with id={}", profile_id),
+        })
+}
+
+// Function to get the current version of the research sharing terms
+#[ic_cdk::query]
+fn get_research_terms_version() -> u32 {
+    RESEARCH_TERMS_VERSION
+}
+
+// Function for admins to produce an anonymized delivery extract for a research partner.
+// A delivery is only used when its donor, receiver and driver all consented to
+// share delivery history, locations are reduced to the donor's region, and any
+// group smaller than k is suppressed.
+#[ic_cdk::update]
+fn produce_research_extract(
+    partner: String,
+    k_threshold: Option<u32>,
+) -> Result<ResearchExtract, FoodShareError> {
+    start_trace("produce_research_extract");
+    require_admin("produce research extracts")?;
+
+    if partner.trim().is_empty() || partner.len() > MAX_PARTNER_LEN {
+        return Err(FoodShareError::Validation {
+            field: "partner".to_string(),
+            reason: format!("Partner must be between 1 and {} bytes", MAX_PARTNER_LEN),
+        });
+    }
+    let k_threshold = k_threshold.unwrap_or(MIN_K_THRESHOLD);
+    if k_threshold < MIN_K_THRESHOLD {
+        return Err(FoodShareError::Validation {
+            field: "k_threshold".to_string(),
+            reason: format!("k threshold must be at least {}", MIN_K_THRESHOLD),
+        });
+    }
+
+    struct Acc {
+        region: String,
+        food_type: FoodType,
+        week_start: u64,
+        deliveries: u64,
+        total_kg: u64,
+        ratings: u64,
+        rating_sum: u64,
+    }
+    let mut accs: Vec<Acc> = Vec::new();
+
+    let records: Vec<_> = SURPLUS_RECORDS_STORAGE
+        .with(|storage| storage.borrow().iter().map(|(_, record)| record).collect());
+    for record in records {
+        let Some(post) =
+            SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&record.surplus_post_id))
+        else {
+            continue;
+        };
+        let Ok(donor_id) = post.donor_id.parse::<u64>() else {
+            continue;
+        };
+        let receiver_id = assignments_for_post(record.surplus_post_id)
+            .into_iter()
+            .find(|assignment| {
+                assignment.driver_id == record.driver_id
+                    && assignment.status == AssignmentStatus::Delivered
+            })
+            .map(|assignment| assignment.receiver_id);
+        let Some(receiver_id) = receiver_id else {
+            continue;
+        };
+        let all_consented = [donor_id, receiver_id, record.driver_id]
+            .iter()
+            .all(|profile_id| consents_to(*profile_id, ResearchScope::DeliveryHistory));
+        if !all_consented {
+            continue;
+        }
+
+        let region = DONORS_STORAGE
+            .with(|storage| storage.borrow().get(&donor_id))
+            .map(|donor| donor.region)
+            .filter(|region| !region.is_empty())
+            .unwrap_or_else(|| UNSPECIFIED_REGION.to_string());
+        let week_start = record.delivered_at - record.delivered_at % WEEK_NANOS;
+
+        let index = match accs.iter().position(|acc| {
+            acc.region == region && acc.food_type == post.food_type && acc.week_start == week_start
+        }) {
+            Some(index) => index,
+            None => {
+                accs.push(Acc {
+                    region,
+                    food_type: post.food_type,
+                    week_start,
+                    deliveries: 0,
+                    total_kg: 0,
+                    ratings: 0,
+                    rating_sum: 0,
+                });
+                accs.len() - 1
+            }
+        };
+        let acc = &mut accs[index];
+        acc.deliveries += 1;
+        acc.total_kg += post.quantity_kg as u64;
+        if let Some(rating) = record
+            .rating
+            .filter(|_| consents_to(record.driver_id, ResearchScope::Ratings))
+        {
+            acc.ratings += 1;
+            acc.rating_sum += rating as u64;
+        }
+    }
+
+    let k = k_threshold as u64;
+    let mut suppressed_deliveries = 0;
+    let mut included_deliveries = 0;
+    let mut groups = Vec::new();
+    for acc in accs {
+        if acc.deliveries < k {
+            suppressed_deliveries += acc.deliveries;
+            continue;
+        }
+        included_deliveries += acc.deliveries;
+        groups.push(ExtractGroup {
+            region: acc.region,
+            food_type: acc.food_type,
+            week_start: acc.week_start,
+            deliveries: acc.deliveries,
+            total_kg: acc.total_kg,
+            average_rating: (acc.ratings >= k).then(|| acc.rating_sum as f64 / acc.ratings as f64),
+        });
+    }
+    groups.sort_by(|a, b| {
+        a.week_start
+            .cmp(&b.week_start)
+            .then_with(|| a.region.cmp(&b.region))
+    });
+
+    let extract_id = next_id();
+    let entry = ExtractLogEntry {
+        id: extract_id,
+        partner,
+        produced_by: ic_cdk::caller(),
+        produced_at: time(),
+        terms_version: RESEARCH_TERMS_VERSION,
+        k_threshold,
+        groups: groups.len() as u64,
+        included_deliveries,
+        suppressed_deliveries,
+    };
+    EXTRACT_LOG.with(|storage| storage.borrow_mut().insert(extract_id, entry.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::ResearchExtract,
+        extract_id,
+        None,
+        Some(&entry),
+    );
+
+    log(
+        LogLevel::Warn,
+        format!(
+            "Research extract {} produced for {} with {} deliveries",
+            extract_id, entry.partner, included_deliveries
+        ),
+    );
+
+    Ok(ResearchExtract {
+        extract_id,
+        terms_version: RESEARCH_TERMS_VERSION,
+        k_threshold,
+        groups,
+        suppressed_deliveries,
+    })
+}
+
+// Function for admins to get the log of every research extract produced
+#[ic_cdk::query]
+fn get_research_extract_log() -> Result<Vec<ExtractLogEntry>, FoodShareError> {
+    require_admin("view the research extract log")?;
+
+    Ok(EXTRACT_LOG.with(|storage| storage.borrow().iter().map(|(_, entry)| entry).collect()))
+}
@@ -0,0 +1,432 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::is_admin;
+use crate::indexes::{assignments_with_status, store_assignment, store_post};
+use crate::notifications::{notify_assignment, NotificationKind};
+use crate::observability::{log, start_timer_trace, start_trace, LogLevel};
+use crate::{
+    browse, split_donations, Assignment, AssignmentStatus, FoodShareError, Memory, DONORS_STORAGE,
+    MEMORY_MANAGER, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const HOLD_SWEEP_INTERVAL: Duration = Duration::from_secs(300);
+const TUNING_STEP_PERCENT: u64 = 10;
+const MIN_TUNING_SAMPLES: u64 = 20;
+const UNSPECIFIED_REGION: &str = "unspecified";
+
+// ReservationOutcome is a custom enum type that is used to represent how a hold on a surplus post ended
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ReservationOutcome {
+    Converted,
+    Expired,
+    Cancelled,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct RegionFunnel {
+    region: String,
+    reserved: u64,
+    converted: u64,
+    expired: u64,
+    cancelled: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct ReservationStats {
+    regions: Vec<RegionFunnel>,
+    // Outcomes observed since the hold timeout was last tuned
+    resolved_since_tuning: u64,
+    expired_since_tuning: u64,
+}
+
+impl ReservationStats {
+    fn region_mut(&mut self, region: String) -> &mut RegionFunnel {
+        let index = match self.regions.iter().position(|f| f.region == region) {
+            Some(index) => index,
+            None => {
+                self.regions.push(RegionFunnel {
+                    region,
+                    ..Default::default()
+                });
+                self.regions.len() - 1
+            }
+        };
+        &mut self.regions[index]
+    }
+}
+
+impl Storable for ReservationStats {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct HoldPolicy {
+    hold_timeout_secs: u64,
+    min_hold_secs: u64,
+    max_hold_secs: u64,
+    auto_tune: bool,
+    target_expiry_rate_percent: u64,
+}
+
+impl Default for HoldPolicy {
+    fn default() -> Self {
+        Self {
+            hold_timeout_secs: 4 * 60 * 60,
+            min_hold_secs: 60 * 60,
+            max_hold_secs: 24 * 60 * 60,
+            auto_tune: false,
+            target_expiry_rate_percent: 20,
+        }
+    }
+}
+
+impl Storable for HoldPolicy {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Running totals of the hold sweep
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct HoldSweepMetrics {
+    sweeps: u64,
+    timed_out: u64,
+    last_sweep_at: Option<u64>,
+}
+
+impl Storable for HoldSweepMetrics {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RegionFunnelReport {
+    region: String,
+    reserved: u64,
+    converted: u64,
+    expired: u64,
+    cancelled: u64,
+    conversion_rate_percent: f64,
+    expiry_rate_percent: f64,
+}
+
+thread_local! {
+    static RESERVATION_STATS: RefCell<Cell<ReservationStats, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))),
+            ReservationStats::default(),
+        )
+        .expect("Cannot create the reservation stats")
+    );
+
+    static HOLD_POLICY: RefCell<Cell<HoldPolicy, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16))),
+            HoldPolicy::default(),
+        )
+        .expect("Cannot create the hold policy")
+    );
+
+    static HOLD_SWEEP_METRICS: RefCell<Cell<HoldSweepMetrics, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(104))),
+            HoldSweepMetrics::default(),
+        )
+        .expect("Cannot create the hold sweep metrics")
+    );
+}
+
+fn hold_policy() -> HoldPolicy {
+    HOLD_POLICY.with(|cell| cell.borrow().get().clone())
+}
+
+fn update_stats(update: impl FnOnce(&mut ReservationStats)) {
+    RESERVATION_STATS.with(|cell| {
+        let mut stats = cell.borrow().get().clone();
+        update(&mut stats);
+        cell.borrow_mut()
+            .set(stats)
+            .expect("Cannot update the reservation stats");
+    });
+}
+
+// Function to find the region of the donor whose post an assignment holds
+fn region_of(assignment: &Assignment) -> String {
+    SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+        .and_then(|post| post.donor_id.parse::<u64>().ok())
+        .and_then(|donor_id| DONORS_STORAGE.with(|storage| storage.borrow().get(&donor_id)))
+        .map(|donor| donor.region)
+        .filter(|region| !region.is_empty())
+        .unwrap_or_else(|| UNSPECIFIED_REGION.to_string())
+}
+
+// Function to count a new hold placed on a surplus post by an assignment
+pub(crate) fn record_reservation(assignment: &Assignment) {
+    let region = region_of(assignment);
+    update_stats(|stats| stats.region_mut(region).reserved += 1);
+}
+
+// Function to count how a hold on a surplus post ended
+pub(crate) fn record_reservation_outcome(assignment: &Assignment, outcome: ReservationOutcome) {
+    let region = region_of(assignment);
+    update_stats(|stats| {
+        let funnel = stats.region_mut(region);
+        match outcome {
+            ReservationOutcome::Converted => funnel.converted += 1,
+            ReservationOutcome::Expired => funnel.expired += 1,
+            ReservationOutcome::Cancelled => funnel.cancelled += 1,
+        }
+        stats.resolved_since_tuning += 1;
+        if outcome == ReservationOutcome::Expired {
+            stats.expired_since_tuning += 1;
+        }
+    });
+}
+
+// Function to return a held surplus post to the open listing
+pub(crate) fn release_post(assignment: &Assignment) {
+    // A reservation's share goes back to the post without reopening the rest of it
+    if split_donations::release_for_assignment(assignment.surplus_post_id, assignment.id) {
+        return;
+    }
+    let Some(before) =
+        SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+    else {
+        return;
+    };
+    if before.withdrawn_at.is_some() || before.expired_at.is_some() {
+        return;
+    }
+    let mut post = before.clone();
+    post.assigned = false;
+    store_post(&post);
+    browse::index_open_post(&post);
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::SurplusPost,
+        post.id,
+        Some(&before),
+        Some(&post),
+    );
+}
+
+// Function to register the periodic sweep that releases expired holds
+pub(crate) fn start_hold_sweep() {
+    ic_cdk_timers::set_timer_interval(HOLD_SWEEP_INTERVAL, sweep_expired_holds);
+}
+
+// Function to time out pending assignments the driver did not accept within
+// the hold timeout, return their surplus posts to the open listing and let
+// the donor, receiver and driver know
+fn sweep_expired_holds() {
+    start_timer_trace("sweep_expired_holds");
+
+    let policy = hold_policy();
+    let now = time();
+    let cutoff = now.saturating_sub(policy.hold_timeout_secs * NANOS_PER_SECOND);
+
+    let expired: Vec<Assignment> = assignments_with_status(AssignmentStatus::Pending)
+        .into_iter()
+        // Onward hub legs are not holds on an open post
+        .filter(|assignment| assignment.previous_leg_id.is_none() && assignment.created_at < cutoff)
+        .collect();
+
+    let mut timed_out = 0;
+    for mut assignment in expired {
+        let before = assignment.clone();
+        if assignment.transition(AssignmentStatus::TimedOut).is_err() {
+            continue;
+        }
+        store_assignment(&assignment);
+        audit(
+            AuditAction::StatusChanged,
+            AuditEntity::Assignment,
+            assignment.id,
+            Some(&before),
+            Some(&assignment),
+        );
+
+        release_post(&assignment);
+
+        record_event(
+            EntityType::Assignment,
+            assignment.id,
+            Some(assignment.id),
+            EventKind::Expired,
+            "Driver did not accept in time, post returned to the open listing".to_string(),
+        );
+        notify_assignment(
+            &assignment,
+            NotificationKind::AssignmentTimedOut,
+            "The driver did not accept in time; the post is open again",
+        );
+        record_reservation_outcome(&assignment, ReservationOutcome::Expired);
+        timed_out += 1;
+        log(
+            LogLevel::Info,
+            format!("Timed out hold of assignment {}", assignment.id),
+        );
+    }
+
+    HOLD_SWEEP_METRICS.with(|cell| {
+        let mut metrics = cell.borrow().get().clone();
+        metrics.sweeps += 1;
+        metrics.timed_out += timed_out;
+        metrics.last_sweep_at = Some(now);
+        cell.borrow_mut()
+            .set(metrics)
+            .expect("Cannot update the hold sweep metrics");
+    });
+
+    if policy.auto_tune {
+        tune_hold_timeout(policy);
+    }
+}
+
+// Function to nudge the hold timeout towards the target expiry rate: frequent
+// expiries mean holders need longer, rare expiries mean holds can be shortened
+fn tune_hold_timeout(mut policy: HoldPolicy) {
+    let (resolved, expired) = RESERVATION_STATS.with(|cell| {
+        let stats = cell.borrow();
+        let stats = stats.get();
+        (stats.resolved_since_tuning, stats.expired_since_tuning)
+    });
+    if resolved < MIN_TUNING_SAMPLES {
+        return;
+    }
+
+    let expiry_rate_percent = expired * 100 / resolved;
+    let step = policy.hold_timeout_secs * TUNING_STEP_PERCENT / 100;
+    let tuned = if expiry_rate_percent > policy.target_expiry_rate_percent {
+        policy.hold_timeout_secs + step
+    } else if expiry_rate_percent < policy.target_expiry_rate_percent / 2 {
+        policy.hold_timeout_secs.saturating_sub(step)
+    } else {
+        policy.hold_timeout_secs
+    }
+    .clamp(policy.min_hold_secs, policy.max_hold_secs);
+
+    update_stats(|stats| {
+        stats.resolved_since_tuning = 0;
+        stats.expired_since_tuning = 0;
+    });
+
+    if tuned != policy.hold_timeout_secs {
+        log(
+            LogLevel::Info,
+            format!(
+                "Tuned hold timeout from {}s to {}s at {}% expiry rate",
+                policy.hold_timeout_secs, tuned, expiry_rate_percent
+            ),
+        );
+        policy.hold_timeout_secs = tuned;
+        HOLD_POLICY
+            .with(|cell| cell.borrow_mut().set(policy))
+            .expect("Cannot update the hold policy");
+    }
+}
+
+fn rate_percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+// Function to get the reservation funnel per region
+#[ic_cdk::query]
+fn get_reservation_funnel() -> Vec<RegionFunnelReport> {
+    RESERVATION_STATS.with(|cell| {
+        cell.borrow()
+            .get()
+            .regions
+            .iter()
+            .map(|funnel| {
+                let resolved = funnel.converted + funnel.expired + funnel.cancelled;
+                RegionFunnelReport {
+                    region: funnel.region.clone(),
+                    reserved: funnel.reserved,
+                    converted: funnel.converted,
+                    expired: funnel.expired,
+                    cancelled: funnel.cancelled,
+                    conversion_rate_percent: rate_percent(funnel.converted, resolved),
+                    expiry_rate_percent: rate_percent(funnel.expired, resolved),
+                }
+            })
+            .collect()
+    })
+}
+
+// Function to get how often the hold sweep ran and how many assignments it timed out
+#[ic_cdk::query]
+fn get_hold_sweep_metrics() -> HoldSweepMetrics {
+    HOLD_SWEEP_METRICS.with(|cell| cell.borrow().get().clone())
+}
+
+// Function to get the hold policy
+#[ic_cdk::query]
+fn get_hold_policy() -> HoldPolicy {
+    hold_policy()
+}
+
+// Function to update the hold policy
+#[ic_cdk::update]
+fn set_hold_policy(policy: HoldPolicy) -> Result<HoldPolicy, FoodShareError> {
+    start_trace("set_hold_policy");
+
+    if !is_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only admins can update the hold policy".to_string(),
+        });
+    }
+
+    if policy.min_hold_secs == 0
+        || policy.min_hold_secs > policy.max_hold_secs
+        || !(policy.min_hold_secs..=policy.max_hold_secs).contains(&policy.hold_timeout_secs)
+        || policy.target_expiry_rate_percent > 100
+    {
+        return Err(FoodShareError::Validation {
+ field: "policy".to_string(),
+ reason: "Hold timeout must lie within non-zero min/max bounds and the target rate within 0-100".to_string(),
+ });
+    }
+
+    let before = HOLD_POLICY
+        .with(|cell| cell.borrow_mut().set(policy.clone()))
+        .expect("Cannot update the hold policy");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::HoldPolicy,
+        0,
+        Some(&before),
+        Some(&policy),
+    );
+
+    log(LogLevel::Info, "Updated hold policy".to_string());
+
+    Ok(policy)
+}
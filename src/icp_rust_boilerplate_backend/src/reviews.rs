@@ -0,0 +1,179 @@
+use crate::assignment_lifecycle::{is_driver_owner, is_receiver_owner};
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::EntityType;
+use crate::governance::{authorize, EndpointClass};
+use crate::indexes::{reviews_for_assignment, store_review};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::settings::validate_rating;
+use crate::{
+    next_id, AssignmentStatus, FoodShareError, Memory, ASSIGNMENTS_STORAGE, MEMORY_MANAGER,
+    SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_COMMENT_LEN: usize = 500;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Review {
+    pub(crate) id: u64,
+    pub(crate) assignment_id: u64,
+    // Receiver or Driver
+    reviewer_type: EntityType,
+    reviewer_id: u64,
+    // Driver, Donor or Receiver
+    subject_type: EntityType,
+    subject_id: u64,
+    // On the rating scale in the settings
+    rating: u8,
+    comment: String,
+    pub(crate) created_at: u64,
+}
+
+impl Storable for Review {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Review {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Review Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct ReviewPayload {
+    assignment_id: u64,
+    // Receivers review the Driver or the Donor; drivers review the Donor
+    // (pickup) or the Receiver (dropoff)
+    subject_type: EntityType,
+    rating: u8,
+    comment: String,
+}
+
+thread_local! {
+    pub(crate) static REVIEWS_STORAGE: RefCell<StableBTreeMap<u64, Review, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(56)))
+    ));
+}
+
+// Function for the receiver or driver of a delivered assignment to review the
+// other parties. Each party reviews each other party once per assignment.
+#[ic_cdk::update]
+async fn submit_review(payload: ReviewPayload) -> Result<Review, FoodShareError> {
+    start_trace("submit_review");
+    authorize(EndpointClass::Logistics).await?;
+
+    let assignment = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&payload.assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })?;
+    if assignment.status != AssignmentStatus::Delivered {
+        return Err(FoodShareError::Conflict {
+            msg: "Only delivered assignments can be reviewed".to_string(),
+        });
+    }
+
+    validate_rating(payload.rating)?;
+    if payload.comment.len() > MAX_COMMENT_LEN {
+        return Err(FoodShareError::Validation {
+            field: "comment".to_string(),
+            reason: format!("Comment must be at most {} bytes", MAX_COMMENT_LEN),
+        });
+    }
+
+    let donor_id = || {
+        SURPLUS_POSTS_STORAGE
+            .with(|storage| storage.borrow().get(&assignment.surplus_post_id))
+            .and_then(|post| post.donor_id.parse::<u64>().ok())
+            .ok_or(FoodShareError::NotFound {
+                msg: "Donor of the surplus post not found".to_string(),
+            })
+    };
+    let receiver = (EntityType::Receiver, assignment.receiver_id);
+    let driver = (EntityType::Driver, assignment.driver_id);
+    let ((reviewer_type, reviewer_id), subject_id) = match payload.subject_type {
+        EntityType::Driver if is_receiver_owner(&assignment) => (receiver, assignment.driver_id),
+        EntityType::Receiver if is_driver_owner(&assignment) => (driver, assignment.receiver_id),
+        EntityType::Donor if is_receiver_owner(&assignment) => (receiver, donor_id()?),
+        EntityType::Donor if is_driver_owner(&assignment) => (driver, donor_id()?),
+        EntityType::Driver | EntityType::Receiver | EntityType::Donor => {
+            return Err(FoodShareError::Unauthorized {
+                msg: "Only the other parties of this assignment can leave this review".to_string(),
+            })
+        }
+        _ => {
+            return Err(FoodShareError::Validation {
+                field: "subject_type".to_string(),
+                reason: "Only drivers, donors and receivers can be reviewed".to_string(),
+            })
+        }
+    };
+
+    let already_reviewed = reviews_for_assignment(assignment.id).iter().any(|review| {
+        review.reviewer_type == reviewer_type && review.subject_type == payload.subject_type
+    });
+    if already_reviewed {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "A {:?} review of the {:?} already exists for this assignment",
+                reviewer_type, payload.subject_type
+            ),
+        });
+    }
+
+    let id = next_id();
+    let review = Review {
+        id,
+        assignment_id: assignment.id,
+        reviewer_type,
+        reviewer_id,
+        subject_type: payload.subject_type,
+        subject_id,
+        rating: payload.rating,
+        comment: payload.comment.trim().to_string(),
+        created_at: time(),
+    };
+
+    store_review(&review);
+    audit(
+        AuditAction::Created,
+        AuditEntity::Review,
+        id,
+        None,
+        Some(&review),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "{:?} {} reviewed {:?} {} for assignment {}",
+            reviewer_type, reviewer_id, review.subject_type, subject_id, assignment.id
+        ),
+    );
+
+    Ok(review)
+}
+
+// Function to get every review left about a driver, donor or receiver, oldest first
+#[ic_cdk::query]
+fn get_reviews_for(entity_type: EntityType, id: u64) -> Vec<Review> {
+    REVIEWS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, review)| review)
+            .filter(|review| review.subject_type == entity_type && review.subject_id == id)
+            .collect()
+    })
+}
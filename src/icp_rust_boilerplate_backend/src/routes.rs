@@ -0,0 +1,368 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::geo::haversine_km;
+use crate::governance::{authorize, is_governance_or_admin, EndpointClass};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    next_id, Assignment, AssignmentStatus, FoodShareError, Memory, ASSIGNMENTS_STORAGE,
+    DRIVERS_STORAGE, MEMORY_MANAGER, RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MIN_ROUTE_ASSIGNMENTS: usize = 2;
+const MAX_ROUTE_ASSIGNMENTS: usize = 10;
+
+// StopKind is a custom enum type that is used to represent what the driver does at a route stop
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum StopKind {
+    Pickup,
+    Dropoff,
+}
+
+// StopStatus is a custom enum type that is used to represent how far a route stop has got
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum StopStatus {
+    Pending,
+    Completed,
+    // The assignment ended before the stop was reached
+    Skipped,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct RouteStop {
+    assignment_id: u64,
+    kind: StopKind,
+    // The post's pickup location or the receiver's location; None when not stored
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+impl RouteStop {
+    fn point(&self) -> Option<(f64, f64)> {
+        Some((self.latitude?, self.longitude?))
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Route {
+    id: u64,
+    driver_id: u64,
+    // In the order the driver should visit them
+    stops: Vec<RouteStop>,
+    // Straight-line length of the route between stops with coordinates
+    distance_km: f64,
+    created_at: u64,
+}
+
+impl Storable for Route {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Route {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static ROUTES: RefCell<StableBTreeMap<u64, Route, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(91)))
+    ));
+}
+
+#[derive(candid::CandidType, Clone, Serialize)]
+pub(crate) struct RouteStopView {
+    assignment_id: u64,
+    kind: StopKind,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    status: StopStatus,
+    completed_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize)]
+pub(crate) struct RouteView {
+    id: u64,
+    driver_id: u64,
+    stops: Vec<RouteStopView>,
+    distance_km: f64,
+    created_at: u64,
+}
+
+// Function to order stops greedily, always moving to the nearest stop that can
+// be visited next; a drop-off only becomes eligible once its pickup is on the
+// route. Stops without coordinates keep their relative order and are visited
+// when nothing with coordinates is eligible.
+fn order_stops(start: Option<(f64, f64)>, mut remaining: Vec<RouteStop>) -> (Vec<RouteStop>, f64) {
+    let mut ordered: Vec<RouteStop> = Vec::with_capacity(remaining.len());
+    let mut position = start;
+    let mut distance_km = 0.0;
+
+    while !remaining.is_empty() {
+        let eligible = |stop: &RouteStop| {
+            stop.kind == StopKind::Pickup
+                || ordered.iter().any(|placed| {
+                    placed.kind == StopKind::Pickup && placed.assignment_id == stop.assignment_id
+                })
+        };
+        let nearest = remaining
+            .iter()
+            .enumerate()
+            .filter(|(_, stop)| eligible(stop))
+            .filter_map(|(index, stop)| {
+                let (lat, lng) = stop.point()?;
+                let distance = position.map_or(0.0, |(from_lat, from_lng)| {
+                    haversine_km(from_lat, from_lng, lat, lng)
+                });
+                Some((index, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        let index = match nearest {
+            Some((index, distance)) => {
+                distance_km += distance;
+                index
+            }
+            None => remaining
+                .iter()
+                .position(eligible)
+                .expect("A pickup is always eligible before its drop-off"),
+        };
+
+        let stop = remaining.remove(index);
+        if let Some(point) = stop.point() {
+            position = Some(point);
+        }
+        ordered.push(stop);
+    }
+
+    (ordered, distance_km)
+}
+
+// Function to work out a stop's status from the assignment behind it
+fn stop_view(stop: &RouteStop, assignment: Option<&Assignment>) -> RouteStopView {
+    let reached = match stop.kind {
+        StopKind::Pickup => AssignmentStatus::PickedUp,
+        StopKind::Dropoff => AssignmentStatus::AwaitingConfirmation,
+    };
+    let completed_at = assignment.and_then(|assignment| {
+        assignment
+            .status_history
+            .iter()
+            .find(|change| change.status == reached)
+            .map(|change| change.changed_at)
+    });
+    let ended = assignment.is_none_or(|assignment| {
+        matches!(
+            assignment.status,
+            AssignmentStatus::Cancelled
+                | AssignmentStatus::Expired
+                | AssignmentStatus::TimedOut
+                | AssignmentStatus::Failed
+        )
+    });
+    let status = match (completed_at, ended) {
+        (Some(_), _) => StopStatus::Completed,
+        (None, true) => StopStatus::Skipped,
+        (None, false) => StopStatus::Pending,
+    };
+
+    RouteStopView {
+        assignment_id: stop.assignment_id,
+        kind: stop.kind,
+        latitude: stop.latitude,
+        longitude: stop.longitude,
+        status,
+        completed_at,
+    }
+}
+
+fn route_view(route: &Route) -> RouteView {
+    let stops = ASSIGNMENTS_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        route
+            .stops
+            .iter()
+            .map(|stop| stop_view(stop, storage.get(&stop.assignment_id).as_ref()))
+            .collect()
+    });
+    RouteView {
+        id: route.id,
+        driver_id: route.driver_id,
+        stops,
+        distance_km: route.distance_km,
+        created_at: route.created_at,
+    }
+}
+
+// Function to get the driver's route that still has stops to visit
+fn active_route(driver_id: u64) -> Option<RouteView> {
+    let routes: Vec<Route> = ROUTES.with(|routes| {
+        routes
+            .borrow()
+            .iter()
+            .map(|(_, route)| route)
+            .filter(|route| route.driver_id == driver_id)
+            .collect()
+    });
+    routes.iter().rev().map(route_view).find(|view| {
+        view.stops
+            .iter()
+            .any(|stop| stop.status == StopStatus::Pending)
+    })
+}
+
+// Function for a driver or governance to group a driver's assignments into
+// one run. Stops are ordered nearest first from the driver's location, with
+// every drop-off after its pickup.
+#[ic_cdk::update]
+async fn build_route(
+    driver_id: u64,
+    assignment_ids: Vec<u64>,
+) -> Result<RouteView, FoodShareError> {
+    start_trace("build_route");
+    authorize(EndpointClass::Logistics).await?;
+
+    let driver = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&driver_id))
+        .filter(|driver| driver.active)
+        .ok_or(FoodShareError::NotFound {
+            msg: "Driver ID does not exist or is deactivated".to_string(),
+        })?;
+    let caller = ic_cdk::caller();
+    if driver.owner != Some(caller) && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the driver or governance can build this route".to_string(),
+        });
+    }
+
+    let mut ids = assignment_ids.clone();
+    ids.sort();
+    ids.dedup();
+    if ids.len() != assignment_ids.len()
+        || !(MIN_ROUTE_ASSIGNMENTS..=MAX_ROUTE_ASSIGNMENTS).contains(&ids.len())
+    {
+        return Err(FoodShareError::Validation {
+            field: "assignment_ids".to_string(),
+            reason: format!(
+                "A route needs between {} and {} distinct assignments",
+                MIN_ROUTE_ASSIGNMENTS, MAX_ROUTE_ASSIGNMENTS
+            ),
+        });
+    }
+    if active_route(driver_id).is_some() {
+        return Err(FoodShareError::Conflict {
+            msg: "Driver already has an active route".to_string(),
+        });
+    }
+
+    let mut stops = Vec::with_capacity(assignment_ids.len() * 2);
+    for assignment_id in &assignment_ids {
+        let assignment = ASSIGNMENTS_STORAGE
+            .with(|storage| storage.borrow().get(assignment_id))
+            .ok_or(FoodShareError::NotFound {
+                msg: format!("Assignment {} does not exist", assignment_id),
+            })?;
+        if assignment.driver_id != driver_id {
+            return Err(FoodShareError::Validation {
+                field: "assignment_ids".to_string(),
+                reason: format!("Assignment {} belongs to another driver", assignment_id),
+            });
+        }
+        if !matches!(
+            assignment.status,
+            AssignmentStatus::Pending | AssignmentStatus::Accepted
+        ) {
+            return Err(FoodShareError::Conflict {
+                msg: format!(
+                    "Assignment {} is {:?}; only assignments not yet picked up can be routed",
+                    assignment_id, assignment.status
+                ),
+            });
+        }
+        // Onward legs start at a hub rather than at the post's location
+        if assignment.previous_leg_id.is_some() {
+            return Err(FoodShareError::Validation {
+                field: "assignment_ids".to_string(),
+                reason: format!("Assignment {} is a hub leg", assignment_id),
+            });
+        }
+
+        let post =
+            SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&assignment.surplus_post_id));
+        let receiver =
+            RECEIVERS_STORAGE.with(|storage| storage.borrow().get(&assignment.receiver_id));
+        stops.push(RouteStop {
+            assignment_id: *assignment_id,
+            kind: StopKind::Pickup,
+            latitude: post.as_ref().and_then(|post| post.latitude),
+            longitude: post.as_ref().and_then(|post| post.longitude),
+        });
+        stops.push(RouteStop {
+            assignment_id: *assignment_id,
+            kind: StopKind::Dropoff,
+            latitude: receiver.as_ref().and_then(|receiver| receiver.latitude),
+            longitude: receiver.as_ref().and_then(|receiver| receiver.longitude),
+        });
+    }
+
+    let start = driver.latitude.zip(driver.longitude);
+    let (stops, distance_km) = order_stops(start, stops);
+    let route = Route {
+        id: next_id(),
+        driver_id,
+        stops,
+        distance_km,
+        created_at: time(),
+    };
+    ROUTES.with(|routes| routes.borrow_mut().insert(route.id, route.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::Route,
+        route.id,
+        None,
+        Some(&route),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Built route {} for driver {} with {} stops over {:.1} km",
+            route.id,
+            driver_id,
+            route.stops.len(),
+            distance_km
+        ),
+    );
+
+    Ok(route_view(&route))
+}
+
+// Function for a driver to get their route that still has stops to visit,
+// with the status of each stop
+#[ic_cdk::query]
+fn get_my_active_route() -> Result<RouteView, FoodShareError> {
+    let caller = ic_cdk::caller();
+    let driver_ids: Vec<u64> = DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, driver)| driver.active && driver.owner == Some(caller))
+            .map(|(id, _)| id)
+            .collect()
+    });
+    driver_ids
+        .into_iter()
+        .find_map(active_route)
+        .ok_or(FoodShareError::NotFound {
+            msg: "Caller has no active route".to_string(),
+        })
+}
@@ -0,0 +1,152 @@
+use crate::governance::{is_admin, require_admin, EndpointClass};
+use crate::{
+    FoodShareError, Memory, StorablePrincipal, DONORS_STORAGE, DRIVERS_STORAGE, MEMORY_MANAGER,
+    RECEIVERS_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct UserActivity {
+    principal: Principal,
+    first_seen_at: u64,
+    last_active_at: u64,
+    // Class of the last authorized call; None when the last activity was a touch
+    last_endpoint_class: Option<EndpointClass>,
+    // Authorized calls and touches since the principal was first seen
+    activity_count: u64,
+}
+
+impl Storable for UserActivity {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for UserActivity {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// WhoAmI groups the caller's principal with the active profiles it owns
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct WhoAmI {
+    principal: Principal,
+    is_anonymous: bool,
+    is_admin: bool,
+    donor_ids: Vec<u64>,
+    receiver_ids: Vec<u64>,
+    driver_ids: Vec<u64>,
+    last_active_at: Option<u64>,
+}
+
+thread_local! {
+    static USER_ACTIVITY: RefCell<StableBTreeMap<StorablePrincipal, UserActivity, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(98)))
+    ));
+}
+
+fn load_activity(principal: Principal) -> Option<UserActivity> {
+    USER_ACTIVITY.with(|storage| storage.borrow().get(&StorablePrincipal(principal)))
+}
+
+// Function to note that a signed-in principal was just active; anonymous
+// callers are not tracked
+pub(crate) fn record_activity(
+    principal: Principal,
+    class: Option<EndpointClass>,
+) -> Option<UserActivity> {
+    if principal == Principal::anonymous() {
+        return None;
+    }
+    let now = time();
+    let activity = match load_activity(principal) {
+        Some(activity) => UserActivity {
+            last_active_at: now,
+            last_endpoint_class: class,
+            activity_count: activity.activity_count + 1,
+            ..activity
+        },
+        None => UserActivity {
+            principal,
+            first_seen_at: now,
+            last_active_at: now,
+            last_endpoint_class: class,
+            activity_count: 1,
+        },
+    };
+    USER_ACTIVITY.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(StorablePrincipal(principal), activity.clone())
+    });
+    Some(activity)
+}
+
+// Function for frontends to mark the caller active, e.g. right after login or
+// on a heartbeat, without making any other call
+#[ic_cdk::update]
+fn touch_session() -> Result<UserActivity, FoodShareError> {
+    record_activity(ic_cdk::caller(), None).ok_or(FoodShareError::Unauthorized {
+        msg: "Sign in to start a session".to_string(),
+    })
+}
+
+// Function for admins to see when a principal was first and last active
+#[ic_cdk::query]
+fn get_user_activity(principal: Principal) -> Result<UserActivity, FoodShareError> {
+    require_admin("view user activity")?;
+    load_activity(principal).ok_or(FoodShareError::NotFound {
+        msg: format!("No activity recorded for {}", principal),
+    })
+}
+
+// Function to get the caller's principal and the ids of the active profiles it
+// owns, so frontends can route a user straight after login
+#[ic_cdk::query]
+fn whoami() -> WhoAmI {
+    let caller = ic_cdk::caller();
+    let owner = Some(caller);
+    let donor_ids = DONORS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, donor)| donor.active && donor.owner == owner)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    let receiver_ids = RECEIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, receiver)| receiver.active && receiver.owner == owner)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    let driver_ids = DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, driver)| driver.active && driver.owner == owner)
+            .map(|(id, _)| id)
+            .collect()
+    });
+
+    WhoAmI {
+        principal: caller,
+        is_anonymous: caller == Principal::anonymous(),
+        is_admin: is_admin(&caller),
+        donor_ids,
+        receiver_ids,
+        driver_ids,
+        last_active_at: load_activity(caller).map(|activity| activity.last_active_at),
+    }
+}
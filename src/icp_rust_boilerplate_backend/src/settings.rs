@@ -0,0 +1,182 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::require_admin;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{post_expiry, FoodShareError, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MIN_PHONE_NUMBER_LENGTH: u32 = 6;
+const MAX_PHONE_NUMBER_LENGTH: u32 = 15;
+const MIN_SWEEP_INTERVAL_SECS: u64 = 60;
+const MAX_SWEEP_INTERVAL_SECS: u64 = 24 * 60 * 60;
+// Calling codes have at most three digits
+const MAX_COUNTRY_CODE: u16 = 999;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Settings {
+    // Characters a profile phone number must have
+    phone_number_length: u32,
+    // Inclusive bounds for delivery ratings and reviews
+    min_rating: u8,
+    max_rating: u8,
+    expiry_sweep_interval_secs: u64,
+    // Country calling code given to phone numbers entered without one, e.g. 254;
+    // None keeps those numbers in national form
+    default_country_code: Option<u16>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            phone_number_length: 10,
+            min_rating: 1,
+            max_rating: 5,
+            expiry_sweep_interval_secs: 60 * 60,
+            default_country_code: None,
+        }
+    }
+}
+
+impl Storable for Settings {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Settings Patch; fields left as None keep their current value
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct SettingsPatch {
+    phone_number_length: Option<u32>,
+    min_rating: Option<u8>,
+    max_rating: Option<u8>,
+    expiry_sweep_interval_secs: Option<u64>,
+    default_country_code: Option<u16>,
+}
+
+thread_local! {
+    static SETTINGS: RefCell<Cell<Settings, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(99))),
+            Settings::default(),
+        )
+        .expect("Cannot create the settings")
+    );
+}
+
+pub(crate) fn settings() -> Settings {
+    SETTINGS.with(|cell| cell.borrow().get().clone())
+}
+
+pub(crate) fn expiry_sweep_interval_secs() -> u64 {
+    settings().expiry_sweep_interval_secs
+}
+
+pub(crate) fn phone_number_length() -> u32 {
+    settings().phone_number_length
+}
+
+pub(crate) fn default_country_code() -> Option<u16> {
+    settings().default_country_code
+}
+
+// Function to check a rating against the configured scale
+pub(crate) fn validate_rating(rating: u8) -> Result<(), FoodShareError> {
+    let settings = settings();
+    if !(settings.min_rating..=settings.max_rating).contains(&rating) {
+        return Err(FoodShareError::Validation {
+            field: "rating".to_string(),
+            reason: format!(
+                "Rating must be between {} and {}",
+                settings.min_rating, settings.max_rating
+            ),
+        });
+    }
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_settings() -> Settings {
+    settings()
+}
+
+// Function for admins to change canister settings; every field given is
+// checked before any of them is applied
+#[ic_cdk::update]
+fn update_settings(patch: SettingsPatch) -> Result<Settings, FoodShareError> {
+    start_trace("update_settings");
+    require_admin("update the settings")?;
+
+    let before = settings();
+    let updated = Settings {
+        phone_number_length: patch
+            .phone_number_length
+            .unwrap_or(before.phone_number_length),
+        min_rating: patch.min_rating.unwrap_or(before.min_rating),
+        max_rating: patch.max_rating.unwrap_or(before.max_rating),
+        expiry_sweep_interval_secs: patch
+            .expiry_sweep_interval_secs
+            .unwrap_or(before.expiry_sweep_interval_secs),
+        default_country_code: patch.default_country_code.or(before.default_country_code),
+    };
+
+    if !(MIN_PHONE_NUMBER_LENGTH..=MAX_PHONE_NUMBER_LENGTH).contains(&updated.phone_number_length) {
+        return Err(FoodShareError::Validation {
+            field: "phone_number_length".to_string(),
+            reason: format!(
+                "Phone number length must be between {} and {}",
+                MIN_PHONE_NUMBER_LENGTH, MAX_PHONE_NUMBER_LENGTH
+            ),
+        });
+    }
+    if updated.min_rating == 0 || updated.min_rating >= updated.max_rating {
+        return Err(FoodShareError::Validation {
+            field: "min_rating".to_string(),
+            reason: "Ratings must start at 1 or more and below the maximum rating".to_string(),
+        });
+    }
+    if !(MIN_SWEEP_INTERVAL_SECS..=MAX_SWEEP_INTERVAL_SECS)
+        .contains(&updated.expiry_sweep_interval_secs)
+    {
+        return Err(FoodShareError::Validation {
+            field: "expiry_sweep_interval_secs".to_string(),
+            reason: format!(
+                "Sweep interval must be between {} and {} seconds",
+                MIN_SWEEP_INTERVAL_SECS, MAX_SWEEP_INTERVAL_SECS
+            ),
+        });
+    }
+
+    if updated
+        .default_country_code
+        .is_some_and(|code| !(1..=MAX_COUNTRY_CODE).contains(&code))
+    {
+        return Err(FoodShareError::Validation {
+            field: "default_country_code".to_string(),
+            reason: format!("Country code must be between 1 and {}", MAX_COUNTRY_CODE),
+        });
+    }
+
+    SETTINGS
+        .with(|cell| cell.borrow_mut().set(updated.clone()))
+        .expect("Cannot update the settings");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::Settings,
+        0,
+        Some(&before),
+        Some(&updated),
+    );
+    if updated.expiry_sweep_interval_secs != before.expiry_sweep_interval_secs {
+        post_expiry::start_expiry_sweep();
+    }
+
+    log(LogLevel::Info, "Updated canister settings".to_string());
+
+    Ok(updated)
+}
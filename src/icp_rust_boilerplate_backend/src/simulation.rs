@@ -0,0 +1,306 @@
+use crate::events::{events_after, EntityType, EventKind};
+use crate::geo::haversine_km;
+use crate::governance::require_admin;
+use crate::indexes::assignments_for_post;
+use crate::post_expiry::expires_at;
+use crate::radius_expansion::{radius_expansion_config, validate_config, RadiusExpansionConfig};
+use crate::reputation::driver_reputation;
+use crate::{
+    AssignmentStatus, FoodShareError, SurplusPost, DRIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use chrono::NaiveDate;
+
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+const NANOS_PER_DAY: u64 = 24 * 60 * NANOS_PER_MINUTE;
+const EVENT_PAGE_SIZE: usize = 1_000;
+const DEFAULT_DRIVER_SPEED_KMH: f64 = 25.0;
+const DEFAULT_HANDLING_MINUTES: u64 = 30;
+
+// DriverStrategy is a custom enum type that is used to represent how the simulation picks a driver for a post
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum DriverStrategy {
+    // The driver who can reach the post first
+    Nearest,
+    // The best rated driver who can reach it before it spoils, the earliest among equals
+    BestRated,
+}
+
+// Candidate settings to replay a day against; None keeps the live value
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct SimulationConfig {
+    radius: Option<RadiusExpansionConfig>,
+    strategy: DriverStrategy,
+    driver_speed_kmh: Option<f64>,
+    // Time a driver is busy with a post after picking it up
+    handling_minutes: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct SimulationMetrics {
+    picked_up: u64,
+    spoiled_posts: u64,
+    spoiled_kg: u64,
+    avg_minutes_to_pickup: Option<f64>,
+    median_minutes_to_pickup: Option<f64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SimulationReport {
+    day: String,
+    posts: u64,
+    // What happened to the day's posts
+    actual: SimulationMetrics,
+    // What the candidate config and strategy would have done with them
+    simulated: SimulationMetrics,
+}
+
+// A post of the replayed day, copied out of storage
+struct SimPost {
+    post: SurplusPost,
+    created_at: u64,
+}
+
+// A driver of the in-memory model
+struct SimDriver {
+    id: u64,
+    latitude: f64,
+    longitude: f64,
+    rating: f64,
+    free_at: u64,
+}
+
+struct Pickup {
+    driver_index: usize,
+    at: u64,
+}
+
+#[derive(Default)]
+struct Tally {
+    minutes_to_pickup: Vec<f64>,
+    spoiled_posts: u64,
+    spoiled_kg: u64,
+}
+
+impl Tally {
+    fn picked_up(&mut self, created_at: u64, at: u64) {
+        self.minutes_to_pickup
+            .push(at.saturating_sub(created_at) as f64 / NANOS_PER_MINUTE as f64);
+    }
+
+    fn spoiled(&mut self, post: &SurplusPost) {
+        self.spoiled_posts += 1;
+        self.spoiled_kg += post.quantity_kg as u64;
+    }
+
+    fn into_metrics(mut self) -> SimulationMetrics {
+        self.minutes_to_pickup.sort_by(f64::total_cmp);
+        let count = self.minutes_to_pickup.len();
+        let median = match count {
+            0 => None,
+            _ if count % 2 == 1 => Some(self.minutes_to_pickup[count / 2]),
+            _ => Some(
+                (self.minutes_to_pickup[count / 2 - 1] + self.minutes_to_pickup[count / 2]) / 2.0,
+            ),
+        };
+        SimulationMetrics {
+            picked_up: count as u64,
+            spoiled_posts: self.spoiled_posts,
+            spoiled_kg: self.spoiled_kg,
+            avg_minutes_to_pickup: (count > 0)
+                .then(|| self.minutes_to_pickup.iter().sum::<f64>() / count as f64),
+            median_minutes_to_pickup: median,
+        }
+    }
+}
+
+fn day_bounds(day: &str) -> Result<(u64, u64), FoodShareError> {
+    let start = NaiveDate::parse_from_str(day, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .and_then(|start| u64::try_from(start.and_utc().timestamp()).ok())
+        .ok_or(FoodShareError::Validation {
+            field: "day".to_string(),
+            reason: "Day must be formatted as YYYY-MM-DD".to_string(),
+        })?;
+    let start = start * 1_000_000_000;
+    Ok((start, start + NANOS_PER_DAY))
+}
+
+// Function to read the posts created during [start, end) from the event log,
+// in the order they were created
+fn posts_created_between(start: u64, end: u64) -> Vec<SimPost> {
+    let mut posts = Vec::new();
+    let mut cursor = None;
+    loop {
+        let events = events_after(cursor, EVENT_PAGE_SIZE);
+        let Some(last) = events.last() else {
+            break;
+        };
+        cursor = Some(last.id);
+        for event in events {
+            if event.entity_type != EntityType::SurplusPost
+                || event.kind != EventKind::Created
+                || event.timestamp < start
+                || event.timestamp >= end
+            {
+                continue;
+            }
+            if let Some(post) =
+                SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&event.entity_id))
+            {
+                posts.push(SimPost {
+                    post,
+                    created_at: event.timestamp,
+                });
+            }
+        }
+    }
+    posts
+}
+
+// Function to tally when each post was actually picked up, or whether it spoiled
+fn actual_metrics(posts: &[SimPost]) -> SimulationMetrics {
+    let mut tally = Tally::default();
+    for sim in posts {
+        let picked_up_at = assignments_for_post(sim.post.id)
+            .iter()
+            .flat_map(|assignment| &assignment.status_history)
+            .filter(|change| change.status == AssignmentStatus::PickedUp)
+            .map(|change| change.changed_at)
+            .min();
+        match picked_up_at {
+            Some(at) => tally.picked_up(sim.created_at, at),
+            None if sim.post.expired_at.is_some() => tally.spoiled(&sim.post),
+            None => {}
+        }
+    }
+    tally.into_metrics()
+}
+
+// Function to get the earliest time a driver could pick up a post: once the
+// driver is free and the post's reach, widened by the candidate config, covers
+// the driver, plus the time to drive there
+fn pickup_time(
+    sim: &SimPost,
+    driver: &SimDriver,
+    radius: &RadiusExpansionConfig,
+    speed_kmh: f64,
+) -> Option<u64> {
+    // Posts without coordinates reach every driver and are treated as next door
+    let distance_km = match (sim.post.latitude, sim.post.longitude) {
+        (Some(lat), Some(lng)) => haversine_km(lat, lng, driver.latitude, driver.longitude),
+        _ => 0.0,
+    };
+    if distance_km > radius.max_radius_km {
+        return None;
+    }
+    let steps = ((distance_km - radius.start_radius_km) / radius.step_km)
+        .ceil()
+        .max(0.0) as u64;
+    let reached_at = sim.created_at + steps * radius.interval_minutes * NANOS_PER_MINUTE;
+    let drive = (distance_km / speed_kmh * 60.0 * NANOS_PER_MINUTE as f64) as u64;
+    Some(reached_at.max(driver.free_at).max(sim.created_at) + drive)
+}
+
+// Function to replay the day's posts against the candidate config in memory:
+// each post goes to one driver by the strategy, and a driver takes one post at a time
+fn simulated_metrics(
+    posts: &[SimPost],
+    config: &SimulationConfig,
+    radius: &RadiusExpansionConfig,
+) -> SimulationMetrics {
+    let speed_kmh = config.driver_speed_kmh.unwrap_or(DEFAULT_DRIVER_SPEED_KMH);
+    let handling = config.handling_minutes.unwrap_or(DEFAULT_HANDLING_MINUTES) * NANOS_PER_MINUTE;
+    let mut drivers: Vec<SimDriver> = DRIVERS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, driver)| driver)
+            .filter(|driver| driver.active)
+            .filter_map(|driver| {
+                Some(SimDriver {
+                    id: driver.id,
+                    latitude: driver.latitude?,
+                    longitude: driver.longitude?,
+                    rating: driver_reputation(driver.id).average_rating.unwrap_or(0.0),
+                    free_at: 0,
+                })
+            })
+            .collect()
+    });
+
+    let mut tally = Tally::default();
+    for sim in posts {
+        let spoils_at = expires_at(&sim.post).unwrap_or(u64::MAX);
+        let candidates = drivers
+            .iter()
+            .enumerate()
+            .filter_map(|(driver_index, driver)| {
+                pickup_time(sim, driver, radius, speed_kmh)
+                    .filter(|at| *at <= spoils_at)
+                    .map(|at| Pickup { driver_index, at })
+            });
+        let best = match config.strategy {
+            DriverStrategy::Nearest => {
+                candidates.min_by_key(|pickup| (pickup.at, drivers[pickup.driver_index].id))
+            }
+            DriverStrategy::BestRated => candidates.min_by(|a, b| {
+                let (a_driver, b_driver) = (&drivers[a.driver_index], &drivers[b.driver_index]);
+                b_driver
+                    .rating
+                    .total_cmp(&a_driver.rating)
+                    .then(a.at.cmp(&b.at))
+                    .then(a_driver.id.cmp(&b_driver.id))
+            }),
+        };
+        match best {
+            Some(pickup) => {
+                tally.picked_up(sim.created_at, pickup.at);
+                drivers[pickup.driver_index].free_at = pickup.at + handling;
+            }
+            // No driver could collect it before it spoiled
+            None => tally.spoiled(&sim.post),
+        }
+    }
+    tally.into_metrics()
+}
+
+// Function for admins to replay the posts of a past day against a candidate
+// radius config and driver strategy, and compare the outcome with what
+// actually happened. The replay runs on an in-memory copy and changes nothing.
+#[ic_cdk::query]
+fn simulate_day(day: String, config: SimulationConfig) -> Result<SimulationReport, FoodShareError> {
+    require_admin("run simulations")?;
+
+    let radius = match &config.radius {
+        Some(radius) => {
+            validate_config(radius)?;
+            radius.clone()
+        }
+        None => radius_expansion_config(),
+    };
+    if config
+        .driver_speed_kmh
+        .is_some_and(|speed| speed.is_nan() || speed <= 0.0)
+    {
+        return Err(FoodShareError::Validation {
+            field: "driver_speed_kmh".to_string(),
+            reason: "Driver speed must be greater than 0".to_string(),
+        });
+    }
+
+    let (start, end) = day_bounds(&day)?;
+    let posts = posts_created_between(start, end);
+    if posts.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: format!("No surplus posts were created on {}", day),
+        });
+    }
+
+    Ok(SimulationReport {
+        day,
+        posts: posts.len() as u64,
+        actual: actual_metrics(&posts),
+        simulated: simulated_metrics(&posts, &config, &radius),
+    })
+}
@@ -0,0 +1,388 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::browse::{index_open_post, remove_open_post};
+use crate::category_pauses::is_food_type_paused;
+use crate::claims::{approved_receiver, is_open_post};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, is_governance_or_admin, EndpointClass};
+use crate::indexes::store_post;
+use crate::matching::matched_receiver;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    next_id, AssignmentPayload, FoodShareError, Memory, SurplusPost, MEMORY_MANAGER,
+    RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_RESERVATIONS_PER_POST: usize = 20;
+
+// QuantityReservationStatus is a custom enum type that is used to represent where a share of a split donation stands
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum QuantityReservationStatus {
+    // Held for the receiver until a driver is assigned
+    Reserved,
+    Assigned,
+    // Given back to the post, by the receiver or because its assignment ended early
+    Released,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct QuantityReservation {
+    id: u64,
+    post_id: u64,
+    receiver_id: u64,
+    pub(crate) quantity_kg: u32,
+    status: QuantityReservationStatus,
+    // The assignment delivering this share, once a driver is assigned
+    assignment_id: Option<u64>,
+    reserved_at: u64,
+    released_at: Option<u64>,
+}
+
+impl Storable for QuantityReservation {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for QuantityReservation {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static QUANTITY_RESERVATIONS: RefCell<StableBTreeMap<u64, QuantityReservation, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(105)))
+    ));
+
+    // (post_id, reservation_id)
+    static RESERVATIONS_BY_POST: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(106)))
+    ));
+}
+
+fn reservations_for_post(post_id: u64) -> Vec<QuantityReservation> {
+    let ids: Vec<u64> = RESERVATIONS_BY_POST.with(|index| {
+        index
+            .borrow()
+            .range((post_id, 0)..=(post_id, u64::MAX))
+            .map(|((_, reservation_id), _)| reservation_id)
+            .collect()
+    });
+    QUANTITY_RESERVATIONS.with(|storage| {
+        let storage = storage.borrow();
+        ids.iter().filter_map(|id| storage.get(id)).collect()
+    })
+}
+
+fn load_reservation(reservation_id: u64) -> Result<QuantityReservation, FoodShareError> {
+    QUANTITY_RESERVATIONS
+        .with(|storage| storage.borrow().get(&reservation_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Reservation ID does not exist".to_string(),
+        })
+}
+
+fn store_reservation(before: Option<&QuantityReservation>, reservation: &QuantityReservation) {
+    QUANTITY_RESERVATIONS.with(|storage| {
+        storage
+            .borrow_mut()
+            .insert(reservation.id, reservation.clone())
+    });
+    audit(
+        match before {
+            None => AuditAction::Created,
+            Some(_) => AuditAction::StatusChanged,
+        },
+        AuditEntity::QuantityReservation,
+        reservation.id,
+        before,
+        Some(reservation),
+    );
+}
+
+// Function to get the kilograms of a post not yet reserved by any receiver
+pub(crate) fn remaining_kg(post: &SurplusPost) -> u32 {
+    post.quantity_kg
+        .saturating_sub(post.reserved_kg.unwrap_or_default())
+}
+
+// Function to check whether a post has been split into reservations that still hold food
+pub(crate) fn has_live_reservations(post_id: u64) -> bool {
+    reservations_for_post(post_id)
+        .iter()
+        .any(|reservation| reservation.status != QuantityReservationStatus::Released)
+}
+
+// Function to add `reserved_kg` to a post, or give it back when negative,
+// closing the post once nothing is left and reopening it when a share returns
+fn adjust_reserved_kg(post_id: u64, reserved_kg: i64) {
+    let Some(before) = SURPLUS_POSTS_STORAGE.with(|storage| storage.borrow().get(&post_id)) else {
+        return;
+    };
+    let mut post = before.clone();
+    let reserved = (post.reserved_kg.unwrap_or_default() as i64 + reserved_kg)
+        .clamp(0, post.quantity_kg as i64) as u32;
+    post.reserved_kg = Some(reserved);
+    let closed = post.withdrawn_at.is_some() || post.expired_at.is_some();
+    if !closed {
+        post.assigned = remaining_kg(&post) == 0;
+    }
+    store_post(&post);
+    audit(
+        AuditAction::Updated,
+        AuditEntity::SurplusPost,
+        post.id,
+        Some(&before),
+        Some(&post),
+    );
+    if is_open_post(&post) {
+        index_open_post(&post);
+    } else if is_open_post(&before) {
+        remove_open_post(post.id);
+    }
+}
+
+// Function to give a reservation's share back to its post
+fn release(reservation: &QuantityReservation, detail: String) {
+    let mut released = reservation.clone();
+    released.status = QuantityReservationStatus::Released;
+    released.released_at = Some(time());
+    store_reservation(Some(reservation), &released);
+    adjust_reserved_kg(reservation.post_id, -(reservation.quantity_kg as i64));
+    record_event(
+        EntityType::SurplusPost,
+        reservation.post_id,
+        None,
+        EventKind::Updated,
+        detail,
+    );
+}
+
+// Function to check that an assignment payload may deliver a reservation
+pub(crate) fn assignable_reservation(
+    reservation_id: u64,
+    payload: &AssignmentPayload,
+) -> Result<QuantityReservation, FoodShareError> {
+    let reservation = load_reservation(reservation_id)?;
+    if reservation.post_id != payload.surplus_post_id
+        || reservation.receiver_id != payload.receiver_id
+    {
+        return Err(FoodShareError::Validation {
+            field: "reservation_id".to_string(),
+            reason: "Reservation is for another post or receiver".to_string(),
+        });
+    }
+    if reservation.status != QuantityReservationStatus::Reserved {
+        return Err(FoodShareError::Conflict {
+            msg: "Reservation has already been assigned or released".to_string(),
+        });
+    }
+    Ok(reservation)
+}
+
+// Function to link a reservation to the assignment created to deliver it
+pub(crate) fn mark_assigned(reservation: &QuantityReservation, assignment_id: u64) {
+    let mut assigned = reservation.clone();
+    assigned.status = QuantityReservationStatus::Assigned;
+    assigned.assignment_id = Some(assignment_id);
+    store_reservation(Some(reservation), &assigned);
+}
+
+// Function to give back the share an assignment was delivering when it ends
+// early. Returns false when the assignment was not for a reservation.
+pub(crate) fn release_for_assignment(post_id: u64, assignment_id: u64) -> bool {
+    let Some(reservation) = reservations_for_post(post_id)
+        .into_iter()
+        .find(|reservation| {
+            reservation.assignment_id == Some(assignment_id)
+                && reservation.status == QuantityReservationStatus::Assigned
+        })
+    else {
+        return false;
+    };
+    release(
+        &reservation,
+        format!(
+            "Assignment {} ended, {} kg returned to the post",
+            assignment_id, reservation.quantity_kg
+        ),
+    );
+    true
+}
+
+// Function to release every reservation on a post that has been withdrawn or
+// has expired; the post stays closed
+pub(crate) fn close_post_reservations(post_id: u64) {
+    for reservation in reservations_for_post(post_id)
+        .into_iter()
+        .filter(|reservation| reservation.status != QuantityReservationStatus::Released)
+    {
+        let mut released = reservation.clone();
+        released.status = QuantityReservationStatus::Released;
+        released.released_at = Some(time());
+        store_reservation(Some(&reservation), &released);
+    }
+}
+
+// Function for a receiver to reserve part of an open post; each reservation is
+// delivered by its own assignment, and the post closes once it is fully reserved
+#[ic_cdk::update]
+async fn reserve_quantity(
+    post_id: u64,
+    quantity_kg: u32,
+) -> Result<QuantityReservation, FoodShareError> {
+    start_trace("reserve_quantity");
+    authorize(EndpointClass::Posting).await?;
+
+    let post = SURPLUS_POSTS_STORAGE
+        .with(|storage| storage.borrow().get(&post_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Surplus post ID does not exist".to_string(),
+        })?;
+    if !is_open_post(&post) {
+        return Err(FoodShareError::Conflict {
+            msg: "Only open posts can be reserved".to_string(),
+        });
+    }
+    let remaining = remaining_kg(&post);
+    if quantity_kg == 0 || quantity_kg > remaining {
+        return Err(FoodShareError::Validation {
+            field: "quantity_kg".to_string(),
+            reason: format!("Quantity must be between 1 and the {} kg left", remaining),
+        });
+    }
+
+    let caller = ic_cdk::caller();
+    let receiver = RECEIVERS_STORAGE
+        .with(|storage| {
+            storage
+                .borrow()
+                .iter()
+                .map(|(_, receiver)| receiver)
+                .find(|receiver| receiver.active && receiver.owner == Some(caller))
+        })
+        .ok_or(FoodShareError::Unauthorized {
+            msg: "Only receivers can reserve posts".to_string(),
+        })?;
+    if is_food_type_paused(receiver.id, post.food_type) {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Receiver is not accepting {:?} at the moment",
+                post.food_type
+            ),
+        });
+    }
+    if matched_receiver(post_id).is_some_and(|receiver_id| receiver_id != receiver.id) {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus post is matched to another receiver".to_string(),
+        });
+    }
+    if approved_receiver(post_id).is_some_and(|receiver_id| receiver_id != receiver.id) {
+        return Err(FoodShareError::Conflict {
+            msg: "Surplus post is claimed by another receiver".to_string(),
+        });
+    }
+    let live = reservations_for_post(post_id)
+        .into_iter()
+        .filter(|reservation| reservation.status != QuantityReservationStatus::Released)
+        .count();
+    if live >= MAX_RESERVATIONS_PER_POST {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "A post can be split into at most {} reservations",
+                MAX_RESERVATIONS_PER_POST
+            ),
+        });
+    }
+
+    let reservation = QuantityReservation {
+        id: next_id(),
+        post_id,
+        receiver_id: receiver.id,
+        quantity_kg,
+        status: QuantityReservationStatus::Reserved,
+        assignment_id: None,
+        reserved_at: time(),
+        released_at: None,
+    };
+    store_reservation(None, &reservation);
+    RESERVATIONS_BY_POST.with(|index| index.borrow_mut().insert((post_id, reservation.id), ()));
+    adjust_reserved_kg(post_id, quantity_kg as i64);
+
+    record_event(
+        EntityType::SurplusPost,
+        post_id,
+        None,
+        EventKind::Updated,
+        format!(
+            "Receiver {} reserved {} of {} kg",
+            receiver.id, quantity_kg, remaining
+        ),
+    );
+    log(
+        LogLevel::Info,
+        format!(
+            "Receiver {} reserved {} kg of post {}",
+            receiver.id, quantity_kg, post_id
+        ),
+    );
+
+    Ok(reservation)
+}
+
+// Function for the receiver or governance to give back a reservation no driver
+// has been assigned to yet
+#[ic_cdk::update]
+async fn release_quantity_reservation(
+    reservation_id: u64,
+) -> Result<QuantityReservation, FoodShareError> {
+    start_trace("release_quantity_reservation");
+    authorize(EndpointClass::Posting).await?;
+
+    let reservation = load_reservation(reservation_id)?;
+    let caller = ic_cdk::caller();
+    let is_receiver = RECEIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&reservation.receiver_id))
+        .is_some_and(|receiver| receiver.owner == Some(caller));
+    if !is_receiver && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the receiver or governance can release this reservation".to_string(),
+        });
+    }
+    if reservation.status != QuantityReservationStatus::Reserved {
+        return Err(FoodShareError::Conflict {
+            msg: "Only unassigned reservations can be released; cancel the assignment instead"
+                .to_string(),
+        });
+    }
+
+    release(
+        &reservation,
+        format!(
+            "Receiver {} released {} kg",
+            reservation.receiver_id, reservation.quantity_kg
+        ),
+    );
+    log(
+        LogLevel::Info,
+        format!("Released reservation {}", reservation_id),
+    );
+
+    load_reservation(reservation_id)
+}
+
+// Function to get every reservation on a post, oldest first
+#[ic_cdk::query]
+fn get_post_reservations(post_id: u64) -> Vec<QuantityReservation> {
+    reservations_for_post(post_id)
+}
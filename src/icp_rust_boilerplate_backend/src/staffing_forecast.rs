@@ -0,0 +1,230 @@
+use crate::recurring_posts::scheduled_template_donors;
+use crate::regions::require_region_admin;
+use crate::{
+    is_active_donor, AssignmentStatus, FoodShareError, ASSIGNMENTS_STORAGE, DONORS_STORAGE,
+    SURPLUS_POSTS_STORAGE,
+};
+use chrono::{DateTime, Datelike, Days, NaiveDate};
+use ic_cdk::api::time;
+use std::collections::{BTreeMap, BTreeSet};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const NANOS_PER_HOUR: f64 = 60.0 * 60.0 * NANOS_PER_SECOND as f64;
+// Same weekdays looked back on for the post volume
+const HISTORY_WEEKS: usize = 8;
+// Used until the region has a completed route to measure
+const DEFAULT_HOURS_PER_ROUTE: f64 = 1.5;
+const SHIFT_HOURS: f64 = 4.0;
+// Two-sided 95% normal quantile
+const Z_95: f64 = 1.96;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DriverNeedForecast {
+    date: String,
+    region: String,
+    // Posts the region's recurring templates will create that day
+    template_posts: u64,
+    // One-off posts, from the same weekday in past weeks
+    expected_other_posts: f64,
+    weeks_sampled: u64,
+    // Average time from a driver accepting a route to dropping the food off
+    hours_per_route: f64,
+    routes_sampled: u64,
+    driver_hours: f64,
+    // 95% bounds, from the spread of past weekday volumes
+    driver_hours_low: f64,
+    driver_hours_high: f64,
+    // Drivers on 4-hour shifts needed to cover the high bound
+    drivers_needed: u64,
+}
+
+fn start_of_day(date: NaiveDate) -> u64 {
+    date.and_hms_opt(0, 0, 0)
+        .and_then(|start| u64::try_from(start.and_utc().timestamp()).ok())
+        .map(|secs| secs * NANOS_PER_SECOND)
+        .unwrap_or_default()
+}
+
+fn date_of(nanos: u64) -> NaiveDate {
+    DateTime::from_timestamp_nanos(nanos as i64).date_naive()
+}
+
+// Function to get the donors whose profile is in the region
+fn donors_in(region: &str) -> BTreeSet<u64> {
+    DONORS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, donor)| donor.region.trim().eq_ignore_ascii_case(region))
+            .map(|(id, _)| id)
+            .collect()
+    })
+}
+
+// Function to get the last HISTORY_WEEKS days before today on the weekday of the
+// target date, newest first
+fn history_days(date: NaiveDate, today: NaiveDate) -> Vec<NaiveDate> {
+    let mut day = date;
+    let mut days = Vec::new();
+    while days.len() < HISTORY_WEEKS {
+        let Some(previous) = day.checked_sub_days(Days::new(7)) else {
+            break;
+        };
+        day = previous;
+        if day < today {
+            days.push(day);
+        }
+    }
+    days
+}
+
+// Function to get the average hours from acceptance to drop-off of the region's
+// routes accepted since a time, and how many routes that average covers
+fn hours_per_route(donors: &BTreeSet<u64>, since: u64) -> (f64, u64) {
+    let hours: Vec<f64> = ASSIGNMENTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, assignment)| assignment)
+            .filter(|assignment| {
+                SURPLUS_POSTS_STORAGE
+                    .with(|posts| posts.borrow().get(&assignment.surplus_post_id))
+                    .and_then(|post| post.donor_id.parse::<u64>().ok())
+                    .is_some_and(|donor_id| donors.contains(&donor_id))
+            })
+            .filter_map(|assignment| {
+                let changed_at = |matches: fn(AssignmentStatus) -> bool| {
+                    assignment
+                        .status_history
+                        .iter()
+                        .find(|change| matches(change.status))
+                        .map(|change| change.changed_at)
+                };
+                let accepted_at = changed_at(|status| status == AssignmentStatus::Accepted)?;
+                let dropped_off_at = changed_at(|status| {
+                    matches!(
+                        status,
+                        AssignmentStatus::AwaitingConfirmation | AssignmentStatus::Delivered
+                    )
+                })?;
+                (accepted_at >= since)
+                    .then(|| dropped_off_at.saturating_sub(accepted_at) as f64 / NANOS_PER_HOUR)
+            })
+            .collect()
+    });
+    if hours.is_empty() {
+        return (DEFAULT_HOURS_PER_ROUTE, 0);
+    }
+    (
+        hours.iter().sum::<f64>() / hours.len() as f64,
+        hours.len() as u64,
+    )
+}
+
+// Function for coordinators to estimate the driver hours a region needs on a
+// day. Posts from recurring templates are counted as scheduled; other posts are
+// the average of the same weekday over the past weeks, less what templates
+// posted then, and each post takes the region's average route time.
+#[ic_cdk::query]
+fn forecast_driver_need(
+    date: String,
+    region: String,
+) -> Result<DriverNeedForecast, FoodShareError> {
+    require_region_admin(&region, "forecast driver need")?;
+
+    let region = region.trim().to_string();
+    if region.is_empty() {
+        return Err(FoodShareError::Validation {
+            field: "region".to_string(),
+            reason: "Region is required".to_string(),
+        });
+    }
+    let target =
+        NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|_| FoodShareError::Validation {
+            field: "date".to_string(),
+            reason: "Date must be formatted as YYYY-MM-DD".to_string(),
+        })?;
+    let weekday = target.weekday().num_days_from_monday() as u8;
+
+    let donors = donors_in(&region);
+    let in_region = |donor_id: &u64| donors.contains(donor_id);
+
+    // Weeks before the region's first post would only drag the average down
+    let mut posts_per_day: BTreeMap<NaiveDate, u64> = BTreeMap::new();
+    let mut first_post_at = None;
+    SURPLUS_POSTS_STORAGE.with(|storage| {
+        for (_, post) in storage.borrow().iter() {
+            let (Some(created_at), Ok(donor_id)) = (post.created_at, post.donor_id.parse::<u64>())
+            else {
+                continue;
+            };
+            if !in_region(&donor_id) {
+                continue;
+            }
+            first_post_at =
+                Some(first_post_at.map_or(created_at, |first: u64| first.min(created_at)));
+            *posts_per_day.entry(date_of(created_at)).or_default() += 1;
+        }
+    });
+    let days: Vec<NaiveDate> = history_days(target, date_of(time()))
+        .into_iter()
+        .filter(|day| first_post_at.is_some_and(|first| date_of(first) <= *day))
+        .collect();
+
+    let other_posts: Vec<f64> = days
+        .iter()
+        .map(|day| {
+            let end_of_day = start_of_day(*day) + 24 * 60 * 60 * NANOS_PER_SECOND;
+            let template_posts = scheduled_template_donors(weekday, None, end_of_day)
+                .iter()
+                .filter(|donor_id| in_region(donor_id))
+                .count() as u64;
+            let posts = posts_per_day.get(day).copied().unwrap_or_default();
+            posts.saturating_sub(template_posts) as f64
+        })
+        .collect();
+    let count = other_posts.len() as f64;
+    let mean = if other_posts.is_empty() {
+        0.0
+    } else {
+        other_posts.iter().sum::<f64>() / count
+    };
+    // Prediction interval for one more day like the sampled ones
+    let margin = if other_posts.len() > 1 {
+        let variance = other_posts
+            .iter()
+            .map(|posts| (posts - mean).powi(2))
+            .sum::<f64>()
+            / (count - 1.0);
+        Z_95 * variance.sqrt() * (1.0 + 1.0 / count).sqrt()
+    } else {
+        0.0
+    };
+
+    let template_posts = scheduled_template_donors(weekday, Some(&date), u64::MAX)
+        .into_iter()
+        .filter(|donor_id| in_region(donor_id) && is_active_donor(*donor_id))
+        .count() as u64;
+
+    let since = days
+        .last()
+        .map(|day| start_of_day(*day))
+        .unwrap_or_default();
+    let (hours_per_route, routes_sampled) = hours_per_route(&donors, since);
+
+    let posts = template_posts as f64 + mean;
+    let driver_hours_high = (posts + margin) * hours_per_route;
+    Ok(DriverNeedForecast {
+        date,
+        region,
+        template_posts,
+        expected_other_posts: mean,
+        weeks_sampled: days.len() as u64,
+        hours_per_route,
+        routes_sampled,
+        driver_hours: posts * hours_per_route,
+        driver_hours_low: (template_posts as f64 + (mean - margin).max(0.0)) * hours_per_route,
+        driver_hours_high,
+        drivers_needed: (driver_hours_high / SHIFT_HOURS).ceil() as u64,
+    })
+}
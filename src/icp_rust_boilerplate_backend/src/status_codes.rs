@@ -0,0 +1,197 @@
+use crate::assignment_lifecycle::{is_driver_owner, is_receiver_owner};
+use crate::governance::is_governance_or_admin;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{AssignmentStatus, FoodShareError, Memory, ASSIGNMENTS_STORAGE, MEMORY_MANAGER};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+// Six random digits followed by a Luhn check digit, so most typos are caught
+// before a lookup is made
+const REFERENCE_DIGITS: u64 = 1_000_000;
+
+// Numeric codes and short labels relayed by SMS/USSD gateways, in lifecycle order
+const STATUS_CODES: &[(AssignmentStatus, u8, &str)] = &[
+    (AssignmentStatus::Pending, 10, "WAITING"),
+    (AssignmentStatus::Accepted, 20, "DRIVER READY"),
+    (AssignmentStatus::PickedUp, 30, "PICKED UP"),
+    (AssignmentStatus::InTransit, 40, "ON THE WAY"),
+    (
+        AssignmentStatus::AwaitingConfirmation,
+        45,
+        "AWAITING RECEIVER",
+    ),
+    (AssignmentStatus::Delivered, 50, "DELIVERED"),
+    (AssignmentStatus::Disputed, 55, "DISPUTED"),
+    (AssignmentStatus::Cancelled, 60, "CANCELLED"),
+    (AssignmentStatus::Failed, 70, "FAILED"),
+    (AssignmentStatus::Expired, 80, "EXPIRED"),
+    (AssignmentStatus::TimedOut, 85, "TIMED OUT"),
+];
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StatusCodeEntry {
+    status: AssignmentStatus,
+    code: u8,
+    label: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AssignmentStatusCode {
+    reference: String,
+    code: u8,
+    label: String,
+    changed_at: u64,
+}
+
+thread_local! {
+    // reference (without check digit) -> assignment id
+    static REFERENCES: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(43)))
+    ));
+
+    // assignment id -> reference (without check digit)
+    static ASSIGNMENT_REFERENCES: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(44)))
+    ));
+}
+
+fn status_entry(status: AssignmentStatus) -> (u8, &'static str) {
+    STATUS_CODES
+        .iter()
+        .find(|(candidate, _, _)| *candidate == status)
+        .map(|(_, code, label)| (*code, *label))
+        .unwrap_or((0, "UNKNOWN"))
+}
+
+fn luhn_check_digit(number: u64) -> u64 {
+    let sum: u64 = format!("{:06}", number)
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(position, digit)| {
+            let digit = (digit - b'0') as u64;
+            if position % 2 == 0 {
+                let doubled = digit * 2;
+                doubled / 10 + doubled % 10
+            } else {
+                digit
+            }
+        })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+fn format_reference(number: u64) -> String {
+    format!("{:06}{}", number, luhn_check_digit(number))
+}
+
+// Function to parse a typed reference, ignoring spaces and dashes; None when
+// it is malformed or the check digit does not match
+fn parse_reference(reference: &str) -> Option<u64> {
+    let digits: String = reference
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect();
+    if digits.len() != 7 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let number: u64 = digits[..6].parse().ok()?;
+    let check: u64 = digits[6..].parse().ok()?;
+    (luhn_check_digit(number) == check).then_some(number)
+}
+
+// Function to give an assignment its short reference, reusing it if one was issued before
+pub(crate) fn issue_reference(assignment_id: u64) -> String {
+    if let Some(number) = ASSIGNMENT_REFERENCES.with(|storage| storage.borrow().get(&assignment_id))
+    {
+        return format_reference(number);
+    }
+
+    // References only need to be hard to guess in bulk, not secret, so they
+    // are drawn from a hash instead of an async randomness call
+    let seed = Sha256::digest([assignment_id.to_be_bytes(), time().to_be_bytes()].concat());
+    let mut number = u64::from_be_bytes(seed[..8].try_into().unwrap()) % REFERENCE_DIGITS;
+    while REFERENCES.with(|storage| storage.borrow().contains_key(&number)) {
+        number = (number + 1) % REFERENCE_DIGITS;
+    }
+
+    REFERENCES.with(|storage| storage.borrow_mut().insert(number, assignment_id));
+    ASSIGNMENT_REFERENCES.with(|storage| storage.borrow_mut().insert(assignment_id, number));
+    format_reference(number)
+}
+
+// Function for the driver, the receiver or governance to get the short reference
+// of an assignment; assignments created before references existed get one now
+#[ic_cdk::update]
+fn get_assignment_reference(assignment_id: u64) -> Result<String, FoodShareError> {
+    start_trace("get_assignment_reference");
+
+    let assignment = ASSIGNMENTS_STORAGE
+        .with(|storage| storage.borrow().get(&assignment_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Assignment ID does not exist".to_string(),
+        })?;
+    if !is_driver_owner(&assignment)
+        && !is_receiver_owner(&assignment)
+        && !is_governance_or_admin(&ic_cdk::caller())
+    {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the driver, the receiver or governance can get this reference".to_string(),
+        });
+    }
+
+    let reference = issue_reference(assignment_id);
+    log(
+        LogLevel::Info,
+        format!("Issued reference for assignment {}", assignment_id),
+    );
+
+    Ok(reference)
+}
+
+// Function for SMS/USSD gateways to look up the numeric status of an assignment by its short reference
+#[ic_cdk::query]
+fn get_assignment_status_code(short_code: String) -> Result<AssignmentStatusCode, FoodShareError> {
+    let number = parse_reference(&short_code).ok_or(FoodShareError::Validation {
+        field: "short_code".to_string(),
+        reason: "Reference must be 7 digits with a valid check digit".to_string(),
+    })?;
+    let assignment = REFERENCES
+        .with(|storage| storage.borrow().get(&number))
+        .and_then(|assignment_id| {
+            ASSIGNMENTS_STORAGE.with(|storage| storage.borrow().get(&assignment_id))
+        })
+        .ok_or(FoodShareError::NotFound {
+            msg: "Reference not found".to_string(),
+        })?;
+
+    let (code, label) = status_entry(assignment.status);
+    Ok(AssignmentStatusCode {
+        reference: format_reference(number),
+        code,
+        label: label.to_string(),
+        changed_at: assignment
+            .status_history
+            .last()
+            .map(|change| change.changed_at)
+            .unwrap_or(assignment.created_at),
+    })
+}
+
+// Function to get the full table of numeric status codes for gateway menus
+#[ic_cdk::query]
+fn get_status_code_table() -> Vec<StatusCodeEntry> {
+    STATUS_CODES
+        .iter()
+        .map(|(status, code, label)| StatusCodeEntry {
+            status: *status,
+            code: *code,
+            label: label.to_string(),
+        })
+        .collect()
+}
@@ -0,0 +1,492 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::require_admin;
+use crate::observability::{log, start_timer_trace, start_trace, LogLevel};
+use crate::{next_id, FoodShareError, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::Memory as _;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const WASM_PAGE_BYTES: u64 = 64 * 1024;
+const USAGE_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const WEEK_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+const GIB: u64 = 1024 * 1024 * 1024;
+
+// Every stable memory the canister uses, by memory id
+const COLLECTIONS: &[(&str, u8)] = &[
+    ("id_counter", 0),
+    ("legacy_donors", 1),
+    ("legacy_receivers", 2),
+    ("legacy_drivers", 3),
+    ("surplus_posts", 4),
+    ("assignments", 5),
+    ("surplus_records", 6),
+    ("logs", 7),
+    ("governance_config", 8),
+    ("decision_cache", 9),
+    ("cache_counters", 10),
+    ("events", 11),
+    ("food_requests", 12),
+    ("group_requests", 13),
+    ("open_post_summaries", 14),
+    ("reservation_stats", 15),
+    ("hold_policy", 16),
+    ("api_tokens", 17),
+    ("support_consents", 18),
+    ("impersonation_sessions", 19),
+    ("tickets", 20),
+    ("emissions_config", 21),
+    ("emissions_stats", 22),
+    ("category_pauses", 23),
+    ("commitments", 24),
+    ("post_questions", 25),
+    ("research_consents", 26),
+    ("extract_log", 27),
+    ("matches", 28),
+    ("frontend_target", 29),
+    ("max_page_size", 30),
+    ("report_anchors", 31),
+    ("assignments_by_post", 32),
+    ("assignments_by_driver", 33),
+    ("payouts", 34),
+    ("reward_config", 35),
+    ("state_snapshot", 36),
+    ("storage_alert_config", 37),
+    ("storage_usage", 38),
+    ("storage_alerts", 39),
+    ("waste_stats", 40),
+    ("maintenance_jobs", 41),
+    ("transparency_cache", 42),
+    ("references", 43),
+    ("assignment_references", 44),
+    ("driver_availability", 45),
+    ("completeness_config", 46),
+    ("region_transfers", 47),
+    ("notifications", 48),
+    ("audit_events", 49),
+    ("radius_expansion_config", 50),
+    ("post_reach", 51),
+    ("driver_reputation", 52),
+    ("donor_reputation", 53),
+    ("recurring_templates", 54),
+    ("standing_commitments", 55),
+    ("reviews", 56),
+    ("assignment_messages", 57),
+    ("contact_vault", 58),
+    ("contact_access_log", 59),
+    ("delivery_proofs", 60),
+    ("delivery_confirmation_config", 61),
+    ("assets", 62),
+    ("asset_chunks", 63),
+    ("disputes", 64),
+    ("post_mortem_config", 65),
+    ("post_mortems", 66),
+    ("categories", 67),
+    ("organizations", 68),
+    ("category_labels", 69),
+    ("notification_templates", 70),
+    ("preferred_locales", 71),
+    ("impact_factors", 72),
+    ("global_impact", 73),
+    ("donor_impact", 74),
+    ("daily_impact", 75),
+    ("active_donors", 76),
+    ("active_receivers", 77),
+    ("dropoff_checklists", 78),
+    ("completed_checklists", 79),
+    ("pickup_confirmation_config", 80),
+    ("pickup_confirmations", 81),
+    ("donor_pickup_counts", 82),
+    ("regions", 83),
+    ("claim_config", 84),
+    ("claims", 85),
+    ("claims_by_post", 86),
+    ("driver_job_releases", 87),
+    ("donor_leads", 88),
+    ("lead_intake_config", 89),
+    ("lead_intake_window", 90),
+    ("routes", 91),
+    ("cold_chain_config", 92),
+    ("temperature_logs", 93),
+    ("verification_requests", 94),
+    ("verification_config", 95),
+    ("rate_limit_config", 96),
+    ("call_buckets", 97),
+    ("user_activity", 98),
+    ("settings", 99),
+    ("assignments_by_status", 100),
+    ("posts_by_donor", 101),
+    ("open_posts_by_food_type", 102),
+    ("id_sequences", 103),
+    ("hold_sweep_metrics", 104),
+    ("quantity_reservations", 105),
+    ("reservations_by_post", 106),
+    ("geocoding_config", 107),
+    ("geocode_cache", 108),
+    ("geocoding_stats", 109),
+    ("webhooks", 110),
+    ("webhook_deliveries", 111),
+    ("thread_read_markers", 112),
+    ("announcements", 113),
+    ("delivery_trails", 114),
+    ("cancellation_policy", 115),
+    ("cancellation_records", 116),
+    ("driver_standings", 117),
+    ("admins", 118),
+    ("logs_by_trace", 119),
+    ("events_by_assignment", 120),
+    ("post_events", 121),
+    ("messages_by_assignment", 122),
+    ("reviews_by_assignment", 123),
+    ("donors", 124),
+    ("receivers", 125),
+    ("drivers", 126),
+    ("pending_webhook_deliveries", 127),
+    ("finished_webhook_deliveries", 128),
+    ("webhook_deliveries_by_webhook", 129),
+    ("food_request_posts", 130),
+    ("assignments_by_receiver", 131),
+    ("profile_emails", 132),
+    ("contact_access_by_profile", 133),
+    ("records_by_assignment", 134),
+];
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CollectionUsage {
+    collection: String,
+    memory_id: u8,
+    bytes: u64,
+    // Size at the start of the current week, if a week has been recorded yet
+    baseline_bytes: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StorageStats {
+    collections: Vec<CollectionUsage>,
+    total_bytes: u64,
+    measured_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StorageAlertConfig {
+    // Alert when a collection grows by more than this percentage over a week
+    weekly_growth_percent: u32,
+    // Alert when a single collection crosses this size
+    collection_threshold_bytes: u64,
+    // Alert when all collections together cross this size
+    total_threshold_bytes: u64,
+}
+
+impl Default for StorageAlertConfig {
+    fn default() -> Self {
+        Self {
+            weekly_growth_percent: 50,
+            collection_threshold_bytes: 4 * GIB,
+            total_threshold_bytes: 64 * GIB,
+        }
+    }
+}
+
+impl Storable for StorageAlertConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Sizes remembered between checks: the start of the current week for growth
+// alerts and the last check for threshold crossings
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct StorageUsage {
+    baseline_at: u64,
+    baseline: Vec<(u8, u64)>,
+    last: Vec<(u8, u64)>,
+}
+
+impl Storable for StorageUsage {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// StorageAlertKind is a custom enum type that is used to represent why a storage alert was raised
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum StorageAlertKind {
+    WeeklyGrowth { percent: u64 },
+    ThresholdCrossed { threshold_bytes: u64 },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StorageAlert {
+    id: u64,
+    // "total" for alerts on all collections together
+    collection: String,
+    kind: StorageAlertKind,
+    bytes: u64,
+    raised_at: u64,
+    acknowledged: bool,
+}
+
+impl Storable for StorageAlert {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for StorageAlert {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static STORAGE_ALERT_CONFIG: RefCell<Cell<StorageAlertConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37))),
+            StorageAlertConfig::default(),
+        )
+        .expect("Cannot create the storage alert config")
+    );
+
+    static STORAGE_USAGE: RefCell<Cell<StorageUsage, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38))),
+            StorageUsage::default(),
+        )
+        .expect("Cannot create the storage usage")
+    );
+
+    static STORAGE_ALERTS: RefCell<StableBTreeMap<u64, StorageAlert, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(39)))
+    ));
+}
+
+fn alert_config() -> StorageAlertConfig {
+    STORAGE_ALERT_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+fn collection_bytes(memory_id: u8) -> u64 {
+    MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(memory_id)).size()) * WASM_PAGE_BYTES
+}
+
+fn bytes_for(sizes: &[(u8, u64)], memory_id: u8) -> Option<u64> {
+    sizes
+        .iter()
+        .find(|(id, _)| *id == memory_id)
+        .map(|(_, bytes)| *bytes)
+}
+
+fn raise_alert(collection: &str, kind: StorageAlertKind, bytes: u64) {
+    let id = next_id();
+    let alert = StorageAlert {
+        id,
+        collection: collection.to_string(),
+        kind,
+        bytes,
+        raised_at: time(),
+        acknowledged: false,
+    };
+    STORAGE_ALERTS.with(|storage| storage.borrow_mut().insert(id, alert));
+    log(
+        LogLevel::Warn,
+        format!(
+            "Storage alert for {}: {:?} at {} bytes",
+            collection, kind, bytes
+        ),
+    );
+}
+
+// Function to register the daily storage usage check
+pub(crate) fn start_usage_check() {
+    ic_cdk_timers::set_timer_interval(USAGE_CHECK_INTERVAL, check_storage_usage);
+}
+
+// Function to measure every collection, raise alerts for threshold crossings
+// since the last check and, once a week, for week-over-week growth
+fn check_storage_usage() {
+    start_timer_trace("check_storage_usage");
+
+    let config = alert_config();
+    let mut usage = STORAGE_USAGE.with(|cell| cell.borrow().get().clone());
+    let now = time();
+    let current: Vec<(u8, u64)> = COLLECTIONS
+        .iter()
+        .map(|(_, memory_id)| (*memory_id, collection_bytes(*memory_id)))
+        .collect();
+    let week_complete =
+        !usage.baseline.is_empty() && now.saturating_sub(usage.baseline_at) >= WEEK_NANOS;
+
+    for (name, memory_id) in COLLECTIONS {
+        let bytes = bytes_for(&current, *memory_id).unwrap_or_default();
+        let last = bytes_for(&usage.last, *memory_id).unwrap_or_default();
+        if last < config.collection_threshold_bytes && bytes >= config.collection_threshold_bytes {
+            raise_alert(
+                name,
+                StorageAlertKind::ThresholdCrossed {
+                    threshold_bytes: config.collection_threshold_bytes,
+                },
+                bytes,
+            );
+        }
+
+        if !week_complete {
+            continue;
+        }
+        let Some(baseline) = bytes_for(&usage.baseline, *memory_id).filter(|b| *b > 0) else {
+            continue;
+        };
+        let percent = bytes.saturating_sub(baseline) * 100 / baseline;
+        if percent > config.weekly_growth_percent as u64 {
+            raise_alert(name, StorageAlertKind::WeeklyGrowth { percent }, bytes);
+        }
+    }
+
+    let total: u64 = current.iter().map(|(_, bytes)| bytes).sum();
+    let last_total: u64 = usage.last.iter().map(|(_, bytes)| bytes).sum();
+    if last_total < config.total_threshold_bytes && total >= config.total_threshold_bytes {
+        raise_alert(
+            "total",
+            StorageAlertKind::ThresholdCrossed {
+                threshold_bytes: config.total_threshold_bytes,
+            },
+            total,
+        );
+    }
+
+    if usage.baseline.is_empty() || week_complete {
+        usage.baseline = current.clone();
+        usage.baseline_at = now;
+    }
+    usage.last = current;
+    STORAGE_USAGE
+        .with(|cell| cell.borrow_mut().set(usage))
+        .expect("Cannot update the storage usage");
+}
+
+// Function to get the stable memory used by each collection
+#[ic_cdk::query]
+fn get_storage_stats() -> StorageStats {
+    let usage = STORAGE_USAGE.with(|cell| cell.borrow().get().clone());
+    let collections: Vec<CollectionUsage> = COLLECTIONS
+        .iter()
+        .map(|(name, memory_id)| CollectionUsage {
+            collection: name.to_string(),
+            memory_id: *memory_id,
+            bytes: collection_bytes(*memory_id),
+            baseline_bytes: bytes_for(&usage.baseline, *memory_id),
+        })
+        .collect();
+
+    StorageStats {
+        total_bytes: collections.iter().map(|usage| usage.bytes).sum(),
+        collections,
+        measured_at: time(),
+    }
+}
+
+// Function for admins to get the storage alerts, optionally only unacknowledged ones
+#[ic_cdk::query]
+fn get_storage_alerts(unacknowledged_only: bool) -> Result<Vec<StorageAlert>, FoodShareError> {
+    require_admin("view storage alerts")?;
+
+    Ok(STORAGE_ALERTS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, alert)| alert)
+            .filter(|alert| !unacknowledged_only || !alert.acknowledged)
+            .collect()
+    }))
+}
+
+// Function for admins to acknowledge a storage alert
+#[ic_cdk::update]
+fn acknowledge_storage_alert(alert_id: u64) -> Result<StorageAlert, FoodShareError> {
+    start_trace("acknowledge_storage_alert");
+    require_admin("acknowledge storage alerts")?;
+
+    let mut alert = STORAGE_ALERTS
+        .with(|storage| storage.borrow().get(&alert_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Storage alert with id={} not found", alert_id),
+        })?;
+    let before = alert.clone();
+    alert.acknowledged = true;
+    STORAGE_ALERTS.with(|storage| storage.borrow_mut().insert(alert_id, alert.clone()));
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::StorageAlert,
+        alert_id,
+        Some(&before),
+        Some(&alert),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Acknowledged storage alert {}", alert_id),
+    );
+
+    Ok(alert)
+}
+
+// Function to get the growth and size limits that raise storage alerts
+#[ic_cdk::query]
+fn get_storage_alert_config() -> StorageAlertConfig {
+    alert_config()
+}
+
+// Function for admins to change the growth and size limits that raise storage alerts
+#[ic_cdk::update]
+fn set_storage_alert_config(
+    config: StorageAlertConfig,
+) -> Result<StorageAlertConfig, FoodShareError> {
+    start_trace("set_storage_alert_config");
+    require_admin("change the storage alert config")?;
+
+    if config.weekly_growth_percent == 0
+        || config.collection_threshold_bytes == 0
+        || config.total_threshold_bytes == 0
+    {
+        return Err(FoodShareError::Validation {
+            field: "config".to_string(),
+            reason: "Growth percentage and thresholds must be greater than zero".to_string(),
+        });
+    }
+
+    let before = STORAGE_ALERT_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the storage alert config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::StorageAlertConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Set storage alerts to {}% weekly growth, {} bytes per collection, {} bytes total",
+            config.weekly_growth_percent,
+            config.collection_threshold_bytes,
+            config.total_threshold_bytes
+        ),
+    );
+
+    Ok(config)
+}
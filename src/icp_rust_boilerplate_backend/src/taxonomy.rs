@@ -0,0 +1,452 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::governance::require_admin;
+use crate::indexes::store_post;
+use crate::localization::category_label;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{
+    next_id, BusinessType, DonorProfile, FoodShareError, FoodType, Memory, SurplusPost,
+    DONORS_STORAGE, MEMORY_MANAGER, SURPLUS_POSTS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const MAX_LABEL_LEN: usize = 64;
+
+// Enum values seeded as built-in categories, so records using them keep a category
+const FOOD_TYPES: [FoodType; 8] = [
+    FoodType::Vegetables,
+    FoodType::Fruits,
+    FoodType::Dairy,
+    FoodType::Meat,
+    FoodType::Grains,
+    FoodType::Bakery,
+    FoodType::Beverages,
+    FoodType::Other,
+];
+const BUSINESS_TYPES: [BusinessType; 4] = [
+    BusinessType::Restaurant,
+    BusinessType::Grocery,
+    BusinessType::Bakery,
+    BusinessType::Other,
+];
+
+// TaxonomyKind is a custom enum type that is used to represent which taxonomy a category belongs to
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum TaxonomyKind {
+    BusinessType,
+    FoodCategory,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Category {
+    id: u64,
+    kind: TaxonomyKind,
+    label: String,
+    parent_id: Option<u64>,
+    // Inactive categories stay on the records using them but cannot be picked again
+    active: bool,
+    // Enum value a built-in category stands for; None for categories admins added
+    builtin: Option<String>,
+    created_at: u64,
+    updated_at: u64,
+}
+
+impl Storable for Category {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Category {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Category Payload
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct CategoryPayload {
+    kind: TaxonomyKind,
+    label: String,
+    // Custom categories without a parent are filed under Other for the enum fields
+    parent_id: Option<u64>,
+}
+
+// Update Category Payload; fields left as None keep their current value
+#[derive(candid::CandidType, Deserialize, Serialize)]
+pub(crate) struct UpdateCategoryPayload {
+    label: Option<String>,
+    parent_id: Option<u64>,
+    active: Option<bool>,
+}
+
+thread_local! {
+    static CATEGORIES: RefCell<StableBTreeMap<u64, Category, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(67)))
+    ));
+}
+
+pub(crate) fn load_category(category_id: u64) -> Result<Category, FoodShareError> {
+    CATEGORIES
+        .with(|storage| storage.borrow().get(&category_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Category with id={} not found", category_id),
+        })
+}
+
+fn builtin_category(kind: TaxonomyKind, variant: &str) -> Option<u64> {
+    CATEGORIES.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .find(|(_, category)| {
+                category.kind == kind && category.builtin.as_deref() == Some(variant)
+            })
+            .map(|(id, _)| id)
+    })
+}
+
+// Function to get the built-in category a food type maps to
+pub(crate) fn builtin_food_category(food_type: FoodType) -> Option<u64> {
+    builtin_category(TaxonomyKind::FoodCategory, &format!("{:?}", food_type))
+}
+
+// Function to get the built-in category a business type maps to
+pub(crate) fn builtin_business_type(business_type: BusinessType) -> Option<u64> {
+    builtin_category(TaxonomyKind::BusinessType, &format!("{:?}", business_type))
+}
+
+// Function to get the enum value of the nearest built-in ancestor of a category,
+// or None when the category sits outside every built-in one
+fn builtin_ancestor(category: &Category) -> Option<String> {
+    let mut current = category.clone();
+    loop {
+        if let Some(variant) = current.builtin {
+            return Some(variant);
+        }
+        current = load_category(current.parent_id?).ok()?;
+    }
+}
+
+// Function to check that a category can be picked for a record of a taxonomy
+fn usable_category(
+    kind: TaxonomyKind,
+    category_id: u64,
+    field: &str,
+) -> Result<Category, FoodShareError> {
+    let category = load_category(category_id)?;
+    if category.kind != kind {
+        return Err(FoodShareError::Validation {
+            field: field.to_string(),
+            reason: format!("Category {} is not a {:?}", category_id, kind),
+        });
+    }
+    if !category.active {
+        return Err(FoodShareError::Validation {
+            field: field.to_string(),
+            reason: format!("Category {} is no longer in use", category_id),
+        });
+    }
+    Ok(category)
+}
+
+// Function to get the food type to store alongside a food category, so clients
+// reading only the enum still see the closest match
+pub(crate) fn food_type_for(category_id: u64) -> Result<FoodType, FoodShareError> {
+    let category = usable_category(TaxonomyKind::FoodCategory, category_id, "food_category_id")?;
+    let variant = builtin_ancestor(&category);
+    Ok(FOOD_TYPES
+        .into_iter()
+        .find(|food_type| Some(format!("{:?}", food_type)) == variant)
+        .unwrap_or(FoodType::Other))
+}
+
+// Function to get the business type to store alongside a business category
+pub(crate) fn business_type_for(category_id: u64) -> Result<BusinessType, FoodShareError> {
+    let category = usable_category(TaxonomyKind::BusinessType, category_id, "business_type_id")?;
+    let variant = builtin_ancestor(&category);
+    Ok(BUSINESS_TYPES
+        .into_iter()
+        .find(|business_type| Some(format!("{:?}", business_type)) == variant)
+        .unwrap_or(BusinessType::Other))
+}
+
+// Function to check whether a category is the given one or filed under it
+fn is_within(category_id: u64, ancestor_id: u64) -> bool {
+    let mut current = Some(category_id);
+    while let Some(id) = current {
+        if id == ancestor_id {
+            return true;
+        }
+        current = CATEGORIES
+            .with(|storage| storage.borrow().get(&id))
+            .and_then(|category| category.parent_id);
+    }
+    false
+}
+
+fn validate_label(
+    kind: TaxonomyKind,
+    label: &str,
+    except: Option<u64>,
+) -> Result<(), FoodShareError> {
+    if label.is_empty() || label.chars().count() > MAX_LABEL_LEN {
+        return Err(FoodShareError::Validation {
+            field: "label".to_string(),
+            reason: format!("Label must be between 1 and {} characters", MAX_LABEL_LEN),
+        });
+    }
+    let taken = CATEGORIES.with(|storage| {
+        storage.borrow().iter().any(|(id, category)| {
+            Some(id) != except
+                && category.kind == kind
+                && category.label.eq_ignore_ascii_case(label)
+        })
+    });
+    if taken {
+        return Err(FoodShareError::Conflict {
+            msg: format!("A {:?} labelled {} already exists", kind, label),
+        });
+    }
+    Ok(())
+}
+
+fn store_category(before: Option<&Category>, category: &Category) {
+    CATEGORIES.with(|storage| storage.borrow_mut().insert(category.id, category.clone()));
+    audit(
+        match before {
+            Some(_) => AuditAction::Updated,
+            None => AuditAction::Created,
+        },
+        AuditEntity::Category,
+        category.id,
+        before,
+        Some(category),
+    );
+}
+
+// Function to seed a built-in category for every enum value that has none yet.
+// Returns whether any was added.
+fn seed_builtin_categories() -> bool {
+    let now = time();
+    let variants = FOOD_TYPES
+        .iter()
+        .map(|food_type| (TaxonomyKind::FoodCategory, format!("{:?}", food_type)))
+        .chain(
+            BUSINESS_TYPES
+                .iter()
+                .map(|business_type| (TaxonomyKind::BusinessType, format!("{:?}", business_type))),
+        );
+    let mut seeded = false;
+    for (kind, variant) in variants {
+        if builtin_category(kind, &variant).is_some() {
+            continue;
+        }
+        let category = Category {
+            id: next_id(),
+            kind,
+            label: variant.clone(),
+            parent_id: None,
+            active: true,
+            builtin: Some(variant),
+            created_at: now,
+            updated_at: now,
+        };
+        CATEGORIES.with(|storage| storage.borrow_mut().insert(category.id, category));
+        seeded = true;
+    }
+    seeded
+}
+
+// Function to seed the taxonomies from the enum values and point posts and
+// donors created before them at the matching built-in category. Runs on every
+// install and upgrade but only migrates records while seeding.
+pub(crate) fn migrate_taxonomy() {
+    if !seed_builtin_categories() {
+        return;
+    }
+
+    let posts: Vec<SurplusPost> = SURPLUS_POSTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, post)| post)
+            .filter(|post| post.food_category_id.is_none())
+            .collect()
+    });
+    for mut post in posts {
+        post.food_category_id = builtin_food_category(post.food_type);
+        store_post(&post);
+    }
+
+    let donors: Vec<DonorProfile> = DONORS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, donor)| donor)
+            .filter(|donor| donor.business_type_id.is_none())
+            .collect()
+    });
+    for mut donor in donors {
+        donor.business_type_id = builtin_business_type(donor.business_type);
+        DONORS_STORAGE.with(|storage| storage.borrow_mut().insert(donor.id, donor));
+    }
+}
+
+// Function for admins to add a category to a taxonomy, optionally under a parent
+#[ic_cdk::update]
+fn add_category(payload: CategoryPayload) -> Result<Category, FoodShareError> {
+    start_trace("add_category");
+    require_admin("add categories")?;
+
+    let label = payload.label.trim().to_string();
+    validate_label(payload.kind, &label, None)?;
+    if let Some(parent_id) = payload.parent_id {
+        usable_category(payload.kind, parent_id, "parent_id")?;
+    }
+
+    let now = time();
+    let category = Category {
+        id: next_id(),
+        kind: payload.kind,
+        label,
+        parent_id: payload.parent_id,
+        active: true,
+        builtin: None,
+        created_at: now,
+        updated_at: now,
+    };
+    store_category(None, &category);
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Added {:?} category {} ({})",
+            category.kind, category.id, category.label
+        ),
+    );
+
+    Ok(category)
+}
+
+// Function for admins to rename, move or retire a category. Built-in categories
+// can be renamed but stay at the top level and in use, since enum values map to them.
+#[ic_cdk::update]
+fn update_category(
+    category_id: u64,
+    payload: UpdateCategoryPayload,
+) -> Result<Category, FoodShareError> {
+    start_trace("update_category");
+    require_admin("update categories")?;
+
+    let before = load_category(category_id)?;
+    let mut category = before.clone();
+
+    if let Some(label) = payload.label {
+        let label = label.trim().to_string();
+        validate_label(category.kind, &label, Some(category_id))?;
+        category.label = label;
+    }
+
+    if let Some(parent_id) = payload.parent_id {
+        if category.builtin.is_some() {
+            return Err(FoodShareError::Conflict {
+                msg: "Built-in categories cannot be moved".to_string(),
+            });
+        }
+        usable_category(category.kind, parent_id, "parent_id")?;
+        if is_within(parent_id, category_id) {
+            return Err(FoodShareError::Validation {
+                field: "parent_id".to_string(),
+                reason: "A category cannot be filed under itself or its subcategories".to_string(),
+            });
+        }
+        category.parent_id = Some(parent_id);
+    }
+
+    if let Some(active) = payload.active {
+        if !active && category.builtin.is_some() {
+            return Err(FoodShareError::Conflict {
+                msg: "Built-in categories cannot be retired".to_string(),
+            });
+        }
+        category.active = active;
+    }
+
+    category.updated_at = time();
+    store_category(Some(&before), &category);
+
+    log(
+        LogLevel::Info,
+        format!("Updated {:?} category {}", category.kind, category_id),
+    );
+
+    Ok(category)
+}
+
+// Function to get the categories of a taxonomy, in creation order. With a
+// locale, labels translated into it replace the default ones.
+#[ic_cdk::query]
+fn get_categories(
+    kind: TaxonomyKind,
+    include_inactive: bool,
+    locale: Option<String>,
+) -> Vec<Category> {
+    CATEGORIES.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, category)| category)
+            .filter(|category| category.kind == kind)
+            .filter(|category| include_inactive || category.active)
+            .map(|mut category| {
+                if let Some(label) = locale
+                    .as_deref()
+                    .and_then(|locale| category_label(category.id, locale))
+                {
+                    category.label = label;
+                }
+                category
+            })
+            .collect()
+    })
+}
+
+// Function to get the surplus posts in a food category or any of its subcategories
+#[ic_cdk::query]
+fn get_surplus_posts_by_category(category_id: u64) -> Result<Vec<SurplusPost>, FoodShareError> {
+    let category = load_category(category_id)?;
+    if category.kind != TaxonomyKind::FoodCategory {
+        return Err(FoodShareError::Validation {
+            field: "category_id".to_string(),
+            reason: format!("Category {} is not a food category", category_id),
+        });
+    }
+
+    let posts: Vec<SurplusPost> = SURPLUS_POSTS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, post)| post)
+            .filter(|post| {
+                post.food_category_id
+                    .is_some_and(|id| is_within(id, category_id))
+            })
+            .collect()
+    });
+
+    if posts.is_empty() {
+        return Err(FoodShareError::NotFound {
+            msg: "No surplus posts found.".to_string(),
+        });
+    }
+
+    Ok(posts)
+}
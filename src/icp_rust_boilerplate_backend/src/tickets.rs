@@ -0,0 +1,421 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::EntityType;
+use crate::governance::{is_admin, require_admin};
+use crate::observability::{log, start_timer_trace, start_trace, LogLevel};
+use crate::{
+    next_id, FoodShareError, Memory, ASSIGNMENTS_STORAGE, DONORS_STORAGE, DRIVERS_STORAGE,
+    FOOD_REQUESTS_STORAGE, MEMORY_MANAGER, RECEIVERS_STORAGE, SURPLUS_POSTS_STORAGE,
+    SURPLUS_RECORDS_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const SLA_SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+const MAX_MESSAGE_LEN: usize = 500;
+const MAX_REPLIES: usize = 20;
+
+// TicketCategory is a custom enum type that is used to represent what a support ticket is about
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum TicketCategory {
+    Delivery,
+    FoodQuality,
+    Account,
+    Other,
+}
+
+impl TicketCategory {
+    // Deliveries in flight need a faster first response than account questions
+    fn sla_secs(&self) -> (u64, u64) {
+        match self {
+            TicketCategory::Delivery => (60 * 60, 24 * 60 * 60),
+            TicketCategory::FoodQuality => (2 * 60 * 60, 48 * 60 * 60),
+            TicketCategory::Account | TicketCategory::Other => (24 * 60 * 60, 5 * 24 * 60 * 60),
+        }
+    }
+}
+
+// TicketStatus is a custom enum type that is used to represent where a ticket is in the support workflow
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum TicketStatus {
+    Open,
+    InProgress,
+    AwaitingUser,
+    Resolved,
+    Closed,
+}
+
+impl TicketStatus {
+    fn can_move_to(&self, next: TicketStatus) -> bool {
+        use TicketStatus::*;
+        matches!(
+            (self, next),
+            (Open, InProgress)
+                | (Open, Resolved)
+                | (InProgress, AwaitingUser)
+                | (InProgress, Resolved)
+                | (AwaitingUser, InProgress)
+                | (AwaitingUser, Resolved)
+                | (Resolved, InProgress)
+                | (Resolved, Closed)
+        )
+    }
+}
+
+// TicketEntityRef points a ticket at the post, assignment or profile it concerns
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct TicketEntityRef {
+    entity_type: EntityType,
+    entity_id: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TicketReply {
+    author: Principal,
+    from_staff: bool,
+    message: String,
+    created_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Ticket {
+    id: u64,
+    category: TicketCategory,
+    entity_ref: Option<TicketEntityRef>,
+    description: String,
+    opened_by: Principal,
+    status: TicketStatus,
+    replies: Vec<TicketReply>,
+    created_at: u64,
+    first_response_due_at: u64,
+    resolution_due_at: u64,
+    first_response_at: Option<u64>,
+    resolved_at: Option<u64>,
+    sla_breached: bool,
+}
+
+impl Storable for Ticket {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Ticket {
+    const MAX_SIZE: u32 = 16384;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static TICKETS_STORAGE: RefCell<StableBTreeMap<u64, Ticket, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+    ));
+}
+
+fn entity_exists(entity_ref: &TicketEntityRef) -> bool {
+    let id = &entity_ref.entity_id;
+    match entity_ref.entity_type {
+        EntityType::Donor => DONORS_STORAGE.with(|s| s.borrow().contains_key(id)),
+        EntityType::Receiver => RECEIVERS_STORAGE.with(|s| s.borrow().contains_key(id)),
+        EntityType::Driver => DRIVERS_STORAGE.with(|s| s.borrow().contains_key(id)),
+        EntityType::SurplusPost => SURPLUS_POSTS_STORAGE.with(|s| s.borrow().contains_key(id)),
+        EntityType::Assignment => ASSIGNMENTS_STORAGE.with(|s| s.borrow().contains_key(id)),
+        EntityType::SurplusRecord => SURPLUS_RECORDS_STORAGE.with(|s| s.borrow().contains_key(id)),
+        EntityType::FoodRequest => FOOD_REQUESTS_STORAGE.with(|s| s.borrow().contains_key(id)),
+    }
+}
+
+fn validate_message(message: &str) -> Result<(), FoodShareError> {
+    if message.trim().is_empty() || message.len() > MAX_MESSAGE_LEN {
+        return Err(FoodShareError::Validation {
+            field: "message".to_string(),
+            reason: format!("Message must be between 1 and {} bytes", MAX_MESSAGE_LEN),
+        });
+    }
+    Ok(())
+}
+
+// Function to load a ticket the caller may see: its opener or support staff
+fn visible_ticket(ticket_id: u64) -> Result<Ticket, FoodShareError> {
+    let ticket = TICKETS_STORAGE
+        .with(|storage| storage.borrow().get(&ticket_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Ticket with id={} not found", ticket_id),
+        })?;
+
+    let caller = ic_cdk::caller();
+    if ticket.opened_by != caller && !is_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the opener and support staff can access this ticket".to_string(),
+        });
+    }
+
+    Ok(ticket)
+}
+
+// Function to register the periodic sweep that flags tickets past their SLA
+pub(crate) fn start_sla_sweep() {
+    ic_cdk_timers::set_timer_interval(SLA_SWEEP_INTERVAL, sweep_sla_breaches);
+}
+
+fn sweep_sla_breaches() {
+    start_timer_trace("sweep_sla_breaches");
+
+    let now = time();
+    let breached: Vec<Ticket> = TICKETS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, ticket)| ticket)
+            .filter(|ticket| {
+                !ticket.sla_breached
+                    && ticket.resolved_at.is_none()
+                    && ((ticket.first_response_at.is_none() && ticket.first_response_due_at < now)
+                        || ticket.resolution_due_at < now)
+            })
+            .collect()
+    });
+
+    for mut ticket in breached {
+        ticket.sla_breached = true;
+        TICKETS_STORAGE.with(|storage| storage.borrow_mut().insert(ticket.id, ticket.clone()));
+        log(
+            LogLevel::Warn,
+            format!(
+                "Ticket {} ({:?}) breached its SLA",
+                ticket.id, ticket.category
+            ),
+        );
+    }
+}
+
+// Function to open a support ticket, optionally linked to an entity
+#[ic_cdk::update]
+fn open_ticket(
+    category: TicketCategory,
+    entity_ref: Option<TicketEntityRef>,
+    description: String,
+) -> Result<Ticket, FoodShareError> {
+    start_trace("open_ticket");
+
+    validate_message(&description)?;
+    if let Some(entity_ref) = &entity_ref {
+        if !entity_exists(entity_ref) {
+            return Err(FoodShareError::NotFound {
+                msg: format!(
+                    "{:?} with id={} does not exist",
+                    entity_ref.entity_type, entity_ref.entity_id
+                ),
+            });
+        }
+    }
+
+    let id = next_id();
+    let now = time();
+    let (response_secs, resolution_secs) = category.sla_secs();
+    let ticket = Ticket {
+        id,
+        category,
+        entity_ref,
+        description,
+        opened_by: ic_cdk::caller(),
+        status: TicketStatus::Open,
+        replies: Vec::new(),
+        created_at: now,
+        first_response_due_at: now + response_secs * NANOS_PER_SECOND,
+        resolution_due_at: now + resolution_secs * NANOS_PER_SECOND,
+        first_response_at: None,
+        resolved_at: None,
+        sla_breached: false,
+    };
+
+    TICKETS_STORAGE.with(|storage| storage.borrow_mut().insert(id, ticket.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::Ticket,
+        id,
+        None,
+        Some(&ticket),
+    );
+
+    log(LogLevel::Info, format!("Opened ticket {}", id));
+
+    Ok(ticket)
+}
+
+// Function to add a reply to a ticket thread, from the opener or support staff
+#[ic_cdk::update]
+fn reply_to_ticket(ticket_id: u64, message: String) -> Result<Ticket, FoodShareError> {
+    start_trace("reply_to_ticket");
+
+    let mut ticket = visible_ticket(ticket_id)?;
+    validate_message(&message)?;
+    if ticket.status == TicketStatus::Closed {
+        return Err(FoodShareError::Conflict {
+            msg: "Ticket is closed".to_string(),
+        });
+    }
+    if ticket.replies.len() >= MAX_REPLIES {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: "Ticket has reached the maximum number of replies".to_string(),
+        });
+    }
+
+    let before = ticket.clone();
+    let caller = ic_cdk::caller();
+    let from_staff = is_admin(&caller);
+    let now = time();
+    ticket.replies.push(TicketReply {
+        author: caller,
+        from_staff,
+        message,
+        created_at: now,
+    });
+
+    if from_staff {
+        ticket.first_response_at.get_or_insert(now);
+    } else if ticket.status == TicketStatus::AwaitingUser {
+        // The user answered, so the ticket is back with support
+        ticket.status = TicketStatus::InProgress;
+    }
+
+    TICKETS_STORAGE.with(|storage| storage.borrow_mut().insert(ticket_id, ticket.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::Ticket,
+        ticket_id,
+        Some(&before),
+        Some(&ticket),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Added reply {} to ticket {}",
+            ticket.replies.len(),
+            ticket_id
+        ),
+    );
+
+    Ok(ticket)
+}
+
+// Function for support staff to move a ticket through the status workflow
+#[ic_cdk::update]
+fn update_ticket_status(ticket_id: u64, status: TicketStatus) -> Result<Ticket, FoodShareError> {
+    start_trace("update_ticket_status");
+    require_admin("update ticket status")?;
+
+    let mut ticket = TICKETS_STORAGE
+        .with(|storage| storage.borrow().get(&ticket_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Ticket with id={} not found", ticket_id),
+        })?;
+
+    if !ticket.status.can_move_to(status) {
+        return Err(FoodShareError::Conflict {
+            msg: format!(
+                "Ticket cannot move from {:?} to {:?}",
+                ticket.status, status
+            ),
+        });
+    }
+
+    let before = ticket.clone();
+    let now = time();
+    match status {
+        TicketStatus::Resolved => ticket.resolved_at = Some(now),
+        TicketStatus::InProgress => {
+            ticket.first_response_at.get_or_insert(now);
+            ticket.resolved_at = None;
+        }
+        _ => {}
+    }
+    ticket.status = status;
+
+    TICKETS_STORAGE.with(|storage| storage.borrow_mut().insert(ticket_id, ticket.clone()));
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::Ticket,
+        ticket_id,
+        Some(&before),
+        Some(&ticket),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Moved ticket {} to {:?}", ticket_id, status),
+    );
+
+    Ok(ticket)
+}
+
+// Function to get a ticket with its reply thread
+#[ic_cdk::query]
+fn get_ticket(ticket_id: u64) -> Result<Ticket, FoodShareError> {
+    visible_ticket(ticket_id)
+}
+
+// Function to get the tickets the caller has opened
+#[ic_cdk::query]
+fn get_my_tickets() -> Vec<Ticket> {
+    let caller = ic_cdk::caller();
+    TICKETS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, ticket)| ticket.opened_by == caller)
+            .map(|(_, ticket)| ticket)
+            .collect()
+    })
+}
+
+// Function for coordinators to get every ticket linked to an entity
+#[ic_cdk::query]
+fn get_tickets_for_entity(
+    entity_type: EntityType,
+    entity_id: u64,
+) -> Result<Vec<Ticket>, FoodShareError> {
+    require_admin("list tickets")?;
+
+    let entity_ref = TicketEntityRef {
+        entity_type,
+        entity_id,
+    };
+    Ok(TICKETS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, ticket)| ticket.entity_ref == Some(entity_ref))
+            .map(|(_, ticket)| ticket)
+            .collect()
+    }))
+}
+
+// Function for support staff to get the unresolved tickets, breached SLAs first
+#[ic_cdk::query]
+fn get_open_tickets() -> Result<Vec<Ticket>, FoodShareError> {
+    require_admin("list tickets")?;
+
+    let mut tickets: Vec<Ticket> = TICKETS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, ticket)| {
+                !matches!(ticket.status, TicketStatus::Resolved | TicketStatus::Closed)
+            })
+            .map(|(_, ticket)| ticket)
+            .collect()
+    });
+    tickets.sort_by_key(|ticket| (!ticket.sla_breached, ticket.resolution_due_at));
+
+    Ok(tickets)
+}
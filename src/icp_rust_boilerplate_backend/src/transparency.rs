@@ -0,0 +1,332 @@
+use crate::api_tokens::to_hex;
+use crate::certification;
+use crate::observability::start_timer_trace;
+use crate::report_anchors;
+use crate::{
+    FoodShareError, Memory, DONORS_STORAGE, DRIVERS_STORAGE, MEMORY_MANAGER, SURPLUS_POSTS_STORAGE,
+    SURPLUS_RECORDS_STORAGE,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const WEEK_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+const TREND_WEEKS: u64 = 8;
+const ROUTE_PREFIX: &str = "/transparency/";
+const ROUTE_SUFFIX: &str = ".json";
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct WeeklySpoilage {
+    // Start of the 7-day window, counted back from when the stats were computed
+    week_start: u64,
+    delivered_kg: u64,
+    expired_kg: u64,
+    spoilage_rate_percent: u32,
+}
+
+// Aggregate, PII-free stats for one region; regions are the donors' and drivers' regions, lowercased
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct RegionTransparency {
+    region: String,
+    kg_rescued: u64,
+    // Active donors and drivers registered in the region
+    active_partners: u64,
+    // Oldest week first
+    spoilage_trend: Vec<WeeklySpoilage>,
+    computed_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct TransparencyCache {
+    // Sorted by region
+    regions: Vec<RegionTransparency>,
+}
+
+impl Storable for TransparencyCache {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Everything a client needs to check one region's stats against the certificate
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CertifiedRegionTransparency {
+    stats: RegionTransparency,
+    // Leaf hashes of every region in region order; the transparency root is their sha256
+    leaf_hashes: Vec<String>,
+    // Certified data is sha256(report chain head || transparency root)
+    chain_head: String,
+    certificate: Option<Vec<u8>>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+thread_local! {
+    static TRANSPARENCY_CACHE: RefCell<Cell<TransparencyCache, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(42))),
+            TransparencyCache::default(),
+        )
+        .expect("Cannot create the transparency cache")
+    );
+}
+
+fn normalize_region(region: &str) -> String {
+    region.trim().to_lowercase()
+}
+
+fn cached_regions() -> Vec<RegionTransparency> {
+    TRANSPARENCY_CACHE.with(|cell| cell.borrow().get().regions.clone())
+}
+
+// Function to hash one region's stats from a plain text form clients can rebuild:
+// region|kg_rescued|active_partners|computed_at then ;week_start:delivered_kg:expired_kg per week
+fn leaf_hash(stats: &RegionTransparency) -> Vec<u8> {
+    let mut leaf = format!(
+        "{}|{}|{}|{}",
+        stats.region, stats.kg_rescued, stats.active_partners, stats.computed_at
+    );
+    for week in &stats.spoilage_trend {
+        leaf.push_str(&format!(
+            ";{}:{}:{}",
+            week.week_start, week.delivered_kg, week.expired_kg
+        ));
+    }
+    Sha256::digest(leaf.as_bytes()).to_vec()
+}
+
+// Function to get the root over every region's leaf hash, as folded into the certified data
+pub(crate) fn transparency_root() -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for stats in cached_regions() {
+        hasher.update(leaf_hash(&stats));
+    }
+    hasher.finalize().to_vec()
+}
+
+// Function to register the hourly refresh; the first refresh runs right away
+// so stats are available again after an upgrade
+pub(crate) fn start_transparency_refresh() {
+    ic_cdk_timers::set_timer(Duration::ZERO, refresh_transparency);
+    ic_cdk_timers::set_timer_interval(REFRESH_INTERVAL, refresh_transparency);
+}
+
+fn week_bucket(now: u64, at: u64) -> Option<usize> {
+    let weeks_ago = now.checked_sub(at)? / WEEK_NANOS;
+    (weeks_ago < TREND_WEEKS).then(|| (TREND_WEEKS - 1 - weeks_ago) as usize)
+}
+
+fn region_entry<'a>(
+    regions: &'a mut BTreeMap<String, RegionTransparency>,
+    region: &str,
+    now: u64,
+) -> &'a mut RegionTransparency {
+    let region = normalize_region(region);
+    regions
+        .entry(region.clone())
+        .or_insert_with(|| RegionTransparency {
+            region,
+            spoilage_trend: (0..TREND_WEEKS)
+                .map(|week| WeeklySpoilage {
+                    week_start: now.saturating_sub((TREND_WEEKS - week) * WEEK_NANOS),
+                    ..Default::default()
+                })
+                .collect(),
+            computed_at: now,
+            ..Default::default()
+        })
+}
+
+// Function to recompute the stats of every region and certify the new root
+fn refresh_transparency() {
+    start_timer_trace("refresh_transparency");
+
+    let now = time();
+    let mut regions: BTreeMap<String, RegionTransparency> = BTreeMap::new();
+    let mut donor_regions: BTreeMap<String, String> = BTreeMap::new();
+
+    DONORS_STORAGE.with(|storage| {
+        for (id, donor) in storage.borrow().iter() {
+            if donor.region.trim().is_empty() {
+                continue;
+            }
+            let stats = region_entry(&mut regions, &donor.region, now);
+            if donor.active {
+                stats.active_partners += 1;
+            }
+            donor_regions.insert(id.to_string(), stats.region.clone());
+        }
+    });
+    DRIVERS_STORAGE.with(|storage| {
+        for (_, driver) in storage.borrow().iter() {
+            if driver.active && !driver.region.trim().is_empty() {
+                region_entry(&mut regions, &driver.region, now).active_partners += 1;
+            }
+        }
+    });
+
+    // Food is attributed to the region of the donor that posted it
+    SURPLUS_RECORDS_STORAGE.with(|storage| {
+        for (_, record) in storage.borrow().iter() {
+            let Some(post) =
+                SURPLUS_POSTS_STORAGE.with(|posts| posts.borrow().get(&record.surplus_post_id))
+            else {
+                continue;
+            };
+            let Some(stats) = donor_regions
+                .get(&post.donor_id)
+                .and_then(|region| regions.get_mut(region))
+            else {
+                continue;
+            };
+            stats.kg_rescued += post.quantity_kg as u64;
+            if let Some(week) = week_bucket(now, record.delivered_at) {
+                stats.spoilage_trend[week].delivered_kg += post.quantity_kg as u64;
+            }
+        }
+    });
+    SURPLUS_POSTS_STORAGE.with(|storage| {
+        for (_, post) in storage.borrow().iter() {
+            let Some(expired_at) = post.expired_at else {
+                continue;
+            };
+            let stats = donor_regions
+                .get(&post.donor_id)
+                .and_then(|region| regions.get_mut(region));
+            if let (Some(stats), Some(week)) = (stats, week_bucket(now, expired_at)) {
+                stats.spoilage_trend[week].expired_kg += post.quantity_kg as u64;
+            }
+        }
+    });
+
+    for stats in regions.values_mut() {
+        for week in &mut stats.spoilage_trend {
+            let handled_kg = week.delivered_kg + week.expired_kg;
+            week.spoilage_rate_percent = (week.expired_kg * 100)
+                .checked_div(handled_kg)
+                .unwrap_or_default() as u32;
+        }
+    }
+
+    let cache = TransparencyCache {
+        regions: regions.into_values().collect(),
+    };
+    TRANSPARENCY_CACHE
+        .with(|cell| cell.borrow_mut().set(cache))
+        .expect("Cannot update the transparency cache");
+    certification::certify();
+}
+
+// Function to get the regions that have public transparency stats
+#[ic_cdk::query]
+fn get_transparency_regions() -> Vec<String> {
+    cached_regions()
+        .into_iter()
+        .map(|stats| stats.region)
+        .collect()
+}
+
+// Function to get a region's public stats together with what is needed to verify them
+#[ic_cdk::query]
+fn get_region_transparency(region: String) -> Result<CertifiedRegionTransparency, FoodShareError> {
+    let region = normalize_region(&region);
+    let regions = cached_regions();
+    let stats = regions
+        .iter()
+        .find(|stats| stats.region == region)
+        .cloned()
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("No transparency stats for region {}", region),
+        })?;
+    let (chain_head, _) = report_anchors::chain_head();
+
+    Ok(CertifiedRegionTransparency {
+        stats,
+        leaf_hashes: regions
+            .iter()
+            .map(|stats| to_hex(&leaf_hash(stats)))
+            .collect(),
+        chain_head: to_hex(&chain_head),
+        certificate: ic_cdk::api::data_certificate(),
+    })
+}
+
+fn json_response(status_code: u16, body: Vec<u8>) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+        ],
+        body,
+    }
+}
+
+// Function to decode %XX escapes in a URL path segment, e.g. region names with spaces
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let escaped = std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if let Some(byte) = escaped {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Function to serve the public transparency pages: GET /transparency/{region}.json
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let path = request.url.split('?').next().unwrap_or_default();
+    let region = path
+        .strip_prefix(ROUTE_PREFIX)
+        .and_then(|rest| rest.strip_suffix(ROUTE_SUFFIX));
+
+    let (Some(region), true) = (region, request.method.eq_ignore_ascii_case("GET")) else {
+        return json_response(404, br#"{"error":"not found"}"#.to_vec());
+    };
+    let region = normalize_region(&percent_decode(region));
+    match cached_regions()
+        .into_iter()
+        .find(|stats| stats.region == region)
+    {
+        Some(stats) => json_response(
+            200,
+            serde_json::to_vec(&stats).expect("Cannot encode transparency stats"),
+        ),
+        None => json_response(404, br#"{"error":"unknown region"}"#.to_vec()),
+    }
+}
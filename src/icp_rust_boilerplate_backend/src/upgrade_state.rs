@@ -0,0 +1,88 @@
+use crate::frontend_notify::{pending_post_changes, restore_pending_post_changes, PostChange};
+use crate::observability::{restore_trace_sequence, trace_sequence};
+use crate::{Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{Cell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Version of the CanisterState layout written by pre_upgrade. Bump it when the
+// layout changes and add a migration from the previous version.
+const STATE_VERSION: u32 = 1;
+
+// Heap state that has to survive upgrades. Everything else already lives in
+// stable structures.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CanisterState {
+    pending_post_changes: Vec<PostChange>,
+    trace_sequence: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct StateSnapshot {
+    // 0 when no snapshot has been written
+    version: u32,
+    state: Vec<u8>,
+}
+
+impl Storable for StateSnapshot {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static STATE_SNAPSHOT: RefCell<Cell<StateSnapshot, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36))),
+            StateSnapshot::default(),
+        )
+        .expect("Cannot create the state snapshot")
+    );
+}
+
+// Function to bring a snapshot written by any earlier version up to the current layout
+fn migrate(snapshot: StateSnapshot) -> Result<CanisterState, String> {
+    match snapshot.version {
+        // Upgraded from a build without pre_upgrade, so there is no heap state to restore
+        0 => Ok(CanisterState::default()),
+        1 => Decode!(&snapshot.state, CanisterState).map_err(|e| e.to_string()),
+        version => Err(format!(
+            "Snapshot version {} is newer than supported version {}",
+            version, STATE_VERSION
+        )),
+    }
+}
+
+// Function to write the heap state to stable memory before an upgrade
+pub(crate) fn save_state() {
+    let state = CanisterState {
+        pending_post_changes: pending_post_changes(),
+        trace_sequence: trace_sequence(),
+    };
+    let snapshot = StateSnapshot {
+        version: STATE_VERSION,
+        state: Encode!(&state).expect("Cannot encode the canister state"),
+    };
+    STATE_SNAPSHOT
+        .with(|cell| cell.borrow_mut().set(snapshot))
+        .expect("Cannot write the state snapshot");
+}
+
+// Function to restore the heap state after an upgrade. The snapshot is cleared
+// afterwards so a later upgrade never restores it a second time.
+pub(crate) fn restore_state() {
+    let snapshot = STATE_SNAPSHOT.with(|cell| cell.borrow().get().clone());
+    let state = migrate(snapshot).unwrap_or_else(|e| ic_cdk::trap(&e));
+
+    restore_pending_post_changes(state.pending_post_changes);
+    restore_trace_sequence(state.trace_sequence);
+
+    STATE_SNAPSHOT
+        .with(|cell| cell.borrow_mut().set(StateSnapshot::default()))
+        .expect("Cannot clear the state snapshot");
+}
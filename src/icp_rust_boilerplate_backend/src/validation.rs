@@ -0,0 +1,199 @@
+use crate::contact_vault::MAX_EMAIL_LEN;
+use crate::settings::{default_country_code, phone_number_length};
+use crate::FoodShareError;
+
+const MAX_LOCAL_PART_LEN: usize = 64;
+const MAX_DOMAIN_LABEL_LEN: usize = 63;
+// E.164 numbers carry at most 15 digits including the country code
+const MIN_E164_DIGITS: usize = 8;
+const MAX_E164_DIGITS: usize = 15;
+// Characters people write between digits, e.g. "+1 (555) 010-0000"
+const PHONE_SEPARATORS: [char; 5] = [' ', '-', '.', '(', ')'];
+// Longest free-text profile fields, which keep a profile with sealed contact
+// details within its stored size
+pub(crate) const MAX_NAME_LEN: usize = 100;
+pub(crate) const MAX_ADDRESS_LEN: usize = 200;
+pub(crate) const MAX_REGION_LEN: usize = 64;
+// Longest food request notes; with them a request still fits its stored size
+pub(crate) const MAX_REQUEST_NOTES_LEN: usize = 256;
+
+fn invalid(field: &str, reason: &str) -> FoodShareError {
+    FoodShareError::Validation {
+        field: field.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+// Function to check that a text field is at most `max` bytes long
+pub(crate) fn check_max_len(field: &str, value: &str, max: usize) -> Result<(), FoodShareError> {
+    if value.len() > max {
+        return Err(FoodShareError::Validation {
+            field: field.to_string(),
+            reason: format!("{} cannot be longer than {} bytes", field, max),
+        });
+    }
+    Ok(())
+}
+
+// Function to check the free-text fields of a new profile against their maximum lengths
+pub(crate) fn check_profile_text(
+    name: &str,
+    address: &str,
+    region: &str,
+) -> Result<(), FoodShareError> {
+    check_max_len("name", name, MAX_NAME_LEN)?;
+    check_max_len("address", address, MAX_ADDRESS_LEN)?;
+    check_max_len("region", region, MAX_REGION_LEN)
+}
+
+fn is_local_part_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~-".contains(c)
+}
+
+// Function to check one dot-separated piece of a domain name
+fn check_domain_label(label: &str) -> Result<(), FoodShareError> {
+    if label.is_empty() || label.len() > MAX_DOMAIN_LABEL_LEN {
+        return Err(invalid(
+            "email",
+            "Each part of the email domain must be between 1 and 63 characters",
+        ));
+    }
+    if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(invalid(
+            "email",
+            "Email domain may only contain letters, digits, hyphens and dots",
+        ));
+    }
+    if label.starts_with('-') || label.ends_with('-') {
+        return Err(invalid(
+            "email",
+            "Parts of the email domain cannot start or end with a hyphen",
+        ));
+    }
+    Ok(())
+}
+
+// Function to check an email address and return it trimmed and lowercased, so
+// addresses that differ only in case are treated as the same. Quoted local
+// parts and IP address domains are not accepted.
+pub(crate) fn normalize_email(email: &str) -> Result<String, FoodShareError> {
+    let email = email.trim().to_ascii_lowercase();
+    if email.len() > MAX_EMAIL_LEN {
+        return Err(FoodShareError::Validation {
+            field: "email".to_string(),
+            reason: format!("Email cannot be longer than {} characters", MAX_EMAIL_LEN),
+        });
+    }
+    let Some((local, domain)) = email.split_once('@') else {
+        return Err(invalid("email", "Email must contain an @"));
+    };
+    if domain.contains('@') {
+        return Err(invalid("email", "Email must contain exactly one @"));
+    }
+
+    if local.is_empty() || local.len() > MAX_LOCAL_PART_LEN {
+        return Err(invalid(
+            "email",
+            "The part before the @ must be between 1 and 64 characters",
+        ));
+    }
+    if !local.chars().all(|c| c == '.' || is_local_part_char(c)) {
+        return Err(invalid(
+            "email",
+            "The part before the @ contains characters that are not allowed",
+        ));
+    }
+    if local.starts_with('.') || local.ends_with('.') || local.contains("..") {
+        return Err(invalid(
+            "email",
+            "Dots before the @ cannot come first, last or twice in a row",
+        ));
+    }
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        return Err(invalid(
+            "email",
+            "Email domain must include a top-level domain, e.g. example.org",
+        ));
+    }
+    for label in &labels {
+        check_domain_label(label)?;
+    }
+    let top_level = labels[labels.len() - 1];
+    if top_level.len() < 2 || !top_level.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(invalid(
+            "email",
+            "Top-level domain must be at least two letters",
+        ));
+    }
+
+    Ok(email)
+}
+
+// Function to check a phone number and return it in E.164 form, e.g.
+// "+254712345678". Numbers may start with + or 00 followed by the country
+// code; separators such as spaces, dashes and brackets are dropped. National
+// numbers must have the configured number of digits and get the default
+// country code in place of their leading trunk 0. Without a default country
+// code they are kept as national digits.
+pub(crate) fn normalize_phone_number(phone_number: &str) -> Result<String, FoodShareError> {
+    let compact: String = phone_number
+        .trim()
+        .chars()
+        .filter(|c| !PHONE_SEPARATORS.contains(c))
+        .collect();
+    if compact.is_empty() {
+        return Err(invalid("phone_number", "Phone number is required"));
+    }
+
+    let international = compact
+        .strip_prefix('+')
+        .or_else(|| compact.strip_prefix("00"));
+    let digits = international.unwrap_or(&compact);
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid(
+            "phone_number",
+            "Phone number may only contain digits after an optional leading +",
+        ));
+    }
+
+    if let Some(digits) = international {
+        if digits.starts_with('0') {
+            return Err(invalid("phone_number", "Country codes cannot start with 0"));
+        }
+        if !(MIN_E164_DIGITS..=MAX_E164_DIGITS).contains(&digits.len()) {
+            return Err(FoodShareError::Validation {
+                field: "phone_number".to_string(),
+                reason: format!(
+                    "International numbers must have between {} and {} digits including the country code",
+                    MIN_E164_DIGITS, MAX_E164_DIGITS
+                ),
+            });
+        }
+        return Ok(format!("+{}", digits));
+    }
+
+    let length = phone_number_length() as usize;
+    if digits.len() != length {
+        return Err(FoodShareError::Validation {
+            field: "phone_number".to_string(),
+            reason: format!(
+                "National numbers must be {} digits long; add the country code with a leading + otherwise",
+                length
+            ),
+        });
+    }
+    let Some(country_code) = default_country_code() else {
+        return Ok(digits.to_string());
+    };
+    let subscriber = digits.strip_prefix('0').unwrap_or(digits);
+    let e164 = format!("{}{}", country_code, subscriber);
+    if e164.len() > MAX_E164_DIGITS {
+        return Err(invalid(
+            "phone_number",
+            "Phone number is too long once the country code is added",
+        ));
+    }
+    Ok(format!("+{}", e164))
+}
@@ -0,0 +1,145 @@
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::cold_chain::requires_refrigeration;
+use crate::contact_vault::redacted;
+use crate::governance::{authorize, is_governance_or_admin, EndpointClass};
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{DriverProfile, FoodShareError, FoodType, VehicleType, DRIVERS_STORAGE};
+
+const MAX_LOAD_KG: u32 = 40_000;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Vehicle {
+    kind: VehicleType,
+    // Heaviest load the vehicle can carry at once
+    max_load_kg: u32,
+    refrigerated: bool,
+}
+
+// Function to get the most a driver's registered vehicle can carry, capped by
+// the capacity from their availability. Drivers without a registered vehicle
+// are limited by their availability alone.
+pub(crate) fn load_limit_kg(driver: &DriverProfile, availability_capacity_kg: u32) -> u32 {
+    driver
+        .vehicle
+        .as_ref()
+        .map_or(availability_capacity_kg, |vehicle| {
+            vehicle.max_load_kg.min(availability_capacity_kg)
+        })
+}
+
+// Function to reject a delivery of food that has to stay cold to a driver whose
+// registered vehicle is not refrigerated. Drivers without a registered vehicle
+// keep the old behaviour and are not checked.
+pub(crate) fn check_refrigeration(
+    driver: &DriverProfile,
+    food_type: FoodType,
+) -> Result<(), FoodShareError> {
+    match &driver.vehicle {
+        Some(vehicle) if !vehicle.refrigerated && requires_refrigeration(food_type) => {
+            Err(FoodShareError::Conflict {
+                msg: format!(
+                    "{:?} has to be refrigerated and the driver's {:?} is not",
+                    food_type, vehicle.kind
+                ),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+fn owned_driver(driver_id: u64) -> Result<DriverProfile, FoodShareError> {
+    let driver = DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&driver_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Driver ID does not exist".to_string(),
+        })?;
+
+    let caller = ic_cdk::caller();
+    if driver.owner != Some(caller) && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the driver or governance can change their vehicle".to_string(),
+        });
+    }
+    Ok(driver)
+}
+
+fn store_driver(before: &DriverProfile, driver: &DriverProfile) {
+    DRIVERS_STORAGE.with(|storage| storage.borrow_mut().insert(driver.id, driver.clone()));
+    audit(
+        AuditAction::Updated,
+        AuditEntity::Driver,
+        driver.id,
+        Some(before),
+        Some(driver),
+    );
+}
+
+// Function for a driver to register or replace the vehicle they deliver with;
+// the profile's vehicle type follows the vehicle's kind
+#[ic_cdk::update]
+async fn set_driver_vehicle(
+    driver_id: u64,
+    vehicle: Vehicle,
+) -> Result<DriverProfile, FoodShareError> {
+    start_trace("set_driver_vehicle");
+    authorize(EndpointClass::Registration).await?;
+
+    let mut driver = owned_driver(driver_id)?;
+    if !(1..=MAX_LOAD_KG).contains(&vehicle.max_load_kg) {
+        return Err(FoodShareError::Validation {
+            field: "max_load_kg".to_string(),
+            reason: format!("Maximum load must be between 1 and {} kg", MAX_LOAD_KG),
+        });
+    }
+
+    let before = driver.clone();
+    driver.vehicle_type = vehicle.kind;
+    driver.vehicle = Some(vehicle);
+    store_driver(&before, &driver);
+
+    log(
+        LogLevel::Info,
+        format!("Driver {} registered a vehicle", driver_id),
+    );
+
+    Ok(redacted(driver))
+}
+
+// Function to get the vehicle a driver has registered
+#[ic_cdk::query]
+fn get_driver_vehicle(driver_id: u64) -> Result<Vehicle, FoodShareError> {
+    DRIVERS_STORAGE
+        .with(|storage| storage.borrow().get(&driver_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Driver with id={} not found", driver_id),
+        })?
+        .vehicle
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Driver with id={} has not registered a vehicle", driver_id),
+        })
+}
+
+// Function for a driver to remove their registered vehicle
+#[ic_cdk::update]
+async fn remove_driver_vehicle(driver_id: u64) -> Result<DriverProfile, FoodShareError> {
+    start_trace("remove_driver_vehicle");
+    authorize(EndpointClass::Registration).await?;
+
+    let mut driver = owned_driver(driver_id)?;
+    if driver.vehicle.is_none() {
+        return Err(FoodShareError::NotFound {
+            msg: format!("Driver with id={} has not registered a vehicle", driver_id),
+        });
+    }
+
+    let before = driver.clone();
+    driver.vehicle = None;
+    store_driver(&before, &driver);
+
+    log(
+        LogLevel::Info,
+        format!("Driver {} removed their vehicle", driver_id),
+    );
+
+    Ok(redacted(driver))
+}
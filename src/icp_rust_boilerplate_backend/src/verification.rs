@@ -0,0 +1,457 @@
+use crate::assets::owned_asset;
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{record_event, EntityType, EventKind};
+use crate::governance::{authorize, is_governance_or_admin, require_admin, EndpointClass};
+use crate::indexes::posts_for_donor;
+use crate::observability::{log, start_trace, LogLevel};
+use crate::{next_id, FoodShareError, Memory, DONORS_STORAGE, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const MAX_DOCUMENTS: usize = 5;
+const MAX_NOTES_LEN: usize = 500;
+const MAX_QUOTA_WINDOW_DAYS: u32 = 90;
+
+// DocumentKind is a custom enum type that is used to represent what a verification document proves
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum DocumentKind {
+    BusinessRegistration,
+    HygieneCertificate,
+}
+
+// VerificationStatus is a custom enum type that is used to represent where a verification request is in review
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum VerificationStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct VerificationDocument {
+    kind: DocumentKind,
+    // A finished upload of the submitter, see the assets module
+    asset_id: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct VerificationRequest {
+    id: u64,
+    donor_id: u64,
+    documents: Vec<VerificationDocument>,
+    status: VerificationStatus,
+    submitted_by: Principal,
+    submitted_at: u64,
+    reviewed_by: Option<Principal>,
+    reviewed_at: Option<u64>,
+    notes: Option<String>,
+}
+
+impl Storable for VerificationRequest {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for VerificationRequest {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct VerificationConfig {
+    // Posts an unverified donor may create within the quota window
+    unverified_post_quota: u32,
+    quota_window_days: u32,
+}
+
+impl Default for VerificationConfig {
+    fn default() -> Self {
+        Self {
+            unverified_post_quota: 3,
+            quota_window_days: 7,
+        }
+    }
+}
+
+impl Storable for VerificationConfig {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static VERIFICATION_REQUESTS: RefCell<StableBTreeMap<u64, VerificationRequest, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(94)))
+    ));
+
+    static VERIFICATION_CONFIG: RefCell<Cell<VerificationConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(95))),
+            VerificationConfig::default(),
+        )
+        .expect("Cannot create the verification config")
+    );
+}
+
+fn verification_config() -> VerificationConfig {
+    VERIFICATION_CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+fn is_verified(donor_id: u64) -> bool {
+    DONORS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .get(&donor_id)
+            .is_some_and(|donor| donor.verified == Some(true))
+    })
+}
+
+// Function to reject posts from an unverified donor who has used up their
+// quota for the window; posts without a creation time are not counted
+pub(crate) fn check_post_quota(donor_id: u64) -> Result<(), FoodShareError> {
+    if is_verified(donor_id) {
+        return Ok(());
+    }
+    let config = verification_config();
+    let since = time().saturating_sub(config.quota_window_days as u64 * NANOS_PER_DAY);
+    let recent = posts_for_donor(donor_id)
+        .iter()
+        .filter(|post| post.created_at.is_some_and(|at| at >= since))
+        .count();
+    if recent >= config.unverified_post_quota as usize {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!(
+                "Unverified donors can create {} posts every {} days; submit verification documents to lift the limit",
+                config.unverified_post_quota, config.quota_window_days
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn load_request(request_id: u64) -> Result<VerificationRequest, FoodShareError> {
+    VERIFICATION_REQUESTS
+        .with(|storage| storage.borrow().get(&request_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Verification request with id={} not found", request_id),
+        })
+}
+
+fn validate_notes(notes: &str, required: bool) -> Result<(), FoodShareError> {
+    if notes.len() > MAX_NOTES_LEN || (required && notes.trim().is_empty()) {
+        return Err(FoodShareError::Validation {
+            field: "notes".to_string(),
+            reason: format!(
+                "Notes must be {}at most {} bytes",
+                if required { "non-empty and " } else { "" },
+                MAX_NOTES_LEN
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Function for a donor to send their business registration and hygiene
+// certificate for review; a donor has at most one request pending at a time
+#[ic_cdk::update]
+async fn submit_verification(
+    donor_id: u64,
+    documents: Vec<VerificationDocument>,
+) -> Result<VerificationRequest, FoodShareError> {
+    start_trace("submit_verification");
+    authorize(EndpointClass::Registration).await?;
+
+    let caller = ic_cdk::caller();
+    let donor = DONORS_STORAGE
+        .with(|storage| storage.borrow().get(&donor_id))
+        .filter(|donor| donor.active)
+        .ok_or(FoodShareError::NotFound {
+            msg: "Donor ID does not exist or is deactivated".to_string(),
+        })?;
+    if donor.owner != Some(caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor can submit verification documents".to_string(),
+        });
+    }
+    if donor.verified == Some(true) {
+        return Err(FoodShareError::Conflict {
+            msg: "Donor is already verified".to_string(),
+        });
+    }
+
+    if documents.is_empty() || documents.len() > MAX_DOCUMENTS {
+        return Err(FoodShareError::Validation {
+            field: "documents".to_string(),
+            reason: format!("Submit between 1 and {} documents", MAX_DOCUMENTS),
+        });
+    }
+    for kind in [
+        DocumentKind::BusinessRegistration,
+        DocumentKind::HygieneCertificate,
+    ] {
+        if !documents.iter().any(|document| document.kind == kind) {
+            return Err(FoodShareError::Validation {
+                field: "documents".to_string(),
+                reason: format!("A {:?} document is required", kind),
+            });
+        }
+    }
+    for (index, document) in documents.iter().enumerate() {
+        owned_asset(document.asset_id)?;
+        if documents[..index]
+            .iter()
+            .any(|other| other.asset_id == document.asset_id)
+        {
+            return Err(FoodShareError::Validation {
+                field: "documents".to_string(),
+                reason: format!("Asset {} is listed more than once", document.asset_id),
+            });
+        }
+    }
+
+    let pending = VERIFICATION_REQUESTS.with(|storage| {
+        storage.borrow().iter().any(|(_, request)| {
+            request.donor_id == donor_id && request.status == VerificationStatus::Pending
+        })
+    });
+    if pending {
+        return Err(FoodShareError::Conflict {
+            msg: "Donor already has a verification request under review".to_string(),
+        });
+    }
+
+    let request = VerificationRequest {
+        id: next_id(),
+        donor_id,
+        documents,
+        status: VerificationStatus::Pending,
+        submitted_by: caller,
+        submitted_at: time(),
+        reviewed_by: None,
+        reviewed_at: None,
+        notes: None,
+    };
+    VERIFICATION_REQUESTS.with(|storage| storage.borrow_mut().insert(request.id, request.clone()));
+    audit(
+        AuditAction::Created,
+        AuditEntity::VerificationRequest,
+        request.id,
+        None,
+        Some(&request),
+    );
+    record_event(
+        EntityType::Donor,
+        donor_id,
+        None,
+        EventKind::Updated,
+        format!("Submitted verification request {}", request.id),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Donor {} submitted verification request {}",
+            donor_id, request.id
+        ),
+    );
+
+    Ok(request)
+}
+
+// Function to settle a pending request and, when approved, give the donor
+// their verified badge
+fn review(
+    request_id: u64,
+    status: VerificationStatus,
+    notes: Option<String>,
+) -> Result<VerificationRequest, FoodShareError> {
+    let caller = ic_cdk::caller();
+    if !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only moderators can review verification requests".to_string(),
+        });
+    }
+
+    let before = load_request(request_id)?;
+    if before.status != VerificationStatus::Pending {
+        return Err(FoodShareError::Conflict {
+            msg: format!("Verification request was already {:?}", before.status),
+        });
+    }
+    let mut request = before.clone();
+    request.status = status;
+    request.reviewed_by = Some(caller);
+    request.reviewed_at = Some(time());
+    request.notes = notes;
+
+    if status == VerificationStatus::Approved {
+        let mut donor = DONORS_STORAGE
+            .with(|storage| storage.borrow().get(&request.donor_id))
+            .filter(|donor| donor.active)
+            .ok_or(FoodShareError::NotFound {
+                msg: "Donor ID does not exist or is deactivated".to_string(),
+            })?;
+        donor.verified = Some(true);
+        DONORS_STORAGE.with(|storage| storage.borrow_mut().insert(donor.id, donor));
+    }
+
+    VERIFICATION_REQUESTS.with(|storage| storage.borrow_mut().insert(request.id, request.clone()));
+    audit(
+        AuditAction::StatusChanged,
+        AuditEntity::VerificationRequest,
+        request.id,
+        Some(&before),
+        Some(&request),
+    );
+    record_event(
+        EntityType::Donor,
+        request.donor_id,
+        None,
+        EventKind::Updated,
+        format!("Verification request {} {:?}", request.id, status),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Verification request {} for donor {} {:?}",
+            request.id, request.donor_id, status
+        ),
+    );
+
+    Ok(request)
+}
+
+// Function for moderators to accept a donor's documents and mark the donor verified
+#[ic_cdk::update]
+fn approve_verification(
+    request_id: u64,
+    notes: Option<String>,
+) -> Result<VerificationRequest, FoodShareError> {
+    start_trace("approve_verification");
+    if let Some(notes) = &notes {
+        validate_notes(notes, false)?;
+    }
+    review(request_id, VerificationStatus::Approved, notes)
+}
+
+// Function for moderators to turn down a donor's documents; the notes tell the
+// donor what to fix before submitting again
+#[ic_cdk::update]
+fn reject_verification(
+    request_id: u64,
+    notes: String,
+) -> Result<VerificationRequest, FoodShareError> {
+    start_trace("reject_verification");
+    validate_notes(&notes, true)?;
+    review(request_id, VerificationStatus::Rejected, Some(notes))
+}
+
+// Function for moderators to list the requests waiting for review, oldest first
+#[ic_cdk::query]
+fn get_pending_verifications() -> Result<Vec<VerificationRequest>, FoodShareError> {
+    if !is_governance_or_admin(&ic_cdk::caller()) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only moderators can view the verification queue".to_string(),
+        });
+    }
+    let mut requests: Vec<VerificationRequest> = VERIFICATION_REQUESTS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, request)| request)
+            .filter(|request| request.status == VerificationStatus::Pending)
+            .collect()
+    });
+    requests.sort_by_key(|request| request.submitted_at);
+
+    Ok(requests)
+}
+
+// Function for the donor or a moderator to get a donor's verification
+// requests, newest first
+#[ic_cdk::query]
+fn get_donor_verifications(donor_id: u64) -> Result<Vec<VerificationRequest>, FoodShareError> {
+    let caller = ic_cdk::caller();
+    let donor = DONORS_STORAGE
+        .with(|storage| storage.borrow().get(&donor_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: "Donor ID does not exist".to_string(),
+        })?;
+    if donor.owner != Some(caller) && !is_governance_or_admin(&caller) {
+        return Err(FoodShareError::Unauthorized {
+            msg: "Only the donor or a moderator can view these requests".to_string(),
+        });
+    }
+    let mut requests: Vec<VerificationRequest> = VERIFICATION_REQUESTS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, request)| request)
+            .filter(|request| request.donor_id == donor_id)
+            .collect()
+    });
+    requests.sort_by_key(|request| std::cmp::Reverse(request.submitted_at));
+
+    Ok(requests)
+}
+
+#[ic_cdk::query]
+fn get_verification_config() -> VerificationConfig {
+    verification_config()
+}
+
+// Function for admins to set how many posts unverified donors may create and
+// over how many days
+#[ic_cdk::update]
+fn set_verification_config(
+    config: VerificationConfig,
+) -> Result<VerificationConfig, FoodShareError> {
+    start_trace("set_verification_config");
+    require_admin("update the verification config")?;
+
+    if !(1..=MAX_QUOTA_WINDOW_DAYS).contains(&config.quota_window_days) {
+        return Err(FoodShareError::Validation {
+            field: "quota_window_days".to_string(),
+            reason: format!(
+                "Quota window must be between 1 and {} days",
+                MAX_QUOTA_WINDOW_DAYS
+            ),
+        });
+    }
+
+    let before = VERIFICATION_CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("Cannot update the verification config");
+    audit(
+        AuditAction::Configured,
+        AuditEntity::VerificationConfig,
+        0,
+        Some(&before),
+        Some(&config),
+    );
+
+    log(
+        LogLevel::Info,
+        format!(
+            "Unverified donors limited to {} posts every {} days",
+            config.unverified_post_quota, config.quota_window_days
+        ),
+    );
+
+    Ok(config)
+}
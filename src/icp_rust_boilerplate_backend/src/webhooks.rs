@@ -0,0 +1,625 @@
+use crate::api_tokens::to_hex;
+use crate::audit::{audit, AuditAction, AuditEntity};
+use crate::events::{DomainEvent, EntityType, EventKind};
+use crate::export_feed::ExportEvent;
+use crate::governance::require_admin;
+use crate::observability::{current_trace_id, log, resume_trace, start_trace, LogLevel};
+use crate::{next_id, FoodShareError, Memory, MEMORY_MANAGER};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+    HttpResponse as OutcallResponse, TransformArgs, TransformContext,
+};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::thread::LocalKey;
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const DELIVERY_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_WEBHOOKS: usize = 10;
+const MAX_URL_LEN: usize = 256;
+// Deliveries sent per timer tick, so one tick stays well within the outcall limits
+const MAX_DELIVERIES_PER_TICK: usize = 10;
+const MAX_ATTEMPTS: u32 = 8;
+// First retry after 30 seconds, doubling up to about an hour
+const BASE_BACKOFF_SECS: u64 = 30;
+const MAX_BACKOFF_SECS: u64 = 60 * 60;
+// How long a delivery is held while its outcall is in flight
+const IN_FLIGHT_LEASE_SECS: u64 = 5 * 60;
+// Finished deliveries are kept this long for inspection
+const DELIVERY_RETENTION_NANOS: u64 = 7 * 24 * 60 * 60 * NANOS_PER_SECOND;
+// Enough for a small request and an ignored response on a 13-node subnet
+const WEBHOOK_OUTCALL_CYCLES: u128 = 1_000_000_000;
+const WEBHOOK_MAX_RESPONSE_BYTES: u64 = 1024;
+const SHA256_BLOCK_LEN: usize = 64;
+
+// WebhookEvent is a custom enum type that is used to represent the events partners can subscribe to
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum WebhookEvent {
+    PostCreated,
+    PostAssigned,
+    PostExpired,
+    // A driver delivered the food and the surplus record was created
+    AssignmentCompleted,
+}
+
+impl WebhookEvent {
+    // Function to map a domain event to the webhook event it is sent as, if any
+    fn from_event(event: &DomainEvent) -> Option<Self> {
+        match (event.entity_type, event.kind) {
+            (EntityType::SurplusPost, EventKind::Created) => Some(WebhookEvent::PostCreated),
+            (EntityType::SurplusPost, EventKind::Assigned) => Some(WebhookEvent::PostAssigned),
+            (EntityType::SurplusPost, EventKind::Expired) => Some(WebhookEvent::PostExpired),
+            (EntityType::SurplusRecord, EventKind::Delivered) => {
+                Some(WebhookEvent::AssignmentCompleted)
+            }
+            _ => None,
+        }
+    }
+
+    // Export feed event type the webhook event is sent as
+    fn event_type(self) -> &'static str {
+        match self {
+            WebhookEvent::PostCreated => "surplus_post.created",
+            WebhookEvent::PostAssigned => "surplus_post.assigned",
+            WebhookEvent::PostExpired => "surplus_post.expired",
+            WebhookEvent::AssignmentCompleted => "surplus_record.delivered",
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Webhook {
+    id: u64,
+    url: String,
+    // Key for the HMAC-SHA256 signature of every body sent to the webhook
+    secret: String,
+    events: Vec<WebhookEvent>,
+    registered_by: Principal,
+    registered_at: u64,
+}
+
+impl Storable for Webhook {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Webhook {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A webhook as listed to admins; the secret is only shown on registration
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct WebhookView {
+    id: u64,
+    url: String,
+    events: Vec<WebhookEvent>,
+    registered_by: Principal,
+    registered_at: u64,
+    pending_deliveries: u64,
+    failed_deliveries: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct WebhookRegistration {
+    webhook: WebhookView,
+    // Shown once; partners verify the X-FoodShare-Signature header with it
+    secret: String,
+}
+
+// WebhookDeliveryStatus is a custom enum type that is used to represent where a webhook delivery stands
+#[derive(candid::CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    // Gave up after the last retry
+    Failed,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct WebhookDelivery {
+    id: u64,
+    webhook_id: u64,
+    event_id: u64,
+    event: WebhookEvent,
+    // JSON body, the export feed form of the event
+    body: String,
+    status: WebhookDeliveryStatus,
+    attempts: u32,
+    next_attempt_at: u64,
+    last_error: Option<String>,
+    created_at: u64,
+    delivered_at: Option<u64>,
+}
+
+impl Storable for WebhookDelivery {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for WebhookDelivery {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// (key, delivery id)
+type DeliveryIndex = RefCell<StableBTreeMap<(u64, u64), (), Memory>>;
+
+thread_local! {
+    static WEBHOOKS: RefCell<StableBTreeMap<u64, Webhook, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(110)))
+    ));
+
+    static WEBHOOK_DELIVERIES: RefCell<StableBTreeMap<u64, WebhookDelivery, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(111)))
+    ));
+
+    // (next_attempt_at, delivery_id), for pending deliveries only
+    static PENDING_DELIVERIES: DeliveryIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(127)))
+    ));
+
+    // (created_at, delivery_id), for delivered and failed deliveries
+    static FINISHED_DELIVERIES: DeliveryIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(128)))
+    ));
+
+    // (webhook_id, delivery_id)
+    static DELIVERIES_BY_WEBHOOK: DeliveryIndex =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(129)))
+    ));
+}
+
+fn pending_key(delivery: &WebhookDelivery) -> Option<(u64, u64)> {
+    (delivery.status == WebhookDeliveryStatus::Pending)
+        .then_some((delivery.next_attempt_at, delivery.id))
+}
+
+fn finished_key(delivery: &WebhookDelivery) -> Option<(u64, u64)> {
+    (delivery.status != WebhookDeliveryStatus::Pending)
+        .then_some((delivery.created_at, delivery.id))
+}
+
+// Function to move a delivery's entry in one index from its previous key to its current one
+fn reindex(
+    index: &'static LocalKey<DeliveryIndex>,
+    old_key: Option<(u64, u64)>,
+    new_key: Option<(u64, u64)>,
+) {
+    if old_key == new_key {
+        return;
+    }
+    index.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(key) = old_key {
+            index.remove(&key);
+        }
+        if let Some(key) = new_key {
+            index.insert(key, ());
+        }
+    });
+}
+
+// Function to store a delivery and update its index entries in the same call
+fn store_delivery(delivery: &WebhookDelivery) {
+    let previous = WEBHOOK_DELIVERIES
+        .with(|storage| storage.borrow_mut().insert(delivery.id, delivery.clone()));
+    let previous = previous.as_ref();
+    reindex(
+        &PENDING_DELIVERIES,
+        previous.and_then(pending_key),
+        pending_key(delivery),
+    );
+    reindex(
+        &FINISHED_DELIVERIES,
+        previous.and_then(finished_key),
+        finished_key(delivery),
+    );
+    reindex(
+        &DELIVERIES_BY_WEBHOOK,
+        previous.map(|previous| (previous.webhook_id, previous.id)),
+        Some((delivery.webhook_id, delivery.id)),
+    );
+}
+
+// Function to remove a delivery together with its index entries
+fn remove_delivery(delivery_id: u64) {
+    let Some(delivery) =
+        WEBHOOK_DELIVERIES.with(|storage| storage.borrow_mut().remove(&delivery_id))
+    else {
+        return;
+    };
+    reindex(&PENDING_DELIVERIES, pending_key(&delivery), None);
+    reindex(&FINISHED_DELIVERIES, finished_key(&delivery), None);
+    reindex(
+        &DELIVERIES_BY_WEBHOOK,
+        Some((delivery.webhook_id, delivery.id)),
+        None,
+    );
+}
+
+fn deliveries_for_webhook(webhook_id: u64) -> Vec<WebhookDelivery> {
+    let ids: Vec<u64> = DELIVERIES_BY_WEBHOOK.with(|index| {
+        index
+            .borrow()
+            .range((webhook_id, 0)..=(webhook_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    WEBHOOK_DELIVERIES.with(|storage| {
+        let storage = storage.borrow();
+        ids.iter().filter_map(|id| storage.get(id)).collect()
+    })
+}
+
+// Function to index deliveries queued before the delivery indexes existed.
+// Runs on every upgrade but only does work while the indexes are empty.
+pub(crate) fn migrate_delivery_indexes() {
+    if !DELIVERIES_BY_WEBHOOK.with(|index| index.borrow().is_empty()) {
+        return;
+    }
+    let deliveries: Vec<WebhookDelivery> = WEBHOOK_DELIVERIES.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, delivery)| delivery)
+            .collect()
+    });
+    for delivery in deliveries {
+        reindex(&PENDING_DELIVERIES, None, pending_key(&delivery));
+        reindex(&FINISHED_DELIVERIES, None, finished_key(&delivery));
+        reindex(
+            &DELIVERIES_BY_WEBHOOK,
+            None,
+            Some((delivery.webhook_id, delivery.id)),
+        );
+    }
+}
+
+// Function to queue a delivery of an event to every webhook subscribed to it
+pub(crate) fn queue_event(event: &DomainEvent) {
+    let Some(subscribed) = WebhookEvent::from_event(event) else {
+        return;
+    };
+    let webhooks: Vec<Webhook> = WEBHOOKS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, webhook)| webhook)
+            .filter(|webhook| webhook.events.contains(&subscribed))
+            .collect()
+    });
+    let body = serde_json::to_string(&ExportEvent::from(event.clone()))
+        .expect("Cannot encode webhook body");
+    let now = time();
+    for webhook in webhooks {
+        store_delivery(&WebhookDelivery {
+            id: next_id(),
+            webhook_id: webhook.id,
+            event_id: event.id,
+            event: subscribed,
+            body: body.clone(),
+            status: WebhookDeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            created_at: now,
+            delivered_at: None,
+        });
+    }
+}
+
+// Function to compute the HMAC-SHA256 of a message (RFC 2104)
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = [0u8; SHA256_BLOCK_LEN];
+    if key.len() > SHA256_BLOCK_LEN {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<u8>>();
+    let mut inner = Sha256::new();
+    inner.update(pad(0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(pad(0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().to_vec()
+}
+
+fn backoff_nanos(attempts: u32) -> u64 {
+    let secs = BASE_BACKOFF_SECS.saturating_mul(1 << attempts.saturating_sub(1).min(16));
+    secs.min(MAX_BACKOFF_SECS) * NANOS_PER_SECOND
+}
+
+// Function to register the periodic delivery of queued webhook events
+pub(crate) fn start_webhook_delivery() {
+    ic_cdk_timers::set_timer_interval(DELIVERY_INTERVAL, deliver_due);
+}
+
+// Function to send the deliveries that are due and drop finished ones past
+// their retention. Both are range reads of the delivery indexes, and ticks with
+// nothing to do return before starting a trace. Each delivery is leased before
+// its outcall so the next tick does not send it twice.
+fn deliver_due() {
+    let now = time();
+    let due: Vec<u64> = PENDING_DELIVERIES.with(|index| {
+        index
+            .borrow()
+            .range(..=(now, u64::MAX))
+            .take(MAX_DELIVERIES_PER_TICK)
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    let cutoff = now.saturating_sub(DELIVERY_RETENTION_NANOS);
+    let expired: Vec<u64> = FINISHED_DELIVERIES.with(|index| {
+        index
+            .borrow()
+            .range(..(cutoff, 0))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    if due.is_empty() && expired.is_empty() {
+        return;
+    }
+    start_trace("deliver_webhooks");
+
+    for id in expired {
+        remove_delivery(id);
+    }
+    for id in due {
+        let Some(mut delivery) = WEBHOOK_DELIVERIES.with(|storage| storage.borrow().get(&id))
+        else {
+            continue;
+        };
+        delivery.next_attempt_at = now + IN_FLIGHT_LEASE_SECS * NANOS_PER_SECOND;
+        store_delivery(&delivery);
+        ic_cdk::spawn(attempt_delivery(delivery));
+    }
+}
+
+async fn attempt_delivery(mut delivery: WebhookDelivery) {
+    let Some(webhook) = WEBHOOKS.with(|storage| storage.borrow().get(&delivery.webhook_id)) else {
+        return;
+    };
+    let signature = to_hex(&hmac_sha256(
+        webhook.secret.as_bytes(),
+        delivery.body.as_bytes(),
+    ));
+    let header = |name: &str, value: String| HttpHeader {
+        name: name.to_string(),
+        value,
+    };
+    let request = CanisterHttpRequestArgument {
+        url: webhook.url.clone(),
+        max_response_bytes: Some(WEBHOOK_MAX_RESPONSE_BYTES),
+        method: HttpMethod::POST,
+        headers: vec![
+            header("Content-Type", "application/json".to_string()),
+            header("X-FoodShare-Event", delivery.event.event_type().to_string()),
+            // Every replica sends the request; relays should drop repeated delivery ids
+            header("X-FoodShare-Delivery", delivery.id.to_string()),
+            header("X-FoodShare-Signature", format!("sha256={}", signature)),
+        ],
+        body: Some(delivery.body.clone().into_bytes()),
+        transform: Some(TransformContext::from_name(
+            "transform_webhook_response".to_string(),
+            Vec::new(),
+        )),
+    };
+
+    let trace_id = current_trace_id();
+    let result = http_request(request, WEBHOOK_OUTCALL_CYCLES).await;
+    resume_trace(&trace_id);
+
+    let outcome = match result {
+        Ok((response,)) if (200u32..300).contains(&status_code(&response)) => Ok(()),
+        Ok((response,)) => Err(format!("Webhook answered {}", status_code(&response))),
+        Err((code, msg)) => Err(format!("Outcall failed ({:?}): {}", code, msg)),
+    };
+
+    delivery.attempts += 1;
+    let now = time();
+    match outcome {
+        Ok(()) => {
+            delivery.status = WebhookDeliveryStatus::Delivered;
+            delivery.delivered_at = Some(now);
+            delivery.last_error = None;
+        }
+        Err(reason) => {
+            if delivery.attempts >= MAX_ATTEMPTS {
+                delivery.status = WebhookDeliveryStatus::Failed;
+                log(
+                    LogLevel::Warn,
+                    format!(
+                        "Gave up on webhook delivery {} after {} attempts: {}",
+                        delivery.id, delivery.attempts, reason
+                    ),
+                );
+            } else {
+                delivery.next_attempt_at = now + backoff_nanos(delivery.attempts);
+            }
+            delivery.last_error = Some(reason);
+        }
+    }
+    // The webhook may have been deleted while the outcall was in flight
+    if WEBHOOK_DELIVERIES.with(|storage| storage.borrow().contains_key(&delivery.id)) {
+        store_delivery(&delivery);
+    }
+}
+
+fn status_code(response: &OutcallResponse) -> u32 {
+    u32::try_from(response.status.0.clone()).unwrap_or_default()
+}
+
+// Function to reduce a webhook response to its status, so every replica sees
+// the same response
+#[ic_cdk::query]
+fn transform_webhook_response(args: TransformArgs) -> OutcallResponse {
+    OutcallResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+fn webhook_view(webhook: &Webhook) -> WebhookView {
+    let (pending, failed) =
+        deliveries_for_webhook(webhook.id)
+            .iter()
+            .fold((0, 0), |(pending, failed), delivery| {
+                match delivery.status {
+                    WebhookDeliveryStatus::Pending => (pending + 1, failed),
+                    WebhookDeliveryStatus::Failed => (pending, failed + 1),
+                    WebhookDeliveryStatus::Delivered => (pending, failed),
+                }
+            });
+    WebhookView {
+        id: webhook.id,
+        url: webhook.url.clone(),
+        events: webhook.events.clone(),
+        registered_by: webhook.registered_by,
+        registered_at: webhook.registered_at,
+        pending_deliveries: pending,
+        failed_deliveries: failed,
+    }
+}
+
+// Function for admins to register a URL that receives the given events as
+// signed JSON POSTs. The signing secret is returned once.
+#[ic_cdk::update]
+async fn register_webhook(
+    url: String,
+    events: Vec<WebhookEvent>,
+) -> Result<WebhookRegistration, FoodShareError> {
+    start_trace("register_webhook");
+    require_admin("register webhooks")?;
+
+    if !url.starts_with("https://") || url.len() > MAX_URL_LEN {
+        return Err(FoodShareError::Validation {
+            field: "url".to_string(),
+            reason: format!("URL must use https and be at most {} bytes", MAX_URL_LEN),
+        });
+    }
+    let mut events = events;
+    events.sort_by_key(|event| event.event_type());
+    events.dedup();
+    if events.is_empty() {
+        return Err(FoodShareError::Validation {
+            field: "events".to_string(),
+            reason: "Subscribe to at least one event".to_string(),
+        });
+    }
+    if WEBHOOKS.with(|storage| storage.borrow().len()) as usize >= MAX_WEBHOOKS {
+        return Err(FoodShareError::QuotaExceeded {
+            msg: format!("At most {} webhooks can be registered", MAX_WEBHOOKS),
+        });
+    }
+
+    let trace_id = current_trace_id();
+    let result = raw_rand().await;
+    resume_trace(&trace_id);
+    let (random_bytes,) = result.map_err(|(code, msg)| FoodShareError::InternalError {
+        msg: format!("Cannot generate webhook secret ({:?}): {}", code, msg),
+    })?;
+
+    let webhook = Webhook {
+        id: next_id(),
+        url,
+        secret: to_hex(&random_bytes),
+        events,
+        registered_by: ic_cdk::caller(),
+        registered_at: time(),
+    };
+    WEBHOOKS.with(|storage| storage.borrow_mut().insert(webhook.id, webhook.clone()));
+    let view = webhook_view(&webhook);
+    audit(
+        AuditAction::Created,
+        AuditEntity::Webhook,
+        webhook.id,
+        None,
+        Some(&view),
+    );
+
+    log(
+        LogLevel::Info,
+        format!("Registered webhook {} for {}", webhook.id, webhook.url),
+    );
+
+    Ok(WebhookRegistration {
+        webhook: view,
+        secret: webhook.secret,
+    })
+}
+
+// Function for admins to list the registered webhooks
+#[ic_cdk::query]
+fn list_webhooks() -> Result<Vec<WebhookView>, FoodShareError> {
+    require_admin("list webhooks")?;
+    Ok(WEBHOOKS.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, webhook)| webhook_view(&webhook))
+            .collect()
+    }))
+}
+
+// Function for admins to remove a webhook; its queued deliveries are dropped
+#[ic_cdk::update]
+fn delete_webhook(webhook_id: u64) -> Result<(), FoodShareError> {
+    start_trace("delete_webhook");
+    require_admin("delete webhooks")?;
+
+    let webhook = WEBHOOKS
+        .with(|storage| storage.borrow().get(&webhook_id))
+        .ok_or(FoodShareError::NotFound {
+            msg: format!("Webhook with id={} not found", webhook_id),
+        })?;
+    let before = webhook_view(&webhook);
+    WEBHOOKS.with(|storage| storage.borrow_mut().remove(&webhook_id));
+    for delivery in deliveries_for_webhook(webhook_id) {
+        remove_delivery(delivery.id);
+    }
+    audit(
+        AuditAction::Revoked,
+        AuditEntity::Webhook,
+        webhook_id,
+        Some(&before),
+        None::<&WebhookView>,
+    );
+
+    log(LogLevel::Info, format!("Deleted webhook {}", webhook_id));
+
+    Ok(())
+}
+
+// Function for admins to see the recent deliveries of a webhook, newest first
+#[ic_cdk::query]
+fn get_webhook_deliveries(webhook_id: u64) -> Result<Vec<WebhookDelivery>, FoodShareError> {
+    require_admin("view webhook deliveries")?;
+    let mut deliveries = deliveries_for_webhook(webhook_id);
+    deliveries.reverse();
+    Ok(deliveries)
+}